@@ -7,8 +7,16 @@
 //!
 //! Set the database path:
 //!   SHYMINI_BENCH_DB=./bench.db cargo bench
+//!
+//! The first benches time single raw queries. The `core_stats`,
+//! `summary_stats`, `url_filter` and `panels` groups call the `db` functions
+//! the dashboard and API use, comparison period and URL filter included.
 
+use chrono::{DateTime, Utc};
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use regex::Regex;
+use shymini::db;
+use shymini::domain::{CompareMode, ServiceId, WeekStart};
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::{Pool, Sqlite};
 use std::str::FromStr;
@@ -337,6 +345,267 @@ fn bench_full_dashboard_stats(c: &mut Criterion) {
     });
 }
 
+/// Connect to the bench database and pick the busiest service
+fn bench_setup(rt: &Runtime) -> (Pool<Sqlite>, ServiceId) {
+    let db_path =
+        std::env::var("SHYMINI_BENCH_DB").unwrap_or_else(|_| "sqlite:bench.db".to_string());
+    let pool = rt.block_on(create_pool(&db_path));
+    let service_id = rt.block_on(get_top_service(&pool));
+    let service_id = ServiceId::from_str(&service_id).expect("Service IDs are UUIDs");
+    (pool, service_id)
+}
+
+fn days_ago(now: DateTime<Utc>, days: i64) -> DateTime<Utc> {
+    now - chrono::Duration::days(days)
+}
+
+/// Pattern matching part of the pages the load test seeds
+fn url_pattern() -> Regex {
+    Regex::new("^/blog").unwrap()
+}
+
+// Twice the default heartbeat frequency, as Settings computes it
+const ACTIVE_USER_TIMEOUT_MS: u64 = 10_000;
+
+fn bench_core_stats(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (pool, service_id) = bench_setup(&rt);
+    let now = Utc::now();
+
+    // Every call also loads the comparison period before the range
+    let mut group = c.benchmark_group("core_stats");
+    for days in [1, 7, 30, 90] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}d", days)),
+            &days,
+            |b, &days| {
+                b.to_async(&rt).iter(|| async {
+                    let stats = db::get_core_stats(
+                        &pool,
+                        service_id,
+                        days_ago(now, days),
+                        now,
+                        None,
+                        now,
+                        None,
+                        None,
+                        None,
+                        None,
+                        ACTIVE_USER_TIMEOUT_MS,
+                        chrono_tz::UTC,
+                        WeekStart::Monday,
+                        Some(CompareMode::PreviousPeriod),
+                    )
+                    .await
+                    .unwrap();
+                    black_box(stats)
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_summary_stats(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (pool, service_id) = bench_setup(&rt);
+    let now = Utc::now();
+
+    let mut group = c.benchmark_group("summary_stats");
+    for days in [7, 30] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}d", days)),
+            &days,
+            |b, &days| {
+                b.to_async(&rt).iter(|| async {
+                    let stats = db::get_summary_stats(
+                        &pool,
+                        service_id,
+                        days_ago(now, days),
+                        now,
+                        None,
+                        now,
+                        None,
+                        None,
+                        None,
+                        None,
+                        ACTIVE_USER_TIMEOUT_MS,
+                        chrono_tz::UTC,
+                        WeekStart::Monday,
+                        Some(CompareMode::PreviousPeriod),
+                    )
+                    .await
+                    .unwrap();
+                    black_box(stats)
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_url_filter(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (pool, service_id) = bench_setup(&rt);
+    let now = Utc::now();
+    let pattern = url_pattern();
+
+    // Filtered stats load every hit in the range and match them in Rust
+    let mut group = c.benchmark_group("url_filter");
+    for days in [7, 30] {
+        group.bench_with_input(
+            BenchmarkId::new("core_stats", format!("{}d", days)),
+            &days,
+            |b, &days| {
+                b.to_async(&rt).iter(|| async {
+                    let stats = db::get_core_stats(
+                        &pool,
+                        service_id,
+                        days_ago(now, days),
+                        now,
+                        None,
+                        now,
+                        None,
+                        Some(&pattern),
+                        None,
+                        None,
+                        ACTIVE_USER_TIMEOUT_MS,
+                        chrono_tz::UTC,
+                        WeekStart::Monday,
+                        Some(CompareMode::PreviousPeriod),
+                    )
+                    .await
+                    .unwrap();
+                    black_box(stats)
+                });
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("summary_stats", format!("{}d", days)),
+            &days,
+            |b, &days| {
+                b.to_async(&rt).iter(|| async {
+                    let stats = db::get_summary_stats(
+                        &pool,
+                        service_id,
+                        days_ago(now, days),
+                        now,
+                        None,
+                        now,
+                        None,
+                        Some(&pattern),
+                        None,
+                        None,
+                        ACTIVE_USER_TIMEOUT_MS,
+                        chrono_tz::UTC,
+                        WeekStart::Monday,
+                        Some(CompareMode::PreviousPeriod),
+                    )
+                    .await
+                    .unwrap();
+                    black_box(stats)
+                });
+            },
+        );
+    }
+    group.bench_function("top_locations/30d", |b| {
+        b.to_async(&rt).iter(|| async {
+            let locations = db::get_top_locations(
+                &pool,
+                service_id,
+                days_ago(now, 30),
+                now,
+                None,
+                Some(&pattern),
+                None,
+            )
+            .await
+            .unwrap();
+            black_box(locations)
+        });
+    });
+    group.bench_function("chart/30d", |b| {
+        b.to_async(&rt).iter(|| async {
+            let chart = db::get_chart(
+                &pool,
+                service_id,
+                days_ago(now, 30),
+                now,
+                None,
+                now,
+                Some(&pattern),
+                None,
+                chrono_tz::UTC,
+                WeekStart::Monday,
+            )
+            .await
+            .unwrap();
+            black_box(chart)
+        });
+    });
+    group.finish();
+}
+
+fn bench_panels(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (pool, service_id) = bench_setup(&rt);
+    let now = Utc::now();
+    let start = days_ago(now, 30);
+
+    // The queries behind the dashboard's panel partials, over 30 days
+    let mut group = c.benchmark_group("panels");
+    group.bench_function("top_locations", |b| {
+        b.to_async(&rt).iter(|| async {
+            black_box(
+                db::get_top_locations(&pool, service_id, start, now, None, None, None)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+    group.bench_function("top_referrers", |b| {
+        b.to_async(&rt).iter(|| async {
+            black_box(
+                db::get_top_referrers(&pool, service_id, start, now, None, None, None, None)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+    group.bench_function("top_countries", |b| {
+        b.to_async(&rt).iter(|| async {
+            black_box(
+                db::get_top_countries(&pool, service_id, start, now, None, None, None)
+                    .await
+                    .unwrap(),
+            )
+        });
+    });
+    for tz in [chrono_tz::UTC, chrono_tz::America::New_York] {
+        group.bench_with_input(BenchmarkId::new("chart", tz.name()), &tz, |b, &tz| {
+            b.to_async(&rt).iter(|| async {
+                black_box(
+                    db::get_chart(
+                        &pool,
+                        service_id,
+                        start,
+                        now,
+                        None,
+                        now,
+                        None,
+                        None,
+                        tz,
+                        WeekStart::Monday,
+                    )
+                    .await
+                    .unwrap(),
+                )
+            });
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_session_count,
@@ -346,6 +615,10 @@ criterion_group!(
     bench_daily_chart,
     bench_sessions_list,
     bench_full_dashboard_stats,
+    bench_core_stats,
+    bench_summary_stats,
+    bench_url_filter,
+    bench_panels,
 );
 
 criterion_main!(benches);
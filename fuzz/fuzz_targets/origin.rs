@@ -0,0 +1,31 @@
+//! Origin, Referer and forwarding headers, as read for the origin check
+
+#![no_main]
+
+use axum::http::{HeaderMap, HeaderValue};
+use libfuzzer_sys::fuzz_target;
+use shymini::privacy::{get_client_ip, get_origin, get_referrer};
+
+const HEADERS: [&str; 4] = ["origin", "referer", "x-forwarded-for", "x-real-ip"];
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the header, the rest is its value
+    let Some((&which, value)) = data.split_first() else {
+        return;
+    };
+    let Ok(value) = HeaderValue::from_bytes(value) else {
+        return;
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(HEADERS[usize::from(which) % HEADERS.len()], value);
+
+    if let Some(origin) = get_origin(&headers) {
+        assert!(!origin.is_empty());
+        assert_eq!(origin.to_lowercase(), origin);
+    }
+    if let Some(ip) = get_client_ip(&headers) {
+        assert_eq!(ip.trim(), ip);
+    }
+    let referrer = get_referrer(&headers);
+    assert_eq!(referrer.trim(), referrer);
+});
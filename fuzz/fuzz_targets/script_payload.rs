@@ -0,0 +1,31 @@
+//! Tracker POST bodies: parsing must never panic, and whatever parses must
+//! clean into a payload that fits the database
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shymini::ingress::{IngressPayload, ScriptPayload, MAX_FIELD_CHARS, MAX_URL_CHARS};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(payload) = serde_json::from_slice::<ScriptPayload>(data) else {
+        return;
+    };
+    let cleaned = IngressPayload {
+        idempotency: payload.idempotency,
+        location: payload.location.unwrap_or_default(),
+        referrer: payload.referrer.unwrap_or_default(),
+        load_time: payload.load_time,
+        ..Default::default()
+    }
+    .cleaned();
+
+    assert!(cleaned.location.chars().count() <= MAX_URL_CHARS);
+    assert!(cleaned.referrer.chars().count() <= MAX_URL_CHARS);
+    assert!(!cleaned.location.contains('\0') && !cleaned.referrer.contains('\0'));
+    if let Some(key) = &cleaned.idempotency {
+        assert!(!key.is_empty() && key.chars().count() <= MAX_FIELD_CHARS);
+    }
+    if let Some(load_time) = cleaned.load_time {
+        assert!(load_time.is_finite() && load_time > 0.0);
+    }
+});
@@ -0,0 +1,12 @@
+//! User agent parsing, on whatever a client puts in the header
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shymini::domain::DeviceType;
+use shymini::ua::parse_user_agent;
+
+fuzz_target!(|data: &[u8]| {
+    let parsed = parse_user_agent(&String::from_utf8_lossy(data));
+    assert!(!parsed.is_bot || parsed.device_type == DeviceType::Robot);
+});
@@ -0,0 +1,860 @@
+# German dashboard messages. Keep the keys in sync with en.ftl.
+
+## Layout
+app-title = shymini Analytics
+nav-dashboard = Übersicht
+nav-new-service = + Neuer Dienst
+nav-back-to = ← Zurück zu { $name }
+nav-organization = Organisation
+nav-account = Konto
+nav-logout = Abmelden
+nav-search = Suchen…
+footer-powered-by = Betrieben mit
+
+## Common
+common-unknown = Unbekannt
+common-direct = Direkt
+common-cancel = Abbrechen
+common-filter = Filtern
+common-to = bis
+common-loading = Wird geladen…
+common-yes = Ja
+common-no = Nein
+common-invalid-range = Ungültiger Zeitraum
+common-start-before-end = Der Beginn muss vor dem Ende liegen
+common-url-filter = URL-Filter (Regex)
+common-view-all = Alle anzeigen
+pagination-page = Seite { $page }
+pagination-previous = Zurück
+pagination-next = Weiter
+
+## Service list
+services-title = Dienste
+services-subtitle = Erfasste Websites von { $name }
+services-empty-title = Noch keine Dienste
+services-empty-body = Lege deinen ersten Dienst an, um mit der Erfassung zu beginnen.
+services-create = Dienst anlegen
+services-sessions-24h = Sitzungen (24 h)
+services-hits-24h = Aufrufe (24 h)
+services-trend-7d = Letzte 7 Tage
+status-active = Aktiv
+status-archived = Archiviert
+install-found = Tracker eingebunden
+install-issue-no_link = Kein Link zum Prüfen
+install-issue-unreachable = Seite nicht erreichbar
+install-issue-http_status = Seite meldet einen Fehler
+install-issue-tracker_missing = Tracker nicht gefunden
+install-issue-wrong_tracking_id = Tracker eines anderen Dienstes
+install-issue-mixed_content = Tracker über HTTP geladen
+uptime-percent = { $percent } % erreichbar (24 h)
+uptime-down = Seite nicht erreichbar
+badge-visitors = Besucher
+badge-month = { $count } diesen Monat
+badge-online = { $count } online
+embed-online = Gerade online
+expiry-certificate = Zertifikat läuft in { $days } Tagen ab
+expiry-certificate-expired = Zertifikat abgelaufen
+expiry-domain = Domain läuft in { $days } Tagen ab
+expiry-domain-expired = Domain abgelaufen
+
+## Service dashboard
+service-manage = Verwalten
+service-campaigns = Kampagnen
+service-links = Links
+service-users = Nutzer
+service-retention = Wiederkehr
+service-default-view = Standardansicht
+service-time-zone = Zeitzone der Daten
+service-custom-range = Eigener Zeitraum
+date-range-24h = Letzte 24 Stunden
+date-range-7d = Letzte 7 Tage
+date-range-30d = Letzte 30 Tage
+date-range-90d = Letzte 90 Tage
+date-range-365d = Letzte 365 Tage
+environment-production = Produktion
+environment-staging = Staging
+environment-dev = Entwicklung
+environment-all = Alle Umgebungen
+service-customize = Ansicht anpassen & speichern
+service-customize-help = Wähle die anzuzeigenden Bereiche und ihre Reihenfolge. Zeitraum und URL-Filter werden mit der Ansicht gespeichert.
+service-view-name = Name der Ansicht
+service-save-view = Ansicht speichern
+service-delete-view = Aktuelle Ansicht löschen
+service-segments = Segmente
+service-segments-help = Beschränke Statistiken, Diagramm und Sitzungen auf Besucher, die alle Bedingungen erfüllen. Leere Zeilen werden übersprungen.
+service-segment-all = Alle Besucher
+service-segment-name = Name des Segments
+service-save-segment = Segment speichern
+service-delete-segment = Aktuelles Segment löschen
+segment-field-country = Land
+segment-field-device = Gerät
+segment-field-browser = Browser
+segment-field-os = Betriebssystem
+segment-field-referrer = Verweis
+segment-field-page = Seite
+segment-op-is = ist
+segment-op-is_not = ist nicht
+segment-op-contains = enthält
+filters-active = Gefiltert nach
+filters-clear = Filter entfernen
+service-get-started = Erste Schritte
+service-get-started-script = Binde dieses Skript in deine Website ein, um mit der Erfassung zu beginnen:
+service-get-started-pixel = Oder nutze den Pixel-Tracker für Erfassung ohne JavaScript:
+
+## Stat cards
+stat-sessions = Sitzungen
+stat-sessions-help = Eindeutige Besucher, erkannt über einen Hash aus IP-Adresse und Browser-Fingerabdruck.
+stat-hits = Aufrufe
+stat-hits-help = Alle Seitenaufrufe aller Sitzungen im gewählten Zeitraum.
+stat-load-time = Ladezeit
+stat-load-time-help = Durchschnittliche Ladezeit in Millisekunden, vom Beginn der Navigation bis zum vollständigen Laden der Seite.
+stat-bounce-rate = Absprungrate
+stat-bounce-rate-help = Anteil der Sitzungen, in denen nur eine Seite aufgerufen wurde.
+stat-duration = Dauer
+stat-duration-help = Durchschnittliche Verweildauer pro Sitzung, gemessen über regelmäßige Heartbeats.
+stat-hits-per-session = Aufrufe/Sitzung
+stat-hits-per-session-help = Durchschnittliche Anzahl aufgerufener Seiten pro Sitzung. Höhere Werte deuten auf engagiertere Besucher hin.
+stat-unique-visitors = ≈ { $count } Besucher
+stat-unique-visitors-help = Verschiedene Besucher an den Tagen des gewählten Zeitraums, einmal gezählt, egal wie viele Sitzungen sie hatten. Bis 512 pro Tag genau, darüber geschätzt.
+
+## PDF reports
+report-generated = Erstellt von shymini am { $time }
+
+## Milestone feeds
+milestone-feed-title = Meilensteine von { $service }
+milestone-monthly-sessions = { $count } Sitzungen in diesem Monat
+milestone-record-day = Rekordtag: { $count } Sitzungen
+milestone-spike = Besucherspitze: { $count } Sitzungen
+milestone-drop = Besuchereinbruch: { $count } Sitzungen
+milestone-expected = Etwa { $count } erwartet nach den vier Wochen davor
+
+## Dashboard panels
+panel-chart = Diagramm
+panel-locations = Top-Seiten
+panel-countries = Länder
+panel-referrers = Verweise
+panel-browsers = Browser
+panel-operating_systems = Betriebssysteme
+panel-device_types = Gerätetypen
+panel-sessions = Letzte Sitzungen
+panel-usage = Nutzung
+panel-content_groups = Inhaltsgruppen
+panel-session_distribution = Sitzungsverteilung
+panel-dimensions = Dimensionen
+panel-data_quality = Datenqualität
+panel-errors = Fehler
+
+## Table columns
+column-location = Seite
+column-hits = Aufrufe
+column-sessions = Sitzungen
+column-country = Land
+column-source = Quelle
+column-browser = Browser
+column-os = BS
+column-type = Typ
+column-session = Sitzung
+column-started = Beginn
+column-last-seen = Zuletzt gesehen
+column-device = Gerät
+column-page = Seite
+column-referrer = Verweis
+column-tracker = Tracker
+column-load-time = Ladezeit
+column-heartbeats = Heartbeats
+column-month = Monat
+column-dropped = Verworfen
+column-duplicates = Duplikate
+column-duplicates-help = Wiederholte Seitenaufrufe, die erkannt wurden, nachdem der Cache sie vergessen hatte, und nicht erneut gezählt wurden
+column-error = Fehler
+column-reports = Meldungen
+column-group = Gruppe
+column-duration = Dauer
+column-pages = Seiten
+
+## Sessions and locations
+sessions-title = Sitzungen
+sessions-empty = Keine Sitzungen gefunden
+locations-title = Alle Seiten
+locations-empty = Keine Seiten gefunden
+locations-orphans = Verwaiste Seiten
+locations-orphans-help = Seiten aus der Sitemap ohne Aufruf in diesem Zeitraum
+content-groups-other = Sonstige Seiten
+content-groups-none = Noch keine Inhaltsgruppen. Regeln lassen sich in den Diensteinstellungen anlegen.
+dimensions-none = Keine eigenen Dimensionen in diesem Zeitraum. Der Tracker sendet sie mit den Seitenaufrufen.
+
+## Email campaigns and tracked links
+campaigns-title = Kampagnen
+campaigns-empty = Keine Kampagnen-Öffnungen in diesem Zeitraum. Gib Pixeln Kennungen wie `fruehjahr:leser@example.com`, um Öffnungen nach Kampagne auszuwerten.
+campaigns-not-found = Diese Kampagne wurde in diesem Zeitraum nicht geöffnet.
+campaigns-open-times = Öffnungszeiten
+campaigns-open-times-help = Zeit seit der ersten Öffnung der Kampagne
+campaigns-recipients = Empfänger
+column-campaign = Kampagne
+column-recipient = Empfänger
+column-opens = Öffnungen
+column-users = Nutzer
+column-devices = Geräte
+column-unique-opens = Eindeutige Öffnungen
+column-first-open = Erste Öffnung
+column-last-open = Letzte Öffnung
+column-since-first-open = Nach
+links-title = Verfolgte Links
+links-empty = Noch keine verfolgten Links. Jeder bekommt eine kurze URL, die Klicks zählt und dann weiterleitet.
+links-add = Link hinzufügen
+links-target = Ziel-URL
+links-campaign-help = Mit der Kampagne deiner Pixel-Kennungen (`fruehjahr` aus `fruehjahr:leser@example.com`) vergleicht die Klickrate eindeutige Klicks mit den eindeutigen Öffnungen der Kampagne.
+links-delete = Löschen
+links-name = Name
+links-slug = Kurzlink
+links-slug-help = Optional: 3 bis 64 Buchstaben, Ziffern, `-` oder `_` nach /r/. Bleibt das Feld leer, wird ein zufälliger gewählt.
+links-clicks-per-day = Klicks pro Tag
+links-qr-code = QR-Code
+links-qr-download = QR-Code herunterladen
+column-link = Link
+column-clicks = Klicks
+column-unique-clicks = Eindeutige Klicks
+column-click-through-rate = Klickrate
+
+## Search
+search-title = Suche
+search-results-for = Ergebnisse für „{ $query }“
+search-help = Durchsucht Dienstnamen, Seitenpfade, Referrer-Domains und Sitzungskennungen.
+search-empty = Nichts gefunden
+search-column-kind = Art
+search-column-match = Treffer
+search-column-service = Dienst
+search-kind-service = Dienst
+search-kind-page = Seite
+search-kind-referrer = Referrer
+search-kind-session = Sitzung
+
+## Session detail
+session-title = Sitzung
+session-details = Sitzungsdetails
+session-info = Sitzung
+session-id = Sitzungs-ID
+session-identifier = Kennung
+session-bounce = Absprung
+session-ended = Verlassen
+session-props = Eigenschaften
+session-device = Gerät
+session-browser = Browser
+session-os = Betriebssystem
+session-device-type = Gerätetyp
+session-location = Standort
+session-country = Land
+session-asn = ASN
+session-time-zone = Zeitzone
+session-ip = IP-Adresse
+session-page-views = Seitenaufrufe ({ $count })
+session-no-page-views = Keine Seitenaufrufe erfasst
+session-initial = Einstieg
+session-prefetched = Vorab geladen
+session-prefetched-help = Der Browser hat diese Seite im Voraus geladen; möglicherweise wurde sie nie geöffnet
+session-referrer-spam = Referrer-Spam
+session-referrer-spam-help = Der Referrer steht auf der Referrer-Spam-Liste
+session-user-agent = User-Agent
+
+## Usage and quotas
+usage-hits-this-month = Aufrufe in diesem Monat
+usage-unlimited = kein Kontingent
+usage-exceeded-keep = Kontingent aufgebraucht. Es wird weiterhin alles erfasst.
+usage-exceeded-sample = Kontingent aufgebraucht. Nur noch eine Stichprobe der Besucher wird erfasst.
+usage-exceeded-drop = Kontingent aufgebraucht. Neuer Traffic wird bis zum nächsten Monat verworfen.
+quota-behavior-keep = Weiter erfassen
+quota-behavior-sample = Stichprobe der Besucher erfassen
+quota-behavior-drop = Erfassung bis zum nächsten Monat stoppen
+theme-mode-default = Wie die Installation
+theme-mode-light = Hell
+theme-mode-dark = Dunkel
+
+## Service forms
+form-create-title = Neuen Dienst anlegen
+form-create-subtitle = Richte eine neue Website für die Erfassung ein
+form-manage-title = Dienst verwalten
+form-manage-page-title = { $name } verwalten
+form-manage-subtitle = Einstellungen für { $name } ändern
+form-name = Name des Dienstes *
+form-name-placeholder = Meine Website
+form-link = Website-URL
+form-origins = Erlaubte Origins
+form-origins-placeholder = * oder https://example.com,https://www.example.com
+form-origins-help = Kommagetrennte Liste erlaubter Origins für CORS, oder * für alle
+form-time-zone = Zeitzone
+form-time-zone-help = IANA-Name wie Europe/Berlin, der für Berichte gilt, sofern die betrachtende Person keine eigene eingestellt hat. Leer lassen für pazifische Zeit.
+form-default-range = Standardzeitraum
+form-default-range-help = Was Berichte zeigen, bis die betrachtende Person selbst einen Zeitraum wählt
+form-week-start = Wochen beginnen am
+form-week-start-default = Wie in der Sprache der betrachtenden Person üblich
+form-week-start-help = Wo die Wochen in Diagrammen langer Zeiträume beginnen, bei Montag nach ISO 8601 nummeriert. Wer in seinem Konto einen Tag gewählt hat, sieht diesen.
+form-privacy = Datenschutz
+form-respect-dnt = Do-Not-Track-Header (DNT) beachten
+form-respect-gpc = Global-Privacy-Control-Header (Sec-GPC) beachten
+form-ignore-robots = Bots und Crawler ignorieren
+form-collect-ips = IP-Adressen speichern
+form-link-users = Sitzungen geräteübergreifend über das Login-Kennzeichen verknüpfen
+form-link-users-help = Sendet deine Website ein Login-Kennzeichen, werden Sitzungen damit unter einem gehashten Schlüssel für den Nutzerbericht verknüpft. Standardmäßig aus; beim Ausschalten werden alle Sitzungen entkoppelt.
+form-tracking = Erfassung
+form-collapse-tabs = Doppelte Tabs zu einem Aufruf zusammenfassen
+form-public-badge = Öffentliches Besucher-Badge
+form-public-badge-help = Jeder darf ein SVG-Badge mit den Besuchern dieses Monats und den gerade Aktiven laden, z. B. für eine README.
+form-signed-pixel-ids = Signierte Pixel-Kennungen verlangen
+form-signed-pixel-ids-help = Pixel-Hits mit einer Kennung, etwa E-Mail-Öffnungen je Empfänger, zählen nur, wenn die URL eine gültige Signatur trägt. Signierte URLs erzeugst du über die API.
+form-debug-log = Ingest-Debug-Log führen
+form-debug-log-help = Hält die letzten 100 an diesen Dienst gesendeten Seitenaufrufe fest, und ob sie erfasst wurden oder warum nicht – im Speicher, bis der Server neu startet.
+form-track-errors = JavaScript-Fehler erfassen
+form-track-errors-help = Der Tracker meldet auch nicht abgefangene Fehler und abgelehnte Promises der Seite (Meldung und Anfang des Stacks, wenige pro Seitenaufruf), angezeigt im Fehler-Panel.
+form-heartbeat-frequency = Heartbeat-Intervall (ms)
+form-heartbeat-frequency-help = Wie oft offene Seiten melden, dass sie noch angesehen werden. Leer lassen für den Server-Standard.
+form-idle-timeout = Leerlauf-Timeout (Minuten)
+form-idle-timeout-help = Heartbeats nach so langer Zeit ohne Scrollen, Tippen oder Klicken anhalten, bis der Besucher wieder aktiv ist. 0 hält sie nie an.
+form-active-user-timeout = Online-Timeout (ms)
+form-active-user-timeout-help = Wie lange ein Besucher nach seinem letzten Heartbeat noch als online gilt. Leer lassen für das doppelte Heartbeat-Intervall.
+form-session-timeout = Sitzungs-Timeout (Minuten)
+form-session-timeout-help = Ein Besucher, der nach so langer Zeit ohne Aktivität zurückkommt, beginnt eine neue Sitzung. Leer lassen, damit Sitzungen so lange laufen, wie sich der Server an Besucher erinnert.
+form-bounce-rule = Absprünge
+form-bounce-rule-help = Sitzungen mit mehr als einem Seitenaufruf sind nie Absprünge. Gilt für Sitzungen, die ab jetzt erfasst werden.
+form-bounce-threshold = Schwelle für aktive Zeit (Sekunden)
+form-bounce-threshold-help = Mit der Regel für aktive Zeit ist eine Sitzung mit einer Seite, die mindestens so lange dauert, kein Absprung.
+bounce-rule-single_hit = Jede Sitzung mit nur einer Seite
+bounce-rule-engaged_time = Sitzungen mit einer Seite, die kürzer als die Schwelle sind
+bounce-rule-no_interaction = Sitzungen mit einer Seite ohne Scrollen, Klicken oder Tippen
+form-ignored-ips = Ignorierte IP-Adressen
+form-ignored-ips-help = Kommagetrennte Liste von IP-Adressen oder CIDR-Bereichen, die ignoriert werden
+form-allowed-countries = Erlaubte Länder
+form-blocked-countries = Gesperrte Länder
+form-allowed-asns = Erlaubte Netze (ASN)
+form-blocked-asns = Gesperrte Netze (ASN)
+form-geo-rules-help = Kommagetrennte Ländercodes und AS-Nummern. Sind erlaubte gesetzt, werden nur Besucher aus diesen erfasst; Besucher aus gesperrten nie. Benötigt die GeoIP-Datenbanken; Besucher, die sich nicht orten lassen, werden erfasst.
+form-hide-referrers = Verweise ausblenden (Regex)
+form-hide-referrers-help = Regulärer Ausdruck für Verweise, die in der Statistik ausgeblendet werden
+form-hide-internal-referrers = Interne Verweise ausblenden (von den erlaubten Origins und dem Link des Dienstes)
+form-script-inject = Eigenes JavaScript einbinden
+form-script-inject-placeholder = // Eigenes JS, das mit dem Tracker-Skript ausgeliefert wird
+form-content-groups = Inhaltsgruppen
+form-content-groups-help = Eine Regel pro Zeile, z. B. /blog/* = Blog. Muster, die mit ^ beginnen, sind reguläre Ausdrücke; die erste passende Regel gilt.
+form-path-patterns = Pfad-Normalisierung
+form-path-patterns-help = Ein Muster pro Zeile, z. B. erfasst /users/:id den Pfad /users/123 als /users/:id. Ein :name-Segment passt auf jedes Segment. Gilt nur für neue Aufrufe.
+form-lowercase-paths = Pfade in Kleinbuchstaben
+form-strip-trailing-slash = Schrägstriche am Ende entfernen
+form-save = Änderungen speichern
+form-delete-service = Dienst löschen
+form-tracking-code = Tracking-Code
+form-tracking-code-help = Binde dieses Skript in deine Website ein:
+form-milestone-feed = Meilenstein-Feed
+form-milestone-feed-help = Abonniere diesen Atom-Feed, etwa mit einer RSS-App in Slack, um von monatlichen Sitzungs-Meilensteinen, Rekordtagen und ungewöhnlichen Spitzen oder Einbrüchen zu erfahren. Jede Person mit dem Link kann ihn lesen.
+form-embed = Einbettbare Widgets
+form-embed-help = Zeige dein Besucherdiagramm oder deine Zähler mit einem dieser iframes auf deiner eigenen Website. Hänge ?theme=dark, ?accent=ff8800 oder ?range=7d an die URL, um Aussehen oder Zeitraum zu ändern. Jede Person mit dem Link kann sie sehen.
+form-embed-chart = Diagramm
+form-embed-counters = Zähler
+form-ingest-script = Ingest-Skript
+form-ingest-script-help = Ein Rhai-Skript, das vor dem Speichern jedes Hits läuft. Es kann hit.location, hit.referrer, hit.dimensions und hit.props ändern und verwirft den Hit, wenn es false zurückgibt. Skripte laufen mit Grenzen für Zeit und Speicher; schlägt eines fehl, bleibt der Hit unverändert. Leer lassen, um es abzuschalten.
+form-ingest-script-invalid = Das Skript wurde nicht gespeichert: { $error }
+form-ingest-script-save = Skript speichern
+form-tracking-code-env-help = Zugriffe von localhost, privaten IPs und Staging-Hosts werden als Dev oder Staging erfasst und im Dashboard standardmäßig ausgeblendet. Um die Umgebung selbst festzulegen, hänge sie an die Skript-URL an:
+form-quota = Kontingent
+form-hit-quota = Monatliches Aufrufkontingent
+form-hit-quota-help = Weiche Grenze für Aufrufe pro Kalendermonat (UTC). 0 bedeutet unbegrenzt.
+form-quota-behavior = Wenn das Kontingent aufgebraucht ist
+form-theme = Design
+form-logo-url = Logo-URL
+form-accent-color = Akzentfarbe
+form-theme-mode = Modus
+
+## Ingest-Debug-Log
+debug-page-title = Debug-Log von { $name }
+debug-title = Ingest-Debug-Log
+debug-help = Die letzten an diesen Dienst gesendeten Seitenaufrufe, die neuesten zuerst, und was aus ihnen wurde. Heartbeats sind nicht aufgeführt.
+debug-log-view = Debug-Log öffnen
+debug-off = Dieser Dienst führt kein Debug-Log. Schalte es in den Einstellungen des Dienstes ein, um zu sehen, warum Seitenaufrufe erfasst werden oder nicht.
+debug-empty = Keine Seitenaufrufe, seit das Debug-Log eingeschaltet wurde oder der Server gestartet ist.
+column-time = Zeit
+column-decision = Ergebnis
+column-user-agent = User-Agent
+column-reason = Grund
+data-quality-help = Seitenaufrufe, die den Server erreicht haben, aber nicht erfasst wurden, und warum. Gezählt pro UTC-Tag, damit du Lücken zwischen deinen Server-Logs und dieser Statistik erklären kannst.
+data-quality-empty = In diesem Zeitraum wurden keine Seitenaufrufe abgewiesen.
+data-quality-total = Gesamt
+errors-empty = In diesem Zeitraum wurden keine JavaScript-Fehler gemeldet.
+errors-off = Die Fehlererfassung ist aus. Schalte sie in den Diensteinstellungen ein.
+data-quality-flagged-spam = { $count } in diesem Zeitraum erfasste Seitenaufrufe kamen von Referrer-Spam-Domains und sind markiert.
+debug-origin = von { $origin }
+ingress-decision-accepted = Erfasst
+ingress-decision-service_archived = Dienst archiviert
+ingress-decision-origin_rejected = Origin nicht erlaubt
+ingress-decision-dropped_dnt = Verworfen: Do Not Track
+ingress-decision-dropped_gpc = Verworfen: Global Privacy Control
+ingress-decision-dropped_excluded = Verworfen: Browser ausgeschlossen
+ingress-decision-dropped_unsigned = Verworfen: unsignierte Kennung
+ingress-decision-dropped_prefetch = Verworfen: Prefetch
+ingress-decision-dropped_ip = Verworfen: ignorierte IP
+ingress-decision-dropped_script = Vom Ingest-Skript verworfen
+ingress-decision-dropped_spam = Verworfen: Referrer-Spam
+ingress-decision-dropped_bot = Verworfen: Bot
+ingress-decision-dropped_geo = Verworfen: Land oder Netz ausgeschlossen
+ingress-decision-dropped_fake_bot = Verworfen: falscher Suchmaschinen-Crawler
+ingress-decision-dropped_quota = Verworfen: Hit-Kontingent aufgebraucht
+ingress-decision-dropped_duplicate = Verworfen: wiederholter Seitenaufruf
+
+## Linked users
+users-title = Verknüpfte Nutzer
+users-help = Sitzungen, die über das Login-Kennzeichen deiner Website geräteübergreifend verknüpft sind; es wird nur als Hash gespeichert. Verknüpfte Nutzer werden nur hier gezählt, getrennt von den anonymen Statistiken.
+users-off = Dieser Dienst verknüpft keine Nutzer. Schalte die Verknüpfung in den Einstellungen des Dienstes ein und lass deine Website ein Login-Kennzeichen mit ihren Seitenaufrufen senden.
+users-empty = Keine verknüpften Nutzer in diesem Zeitraum.
+users-users = Nutzer
+users-sessions = Sitzungen
+users-sessions-per-user = Sitzungen pro Nutzer
+users-multi-device = Nutzer auf mehreren Geräten
+users-devices-per-user = Geräte pro Nutzer
+users-devices-help = Ein Gerät ist eine Kombination aus Gerätetyp, Betriebssystem und Browser.
+users-view = Nutzerbericht öffnen
+retention-title = Wiederkehr
+retention-help = Verknüpfte Nutzer, gruppiert nach der Woche ihrer ersten Sitzung, und der Anteil der Nutzer jeder Woche, der in den Wochen danach wiederkam. Nur verknüpfte Nutzer lassen sich von einer Woche zur nächsten wiedererkennen.
+retention-empty = In diesem Zeitraum wurden keine verknüpften Nutzer zum ersten Mal gesehen.
+retention-cohort = Erste Woche
+retention-week = Woche { $n }
+
+## Service deletion
+delete-page-title = { $name } löschen
+delete-title = Dienst löschen
+delete-confirm = Soll { $name } wirklich gelöscht werden? Dies kann nicht rückgängig gemacht werden.
+delete-warning = Alle Sitzungen und Aufrufe dieses Dienstes werden endgültig gelöscht.
+delete-submit = Endgültig löschen
+
+## Excluding your own visits
+exclude-page-title = Meine Besuche auf { $name } ausschließen
+exclude-title = Meine Besuche ausschließen
+exclude-status-excluded = Besuche dieses Browsers auf { $name } werden nicht gezählt.
+exclude-status-included = Besuche dieses Browsers auf { $name } werden gezählt.
+exclude-help = Ein Cookie in diesem Browser weist den Tracker an, ihn zu überspringen. Nach dem Löschen der Cookies muss es erneut gesetzt werden, und zwar in jedem Browser, den du nutzt.
+exclude-submit-exclude = Diesen Browser ausschließen
+exclude-submit-include = Diesen Browser wieder zählen
+exclude-back = Zurück zum Dashboard
+exclude-link = Eigene Besuche in diesem Browser ausschließen
+
+## Accounts and organizations
+account-name = Name
+account-email = E-Mail
+account-password = Passwort
+login-title = Anmelden
+login-submit = Anmelden
+login-invalid = E-Mail oder Passwort ist falsch.
+login-unverified = Bestätige zuerst deine E-Mail-Adresse. Den Link findest du in deinem Posteingang.
+login-resend-verification = Bestätigungslink erneut senden
+login-forgot-password = Passwort vergessen?
+login-signup-link = Konto erstellen
+login-sso = Mit Single Sign-on anmelden
+login-locked = Zu viele fehlgeschlagene Anmeldungen. Versuche es in { $minutes } Minuten erneut.
+signup-title = Konto erstellen
+signup-first-user = Dies ist das erste Konto. Es tritt der Standardorganisation und ihren Diensten bei.
+signup-organization = Name der Organisation
+signup-submit = Konto erstellen
+signup-login-link = Schon ein Konto? Anmelden
+signup-invalid-email = Gib eine gültige E-Mail-Adresse ein.
+signup-password-too-short = Passwörter brauchen mindestens 8 Zeichen.
+signup-email-taken = Mit dieser E-Mail-Adresse gibt es bereits ein Konto.
+signup-invited = Du bist eingeladen, { $name } beizutreten.
+signup-invite-mismatch = Diese Einladung wurde an eine andere E-Mail-Adresse gesendet.
+check-email-title = Prüfe deine E-Mails
+check-email-verify = Wir haben dir einen Link zur Bestätigung deiner E-Mail-Adresse gesendet. Öffne ihn, um dich anzumelden.
+check-email-password-reset = Falls ein Konto diese Adresse verwendet, haben wir ihm einen Link zum Festlegen eines neuen Passworts gesendet.
+error-400 = Diese Anfrage ist ungültig.
+error-401 = Melde dich an, um diese Seite zu sehen.
+error-403 = Deine Rolle erlaubt das nicht.
+error-404 = Diese Seite gibt es nicht, oder sie ist nicht für dich bestimmt.
+error-500 = Bei uns ist etwas schiefgelaufen. Versuch es gleich noch einmal.
+error-back = Zurück zum Dashboard
+password-reset-title = Passwort zurücksetzen
+password-reset-request-help = Gib die E-Mail-Adresse deines Kontos ein, und wir senden dir einen Link zum Festlegen eines neuen Passworts.
+password-reset-send = Link senden
+password-reset-new-password = Neues Passwort
+password-reset-submit = Passwort festlegen
+password-reset-invalid = Dieser Link ist ungültig, abgelaufen oder wurde schon verwendet. Fordere einen neuen an.
+account-title = Konto
+account-login-history = Letzte Anmeldungen
+account-login-time = Zeit
+account-login-ip = IP-Adresse
+account-login-outcome = Ergebnis
+account-preferences = Einstellungen
+account-time-zone = Zeitzone
+account-time-zone-help = IANA-Name wie Europe/Berlin, gilt für Berichte, sofern ein Link keine andere nennt. Leer lassen, um die Zeitzone des jeweiligen Dienstes zu verwenden.
+account-week-start = Wochen beginnen am
+account-hour-cycle = Uhrzeit
+account-language-default = Wie in meiner Sprache üblich
+account-formats-help = Wird auch an Apps gesendet, die Statistiken mit von dir erstellten API-Tokens lesen.
+week-start-monday = Montag
+week-start-sunday = Sonntag
+hour-cycle-h12 = 12 Stunden (3:00 PM)
+hour-cycle-h23 = 24 Stunden (15:00)
+account-settings-saved = Deine Einstellungen wurden gespeichert.
+account-save = Speichern
+login-outcome-success = Angemeldet
+login-outcome-wrong_password = Falsches Passwort
+login-outcome-wrong_code = Falscher Bestätigungscode
+login-outcome-locked = Während der Sperre abgewiesen
+two-factor-title = Zwei-Faktor-Authentifizierung
+two-factor-login-help = Gib den 6-stelligen Code aus deiner Authenticator-App ein.
+two-factor-code = Bestätigungscode
+two-factor-start-over = Mit einem anderen Konto anmelden
+two-factor-invalid-code = Falscher Code. Prüfe, ob die Uhr deiner Authenticator-App stimmt, und versuche es erneut.
+two-factor-expired = Die Anmeldung hat zu lange gedauert. Melde dich erneut an.
+two-factor-on = Die Zwei-Faktor-Authentifizierung ist aktiv. Gib einen aktuellen Code ein, um sie auszuschalten.
+two-factor-setup-help = Füge diesen Schlüssel einer Authenticator-App hinzu und gib den angezeigten Code ein, um die Zwei-Faktor-Authentifizierung einzuschalten.
+two-factor-open-app = In Authenticator-App öffnen
+two-factor-enable = Einschalten
+two-factor-disable = Ausschalten
+two-factor-enabled = Die Zwei-Faktor-Authentifizierung ist aktiv.
+two-factor-disabled = Die Zwei-Faktor-Authentifizierung ist ausgeschaltet.
+organization-subtitle = Einstellungen, Mitglieder und API-Tokens der Organisation
+organization-name = Name der Organisation
+organization-hit-quota-help = Weiche Grenze für Hits pro Kalendermonat (UTC) über alle Dienste. 0 bedeutet unbegrenzt.
+organization-theme-help = Wie das Dashboard für die Mitglieder dieser Organisation aussieht. Leere Felder übernehmen die Einstellungen der Installation.
+organization-theme-invalid = Das Logo muss eine http(s)-URL oder ein mit / beginnender Pfad sein und die Akzentfarbe eine Hex-Farbe wie #5cc265.
+organization-members = Mitglieder
+organization-add-member = Hinzufügen oder einladen
+organization-remove = Entfernen
+organization-api-tokens = API-Tokens
+organization-api-tokens-help = Als "Authorization: Bearer <token>" senden, um die Daten dieser Organisation über die JSON-API zu lesen.
+organization-token-name = Name des Tokens
+organization-create-token = Token erstellen
+organization-token-created = Kopiere diesen Token jetzt. Er wird nicht noch einmal angezeigt.
+organization-revoke = Widerrufen
+organization-create = Organisation erstellen
+organization-invite-sent = Einladung gesendet.
+organization-role = Rolle
+organization-change-role = Ändern
+role-viewer = Betrachter
+role-editor = Bearbeiter
+role-owner = Eigentümer
+mail-send-failed = Die E-Mail konnte nicht gesendet werden. Prüfe die Einstellungen des Mailservers.
+email-invite-subject = Einladung zu { $name } auf shymini
+email-invite-body = Du wurdest eingeladen, { $name } auf shymini beizutreten. Öffne diesen Link, um anzunehmen:
+email-invite-expiry = Die Einladung läuft in 7 Tagen ab.
+email-verify-subject = Bestätige deine E-Mail-Adresse für shymini
+email-verify-body = Öffne diesen Link, um deine E-Mail-Adresse zu bestätigen und dich bei shymini anzumelden:
+email-verify-expiry = Der Link läuft in 2 Tagen ab.
+email-password-reset-subject = Setze dein shymini-Passwort zurück
+email-password-reset-body = Öffne diesen Link, um ein neues shymini-Passwort festzulegen:
+email-password-reset-expiry = Der Link läuft in 1 Stunde ab und funktioniert einmal.
+email-ignore = Falls du diese E-Mail nicht erwartet hast, kannst du sie ignorieren.
+
+## Countries (ISO 3166-1 alpha-2, plus XK for Kosovo as reported by MaxMind)
+country-AD = Andorra
+country-AE = Vereinigte Arabische Emirate
+country-AF = Afghanistan
+country-AG = Antigua und Barbuda
+country-AI = Anguilla
+country-AL = Albanien
+country-AM = Armenien
+country-AO = Angola
+country-AQ = Antarktis
+country-AR = Argentinien
+country-AS = Amerikanisch-Samoa
+country-AT = Österreich
+country-AU = Australien
+country-AW = Aruba
+country-AX = Ålandinseln
+country-AZ = Aserbaidschan
+country-BA = Bosnien und Herzegowina
+country-BB = Barbados
+country-BD = Bangladesch
+country-BE = Belgien
+country-BF = Burkina Faso
+country-BG = Bulgarien
+country-BH = Bahrain
+country-BI = Burundi
+country-BJ = Benin
+country-BL = Saint-Barthélemy
+country-BM = Bermuda
+country-BN = Brunei
+country-BO = Bolivien
+country-BQ = Karibische Niederlande
+country-BR = Brasilien
+country-BS = Bahamas
+country-BT = Bhutan
+country-BV = Bouvetinsel
+country-BW = Botsuana
+country-BY = Belarus
+country-BZ = Belize
+country-CA = Kanada
+country-CC = Kokos-(Keeling-)Inseln
+country-CD = Demokratische Republik Kongo
+country-CF = Zentralafrikanische Republik
+country-CG = Republik Kongo
+country-CH = Schweiz
+country-CI = Côte d'Ivoire
+country-CK = Cookinseln
+country-CL = Chile
+country-CM = Kamerun
+country-CN = China
+country-CO = Kolumbien
+country-CR = Costa Rica
+country-CU = Kuba
+country-CV = Kap Verde
+country-CW = Curaçao
+country-CX = Weihnachtsinsel
+country-CY = Zypern
+country-CZ = Tschechien
+country-DE = Deutschland
+country-DJ = Dschibuti
+country-DK = Dänemark
+country-DM = Dominica
+country-DO = Dominikanische Republik
+country-DZ = Algerien
+country-EC = Ecuador
+country-EE = Estland
+country-EG = Ägypten
+country-EH = Westsahara
+country-ER = Eritrea
+country-ES = Spanien
+country-ET = Äthiopien
+country-FI = Finnland
+country-FJ = Fidschi
+country-FK = Falklandinseln
+country-FM = Mikronesien
+country-FO = Färöer
+country-FR = Frankreich
+country-GA = Gabun
+country-GB = Vereinigtes Königreich
+country-GD = Grenada
+country-GE = Georgien
+country-GF = Französisch-Guyana
+country-GG = Guernsey
+country-GH = Ghana
+country-GI = Gibraltar
+country-GL = Grönland
+country-GM = Gambia
+country-GN = Guinea
+country-GP = Guadeloupe
+country-GQ = Äquatorialguinea
+country-GR = Griechenland
+country-GS = South Georgia und die Südlichen Sandwichinseln
+country-GT = Guatemala
+country-GU = Guam
+country-GW = Guinea-Bissau
+country-GY = Guyana
+country-HK = Hongkong
+country-HM = Heard und McDonaldinseln
+country-HN = Honduras
+country-HR = Kroatien
+country-HT = Haiti
+country-HU = Ungarn
+country-ID = Indonesien
+country-IE = Irland
+country-IL = Israel
+country-IM = Insel Man
+country-IN = Indien
+country-IO = Britisches Territorium im Indischen Ozean
+country-IQ = Irak
+country-IR = Iran
+country-IS = Island
+country-IT = Italien
+country-JE = Jersey
+country-JM = Jamaika
+country-JO = Jordanien
+country-JP = Japan
+country-KE = Kenia
+country-KG = Kirgisistan
+country-KH = Kambodscha
+country-KI = Kiribati
+country-KM = Komoren
+country-KN = St. Kitts und Nevis
+country-KP = Nordkorea
+country-KR = Südkorea
+country-KW = Kuwait
+country-KY = Kaimaninseln
+country-KZ = Kasachstan
+country-LA = Laos
+country-LB = Libanon
+country-LC = St. Lucia
+country-LI = Liechtenstein
+country-LK = Sri Lanka
+country-LR = Liberia
+country-LS = Lesotho
+country-LT = Litauen
+country-LU = Luxemburg
+country-LV = Lettland
+country-LY = Libyen
+country-MA = Marokko
+country-MC = Monaco
+country-MD = Moldau
+country-ME = Montenegro
+country-MF = Saint-Martin
+country-MG = Madagaskar
+country-MH = Marshallinseln
+country-MK = Nordmazedonien
+country-ML = Mali
+country-MM = Myanmar
+country-MN = Mongolei
+country-MO = Macao
+country-MP = Nördliche Marianen
+country-MQ = Martinique
+country-MR = Mauretanien
+country-MS = Montserrat
+country-MT = Malta
+country-MU = Mauritius
+country-MV = Malediven
+country-MW = Malawi
+country-MX = Mexiko
+country-MY = Malaysia
+country-MZ = Mosambik
+country-NA = Namibia
+country-NC = Neukaledonien
+country-NE = Niger
+country-NF = Norfolkinsel
+country-NG = Nigeria
+country-NI = Nicaragua
+country-NL = Niederlande
+country-NO = Norwegen
+country-NP = Nepal
+country-NR = Nauru
+country-NU = Niue
+country-NZ = Neuseeland
+country-OM = Oman
+country-PA = Panama
+country-PE = Peru
+country-PF = Französisch-Polynesien
+country-PG = Papua-Neuguinea
+country-PH = Philippinen
+country-PK = Pakistan
+country-PL = Polen
+country-PM = St. Pierre und Miquelon
+country-PN = Pitcairninseln
+country-PR = Puerto Rico
+country-PS = Palästina
+country-PT = Portugal
+country-PW = Palau
+country-PY = Paraguay
+country-QA = Katar
+country-RE = Réunion
+country-RO = Rumänien
+country-RS = Serbien
+country-RU = Russland
+country-RW = Ruanda
+country-SA = Saudi-Arabien
+country-SB = Salomoninseln
+country-SC = Seychellen
+country-SD = Sudan
+country-SE = Schweden
+country-SG = Singapur
+country-SH = St. Helena
+country-SI = Slowenien
+country-SJ = Svalbard und Jan Mayen
+country-SK = Slowakei
+country-SL = Sierra Leone
+country-SM = San Marino
+country-SN = Senegal
+country-SO = Somalia
+country-SR = Suriname
+country-SS = Südsudan
+country-ST = São Tomé und Príncipe
+country-SV = El Salvador
+country-SX = Sint Maarten
+country-SY = Syrien
+country-SZ = Eswatini
+country-TC = Turks- und Caicosinseln
+country-TD = Tschad
+country-TF = Französische Süd- und Antarktisgebiete
+country-TG = Togo
+country-TH = Thailand
+country-TJ = Tadschikistan
+country-TK = Tokelau
+country-TL = Timor-Leste
+country-TM = Turkmenistan
+country-TN = Tunesien
+country-TO = Tonga
+country-TR = Türkei
+country-TT = Trinidad und Tobago
+country-TV = Tuvalu
+country-TW = Taiwan
+country-TZ = Tansania
+country-UA = Ukraine
+country-UG = Uganda
+country-UM = Amerikanische Überseeinseln
+country-US = Vereinigte Staaten
+country-UY = Uruguay
+country-UZ = Usbekistan
+country-VA = Vatikanstadt
+country-VC = St. Vincent und die Grenadinen
+country-VE = Venezuela
+country-VG = Britische Jungferninseln
+country-VI = Amerikanische Jungferninseln
+country-VN = Vietnam
+country-VU = Vanuatu
+country-WF = Wallis und Futuna
+country-WS = Samoa
+country-XK = Kosovo
+country-YE = Jemen
+country-YT = Mayotte
+country-ZA = Südafrika
+country-ZM = Sambia
+country-ZW = Simbabwe
+
+## Continents
+continent-AF = Afrika
+continent-AN = Antarktika
+continent-AS = Asien
+continent-EU = Europa
+continent-NA = Nordamerika
+continent-OC = Ozeanien
+continent-SA = Südamerika
+
+## System status
+admin-status-title = Systemstatus
+admin-status-subtitle = Zustand dieser shymini-Installation
+admin-maintenance = Datenbankwartung
+admin-maintenance-schedule = Läuft täglich um { $time }
+admin-maintenance-off = Nicht geplant; setze SHYMINI__MAINTENANCE_HOUR, um sie täglich auszuführen
+admin-maintenance-run = Jetzt ausführen
+admin-maintenance-task = Aufgabe
+admin-maintenance-last-run = Letzter Lauf
+admin-maintenance-duration = Dauer
+admin-maintenance-result = Ergebnis
+admin-maintenance-ok = OK
+admin-maintenance-never = Noch nie gelaufen
+maintenance-task-compact_rollups = Alte Tagesauswertungen entfernen
+maintenance-task-prune_caches = Caches bereinigen
+maintenance-task-optimize = Datenbank optimieren
+admin-version = Version
+admin-build-release = { $backend }, Release-Build
+admin-build-debug = { $backend }, Debug-Build
+admin-uptime = Laufzeit
+admin-database-size = Datenbankgröße
+admin-hits-last-hour = Aufrufe in der letzten Stunde
+admin-sessions-last-hour = Sitzungen in der letzten Stunde
+admin-tables = Tabellen
+admin-table = Tabelle
+admin-rows = Zeilen
+admin-caches = Caches
+admin-cache = Cache
+admin-cache-entries = Einträge
+admin-cache-hits = Treffer
+admin-cache-misses = Nicht gefunden
+admin-cache-hit-rate = Trefferquote
+admin-tasks = Hintergrundaufgaben
+admin-task = Aufgabe
+admin-task-state = Zustand
+admin-task-last-success = Letzter Erfolg
+admin-task-last-error = Letzter Fehler
+admin-task-off = Aus
+admin-task-waiting = Noch nicht gelaufen
+admin-task-ok = OK
+admin-task-failing = Fehlerhaft
+admin-origin-mismatches = Abgelehnte Origins
+admin-origin-mismatches-help = Tracking-Anfragen, die seit dem Serverstart abgelehnt wurden, weil ihr Origin für den Dienst nicht erlaubt ist
+admin-origin-service = Dienst
+admin-origin-count = Anfragen
+admin-origin-last = Letzter Origin
+admin-origin-last-seen = Zuletzt gesehen
+admin-origin-none = Kein Origin
+background-task-monitor = Verfügbarkeitsprüfungen
+background-task-crawler = Sitemap-Crawls
+background-task-hit_filter_flush = Speichern der Idempotenzfilter
+background-task-top_pages = Aktualisierung der Top-Seiten
+background-task-maintenance = Datenbankwartung
+background-task-update_check = Suche nach Updates
+background-task-hit_partitions = Partitionen der Aufrufe
+background-task-referrer_spam = Referrer-Spam-Liste
+update-available = shymini { $version } ist verfügbar.
+update-release-notes = Versionshinweise
@@ -0,0 +1,861 @@
+# English dashboard messages. This is the reference catalog: every other
+# locale must define the same keys.
+
+## Layout
+app-title = shymini Analytics
+nav-dashboard = Dashboard
+nav-new-service = + New Service
+nav-back-to = ← Back to { $name }
+nav-organization = Organization
+nav-account = Account
+nav-logout = Log out
+nav-search = Search…
+footer-powered-by = Powered by
+
+## Common
+common-unknown = Unknown
+common-direct = Direct
+common-cancel = Cancel
+common-filter = Filter
+common-to = to
+common-loading = Loading…
+common-yes = Yes
+common-no = No
+common-invalid-range = Invalid range
+common-start-before-end = Start must be before end
+common-url-filter = URL regex filter
+common-view-all = View all
+pagination-page = Page { $page }
+pagination-previous = Previous
+pagination-next = Next
+
+## Service list
+services-title = Services
+services-subtitle = Tracked websites of { $name }
+services-empty-title = No services yet
+services-empty-body = Create your first service to start tracking analytics.
+services-create = Create Service
+services-sessions-24h = Sessions (24h)
+services-hits-24h = Hits (24h)
+services-trend-7d = Last 7 days
+status-active = Active
+status-archived = Archived
+install-found = Tracker installed
+install-issue-no_link = No link to check
+install-issue-unreachable = Site unreachable
+install-issue-http_status = Site returned an error
+install-issue-tracker_missing = Tracker not found
+install-issue-wrong_tracking_id = Tracker of another service
+install-issue-mixed_content = Tracker loaded over HTTP
+uptime-percent = { $percent }% up (24h)
+uptime-down = Site down
+badge-visitors = visitors
+badge-month = { $count } this month
+badge-online = { $count } online
+embed-online = Online now
+expiry-certificate = Certificate expires in { $days } days
+expiry-certificate-expired = Certificate expired
+expiry-domain = Domain expires in { $days } days
+expiry-domain-expired = Domain expired
+
+## Service dashboard
+service-manage = Manage
+service-campaigns = Campaigns
+service-links = Links
+service-users = Users
+service-retention = Retention
+service-default-view = Default view
+service-time-zone = Time zone of the dates
+service-custom-range = Custom range
+date-range-24h = Last 24 hours
+date-range-7d = Last 7 days
+date-range-30d = Last 30 days
+date-range-90d = Last 90 days
+date-range-365d = Last 365 days
+environment-production = Production
+environment-staging = Staging
+environment-dev = Dev
+environment-all = All environments
+service-customize = Customize & save view
+service-customize-help = Pick the panels to show and their order. The current date range and URL filter are saved with the view.
+service-view-name = View name
+service-save-view = Save view
+service-delete-view = Delete current view
+service-segments = Segments
+service-segments-help = Narrow the stats, chart and sessions to visitors matching every condition. Rows left blank are skipped.
+service-segment-all = All visitors
+service-segment-name = Segment name
+service-save-segment = Save segment
+service-delete-segment = Delete current segment
+segment-field-country = Country
+segment-field-device = Device
+segment-field-browser = Browser
+segment-field-os = OS
+segment-field-referrer = Referrer
+segment-field-page = Page
+segment-op-is = is
+segment-op-is_not = is not
+segment-op-contains = contains
+filters-active = Filtered by
+filters-clear = Remove filter
+service-get-started = Get Started
+service-get-started-script = Add this script to your website to start tracking:
+service-get-started-pixel = Or use the pixel tracker for no-JS tracking:
+
+## Stat cards
+stat-sessions = Sessions
+stat-sessions-help = Unique visitors identified by a hash of IP address and browser fingerprint.
+stat-hits = Hits
+stat-hits-help = Total page views across all sessions in the selected time period.
+stat-load-time = Load Time
+stat-load-time-help = Average page load time in milliseconds, measured from navigation start to page load complete.
+stat-bounce-rate = Bounce Rate
+stat-bounce-rate-help = Percentage of sessions where the visitor viewed only one page before leaving.
+stat-duration = Duration
+stat-duration-help = Average time visitors spend on your site per session, measured via periodic heartbeats.
+stat-hits-per-session = Hits/Session
+stat-hits-per-session-help = Average number of pages viewed per session. Higher values indicate more engaged visitors.
+stat-unique-visitors = ≈ { $count } visitors
+stat-unique-visitors-help = Distinct visitors on the days of the selected range, counted once however many sessions they had. Exact up to 512 a day, estimated above.
+
+## PDF reports
+report-generated = Generated by shymini on { $time }
+
+## Milestone feeds
+milestone-feed-title = { $service } milestones
+milestone-monthly-sessions = { $count } sessions this month
+milestone-record-day = Record day: { $count } sessions
+milestone-spike = Traffic spike: { $count } sessions
+milestone-drop = Traffic drop: { $count } sessions
+milestone-expected = About { $count } expected from the four weeks before
+
+## Dashboard panels
+panel-chart = Chart
+panel-locations = Top Pages
+panel-countries = Countries
+panel-referrers = Referrers
+panel-browsers = Browsers
+panel-operating_systems = Operating Systems
+panel-device_types = Device Types
+panel-sessions = Recent Sessions
+panel-usage = Usage
+panel-content_groups = Content Groups
+panel-session_distribution = Session Distribution
+panel-dimensions = Dimensions
+panel-data_quality = Data Quality
+panel-errors = Errors
+
+## Table columns
+column-location = Location
+column-hits = Hits
+column-sessions = Sessions
+column-country = Country
+column-source = Source
+column-browser = Browser
+column-os = OS
+column-type = Type
+column-session = Session
+column-started = Started
+column-last-seen = Last Seen
+column-device = Device
+column-page = Page
+column-referrer = Referrer
+column-tracker = Tracker
+column-load-time = Load Time
+column-heartbeats = Heartbeats
+column-month = Month
+column-dropped = Dropped
+column-duplicates = Duplicates
+column-duplicates-help = Repeated page loads recognized after the cache had forgotten them, and not counted again
+column-error = Error
+column-reports = Reports
+column-group = Group
+column-duration = Duration
+column-pages = Pages
+
+## Sessions and locations
+sessions-title = Sessions
+sessions-empty = No sessions found
+locations-title = All Locations
+locations-empty = No locations found
+locations-orphans = Orphan pages
+locations-orphans-help = Pages in the sitemap without a hit in this range
+content-groups-other = Other pages
+content-groups-none = No content groups yet. Add rules in the service settings.
+dimensions-none = No custom dimensions in this period. The tracker sends them with page views.
+
+## Email campaigns and tracked links
+campaigns-title = Campaigns
+campaigns-empty = No campaign opens in this range. Give pixels identifiers such as `spring-sale:reader@example.com` to report opens by campaign.
+campaigns-not-found = This campaign was not opened in this range.
+campaigns-open-times = Open times
+campaigns-open-times-help = Time since the campaign's first open
+campaigns-recipients = Recipients
+column-campaign = Campaign
+column-recipient = Recipient
+column-opens = Opens
+column-users = Users
+column-devices = Devices
+column-unique-opens = Unique opens
+column-first-open = First open
+column-last-open = Last open
+column-since-first-open = After
+links-title = Tracked links
+links-empty = No tracked links yet. Each one gets a short URL that counts clicks before sending visitors on.
+links-add = Add link
+links-target = Target URL
+links-campaign-help = With the campaign of your pixel identifiers (`spring-sale` of `spring-sale:reader@example.com`), the click-through rate compares unique clicks with the campaign's unique opens.
+links-delete = Delete
+links-name = Name
+links-slug = Short link
+links-slug-help = Optional: 3 to 64 letters, digits, `-` or `_` after /r/. Left empty, a random one is picked.
+links-clicks-per-day = Clicks per day
+links-qr-code = QR code
+links-qr-download = Download QR code
+column-link = Link
+column-clicks = Clicks
+column-unique-clicks = Unique clicks
+column-click-through-rate = Click-through rate
+
+## Search
+search-title = Search
+search-results-for = Results for “{ $query }”
+search-help = Search service names, page paths, referrer domains and session identifiers.
+search-empty = Nothing found
+search-column-kind = Type
+search-column-match = Match
+search-column-service = Service
+search-kind-service = Service
+search-kind-page = Page
+search-kind-referrer = Referrer
+search-kind-session = Session
+
+## Session detail
+session-title = Session
+session-details = Session Details
+session-info = Session Info
+session-id = Session ID
+session-identifier = Identifier
+session-bounce = Bounce
+session-ended = Left
+session-props = Properties
+session-device = Device
+session-browser = Browser
+session-os = Operating System
+session-device-type = Device Type
+session-location = Location
+session-country = Country
+session-asn = ASN
+session-time-zone = Time Zone
+session-ip = IP Address
+session-page-views = Page Views ({ $count })
+session-no-page-views = No page views recorded
+session-initial = Initial
+session-prefetched = Prefetched
+session-prefetched-help = The browser loaded this page in advance; the visitor may not have opened it
+session-referrer-spam = Referrer spam
+session-referrer-spam-help = The referrer is on the referrer spam list
+session-user-agent = User Agent
+
+## Usage and quotas
+usage-hits-this-month = Hits this month
+usage-unlimited = no quota
+usage-exceeded-keep = Quota used up. Everything is still being recorded.
+usage-exceeded-sample = Quota used up. Only a sample of visitors is being recorded.
+usage-exceeded-drop = Quota used up. New traffic is dropped until next month.
+quota-behavior-keep = Keep recording
+quota-behavior-sample = Record a sample of visitors
+quota-behavior-drop = Stop recording until next month
+theme-mode-default = Same as the install
+theme-mode-light = Light
+theme-mode-dark = Dark
+
+## Service forms
+form-create-title = Create New Service
+form-create-subtitle = Set up a new website for analytics tracking
+form-manage-title = Manage Service
+form-manage-page-title = Manage { $name }
+form-manage-subtitle = Update settings for { $name }
+form-name = Service Name *
+form-name-placeholder = My Website
+form-link = Website URL
+form-origins = Allowed Origins
+form-origins-placeholder = * or https://example.com,https://www.example.com
+form-origins-help = Comma-separated list of allowed origins for CORS, or * for all
+form-time-zone = Time Zone
+form-time-zone-help = IANA name such as Europe/Berlin that reports default to, unless the viewer has set their own. Leave blank for Pacific Time.
+form-default-range = Default Date Range
+form-default-range-help = What reports show until a viewer picks a range of their own
+form-week-start = Weeks Start On
+form-week-start-default = As usual in the viewer's language
+form-week-start-help = Where the weeks of long-range charts begin, numbered by ISO 8601 when on Monday. Viewers who picked a day on their account page see theirs.
+form-privacy = Privacy Settings
+form-respect-dnt = Respect Do Not Track (DNT) header
+form-respect-gpc = Respect Global Privacy Control (Sec-GPC) header
+form-ignore-robots = Ignore bots and crawlers
+form-collect-ips = Collect IP addresses
+form-link-users = Link sessions across devices by login identifier
+form-link-users-help = When your site sends a login identifier, sessions sharing it are linked under a hashed key for the users report. Off by default; turning it off unlinks every session.
+form-tracking = Tracking Settings
+form-collapse-tabs = Collapse duplicate tabs into a single hit
+form-public-badge = Public visitor badge
+form-public-badge-help = Anyone may load an SVG badge with this month's visitors and who is online, e.g. for a README.
+form-signed-pixel-ids = Require signed pixel identifiers
+form-signed-pixel-ids-help = Pixel hits with an identifier, such as email opens per recipient, only count when the URL carries a valid signature. Mint signed URLs through the API.
+form-debug-log = Keep an ingest debug log
+form-debug-log-help = Keeps the last 100 page views sent to this service and whether they were recorded or why they were dropped, in memory until the server restarts.
+form-track-errors = Track JavaScript errors
+form-track-errors-help = The tracker also reports uncaught errors and rejected promises on the page (message and top of the stack, a few per page view), shown in the Errors panel.
+form-heartbeat-frequency = Heartbeat interval (ms)
+form-heartbeat-frequency-help = How often open pages report that they are still being viewed. Leave blank for the server default.
+form-idle-timeout = Idle timeout (minutes)
+form-idle-timeout-help = Stop heartbeats after this long without scrolling, typing or clicking, until the visitor is active again. 0 never stops them.
+form-active-user-timeout = Online timeout (ms)
+form-active-user-timeout-help = How long after its last heartbeat a visitor still counts as online. Leave blank for twice the heartbeat interval.
+form-session-timeout = Session timeout (minutes)
+form-session-timeout-help = A visitor who comes back after this long without activity starts a new session. Leave blank to keep sessions going for as long as the server remembers visitors.
+form-bounce-rule = Bounces
+form-bounce-rule-help = Sessions with more than one page view are never bounces. Applies to sessions recorded from now on.
+form-bounce-threshold = Engaged time threshold (seconds)
+form-bounce-threshold-help = With the engaged-time rule, a single-page session this long or longer is not a bounce.
+bounce-rule-single_hit = Every single-page session
+bounce-rule-engaged_time = Single-page sessions shorter than the threshold
+bounce-rule-no_interaction = Single-page sessions without scrolling, clicking or typing
+form-ignored-ips = Ignored IP Addresses
+form-ignored-ips-help = Comma-separated list of IP addresses or CIDR ranges to ignore
+form-allowed-countries = Allowed Countries
+form-blocked-countries = Blocked Countries
+form-allowed-asns = Allowed Networks (ASN)
+form-blocked-asns = Blocked Networks (ASN)
+form-geo-rules-help = Comma-separated country codes and AS numbers. With allowed ones set, only visitors from them are recorded; visitors from blocked ones never are. Needs the GeoIP databases; visitors who can't be located are recorded.
+form-hide-referrers = Hide Referrers Matching (Regex)
+form-hide-referrers-help = Regular expression to hide referrers from stats
+form-hide-internal-referrers = Hide internal referrers (from the allowed origins and the service link)
+form-script-inject = Custom JavaScript Inject
+form-script-inject-placeholder = // Custom JS to inject with tracker script
+form-content-groups = Content Groups
+form-content-groups-help = One rule per line, e.g. /blog/* = Blog. Patterns starting with ^ are regular expressions; the first matching rule wins.
+form-path-patterns = Path Normalization
+form-path-patterns-help = One pattern per line, e.g. /users/:id records /users/123 as /users/:id. A :name segment matches any segment. Applies to new hits only.
+form-lowercase-paths = Lowercase paths
+form-strip-trailing-slash = Strip trailing slashes
+form-save = Save Changes
+form-delete-service = Delete Service
+form-tracking-code = Tracking Code
+form-tracking-code-help = Add this script to your website:
+form-milestone-feed = Milestone Feed
+form-milestone-feed-help = Subscribe to this Atom feed, for example with a Slack RSS app, to hear about monthly session milestones, record days and unusual spikes or drops. Anyone with the link can read it.
+form-embed = Embeddable Widgets
+form-embed-help = Show your visitor chart or counters on your own site with one of these iframes. Add ?theme=dark, ?accent=ff8800 or ?range=7d to the URL to change their look or range. Anyone with the link can see them.
+form-embed-chart = Chart
+form-embed-counters = Counters
+form-ingest-script = Ingest Script
+form-ingest-script-help = A Rhai script run on every hit before it is recorded. It may change hit.location, hit.referrer, hit.dimensions and hit.props, and drops the hit by returning false. Scripts run with limits on time and memory; one that fails leaves the hit unchanged. Leave empty to turn it off.
+form-ingest-script-invalid = The script was not saved: { $error }
+form-ingest-script-save = Save Script
+form-tracking-code-env-help = Traffic from localhost, private IPs and staging hosts is recorded as dev or staging and left out of the dashboard by default. To pick the environment yourself, add it to the script URL:
+form-quota = Quota
+form-hit-quota = Monthly hit quota
+form-hit-quota-help = Soft limit on hits per calendar month (UTC). 0 means unlimited.
+form-quota-behavior = When the quota is used up
+form-theme = Theme
+form-logo-url = Logo URL
+form-accent-color = Accent color
+form-theme-mode = Mode
+
+## Ingest debug log
+debug-page-title = Debug log of { $name }
+debug-title = Ingest debug log
+debug-help = The latest page views sent to this service, newest first, and what became of them. Heartbeats aren't listed.
+debug-log-view = Open the debug log
+debug-off = This service keeps no debug log. Turn it on in the service's settings to see why page views are or aren't recorded.
+debug-empty = No page views since the debug log was turned on or the server started.
+column-time = Time
+column-decision = Outcome
+column-user-agent = User agent
+column-reason = Reason
+data-quality-help = Page views that reached the server but weren't recorded, and why. Counted per UTC day, so they explain gaps between your server logs and these stats.
+data-quality-empty = No page views were turned away in this period.
+data-quality-total = Total
+errors-empty = No JavaScript errors were reported in this period.
+errors-off = Error tracking is off. Turn it on in the service settings.
+data-quality-flagged-spam = { $count } page views recorded in this period came from referrer spam domains and are flagged.
+debug-origin = from { $origin }
+ingress-decision-accepted = Recorded
+ingress-decision-service_archived = Service archived
+ingress-decision-origin_rejected = Origin not allowed
+ingress-decision-dropped_dnt = Dropped: Do Not Track
+ingress-decision-dropped_gpc = Dropped: Global Privacy Control
+ingress-decision-dropped_excluded = Dropped: browser excluded
+ingress-decision-dropped_unsigned = Dropped: unsigned identifier
+ingress-decision-dropped_prefetch = Dropped: prefetch
+ingress-decision-dropped_ip = Dropped: ignored IP
+ingress-decision-dropped_script = Dropped by the ingest script
+ingress-decision-dropped_spam = Dropped: referrer spam
+ingress-decision-dropped_bot = Dropped: bot
+ingress-decision-dropped_geo = Dropped: country or network excluded
+ingress-decision-dropped_fake_bot = Dropped: fake search engine crawler
+ingress-decision-dropped_quota = Dropped: hit quota used up
+ingress-decision-dropped_duplicate = Dropped: repeated page load
+
+## Linked users
+users-title = Linked users
+users-help = Sessions linked across devices by the login identifier your site sends, which is only stored as a hash. Linked users are counted here alone, apart from the anonymous statistics.
+users-off = This service doesn't link users. Turn linking on in the service's settings and have your site send a login identifier with its page views.
+users-empty = No linked users in this range.
+users-users = Users
+users-sessions = Sessions
+users-sessions-per-user = Sessions per user
+users-multi-device = Users on several devices
+users-devices-per-user = Devices per user
+users-devices-help = A device is a combination of device type, operating system and browser.
+users-view = Open the users report
+retention-title = Retention
+retention-help = Linked users grouped by the week of their first session, and the share of each week's users that came back in each week after. Only linked users can be recognized from one week to the next.
+retention-empty = No linked users were first seen in this range.
+retention-cohort = First week
+retention-week = Week { $n }
+
+## Service deletion
+delete-page-title = Delete { $name }
+delete-title = Delete Service
+delete-confirm = Are you sure you want to delete { $name }? This action cannot be undone.
+delete-warning = All sessions and hits associated with this service will be permanently deleted.
+delete-submit = Delete Permanently
+
+## Excluding your own visits
+exclude-page-title = Exclude my visits to { $name }
+exclude-title = Exclude My Visits
+exclude-status-excluded = This browser's visits to { $name } are not counted.
+exclude-status-included = This browser's visits to { $name } are counted.
+exclude-help = A cookie in this browser tells the tracker to skip it. It has to be set again after clearing cookies, and separately in every browser you use.
+exclude-submit-exclude = Exclude this browser
+exclude-submit-include = Count this browser again
+exclude-back = Back to dashboard
+exclude-link = Exclude your own visits in this browser
+
+## Accounts and organizations
+account-name = Name
+account-email = Email
+account-password = Password
+login-title = Log in
+login-submit = Log in
+login-invalid = Wrong email or password.
+login-unverified = Verify your email address first. Check your inbox for the link.
+login-resend-verification = Send the verification link again
+login-forgot-password = Forgot your password?
+login-signup-link = Create an account
+login-sso = Log in with single sign-on
+login-locked = Too many failed logins. Try again in { $minutes } minutes.
+signup-title = Create an account
+signup-first-user = This is the first account. It joins the default organization and its services.
+signup-organization = Organization name
+signup-submit = Create account
+signup-login-link = Already have an account? Log in
+signup-invalid-email = Enter a valid email address.
+signup-password-too-short = Passwords need at least 8 characters.
+signup-email-taken = An account with this email already exists.
+signup-invited = You're invited to join { $name }.
+signup-invite-mismatch = This invitation was sent to a different email address.
+check-email-title = Check your email
+check-email-verify = We sent you a link to verify your email address. Open it to log in.
+check-email-password-reset = If an account uses that address, we sent it a link to choose a new password.
+error-400 = This request isn't valid.
+error-401 = Log in to see this page.
+error-403 = Your role doesn't allow this.
+error-404 = This page doesn't exist, or isn't yours to see.
+error-500 = Something went wrong on our side. Try again in a moment.
+error-back = Back to the dashboard
+password-reset-title = Reset password
+password-reset-request-help = Enter your account's email address and we'll send you a link to choose a new password.
+password-reset-send = Send reset link
+password-reset-new-password = New password
+password-reset-submit = Set password
+password-reset-invalid = This reset link is invalid, expired or already used. Request a new one.
+account-title = Account
+account-login-history = Recent logins
+account-login-time = Time
+account-login-ip = IP address
+account-login-outcome = Result
+account-preferences = Preferences
+account-time-zone = Time zone
+account-time-zone-help = IANA name such as Europe/Berlin, used for reports unless a link names another. Leave blank to use each service's time zone.
+account-week-start = Weeks start on
+account-hour-cycle = Clock
+account-language-default = As usual in my language
+account-formats-help = Also sent to apps reading stats with API tokens you create.
+week-start-monday = Monday
+week-start-sunday = Sunday
+hour-cycle-h12 = 12-hour (3:00 PM)
+hour-cycle-h23 = 24-hour (15:00)
+account-settings-saved = Your preferences were saved.
+account-save = Save
+login-outcome-success = Logged in
+login-outcome-wrong_password = Wrong password
+login-outcome-wrong_code = Wrong two-factor code
+login-outcome-locked = Refused while locked out
+two-factor-title = Two-factor authentication
+two-factor-login-help = Enter the 6-digit code from your authenticator app.
+two-factor-code = Authentication code
+two-factor-start-over = Log in with a different account
+two-factor-invalid-code = Wrong code. Check that your authenticator app's clock is right and try again.
+two-factor-expired = The login took too long. Log in again.
+two-factor-on = Two-factor authentication is on. Enter a current code to turn it off.
+two-factor-setup-help = Add this secret to an authenticator app, then enter the code it shows to turn two-factor authentication on.
+two-factor-open-app = Open in authenticator app
+two-factor-enable = Turn on
+two-factor-disable = Turn off
+two-factor-enabled = Two-factor authentication is on.
+two-factor-disabled = Two-factor authentication is off.
+organization-subtitle = Organization settings, members and API tokens
+organization-name = Organization name
+organization-hit-quota-help = Soft limit on hits per calendar month (UTC) across all services. 0 means unlimited.
+organization-theme-help = How the dashboard looks to this organization's members. Leave a field empty to use the install's.
+organization-theme-invalid = The logo must be an http(s) URL or a path starting with /, and the accent color a hex color such as #5cc265.
+organization-members = Members
+organization-add-member = Add or invite
+organization-remove = Remove
+organization-api-tokens = API tokens
+organization-api-tokens-help = Send as "Authorization: Bearer <token>" to read this organization's data from the JSON API.
+organization-token-name = Token name
+organization-create-token = Create token
+organization-token-created = Copy this token now. It will not be shown again.
+organization-revoke = Revoke
+organization-create = Create organization
+organization-invite-sent = Invitation sent.
+organization-role = Role
+organization-change-role = Change
+role-viewer = Viewer
+role-editor = Editor
+role-owner = Owner
+mail-send-failed = The email could not be sent. Check the mail server settings.
+email-invite-subject = You're invited to { $name } on shymini
+email-invite-body = You've been invited to join { $name } on shymini. Open this link to accept:
+email-invite-expiry = The invitation expires in 7 days.
+email-verify-subject = Verify your email address for shymini
+email-verify-body = Open this link to verify your email address and log in to shymini:
+email-verify-expiry = The link expires in 2 days.
+email-password-reset-subject = Reset your shymini password
+email-password-reset-body = Open this link to choose a new shymini password:
+email-password-reset-expiry = The link expires in 1 hour and works once.
+email-ignore = If you didn't expect this email, you can ignore it.
+
+## Countries (ISO 3166-1 alpha-2, plus XK for Kosovo as reported by MaxMind)
+country-AD = Andorra
+country-AE = United Arab Emirates
+country-AF = Afghanistan
+country-AG = Antigua and Barbuda
+country-AI = Anguilla
+country-AL = Albania
+country-AM = Armenia
+country-AO = Angola
+country-AQ = Antarctica
+country-AR = Argentina
+country-AS = American Samoa
+country-AT = Austria
+country-AU = Australia
+country-AW = Aruba
+country-AX = Åland Islands
+country-AZ = Azerbaijan
+country-BA = Bosnia and Herzegovina
+country-BB = Barbados
+country-BD = Bangladesh
+country-BE = Belgium
+country-BF = Burkina Faso
+country-BG = Bulgaria
+country-BH = Bahrain
+country-BI = Burundi
+country-BJ = Benin
+country-BL = Saint Barthélemy
+country-BM = Bermuda
+country-BN = Brunei
+country-BO = Bolivia
+country-BQ = Caribbean Netherlands
+country-BR = Brazil
+country-BS = Bahamas
+country-BT = Bhutan
+country-BV = Bouvet Island
+country-BW = Botswana
+country-BY = Belarus
+country-BZ = Belize
+country-CA = Canada
+country-CC = Cocos (Keeling) Islands
+country-CD = DR Congo
+country-CF = Central African Republic
+country-CG = Republic of the Congo
+country-CH = Switzerland
+country-CI = Côte d'Ivoire
+country-CK = Cook Islands
+country-CL = Chile
+country-CM = Cameroon
+country-CN = China
+country-CO = Colombia
+country-CR = Costa Rica
+country-CU = Cuba
+country-CV = Cabo Verde
+country-CW = Curaçao
+country-CX = Christmas Island
+country-CY = Cyprus
+country-CZ = Czechia
+country-DE = Germany
+country-DJ = Djibouti
+country-DK = Denmark
+country-DM = Dominica
+country-DO = Dominican Republic
+country-DZ = Algeria
+country-EC = Ecuador
+country-EE = Estonia
+country-EG = Egypt
+country-EH = Western Sahara
+country-ER = Eritrea
+country-ES = Spain
+country-ET = Ethiopia
+country-FI = Finland
+country-FJ = Fiji
+country-FK = Falkland Islands
+country-FM = Micronesia
+country-FO = Faroe Islands
+country-FR = France
+country-GA = Gabon
+country-GB = United Kingdom
+country-GD = Grenada
+country-GE = Georgia
+country-GF = French Guiana
+country-GG = Guernsey
+country-GH = Ghana
+country-GI = Gibraltar
+country-GL = Greenland
+country-GM = Gambia
+country-GN = Guinea
+country-GP = Guadeloupe
+country-GQ = Equatorial Guinea
+country-GR = Greece
+country-GS = South Georgia and the South Sandwich Islands
+country-GT = Guatemala
+country-GU = Guam
+country-GW = Guinea-Bissau
+country-GY = Guyana
+country-HK = Hong Kong
+country-HM = Heard and McDonald Islands
+country-HN = Honduras
+country-HR = Croatia
+country-HT = Haiti
+country-HU = Hungary
+country-ID = Indonesia
+country-IE = Ireland
+country-IL = Israel
+country-IM = Isle of Man
+country-IN = India
+country-IO = British Indian Ocean Territory
+country-IQ = Iraq
+country-IR = Iran
+country-IS = Iceland
+country-IT = Italy
+country-JE = Jersey
+country-JM = Jamaica
+country-JO = Jordan
+country-JP = Japan
+country-KE = Kenya
+country-KG = Kyrgyzstan
+country-KH = Cambodia
+country-KI = Kiribati
+country-KM = Comoros
+country-KN = Saint Kitts and Nevis
+country-KP = North Korea
+country-KR = South Korea
+country-KW = Kuwait
+country-KY = Cayman Islands
+country-KZ = Kazakhstan
+country-LA = Laos
+country-LB = Lebanon
+country-LC = Saint Lucia
+country-LI = Liechtenstein
+country-LK = Sri Lanka
+country-LR = Liberia
+country-LS = Lesotho
+country-LT = Lithuania
+country-LU = Luxembourg
+country-LV = Latvia
+country-LY = Libya
+country-MA = Morocco
+country-MC = Monaco
+country-MD = Moldova
+country-ME = Montenegro
+country-MF = Saint Martin
+country-MG = Madagascar
+country-MH = Marshall Islands
+country-MK = North Macedonia
+country-ML = Mali
+country-MM = Myanmar
+country-MN = Mongolia
+country-MO = Macao
+country-MP = Northern Mariana Islands
+country-MQ = Martinique
+country-MR = Mauritania
+country-MS = Montserrat
+country-MT = Malta
+country-MU = Mauritius
+country-MV = Maldives
+country-MW = Malawi
+country-MX = Mexico
+country-MY = Malaysia
+country-MZ = Mozambique
+country-NA = Namibia
+country-NC = New Caledonia
+country-NE = Niger
+country-NF = Norfolk Island
+country-NG = Nigeria
+country-NI = Nicaragua
+country-NL = Netherlands
+country-NO = Norway
+country-NP = Nepal
+country-NR = Nauru
+country-NU = Niue
+country-NZ = New Zealand
+country-OM = Oman
+country-PA = Panama
+country-PE = Peru
+country-PF = French Polynesia
+country-PG = Papua New Guinea
+country-PH = Philippines
+country-PK = Pakistan
+country-PL = Poland
+country-PM = Saint Pierre and Miquelon
+country-PN = Pitcairn Islands
+country-PR = Puerto Rico
+country-PS = Palestine
+country-PT = Portugal
+country-PW = Palau
+country-PY = Paraguay
+country-QA = Qatar
+country-RE = Réunion
+country-RO = Romania
+country-RS = Serbia
+country-RU = Russia
+country-RW = Rwanda
+country-SA = Saudi Arabia
+country-SB = Solomon Islands
+country-SC = Seychelles
+country-SD = Sudan
+country-SE = Sweden
+country-SG = Singapore
+country-SH = Saint Helena
+country-SI = Slovenia
+country-SJ = Svalbard and Jan Mayen
+country-SK = Slovakia
+country-SL = Sierra Leone
+country-SM = San Marino
+country-SN = Senegal
+country-SO = Somalia
+country-SR = Suriname
+country-SS = South Sudan
+country-ST = Sao Tome and Principe
+country-SV = El Salvador
+country-SX = Sint Maarten
+country-SY = Syria
+country-SZ = Eswatini
+country-TC = Turks and Caicos Islands
+country-TD = Chad
+country-TF = French Southern Territories
+country-TG = Togo
+country-TH = Thailand
+country-TJ = Tajikistan
+country-TK = Tokelau
+country-TL = Timor-Leste
+country-TM = Turkmenistan
+country-TN = Tunisia
+country-TO = Tonga
+country-TR = Türkiye
+country-TT = Trinidad and Tobago
+country-TV = Tuvalu
+country-TW = Taiwan
+country-TZ = Tanzania
+country-UA = Ukraine
+country-UG = Uganda
+country-UM = U.S. Minor Outlying Islands
+country-US = United States
+country-UY = Uruguay
+country-UZ = Uzbekistan
+country-VA = Vatican City
+country-VC = Saint Vincent and the Grenadines
+country-VE = Venezuela
+country-VG = British Virgin Islands
+country-VI = U.S. Virgin Islands
+country-VN = Vietnam
+country-VU = Vanuatu
+country-WF = Wallis and Futuna
+country-WS = Samoa
+country-XK = Kosovo
+country-YE = Yemen
+country-YT = Mayotte
+country-ZA = South Africa
+country-ZM = Zambia
+country-ZW = Zimbabwe
+
+## Continents
+continent-AF = Africa
+continent-AN = Antarctica
+continent-AS = Asia
+continent-EU = Europe
+continent-NA = North America
+continent-OC = Oceania
+continent-SA = South America
+
+## System status
+admin-status-title = System status
+admin-status-subtitle = Health of this shymini install
+admin-maintenance = Database maintenance
+admin-maintenance-schedule = Runs daily at { $time }
+admin-maintenance-off = Not scheduled; set SHYMINI__MAINTENANCE_HOUR to run it daily
+admin-maintenance-run = Run now
+admin-maintenance-task = Task
+admin-maintenance-last-run = Last run
+admin-maintenance-duration = Duration
+admin-maintenance-result = Result
+admin-maintenance-ok = OK
+admin-maintenance-never = Never run
+maintenance-task-compact_rollups = Remove old rollups
+maintenance-task-prune_caches = Prune caches
+maintenance-task-optimize = Optimize database
+admin-version = Version
+admin-build-release = { $backend }, release build
+admin-build-debug = { $backend }, debug build
+admin-uptime = Uptime
+admin-database-size = Database size
+admin-hits-last-hour = Hits in the last hour
+admin-sessions-last-hour = Sessions in the last hour
+admin-tables = Tables
+admin-table = Table
+admin-rows = Rows
+admin-caches = Caches
+admin-cache = Cache
+admin-cache-entries = Entries
+admin-cache-hits = Hits
+admin-cache-misses = Misses
+admin-cache-hit-rate = Hit rate
+admin-tasks = Background tasks
+admin-task = Task
+admin-task-state = State
+admin-task-last-success = Last success
+admin-task-last-error = Last error
+admin-task-off = Off
+admin-task-waiting = Not run yet
+admin-task-ok = OK
+admin-task-failing = Failing
+admin-origin-mismatches = Rejected origins
+admin-origin-mismatches-help = Tracking requests refused since the server started because their origin isn't one the service allows
+admin-origin-service = Service
+admin-origin-count = Requests
+admin-origin-last = Last origin
+admin-origin-last-seen = Last seen
+admin-origin-none = No origin
+background-task-monitor = Uptime checks
+background-task-crawler = Sitemap crawls
+background-task-hit_filter_flush = Idempotency filter saves
+background-task-top_pages = Top pages refresh
+background-task-maintenance = Database maintenance
+background-task-update_check = Update check
+background-task-hit_partitions = Hit partitions
+background-task-referrer_spam = Referrer spam list
+update-available = shymini { $version } is available.
+update-release-notes = Release notes
@@ -0,0 +1,2 @@
+-- Coordinate tracker tabs and collapse concurrent identical hits (per service)
+ALTER TABLE services ADD COLUMN IF NOT EXISTS collapse_tabs BOOLEAN NOT NULL DEFAULT TRUE;
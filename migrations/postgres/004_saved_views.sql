@@ -0,0 +1,13 @@
+-- Named dashboard views (date range, URL filter and panel layout) per service
+CREATE TABLE IF NOT EXISTS saved_views (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    start_date VARCHAR(64) NOT NULL DEFAULT '',
+    end_date VARCHAR(64) NOT NULL DEFAULT '',
+    url_pattern TEXT NOT NULL DEFAULT '',
+    layout TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_saved_views_service ON saved_views(service_id, name);
@@ -0,0 +1,3 @@
+-- Monthly hit quota (0 = unlimited) and what to do once it is used up
+ALTER TABLE services ADD COLUMN IF NOT EXISTS hit_quota BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE services ADD COLUMN IF NOT EXISTS quota_behavior VARCHAR(16) NOT NULL DEFAULT 'keep';
@@ -0,0 +1,9 @@
+-- Per-service write counters, one row per calendar month (UTC)
+CREATE TABLE IF NOT EXISTS service_usage (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    month VARCHAR(7) NOT NULL,
+    hits BIGINT NOT NULL DEFAULT 0,
+    sessions BIGINT NOT NULL DEFAULT 0,
+    dropped BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (service_id, month)
+);
@@ -0,0 +1,5 @@
+-- Existing services move into the default organization
+ALTER TABLE services ADD COLUMN IF NOT EXISTS organization_id UUID NOT NULL
+    DEFAULT '00000000-0000-0000-0000-000000000000' REFERENCES organizations(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS idx_services_organization ON services(organization_id, name);
@@ -0,0 +1,2 @@
+-- Base32 TOTP secret of users who turned on two-factor logins
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_secret VARCHAR(64);
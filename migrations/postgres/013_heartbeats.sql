@@ -0,0 +1,4 @@
+-- Per-service heartbeat interval (0 = the server default) and minutes without
+-- interaction after which the tracker stops heartbeating (0 = never)
+ALTER TABLE services ADD COLUMN IF NOT EXISTS heartbeat_frequency_ms BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE services ADD COLUMN IF NOT EXISTS idle_timeout_mins BIGINT NOT NULL DEFAULT 30;
@@ -0,0 +1,2 @@
+-- When the tracker reported the visitor leaving; cleared by later activity
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS ended_at TIMESTAMPTZ;
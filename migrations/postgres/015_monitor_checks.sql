@@ -0,0 +1,12 @@
+-- Requests to each service's site by the uptime monitor
+CREATE TABLE IF NOT EXISTS monitor_checks (
+    id BIGSERIAL PRIMARY KEY,
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    checked_at TIMESTAMPTZ NOT NULL,
+    status_code INTEGER,
+    latency_ms BIGINT,
+    up BOOLEAN NOT NULL,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_monitor_checks_service ON monitor_checks(service_id, checked_at);
@@ -0,0 +1,7 @@
+-- When each monitored service's TLS certificate and domain run out, as last looked up
+CREATE TABLE IF NOT EXISTS expiry_checks (
+    service_id UUID PRIMARY KEY REFERENCES services(id) ON DELETE CASCADE,
+    checked_at TIMESTAMPTZ NOT NULL,
+    certificate_expires_at TIMESTAMPTZ,
+    domain_expires_at TIMESTAMPTZ
+);
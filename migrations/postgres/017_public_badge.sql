@@ -0,0 +1,2 @@
+-- Services opt in to a public visitor badge
+ALTER TABLE services ADD COLUMN IF NOT EXISTS public_badge BOOLEAN NOT NULL DEFAULT FALSE;
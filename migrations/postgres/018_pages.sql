@@ -0,0 +1,8 @@
+-- Pages each service's sitemap lists, kept up to date by the crawler
+CREATE TABLE IF NOT EXISTS pages (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (service_id, url)
+);
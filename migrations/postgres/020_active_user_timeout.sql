@@ -0,0 +1,3 @@
+-- Per-service time after the last heartbeat that a visitor still counts as
+-- online (0 = twice the heartbeat interval)
+ALTER TABLE services ADD COLUMN IF NOT EXISTS active_user_timeout_ms BIGINT NOT NULL DEFAULT 0;
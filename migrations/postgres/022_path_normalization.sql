@@ -0,0 +1,4 @@
+-- How a service folds URL variants of a page into one location at ingest
+ALTER TABLE services ADD COLUMN IF NOT EXISTS lowercase_paths BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE services ADD COLUMN IF NOT EXISTS strip_trailing_slash BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE services ADD COLUMN IF NOT EXISTS path_patterns TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,17 @@
+-- Hits per page for each completed UTC day, kept by the top pages job so the
+-- top pages of long ranges are read instead of grouped from every hit
+CREATE TABLE IF NOT EXISTS top_locations_daily (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    environment TEXT NOT NULL,
+    location TEXT NOT NULL,
+    hits BIGINT NOT NULL,
+    PRIMARY KEY (service_id, day, environment, location)
+);
+
+-- Days whose rows above are complete (a day without hits has none)
+CREATE TABLE IF NOT EXISTS top_locations_days (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    PRIMARY KEY (service_id, day)
+);
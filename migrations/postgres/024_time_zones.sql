@@ -0,0 +1,8 @@
+-- Per-user preferences; a user without a row has the defaults
+CREATE TABLE IF NOT EXISTS user_settings (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    time_zone TEXT NOT NULL DEFAULT ''
+);
+
+-- Time zone a service's reports default to; empty means none set
+ALTER TABLE services ADD COLUMN IF NOT EXISTS time_zone TEXT NOT NULL DEFAULT '';
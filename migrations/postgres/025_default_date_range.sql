@@ -0,0 +1,10 @@
+-- The preset range each user last picked on a service's dashboard
+CREATE TABLE IF NOT EXISTS user_service_settings (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    date_range TEXT NOT NULL,
+    PRIMARY KEY (user_id, service_id)
+);
+
+-- Range a service's reports cover when neither the request nor the user picks one
+ALTER TABLE services ADD COLUMN IF NOT EXISTS default_range TEXT NOT NULL DEFAULT '30d';
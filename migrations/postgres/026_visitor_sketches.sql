@@ -0,0 +1,9 @@
+-- Distinct visitors of each UTC day, kept at ingest when visitor sketches
+-- are on; see src/sketch.rs for the format
+CREATE TABLE IF NOT EXISTS visitor_sketches (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    environment TEXT NOT NULL,
+    sketch BYTEA NOT NULL,
+    PRIMARY KEY (service_id, day, environment)
+);
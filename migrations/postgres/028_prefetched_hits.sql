@@ -0,0 +1,3 @@
+-- Hits from pages the browser prefetched or prerendered, recorded when
+-- prefetch_hits is flag
+ALTER TABLE hits ADD COLUMN IF NOT EXISTS prefetched BOOLEAN NOT NULL DEFAULT FALSE;
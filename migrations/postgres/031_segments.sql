@@ -0,0 +1,11 @@
+-- Named sets of session conditions per service; `conditions` is a JSON array
+-- of {field, op, value}
+CREATE TABLE IF NOT EXISTS segments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    conditions TEXT NOT NULL DEFAULT '[]',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_segments_service ON segments(service_id, name);
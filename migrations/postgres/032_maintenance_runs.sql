@@ -0,0 +1,7 @@
+-- How each scheduled maintenance task went the last time it ran
+CREATE TABLE IF NOT EXISTS maintenance_runs (
+    task TEXT PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL,
+    duration_ms BIGINT NOT NULL,
+    error TEXT
+);
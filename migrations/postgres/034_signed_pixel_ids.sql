@@ -0,0 +1,2 @@
+-- Services may require pixel identifiers to carry a signature
+ALTER TABLE services ADD COLUMN IF NOT EXISTS signed_pixel_ids BOOLEAN NOT NULL DEFAULT FALSE;
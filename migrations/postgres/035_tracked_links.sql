@@ -0,0 +1,25 @@
+-- Redirect links per service; `/r/:token` records a click and sends the
+-- visitor on to `target_url`
+CREATE TABLE IF NOT EXISTS tracked_links (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    token VARCHAR(64) NOT NULL UNIQUE,
+    campaign VARCHAR(128) NOT NULL DEFAULT '',
+    target_url TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_tracked_links_service ON tracked_links(service_id, created_at DESC);
+
+-- One row per click. `visitor` is a prefix of the session association hash,
+-- for counting distinct clickers; `session_id` is the visitor's session on
+-- the site, if one was open
+CREATE TABLE IF NOT EXISTS link_clicks (
+    id BIGSERIAL PRIMARY KEY,
+    link_id UUID NOT NULL REFERENCES tracked_links(id) ON DELETE CASCADE,
+    session_id UUID REFERENCES sessions(id) ON DELETE SET NULL,
+    visitor VARCHAR(64) NOT NULL,
+    time TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_link_clicks_link_time ON link_clicks(link_id, time);
@@ -0,0 +1,4 @@
+-- Short links: a name for each tracked link, and where each click came from
+ALTER TABLE tracked_links ADD COLUMN IF NOT EXISTS name TEXT NOT NULL DEFAULT '';
+ALTER TABLE link_clicks ADD COLUMN IF NOT EXISTS referrer TEXT NOT NULL DEFAULT '';
+ALTER TABLE link_clicks ADD COLUMN IF NOT EXISTS country TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,4 @@
+-- Per-service bounce rules, and whether a session saw any interaction
+ALTER TABLE services ADD COLUMN IF NOT EXISTS bounce_rule TEXT NOT NULL DEFAULT 'single_hit';
+ALTER TABLE services ADD COLUMN IF NOT EXISTS bounce_threshold_secs BIGINT NOT NULL DEFAULT 10;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS interacted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,6 @@
+-- Dimensions carry their hit's start time, the hits partition key, so they
+-- can reference hits once the table is partitioned by month
+ALTER TABLE hit_dimensions ADD COLUMN IF NOT EXISTS hit_start_time TIMESTAMPTZ;
+UPDATE hit_dimensions d SET hit_start_time = h.start_time
+FROM hits h WHERE h.id = d.hit_id AND d.hit_start_time IS NULL;
+ALTER TABLE hit_dimensions ALTER COLUMN hit_start_time SET NOT NULL;
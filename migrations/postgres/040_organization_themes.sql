@@ -0,0 +1,5 @@
+-- An organization's own dashboard logo, accent color and light or dark
+-- mode; empty means the install's
+ALTER TABLE organizations ADD COLUMN IF NOT EXISTS logo_url TEXT NOT NULL DEFAULT '';
+ALTER TABLE organizations ADD COLUMN IF NOT EXISTS accent_color TEXT NOT NULL DEFAULT '';
+ALTER TABLE organizations ADD COLUMN IF NOT EXISTS theme_mode TEXT NOT NULL DEFAULT '';
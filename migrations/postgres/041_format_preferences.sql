@@ -0,0 +1,6 @@
+-- How users like dates and times shown; empty means their language's custom.
+-- API tokens remember who made them, so responses follow that user's
+-- preferences.
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS week_start TEXT NOT NULL DEFAULT '';
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS hour_cycle TEXT NOT NULL DEFAULT '';
+ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS user_id UUID REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,2 @@
+-- Services may keep their latest ingress decisions for a debug page
+ALTER TABLE services ADD COLUMN IF NOT EXISTS debug_log BOOLEAN NOT NULL DEFAULT FALSE;
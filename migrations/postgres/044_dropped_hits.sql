@@ -0,0 +1,9 @@
+-- Page views ingress didn't record, per service, UTC day and reason (an
+-- IngressDecision key), for the dashboard's data quality panel
+CREATE TABLE IF NOT EXISTS dropped_hits (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    reason TEXT NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (service_id, day, reason)
+);
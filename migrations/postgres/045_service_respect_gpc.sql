@@ -0,0 +1,4 @@
+-- Global Privacy Control gets its own switch; services keep honoring it as
+-- they did along with Do Not Track
+ALTER TABLE services ADD COLUMN respect_gpc BOOLEAN NOT NULL DEFAULT TRUE;
+UPDATE services SET respect_gpc = respect_dnt;
@@ -0,0 +1,3 @@
+-- Referrers from the service's own site (navigations within it) are left
+-- out of its referrer breakdowns unless switched back on
+ALTER TABLE services ADD COLUMN hide_internal_referrers BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,3 @@
+-- Hits referred from a referrer spam domain, recorded when referrer_spam is
+-- flag
+ALTER TABLE hits ADD COLUMN referrer_spam BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,18 @@
+-- JavaScript errors reported by the trackers of services tracking them, per
+-- UTC day, environment, page and fingerprint (a hash of the message and the
+-- top of the stack). `message` and `stack` are those of the latest report.
+CREATE TABLE IF NOT EXISTS errors (
+    service_id UUID NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    environment TEXT NOT NULL DEFAULT 'production',
+    location TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    message TEXT NOT NULL,
+    stack TEXT NOT NULL DEFAULT '',
+    count BIGINT NOT NULL DEFAULT 0,
+    last_seen TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (service_id, day, environment, location, fingerprint)
+);
+
+-- Off unless the service asks for it: the tracker then reports errors too
+ALTER TABLE services ADD COLUMN track_errors BOOLEAN NOT NULL DEFAULT FALSE;
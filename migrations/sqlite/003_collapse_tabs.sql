@@ -0,0 +1,2 @@
+-- Coordinate tracker tabs and collapse concurrent identical hits (per service)
+ALTER TABLE services ADD COLUMN collapse_tabs INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+-- Monthly hit quota (0 = unlimited) and what to do once it is used up
+ALTER TABLE services ADD COLUMN hit_quota INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE services ADD COLUMN quota_behavior TEXT NOT NULL DEFAULT 'keep';
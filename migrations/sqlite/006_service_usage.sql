@@ -0,0 +1,9 @@
+-- Per-service write counters, one row per calendar month (UTC)
+CREATE TABLE IF NOT EXISTS service_usage (
+    service_id TEXT NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    month TEXT NOT NULL,
+    hits INTEGER NOT NULL DEFAULT 0,
+    sessions INTEGER NOT NULL DEFAULT 0,
+    dropped INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (service_id, month)
+);
@@ -0,0 +1,5 @@
+-- Existing services move into the default organization. SQLite cannot add a
+-- REFERENCES column with a non-NULL default, so the link is kept by the app.
+ALTER TABLE services ADD COLUMN organization_id TEXT NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+
+CREATE INDEX IF NOT EXISTS idx_services_organization ON services(organization_id, name);
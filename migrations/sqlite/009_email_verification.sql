@@ -0,0 +1,5 @@
+-- When the user proved they own their email address; NULL until then
+ALTER TABLE users ADD COLUMN email_verified_at TEXT;
+
+-- Accounts from before verification existed are trusted
+UPDATE users SET email_verified_at = created_at WHERE email_verified_at IS NULL;
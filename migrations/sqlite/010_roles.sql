@@ -0,0 +1,4 @@
+-- What members and API tokens may do; everyone from before roles existed
+-- keeps full access
+ALTER TABLE memberships ADD COLUMN role TEXT NOT NULL DEFAULT 'owner';
+ALTER TABLE api_tokens ADD COLUMN role TEXT NOT NULL DEFAULT 'owner';
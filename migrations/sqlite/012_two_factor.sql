@@ -0,0 +1,2 @@
+-- Base32 TOTP secret of users who turned on two-factor logins
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
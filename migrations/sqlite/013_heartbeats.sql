@@ -0,0 +1,4 @@
+-- Per-service heartbeat interval (0 = the server default) and minutes without
+-- interaction after which the tracker stops heartbeating (0 = never)
+ALTER TABLE services ADD COLUMN heartbeat_frequency_ms INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE services ADD COLUMN idle_timeout_mins INTEGER NOT NULL DEFAULT 30;
@@ -0,0 +1,2 @@
+-- When the tracker reported the visitor leaving; cleared by later activity
+ALTER TABLE sessions ADD COLUMN ended_at TEXT;
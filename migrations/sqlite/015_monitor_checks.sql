@@ -0,0 +1,12 @@
+-- Requests to each service's site by the uptime monitor
+CREATE TABLE IF NOT EXISTS monitor_checks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    service_id TEXT NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    checked_at TEXT NOT NULL,
+    status_code INTEGER,
+    latency_ms INTEGER,
+    up INTEGER NOT NULL,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_monitor_checks_service ON monitor_checks(service_id, checked_at);
@@ -0,0 +1,2 @@
+-- Services opt in to a public visitor badge
+ALTER TABLE services ADD COLUMN public_badge INTEGER NOT NULL DEFAULT 0;
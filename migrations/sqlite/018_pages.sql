@@ -0,0 +1,8 @@
+-- Pages each service's sitemap lists, kept up to date by the crawler
+CREATE TABLE IF NOT EXISTS pages (
+    service_id TEXT NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    first_seen TEXT NOT NULL,
+    last_seen TEXT NOT NULL,
+    PRIMARY KEY (service_id, url)
+);
@@ -0,0 +1,2 @@
+-- Rules sorting a service's pages into content groups, one per line
+ALTER TABLE services ADD COLUMN content_groups TEXT NOT NULL DEFAULT '';
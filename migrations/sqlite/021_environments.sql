@@ -0,0 +1,4 @@
+-- Deployment the traffic came from (production, staging or dev); stats show
+-- production unless asked otherwise
+ALTER TABLE sessions ADD COLUMN environment TEXT NOT NULL DEFAULT 'production';
+ALTER TABLE hits ADD COLUMN environment TEXT NOT NULL DEFAULT 'production';
@@ -0,0 +1,4 @@
+-- How a service folds URL variants of a page into one location at ingest
+ALTER TABLE services ADD COLUMN lowercase_paths INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE services ADD COLUMN strip_trailing_slash INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE services ADD COLUMN path_patterns TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,9 @@
+-- Distinct visitors of each UTC day, kept at ingest when visitor sketches
+-- are on; see src/sketch.rs for the format
+CREATE TABLE IF NOT EXISTS visitor_sketches (
+    service_id TEXT NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day TEXT NOT NULL,
+    environment TEXT NOT NULL,
+    sketch BLOB NOT NULL,
+    PRIMARY KEY (service_id, day, environment)
+);
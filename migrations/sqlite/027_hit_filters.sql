@@ -0,0 +1,9 @@
+-- Repeated page loads matched to their hit by the idempotency filter
+ALTER TABLE service_usage ADD COLUMN duplicates INTEGER NOT NULL DEFAULT 0;
+
+-- Bloom filter of the hit idempotency keys of each UTC day, kept for today
+-- and yesterday
+CREATE TABLE IF NOT EXISTS hit_filters (
+    day TEXT PRIMARY KEY,
+    bits BLOB NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Custom key/value dimensions the tracker attached to a page view
+CREATE TABLE IF NOT EXISTS hit_dimensions (
+    hit_id INTEGER NOT NULL REFERENCES hits(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (hit_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_hit_dimensions_key ON hit_dimensions(key, hit_id);
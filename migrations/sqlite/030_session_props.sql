@@ -0,0 +1,2 @@
+-- Properties the tracker attached to a session, as a JSON object of strings
+ALTER TABLE sessions ADD COLUMN props TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,11 @@
+-- Named sets of session conditions per service; `conditions` is a JSON array
+-- of {field, op, value}
+CREATE TABLE IF NOT EXISTS segments (
+    id TEXT PRIMARY KEY,
+    service_id TEXT NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    conditions TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_segments_service ON segments(service_id, name);
@@ -0,0 +1,7 @@
+-- Rhai script each of a service's tracker payloads runs through before it
+-- is recorded; a service without a row has none
+CREATE TABLE IF NOT EXISTS ingest_scripts (
+    service_id TEXT PRIMARY KEY REFERENCES services(id) ON DELETE CASCADE,
+    source TEXT NOT NULL,
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,2 @@
+-- Services may require pixel identifiers to carry a signature
+ALTER TABLE services ADD COLUMN signed_pixel_ids INTEGER NOT NULL DEFAULT 0;
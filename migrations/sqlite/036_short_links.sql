@@ -0,0 +1,4 @@
+-- Short links: a name for each tracked link, and where each click came from
+ALTER TABLE tracked_links ADD COLUMN name TEXT NOT NULL DEFAULT '';
+ALTER TABLE link_clicks ADD COLUMN referrer TEXT NOT NULL DEFAULT '';
+ALTER TABLE link_clicks ADD COLUMN country TEXT NOT NULL DEFAULT '';
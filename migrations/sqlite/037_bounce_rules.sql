@@ -0,0 +1,4 @@
+-- Per-service bounce rules, and whether a session saw any interaction
+ALTER TABLE services ADD COLUMN bounce_rule TEXT NOT NULL DEFAULT 'single_hit';
+ALTER TABLE services ADD COLUMN bounce_threshold_secs INTEGER NOT NULL DEFAULT 10;
+ALTER TABLE sessions ADD COLUMN interacted INTEGER NOT NULL DEFAULT 0;
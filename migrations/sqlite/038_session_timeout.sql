@@ -0,0 +1,2 @@
+-- Services may start a new session after a while without activity
+ALTER TABLE services ADD COLUMN session_timeout_mins INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Dimensions carry their hit's start time, the hits partition key on
+-- Postgres
+ALTER TABLE hit_dimensions ADD COLUMN hit_start_time TEXT NOT NULL DEFAULT '';
+UPDATE hit_dimensions SET hit_start_time = (
+    SELECT start_time FROM hits WHERE hits.id = hit_dimensions.hit_id
+);
@@ -0,0 +1,5 @@
+-- An organization's own dashboard logo, accent color and light or dark
+-- mode; empty means the install's
+ALTER TABLE organizations ADD COLUMN logo_url TEXT NOT NULL DEFAULT '';
+ALTER TABLE organizations ADD COLUMN accent_color TEXT NOT NULL DEFAULT '';
+ALTER TABLE organizations ADD COLUMN theme_mode TEXT NOT NULL DEFAULT '';
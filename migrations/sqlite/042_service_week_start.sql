@@ -0,0 +1,3 @@
+-- The day a service's weekly charts start on for viewers who haven't picked
+-- one; empty means the viewer's language's custom.
+ALTER TABLE services ADD COLUMN week_start TEXT NOT NULL DEFAULT '';
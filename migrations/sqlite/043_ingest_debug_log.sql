@@ -0,0 +1,2 @@
+-- Services may keep their latest ingress decisions for a debug page
+ALTER TABLE services ADD COLUMN debug_log INTEGER NOT NULL DEFAULT 0;
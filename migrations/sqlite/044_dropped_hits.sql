@@ -0,0 +1,9 @@
+-- Page views ingress didn't record, per service, UTC day and reason (an
+-- IngressDecision key), for the dashboard's data quality panel
+CREATE TABLE IF NOT EXISTS dropped_hits (
+    service_id TEXT NOT NULL REFERENCES services(id) ON DELETE CASCADE,
+    day TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (service_id, day, reason)
+);
@@ -0,0 +1,4 @@
+-- Opt-in linking of sessions across devices under a hashed login identifier
+ALTER TABLE services ADD COLUMN link_users INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE sessions ADD COLUMN user_key TEXT NOT NULL DEFAULT '';
+CREATE INDEX IF NOT EXISTS idx_sessions_service_user_key ON sessions(service_id, user_key);
@@ -0,0 +1,4 @@
+-- Visitor IPs encrypted at rest, with a keyed hash to look them up by
+ALTER TABLE sessions ADD COLUMN ip_encrypted TEXT;
+ALTER TABLE sessions ADD COLUMN ip_hash TEXT;
+CREATE INDEX IF NOT EXISTS idx_sessions_ip_hash ON sessions(ip_hash);
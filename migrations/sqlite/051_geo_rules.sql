@@ -0,0 +1,8 @@
+-- Per-service country and ASN rules, checked at ingest once the visitor's
+-- location is known: comma-separated ISO country codes and AS numbers. With
+-- an allowed list set, only visitors matching it are recorded; visitors
+-- matching a blocked list never are.
+ALTER TABLE services ADD COLUMN allowed_countries TEXT NOT NULL DEFAULT '';
+ALTER TABLE services ADD COLUMN blocked_countries TEXT NOT NULL DEFAULT '';
+ALTER TABLE services ADD COLUMN allowed_asns TEXT NOT NULL DEFAULT '';
+ALTER TABLE services ADD COLUMN blocked_asns TEXT NOT NULL DEFAULT '';
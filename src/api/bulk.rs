@@ -0,0 +1,350 @@
+//! Changing many services at once, such as respecting Do Not Track on all of
+//! them or ignoring an office network everywhere. A dry run answers with the
+//! changes the request would make, field by field, without making them.
+
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono_tz::Tz;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+
+use super::{ApiResponse, ApiResult};
+use crate::auth::ApiTenant;
+use crate::db;
+use crate::domain::{
+    DateRangePreset, Permission, QuotaBehavior, Service, ServiceId, ServiceStatus, UpdateService,
+    WeekStart,
+};
+use crate::error::Error;
+use crate::state::AppState;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BulkServiceUpdate {
+    /// Services to change; every one of the organization's when empty
+    pub services: Vec<ServiceId>,
+    pub set: ServiceSettings,
+    /// Networks (`10.0.0.0/8`) or addresses to add to the ignored IPs
+    pub add_ignored_ips: Vec<String>,
+    /// Networks or addresses to take off the ignored IPs
+    pub remove_ignored_ips: Vec<String>,
+    /// Answer with the changes without making them
+    pub dry_run: bool,
+}
+
+/// Settings given the same value on every service
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceSettings {
+    pub status: Option<ServiceStatus>,
+    pub respect_dnt: Option<bool>,
+    pub respect_gpc: Option<bool>,
+    pub ignore_robots: Option<bool>,
+    pub collect_ips: Option<bool>,
+    pub collapse_tabs: Option<bool>,
+    pub public_badge: Option<bool>,
+    pub lowercase_paths: Option<bool>,
+    pub strip_trailing_slash: Option<bool>,
+    pub signed_pixel_ids: Option<bool>,
+    pub hide_internal_referrers: Option<bool>,
+    pub track_errors: Option<bool>,
+    pub hit_quota: Option<i64>,
+    pub quota_behavior: Option<QuotaBehavior>,
+    /// IANA name, or empty for none
+    pub time_zone: Option<String>,
+    pub default_range: Option<DateRangePreset>,
+    /// `null` leaves weeks to the viewers' languages
+    #[serde(deserialize_with = "some")]
+    pub week_start: Option<Option<WeekStart>>,
+}
+
+/// Tells an explicit `null` (`Some(None)`) from a missing field (`None`)
+fn some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkServiceResult {
+    pub dry_run: bool,
+    /// Services the request changes, leaving out those it leaves as they are
+    pub services: Vec<ServiceChanges>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceChanges {
+    pub id: ServiceId,
+    pub name: String,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
+impl BulkServiceUpdate {
+    /// Why the update can't be made, if it can't
+    pub fn problem(&self) -> Option<String> {
+        let set = &self.set;
+        let changes_settings = set.status.is_some()
+            || set.respect_dnt.is_some()
+            || set.respect_gpc.is_some()
+            || set.ignore_robots.is_some()
+            || set.collect_ips.is_some()
+            || set.collapse_tabs.is_some()
+            || set.public_badge.is_some()
+            || set.lowercase_paths.is_some()
+            || set.strip_trailing_slash.is_some()
+            || set.signed_pixel_ids.is_some()
+            || set.hide_internal_referrers.is_some()
+            || set.track_errors.is_some()
+            || set.hit_quota.is_some()
+            || set.quota_behavior.is_some()
+            || set.time_zone.is_some()
+            || set.default_range.is_some()
+            || set.week_start.is_some();
+        if !changes_settings
+            && self.add_ignored_ips.is_empty()
+            && self.remove_ignored_ips.is_empty()
+        {
+            return Some("Nothing to change".to_string());
+        }
+        if set.hit_quota.is_some_and(|quota| quota < 0) {
+            return Some("The hit quota may not be negative".to_string());
+        }
+        if let Some(tz) = set.time_zone.as_deref().map(str::trim) {
+            if !tz.is_empty() && tz.parse::<Tz>().is_err() {
+                return Some(format!("Unknown time zone: {}", tz));
+            }
+        }
+        self.add_ignored_ips
+            .iter()
+            .chain(&self.remove_ignored_ips)
+            .find(|ip| ip.trim().parse::<IpNetwork>().is_err())
+            .map(|ip| format!("Not an IP address or network: {}", ip))
+    }
+
+    /// `service` as the update leaves it
+    pub fn apply(&self, service: &Service) -> Service {
+        let set = &self.set;
+        let mut updated = service.clone();
+        if let Some(status) = set.status {
+            updated.status = status;
+        }
+        for (value, field) in [
+            (set.respect_dnt, &mut updated.respect_dnt),
+            (set.respect_gpc, &mut updated.respect_gpc),
+            (set.ignore_robots, &mut updated.ignore_robots),
+            (set.collect_ips, &mut updated.collect_ips),
+            (set.collapse_tabs, &mut updated.collapse_tabs),
+            (set.public_badge, &mut updated.public_badge),
+            (set.lowercase_paths, &mut updated.lowercase_paths),
+            (set.strip_trailing_slash, &mut updated.strip_trailing_slash),
+            (set.signed_pixel_ids, &mut updated.signed_pixel_ids),
+            (
+                set.hide_internal_referrers,
+                &mut updated.hide_internal_referrers,
+            ),
+            (set.track_errors, &mut updated.track_errors),
+        ] {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        if let Some(quota) = set.hit_quota {
+            updated.hit_quota = quota;
+        }
+        if let Some(behavior) = set.quota_behavior {
+            updated.quota_behavior = behavior;
+        }
+        if let Some(tz) = &set.time_zone {
+            updated.time_zone = tz
+                .trim()
+                .parse::<Tz>()
+                .map_or_else(|_| String::new(), |tz| tz.name().to_string());
+        }
+        if let Some(range) = set.default_range {
+            updated.default_range = range;
+        }
+        if let Some(week_start) = set.week_start {
+            updated.week_start = week_start;
+        }
+        updated.ignored_ips = self.ignored_ips(&service.ignored_ips);
+        updated
+    }
+
+    /// The comma-separated `ignored_ips` with the networks added and taken
+    /// off; entries are kept as written unless taken off
+    fn ignored_ips(&self, ignored_ips: &str) -> String {
+        let parse = |ips: &[String]| -> Vec<IpNetwork> {
+            ips.iter().filter_map(|ip| ip.trim().parse().ok()).collect()
+        };
+        let (added, removed) = (
+            parse(&self.add_ignored_ips),
+            parse(&self.remove_ignored_ips),
+        );
+        if added.is_empty() && removed.is_empty() {
+            return ignored_ips.to_string();
+        }
+
+        let mut entries: Vec<String> = ignored_ips
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter(|entry| !entry.parse().is_ok_and(|net| removed.contains(&net)))
+            .map(str::to_string)
+            .collect();
+        for network in added {
+            let listed = entries
+                .iter()
+                .any(|entry| entry.parse::<IpNetwork>().is_ok_and(|net| net == network));
+            if !listed && !removed.contains(&network) {
+                entries.push(network.to_string());
+            }
+        }
+        entries.join(", ")
+    }
+}
+
+/// The fields in which `before` and `after` differ, by name
+fn changes(before: &Service, after: &Service) -> Vec<FieldChange> {
+    let (Ok(serde_json::Value::Object(before)), Ok(serde_json::Value::Object(after))) =
+        (serde_json::to_value(before), serde_json::to_value(after))
+    else {
+        return Vec::new();
+    };
+    after
+        .into_iter()
+        .filter(|(field, value)| before.get(field) != Some(value))
+        .map(|(field, to)| FieldChange {
+            from: before.get(&field).cloned().unwrap_or_default(),
+            field,
+            to,
+        })
+        .collect()
+}
+
+/// POST /api/services/bulk
+pub async fn update_services(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Json(input): Json<BulkServiceUpdate>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    if let Some(problem) = input.problem() {
+        return Err(Error::BadRequest(problem).into());
+    }
+
+    let mut services = db::list_services(&state.pool, tenant.organization_id).await?;
+    if !input.services.is_empty() {
+        // All of them must be the organization's
+        if input
+            .services
+            .iter()
+            .any(|id| !services.iter().any(|service| service.id == *id))
+        {
+            return Err(Error::ServiceNotFound.into());
+        }
+        services.retain(|service| input.services.contains(&service.id));
+    }
+
+    let mut result = BulkServiceResult {
+        dry_run: input.dry_run,
+        services: Vec::new(),
+    };
+    for service in services {
+        let updated = input.apply(&service);
+        let changes = changes(&service, &updated);
+        if changes.is_empty() {
+            continue;
+        }
+        if !input.dry_run {
+            let update = UpdateService {
+                status: Some(updated.status),
+                respect_dnt: Some(updated.respect_dnt),
+                respect_gpc: Some(updated.respect_gpc),
+                ignore_robots: Some(updated.ignore_robots),
+                collect_ips: Some(updated.collect_ips),
+                collapse_tabs: Some(updated.collapse_tabs),
+                public_badge: Some(updated.public_badge),
+                lowercase_paths: Some(updated.lowercase_paths),
+                strip_trailing_slash: Some(updated.strip_trailing_slash),
+                signed_pixel_ids: Some(updated.signed_pixel_ids),
+                hide_internal_referrers: Some(updated.hide_internal_referrers),
+                track_errors: Some(updated.track_errors),
+                hit_quota: Some(updated.hit_quota),
+                quota_behavior: Some(updated.quota_behavior),
+                time_zone: Some(updated.time_zone),
+                default_range: Some(updated.default_range),
+                week_start: Some(updated.week_start),
+                ignored_ips: Some(updated.ignored_ips),
+                ..Default::default()
+            };
+            db::update_service(&state.pool, service.id, update).await?;
+            state.cache.invalidate_service(service.id).await;
+        }
+        result.services.push(ServiceChanges {
+            id: service.id,
+            name: service.name,
+            changes,
+        });
+    }
+
+    Ok(Json(ApiResponse::success(result)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(json: serde_json::Value) -> BulkServiceUpdate {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_problem() {
+        assert_eq!(
+            update(serde_json::json!({"dry_run": true})).problem(),
+            Some("Nothing to change".to_string())
+        );
+        assert!(
+            update(serde_json::json!({"set": {"time_zone": "Mars/Olympus"}}))
+                .problem()
+                .is_some()
+        );
+        assert!(
+            update(serde_json::json!({"add_ignored_ips": ["10.0.0.0/33"]}))
+                .problem()
+                .is_some()
+        );
+        assert!(update(serde_json::json!({"set": {"week_start": null}}))
+            .problem()
+            .is_none());
+        // Misspelled settings aren't silently skipped
+        assert!(serde_json::from_value::<BulkServiceUpdate>(
+            serde_json::json!({"set": {"dnt": true}})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_ignored_ips() {
+        let bulk = update(serde_json::json!({
+            "add_ignored_ips": ["10.0.0.0/8", " 192.0.2.1 "],
+            "remove_ignored_ips": ["198.51.100.0/24"],
+        }));
+        assert_eq!(bulk.ignored_ips(""), "10.0.0.0/8, 192.0.2.1/32");
+        assert_eq!(
+            bulk.ignored_ips("198.51.100.0/24,10.0.0.0/8, junk"),
+            "10.0.0.0/8, junk, 192.0.2.1/32"
+        );
+    }
+}
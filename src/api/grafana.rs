@@ -0,0 +1,279 @@
+//! The Grafana JSON datasource contract, so Grafana's JSON and Infinity
+//! datasources can graph shymini next to other dashboards. Point the
+//! datasource at `/grafana` with the API token as a bearer header. Targets
+//! are `<service id>:<metric>`; `/grafana/search` lists them with readable
+//! names. `sessions` and `hits` are time series in buckets of Grafana's
+//! interval, `pages`, `referrers` and `countries` tables of the top values.
+//! All count production traffic.
+
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{tenant_service, ApiResult};
+use crate::auth::ApiTenant;
+use crate::db;
+use crate::domain::{CountedItem, Environment, ServiceId};
+use crate::error::Error;
+use crate::state::AppState;
+
+/// Most buckets of one time series, whatever interval Grafana asks for
+pub const MAX_DATA_POINTS: u64 = 10_000;
+
+/// Shortest bucket of a time series
+const MIN_INTERVAL_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Sessions,
+    Hits,
+    Pages,
+    Referrers,
+    Countries,
+}
+
+impl Metric {
+    pub const ALL: [Self; 5] = [
+        Self::Sessions,
+        Self::Hits,
+        Self::Pages,
+        Self::Referrers,
+        Self::Countries,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sessions => "sessions",
+            Self::Hits => "hits",
+            Self::Pages => "pages",
+            Self::Referrers => "referrers",
+            Self::Countries => "countries",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|metric| metric.as_str() == s)
+    }
+}
+
+/// A service's metric, as `<service id>:<metric>`
+fn parse_target(target: &str) -> Option<(ServiceId, Metric)> {
+    let (service_id, metric) = target.trim().split_once(':')?;
+    Some((ServiceId(service_id.parse().ok()?), Metric::parse(metric)?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchRequest {
+    /// What the user typed, to narrow the targets down
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub text: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    pub range: QueryRange,
+    #[serde(rename = "intervalMs", default)]
+    pub interval_ms: u64,
+    #[serde(rename = "maxDataPoints", default)]
+    pub max_data_points: u64,
+    pub targets: Vec<QueryTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryTarget {
+    #[serde(default)]
+    pub target: String,
+    /// Hidden in the panel, so not queried
+    #[serde(default)]
+    pub hide: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum QueryResult {
+    TimeSeries {
+        target: String,
+        /// `[value, unix milliseconds]` pairs
+        datapoints: Vec<(i64, i64)>,
+    },
+    Table {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        columns: [Column; 2],
+        rows: Vec<(String, i64)>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Column {
+    pub text: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+/// GET /grafana, Grafana's connection test
+pub async fn test_connection(_tenant: ApiTenant) -> ApiResult {
+    Ok("OK".into_response())
+}
+
+/// POST /grafana/search
+pub async fn search(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    input: Option<Json<SearchRequest>>,
+) -> ApiResult {
+    let Json(input) = input.unwrap_or_default();
+    let filter = input.target.trim().to_lowercase();
+
+    let services = db::list_services(&state.pool, tenant.organization_id).await?;
+    let targets: Vec<SearchResult> = services
+        .iter()
+        .flat_map(|service| {
+            Metric::ALL.into_iter().map(|metric| SearchResult {
+                text: format!("{}: {}", service.name, metric.as_str()),
+                value: format!("{}:{}", service.id.0, metric.as_str()),
+            })
+        })
+        .filter(|result| result.text.to_lowercase().contains(&filter))
+        .collect();
+    Ok(Json(targets).into_response())
+}
+
+/// POST /grafana/query
+pub async fn query(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Json(input): Json<QueryRequest>,
+) -> ApiResult {
+    let QueryRange { from, to } = input.range;
+    if to <= from {
+        return Err(Error::BadRequest("The range ends before it starts".to_string()).into());
+    }
+    let range_ms = u64::try_from((to - from).num_milliseconds()).unwrap_or_default();
+    let interval_ms = input
+        .interval_ms
+        .max(MIN_INTERVAL_MS)
+        .max(range_ms.div_ceil(input.max_data_points.clamp(1, MAX_DATA_POINTS)));
+    let environment = Some(Environment::Production);
+
+    let mut results = Vec::new();
+    for target in input.targets.iter().filter(|target| !target.hide) {
+        let Some((service_id, metric)) = parse_target(&target.target) else {
+            let problem = format!("Unknown target \"{}\"", target.target);
+            return Err(Error::BadRequest(problem).into());
+        };
+        let service = tenant_service(&state, &tenant, service_id).await?;
+        let name = format!("{}: {}", service.name, metric.as_str());
+
+        let (label, counted, items) = match metric {
+            Metric::Sessions | Metric::Hits => {
+                let series = db::get_time_series(
+                    &state.pool,
+                    service_id,
+                    from,
+                    to,
+                    environment,
+                    (interval_ms / 1000) as i64,
+                )
+                .await?;
+                let counts = if metric == Metric::Sessions {
+                    &series.sessions
+                } else {
+                    &series.hits
+                };
+                let datapoints = counts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &count)| (count, series.bucket_start(i).timestamp_millis()))
+                    .collect();
+                results.push(QueryResult::TimeSeries {
+                    target: name,
+                    datapoints,
+                });
+                continue;
+            }
+            Metric::Pages => (
+                "Page",
+                "Hits",
+                db::get_top_locations(&state.pool, service_id, from, to, environment, None, None)
+                    .await?,
+            ),
+            Metric::Referrers => {
+                let referrer_filter = service.get_referrer_filter();
+                let mut referrers = db::get_top_referrers(
+                    &state.pool,
+                    service_id,
+                    from,
+                    to,
+                    environment,
+                    Some(&referrer_filter),
+                    None,
+                    None,
+                )
+                .await?;
+                // Named as on the dashboard
+                for referrer in referrers.iter_mut().filter(|r| r.value.is_empty()) {
+                    referrer.value = "Direct".to_string();
+                }
+                ("Referrer", "Sessions", referrers)
+            }
+            Metric::Countries => (
+                "Country",
+                "Sessions",
+                db::get_top_countries(&state.pool, service_id, from, to, environment, None, None)
+                    .await?,
+            ),
+        };
+        results.push(QueryResult::Table {
+            kind: "table",
+            columns: [
+                Column {
+                    text: label,
+                    kind: "string",
+                },
+                Column {
+                    text: counted,
+                    kind: "number",
+                },
+            ],
+            rows: items
+                .into_iter()
+                .map(|CountedItem { value, count, .. }| (value, count))
+                .collect(),
+        });
+    }
+    Ok(Json(results).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        let id = uuid::Uuid::new_v4();
+        assert_eq!(
+            parse_target(&format!("{id}:sessions")),
+            Some((ServiceId(id), Metric::Sessions))
+        );
+        assert_eq!(
+            parse_target(&format!(" {id}:countries ")),
+            Some((ServiceId(id), Metric::Countries))
+        );
+        assert_eq!(parse_target(&format!("{id}:visitors")), None);
+        assert_eq!(parse_target("site:hits"), None);
+        assert_eq!(parse_target("hits"), None);
+    }
+}
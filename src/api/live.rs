@@ -0,0 +1,364 @@
+//! `GET /api/ws`: live stats over a WebSocket, for wallboards and other
+//! custom frontends. Clients authenticate like the rest of the API, with a
+//! bearer token or, as browsers can't set headers on WebSockets, `?token=`.
+//! They then send JSON messages:
+//!
+//! - `{"type": "subscribe", "service_id": "..."}` to get the service's
+//!   counters and its busiest pages and referrers of the last hour every
+//!   few seconds, and each new production hit
+//! - `{"type": "unsubscribe", "service_id": "..."}` to stop
+//!
+//! and get `subscribed`, `unsubscribed`, `counters`, `top`, `hit`, `lagged` (hits
+//! skipped for reading too slowly) and `error` messages back. A client
+//! sending more than `ws_messages_per_min` messages a minute is
+//! disconnected.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{
+            close_code, rejection::WebSocketUpgradeRejection, CloseFrame, Message, WebSocket,
+            WebSocketUpgrade,
+        },
+        Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::error;
+
+use super::{service_timezone, tenant_service, ApiResponse};
+use crate::auth::{bearer_token, ApiTenant};
+use crate::db;
+use crate::domain::{Environment, Hit, LiveCounters, Service, ServiceId};
+use crate::error::Error;
+use crate::realtime::HotValues;
+use crate::state::AppState;
+
+/// Services one connection may subscribe to
+pub const MAX_SUBSCRIPTIONS: usize = 50;
+
+/// How often subscribers get fresh counters
+const COUNTERS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Largest message taken from a client; subscriptions are tiny
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+type Sender = SplitSink<WebSocket, Message>;
+
+#[derive(Debug, Deserialize)]
+pub struct ConnectQuery {
+    /// API token, for clients that can't send an `Authorization` header
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { service_id: ServiceId },
+    Unsubscribe { service_id: ServiceId },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Subscribed {
+        service_id: ServiceId,
+    },
+    Unsubscribed {
+        service_id: ServiceId,
+    },
+    Counters {
+        service_id: ServiceId,
+        time: DateTime<Utc>,
+        #[serde(flatten)]
+        counters: LiveCounters,
+    },
+    Top {
+        service_id: ServiceId,
+        time: DateTime<Utc>,
+        #[serde(flatten)]
+        top: HotValues,
+    },
+    Hit {
+        hit: &'a Hit,
+    },
+    Lagged {
+        skipped: u64,
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+/// GET /api/ws
+pub async fn connect(
+    State(state): State<AppState>,
+    Query(query): Query<ConnectQuery>,
+    headers: HeaderMap,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    let token = bearer_token(&headers).or(query.token.as_deref());
+    let tenant = match ApiTenant::from_token(&state, token).await {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    let Ok(upgrade) = upgrade else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("Expected a WebSocket upgrade")),
+        )
+            .into_response();
+    };
+
+    upgrade
+        .max_message_size(MAX_MESSAGE_BYTES)
+        .on_upgrade(move |socket| run(state, tenant, socket))
+}
+
+async fn run(state: AppState, tenant: ApiTenant, socket: WebSocket) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut connection = Connection {
+        state: &state,
+        tenant: &tenant,
+        subscriptions: HashMap::new(),
+    };
+    if let Ok(Some(close)) = connection.serve(&mut receiver, &mut sender).await {
+        let _ = sender.send(close).await;
+    }
+}
+
+struct Connection<'a> {
+    state: &'a AppState,
+    tenant: &'a ApiTenant,
+    subscriptions: HashMap<ServiceId, Service>,
+}
+
+impl Connection<'_> {
+    /// Answer the client until either side hangs up; the close frame to
+    /// send, if the client is still there
+    async fn serve(
+        &mut self,
+        messages: &mut SplitStream<WebSocket>,
+        writer: &mut Sender,
+    ) -> Result<Option<Message>, axum::Error> {
+        let mut hits = self.state.live.subscribe();
+        let mut counters = tokio::time::interval(COUNTERS_INTERVAL);
+        let mut budget = MessageBudget::new(
+            self.state.settings.ws_messages_per_min,
+            self.state.clock.now(),
+        );
+
+        loop {
+            tokio::select! {
+                message = messages.next() => {
+                    // Protocol errors, oversized messages and text that
+                    // isn't UTF-8 end the connection
+                    let Some(Ok(message)) = message else {
+                        return Ok(None);
+                    };
+                    if !budget.take(self.state.clock.now()) {
+                        return Ok(Some(close(close_code::POLICY, "Too many messages")));
+                    }
+                    match message {
+                        Message::Text(text) => self.handle(&text, writer).await?,
+                        Message::Binary(_) => {
+                            return Ok(Some(close(
+                                close_code::UNSUPPORTED,
+                                "Only text messages are supported",
+                            )));
+                        }
+                        // Pings are answered by the socket itself
+                        Message::Ping(_) | Message::Pong(_) => {}
+                        // And so is closing
+                        Message::Close(_) => return Ok(None),
+                    }
+                }
+                hit = hits.recv() => match hit {
+                    Ok(hit) => {
+                        if hit.environment == Environment::Production
+                            && self.subscriptions.contains_key(&hit.service_id)
+                        {
+                            send(writer, &ServerMessage::Hit { hit: &hit }).await?;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        send(writer, &ServerMessage::Lagged { skipped }).await?;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(None),
+                },
+                _ = counters.tick() => {
+                    for service in self.subscriptions.values() {
+                        self.send_counters(service, writer).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, text: &str, writer: &mut Sender) -> Result<(), axum::Error> {
+        let service_id = match serde_json::from_str(text) {
+            Ok(ClientMessage::Subscribe { service_id }) => service_id,
+            Ok(ClientMessage::Unsubscribe { service_id }) => {
+                self.subscriptions.remove(&service_id);
+                return send(writer, &ServerMessage::Unsubscribed { service_id }).await;
+            }
+            Err(_) => {
+                let message = "Expected a subscribe or unsubscribe message";
+                return send(writer, &ServerMessage::Error { message }).await;
+            }
+        };
+
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS
+            && !self.subscriptions.contains_key(&service_id)
+        {
+            let message = "Too many subscriptions";
+            return send(writer, &ServerMessage::Error { message }).await;
+        }
+        let service = match tenant_service(self.state, self.tenant, service_id).await {
+            Ok(service) => service,
+            Err(Error::ServiceNotFound) => {
+                let message = "Service not found";
+                return send(writer, &ServerMessage::Error { message }).await;
+            }
+            Err(e) => {
+                error!("Error fetching service: {}", e);
+                let message = "Internal error";
+                return send(writer, &ServerMessage::Error { message }).await;
+            }
+        };
+
+        send(writer, &ServerMessage::Subscribed { service_id }).await?;
+        self.send_counters(&service, writer).await?;
+        self.subscriptions.insert(service_id, service);
+        Ok(())
+    }
+
+    async fn send_counters(
+        &self,
+        service: &Service,
+        writer: &mut Sender,
+    ) -> Result<(), axum::Error> {
+        let now = self.state.clock.now();
+        let tz = service_timezone(service);
+        let today = now.with_timezone(&tz).date_naive();
+        let day_start = tz
+            .from_local_datetime(&today.and_time(chrono::NaiveTime::MIN))
+            .earliest()
+            .map_or_else(|| db::day_start(today), |start| start.with_timezone(&Utc));
+        let active_cutoff = now
+            - chrono::Duration::milliseconds(
+                self.state.settings.active_user_timeout_ms(service) as i64
+            );
+
+        match db::get_live_counters(&self.state.pool, service.id, day_start, active_cutoff).await {
+            Ok(counters) => {
+                let message = ServerMessage::Counters {
+                    service_id: service.id,
+                    time: now,
+                    counters,
+                };
+                send(writer, &message).await?;
+            }
+            Err(e) => {
+                error!("Error counting live stats: {}", e);
+            }
+        }
+
+        // Counted at ingress, so these need no query
+        let mut top = self.state.realtime.top(service.id, now);
+        let referrer_filter = service.get_referrer_filter();
+        top.referrers.retain(|r| !referrer_filter.hides(&r.value));
+        let message = ServerMessage::Top {
+            service_id: service.id,
+            time: now,
+            top,
+        };
+        send(writer, &message).await
+    }
+}
+
+fn close(code: u16, reason: &'static str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+}
+
+async fn send(writer: &mut Sender, message: &ServerMessage<'_>) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).map_err(axum::Error::new)?;
+    writer.send(Message::Text(text)).await
+}
+
+/// Messages a client may still send: up to `per_min` at once, refilled
+/// evenly over a minute. 0 doesn't limit them.
+struct MessageBudget {
+    per_min: u32,
+    left: f64,
+    updated: DateTime<Utc>,
+}
+
+impl MessageBudget {
+    fn new(per_min: u32, now: DateTime<Utc>) -> Self {
+        Self {
+            per_min,
+            left: f64::from(per_min),
+            updated: now,
+        }
+    }
+
+    /// Count a message; false once the client has sent too many
+    fn take(&mut self, now: DateTime<Utc>) -> bool {
+        if self.per_min == 0 {
+            return true;
+        }
+        let minutes = (now - self.updated).num_milliseconds().max(0) as f64 / 60_000.0;
+        let per_min = f64::from(self.per_min);
+        self.left = (self.left + minutes * per_min).min(per_min);
+        self.updated = now;
+        if self.left >= 1.0 {
+            self.left -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_budget() {
+        let start = Utc::now();
+        let mut budget = MessageBudget::new(3, start);
+        assert!(budget.take(start));
+        assert!(budget.take(start));
+        assert!(budget.take(start));
+        assert!(!budget.take(start));
+
+        // One message back every 20 seconds
+        let later = start + chrono::Duration::seconds(20);
+        assert!(budget.take(later));
+        assert!(!budget.take(later));
+
+        // Never more than a minute's worth
+        let much_later = later + chrono::Duration::hours(1);
+        for _ in 0..3 {
+            assert!(budget.take(much_later));
+        }
+        assert!(!budget.take(much_later));
+
+        let mut unlimited = MessageBudget::new(0, start);
+        assert!((0..1000).all(|_| unlimited.take(start)));
+    }
+}
@@ -1,19 +1,42 @@
+pub mod bulk;
+pub mod grafana;
+pub mod live;
+pub mod presentation;
+
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, Query, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::{Duration, TimeZone, Utc};
+use chrono::{TimeZone, Utc};
 use chrono_tz::Tz;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 use tracing::error;
 
+use crate::auth::ApiTenant;
 use crate::db;
-use crate::domain::{ServiceId, SessionId};
+use crate::domain::{
+    BreakdownFilters, CompareMode, CoreStats, CreateSavedView, CreateSegment, CreateTrackedLink,
+    DateRangePreset, Environment, LinkId, Permission, SavedView, SavedViewId, Segment, SegmentId,
+    Service, ServiceId, Session, SessionId, SessionPropFilter, TrackedLink, TrackerType,
+};
 use crate::error::Error;
+use crate::export;
+use crate::extract::{self, FromPathParams};
+use crate::geo::countries;
+use crate::i18n::I18n;
+use crate::ingress;
+use crate::install;
+use crate::query;
+use crate::report::Report;
 use crate::state::AppState;
+use crate::status;
+use presentation::Presentation;
 
 #[derive(Debug, Deserialize)]
 pub struct DateRangeQuery {
@@ -23,8 +46,36 @@ pub struct DateRangeQuery {
     pub end_date: Option<String>,
     #[serde(rename = "urlPattern")]
     pub url_pattern: Option<String>,
+    /// Session property filter, `key:value` (e.g. `plan:pro`)
+    pub prop: Option<String>,
+    /// Saved segment of the service to narrow the results to
+    pub segment_id: Option<String>,
     /// Timezone for interpreting dates and displaying results (e.g., "America/New_York")
     pub tz: Option<String>,
+    /// Preset range ending now (e.g. "7d") for when no dates are given;
+    /// the service's default range when unset
+    pub range: Option<String>,
+    /// Environment to report on ("all" for every one); production when unset
+    pub env: Option<String>,
+    /// Period to compare with: `previous_period` (also when unset or
+    /// `true`), `last_month` or `last_week`; `false` compares with none
+    pub compare: Option<String>,
+    /// Breakdown values to narrow the results to (`country`, `browser`,
+    /// `os`, `deviceType`, `referrer`, `page`)
+    #[serde(flatten)]
+    pub filters: BreakdownFilters,
+}
+
+impl DateRangeQuery {
+    /// The comparison the query asks for; unknown modes mean the previous
+    /// period
+    fn compare_mode(&self) -> Option<CompareMode> {
+        match self.compare.as_deref().map(str::trim) {
+            Some("false") => None,
+            Some(mode) => Some(CompareMode::from_str(mode).unwrap_or_default()),
+            None => Some(CompareMode::default()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -54,11 +105,17 @@ impl<T: Serialize> ApiResponse<T> {
     }
 }
 
-/// Parse a timezone string, defaulting to Pacific Time if invalid or not provided
-fn parse_timezone(tz_str: Option<&str>) -> Tz {
-    tz_str
-        .and_then(|s| s.parse::<Tz>().ok())
-        .unwrap_or(chrono_tz::America::Los_Angeles)
+/// Time zone of reports when neither the request nor the service names one
+const FALLBACK_TIMEZONE: Tz = chrono_tz::America::Los_Angeles;
+
+/// Parse a timezone string, defaulting to `default` if invalid or not provided
+fn parse_timezone(tz_str: Option<&str>, default: Tz) -> Tz {
+    tz_str.and_then(|s| s.parse::<Tz>().ok()).unwrap_or(default)
+}
+
+/// The time zone reports on `service` use when the request names none
+pub(crate) fn service_timezone(service: &Service) -> Tz {
+    parse_timezone(Some(&service.time_zone), FALLBACK_TIMEZONE)
 }
 
 /// Parse a datetime string. Supports:
@@ -101,10 +158,21 @@ fn parse_datetime_string(s: &str, is_end: bool, tz: Tz) -> Option<chrono::DateTi
     None
 }
 
-fn parse_date_range(query: &DateRangeQuery) -> (chrono::DateTime<Utc>, chrono::DateTime<Utc>, Tz) {
-    let tz = parse_timezone(query.tz.as_deref());
-    let now = Utc::now();
-    let default_start = now - Duration::days(30);
+/// The query's range, defaulting to its preset's or else `default_range` up
+/// to `now`; dates are read in the query's time zone, else `default_tz`
+fn parse_date_range(
+    query: &DateRangeQuery,
+    now: chrono::DateTime<Utc>,
+    default_tz: Tz,
+    default_range: DateRangePreset,
+) -> (chrono::DateTime<Utc>, chrono::DateTime<Utc>, Tz) {
+    let tz = parse_timezone(query.tz.as_deref(), default_tz);
+    let range = query
+        .range
+        .as_deref()
+        .and_then(DateRangePreset::from_str)
+        .unwrap_or(default_range);
+    let default_start = now - range.duration();
 
     let start = query
         .start_date
@@ -128,233 +196,1117 @@ fn parse_url_pattern(pattern: &Option<String>) -> Option<Regex> {
         .and_then(|s| Regex::new(s).ok())
 }
 
-/// GET /api/services
-pub async fn list_services(State(state): State<AppState>) -> Response {
-    match db::list_services(&state.pool).await {
-        Ok(services) => Json(ApiResponse::success(services)).into_response(),
-        Err(e) => {
-            error!("Error listing services: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to list services")),
-            )
-                .into_response()
+/// The segment the query's `segment_id` names, with the query's breakdown
+/// filters added to its conditions; one of another service, or none at all,
+/// is not found
+async fn query_segment(
+    state: &AppState,
+    service_id: ServiceId,
+    query: &DateRangeQuery,
+) -> Result<Option<Segment>, Error> {
+    let segment = match query.segment_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(id) => {
+            let id: SegmentId = id.parse().map_err(|_| Error::SegmentNotFound)?;
+            let segment = db::get_segment(&state.pool, id).await?;
+            if segment.service_id != service_id {
+                return Err(Error::SegmentNotFound);
+            }
+            Some(segment)
         }
+        None => None,
+    };
+    Ok(Segment::with_filters(
+        segment,
+        service_id,
+        &query.filters,
+        state.clock.now(),
+    ))
+}
+
+/// An error as the API's JSON error body
+#[derive(Debug)]
+pub struct ApiError(pub Error);
+
+/// What API handlers return
+pub type ApiResult = Result<Response, ApiError>;
+
+impl<E: Into<Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.0.status(),
+            Json(ApiResponse::<()>::error(&self.0.public_message())),
+        )
+            .into_response()
     }
 }
 
+/// Typed path parameters of an API route; a malformed one gets a JSON 400
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: FromPathParams,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(extract::path_params(parts, state).await?))
+    }
+}
+
+/// The tenant's service; other organizations' services are not found
+async fn tenant_service(
+    state: &AppState,
+    tenant: &ApiTenant,
+    service_id: ServiceId,
+) -> Result<Service, Error> {
+    db::get_organization_service(&state.pool, tenant.organization_id, service_id).await
+}
+
+/// GET /api/organization
+pub async fn get_organization(State(state): State<AppState>, tenant: ApiTenant) -> ApiResult {
+    let organization = db::get_organization(&state.pool, tenant.organization_id).await?;
+    Ok(Json(ApiResponse::success(organization)).into_response())
+}
+
+/// GET /api/services
+pub async fn list_services(State(state): State<AppState>, tenant: ApiTenant) -> ApiResult {
+    let services = db::list_services(&state.pool, tenant.organization_id).await?;
+    Ok(Json(ApiResponse::success(services)).into_response())
+}
+
 /// GET /api/services/:id
 pub async fn get_service(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("Invalid service ID")),
-            )
-                .into_response()
-        }
-    };
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+    Ok(Json(ApiResponse::success(service)).into_response())
+}
 
-    match db::get_service(&state.pool, service_id).await {
-        Ok(service) => Json(ApiResponse::success(service)).into_response(),
-        Err(Error::ServiceNotFound) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<()>::error("Service not found")),
-        )
-            .into_response(),
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to fetch service")),
-            )
-                .into_response()
-        }
+/// Most pixel URLs minted by one request
+const MAX_PIXEL_URLS: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct PixelUrlsRequest {
+    /// Identifiers to mint URLs for, e.g. newsletter recipients
+    pub identifiers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PixelUrl {
+    pub identifier: String,
+    pub url: String,
+}
+
+/// POST /api/services/:id/pixel-urls
+///
+/// Signed pixel URLs for the given identifiers, to embed in emails
+pub async fn create_pixel_urls(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Json(input): Json<PixelUrlsRequest>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    if input.identifiers.len() > MAX_PIXEL_URLS {
+        let problem = format!("At most {} identifiers per request", MAX_PIXEL_URLS);
+        return Err(Error::BadRequest(problem).into());
     }
+    if input.identifiers.iter().any(|i| i.trim().is_empty()) {
+        return Err(Error::BadRequest("Identifiers may not be empty".to_string()).into());
+    }
+
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let urls = input
+        .identifiers
+        .into_iter()
+        .map(|identifier| {
+            let url = ingress::signed_pixel_url(&state, &service, &identifier)?;
+            Ok(PixelUrl { identifier, url })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Json(ApiResponse::success(urls)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// Months of history to return, including the current one
+    pub months: Option<u32>,
+}
+
+/// GET /api/services/:id/usage
+pub async fn get_service_usage(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<UsageQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let months = query.months.unwrap_or(12).clamp(1, 36);
+    let usage = db::get_quota_usage(&state.pool, &service, state.clock.now(), months).await?;
+    Ok(Json(ApiResponse::success(usage)).into_response())
+}
+
+/// A service's stats with hints for showing them
+#[derive(Debug, Serialize)]
+pub struct ServiceStats {
+    #[serde(flatten)]
+    pub stats: CoreStats,
+    pub presentation: Presentation,
 }
 
 /// GET /api/services/:id/stats
 pub async fn get_service_stats(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: ApiTenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
     Query(query): Query<DateRangeQuery>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("Invalid service ID")),
-            )
-                .into_response()
-        }
-    };
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
 
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (
-                StatusCode::NOT_FOUND,
-                Json(ApiResponse::<()>::error("Service not found")),
-            )
-                .into_response()
-        }
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to fetch service")),
-            )
-                .into_response();
-        }
-    };
+    let now = state.clock.now();
+    let (start, end, tz) = parse_date_range(
+        &query,
+        now,
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
+    let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = query_segment(&state, service_id, &query).await?;
+    let prop = query.prop.as_deref().and_then(SessionPropFilter::parse);
+
+    let referrer_filter = service.get_referrer_filter();
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let presentation = Presentation::load(&state, &tenant, &service, &i18n, tz).await?;
+    let mut stats = db::get_core_stats(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        environment,
+        now,
+        Some(&referrer_filter),
+        url_pattern.as_ref(),
+        prop.as_ref(),
+        segment.as_ref(),
+        state.settings.active_user_timeout_ms(&service),
+        tz,
+        presentation.week_start,
+        query.compare_mode(),
+    )
+    .await?;
+
+    state.hooks.adjust_stats(&state, &service, &mut stats).await;
+
+    countries::localize_stats(&mut stats, &i18n);
+    Ok(Json(ApiResponse::success(ServiceStats {
+        stats,
+        presentation,
+    }))
+    .into_response())
+}
 
-    let (start, end, tz) = parse_date_range(&query);
+/// GET /api/services/:id/report.pdf
+pub async fn get_service_report(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let now = state.clock.now();
+    let (start, end, tz) = parse_date_range(
+        &query,
+        now,
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
     let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = query_segment(&state, service_id, &query).await?;
+    let prop = query.prop.as_deref().and_then(SessionPropFilter::parse);
 
-    let hide_referrer_regex = if service.hide_referrer_regex.is_empty() {
-        None
-    } else {
-        Regex::new(&service.hide_referrer_regex).ok()
-    };
+    let referrer_filter = service.get_referrer_filter();
 
-    match db::get_core_stats(
+    // The chart's weeks start where the API's presentation hints say
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let presentation = Presentation::load(&state, &tenant, &service, &i18n, tz).await?;
+    let mut stats = db::get_core_stats(
         &state.pool,
         service_id,
         start,
         end,
-        hide_referrer_regex.as_ref(),
+        environment,
+        now,
+        Some(&referrer_filter),
         url_pattern.as_ref(),
-        state.settings.active_user_timeout_ms(),
+        prop.as_ref(),
+        segment.as_ref(),
+        state.settings.active_user_timeout_ms(&service),
         tz,
+        presentation.week_start,
+        // The report shows no previous period
+        None,
     )
-    .await
-    {
-        Ok(stats) => Json(ApiResponse::success(stats)).into_response(),
-        Err(e) => {
-            error!("Error fetching stats: {}", e);
+    .await?;
+    state.hooks.adjust_stats(&state, &service, &mut stats).await;
+
+    let report = Report {
+        title: &service.name,
+        start,
+        end,
+        tz,
+        stats: &stats,
+        generated_at: now,
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to fetch stats")),
-            )
-                .into_response()
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", report.filename()),
+            ),
+        ],
+        report.render_pdf(&i18n),
+    )
+        .into_response())
+}
+
+/// GET /api/services/:id/export.parquet
+///
+/// The range's hits, oldest first, as a Parquet file streamed as it is
+/// written; each hit carries its session's country, browser, OS and device
+/// type
+pub async fn export_hits_parquet(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
+
+    let (sink, hits) = mpsc::channel(STREAM_BUFFER);
+    let pool = state.pool.clone();
+    tokio::spawn(async move {
+        let result = db::stream_hits(&pool, service_id, start, end, environment, &sink).await;
+        if let Err(e) = result {
+            error!("Exporting hits of service {} failed: {}", service_id, e);
+            let _ = sink.send(Err(e)).await;
+        }
+    });
+
+    let filename = format!(
+        "{}-hits-{}-{}.parquet",
+        service.tracking_id,
+        start.with_timezone(&tz).format("%Y-%m-%d"),
+        end.with_timezone(&tz).format("%Y-%m-%d")
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, export::PARQUET.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(export::hits_parquet(hits)),
+    )
+        .into_response())
+}
+
+/// GET /api/services/:id/content-groups
+///
+/// Hits per content group in the date range; hits on pages outside every
+/// group are counted under `""`
+pub async fn get_content_groups(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
+    let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = query_segment(&state, service_id, &query).await?;
+
+    let groups = db::get_content_group_counts(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        environment,
+        &service.get_content_groups(),
+        url_pattern.as_ref(),
+        segment.as_ref(),
+    )
+    .await?;
+    Ok(Json(ApiResponse::success(groups)).into_response())
+}
+
+/// GET /api/services/:id/dimensions/:key
+///
+/// Hits per value of a custom dimension in the date range
+pub async fn get_dimension_counts(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path((service_id, key)): Path<(ServiceId, String)>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
+    let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = query_segment(&state, service_id, &query).await?;
+
+    let counts = db::get_dimension_counts(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        environment,
+        &key,
+        url_pattern.as_ref(),
+        segment.as_ref(),
+    )
+    .await?;
+    Ok(Json(ApiResponse::success(counts)).into_response())
+}
+
+/// GET /api/services/:id/paths
+///
+/// The most common page-to-page transitions within sessions in the date
+/// range, as an edge list for a Sankey diagram: the nodes are pages at a step
+/// of the visit, so the graph has no cycles
+pub async fn get_page_paths(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
+    let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = query_segment(&state, service_id, &query).await?;
+
+    let transitions = db::get_page_transitions(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        environment,
+        url_pattern.as_ref(),
+        segment.as_ref(),
+    )
+    .await?;
+    Ok(Json(ApiResponse::success(transitions)).into_response())
+}
+
+/// GET /api/services/:id/verify-install
+///
+/// Fetches the service's site now and reports whether its tracker is there;
+/// the dashboard shows the latest result
+pub async fn verify_install(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let check = install::verify_install(&service, state.clock.now()).await;
+    state
+        .cache
+        .set_install_check(service_id, check.clone())
+        .await;
+    Ok(Json(ApiResponse::success(check)).into_response())
+}
+
+/// A page view to try on a service, as a browser would send it
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HitTrial {
+    /// Send it to the pixel rather than as a tracker POST
+    pub pixel: bool,
+    /// Page the hit is for, also sent as the `Referer`
+    pub location: String,
+    pub referrer: String,
+    /// `Origin` of the page; by default that of `location`
+    pub origin: Option<String>,
+    pub user_agent: String,
+    /// The visitor's address; `0.0.0.0` if none
+    pub ip: Option<String>,
+    /// Send `DNT: 1`
+    pub dnt: bool,
+    /// Send `Sec-GPC: 1`
+    pub gpc: bool,
+    /// Environment the embed code asks for, as `?env=`
+    pub env: Option<String>,
+}
+
+impl HitTrial {
+    /// The request headers a browser would send with the page view
+    fn headers(&self) -> Result<HeaderMap, Error> {
+        let mut headers = HeaderMap::new();
+        let fields = [
+            (header::REFERER.as_str(), Some(self.location.as_str())),
+            (header::ORIGIN.as_str(), self.origin.as_deref()),
+            (header::USER_AGENT.as_str(), Some(self.user_agent.as_str())),
+            ("x-forwarded-for", self.ip.as_deref()),
+            ("dnt", self.dnt.then_some("1")),
+            ("sec-gpc", self.gpc.then_some("1")),
+        ];
+        for (name, value) in fields {
+            let Some(value) = value.filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            let value = value
+                .parse()
+                .map_err(|_| Error::BadRequest(format!("Invalid {}: {}", name, value)))?;
+            headers.insert(name, value);
         }
+        Ok(headers)
     }
 }
 
+/// POST /api/services/:id/validate-hit
+///
+/// Runs a page view through ingress without recording it and answers with
+/// the decision and what would be stored, to debug an integration
+pub async fn validate_hit(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Json(trial): Json<HitTrial>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let headers = trial.headers()?;
+    let (tracker, payload) = if trial.pixel {
+        let payload = ingress::IngressPayload {
+            location: trial.location,
+            ..Default::default()
+        };
+        (TrackerType::Pixel, payload)
+    } else {
+        let payload = ingress::IngressPayload {
+            location: trial.location,
+            referrer: trial.referrer,
+            load_time: Some(1.0),
+            ..Default::default()
+        };
+        (TrackerType::Js, payload)
+    };
+    let validation = ingress::validate_hit(
+        &state,
+        &service,
+        tracker,
+        &headers,
+        trial.env.as_deref(),
+        payload,
+    )
+    .await?;
+    Ok(Json(ApiResponse::success(validation)).into_response())
+}
+
+/// Content type of newline-delimited JSON
+const NDJSON: &str = "application/x-ndjson";
+
+/// Rows buffered between a streaming query and a slow client
+const STREAM_BUFFER: usize = 256;
+
+/// Whether the client asked for newline-delimited JSON
+fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains(NDJSON))
+}
+
+/// The rows received as newline-delimited JSON, each written as it comes
+/// as `show` makes it. An error cuts the body off, so clients can tell the
+/// export is incomplete.
+fn ndjson_response<T: Serialize + Send + 'static>(
+    rows: mpsc::Receiver<Result<T, Error>>,
+    show: impl Fn(T) -> T + Send + 'static,
+) -> Response {
+    let lines = futures_util::stream::unfold((rows, show), |(mut rows, show)| async move {
+        let line = rows.recv().await?.and_then(|row| {
+            let mut line = serde_json::to_vec(&show(row))?;
+            line.push(b'\n');
+            Ok(line)
+        });
+        Some((line, (rows, show)))
+    });
+    ([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(lines)).into_response()
+}
+
 /// GET /api/services/:id/sessions
+///
+/// The range's latest 100 sessions, or with `Accept: application/x-ndjson`
+/// all of them, streamed one per line
 pub async fn list_sessions(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    headers: HeaderMap,
     Query(query): Query<DateRangeQuery>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("Invalid service ID")),
-            )
-                .into_response()
-        }
-    };
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
 
-    let (start, end, _tz) = parse_date_range(&query);
+    let (start, end, _tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
     let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = query_segment(&state, service_id, &query).await?;
+
+    if accepts_ndjson(&headers) {
+        let (sink, rows) = mpsc::channel(STREAM_BUFFER);
+        let pool = state.pool.clone();
+        tokio::spawn(async move {
+            let result = db::stream_sessions(
+                &pool,
+                service_id,
+                start,
+                end,
+                environment,
+                url_pattern.as_ref(),
+                segment.as_ref(),
+                &sink,
+            )
+            .await;
+            if let Err(e) = result {
+                error!("Streaming sessions of service {} failed: {}", service_id, e);
+                let _ = sink.send(Err(e)).await;
+            }
+        });
+        return Ok(ndjson_response(rows, move |session| {
+            state.reveal_ip(session)
+        }));
+    }
 
-    match db::list_sessions(
+    let sessions = db::list_sessions(
         &state.pool,
         service_id,
         start,
         end,
+        environment,
         url_pattern.as_ref(),
+        segment.as_ref(),
         100,
         0,
     )
-    .await
-    {
-        Ok(sessions) => Json(ApiResponse::success(sessions)).into_response(),
-        Err(e) => {
-            error!("Error listing sessions: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to list sessions")),
-            )
-                .into_response()
-        }
-    }
+    .await?;
+    let sessions: Vec<_> = sessions.into_iter().map(|s| state.reveal_ip(s)).collect();
+    Ok(Json(ApiResponse::success(sessions)).into_response())
+}
+
+/// GET /api/services/:id/campaigns
+///
+/// Email campaigns opened in the range, from pixel hits whose identifier is
+/// `campaign:recipient`
+pub async fn list_campaigns(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let campaigns = db::list_campaigns(&state.pool, service_id, start, end).await?;
+    Ok(Json(ApiResponse::success(campaigns)).into_response())
+}
+
+/// GET /api/services/:id/campaigns/:campaign
+///
+/// A campaign's opens, open times and recipients in the range
+pub async fn get_campaign(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path((service_id, campaign)): Path<(ServiceId, String)>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let report = db::get_campaign_report(&state.pool, service_id, &campaign, start, end)
+        .await?
+        .ok_or(Error::CampaignNotFound)?;
+    Ok(Json(ApiResponse::success(report)).into_response())
+}
+
+/// GET /api/services/:id/users
+///
+/// Sessions and devices per user in the range, for services linking sessions
+/// under hashed login identifiers
+pub async fn get_users(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let stats = db::get_user_stats(&state.pool, service_id, start, end).await?;
+    Ok(Json(ApiResponse::success(stats)).into_response())
+}
+
+/// GET /api/services/:id/retention
+///
+/// Weekly cohorts of the users first seen in the range (by default the last
+/// 90 days) and the share of each that came back in the weeks after, for
+/// services linking users; weeks begin as in the stats' `presentation`
+pub async fn get_retention(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        DateRangePreset::Quarter,
+    );
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let presentation = Presentation::load(&state, &tenant, &service, &i18n, tz).await?;
+    let retention = db::get_retention(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        tz,
+        presentation.week_start,
+    )
+    .await?;
+    Ok(Json(ApiResponse::success(retention)).into_response())
 }
 
 /// GET /api/sessions/:id
 pub async fn get_session(
     State(state): State<AppState>,
-    Path(session_id): Path<String>,
-) -> Response {
-    let session_id: SessionId = match session_id.parse() {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("Invalid session ID")),
-            )
-                .into_response()
-        }
-    };
+    tenant: ApiTenant,
+    Path(session_id): Path<SessionId>,
+) -> ApiResult {
+    let session = tenant_session(&state, &tenant, session_id).await?;
+    Ok(Json(ApiResponse::success(state.reveal_ip(session))).into_response())
+}
 
-    match db::get_session(&state.pool, session_id).await {
-        Ok(session) => Json(ApiResponse::success(session)).into_response(),
-        Err(Error::SessionNotFound) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<()>::error("Session not found")),
-        )
-            .into_response(),
-        Err(e) => {
-            error!("Error fetching session: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to fetch session")),
-            )
-                .into_response()
-        }
+/// The session if it belongs to one of the tenant's services
+async fn tenant_session(
+    state: &AppState,
+    tenant: &ApiTenant,
+    session_id: SessionId,
+) -> Result<Session, Error> {
+    let session = db::get_session(&state.pool, session_id).await?;
+    match tenant_service(state, tenant, session.service_id).await {
+        Ok(_) => Ok(session),
+        // Another organization's session is as good as missing
+        Err(Error::ServiceNotFound) => Err(Error::SessionNotFound),
+        Err(e) => Err(e),
     }
 }
 
 /// GET /api/sessions/:id/hits
 pub async fn list_session_hits(
     State(state): State<AppState>,
-    Path(session_id): Path<String>,
-) -> Response {
-    let session_id: SessionId = match session_id.parse() {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("Invalid session ID")),
-            )
-                .into_response()
-        }
+    tenant: ApiTenant,
+    Path(session_id): Path<SessionId>,
+) -> ApiResult {
+    tenant_session(&state, &tenant, session_id).await?;
+
+    let hits = db::list_hits_for_session(&state.pool, session_id, 100, 0).await?;
+    Ok(Json(ApiResponse::success(hits)).into_response())
+}
+
+/// GET /api/services/:id/views
+pub async fn list_saved_views(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+) -> ApiResult {
+    tenant_service(&state, &tenant, service_id).await?;
+
+    let views = db::list_saved_views(&state.pool, service_id).await?;
+    Ok(Json(ApiResponse::success(views)).into_response())
+}
+
+/// POST /api/services/:id/views
+pub async fn create_saved_view(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Json(input): Json<CreateSavedView>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    if input.name.trim().is_empty() {
+        return Err(Error::BadRequest("Name is required".to_string()).into());
+    }
+
+    tenant_service(&state, &tenant, service_id).await?;
+
+    let view = db::create_saved_view(&state.pool, service_id, input).await?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(view))).into_response())
+}
+
+/// GET /api/views/:id
+pub async fn get_saved_view(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(view_id): Path<SavedViewId>,
+) -> ApiResult {
+    let view = tenant_saved_view(&state, &tenant, view_id).await?;
+    Ok(Json(ApiResponse::success(view)).into_response())
+}
+
+/// The saved view if it belongs to one of the tenant's services
+async fn tenant_saved_view(
+    state: &AppState,
+    tenant: &ApiTenant,
+    view_id: SavedViewId,
+) -> Result<SavedView, Error> {
+    let view = db::get_saved_view(&state.pool, view_id).await?;
+    match tenant_service(state, tenant, view.service_id).await {
+        Ok(_) => Ok(view),
+        Err(Error::ServiceNotFound) => Err(Error::SavedViewNotFound),
+        Err(e) => Err(e),
+    }
+}
+
+/// DELETE /api/views/:id
+pub async fn delete_saved_view(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(view_id): Path<SavedViewId>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+    tenant_saved_view(&state, &tenant, view_id).await?;
+
+    db::delete_saved_view(&state.pool, view_id).await?;
+    Ok(Json(ApiResponse::success(())).into_response())
+}
+
+/// GET /api/services/:id/segments
+pub async fn list_segments(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+) -> ApiResult {
+    tenant_service(&state, &tenant, service_id).await?;
+
+    let segments = db::list_segments(&state.pool, service_id).await?;
+    Ok(Json(ApiResponse::success(segments)).into_response())
+}
+
+/// POST /api/services/:id/segments
+pub async fn create_segment(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Json(input): Json<CreateSegment>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    if let Some(problem) = input.problem() {
+        return Err(Error::BadRequest(problem.to_string()).into());
+    }
+
+    tenant_service(&state, &tenant, service_id).await?;
+
+    let segment = db::create_segment(&state.pool, service_id, input).await?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(segment))).into_response())
+}
+
+/// GET /api/segments/:id
+pub async fn get_segment(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(segment_id): Path<SegmentId>,
+) -> ApiResult {
+    let segment = tenant_segment(&state, &tenant, segment_id).await?;
+    Ok(Json(ApiResponse::success(segment)).into_response())
+}
+
+/// The segment if it belongs to one of the tenant's services
+async fn tenant_segment(
+    state: &AppState,
+    tenant: &ApiTenant,
+    segment_id: SegmentId,
+) -> Result<Segment, Error> {
+    let segment = db::get_segment(&state.pool, segment_id).await?;
+    match tenant_service(state, tenant, segment.service_id).await {
+        Ok(_) => Ok(segment),
+        Err(Error::ServiceNotFound) => Err(Error::SegmentNotFound),
+        Err(e) => Err(e),
+    }
+}
+
+/// DELETE /api/segments/:id
+pub async fn delete_segment(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(segment_id): Path<SegmentId>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+    tenant_segment(&state, &tenant, segment_id).await?;
+
+    db::delete_segment(&state.pool, segment_id).await?;
+    Ok(Json(ApiResponse::success(())).into_response())
+}
+
+/// A tracked link, or its stats, with the URL to share it as
+#[derive(Debug, Serialize)]
+pub struct SharedLink<T> {
+    #[serde(flatten)]
+    pub link: T,
+    pub url: String,
+}
+
+/// GET /api/services/:id/links
+///
+/// The service's tracked links with their clicks in the range (same date
+/// range parameters as stats)
+pub async fn list_links(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let service = tenant_service(&state, &tenant, service_id).await?;
+
+    let (start, end, _tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let links: Vec<_> = db::get_tracked_link_stats(&state.pool, service_id, start, end)
+        .await?
+        .into_iter()
+        .map(|stats| SharedLink {
+            url: ingress::tracked_link_url(&state, &stats.link),
+            link: stats,
+        })
+        .collect();
+    Ok(Json(ApiResponse::success(links)).into_response())
+}
+
+/// POST /api/services/:id/links
+pub async fn create_link(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(service_id): Path<ServiceId>,
+    Json(input): Json<CreateTrackedLink>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    if let Some(problem) = input.problem() {
+        return Err(Error::BadRequest(problem.to_string()).into());
+    }
+
+    tenant_service(&state, &tenant, service_id).await?;
+
+    let link = db::create_tracked_link(&state.pool, service_id, input).await?;
+    let link = SharedLink {
+        url: ingress::tracked_link_url(&state, &link),
+        link,
     };
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(link))).into_response())
+}
 
-    match db::list_hits_for_session(&state.pool, session_id, 100, 0).await {
-        Ok(hits) => Json(ApiResponse::success(hits)).into_response(),
-        Err(e) => {
-            error!("Error listing hits: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Failed to list hits")),
-            )
-                .into_response()
-        }
+/// A tracked link and its service, if the service belongs to the tenant's
+/// organization
+async fn tenant_link(
+    state: &AppState,
+    tenant: &ApiTenant,
+    link_id: LinkId,
+) -> Result<(Service, TrackedLink), Error> {
+    let link = db::get_tracked_link(&state.pool, link_id).await?;
+    match tenant_service(state, tenant, link.service_id).await {
+        Ok(service) => Ok((service, link)),
+        Err(Error::ServiceNotFound) => Err(Error::LinkNotFound),
+        Err(e) => Err(e),
     }
 }
 
+/// GET /api/links/:id
+///
+/// One tracked link's clicks in the range with clicks per day, referrers and
+/// countries (same date range parameters as stats)
+pub async fn get_link(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    headers: HeaderMap,
+    Path(link_id): Path<LinkId>,
+    Query(query): Query<DateRangeQuery>,
+) -> ApiResult {
+    let (service, _) = tenant_link(&state, &tenant, link_id).await?;
+
+    let (start, end, _tz) = parse_date_range(
+        &query,
+        state.clock.now(),
+        service_timezone(&service),
+        service.default_range,
+    );
+    let mut report =
+        db::get_tracked_link_report(&state.pool, service.id, link_id, start, end).await?;
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    countries::enrich_countries(&mut report.countries, &i18n);
+    let report = SharedLink {
+        url: ingress::tracked_link_url(&state, &report.stats.link),
+        link: report,
+    };
+    Ok(Json(ApiResponse::success(report)).into_response())
+}
+
+/// GET /api/links/:id/qr.svg
+///
+/// QR code of the link's URL, for print
+pub async fn get_link_qr_code(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(link_id): Path<LinkId>,
+) -> ApiResult {
+    let (_, link) = tenant_link(&state, &tenant, link_id).await?;
+
+    let svg = ingress::tracked_link_qr_svg(&state, &link)?;
+    Ok((
+        [(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")],
+        svg,
+    )
+        .into_response())
+}
+
+/// DELETE /api/links/:id
+pub async fn delete_link(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Path(link_id): Path<LinkId>,
+) -> ApiResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    tenant_link(&state, &tenant, link_id).await?;
+
+    db::delete_tracked_link(&state.pool, link_id).await?;
+    Ok(Json(ApiResponse::success(())).into_response())
+}
+
+/// GET /api/status
+///
+/// The admin status page as JSON; for owners of the default organization
+pub async fn get_status(State(state): State<AppState>, tenant: ApiTenant) -> ApiResult {
+    tenant.authorize_admin()?;
+
+    let status = status::system_status(&state).await?;
+    Ok(Json(ApiResponse::success(status)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    pub sql: String,
+}
+
+/// POST /api/query
+///
+/// Read-only SQL against the database, for owners of the default
+/// organization on servers with `query_api` on
+pub async fn run_query(
+    State(state): State<AppState>,
+    tenant: ApiTenant,
+    Json(request): Json<QueryRequest>,
+) -> ApiResult {
+    // Queries read every table, so this takes a token even on a
+    // single-tenant instance
+    tenant.authorize_token_admin()?;
+    if !state.settings.query_api {
+        return Err(Error::BadRequest("The query API is turned off".to_string()).into());
+    }
+
+    let result = query::run_query(
+        &state.pool,
+        &request.sql,
+        std::time::Duration::from_secs(state.settings.query_timeout_secs),
+        state.settings.query_max_rows,
+    )
+    .await?;
+    Ok(Json(ApiResponse::success(result)).into_response())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::Duration;
 
     #[test]
     fn test_api_response_success() {
@@ -391,17 +1343,55 @@ mod tests {
             start_date: None,
             end_date: None,
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: None,
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (start, end, _tz) = parse_date_range(&query);
+        let now = Utc::now();
+        let (start, end, _tz) =
+            parse_date_range(&query, now, FALLBACK_TIMEZONE, DateRangePreset::default());
 
         // Default is last 30 days
+        assert_eq!(start, now - Duration::days(30));
+        assert_eq!(end, now);
+    }
+
+    #[test]
+    fn test_parse_date_range_presets() {
+        let mut query = DateRangeQuery {
+            start_date: None,
+            end_date: None,
+            url_pattern: None,
+            prop: None,
+            segment_id: None,
+            tz: None,
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
+        };
         let now = Utc::now();
-        let expected_start = now - Duration::days(30);
 
-        // Should be within a second of expected
-        assert!((start - expected_start).num_seconds().abs() < 2);
-        assert!((end - now).num_seconds().abs() < 2);
+        // The default range applies when the query names none
+        let (start, _end, _tz) =
+            parse_date_range(&query, now, FALLBACK_TIMEZONE, DateRangePreset::Week);
+        assert_eq!(start, now - Duration::days(7));
+
+        query.range = Some("24h".to_string());
+        let (start, end, _tz) =
+            parse_date_range(&query, now, FALLBACK_TIMEZONE, DateRangePreset::Week);
+        assert_eq!(start, now - Duration::hours(24));
+        assert_eq!(end, now);
+
+        // Explicit dates win over the preset
+        query.start_date = Some("2024-01-01".to_string());
+        let (start, _end, _tz) =
+            parse_date_range(&query, now, FALLBACK_TIMEZONE, DateRangePreset::Week);
+        assert_eq!(start.format("%Y-%m-%d").to_string(), "2024-01-01");
     }
 
     #[test]
@@ -410,9 +1400,20 @@ mod tests {
             start_date: Some("2024-01-01".to_string()),
             end_date: None,
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: None,
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (start, _end, _tz) = parse_date_range(&query);
+        let (start, _end, _tz) = parse_date_range(
+            &query,
+            Utc::now(),
+            FALLBACK_TIMEZONE,
+            DateRangePreset::default(),
+        );
 
         assert_eq!(start.format("%Y-%m-%d").to_string(), "2024-01-01");
     }
@@ -425,9 +1426,20 @@ mod tests {
             start_date: None,
             end_date: Some("2099-12-31".to_string()),
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: Some("UTC".to_string()),
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (_start, end, _tz) = parse_date_range(&query);
+        let (_start, end, _tz) = parse_date_range(
+            &query,
+            Utc::now(),
+            FALLBACK_TIMEZONE,
+            DateRangePreset::default(),
+        );
 
         assert_eq!(end.format("%Y-%m-%d").to_string(), "2099-12-31");
     }
@@ -439,9 +1451,20 @@ mod tests {
             start_date: Some("2024-06-01".to_string()),
             end_date: Some("2024-06-30".to_string()),
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: Some("UTC".to_string()),
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (start, end, _tz) = parse_date_range(&query);
+        let (start, end, _tz) = parse_date_range(
+            &query,
+            Utc::now(),
+            FALLBACK_TIMEZONE,
+            DateRangePreset::default(),
+        );
 
         assert_eq!(start.format("%Y-%m-%d").to_string(), "2024-06-01");
         assert_eq!(end.format("%Y-%m-%d").to_string(), "2024-06-30");
@@ -453,14 +1476,20 @@ mod tests {
             start_date: Some("not-a-date".to_string()),
             end_date: None,
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: None,
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (start, _end, _tz) = parse_date_range(&query);
+        let now = Utc::now();
+        let (start, _end, _tz) =
+            parse_date_range(&query, now, FALLBACK_TIMEZONE, DateRangePreset::default());
 
         // Should fall back to default (30 days ago)
-        let now = Utc::now();
-        let expected_start = now - Duration::days(30);
-        assert!((start - expected_start).num_seconds().abs() < 2);
+        assert_eq!(start, now - Duration::days(30));
     }
 
     #[test]
@@ -469,13 +1498,20 @@ mod tests {
             start_date: None,
             end_date: Some("invalid".to_string()),
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: None,
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (_start, end, _tz) = parse_date_range(&query);
+        let now = Utc::now();
+        let (_start, end, _tz) =
+            parse_date_range(&query, now, FALLBACK_TIMEZONE, DateRangePreset::default());
 
         // Should fall back to now
-        let now = Utc::now();
-        assert!((end - now).num_seconds().abs() < 2);
+        assert_eq!(end, now);
     }
 
     #[test]
@@ -485,9 +1521,20 @@ mod tests {
             start_date: Some("2024-06-01T09:30".to_string()),
             end_date: Some("2024-06-30T17:45".to_string()),
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: Some("UTC".to_string()),
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (start, end, _tz) = parse_date_range(&query);
+        let (start, end, _tz) = parse_date_range(
+            &query,
+            Utc::now(),
+            FALLBACK_TIMEZONE,
+            DateRangePreset::default(),
+        );
 
         assert_eq!(
             start.format("%Y-%m-%dT%H:%M").to_string(),
@@ -504,9 +1551,20 @@ mod tests {
             start_date: Some("2024-06-01T14:00".to_string()),
             end_date: Some("2024-06-30".to_string()),
             url_pattern: None,
+            prop: None,
+            segment_id: None,
             tz: Some("UTC".to_string()),
+            range: None,
+            env: None,
+            compare: None,
+            filters: BreakdownFilters::default(),
         };
-        let (start, end, _tz) = parse_date_range(&query);
+        let (start, end, _tz) = parse_date_range(
+            &query,
+            Utc::now(),
+            FALLBACK_TIMEZONE,
+            DateRangePreset::default(),
+        );
 
         assert_eq!(
             start.format("%Y-%m-%dT%H:%M").to_string(),
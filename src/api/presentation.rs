@@ -0,0 +1,180 @@
+//! Hints for showing API numbers the way their reader expects, so frontends
+//! built on the API needn't assume US formats: the separators of the
+//! request's language (its Accept-Language), the first day of the week and
+//! clock the token's creator picked on their account page (else the
+//! service's week start and the language's custom), and the colors of the organization's dashboard theme
+//! for charts and tooltips.
+
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::auth::ApiTenant;
+use crate::db;
+use crate::domain::{HourCycle, Service, ThemeMode, UserSettings, WeekStart};
+use crate::error::Result;
+use crate::i18n::I18n;
+use crate::state::AppState;
+use crate::theme::Theme;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Presentation {
+    /// Language tag, e.g. for `Intl.NumberFormat`
+    pub locale: &'static str,
+    pub decimal_separator: &'static str,
+    pub group_separator: &'static str,
+    /// Also where the chart's weekly buckets begin
+    pub week_start: WeekStart,
+    /// As `Intl.DateTimeFormat`'s `hourCycle`
+    pub hour_cycle: HourCycle,
+    /// IANA time zone the stats' days and hours are in
+    pub time_zone: String,
+    pub chart: ChartTheme,
+}
+
+/// The dashboard theme as chart libraries take it
+#[derive(Debug, Clone, Serialize)]
+pub struct ChartTheme {
+    /// Also the tooltip style, as ApexCharts' and ECharts' `theme`
+    pub mode: ThemeMode,
+    /// Series colors in the dashboard's order: sessions, then hits
+    pub colors: [String; 2],
+    /// Axis labels and legends
+    pub text_color: &'static str,
+    pub grid_color: &'static str,
+}
+
+impl From<Theme> for ChartTheme {
+    fn from(theme: Theme) -> Self {
+        Self {
+            text_color: theme.text_secondary(),
+            grid_color: theme.border(),
+            mode: theme.mode,
+            colors: [theme.strong, theme.accent],
+        }
+    }
+}
+
+impl Presentation {
+    /// `service_week_start` is the service's own, which the user's overrides
+    pub fn new(
+        i18n: &I18n,
+        preferences: &UserSettings,
+        service_week_start: Option<WeekStart>,
+        tz: Tz,
+        theme: Theme,
+    ) -> Self {
+        let locale = i18n.locale();
+        Self {
+            locale: locale.as_str(),
+            decimal_separator: locale.decimal_separator(),
+            group_separator: locale.group_separator(),
+            week_start: preferences
+                .week_start
+                .or(service_week_start)
+                .unwrap_or(locale.week_start()),
+            hour_cycle: preferences.hour_cycle.unwrap_or(locale.hour_cycle()),
+            time_zone: tz.name().to_string(),
+            chart: theme.into(),
+        }
+    }
+
+    /// Hints for a request of `tenant`, about stats of `service` in `tz`
+    pub async fn load(
+        state: &AppState,
+        tenant: &ApiTenant,
+        service: &Service,
+        i18n: &I18n,
+        tz: Tz,
+    ) -> Result<Self> {
+        let preferences = match tenant.user_id {
+            Some(user_id) => db::get_user_settings(&state.pool, user_id).await?,
+            None => UserSettings::default(),
+        };
+        let organization = state
+            .cache
+            .get_or_insert_organization(tenant.organization_id, || async {
+                db::get_organization(&state.pool, tenant.organization_id)
+                    .await
+                    .ok()
+            })
+            .await;
+        let theme = Theme::new(&state.settings, organization.as_ref());
+        Ok(Self::new(i18n, &preferences, service.week_start, tz, theme))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Locale;
+
+    fn theme(mode: ThemeMode) -> Theme {
+        Theme {
+            logo_url: String::new(),
+            accent: "#5cc265".to_string(),
+            accent_hover: "#4aa854".to_string(),
+            strong: "#2c4931".to_string(),
+            strong_hover: "#3d5f42".to_string(),
+            accent_rgb: "92, 194, 101".to_string(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn test_language_customs() {
+        let hints = Presentation::new(
+            &I18n::new(Locale::De),
+            &UserSettings::default(),
+            None,
+            chrono_tz::Europe::Berlin,
+            theme(ThemeMode::Light),
+        );
+        assert_eq!(hints.locale, "de");
+        assert_eq!(hints.decimal_separator, ",");
+        assert_eq!(hints.group_separator, ".");
+        assert_eq!(hints.week_start, WeekStart::Monday);
+        assert_eq!(hints.hour_cycle, HourCycle::H23);
+        assert_eq!(hints.time_zone, "Europe/Berlin");
+        assert_eq!(hints.chart.colors, ["#2c4931", "#5cc265"]);
+        assert_eq!(hints.chart.text_color, "#666");
+    }
+
+    #[test]
+    fn test_service_week_start() {
+        let hints = Presentation::new(
+            &I18n::new(Locale::En),
+            &UserSettings::default(),
+            Some(WeekStart::Monday),
+            chrono_tz::UTC,
+            theme(ThemeMode::Light),
+        );
+        assert_eq!(hints.week_start, WeekStart::Monday);
+        assert_eq!(hints.hour_cycle, HourCycle::H12);
+    }
+
+    #[test]
+    fn test_preferences_win() {
+        let preferences = UserSettings {
+            week_start: Some(WeekStart::Monday),
+            hour_cycle: Some(HourCycle::H23),
+            ..Default::default()
+        };
+        let hints = Presentation::new(
+            &I18n::new(Locale::En),
+            &preferences,
+            Some(WeekStart::Sunday),
+            chrono_tz::UTC,
+            theme(ThemeMode::Dark),
+        );
+        assert_eq!(hints.decimal_separator, ".");
+        assert_eq!(hints.week_start, WeekStart::Monday);
+        assert_eq!(hints.hour_cycle, HourCycle::H23);
+        assert_eq!(hints.chart.mode, ThemeMode::Dark);
+        assert_eq!(hints.chart.grid_color, "#3a403c");
+
+        let json = serde_json::to_value(&hints).unwrap();
+        assert_eq!(json["week_start"], "monday");
+        assert_eq!(json["hour_cycle"], "h23");
+        assert_eq!(json["chart"]["mode"], "dark");
+    }
+}
@@ -0,0 +1,519 @@
+pub mod oidc;
+pub mod proxy;
+pub mod throttle;
+mod tokens;
+pub mod totp;
+
+pub use tokens::{SigningKey, TokenPurpose};
+
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+use std::net::SocketAddr;
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use chrono::Duration;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::api::ApiResponse;
+use crate::db;
+use crate::domain::{
+    CreateOrganization, Organization, OrganizationId, Permission, Role, User, UserId,
+};
+use crate::error::{Error, Result};
+use crate::privacy::get_client_ip;
+use crate::state::AppState;
+
+/// Cookie holding a dashboard login
+pub const SESSION_COOKIE: &str = "shymini_session";
+/// Cookie holding the organization picked in the switcher
+pub const ORG_COOKIE: &str = "shymini_org";
+/// Prefix of API tokens, so leaked tokens are easy to recognize
+pub const API_TOKEN_PREFIX: &str = "shy_";
+/// How long a dashboard login lasts
+pub const LOGIN_SESSION_DAYS: i64 = 30;
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::Internal(format!("password hashing failed: {}", e)))
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// A random secret for a cookie or API token
+pub fn generate_token(prefix: &str) -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", prefix, hex::encode(bytes))
+}
+
+/// Secrets are only stored hashed. They carry 256 bits of entropy, so a fast
+/// hash is enough.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Address a dashboard request comes from: the one a reverse proxy forwarded,
+/// else the connection's
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+    get_client_ip(headers).or_else(|| peer.map(|addr| addr.ip().to_string()))
+}
+
+/// Value of a request cookie
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// `Set-Cookie` value for a dashboard cookie
+pub fn set_cookie(name: &str, value: &str, max_age: Duration) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        name,
+        value,
+        max_age.num_seconds()
+    )
+}
+
+/// `Set-Cookie` value removing a dashboard cookie
+pub fn clear_cookie(name: &str) -> String {
+    set_cookie(name, "", Duration::zero())
+}
+
+/// Start a dashboard login for the user, returning the `Set-Cookie` value
+pub async fn start_login(state: &AppState, user_id: UserId) -> Result<String> {
+    let token = generate_token("");
+    let max_age = Duration::days(LOGIN_SESSION_DAYS);
+    db::create_login_session(
+        &state.pool,
+        &hash_token(&token),
+        user_id,
+        state.clock.now() + max_age,
+    )
+    .await?;
+    Ok(set_cookie(SESSION_COOKIE, &token, max_age))
+}
+
+/// End the request's dashboard login, if any
+pub async fn end_login(state: &AppState, headers: &HeaderMap) -> Result<()> {
+    if let Some(token) = cookie(headers, SESSION_COOKIE) {
+        db::delete_login_session(&state.pool, &hash_token(token)).await?;
+    }
+    Ok(())
+}
+
+/// The user logged in with the request's session cookie
+pub async fn current_user(state: &AppState, headers: &HeaderMap) -> Option<User> {
+    let token = cookie(headers, SESSION_COOKIE)?;
+    db::get_login_session_user(&state.pool, &hash_token(token), state.clock.now())
+        .await
+        .ok()
+}
+
+/// Find the user an outside login (single sign-on or an authenticating proxy)
+/// vouches for, creating an account without a password if there is none, and
+/// add them to the organizations named in `groups`: a slug, or `slug:role` for
+/// more than viewing. Memberships are only ever added here, never changed. A
+/// user left without any organization owns the default one if they are the
+/// first user, or gets one of their own.
+pub async fn provision_user(
+    state: &AppState,
+    email: &str,
+    name: &str,
+    groups: &[String],
+) -> Result<User> {
+    let (user, first_user) = match db::get_user_by_email(&state.pool, email).await {
+        Ok(user) => {
+            // The outside login vouches for the address
+            if user.email_verified_at.is_none() {
+                db::verify_user_email(&state.pool, user.id).await?;
+            }
+            (user, false)
+        }
+        Err(Error::UserNotFound) => {
+            let first_user = db::count_users(&state.pool).await? == 0;
+            // An empty hash never matches, so the account has no password
+            // until one is set through a password reset
+            let user = db::create_user(&state.pool, email, name, "", true).await?;
+            (user, first_user)
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut organizations = db::list_user_organizations(&state.pool, user.id).await?;
+    for group in groups {
+        let (slug, role) = match group.split_once(':') {
+            Some((slug, role)) => (slug, Role::from_str(role).unwrap_or_default()),
+            None => (group.as_str(), Role::Viewer),
+        };
+        if organizations.iter().any(|o| o.slug == slug) {
+            continue;
+        }
+        match db::get_organization_by_slug(&state.pool, slug).await {
+            Ok(organization) => {
+                db::add_membership(&state.pool, organization.id, user.id, role).await?;
+                organizations.push(organization);
+            }
+            Err(Error::OrganizationNotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if organizations.is_empty() {
+        let organization_id = if first_user {
+            OrganizationId::DEFAULT
+        } else {
+            let input = CreateOrganization {
+                name: email.to_string(),
+                ..Default::default()
+            };
+            db::create_organization(&state.pool, input).await?.id
+        };
+        db::add_membership(&state.pool, organization_id, user.id, Role::Owner).await?;
+    }
+    Ok(user)
+}
+
+/// The user an authenticating proxy put in the request's headers. `Err` holds
+/// the rejection for a request from outside the proxy's trusted networks.
+async fn proxy_user(
+    state: &AppState,
+    parts: &Parts,
+) -> std::result::Result<Option<User>, Response> {
+    let Some(proxy) = &state.proxy_auth else {
+        return Ok(None);
+    };
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if !proxy.trusts(peer) {
+        return Err((StatusCode::FORBIDDEN, "Forbidden").into_response());
+    }
+    let Some(identity) = proxy.identity(&parts.headers) else {
+        return Ok(None);
+    };
+    match provision_user(state, &identity.email, &identity.name, &identity.groups).await {
+        Ok(user) => Ok(Some(user)),
+        Err(e) => {
+            error!("Error provisioning proxy user: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response())
+        }
+    }
+}
+
+/// Send the browser to the login page; HTMX requests get a 401 telling
+/// HTMX to redirect the whole page instead of swapping in the login form
+fn login_required(headers: &HeaderMap) -> Response {
+    if headers.contains_key("HX-Request") {
+        (StatusCode::UNAUTHORIZED, [("HX-Redirect", "/login")]).into_response()
+    } else {
+        Redirect::to("/login").into_response()
+    }
+}
+
+/// Who is using the dashboard and which organization they are looking at.
+///
+/// With `multi_tenant` off there is no login: every organization is open and
+/// the default one is shown unless another was picked in the switcher. With it
+/// on, a login (or a user named by a trusted proxy) is required and only the
+/// user's own organizations are visible, with what they may do there set by
+/// their role.
+pub struct Tenant {
+    pub user: Option<User>,
+    pub organization: Organization,
+    /// Organizations the switcher offers
+    pub organizations: Vec<Organization>,
+    /// The user's role in `organization`; `Owner` without logins
+    pub role: Role,
+}
+
+impl Tenant {
+    /// Refuse a request the role does not allow
+    pub fn authorize(&self, permission: Permission) -> Result<()> {
+        if self.role.allows(permission) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// Refuse a request that isn't from an owner of the default
+    /// organization, who look after the whole install
+    pub fn authorize_admin(&self) -> Result<()> {
+        if self.organization.id == OrganizationId::DEFAULT {
+            self.authorize(Permission::ManageOrganization)
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let user = if state.settings.multi_tenant {
+            // A user named by the proxy wins over a login cookie
+            let user = match proxy_user(state, parts).await? {
+                Some(user) => Some(user),
+                None => current_user(state, &parts.headers).await,
+            };
+            match user {
+                Some(user) => Some(user),
+                None => return Err(login_required(&parts.headers)),
+            }
+        } else {
+            None
+        };
+
+        let organizations = match &user {
+            Some(user) => db::list_user_organizations(&state.pool, user.id).await,
+            None => db::list_organizations(&state.pool).await,
+        };
+        let organizations = match organizations {
+            Ok(o) => o,
+            Err(e) => {
+                error!("Error listing organizations: {}", e);
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response());
+            }
+        };
+
+        let selected = cookie(&parts.headers, ORG_COOKIE)
+            .and_then(|id| id.parse::<OrganizationId>().ok())
+            .and_then(|id| organizations.iter().find(|o| o.id == id));
+        let Some(organization) = selected.or_else(|| organizations.first()).cloned() else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "You are not a member of any organization",
+            )
+                .into_response());
+        };
+
+        let role = match &user {
+            Some(user) => {
+                match db::get_membership_role(&state.pool, organization.id, user.id).await {
+                    Ok(role) => role,
+                    Err(e) => {
+                        error!("Error fetching membership role: {}", e);
+                        return Err(
+                            (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+                        );
+                    }
+                }
+            }
+            None => Role::Owner,
+        };
+
+        Ok(Self {
+            user,
+            organization,
+            organizations,
+            role,
+        })
+    }
+}
+
+/// The organization an API request acts for: the one owning the bearer
+/// token, or the default organization for token-less requests when
+/// `multi_tenant` is off
+pub struct ApiTenant {
+    pub organization_id: OrganizationId,
+    /// The token's role; `Owner` for token-less requests
+    pub role: Role,
+    /// Who created the token, if it was made by a logged-in user
+    pub user_id: Option<UserId>,
+    /// Whether the request came with a token
+    pub authenticated: bool,
+}
+
+impl ApiTenant {
+    /// Refuse a request the role does not allow
+    pub fn authorize(&self, permission: Permission) -> Result<()> {
+        if self.role.allows(permission) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// Refuse a request that isn't from an owner of the default
+    /// organization, as `Tenant::authorize_admin` does
+    pub fn authorize_admin(&self) -> Result<()> {
+        if self.organization_id == OrganizationId::DEFAULT {
+            self.authorize(Permission::ManageOrganization)
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// Refuse a request that doesn't bring an owner token of the default
+    /// organization; token-less requests, let through as owners while
+    /// `multi_tenant` is off, don't count
+    pub fn authorize_token_admin(&self) -> Result<()> {
+        if !self.authenticated {
+            return Err(Error::Unauthorized);
+        }
+        self.authorize_admin()
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ApiTenant {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        Self::from_token(state, bearer_token(&parts.headers)).await
+    }
+}
+
+/// The token of an `Authorization: Bearer` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+impl ApiTenant {
+    /// The tenant of an API token, or of a token-less request; for clients
+    /// that pass the token elsewhere than the `Authorization` header
+    pub async fn from_token(
+        state: &AppState,
+        token: Option<&str>,
+    ) -> std::result::Result<Self, Response> {
+        let unauthorized = || {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::<()>::error("Unauthorized")),
+            )
+                .into_response()
+        };
+
+        match token {
+            Some(token) => match db::use_api_token(&state.pool, &hash_token(token)).await {
+                Ok(api_token) => Ok(Self {
+                    organization_id: api_token.organization_id,
+                    role: api_token.role,
+                    user_id: api_token.user_id,
+                    authenticated: true,
+                }),
+                Err(Error::Unauthorized) => Err(unauthorized()),
+                Err(e) => {
+                    error!("Error checking API token: {}", e);
+                    Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ApiResponse::<()>::error("Internal error")),
+                    )
+                        .into_response())
+                }
+            },
+            None if state.settings.multi_tenant => Err(unauthorized()),
+            None => Ok(Self {
+                organization_id: OrganizationId::DEFAULT,
+                role: Role::Owner,
+                user_id: None,
+                authenticated: false,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_roundtrip() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("battery staple", &hash));
+        assert!(!verify_password("correct horse", "not-a-hash"));
+    }
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token(API_TOKEN_PREFIX);
+        assert!(token.starts_with("shy_"));
+        assert_eq!(token.len(), 4 + 64);
+        assert_ne!(token, generate_token(API_TOKEN_PREFIX));
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_eq!(hash_token(&token).len(), 64);
+    }
+
+    #[test]
+    fn test_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(cookie(&headers, SESSION_COOKIE), None);
+
+        headers.insert(
+            header::COOKIE,
+            "theme=dark; shymini_session=abc123; shymini_org=xyz"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(cookie(&headers, SESSION_COOKIE), Some("abc123"));
+        assert_eq!(cookie(&headers, ORG_COOKIE), Some("xyz"));
+        assert_eq!(cookie(&headers, "shymini"), None);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let peer: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, None), None);
+        assert_eq!(
+            client_ip(&headers, Some(peer)).as_deref(),
+            Some("192.0.2.1")
+        );
+
+        headers.insert("X-Forwarded-For", "203.0.113.9, 10.0.0.1".parse().unwrap());
+        assert_eq!(
+            client_ip(&headers, Some(peer)).as_deref(),
+            Some("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn test_set_cookie() {
+        assert_eq!(
+            set_cookie(ORG_COOKIE, "abc", Duration::days(1)),
+            "shymini_org=abc; Path=/; HttpOnly; SameSite=Lax; Max-Age=86400"
+        );
+        assert!(clear_cookie(SESSION_COOKIE).ends_with("Max-Age=0"));
+    }
+}
@@ -0,0 +1,437 @@
+//! Dashboard single sign-on through a generic OpenID Connect provider
+//! (Authelia, Keycloak, Google, ...) using the authorization code flow with
+//! PKCE.
+//!
+//! The ID token comes straight from the provider's token endpoint over TLS,
+//! which OpenID Connect Core (3.1.3.7) accepts in place of checking its
+//! signature; issuer, audience, expiry and nonce are still checked. The
+//! discovery document must be the configured issuer's own.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+use crate::config::Settings;
+use crate::error::{Error, Result};
+
+/// Path the provider sends the browser back to
+pub const CALLBACK_PATH: &str = "/auth/oidc/callback";
+/// Cookie holding the pending login's state, nonce and PKCE verifier
+pub const LOGIN_COOKIE: &str = "shymini_oidc";
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Clone, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: String,
+}
+
+/// The `aud` claim is a string or a list of strings
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    profile: Claims,
+}
+
+/// What a login tells us about the user
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Claims {
+    #[serde(default)]
+    pub sub: String,
+    pub email: Option<String>,
+    /// Providers that omit it are trusted to only hand out verified addresses
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+    /// Everything else, for the configurable groups claim
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Claims {
+    /// Display name for a new user
+    pub fn display_name(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.preferred_username.clone())
+            .unwrap_or_default()
+    }
+
+    /// Organization slugs listed in `claim`, which may hold a list or a
+    /// single string
+    pub fn groups(&self, claim: &str) -> Vec<String> {
+        match self.extra.get(claim) {
+            Some(serde_json::Value::Array(values)) => values
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(str::to_string)
+                .collect(),
+            Some(serde_json::Value::String(value)) => vec![value.clone()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A login started by `authorize_url`, remembered in a cookie until the
+/// provider redirects back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingLogin {
+    pub state: String,
+    pub nonce: String,
+    pub verifier: String,
+}
+
+impl PendingLogin {
+    pub fn new() -> Self {
+        Self {
+            state: random_string(),
+            nonce: random_string(),
+            verifier: random_string(),
+        }
+    }
+
+    /// Subject of the signed login cookie
+    pub fn encode(&self) -> String {
+        format!("{}:{}:{}", self.state, self.nonce, self.verifier)
+    }
+
+    pub fn decode(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(3, ':');
+        Some(Self {
+            state: parts.next()?.to_string(),
+            nonce: parts.next()?.to_string(),
+            verifier: parts.next()?.to_string(),
+        })
+    }
+}
+
+impl Default for PendingLogin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 32 random bytes, base64url encoded (43 characters, a valid PKCE verifier)
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// PKCE `S256` code challenge for a verifier
+pub fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// A configured OpenID Connect provider
+pub struct OidcClient {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    scopes: String,
+    redirect_uri: String,
+    pub groups_claim: Option<String>,
+    http: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+}
+
+impl OidcClient {
+    /// The provider from the settings, if one is configured
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        let issuer = settings.oidc_issuer.clone().filter(|s| !s.is_empty())?;
+        let client_id = settings.oidc_client_id.clone().filter(|s| !s.is_empty())?;
+        Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: settings
+                .oidc_client_secret
+                .clone()
+                .filter(|s| !s.is_empty()),
+            scopes: settings.oidc_scopes.clone(),
+            redirect_uri: settings.public_link(CALLBACK_PATH),
+            groups_claim: settings.oidc_groups_claim.clone().filter(|s| !s.is_empty()),
+            http: reqwest::Client::new(),
+            discovery: OnceCell::new(),
+        })
+    }
+
+    /// The provider's discovery document, fetched once
+    async fn discovery(&self) -> Result<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                let discovery: Discovery = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| Error::Oidc(format!("discovery failed: {}", e)))?
+                    .json()
+                    .await
+                    .map_err(|e| Error::Oidc(format!("invalid discovery document: {}", e)))?;
+                self.check_discovery(&discovery)?;
+                Ok(discovery)
+            })
+            .await
+    }
+
+    /// A discovery document is only trusted for the issuer it was fetched
+    /// from (OpenID Connect Discovery 4.3), or it could send tokens elsewhere
+    fn check_discovery(&self, discovery: &Discovery) -> Result<()> {
+        if discovery.issuer.trim_end_matches('/') != self.issuer {
+            return Err(Error::Oidc(format!(
+                "discovery document is for another issuer: {}",
+                discovery.issuer
+            )));
+        }
+        Ok(())
+    }
+
+    /// Where to send the browser to log in
+    pub async fn authorize_url(&self, login: &PendingLogin) -> Result<String> {
+        let discovery = self.discovery().await?;
+        let mut url = url::Url::parse(&discovery.authorization_endpoint)
+            .map_err(|e| Error::Oidc(format!("invalid authorization endpoint: {}", e)))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", &login.state)
+            .append_pair("nonce", &login.nonce)
+            .append_pair("code_challenge", &code_challenge(&login.verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.to_string())
+    }
+
+    /// Trade the callback's code for the user's claims
+    pub async fn exchange(&self, code: &str, login: &PendingLogin) -> Result<Claims> {
+        let discovery = self.discovery().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let tokens: TokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| Error::Oidc(format!("token request failed: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| Error::Oidc(format!("invalid token response: {}", e)))?;
+
+        let mut claims =
+            self.check_id_token(discovery, &tokens.id_token, &login.nonce, Utc::now())?;
+
+        // Some providers only put the profile in the userinfo response
+        if claims.email.is_none() {
+            if let Some(endpoint) = &discovery.userinfo_endpoint {
+                let userinfo: Claims = self
+                    .http
+                    .get(endpoint)
+                    .bearer_auth(&tokens.access_token)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| Error::Oidc(format!("userinfo request failed: {}", e)))?
+                    .json()
+                    .await
+                    .map_err(|e| Error::Oidc(format!("invalid userinfo response: {}", e)))?;
+                if userinfo.sub != claims.sub {
+                    return Err(Error::Oidc("userinfo is for another subject".to_string()));
+                }
+                claims = userinfo;
+            }
+        }
+        Ok(claims)
+    }
+
+    fn check_id_token(
+        &self,
+        discovery: &Discovery,
+        id_token: &str,
+        nonce: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Claims> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| Error::Oidc("malformed ID token".to_string()))?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .map_err(|_| Error::Oidc("malformed ID token".to_string()))?;
+        let claims: IdTokenClaims = serde_json::from_slice(&payload)?;
+
+        if claims.iss.trim_end_matches('/') != discovery.issuer.trim_end_matches('/') {
+            return Err(Error::Oidc("ID token from another issuer".to_string()));
+        }
+        if !claims.aud.contains(&self.client_id) {
+            return Err(Error::Oidc("ID token for another client".to_string()));
+        }
+        if claims.exp <= now.timestamp() {
+            return Err(Error::Oidc("ID token expired".to_string()));
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(Error::Oidc("ID token nonce mismatch".to_string()));
+        }
+        Ok(claims.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> OidcClient {
+        OidcClient {
+            issuer: "https://id.example.com".to_string(),
+            client_id: "shymini".to_string(),
+            client_secret: None,
+            scopes: "openid email".to_string(),
+            redirect_uri: "https://stats.example.com/auth/oidc/callback".to_string(),
+            groups_claim: Some("groups".to_string()),
+            http: reqwest::Client::new(),
+            discovery: OnceCell::new(),
+        }
+    }
+
+    fn discovery() -> Discovery {
+        Discovery {
+            issuer: "https://id.example.com".to_string(),
+            authorization_endpoint: "https://id.example.com/authorize".to_string(),
+            token_endpoint: "https://id.example.com/token".to_string(),
+            userinfo_endpoint: None,
+        }
+    }
+
+    fn id_token(claims: serde_json::Value) -> String {
+        format!(
+            "eyJhbGciOiJSUzI1NiJ9.{}.signature",
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_pending_login_roundtrip() {
+        let login = PendingLogin::new();
+        assert_eq!(login.verifier.len(), 43);
+        assert_ne!(login.state, login.nonce);
+        assert_eq!(PendingLogin::decode(&login.encode()), Some(login));
+        assert_eq!(PendingLogin::decode("only:two"), None);
+    }
+
+    #[test]
+    fn test_check_id_token() {
+        let now = Utc::now();
+        let valid = serde_json::json!({
+            "iss": "https://id.example.com",
+            "aud": ["shymini", "other"],
+            "exp": now.timestamp() + 60,
+            "nonce": "n0nce",
+            "sub": "42",
+            "email": "ada@example.com",
+            "email_verified": true,
+            "groups": ["acme", "default"],
+        });
+        let claims = client()
+            .check_id_token(&discovery(), &id_token(valid.clone()), "n0nce", now)
+            .unwrap();
+        assert_eq!(claims.sub, "42");
+        assert_eq!(claims.email.as_deref(), Some("ada@example.com"));
+        assert_eq!(claims.groups("groups"), vec!["acme", "default"]);
+        assert!(claims.groups("roles").is_empty());
+
+        for (key, value) in [
+            ("iss", serde_json::json!("https://evil.example.com")),
+            ("aud", serde_json::json!("other")),
+            ("exp", serde_json::json!(now.timestamp() - 1)),
+            ("nonce", serde_json::json!("replayed")),
+        ] {
+            let mut claims = valid.clone();
+            claims[key] = value;
+            assert!(client()
+                .check_id_token(&discovery(), &id_token(claims), "n0nce", now)
+                .is_err());
+        }
+        assert!(client()
+            .check_id_token(&discovery(), "not-a-jwt", "n0nce", now)
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_discovery() {
+        assert!(client().check_discovery(&discovery()).is_ok());
+        let trailing_slash = Discovery {
+            issuer: "https://id.example.com/".to_string(),
+            ..discovery()
+        };
+        assert!(client().check_discovery(&trailing_slash).is_ok());
+        let other = Discovery {
+            issuer: "https://evil.example.com".to_string(),
+            ..discovery()
+        };
+        assert!(client().check_discovery(&other).is_err());
+    }
+
+    #[test]
+    fn test_display_name() {
+        let claims = Claims {
+            preferred_username: Some("ada".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(claims.display_name(), "ada");
+        assert_eq!(Claims::default().display_name(), "");
+    }
+}
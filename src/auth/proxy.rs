@@ -0,0 +1,151 @@
+//! Dashboard logins handled by an authenticating reverse proxy
+//! (oauth2-proxy, Authelia, Authentik, ...), which passes the user along in
+//! request headers.
+
+use std::net::IpAddr;
+
+use axum::http::HeaderMap;
+use ipnetwork::IpNetwork;
+
+use crate::config::Settings;
+use crate::privacy::parse_ignored_networks;
+
+/// Who the proxy says is making the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyIdentity {
+    pub email: String,
+    pub name: String,
+    /// Slugs of the organizations the user belongs to
+    pub groups: Vec<String>,
+}
+
+/// The configured proxy headers and the networks they are accepted from
+pub struct ProxyAuth {
+    user_header: String,
+    email_header: String,
+    name_header: String,
+    groups_header: String,
+    trusted_networks: Vec<IpNetwork>,
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+impl ProxyAuth {
+    /// The proxy from the settings, if one is configured
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        let user_header = settings
+            .proxy_user_header
+            .clone()
+            .filter(|s| !s.is_empty())?;
+        Some(Self {
+            user_header,
+            email_header: settings.proxy_email_header.clone(),
+            name_header: settings.proxy_name_header.clone(),
+            groups_header: settings.proxy_groups_header.clone(),
+            trusted_networks: parse_ignored_networks(&settings.proxy_trusted_networks),
+        })
+    }
+
+    /// Whether a connection from `peer` may use the dashboard. With trusted
+    /// networks configured, everything else is refused, so the headers cannot
+    /// be forged by going around the proxy.
+    pub fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        if self.trusted_networks.is_empty() {
+            return true;
+        }
+        peer.is_some_and(|ip| self.trusted_networks.iter().any(|n| n.contains(ip)))
+    }
+
+    /// The user named in the request's headers. The user header doubles as
+    /// the email address when the proxy sends no separate one.
+    pub fn identity(&self, headers: &HeaderMap) -> Option<ProxyIdentity> {
+        let user = header(headers, &self.user_header)?;
+        let email = header(headers, &self.email_header)
+            .unwrap_or(user)
+            .to_lowercase();
+        if !email.contains('@') {
+            return None;
+        }
+        let name = header(headers, &self.name_header).unwrap_or(user);
+        let groups = header(headers, &self.groups_header)
+            .map(|groups| {
+                groups
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|g| !g.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(ProxyIdentity {
+            email,
+            name: name.to_string(),
+            groups,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(trusted_networks: &str) -> ProxyAuth {
+        ProxyAuth {
+            user_header: "Remote-User".to_string(),
+            email_header: "Remote-Email".to_string(),
+            name_header: "Remote-Name".to_string(),
+            groups_header: "Remote-Groups".to_string(),
+            trusted_networks: parse_ignored_networks(trusted_networks),
+        }
+    }
+
+    #[test]
+    fn test_trusts() {
+        assert!(proxy("").trusts(None));
+
+        let proxy = proxy("10.0.0.0/8, ::1/128");
+        assert!(proxy.trusts(Some("10.1.2.3".parse().unwrap())));
+        assert!(proxy.trusts(Some("::1".parse().unwrap())));
+        assert!(!proxy.trusts(Some("192.168.1.1".parse().unwrap())));
+        assert!(!proxy.trusts(None));
+    }
+
+    #[test]
+    fn test_identity() {
+        let proxy = proxy("");
+        let mut headers = HeaderMap::new();
+        assert_eq!(proxy.identity(&headers), None);
+
+        headers.insert("Remote-User", "ada".parse().unwrap());
+        // No email address to match the user by
+        assert_eq!(proxy.identity(&headers), None);
+
+        headers.insert("Remote-Email", "Ada@Example.com".parse().unwrap());
+        headers.insert("Remote-Groups", "acme, default,".parse().unwrap());
+        assert_eq!(
+            proxy.identity(&headers),
+            Some(ProxyIdentity {
+                email: "ada@example.com".to_string(),
+                name: "ada".to_string(),
+                groups: vec!["acme".to_string(), "default".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_identity_user_is_email() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Remote-User", "ada@example.com".parse().unwrap());
+        headers.insert("Remote-Name", "Ada Lovelace".parse().unwrap());
+        let identity = proxy("").identity(&headers).unwrap();
+        assert_eq!(identity.email, "ada@example.com");
+        assert_eq!(identity.name, "Ada Lovelace");
+        assert!(identity.groups.is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Slowing down password guessing. Failed dashboard logins are counted per
+//! account and per IP address since the last successful one; past a limit,
+//! logins are refused for a lockout that doubles with every further failure.
+//! Every attempt goes to the login audit log.
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::warn;
+
+use crate::config::Settings;
+use crate::db;
+use crate::domain::{LoginFailures, LoginOutcome};
+use crate::error::Result;
+use crate::state::AppState;
+
+/// Failures older than this are forgotten, and no lockout lasts longer
+const FORGET_AFTER_HOURS: i64 = 24;
+
+pub struct LoginThrottle {
+    max_failures: i64,
+    max_failures_per_ip: i64,
+    lockout: Duration,
+}
+
+impl LoginThrottle {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            max_failures: i64::from(settings.login_max_failures),
+            max_failures_per_ip: i64::from(settings.login_max_failures_per_ip),
+            lockout: Duration::seconds(settings.login_lockout_secs as i64),
+        }
+    }
+
+    /// Start of the period failures are counted over
+    pub fn window_start(now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::hours(FORGET_AFTER_HOURS)
+    }
+
+    /// End of the lockout `failures` earn against a limit of `max`; a limit
+    /// of 0 turns the lockout off
+    fn lockout_end(&self, failures: LoginFailures, max: i64) -> Option<DateTime<Utc>> {
+        if max == 0 || failures.count < max {
+            return None;
+        }
+        let doublings = (failures.count - max).min(20) as i32;
+        let lockout = (self.lockout * (1 << doublings)).min(Duration::hours(FORGET_AFTER_HOURS));
+        failures.last_at.map(|last_at| last_at + lockout)
+    }
+
+    /// When logins are allowed again, if the account's or the address's
+    /// failures lock them at `now`
+    pub fn locked_until(
+        &self,
+        account: LoginFailures,
+        ip: LoginFailures,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        [
+            self.lockout_end(account, self.max_failures),
+            self.lockout_end(ip, self.max_failures_per_ip),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .filter(|until| *until > now)
+    }
+}
+
+/// When logins to `email` from `ip` are allowed again, if they are locked now
+pub async fn locked_until(
+    state: &AppState,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<Option<DateTime<Utc>>> {
+    let now = state.clock.now();
+    let since = LoginThrottle::window_start(now);
+    let account = db::get_login_failures_for_email(&state.pool, email, since).await?;
+    let ip = match ip {
+        Some(ip) => db::get_login_failures_for_ip(&state.pool, ip, since).await?,
+        None => LoginFailures::default(),
+    };
+    Ok(LoginThrottle::from_settings(&state.settings).locked_until(account, ip, now))
+}
+
+/// Add an attempt to the audit log, and failures to the application log too
+pub async fn record(
+    state: &AppState,
+    email: &str,
+    ip: Option<&str>,
+    outcome: LoginOutcome,
+) -> Result<()> {
+    if outcome != LoginOutcome::Success {
+        warn!(
+            "Refused dashboard login for {} from {}: {}",
+            email,
+            ip.unwrap_or("unknown address"),
+            outcome.as_str()
+        );
+    }
+    db::record_login_attempt(&state.pool, email, ip, outcome, state.clock.now()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            max_failures: 5,
+            max_failures_per_ip: 20,
+            lockout: Duration::seconds(60),
+        }
+    }
+
+    fn failures(count: i64, last_at: DateTime<Utc>) -> LoginFailures {
+        LoginFailures {
+            count,
+            last_at: Some(last_at),
+        }
+    }
+
+    #[test]
+    fn test_lockout_doubles() {
+        let throttle = throttle();
+        let now = Utc::now();
+        let none = LoginFailures::default();
+
+        assert_eq!(throttle.locked_until(failures(4, now), none, now), None);
+        assert_eq!(
+            throttle.locked_until(failures(5, now), none, now),
+            Some(now + Duration::seconds(60))
+        );
+        assert_eq!(
+            throttle.locked_until(failures(7, now), none, now),
+            Some(now + Duration::seconds(240))
+        );
+        // Capped at a day
+        assert_eq!(
+            throttle.locked_until(failures(500, now), none, now),
+            Some(now + Duration::hours(24))
+        );
+    }
+
+    #[test]
+    fn test_lockout_expires() {
+        let throttle = throttle();
+        let now = Utc::now();
+        let earlier = now - Duration::seconds(61);
+        let none = LoginFailures::default();
+        assert_eq!(throttle.locked_until(failures(5, earlier), none, now), None);
+        assert!(throttle
+            .locked_until(failures(6, earlier), none, now)
+            .is_some());
+    }
+
+    #[test]
+    fn test_ip_limit() {
+        let throttle = throttle();
+        let now = Utc::now();
+        let none = LoginFailures::default();
+        assert_eq!(throttle.locked_until(none, failures(19, now), now), None);
+        assert_eq!(
+            throttle.locked_until(none, failures(20, now), now),
+            Some(now + Duration::seconds(60))
+        );
+        // The longer of the two lockouts wins
+        assert_eq!(
+            throttle.locked_until(failures(6, now), failures(20, now), now),
+            Some(now + Duration::seconds(120))
+        );
+    }
+
+    #[test]
+    fn test_disabled() {
+        let throttle = LoginThrottle {
+            max_failures: 0,
+            max_failures_per_ip: 0,
+            lockout: Duration::seconds(60),
+        };
+        let now = Utc::now();
+        assert_eq!(
+            throttle.locked_until(failures(100, now), failures(100, now), now),
+            None
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use tracing::warn;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes of the HMAC kept in a pixel identifier's signature
+const PIXEL_SIGNATURE_BYTES: usize = 16;
+/// Bytes of the MAC kept in a hashed user key
+const USER_KEY_BYTES: usize = 16;
+
+/// What an emailed link is for. Part of the signature, so a token minted for
+/// one purpose is useless for another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    /// Join an organization; subject is `{organization_id}:{role}:{email}`
+    Invite,
+    /// Confirm an address; subject is `{user_id}:{email}`
+    VerifyEmail,
+    /// Choose a new password; subject is `{user_id}:{password fingerprint}`
+    PasswordReset,
+    /// Finish a single sign-on login; subject is the encoded `PendingLogin`
+    OidcLogin,
+    /// Finish a password login with a two-factor code; subject is
+    /// `{user_id}:{password fingerprint}`
+    TwoFactor,
+    /// Read a service's milestone feed; subject is the service ID
+    MilestoneFeed,
+    /// Show a service's embeddable widgets; subject is the service ID
+    Embed,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Invite => "invite",
+            TokenPurpose::VerifyEmail => "verify-email",
+            TokenPurpose::PasswordReset => "password-reset",
+            TokenPurpose::OidcLogin => "oidc-login",
+            TokenPurpose::TwoFactor => "two-factor",
+            TokenPurpose::MilestoneFeed => "milestone-feed",
+            TokenPurpose::Embed => "embed",
+        }
+    }
+
+    /// How long a link stays valid
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            TokenPurpose::Invite => Duration::days(7),
+            TokenPurpose::VerifyEmail => Duration::days(2),
+            TokenPurpose::PasswordReset => Duration::hours(1),
+            TokenPurpose::OidcLogin => Duration::minutes(10),
+            TokenPurpose::TwoFactor => Duration::minutes(5),
+            // Feed readers keep polling the URL they were given
+            TokenPurpose::MilestoneFeed => Duration::days(3650),
+            // As do the pages widgets are embedded in
+            TokenPurpose::Embed => Duration::days(3650),
+        }
+    }
+}
+
+/// Signs and checks the stateless tokens in emailed links. A token is
+/// `hex(payload).hex(hmac)`, with the purpose, expiry and subject in the
+/// payload, so nothing needs to be stored until the link is used.
+pub struct SigningKey(Vec<u8>);
+
+impl SigningKey {
+    /// Use the configured secret, or a random one that only lives as long as
+    /// the process
+    pub fn new(secret: Option<&str>) -> Self {
+        match secret.filter(|s| !s.is_empty()) {
+            Some(secret) => Self(secret.as_bytes().to_vec()),
+            None => {
+                warn!("No secret_key configured; emailed links stop working on restart");
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                Self(key)
+            }
+        }
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        mac
+    }
+
+    pub fn sign(&self, purpose: TokenPurpose, subject: &str, now: DateTime<Utc>) -> String {
+        let expires_at = now + purpose.lifetime();
+        let payload = format!(
+            "{}\n{}\n{}",
+            purpose.as_str(),
+            expires_at.timestamp(),
+            subject
+        );
+        let signature = self.mac(payload.as_bytes()).finalize().into_bytes();
+        format!("{}.{}", hex::encode(payload), hex::encode(signature))
+    }
+
+    /// The token's subject, if it was signed with this key for `purpose` and
+    /// has not expired
+    pub fn verify(&self, purpose: TokenPurpose, token: &str, now: DateTime<Utc>) -> Option<String> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = hex::decode(payload).ok()?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(&payload).verify_slice(&signature).ok()?;
+
+        let payload = String::from_utf8(payload).ok()?;
+        let mut parts = payload.splitn(3, '\n');
+        if parts.next()? != purpose.as_str() {
+            return None;
+        }
+        let expires_at = Utc.timestamp_opt(parts.next()?.parse().ok()?, 0).single()?;
+        if expires_at <= now {
+            return None;
+        }
+        parts.next().map(str::to_string)
+    }
+
+    fn pixel_id_mac(&self, tracking_id: &str, identifier: &str) -> HmacSha256 {
+        self.mac(format!("pixel-id\n{}\n{}", tracking_id, identifier).as_bytes())
+    }
+
+    /// Signature of an identifier in a service's pixel URL, its `sig`
+    /// parameter. Unlike tokens it never expires, as emails are opened long
+    /// after they are sent.
+    pub fn sign_pixel_id(&self, tracking_id: &str, identifier: &str) -> String {
+        let signature = self.pixel_id_mac(tracking_id, identifier).finalize();
+        hex::encode(&signature.into_bytes()[..PIXEL_SIGNATURE_BYTES])
+    }
+
+    /// Whether `signature` is this key's signature of the identifier
+    pub fn verify_pixel_id(&self, tracking_id: &str, identifier: &str, signature: &str) -> bool {
+        match hex::decode(signature) {
+            Ok(signature) if signature.len() == PIXEL_SIGNATURE_BYTES => self
+                .pixel_id_mac(tracking_id, identifier)
+                .verify_truncated_left(&signature)
+                .is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Key a service links a visitor's sessions under, from the login
+    /// identifier the site sent. It can't be turned back into the
+    /// identifier, and only stays the same across restarts with a
+    /// configured secret.
+    pub fn user_key(&self, tracking_id: &str, identifier: &str) -> String {
+        let mac = self.mac(format!("user-key\n{}\n{}", tracking_id, identifier).as_bytes());
+        hex::encode(&mac.finalize().into_bytes()[..USER_KEY_BYTES])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::new(Some("secret"));
+        let now = Utc::now();
+        let token = key.sign(TokenPurpose::Invite, "org:a@example.com", now);
+
+        assert_eq!(
+            key.verify(TokenPurpose::Invite, &token, now).as_deref(),
+            Some("org:a@example.com")
+        );
+        // Wrong purpose, wrong key, or tampered
+        assert_eq!(key.verify(TokenPurpose::PasswordReset, &token, now), None);
+        assert_eq!(
+            SigningKey::new(Some("other")).verify(TokenPurpose::Invite, &token, now),
+            None
+        );
+        let tampered = format!("{}0", token);
+        assert_eq!(key.verify(TokenPurpose::Invite, &tampered, now), None);
+        assert_eq!(key.verify(TokenPurpose::Invite, "garbage", now), None);
+    }
+
+    #[test]
+    fn test_tokens_expire() {
+        let key = SigningKey::new(Some("secret"));
+        let now = Utc::now();
+        let token = key.sign(TokenPurpose::PasswordReset, "user", now);
+
+        let later = now + Duration::minutes(59);
+        assert!(key
+            .verify(TokenPurpose::PasswordReset, &token, later)
+            .is_some());
+        let expired = now + Duration::minutes(61);
+        assert!(key
+            .verify(TokenPurpose::PasswordReset, &token, expired)
+            .is_none());
+    }
+
+    #[test]
+    fn test_pixel_id_signatures() {
+        let key = SigningKey::new(Some("secret"));
+        let signature = key.sign_pixel_id("abc123", "reader@example.com");
+        assert_eq!(signature.len(), PIXEL_SIGNATURE_BYTES * 2);
+        assert!(key.verify_pixel_id("abc123", "reader@example.com", &signature));
+
+        // Another identifier, service or key, or a tampered signature
+        assert!(!key.verify_pixel_id("abc123", "other@example.com", &signature));
+        assert!(!key.verify_pixel_id("xyz789", "reader@example.com", &signature));
+        assert!(!SigningKey::new(Some("other")).verify_pixel_id(
+            "abc123",
+            "reader@example.com",
+            &signature
+        ));
+        assert!(!key.verify_pixel_id("abc123", "reader@example.com", &signature[..30]));
+        assert!(!key.verify_pixel_id("abc123", "reader@example.com", "not hex"));
+    }
+
+    #[test]
+    fn test_user_keys() {
+        let key = SigningKey::new(Some("secret"));
+        let user_key = key.user_key("abc123", "user-42");
+        assert_eq!(user_key.len(), USER_KEY_BYTES * 2);
+        assert_eq!(user_key, key.user_key("abc123", "user-42"));
+        assert!(!user_key.contains("user-42"));
+
+        // Another identifier, service or key
+        assert_ne!(user_key, key.user_key("abc123", "user-43"));
+        assert_ne!(user_key, key.user_key("xyz789", "user-42"));
+        assert_ne!(
+            user_key,
+            SigningKey::new(Some("other")).user_key("abc123", "user-42")
+        );
+        // Differs from the identifier's pixel signature
+        assert_ne!(user_key, key.sign_pixel_id("abc123", "user-42"));
+    }
+
+    #[test]
+    fn test_random_keys_differ() {
+        let now = Utc::now();
+        let token = SigningKey::new(None).sign(TokenPurpose::VerifyEmail, "user", now);
+        assert!(SigningKey::new(None)
+            .verify(TokenPurpose::VerifyEmail, &token, now)
+            .is_none());
+    }
+}
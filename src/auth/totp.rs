@@ -0,0 +1,195 @@
+//! Time-based one-time passwords (RFC 6238) for two-factor logins, as shown by
+//! authenticator apps: HMAC-SHA1 over 30-second steps, 6 digits.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use url::Url;
+
+type HmacSha1 = Hmac<Sha1>;
+
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Steps accepted either side of the current one, for clocks that drift
+const SKEW_STEPS: i64 = 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret, base32-encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base32_encode(&bytes)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            encoded.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 31) as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode base32, ignoring case, spaces and padding
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bytes.push((buffer >> (bits - 8)) as u8);
+            bits -= 8;
+        }
+    }
+    Some(bytes)
+}
+
+/// The code for one time step
+fn code(key: &[u8], step: u64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    // Dynamic truncation (RFC 4226 5.3)
+    let offset = (digest[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// The code an authenticator app shows for the secret at `now`
+pub fn code_at(secret: &str, now: DateTime<Utc>) -> Option<String> {
+    let key = base32_decode(secret).filter(|k| !k.is_empty())?;
+    let step = u64::try_from(now.timestamp() / STEP_SECS).ok()?;
+    Some(format!(
+        "{:0width$}",
+        code(&key, step),
+        width = DIGITS as usize
+    ))
+}
+
+/// Whether `code_input` is the secret's code at `now`, give or take a step
+pub fn verify(secret: &str, code_input: &str, now: DateTime<Utc>) -> bool {
+    let code_input: String = code_input.chars().filter(|c| !c.is_whitespace()).collect();
+    if code_input.len() != DIGITS as usize || !code_input.chars().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+    let Ok(expected) = code_input.parse::<u32>() else {
+        return false;
+    };
+    let Some(key) = base32_decode(secret).filter(|k| !k.is_empty()) else {
+        return false;
+    };
+    let step = now.timestamp() / STEP_SECS;
+    (step - SKEW_STEPS..=step + SKEW_STEPS)
+        .filter_map(|s| u64::try_from(s).ok())
+        .any(|s| code(&key, s) == expected)
+}
+
+/// Whether a secret typed in or posted back decodes to a usable key
+pub fn is_valid_secret(secret: &str) -> bool {
+    base32_decode(secret).is_some_and(|key| key.len() >= 10)
+}
+
+/// `otpauth://` link that adds the secret to an authenticator app
+pub fn provisioning_uri(secret: &str, account: &str, issuer: &str) -> String {
+    let mut url = Url::parse("otpauth://totp/").expect("static URL is valid");
+    url.set_path(&format!("{}:{}", issuer, account));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECS.to_string());
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The SHA-1 secret from RFC 6238's test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_base32_roundtrip() {
+        let encoded = base32_encode(RFC_SECRET);
+        assert_eq!(encoded, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode(&encoded).unwrap(), RFC_SECRET);
+        assert_eq!(
+            base32_decode("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap(),
+            RFC_SECRET
+        );
+        assert_eq!(base32_decode("not base32!"), None);
+        assert_eq!(base32_encode(b"f"), "MY");
+    }
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        // The RFC's 8-digit codes, cut to 6 digits
+        assert_eq!(code(RFC_SECRET, 59 / 30), 287082);
+        assert_eq!(code(RFC_SECRET, 1111111109 / 30), 81804);
+        assert_eq!(code(RFC_SECRET, 1234567890 / 30), 5924);
+        assert_eq!(code(RFC_SECRET, 2000000000 / 30), 279037);
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret = base32_encode(RFC_SECRET);
+        let now = Utc.timestamp_opt(1111111109, 0).unwrap();
+        assert_eq!(code_at(&secret, now).as_deref(), Some("081804"));
+        assert!(verify(&secret, "081804", now));
+        assert!(verify(&secret, "081 804", now));
+        // One step of drift either way is fine, two are not
+        assert!(verify(
+            &secret,
+            "081804",
+            now + chrono::Duration::seconds(30)
+        ));
+        assert!(!verify(
+            &secret,
+            "081804",
+            now + chrono::Duration::seconds(90)
+        ));
+        assert!(!verify(&secret, "81804", now));
+        assert!(!verify(&secret, "000000", now));
+        assert!(!verify("", "081804", now));
+    }
+
+    #[test]
+    fn test_generate_secret() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert!(is_valid_secret(&secret));
+        assert_ne!(secret, generate_secret());
+        assert!(!is_valid_secret("ABC"));
+        assert!(!is_valid_secret("1111"));
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        assert_eq!(
+            provisioning_uri("GEZDGNBV", "ada@example.com", "shymini"),
+            "otpauth://totp/shymini:ada@example.com?secret=GEZDGNBV&issuer=shymini&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
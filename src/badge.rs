@@ -0,0 +1,173 @@
+//! Public SVG badges with a service's visitor counts, for READMEs and status
+//! pages. Only services with `public_badge` set have one; for every other
+//! tracking ID the badge is not found, the same as for unknown ones.
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{Datelike, Duration, TimeZone, Utc};
+use tracing::error;
+
+use crate::cache::BADGE_TTL;
+use crate::dashboard::intcomma;
+use crate::db;
+use crate::domain::BadgeCounts;
+use crate::error::Error;
+use crate::i18n::I18n;
+use crate::state::AppState;
+
+/// Space around each half's text
+const PADDING: u32 = 12;
+
+#[derive(Template)]
+#[template(path = "badge/visitors.svg")]
+pub struct BadgeTemplate {
+    pub label: String,
+    pub message: String,
+}
+
+impl BadgeTemplate {
+    pub fn new(i18n: &I18n, counts: BadgeCounts) -> Self {
+        Self {
+            label: i18n.t("badge-visitors").to_string(),
+            message: format!(
+                "{} · {}",
+                i18n.t1("badge-month", "count", intcomma(counts.visitors)),
+                i18n.t1("badge-online", "count", intcomma(counts.online))
+            ),
+        }
+    }
+
+    pub fn label_width(&self) -> u32 {
+        text_width(&self.label) + PADDING
+    }
+
+    pub fn message_width(&self) -> u32 {
+        text_width(&self.message) + PADDING
+    }
+
+    pub fn width(&self) -> u32 {
+        self.label_width() + self.message_width()
+    }
+}
+
+/// Rough width of `text` in 11px Verdana, as badges usually estimate it
+fn text_width(text: &str) -> u32 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | 'I' | '1' | '.' | ',' | ':' | ';' | '|' | '!' | '\'' | ' ' | '·' => {
+                4
+            }
+            'm' | 'w' | 'M' | 'W' => 10,
+            c if c.is_uppercase() => 8,
+            _ => 7,
+        })
+        .sum()
+}
+
+/// GET /badge/:tracking_id/visitors.svg
+pub async fn visitors_badge(
+    State(state): State<AppState>,
+    Path(tracking_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let service = match db::get_active_service_by_tracking_id(&state.pool, &tracking_id).await {
+        Ok(service) if service.public_badge => service,
+        Ok(_) | Err(Error::ServiceNotFound) => {
+            return (StatusCode::NOT_FOUND, "Badge not found").into_response();
+        }
+        Err(e) => {
+            error!("Error fetching service: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+        }
+    };
+
+    let now = state.clock.now();
+    let month_start = Utc
+        .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .unwrap_or(now);
+    let active_cutoff =
+        now - Duration::milliseconds(state.settings.active_user_timeout_ms(&service) as i64);
+    let counts = state
+        .cache
+        .get_or_insert_badge_counts(service.id, || async {
+            db::get_badge_counts(&state.pool, service.id, month_start, active_cutoff)
+                .await
+                .map_err(|e| error!("Error counting badge visitors: {}", e))
+                .ok()
+        })
+        .await;
+    let Some(counts) = counts else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+    };
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    match BadgeTemplate::new(&i18n, counts).render() {
+        Ok(svg) => (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    "image/svg+xml; charset=utf-8".to_string(),
+                ),
+                (
+                    header::CACHE_CONTROL,
+                    format!("public, max-age={}", BADGE_TTL.as_secs()),
+                ),
+            ],
+            svg,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Error rendering badge: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Locale;
+
+    #[test]
+    fn test_badge_svg() {
+        let i18n = I18n::new(Locale::En);
+        let badge = BadgeTemplate::new(
+            &i18n,
+            BadgeCounts {
+                visitors: 1234,
+                online: 5,
+            },
+        );
+        assert_eq!(badge.label, "visitors");
+        assert_eq!(badge.message, "1,234 this month · 5 online");
+        assert_eq!(badge.width(), badge.label_width() + badge.message_width());
+
+        let svg = badge.render().unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.contains(&format!("width=\"{}\"", badge.width())));
+        assert!(svg.contains(">1,234 this month · 5 online</text>"));
+    }
+
+    #[test]
+    fn test_badge_text_is_escaped() {
+        let badge = BadgeTemplate {
+            label: "<script>".to_string(),
+            message: "a & b".to_string(),
+        };
+        let svg = badge.render().unwrap();
+        assert!(!svg.contains("<script>"));
+        assert!(svg.contains("&lt;script&gt;"));
+        assert!(svg.contains("a &amp; b"));
+    }
+
+    #[test]
+    fn test_text_width_grows_with_text() {
+        assert!(text_width("1,234 this month") > text_width("12 this month"));
+        assert_eq!(text_width(""), 0);
+    }
+}
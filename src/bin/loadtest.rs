@@ -18,16 +18,24 @@
 //! # Seed and immediately benchmark
 //! cargo run --release --bin loadtest -- seed --bench
 //!
+//! # Reproduce a dataset: the same seed gives the same data, relative to the
+//! # time it is seeded
+//! cargo run --release --bin loadtest -- seed --seed 42 --profile spiky
+//!
 //! # Then start the server with this database:
 //! SHYMINI__DATABASE_PATH=./loadtest.db cargo run --release
+//!
+//! # Send real ingress traffic to the running server
+//! cargo run --release --bin loadtest -- fire --url http://localhost:8080 --rps 500
 //! ```
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Datelike, Duration, Utc, Weekday};
 use rand::prelude::*;
 use rand_distr::Exp;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::{Pool, Sqlite};
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -133,6 +141,26 @@ const USER_AGENTS: &[&str] = &[
     "Mozilla/5.0 (Linux; Android 13; SM-S918B) AppleWebKit/537.36 Mobile Safari/537.36",
 ];
 
+const BOT_USER_AGENTS: &[(&str, &str)] = &[
+    (
+        "Googlebot",
+        "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+    ),
+    (
+        "Bingbot",
+        "Mozilla/5.0 (compatible; bingbot/2.0; +http://www.bing.com/bingbot.htm)",
+    ),
+    (
+        "DuckDuckBot",
+        "DuckDuckBot/1.1; (+http://duckduckgo.com/duckduckbot.html)",
+    ),
+    (
+        "AhrefsBot",
+        "Mozilla/5.0 (compatible; AhrefsBot/7.0; +http://ahrefs.com/robot/)",
+    ),
+    ("curl", "curl/8.4.0"),
+];
+
 const COUNTRIES: &[&str] = &[
     "US", "US", "US", "US", "US", // US weighted higher
     "GB", "GB", "DE", "DE", "FR", "CA", "AU", "NL", "SE", "JP", "BR", "IN", "MX", "ES", "IT",
@@ -183,8 +211,7 @@ fn random_ip(rng: &mut impl Rng) -> String {
 }
 
 /// Generate a random datetime within the last N days, weighted toward recent
-fn random_recent_datetime(rng: &mut impl Rng, days_back: u32) -> DateTime<Utc> {
-    let now = Utc::now();
+fn random_recent_datetime(rng: &mut impl Rng, now: DateTime<Utc>, days_back: u32) -> DateTime<Utc> {
     let max_ms = (days_back as i64) * 24 * 60 * 60 * 1000;
     // Exponential distribution favoring recent dates
     let exp = Exp::new(3.0 / max_ms as f64).unwrap();
@@ -192,6 +219,79 @@ fn random_recent_datetime(rng: &mut impl Rng, days_back: u32) -> DateTime<Utc> {
     now - Duration::milliseconds(offset_ms)
 }
 
+/// Shape of the generated traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Profile {
+    /// Visits weighted toward recent days
+    Steady,
+    /// Most visits crowd into a few two-hour bursts
+    Spiky,
+    /// Saturdays and Sundays get three times the weekday traffic
+    WeekendHeavy,
+    /// Two in five sessions are crawlers
+    BotHeavy,
+}
+
+impl Profile {
+    const ALL: [Profile; 4] = [
+        Profile::Steady,
+        Profile::Spiky,
+        Profile::WeekendHeavy,
+        Profile::BotHeavy,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Profile::Steady => "steady",
+            Profile::Spiky => "spiky",
+            Profile::WeekendHeavy => "weekend-heavy",
+            Profile::BotHeavy => "bot-heavy",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == s)
+    }
+
+    /// Share of sessions that are crawlers
+    fn bot_share(self) -> f64 {
+        match self {
+            Profile::BotHeavy => 0.4,
+            _ => 0.0,
+        }
+    }
+
+    /// Start of a session; `spikes` are the burst centres of a spiky profile
+    fn session_time(
+        self,
+        rng: &mut impl Rng,
+        now: DateTime<Utc>,
+        days_back: u32,
+        spikes: &[DateTime<Utc>],
+    ) -> DateTime<Utc> {
+        match self {
+            Profile::Spiky if !spikes.is_empty() && rng.gen_bool(0.7) => {
+                let centre = spikes[rng.gen_range(0..spikes.len())];
+                let offset = Duration::minutes(rng.gen_range(-60..=60));
+                (centre + offset).min(now)
+            }
+            Profile::WeekendHeavy => loop {
+                let time = random_recent_datetime(rng, now, days_back);
+                let weekend = matches!(time.weekday(), Weekday::Sat | Weekday::Sun);
+                if weekend || rng.gen_bool(1.0 / 3.0) {
+                    break time;
+                }
+            },
+            _ => random_recent_datetime(rng, now, days_back),
+        }
+    }
+}
+
+/// A v4 UUID drawn from the seeded generator, so seeded runs repeat exactly
+fn random_uuid(rng: &mut impl Rng) -> Uuid {
+    uuid::Builder::from_random_bytes(rng.gen()).into_uuid()
+}
+
 struct ServiceData {
     id: Uuid,
     tracking_id: String,
@@ -199,10 +299,8 @@ struct ServiceData {
 }
 
 /// Generate a random 8-character alphanumeric tracking ID
-fn generate_tracking_id() -> String {
-    use rand::Rng;
+fn generate_tracking_id(rng: &mut impl Rng) -> String {
     const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
-    let mut rng = rand::thread_rng();
     (0..8)
         .map(|_| {
             let idx = rng.gen_range(0..CHARSET.len());
@@ -211,7 +309,7 @@ fn generate_tracking_id() -> String {
         .collect()
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 struct SessionData {
     id: Uuid,
     service_id: Uuid,
@@ -224,6 +322,61 @@ struct SessionData {
     start_time: DateTime<Utc>,
 }
 
+/// Sessions for one service, shaped by the profile
+fn generate_sessions(
+    rng: &mut impl Rng,
+    service_id: Uuid,
+    count: usize,
+    profile: Profile,
+    now: DateTime<Utc>,
+    days_back: u32,
+) -> Vec<SessionData> {
+    let spikes: Vec<DateTime<Utc>> = match profile {
+        Profile::Spiky => (0..3)
+            .map(|_| {
+                now - Duration::minutes(rng.gen_range(0..i64::from(days_back.max(1)) * 24 * 60))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    (0..count)
+        .map(|_| {
+            let ip = random_ip(rng);
+            let (user_agent, browser, os, device_type) = if rng.gen_bool(profile.bot_share()) {
+                let (name, user_agent) = BOT_USER_AGENTS[rng.gen_range(0..BOT_USER_AGENTS.len())];
+                (
+                    user_agent.to_string(),
+                    name.to_string(),
+                    "Other".to_string(),
+                    "Robot".to_string(),
+                )
+            } else {
+                (
+                    USER_AGENTS[rng.gen_range(0..USER_AGENTS.len())].to_string(),
+                    BROWSERS[rng.gen_range(0..BROWSERS.len())].to_string(),
+                    OPERATING_SYSTEMS[rng.gen_range(0..OPERATING_SYSTEMS.len())].to_string(),
+                    DEVICE_TYPES[rng.gen_range(0..DEVICE_TYPES.len())].to_string(),
+                )
+            };
+            let country = COUNTRIES[rng.gen_range(0..COUNTRIES.len())].to_string();
+            let start_time = profile.session_time(rng, now, days_back, &spikes);
+
+            SessionData {
+                id: random_uuid(rng),
+                service_id,
+                ip: Some(ip),
+                user_agent,
+                browser,
+                os,
+                device_type,
+                country,
+                start_time,
+            }
+        })
+        .collect()
+}
+
 async fn create_pool(db_path: &str) -> Pool<Sqlite> {
     let options = SqliteConnectOptions::from_str(db_path)
         .unwrap()
@@ -261,8 +414,12 @@ async fn seed_database(
     hits_per_service: u64,
     sessions_per_service: usize,
     days_back: u32,
+    seed: u64,
+    profile: Profile,
 ) -> Vec<ServiceData> {
-    let mut rng = rand::thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
+    // One reference time, so every generated offset depends only on the seed
+    let now = Utc::now();
 
     println!("Creating {} services...", num_services);
     let start = Instant::now();
@@ -271,8 +428,8 @@ async fn seed_database(
     let mut services: Vec<ServiceData> = Vec::with_capacity(num_services);
 
     for i in 0..num_services {
-        let id = Uuid::new_v4();
-        let tracking_id = generate_tracking_id();
+        let id = random_uuid(&mut rng);
+        let tracking_id = generate_tracking_id(&mut rng);
         let name = SERVICE_NAMES
             .get(i)
             .map(|s| s.to_string())
@@ -331,29 +488,14 @@ async fn seed_database(
     );
     let mut session_pools: HashMap<Uuid, Vec<SessionData>> = HashMap::new();
     for service in &services {
-        let mut sessions = Vec::with_capacity(sessions_per_service);
-
-        for _ in 0..sessions_per_service {
-            let ip = random_ip(&mut rng);
-            let user_agent = USER_AGENTS[rng.gen_range(0..USER_AGENTS.len())].to_string();
-            let browser = BROWSERS[rng.gen_range(0..BROWSERS.len())].to_string();
-            let os = OPERATING_SYSTEMS[rng.gen_range(0..OPERATING_SYSTEMS.len())].to_string();
-            let device_type = DEVICE_TYPES[rng.gen_range(0..DEVICE_TYPES.len())].to_string();
-            let country = COUNTRIES[rng.gen_range(0..COUNTRIES.len())].to_string();
-            let start_time = random_recent_datetime(&mut rng, days_back);
-
-            sessions.push(SessionData {
-                id: Uuid::new_v4(),
-                service_id: service.id,
-                ip: Some(ip),
-                user_agent,
-                browser,
-                os,
-                device_type,
-                country,
-                start_time,
-            });
-        }
+        let sessions = generate_sessions(
+            &mut rng,
+            service.id,
+            sessions_per_service,
+            profile,
+            now,
+            days_back,
+        );
         session_pools.insert(service.id, sessions);
     }
     println!(
@@ -438,7 +580,7 @@ async fn seed_database(
             let load_time = rng.gen_range(100..2100);
 
             // Hit time is sometime after session start
-            let session_age_ms = (Utc::now() - session.start_time).num_milliseconds().max(1);
+            let session_age_ms = (now - session.start_time).num_milliseconds().max(1);
             let hit_offset_ms = rng.gen_range(0..session_age_ms.min(3600000)); // up to 1 hour after session start
             let hit_time = session.start_time + Duration::milliseconds(hit_offset_ms);
 
@@ -847,6 +989,254 @@ async fn run_benchmarks(pool: &Pool<Sqlite>) {
     );
 }
 
+/// Kind of ingress request sent by `fire`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FireKind {
+    Pixel,
+    PageLoad,
+    Heartbeat,
+}
+
+impl FireKind {
+    const ALL: [FireKind; 3] = [FireKind::PageLoad, FireKind::Heartbeat, FireKind::Pixel];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            FireKind::Pixel => "pixel",
+            FireKind::PageLoad => "page load",
+            FireKind::Heartbeat => "heartbeat",
+        }
+    }
+}
+
+/// A simulated browser, remembering the page it is on so it can send that
+/// page's heartbeats
+struct Visitor {
+    ip: String,
+    user_agent: &'static str,
+    /// Idempotency key and location of the current page view
+    page: Option<(String, String)>,
+}
+
+/// A request `fire` is about to send
+#[derive(Debug)]
+struct PlannedRequest {
+    kind: FireKind,
+    /// Path under the server URL
+    path: String,
+    /// JSON body of a tracker POST; pixels are GETs
+    body: Option<serde_json::Value>,
+    referrer: &'static str,
+}
+
+/// Next request of a visitor: mostly page loads, heartbeats for the page
+/// they are on, and some pixel views
+fn plan_request(rng: &mut impl Rng, visitor: &mut Visitor, tracking_id: &str) -> PlannedRequest {
+    let referrer = REFERRERS[rng.gen_range(0..REFERRERS.len())];
+    let roll = rng.gen_range(0..10);
+
+    if roll == 0 {
+        return PlannedRequest {
+            kind: FireKind::Pixel,
+            path: format!("/trace/px_{}.gif", tracking_id),
+            body: None,
+            referrer,
+        };
+    }
+
+    let path = format!("/trace/app_{}.js", tracking_id);
+    match &visitor.page {
+        Some((idempotency, location)) if roll < 4 => PlannedRequest {
+            kind: FireKind::Heartbeat,
+            path,
+            body: Some(serde_json::json!({
+                "idempotency": idempotency,
+                "location": location,
+                "referrer": referrer,
+            })),
+            referrer,
+        },
+        _ => {
+            let idempotency = format!("{:016x}", rng.gen::<u64>());
+            let location = format!(
+                "https://example.com{}",
+                PAGES[rng.gen_range(0..PAGES.len())]
+            );
+            let body = serde_json::json!({
+                "idempotency": idempotency,
+                "location": location,
+                "referrer": referrer,
+                "loadTime": rng.gen_range(100..2100),
+            });
+            visitor.page = Some((idempotency, location));
+            PlannedRequest {
+                kind: FireKind::PageLoad,
+                path,
+                body: Some(body),
+                referrer,
+            }
+        }
+    }
+}
+
+struct FireOutcome {
+    kind: FireKind,
+    latency_ms: f64,
+    /// Status code or transport error, for failed requests
+    error: Option<String>,
+}
+
+/// Value at percentile `p` (0-100) of sorted samples
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Send ingress requests to a running server at a fixed rate and report
+/// latency percentiles and error rates
+async fn fire(url: &str, tracking_ids: &[String], rps: u32, duration_secs: u64, seed: u64) {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .expect("Failed to build HTTP client");
+    let url = url.trim_end_matches('/').to_string();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut visitors: Vec<Visitor> = (0..1000)
+        .map(|_| Visitor {
+            ip: random_ip(&mut rng),
+            user_agent: USER_AGENTS[rng.gen_range(0..USER_AGENTS.len())],
+            page: None,
+        })
+        .collect();
+
+    let total = u64::from(rps) * duration_secs;
+    println!(
+        "Firing {} requests at {} rps for {}s at {}",
+        total, rps, duration_secs, url
+    );
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut ticker =
+        tokio::time::interval(std::time::Duration::from_secs_f64(1.0 / f64::from(rps)));
+    let started = Instant::now();
+
+    for sent in 0..total {
+        ticker.tick().await;
+        let tracking_id = &tracking_ids[rng.gen_range(0..tracking_ids.len())];
+        let index = rng.gen_range(0..visitors.len());
+        let visitor = &mut visitors[index];
+        let planned = plan_request(&mut rng, visitor, tracking_id);
+
+        let target = format!("{}{}", url, planned.path);
+        let request = match &planned.body {
+            Some(body) => client.post(&target).json(body),
+            None => client.get(&target),
+        }
+        .header("User-Agent", visitor.user_agent)
+        .header("X-Forwarded-For", &visitor.ip)
+        .header("Origin", "https://example.com")
+        .header("Referer", planned.referrer)
+        .header("Accept-Language", "en-US,en;q=0.9");
+
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let request_start = Instant::now();
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => None,
+                Ok(response) => Some(response.status().to_string()),
+                Err(e) if e.is_timeout() => Some("timeout".to_string()),
+                Err(e) if e.is_connect() => Some("connection failed".to_string()),
+                Err(_) => Some("request failed".to_string()),
+            };
+            let _ = tx.send(FireOutcome {
+                kind: planned.kind,
+                latency_ms: request_start.elapsed().as_secs_f64() * 1000.0,
+                error,
+            });
+        });
+
+        if (sent + 1) % u64::from(rps) == 0 {
+            print!("\r  Sent {}/{}    ", sent + 1, total);
+            let _ = std::io::stdout().flush();
+        }
+    }
+    drop(tx);
+
+    let mut outcomes = Vec::with_capacity(total as usize);
+    while let Some(outcome) = rx.recv().await {
+        outcomes.push(outcome);
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    println!("\n\n{}", "=".repeat(86));
+    println!("Fire Results");
+    println!("{}", "=".repeat(86));
+    println!(
+        "{:<12} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "Request",
+        "Count",
+        "Errors",
+        "Error %",
+        "Mean(ms)",
+        "p50(ms)",
+        "p95(ms)",
+        "p99(ms)",
+        "Max(ms)"
+    );
+    println!("{}", "-".repeat(86));
+
+    let kinds = FireKind::ALL
+        .iter()
+        .map(|kind| (kind.as_str(), Some(*kind)));
+    for (name, kind) in kinds.chain(std::iter::once(("all", None))) {
+        let selected: Vec<&FireOutcome> = outcomes
+            .iter()
+            .filter(|o| kind.is_none_or(|k| o.kind == k))
+            .collect();
+        if selected.is_empty() {
+            continue;
+        }
+        let errors = selected.iter().filter(|o| o.error.is_some()).count();
+        let mut latencies: Vec<f64> = selected.iter().map(|o| o.latency_ms).collect();
+        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        println!(
+            "{:<12} {:>8} {:>8} {:>7.1}% {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+            name,
+            selected.len(),
+            errors,
+            errors as f64 * 100.0 / selected.len() as f64,
+            latencies.iter().sum::<f64>() / latencies.len() as f64,
+            percentile(&latencies, 50.0),
+            percentile(&latencies, 95.0),
+            percentile(&latencies, 99.0),
+            latencies[latencies.len() - 1],
+        );
+    }
+
+    let mut error_counts: HashMap<&str, usize> = HashMap::new();
+    for error in outcomes.iter().filter_map(|o| o.error.as_deref()) {
+        *error_counts.entry(error).or_insert(0) += 1;
+    }
+    if !error_counts.is_empty() {
+        let mut error_counts: Vec<_> = error_counts.into_iter().collect();
+        error_counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        println!("\nErrors:");
+        for (error, count) in error_counts {
+            println!("  {:<24} {}", error, count);
+        }
+    }
+
+    println!(
+        "\nAchieved {:.0} requests/sec over {:.1}s (target {})",
+        outcomes.len() as f64 / elapsed,
+        elapsed,
+        rps
+    );
+}
+
 fn print_usage() {
     eprintln!(
         r#"
@@ -855,6 +1245,7 @@ Usage: loadtest <command> [options]
 Commands:
   seed     Seed the database with test data
   bench    Run benchmarks on existing database
+  fire     Send tracker requests to a running server
 
 Options for 'seed':
   --db <path>       Database path (default: loadtest.db)
@@ -862,15 +1253,28 @@ Options for 'seed':
   --sessions <n>    Sessions PER SERVICE (default: 10000)
   --services <n>    Number of services (default: 5)
   --days <n>        Days of history to generate (default: 7)
+  --seed <n>        Random seed, for reproducible datasets (default: random)
+  --profile <name>  Traffic shape: steady, spiky, weekend-heavy or bot-heavy
+                    (default: steady)
   --bench           Run benchmarks after seeding
 
 Options for 'bench':
   --db <path>       Database path (default: loadtest.db)
 
+Options for 'fire':
+  --url <url>           Server URL (default: http://localhost:8080)
+  --rps <n>             Requests per second (default: 500)
+  --duration <secs>     How long to fire for (default: 30)
+  --tracking-id <id>    Service to track; repeat for several (default: the
+                        services in the --db database)
+  --seed <n>            Random seed for the request mix (default: random)
+
 Examples:
   cargo run --release --bin loadtest -- seed
   cargo run --release --bin loadtest -- seed --hits 100000 --sessions 10000 --services 5 --bench
+  cargo run --release --bin loadtest -- seed --seed 42 --profile weekend-heavy
   cargo run --release --bin loadtest -- bench --db ./loadtest.db
+  cargo run --release --bin loadtest -- fire --url http://localhost:8080 --rps 500
 
 After seeding, start the server with:
   SHYMINI__DATABASE_PATH=./loadtest.db cargo run --release
@@ -893,7 +1297,13 @@ async fn main() {
     let mut num_services = 5usize;
     let mut days_back = 7u32;
     let mut sessions_per_service = 10_000usize;
+    let mut seed: u64 = rand::random();
+    let mut profile = Profile::Steady;
     let mut run_bench = false;
+    let mut url = "http://localhost:8080".to_string();
+    let mut rps = 500u32;
+    let mut duration_secs = 30u64;
+    let mut tracking_ids: Vec<String> = Vec::new();
 
     // Parse arguments
     let mut i = 2;
@@ -919,9 +1329,41 @@ async fn main() {
                 i += 1;
                 sessions_per_service = args[i].parse().expect("Invalid sessions count");
             }
+            "--seed" => {
+                i += 1;
+                seed = args[i].parse().expect("Invalid seed");
+            }
+            "--profile" => {
+                i += 1;
+                profile = Profile::from_str(&args[i]).unwrap_or_else(|| {
+                    eprintln!("Unknown profile: {}", args[i]);
+                    print_usage();
+                    std::process::exit(1);
+                });
+            }
             "--bench" => {
                 run_bench = true;
             }
+            "--url" => {
+                i += 1;
+                url = args[i].clone();
+            }
+            "--rps" => {
+                i += 1;
+                rps = args[i].parse().expect("Invalid requests per second");
+                if rps == 0 {
+                    eprintln!("--rps must be at least 1");
+                    std::process::exit(1);
+                }
+            }
+            "--duration" => {
+                i += 1;
+                duration_secs = args[i].parse().expect("Invalid duration");
+            }
+            "--tracking-id" => {
+                i += 1;
+                tracking_ids.push(args[i].clone());
+            }
             _ => {
                 eprintln!("Unknown option: {}", args[i]);
                 print_usage();
@@ -943,6 +1385,8 @@ async fn main() {
             println!("Hits per service: {}", hits_per_service);
             println!("Sessions per service: {}", sessions_per_service);
             println!("Days of history: {}", days_back);
+            println!("Profile: {}", profile.as_str());
+            println!("Seed: {} (pass --seed {} to reproduce)", seed, seed);
             println!();
 
             let pool = create_pool(&db_url).await;
@@ -954,6 +1398,8 @@ async fn main() {
                 hits_per_service,
                 sessions_per_service,
                 days_back,
+                seed,
+                profile,
             )
             .await;
 
@@ -987,6 +1433,23 @@ async fn main() {
             let pool = create_pool(&db_url).await;
             run_benchmarks(&pool).await;
         }
+        "fire" => {
+            if tracking_ids.is_empty() && db_path.exists() {
+                let pool = create_pool(&db_url).await;
+                tracking_ids = sqlx::query_scalar("SELECT tracking_id FROM services")
+                    .fetch_all(&pool)
+                    .await
+                    .expect("Failed to fetch services");
+            }
+            if tracking_ids.is_empty() {
+                eprintln!(
+                    "No services to track: pass --tracking-id, or --db with a seeded database"
+                );
+                std::process::exit(1);
+            }
+
+            fire(&url, &tracking_ids, rps, duration_secs, seed).await;
+        }
         _ => {
             eprintln!("Unknown command: {}", command);
             print_usage();
@@ -994,3 +1457,102 @@ async fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sessions(seed: u64, profile: Profile) -> Vec<SessionData> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let now = Utc.with_ymd_and_hms(2024, 6, 12, 12, 0, 0).unwrap();
+        generate_sessions(&mut rng, Uuid::nil(), 2000, profile, now, 28)
+    }
+
+    #[test]
+    fn test_seed_reproduces_sessions() {
+        for profile in Profile::ALL {
+            assert_eq!(sessions(7, profile), sessions(7, profile));
+            assert_ne!(sessions(7, profile), sessions(8, profile));
+        }
+    }
+
+    #[test]
+    fn test_profile_names() {
+        for profile in Profile::ALL {
+            assert_eq!(Profile::from_str(profile.as_str()), Some(profile));
+        }
+        assert_eq!(Profile::from_str("bursty"), None);
+    }
+
+    #[test]
+    fn test_profiles_shape_traffic() {
+        let bots = |profile| {
+            sessions(1, profile)
+                .iter()
+                .filter(|s| s.device_type == "Robot")
+                .count()
+        };
+        assert_eq!(bots(Profile::Steady), 0);
+        assert!((600..1000).contains(&bots(Profile::BotHeavy)));
+
+        let weekend_share = |profile| {
+            let sessions = sessions(1, profile);
+            let weekend = sessions
+                .iter()
+                .filter(|s| matches!(s.start_time.weekday(), Weekday::Sat | Weekday::Sun))
+                .count();
+            weekend as f64 / sessions.len() as f64
+        };
+        assert!(weekend_share(Profile::WeekendHeavy) > weekend_share(Profile::Steady) + 0.2);
+    }
+
+    #[test]
+    fn test_plan_request() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut visitor = Visitor {
+            ip: "10.0.0.1".to_string(),
+            user_agent: USER_AGENTS[0],
+            page: None,
+        };
+        let mut kinds = Vec::new();
+        for _ in 0..500 {
+            let before = visitor.page.clone();
+            let planned = plan_request(&mut rng, &mut visitor, "abc12345");
+            match planned.kind {
+                FireKind::Pixel => {
+                    assert_eq!(planned.path, "/trace/px_abc12345.gif");
+                    assert!(planned.body.is_none());
+                }
+                FireKind::PageLoad => {
+                    let body = planned.body.unwrap();
+                    assert!(body["loadTime"].is_number());
+                    assert_eq!(
+                        visitor.page.as_ref().unwrap().0,
+                        body["idempotency"].as_str().unwrap()
+                    );
+                }
+                FireKind::Heartbeat => {
+                    // Heartbeats repeat the current page's key, without a load time
+                    let body = planned.body.unwrap();
+                    assert!(body.get("loadTime").is_none());
+                    assert_eq!(before.unwrap().0, body["idempotency"].as_str().unwrap());
+                }
+            }
+            kinds.push(planned.kind);
+        }
+        for kind in FireKind::ALL {
+            assert!(kinds.contains(&kind), "no {} requests", kind.as_str());
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&samples, 50.0), 50.0);
+        assert_eq!(percentile(&samples, 95.0), 95.0);
+        assert_eq!(percentile(&samples, 100.0), 100.0);
+        assert_eq!(percentile(&samples, 0.0), 1.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+}
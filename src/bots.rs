@@ -0,0 +1,259 @@
+//! Search engine crawlers. Anyone can send Googlebot's user agent; with
+//! `verify_bots`, the address of a hit claiming to be Googlebot or Bingbot is
+//! checked in the background: its reverse DNS name must be under the search
+//! engine's domains and resolve back to it. Verdicts are cached per address,
+//! and hits from addresses found to be fake are dropped, so the crawlers in
+//! the stats are the genuine ones.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use moka::future::Cache;
+use tracing::debug;
+
+/// How long a verdict on an address is kept
+const VERDICT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most addresses a verdict is kept for
+const MAX_VERDICTS: u64 = 10_000;
+
+/// Longest a check may take; one that runs out is tried again on a later hit
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A crawler a user agent claims to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimedBot {
+    Googlebot,
+    Bingbot,
+}
+
+impl ClaimedBot {
+    pub fn from_user_agent(user_agent: &str) -> Option<Self> {
+        let user_agent = user_agent.to_ascii_lowercase();
+        if user_agent.contains("googlebot") {
+            Some(Self::Googlebot)
+        } else if user_agent.contains("bingbot") {
+            Some(Self::Bingbot)
+        } else {
+            None
+        }
+    }
+
+    /// Domains the crawler's reverse DNS names are under
+    fn domains(&self) -> &'static [&'static str] {
+        match self {
+            Self::Googlebot => &["googlebot.com", "google.com", "googleusercontent.com"],
+            Self::Bingbot => &["search.msn.com"],
+        }
+    }
+
+    /// Whether `host` is a name under one of the crawler's domains
+    pub fn owns(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.domains().iter().any(|domain| {
+            host.strip_suffix(domain)
+                .is_some_and(|name| name.ends_with('.'))
+        })
+    }
+}
+
+/// DNS lookups for the checks
+#[async_trait]
+pub trait HostResolver: Send + Sync {
+    /// The address's reverse DNS name
+    async fn reverse(&self, ip: IpAddr) -> Option<String>;
+
+    /// The addresses a name resolves to
+    async fn forward(&self, host: &str) -> Vec<IpAddr>;
+}
+
+/// The system's resolver
+pub struct SystemResolver;
+
+#[async_trait]
+impl HostResolver for SystemResolver {
+    async fn reverse(&self, ip: IpAddr) -> Option<String> {
+        // Without a name, getnameinfo gives the address back, which no
+        // crawler's domain matches
+        tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip).ok())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn forward(&self, host: &str) -> Vec<IpAddr> {
+        match tokio::net::lookup_host((host, 0)).await {
+            Ok(addresses) => addresses.map(|address| address.ip()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Verdicts on the addresses of claimed crawlers, shared through `AppState`
+pub struct BotVerifier {
+    resolver: Arc<dyn HostResolver>,
+    /// Whether the address is the crawler's it claimed to be
+    verdicts: Cache<IpAddr, bool>,
+}
+
+impl Default for BotVerifier {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemResolver))
+    }
+}
+
+impl BotVerifier {
+    pub fn new(resolver: Arc<dyn HostResolver>) -> Self {
+        Self {
+            resolver,
+            verdicts: Cache::builder()
+                .max_capacity(MAX_VERDICTS)
+                .time_to_live(VERDICT_TTL)
+                .build(),
+        }
+    }
+
+    /// Whether a hit from `ip` with `user_agent` may be recorded: anything
+    /// but a claimed crawler found to be fake. Addresses without a verdict
+    /// pass while they are checked in the background.
+    pub async fn admits(self: &Arc<Self>, ip: &str, user_agent: &str) -> bool {
+        let (Some(bot), Ok(ip)) = (ClaimedBot::from_user_agent(user_agent), ip.parse()) else {
+            return true;
+        };
+        if let Some(genuine) = self.verdicts.get(&ip).await {
+            return genuine;
+        }
+        let verifier = Arc::clone(self);
+        tokio::spawn(async move { verifier.check(bot, ip).await });
+        true
+    }
+
+    /// Find and cache the verdict on `ip`; concurrent checks of one address
+    /// share a lookup, and one that times out is not cached
+    async fn check(&self, bot: ClaimedBot, ip: IpAddr) {
+        let verdict = self
+            .verdicts
+            .try_get_with(
+                ip,
+                tokio::time::timeout(LOOKUP_TIMEOUT, self.verify(bot, ip)),
+            )
+            .await;
+        match verdict {
+            Ok(true) => debug!("{:?} at {} is genuine", bot, ip),
+            Ok(false) => debug!("{:?} at {} is fake", bot, ip),
+            Err(_) => debug!("Checking {:?} at {} timed out", bot, ip),
+        }
+    }
+
+    /// Whether `ip`'s reverse DNS name is under the crawler's domains and
+    /// resolves back to `ip`
+    pub async fn verify(&self, bot: ClaimedBot, ip: IpAddr) -> bool {
+        let Some(host) = self.resolver.reverse(ip).await else {
+            return false;
+        };
+        bot.owns(&host) && self.resolver.forward(&host).await.contains(&ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reverse names of addresses, and addresses of names
+    struct FakeResolver {
+        names: Vec<(IpAddr, &'static str)>,
+        addresses: Vec<(&'static str, IpAddr)>,
+    }
+
+    #[async_trait]
+    impl HostResolver for FakeResolver {
+        async fn reverse(&self, ip: IpAddr) -> Option<String> {
+            self.names
+                .iter()
+                .find(|(address, _)| *address == ip)
+                .map(|(_, host)| host.to_string())
+        }
+
+        async fn forward(&self, host: &str) -> Vec<IpAddr> {
+            self.addresses
+                .iter()
+                .filter(|(name, _)| *name == host)
+                .map(|(_, address)| *address)
+                .collect()
+        }
+    }
+
+    fn google() -> FakeResolver {
+        let genuine = "66.249.66.1".parse().unwrap();
+        FakeResolver {
+            names: vec![
+                (genuine, "crawl-66-249-66-1.googlebot.com"),
+                // Claims a Google name that doesn't resolve back to it
+                (
+                    "203.0.113.1".parse().unwrap(),
+                    "crawl-66-249-66-1.googlebot.com",
+                ),
+            ],
+            addresses: vec![("crawl-66-249-66-1.googlebot.com", genuine)],
+        }
+    }
+
+    #[test]
+    fn test_claimed_bot() {
+        assert_eq!(
+            ClaimedBot::from_user_agent(
+                "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"
+            ),
+            Some(ClaimedBot::Googlebot)
+        );
+        assert_eq!(
+            ClaimedBot::from_user_agent("Mozilla/5.0 (compatible; bingbot/2.0)"),
+            Some(ClaimedBot::Bingbot)
+        );
+        assert_eq!(
+            ClaimedBot::from_user_agent("Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0"),
+            None
+        );
+
+        assert!(ClaimedBot::Googlebot.owns("crawl-66-249-66-1.googlebot.com."));
+        assert!(ClaimedBot::Googlebot.owns("rate-limited-proxy-66-249-90-77.google.com"));
+        assert!(!ClaimedBot::Googlebot.owns("googlebot.com.evil.example"));
+        assert!(!ClaimedBot::Googlebot.owns("notgooglebot.com"));
+        assert!(ClaimedBot::Bingbot.owns("msnbot-157-55-39-1.search.msn.com"));
+        assert!(!ClaimedBot::Bingbot.owns("66.249.66.1"));
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let verifier = BotVerifier::new(Arc::new(google()));
+        let genuine = "66.249.66.1".parse().unwrap();
+        assert!(verifier.verify(ClaimedBot::Googlebot, genuine).await);
+        assert!(!verifier.verify(ClaimedBot::Bingbot, genuine).await);
+        let spoofed = "203.0.113.1".parse().unwrap();
+        assert!(!verifier.verify(ClaimedBot::Googlebot, spoofed).await);
+        let unnamed = "203.0.113.2".parse().unwrap();
+        assert!(!verifier.verify(ClaimedBot::Googlebot, unnamed).await);
+    }
+
+    #[tokio::test]
+    async fn test_admits() {
+        let googlebot = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+        let verifier = Arc::new(BotVerifier::new(Arc::new(google())));
+
+        // Passes while checked, then by the verdict
+        assert!(verifier.admits("203.0.113.1", googlebot).await);
+        assert!(verifier.admits("66.249.66.1", googlebot).await);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!verifier.admits("203.0.113.1", googlebot).await);
+        assert!(verifier.admits("66.249.66.1", googlebot).await);
+
+        // Browsers aren't checked
+        assert!(
+            verifier
+                .admits("203.0.113.1", "Mozilla/5.0 Firefox/120.0")
+                .await
+        );
+    }
+}
@@ -1,8 +1,23 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use moka::future::Cache;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Mutex;
 
 use crate::config::Settings;
-use crate::domain::{HitId, ServiceId, SessionId};
+use crate::domain::{
+    BadgeCounts, Environment, HitId, InstallCheck, Organization, OrganizationId, ServiceId,
+    SessionId,
+};
+use crate::error::{Error, Result};
+use crate::ingress::{EncodedScript, IngestScript};
+use crate::sketch::VisitorSketch;
+
+/// How long public badge counts are reused; badges are embedded in pages
+/// anyone can load, so they shouldn't each query the database
+pub const BADGE_TTL: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub struct AppCache {
@@ -12,13 +27,98 @@ pub struct AppCache {
     /// Cache for script inject content (ServiceId -> script)
     pub script_inject: Cache<ServiceId, String>,
 
-    /// Cache for session associations (hash -> SessionId)
-    pub session_associations: Cache<String, SessionId>,
+    /// Cache for precompressed tracker scripts (ETag -> encoded variants)
+    pub tracker_scripts: Cache<String, Arc<EncodedScript>>,
+
+    /// Cache for session associations (hash -> SessionAssociation)
+    pub session_associations: Cache<String, SessionAssociation>,
 
     /// Cache for hit idempotency (idempotency key -> HitId)
     pub hit_idempotency: Cache<String, HitId>,
+
+    /// Cache for organizations, read by ingress for their hit quotas
+    pub organizations: Cache<OrganizationId, Organization>,
+
+    /// Cache for the latest install check of each service's site
+    pub install_checks: Cache<ServiceId, InstallCheck>,
+
+    /// Cache for the counts on public badges, kept for `BADGE_TTL`
+    pub badge_counts: Cache<ServiceId, BadgeCounts>,
+
+    /// Cache for the visitor sketches ingress adds to, locked while one is
+    /// updated and saved
+    pub visitor_sketches: Cache<VisitorSketchKey, Arc<Mutex<VisitorSketch>>>,
+
+    /// Cache for each service's compiled ingest script, `None` for services
+    /// without one
+    pub ingest_scripts: Cache<ServiceId, Option<Arc<IngestScript>>>,
+
+    /// Hits and misses of the lookups below, since the server started
+    lookups: Arc<CacheLookups>,
+}
+
+/// A visitor's current session, and its last activity as ingress recorded
+/// it, so timeouts are checked without reading the session back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionAssociation {
+    pub session_id: SessionId,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Hit and miss counters of one cache; moka keeps none itself
+#[derive(Default)]
+struct Lookups {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Lookups {
+    /// Count a lookup, passing its result through
+    fn record<T>(&self, found: Option<T>) -> Option<T> {
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+}
+
+#[derive(Default)]
+struct CacheLookups {
+    service_origins: Lookups,
+    script_inject: Lookups,
+    tracker_scripts: Lookups,
+    session_associations: Lookups,
+    hit_idempotency: Lookups,
+    organizations: Lookups,
+    install_checks: Lookups,
+    badge_counts: Lookups,
+    visitor_sketches: Lookups,
+    ingest_scripts: Lookups,
 }
 
+/// How full and how useful a cache is, for the status page
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub entries: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups that found an entry; `None` before the first
+    pub fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+/// The service, UTC day and environment a visitor sketch counts
+pub type VisitorSketchKey = (ServiceId, NaiveDate, Environment);
+
 impl AppCache {
     pub fn new(settings: &Settings) -> Self {
         let cache_ttl = Duration::from_secs(settings.cache_ttl_secs);
@@ -36,6 +136,11 @@ impl AppCache {
                 .time_to_live(cache_ttl)
                 .build(),
 
+            tracker_scripts: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(cache_ttl)
+                .build(),
+
             session_associations: Cache::builder()
                 .max_capacity(max_entries * 10)
                 .time_to_live(session_ttl)
@@ -45,6 +150,33 @@ impl AppCache {
                 .max_capacity(max_entries * 100)
                 .time_to_live(session_ttl)
                 .build(),
+
+            organizations: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(cache_ttl)
+                .build(),
+
+            install_checks: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(cache_ttl)
+                .build(),
+
+            badge_counts: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(BADGE_TTL)
+                .build(),
+
+            visitor_sketches: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(cache_ttl)
+                .build(),
+
+            ingest_scripts: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(cache_ttl)
+                .build(),
+
+            lookups: Arc::default(),
         }
     }
 
@@ -54,7 +186,8 @@ impl AppCache {
         F: FnOnce() -> Fut,
         Fut: std::future::Future<Output = Option<String>>,
     {
-        if let Some(origins) = self.service_origins.get(&service_id).await {
+        let cached = self.service_origins.get(&service_id).await;
+        if let Some(origins) = self.lookups.service_origins.record(cached) {
             return Some(origins);
         }
 
@@ -78,7 +211,8 @@ impl AppCache {
         F: FnOnce() -> Fut,
         Fut: std::future::Future<Output = Option<String>>,
     {
-        if let Some(script) = self.script_inject.get(&service_id).await {
+        let cached = self.script_inject.get(&service_id).await;
+        if let Some(script) = self.lookups.script_inject.record(cached) {
             return Some(script);
         }
 
@@ -90,29 +224,53 @@ impl AppCache {
         }
     }
 
+    /// Get or build the precompressed variants of a rendered tracker script.
+    /// Keyed by the script's ETag, so a changed script never hits a stale entry.
+    pub async fn get_or_insert_tracker_script<F>(&self, etag: &str, f: F) -> Arc<EncodedScript>
+    where
+        F: FnOnce() -> EncodedScript,
+    {
+        let cached = self.tracker_scripts.get(etag).await;
+        if let Some(script) = self.lookups.tracker_scripts.record(cached) {
+            return script;
+        }
+
+        let script = Arc::new(f());
+        self.tracker_scripts
+            .insert(etag.to_string(), script.clone())
+            .await;
+        script
+    }
+
     /// Get session from association cache
-    pub async fn get_session_association(&self, hash: &str) -> Option<SessionId> {
-        self.session_associations.get(hash).await
+    pub async fn get_session_association(&self, hash: &str) -> Option<SessionAssociation> {
+        let cached = self.session_associations.get(hash).await;
+        self.lookups.session_associations.record(cached)
     }
 
     /// Set session association (and touch TTL if exists)
-    pub async fn set_session_association(&self, hash: String, session_id: SessionId) {
-        self.session_associations.insert(hash, session_id).await;
+    pub async fn set_session_association(&self, hash: String, association: SessionAssociation) {
+        self.session_associations.insert(hash, association).await;
     }
 
-    /// Touch session association TTL (re-insert to reset TTL)
-    pub async fn touch_session_association(&self, hash: &str) {
-        if let Some(session_id) = self.session_associations.get(hash).await {
+    /// Touch session association TTL (re-insert to reset TTL), moving its
+    /// last activity up to `last_seen` if given
+    pub async fn touch_session_association(&self, hash: &str, last_seen: Option<DateTime<Utc>>) {
+        if let Some(mut association) = self.session_associations.get(hash).await {
+            if let Some(last_seen) = last_seen {
+                association.last_seen = association.last_seen.max(last_seen);
+            }
             // Re-insert to reset TTL
             self.session_associations
-                .insert(hash.to_string(), session_id)
+                .insert(hash.to_string(), association)
                 .await;
         }
     }
 
     /// Get hit from idempotency cache
     pub async fn get_hit_idempotency(&self, key: &str) -> Option<HitId> {
-        self.hit_idempotency.get(key).await
+        let cached = self.hit_idempotency.get(key).await;
+        self.lookups.hit_idempotency.record(cached)
     }
 
     /// Set hit idempotency
@@ -127,10 +285,210 @@ impl AppCache {
         }
     }
 
+    /// Get or insert an organization
+    pub async fn get_or_insert_organization<F, Fut>(
+        &self,
+        organization_id: OrganizationId,
+        f: F,
+    ) -> Option<Organization>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Option<Organization>>,
+    {
+        let cached = self.organizations.get(&organization_id).await;
+        if let Some(organization) = self.lookups.organizations.record(cached) {
+            return Some(organization);
+        }
+
+        if let Some(organization) = f().await {
+            self.organizations
+                .insert(organization_id, organization.clone())
+                .await;
+            Some(organization)
+        } else {
+            None
+        }
+    }
+
+    /// Invalidate a cached organization after it changed
+    pub async fn invalidate_organization(&self, organization_id: OrganizationId) {
+        self.organizations.invalidate(&organization_id).await;
+    }
+
+    /// Get the latest install check of a service's site
+    pub async fn get_install_check(&self, service_id: ServiceId) -> Option<InstallCheck> {
+        let cached = self.install_checks.get(&service_id).await;
+        self.lookups.install_checks.record(cached)
+    }
+
+    /// Remember an install check, replacing any earlier one
+    pub async fn set_install_check(&self, service_id: ServiceId, check: InstallCheck) {
+        self.install_checks.insert(service_id, check).await;
+    }
+
+    /// Get or insert the counts on a service's public badge
+    pub async fn get_or_insert_badge_counts<F, Fut>(
+        &self,
+        service_id: ServiceId,
+        f: F,
+    ) -> Option<BadgeCounts>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Option<BadgeCounts>>,
+    {
+        let cached = self.badge_counts.get(&service_id).await;
+        if let Some(counts) = self.lookups.badge_counts.record(cached) {
+            return Some(counts);
+        }
+
+        let counts = f().await?;
+        self.badge_counts.insert(service_id, counts).await;
+        Some(counts)
+    }
+
+    /// Get a visitor sketch, loading it once however many requests ask
+    pub async fn get_or_load_visitor_sketch<Fut>(
+        &self,
+        key: VisitorSketchKey,
+        load: Fut,
+    ) -> Result<Arc<Mutex<VisitorSketch>>>
+    where
+        Fut: std::future::Future<Output = Result<VisitorSketch>>,
+    {
+        let loaded = AtomicBool::new(false);
+        let sketch = self
+            .visitor_sketches
+            .try_get_with(key, async {
+                loaded.store(true, Ordering::Relaxed);
+                load.await.map(|s| Arc::new(Mutex::new(s)))
+            })
+            .await
+            .map_err(|e: Arc<Error>| Error::Internal(e.to_string()))?;
+        let found = (!loaded.load(Ordering::Relaxed)).then_some(());
+        self.lookups.visitor_sketches.record(found);
+        Ok(sketch)
+    }
+
+    /// Get a service's compiled ingest script, loading it once however many
+    /// requests ask
+    pub async fn get_or_load_ingest_script<Fut>(
+        &self,
+        service_id: ServiceId,
+        load: Fut,
+    ) -> Result<Option<Arc<IngestScript>>>
+    where
+        Fut: std::future::Future<Output = Result<Option<IngestScript>>>,
+    {
+        let loaded = AtomicBool::new(false);
+        let script = self
+            .ingest_scripts
+            .try_get_with(service_id, async {
+                loaded.store(true, Ordering::Relaxed);
+                load.await.map(|s| s.map(Arc::new))
+            })
+            .await
+            .map_err(|e: Arc<Error>| Error::Internal(e.to_string()))?;
+        let found = (!loaded.load(Ordering::Relaxed)).then_some(());
+        self.lookups.ingest_scripts.record(found);
+        Ok(script)
+    }
+
     /// Invalidate service-related caches
     pub async fn invalidate_service(&self, service_id: ServiceId) {
         self.service_origins.invalidate(&service_id).await;
         self.script_inject.invalidate(&service_id).await;
+        self.install_checks.invalidate(&service_id).await;
+        self.ingest_scripts.invalidate(&service_id).await;
+    }
+
+    /// Evict every expired entry now, rather than bit by bit as the caches
+    /// are used. Returns the number of entries left.
+    pub async fn prune(&self) -> u64 {
+        self.service_origins.run_pending_tasks().await;
+        self.script_inject.run_pending_tasks().await;
+        self.tracker_scripts.run_pending_tasks().await;
+        self.session_associations.run_pending_tasks().await;
+        self.hit_idempotency.run_pending_tasks().await;
+        self.organizations.run_pending_tasks().await;
+        self.install_checks.run_pending_tasks().await;
+        self.badge_counts.run_pending_tasks().await;
+        self.visitor_sketches.run_pending_tasks().await;
+        self.ingest_scripts.run_pending_tasks().await;
+
+        self.service_origins.entry_count()
+            + self.script_inject.entry_count()
+            + self.tracker_scripts.entry_count()
+            + self.session_associations.entry_count()
+            + self.hit_idempotency.entry_count()
+            + self.organizations.entry_count()
+            + self.install_checks.entry_count()
+            + self.badge_counts.entry_count()
+            + self.visitor_sketches.entry_count()
+            + self.ingest_scripts.entry_count()
+    }
+
+    /// Entries, hits and misses of every cache. Entry counts lag behind
+    /// recent inserts and evictions by a little.
+    pub fn stats(&self) -> Vec<CacheStats> {
+        let stats = |name, entries, lookups: &Lookups| CacheStats {
+            name,
+            entries,
+            hits: lookups.hits.load(Ordering::Relaxed),
+            misses: lookups.misses.load(Ordering::Relaxed),
+        };
+        let lookups = &self.lookups;
+        vec![
+            stats(
+                "service_origins",
+                self.service_origins.entry_count(),
+                &lookups.service_origins,
+            ),
+            stats(
+                "script_inject",
+                self.script_inject.entry_count(),
+                &lookups.script_inject,
+            ),
+            stats(
+                "tracker_scripts",
+                self.tracker_scripts.entry_count(),
+                &lookups.tracker_scripts,
+            ),
+            stats(
+                "session_associations",
+                self.session_associations.entry_count(),
+                &lookups.session_associations,
+            ),
+            stats(
+                "hit_idempotency",
+                self.hit_idempotency.entry_count(),
+                &lookups.hit_idempotency,
+            ),
+            stats(
+                "organizations",
+                self.organizations.entry_count(),
+                &lookups.organizations,
+            ),
+            stats(
+                "install_checks",
+                self.install_checks.entry_count(),
+                &lookups.install_checks,
+            ),
+            stats(
+                "badge_counts",
+                self.badge_counts.entry_count(),
+                &lookups.badge_counts,
+            ),
+            stats(
+                "visitor_sketches",
+                self.visitor_sketches.entry_count(),
+                &lookups.visitor_sketches,
+            ),
+            stats(
+                "ingest_scripts",
+                self.ingest_scripts.entry_count(),
+                &lookups.ingest_scripts,
+            ),
+        ]
     }
 }
 
@@ -143,6 +501,14 @@ mod tests {
         Settings {
             host: "0.0.0.0".to_string(),
             port: 8080,
+            listen: None,
+            #[cfg(unix)]
+            listen_mode: crate::server::SocketMode(0o660),
+            keep_alive: true,
+            keep_alive_timeout_secs: 60,
+            max_connections: 0,
+            max_concurrent_streams: 200,
+            request_timeout_secs: 30,
             database_url: None,
             database_path: None,
             maxmind_city_db: None,
@@ -150,9 +516,64 @@ mod tests {
             block_all_ips: false,
             aggressive_hash_salting: false,
             script_heartbeat_frequency_ms: 5000,
+            max_heartbeats_per_hit: 720,
             cache_max_entries: 100,
             cache_ttl_secs: 60,
             session_memory_timeout_secs: 30,
+            max_timestamp_skew_secs: 30,
+            locale: "en".to_string(),
+            quota_sample_rate: 0.1,
+            multi_tenant: false,
+            signup_enabled: false,
+            public_url: "http://localhost:8080".to_string(),
+            secret_key: None,
+            smtp_url: None,
+            mail_from: "shymini <shymini@localhost>".to_string(),
+            oidc_issuer: None,
+            oidc_client_id: None,
+            oidc_client_secret: None,
+            oidc_scopes: "openid email profile".to_string(),
+            oidc_groups_claim: None,
+            proxy_user_header: None,
+            proxy_email_header: "Remote-Email".to_string(),
+            proxy_name_header: "Remote-Name".to_string(),
+            proxy_groups_header: "Remote-Groups".to_string(),
+            proxy_trusted_networks: String::new(),
+            login_max_failures: 5,
+            login_max_failures_per_ip: 20,
+            login_lockout_secs: 60,
+            monitor_interval_secs: 0,
+            monitor_webhook_url: None,
+            monitor_rdap_url: "https://rdap.org".to_string(),
+            sitemap_crawl_interval_secs: 0,
+            top_pages_refresh_secs: 0,
+            visitor_sketches: false,
+            idempotency_filter_capacity: 0,
+            prefetch_hits: Default::default(),
+            verify_bots: false,
+            referrer_spam: Default::default(),
+            referrer_spam_list_url: String::new(),
+            error_sample_rate: 1.0,
+            max_hit_dimensions: 5,
+            max_session_props: 10,
+            maintenance_hour: None,
+            rollup_retention_days: 400,
+            hit_partitions: false,
+            hit_retention_months: 0,
+            ws_messages_per_min: 60,
+            theme_logo_url: None,
+            theme_accent: "#5cc265".to_string(),
+            theme_mode: Default::default(),
+            update_check: false,
+            update_check_url: "https://api.github.com/repos/cdaringe/shymini/releases/latest"
+                .to_string(),
+            ip_encryption_key: None,
+            query_api: false,
+            query_timeout_secs: 10,
+            query_max_rows: 1000,
+            vault_addr: None,
+            vault_token: None,
+            vault_path: "secret/data/shymini".to_string(),
         }
     }
 
@@ -171,20 +592,22 @@ mod tests {
         let cache = AppCache::new(&settings);
 
         let hash = "test_hash_12345".to_string();
-        let session_id = SessionId::from_uuid(Uuid::new_v4());
+        let association = SessionAssociation {
+            session_id: SessionId::from_uuid(Uuid::new_v4()),
+            last_seen: Utc::now(),
+        };
 
         // Initially empty
         assert!(cache.get_session_association(&hash).await.is_none());
 
         // Set association
         cache
-            .set_session_association(hash.clone(), session_id)
+            .set_session_association(hash.clone(), association)
             .await;
 
         // Should be retrievable
         let retrieved = cache.get_session_association(&hash).await;
-        assert!(retrieved.is_some());
-        assert_eq!(retrieved.unwrap(), session_id);
+        assert_eq!(retrieved, Some(association));
     }
 
     #[tokio::test]
@@ -213,17 +636,31 @@ mod tests {
         let cache = AppCache::new(&settings);
 
         let hash = "test_hash_touch".to_string();
-        let session_id = SessionId::from_uuid(Uuid::new_v4());
+        let start = Utc::now();
+        let association = SessionAssociation {
+            session_id: SessionId::from_uuid(Uuid::new_v4()),
+            last_seen: start,
+        };
 
         cache
-            .set_session_association(hash.clone(), session_id)
+            .set_session_association(hash.clone(), association)
             .await;
 
         // Touch should not error
-        cache.touch_session_association(&hash).await;
+        cache.touch_session_association(&hash, None).await;
 
         // Should still be retrievable
-        assert!(cache.get_session_association(&hash).await.is_some());
+        assert_eq!(
+            cache.get_session_association(&hash).await,
+            Some(association)
+        );
+
+        // Later activity moves the last activity up, earlier doesn't
+        let later = start + chrono::Duration::minutes(5);
+        cache.touch_session_association(&hash, Some(later)).await;
+        cache.touch_session_association(&hash, Some(start)).await;
+        let touched = cache.get_session_association(&hash).await.unwrap();
+        assert_eq!(touched.last_seen, later);
     }
 
     #[tokio::test]
@@ -286,6 +723,23 @@ mod tests {
         assert_eq!(script, Some("console.log('test');".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_get_or_insert_tracker_script() {
+        let settings = test_settings();
+        let cache = AppCache::new(&settings);
+
+        let script = cache
+            .get_or_insert_tracker_script("\"abc\"", || EncodedScript::new("var a;"))
+            .await;
+        assert_eq!(&script.identity[..], b"var a;");
+
+        // Same ETag returns the cached variants
+        let script = cache
+            .get_or_insert_tracker_script("\"abc\"", || EncodedScript::new("var b;"))
+            .await;
+        assert_eq!(&script.identity[..], b"var a;");
+    }
+
     #[tokio::test]
     async fn test_invalidate_service() {
         let settings = test_settings();
@@ -315,6 +769,39 @@ mod tests {
         assert!(cache.script_inject.get(&service_id).await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_organization_cache() {
+        let settings = test_settings();
+        let cache = AppCache::new(&settings);
+
+        let organization = Organization {
+            id: OrganizationId::new(),
+            name: "Acme".to_string(),
+            slug: "acme".to_string(),
+            hit_quota: 100,
+            quota_behavior: crate::domain::QuotaBehavior::Drop,
+            logo_url: String::new(),
+            accent_color: String::new(),
+            theme_mode: None,
+            created_at: chrono::Utc::now(),
+        };
+        let id = organization.id;
+
+        let cached = cache
+            .get_or_insert_organization(id, || async { Some(organization) })
+            .await;
+        assert_eq!(cached.map(|o| o.hit_quota), Some(100));
+
+        // Served from the cache without calling the loader
+        let cached = cache
+            .get_or_insert_organization(id, || async { None })
+            .await;
+        assert_eq!(cached.map(|o| o.slug), Some("acme".to_string()));
+
+        cache.invalidate_organization(id).await;
+        assert!(cache.organizations.get(&id).await.is_none());
+    }
+
     #[tokio::test]
     async fn test_get_or_insert_origins_returns_none() {
         let settings = test_settings();
@@ -329,4 +816,26 @@ mod tests {
 
         assert!(origins.is_none());
     }
+
+    #[tokio::test]
+    async fn test_stats_count_hits_and_misses() {
+        let settings = test_settings();
+        let cache = AppCache::new(&settings);
+        let service_id = ServiceId::from_uuid(Uuid::new_v4());
+
+        for _ in 0..3 {
+            cache
+                .get_or_insert_origins(service_id, || async { Some("*".to_string()) })
+                .await;
+        }
+        cache.service_origins.run_pending_tasks().await;
+
+        let stats = cache.stats();
+        let origins = stats.iter().find(|s| s.name == "service_origins").unwrap();
+        assert_eq!((origins.entries, origins.hits, origins.misses), (1, 2, 1));
+        assert_eq!(origins.hit_rate(), Some(2.0 / 3.0));
+
+        let badges = stats.iter().find(|s| s.name == "badge_counts").unwrap();
+        assert_eq!(badges.hit_rate(), None);
+    }
 }
@@ -0,0 +1,263 @@
+//! Page inventory from sitemaps. With `sitemap_crawl_interval_secs` set, the
+//! `sitemap.xml` at the root of every active service's `link` is fetched on
+//! that schedule and its pages recorded, so reports can list the pages that
+//! get no traffic. Sitemap indexes are followed one level down, and gzipped
+//! sitemaps are unpacked.
+
+use std::io::Read;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use regex::Regex;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+use url::Url;
+
+use crate::db;
+use crate::domain::BackgroundTask;
+use crate::error::Result;
+use crate::state::AppState;
+
+/// How long each sitemap gets to download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Sitemaps are at most 50MB uncompressed, by the protocol
+const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+/// Sitemaps read from one index
+const MAX_SITEMAPS: usize = 50;
+/// Pages kept per service
+const MAX_PAGES: usize = 50_000;
+
+/// `<loc>` elements, the only part of a sitemap used
+fn loc_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?s)<(?:\w+:)?loc>\s*(.*?)\s*</(?:\w+:)?loc>").expect("loc pattern is valid")
+    })
+}
+
+/// A parsed sitemap: the pages it lists, or the sitemaps an index points to
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sitemap {
+    Pages(Vec<String>),
+    Index(Vec<String>),
+}
+
+/// Read the `<loc>`s out of a sitemap or sitemap index
+pub fn parse_sitemap(xml: &str) -> Sitemap {
+    let locs = loc_pattern()
+        .captures_iter(xml)
+        .map(|captures| unescape_xml(&captures[1]))
+        .filter(|loc| !loc.is_empty())
+        .collect();
+    if xml.contains("<sitemapindex") || xml.contains(":sitemapindex") {
+        Sitemap::Index(locs)
+    } else {
+        Sitemap::Pages(locs)
+    }
+}
+
+/// Undo the entity escaping sitemaps are required to use
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Where the sitemap of the site at `link` is
+pub fn sitemap_url(link: &str) -> Option<Url> {
+    let link = Url::parse(link.trim()).ok()?;
+    if !matches!(link.scheme(), "http" | "https") {
+        return None;
+    }
+    link.join("/sitemap.xml").ok()
+}
+
+/// The HTTP client sitemaps are fetched with
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent(concat!("shymini/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Fetch one sitemap, unpacking it if it is gzipped
+async fn fetch_sitemap(client: &reqwest::Client, url: &Url) -> std::result::Result<String, String> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_SITEMAP_BYTES)
+    {
+        return Err(format!("{} is too large", url));
+    }
+    let body = response.bytes().await.map_err(|e| e.to_string())?;
+
+    if body.starts_with(&[0x1f, 0x8b]) {
+        let mut xml = String::new();
+        GzDecoder::new(&body[..])
+            .take(MAX_SITEMAP_BYTES)
+            .read_to_string(&mut xml)
+            .map_err(|e| e.to_string())?;
+        Ok(xml)
+    } else {
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// The pages the sitemap of the site at `link` lists, on the site's own host
+pub async fn crawl(
+    client: &reqwest::Client,
+    link: &str,
+) -> std::result::Result<Vec<String>, String> {
+    let root = sitemap_url(link).ok_or_else(|| format!("{} is not an HTTP(S) URL", link))?;
+    let same_site = |loc: &str| {
+        Url::parse(loc)
+            .is_ok_and(|url| url.host_str() == root.host_str() && url.port() == root.port())
+    };
+
+    let mut pages = match parse_sitemap(&fetch_sitemap(client, &root).await?) {
+        Sitemap::Pages(pages) => pages,
+        Sitemap::Index(sitemaps) => {
+            let mut pages = Vec::new();
+            for sitemap in sitemaps
+                .iter()
+                .filter(|loc| same_site(loc))
+                .take(MAX_SITEMAPS)
+            {
+                let Ok(url) = Url::parse(sitemap) else {
+                    continue;
+                };
+                match fetch_sitemap(client, &url)
+                    .await
+                    .map(|xml| parse_sitemap(&xml))
+                {
+                    Ok(Sitemap::Pages(more)) => pages.extend(more),
+                    // Indexes may not nest
+                    Ok(Sitemap::Index(_)) => {}
+                    Err(e) => warn!("Failed to fetch sitemap {}: {}", url, e),
+                }
+            }
+            pages
+        }
+    };
+
+    pages.retain(|page| same_site(page));
+    pages.sort();
+    pages.dedup();
+    pages.truncate(MAX_PAGES);
+    Ok(pages)
+}
+
+/// Crawl every active service's sitemap once and record its pages. A service
+/// whose sitemap can't be read keeps the pages it had.
+pub async fn run_crawl(state: &AppState, client: &reqwest::Client) -> Result<()> {
+    let now = state.clock.now();
+    for service in db::list_monitored_services(&state.pool).await? {
+        match crawl(client, &service.link).await {
+            Ok(pages) => db::replace_pages(&state.pool, service.id, &pages, now).await?,
+            Err(e) => warn!("Failed to crawl the sitemap of {}: {}", service.name, e),
+        }
+    }
+    Ok(())
+}
+
+/// Crawl in the background every `sitemap_crawl_interval_secs`, if set
+pub fn spawn(state: AppState) {
+    let interval_secs = state.settings.sitemap_crawl_interval_secs;
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = client();
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = run_crawl(&state, &client).await;
+            state
+                .tasks
+                .record(BackgroundTask::Crawler, state.clock.now(), &result);
+            match result {
+                Ok(()) => info!("Sitemaps crawled"),
+                Err(e) => error!("Sitemap crawl failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sitemap() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>https://example.com/</loc><lastmod>2024-06-01</lastmod></url>
+              <url>
+                <loc>
+                  https://example.com/search?q=a&amp;page=2
+                </loc>
+              </url>
+            </urlset>"#;
+        assert_eq!(
+            parse_sitemap(xml),
+            Sitemap::Pages(vec![
+                "https://example.com/".to_string(),
+                "https://example.com/search?q=a&page=2".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_sitemap_index() {
+        let xml = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap><loc>https://example.com/sitemap-posts.xml</loc></sitemap>
+              <sitemap><loc>https://example.com/sitemap-pages.xml.gz</loc></sitemap>
+            </sitemapindex>"#;
+        assert_eq!(
+            parse_sitemap(xml),
+            Sitemap::Index(vec![
+                "https://example.com/sitemap-posts.xml".to_string(),
+                "https://example.com/sitemap-pages.xml.gz".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_sitemap_prefixed() {
+        let xml = r#"<sm:urlset xmlns:sm="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sm:url><sm:loc>https://example.com/a</sm:loc></sm:url>
+            </sm:urlset>"#;
+        assert_eq!(
+            parse_sitemap(xml),
+            Sitemap::Pages(vec!["https://example.com/a".to_string()])
+        );
+        assert_eq!(parse_sitemap("not xml"), Sitemap::Pages(Vec::new()));
+    }
+
+    #[test]
+    fn test_sitemap_url() {
+        assert_eq!(
+            sitemap_url("https://example.com/blog/post")
+                .unwrap()
+                .as_str(),
+            "https://example.com/sitemap.xml"
+        );
+        assert_eq!(
+            sitemap_url("http://localhost:3000").unwrap().as_str(),
+            "http://localhost:3000/sitemap.xml"
+        );
+        assert_eq!(sitemap_url("ftp://example.com"), None);
+        assert_eq!(sitemap_url(""), None);
+    }
+}
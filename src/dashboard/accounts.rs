@@ -0,0 +1,1497 @@
+use askama::Template;
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use tracing::error;
+
+use crate::auth::oidc::{self, PendingLogin};
+use crate::auth::{self, throttle, totp, Tenant, TokenPurpose};
+use crate::db;
+use crate::domain::{
+    ApiTokenId, CreateOrganization, HourCycle, LoginOutcome, OrganizationId, Permission, Role,
+    ServiceUsage, ThemeMode, UpdateOrganization, User, UserId, UserSettings, WeekStart,
+};
+use crate::error::Error;
+use crate::i18n::I18n;
+use crate::mailer::Email;
+use crate::state::AppState;
+use crate::theme;
+
+use super::errors::{PageResult, Path};
+use super::handlers::{parse_hit_quota, parse_quota_behavior, parse_timezone_setting};
+use super::templates::*;
+
+/// Shortest password accepted at signup
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// How long the organization picked in the switcher is remembered
+const ORG_COOKIE_DAYS: i64 = 365;
+/// Logins listed on the account page
+const ACCOUNT_LOGIN_HISTORY: i64 = 10;
+/// Name authenticator apps list two-factor secrets under
+const TOTP_ISSUER: &str = "shymini";
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    pub email: String,
+    pub password: String,
+    pub invite: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginForm {
+    pub token: String,
+    pub code: String,
+    pub invite: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpForm {
+    /// The secret being turned on; absent when turning two-factor logins off
+    pub secret: Option<String>,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserSettingsForm {
+    pub time_zone: Option<String>,
+    pub week_start: Option<String>,
+    pub hour_cycle: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteQuery {
+    pub invite: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignupForm {
+    pub name: String,
+    pub email: String,
+    pub password: String,
+    pub organization: Option<String>,
+    pub invite: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordForm {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set by the provider when the user cancelled or was refused
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrganizationForm {
+    pub name: String,
+    pub hit_quota: Option<String>,
+    pub quota_behavior: Option<String>,
+    pub logo_url: Option<String>,
+    pub accent_color: Option<String>,
+    pub theme_mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewOrganizationForm {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SwitchOrganizationForm {
+    pub organization: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailForm {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberForm {
+    pub email: String,
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleForm {
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiTokenForm {
+    pub name: String,
+    pub role: Option<String>,
+}
+
+fn render<T: Template>(status: StatusCode, template: T) -> Response {
+    match template.render() {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => {
+            error!("Template render error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+        }
+    }
+}
+
+/// Role picked in a form; anything unknown gets the least access
+fn parse_role(value: Option<&str>) -> Role {
+    value.and_then(Role::from_str).unwrap_or_default()
+}
+
+fn org_cookie(organization_id: OrganizationId) -> String {
+    auth::set_cookie(
+        auth::ORG_COOKIE,
+        &organization_id.to_string(),
+        Duration::days(ORG_COOKIE_DAYS),
+    )
+}
+
+/// Render an account email and send it
+async fn send_email<T: Template>(
+    state: &AppState,
+    to: &str,
+    subject: String,
+    body: T,
+) -> Result<(), Error> {
+    let body = body
+        .render()
+        .map_err(|e| Error::Internal(format!("email render failed: {}", e)))?;
+    state
+        .mailer
+        .send(Email {
+            to: to.to_string(),
+            subject,
+            body,
+        })
+        .await
+}
+
+/// Email the user a link that verifies their address and logs them in
+async fn send_verification(state: &AppState, i18n: I18n, user: &User) -> Result<(), Error> {
+    let subject = format!("{}:{}", user.id, user.email);
+    let token = state
+        .signing_key
+        .sign(TokenPurpose::VerifyEmail, &subject, state.clock.now());
+    let link = state
+        .settings
+        .public_link(&format!("/verify-email/{}", token));
+    send_email(
+        state,
+        &user.email,
+        i18n.t("email-verify-subject").to_string(),
+        VerifyEmailTemplate { i18n, link },
+    )
+    .await
+}
+
+/// Identifies the user's current password in reset links, so a link stops
+/// working once it has been used
+fn password_fingerprint(user: &User) -> String {
+    auth::hash_token(&user.password_hash)[..16].to_string()
+}
+
+/// The organization, role and email address of a valid invite token
+fn read_invite(state: &AppState, token: &str) -> Option<(OrganizationId, Role, String)> {
+    let subject = state
+        .signing_key
+        .verify(TokenPurpose::Invite, token, state.clock.now())?;
+    let mut parts = subject.splitn(3, ':');
+    let organization_id = parts.next()?.parse().ok()?;
+    let role = Role::from_str(parts.next()?)?;
+    Some((organization_id, role, parts.next()?.to_string()))
+}
+
+/// The organization and role an invite gives `email`, if the token is valid
+/// and was sent to that address
+fn invite_for(
+    state: &AppState,
+    token: Option<&str>,
+    email: &str,
+) -> Option<(OrganizationId, Role)> {
+    let (organization_id, role, invited) = read_invite(state, token?)?;
+    (invited == email.trim().to_lowercase()).then_some((organization_id, role))
+}
+
+async fn organization_name(state: &AppState, organization_id: OrganizationId) -> String {
+    db::get_organization(&state.pool, organization_id)
+        .await
+        .map(|o| o.name)
+        .unwrap_or_default()
+}
+
+/// Whether `/signup` accepts new accounts: always for the very first one
+async fn signup_open(state: &AppState) -> bool {
+    if state.settings.signup_enabled {
+        return true;
+    }
+    match db::count_users(&state.pool).await {
+        Ok(count) => count == 0,
+        Err(e) => {
+            error!("Error counting users: {}", e);
+            false
+        }
+    }
+}
+
+/// GET /login
+pub async fn login_form(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<InviteQuery>,
+) -> Response {
+    if !state.settings.multi_tenant {
+        return Redirect::to("/").into_response();
+    }
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let email = query
+        .invite
+        .as_deref()
+        .and_then(|token| read_invite(&state, token))
+        .map(|(_, _, email)| email)
+        .unwrap_or_default();
+    render(
+        StatusCode::OK,
+        LoginTemplate {
+            i18n,
+            email,
+            error: String::new(),
+            signup_open: signup_open(&state).await,
+            invite: query.invite.unwrap_or_default(),
+            unverified: false,
+            sso: state.oidc.is_some(),
+        },
+    )
+}
+
+/// The login page with a message
+async fn login_page(
+    state: &AppState,
+    i18n: I18n,
+    status: StatusCode,
+    email: String,
+    error: String,
+    invite: Option<String>,
+) -> Response {
+    render(
+        status,
+        LoginTemplate {
+            i18n,
+            email,
+            error,
+            signup_open: signup_open(state).await,
+            invite: invite.unwrap_or_default(),
+            unverified: false,
+            sso: state.oidc.is_some(),
+        },
+    )
+}
+
+/// The login page saying how long logins stay locked
+async fn locked_page(
+    state: &AppState,
+    i18n: I18n,
+    email: String,
+    until: DateTime<Utc>,
+    invite: Option<String>,
+) -> Response {
+    let minutes = ((until - state.clock.now()).num_seconds() + 59) / 60;
+    let error = i18n.t1("login-locked", "minutes", minutes.max(1));
+    login_page(
+        state,
+        i18n,
+        StatusCode::TOO_MANY_REQUESTS,
+        email,
+        error,
+        invite,
+    )
+    .await
+}
+
+/// The page refusing a login to `email` from `ip` while logins are locked
+async fn check_throttle(
+    state: &AppState,
+    i18n: I18n,
+    email: &str,
+    ip: Option<&str>,
+    invite: &Option<String>,
+) -> Result<Option<Response>, Error> {
+    let Some(until) = throttle::locked_until(state, email, ip).await? else {
+        return Ok(None);
+    };
+    throttle::record(state, email, ip, LoginOutcome::Locked).await?;
+    Ok(Some(
+        locked_page(state, i18n, email.to_string(), until, invite.clone()).await,
+    ))
+}
+
+/// Log the user in once every check has passed, accepting the invite if any
+async fn finish_login(
+    state: &AppState,
+    user: &User,
+    ip: Option<&str>,
+    invite: Option<&str>,
+) -> PageResult {
+    let invited = invite_for(state, invite, &user.email);
+    throttle::record(state, &user.email, ip, LoginOutcome::Success).await?;
+    if let Some((organization_id, role)) = invited {
+        db::add_membership(&state.pool, organization_id, user.id, role).await?;
+    }
+    let cookie = auth::start_login(state, user.id).await?;
+
+    let mut cookies = vec![(header::SET_COOKIE, cookie)];
+    if let Some((organization_id, _)) = invited {
+        cookies.push((header::SET_COOKIE, org_cookie(organization_id)));
+    }
+    Ok((AppendHeaders(cookies), Redirect::to("/")).into_response())
+}
+
+/// Subject of the token carrying a password login on to the two-factor step
+fn two_factor_subject(user: &User) -> String {
+    format!("{}:{}", user.id, password_fingerprint(user))
+}
+
+/// POST /login
+pub async fn login(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Form(form): Form<LoginForm>,
+) -> PageResult {
+    if !state.settings.multi_tenant {
+        return Ok(Redirect::to("/").into_response());
+    }
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let ip = auth::client_ip(&headers, connect_info.map(|ConnectInfo(addr)| addr));
+    let ip = ip.as_deref();
+    let email = form.email.trim().to_lowercase();
+    if let Some(response) = check_throttle(&state, i18n, &email, ip, &form.invite).await? {
+        return Ok(response);
+    }
+
+    let user = match db::get_user_by_email(&state.pool, &email).await {
+        Ok(user) if auth::verify_password(&form.password, &user.password_hash) => user,
+        // Unknown addresses count too, so they cannot be told apart
+        Ok(_) | Err(Error::UserNotFound) => {
+            throttle::record(&state, &email, ip, LoginOutcome::WrongPassword).await?;
+            return Ok(login_page(
+                &state,
+                i18n,
+                StatusCode::UNAUTHORIZED,
+                form.email,
+                i18n.t("login-invalid").to_string(),
+                form.invite,
+            )
+            .await);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if user.email_verified_at.is_none() {
+        return Ok(render(
+            StatusCode::FORBIDDEN,
+            LoginTemplate {
+                i18n,
+                email: user.email,
+                error: i18n.t("login-unverified").to_string(),
+                signup_open: signup_open(&state).await,
+                invite: form.invite.unwrap_or_default(),
+                unverified: true,
+                sso: state.oidc.is_some(),
+            },
+        ));
+    }
+
+    if user.totp_secret.is_some() {
+        let token = state.signing_key.sign(
+            TokenPurpose::TwoFactor,
+            &two_factor_subject(&user),
+            state.clock.now(),
+        );
+        return Ok(render(
+            StatusCode::OK,
+            TwoFactorLoginTemplate {
+                i18n,
+                token,
+                error: String::new(),
+                invite: form.invite.unwrap_or_default(),
+            },
+        ));
+    }
+
+    finish_login(&state, &user, ip, form.invite.as_deref()).await
+}
+
+/// POST /login/two-factor
+pub async fn login_two_factor(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Form(form): Form<TwoFactorLoginForm>,
+) -> PageResult {
+    if !state.settings.multi_tenant {
+        return Ok(Redirect::to("/").into_response());
+    }
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let user = async {
+        let subject =
+            state
+                .signing_key
+                .verify(TokenPurpose::TwoFactor, &form.token, state.clock.now())?;
+        let (user_id, _) = subject.split_once(':')?;
+        let user = db::get_user(&state.pool, user_id.parse().ok()?)
+            .await
+            .ok()?;
+        // A password change since the first step cancels the login
+        (two_factor_subject(&user) == subject).then_some(user)
+    }
+    .await;
+    let Some(user) = user else {
+        return Ok(login_page(
+            &state,
+            i18n,
+            StatusCode::BAD_REQUEST,
+            String::new(),
+            i18n.t("two-factor-expired").to_string(),
+            form.invite,
+        )
+        .await);
+    };
+
+    let ip = auth::client_ip(&headers, connect_info.map(|ConnectInfo(addr)| addr));
+    let ip = ip.as_deref();
+    if let Some(response) = check_throttle(&state, i18n, &user.email, ip, &form.invite).await? {
+        return Ok(response);
+    }
+
+    let valid = user
+        .totp_secret
+        .as_deref()
+        .is_some_and(|secret| totp::verify(secret, &form.code, state.clock.now()));
+    if !valid {
+        throttle::record(&state, &user.email, ip, LoginOutcome::WrongCode).await?;
+        return Ok(render(
+            StatusCode::UNAUTHORIZED,
+            TwoFactorLoginTemplate {
+                i18n,
+                token: form.token,
+                error: i18n.t("two-factor-invalid-code").to_string(),
+                invite: form.invite.unwrap_or_default(),
+            },
+        ));
+    }
+
+    finish_login(&state, &user, ip, form.invite.as_deref()).await
+}
+
+/// POST /logout
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = auth::end_login(&state, &headers).await {
+        error!("Error ending login: {}", e);
+    }
+
+    let target = if state.settings.multi_tenant {
+        "/login"
+    } else {
+        "/"
+    };
+    (
+        AppendHeaders([
+            (header::SET_COOKIE, auth::clear_cookie(auth::SESSION_COOKIE)),
+            (header::SET_COOKIE, auth::clear_cookie(auth::ORG_COOKIE)),
+        ]),
+        Redirect::to(target),
+    )
+        .into_response()
+}
+
+/// GET /signup
+pub async fn signup_form(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !state.settings.multi_tenant {
+        return Redirect::to("/").into_response();
+    }
+    if !signup_open(&state).await {
+        return Redirect::to("/login").into_response();
+    }
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let first_user = db::count_users(&state.pool).await.unwrap_or(0) == 0;
+    render(
+        StatusCode::OK,
+        SignupTemplate {
+            i18n,
+            name: String::new(),
+            email: String::new(),
+            organization_name: String::new(),
+            error: String::new(),
+            first_user,
+            invite: String::new(),
+            invite_organization: String::new(),
+        },
+    )
+}
+
+/// POST /signup
+pub async fn signup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<SignupForm>,
+) -> PageResult {
+    if !state.settings.multi_tenant {
+        return Ok(Redirect::to("/").into_response());
+    }
+    // An invite opens signup for the invited address
+    let invite = form
+        .invite
+        .as_deref()
+        .and_then(|token| read_invite(&state, token));
+    if invite.is_none() && !signup_open(&state).await {
+        return Ok(Redirect::to("/login").into_response());
+    }
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let first_user = db::count_users(&state.pool).await? == 0;
+
+    let email = form.email.trim().to_lowercase();
+    let problem = if !email.contains('@') {
+        Some("signup-invalid-email")
+    } else if invite
+        .as_ref()
+        .is_some_and(|(_, _, invited)| *invited != email)
+    {
+        Some("signup-invite-mismatch")
+    } else if form.password.chars().count() < MIN_PASSWORD_LENGTH {
+        Some("signup-password-too-short")
+    } else {
+        match db::get_user_by_email(&state.pool, &email).await {
+            Ok(_) => Some("signup-email-taken"),
+            Err(Error::UserNotFound) => None,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    if let Some(problem) = problem {
+        let invite_organization = match &invite {
+            Some((organization_id, _, _)) => organization_name(&state, *organization_id).await,
+            None => String::new(),
+        };
+        return Ok(render(
+            StatusCode::BAD_REQUEST,
+            SignupTemplate {
+                i18n,
+                name: form.name,
+                email: form.email,
+                organization_name: form.organization.unwrap_or_default(),
+                error: i18n.t(problem).to_string(),
+                first_user,
+                invite: form.invite.unwrap_or_default(),
+                invite_organization,
+            },
+        ));
+    }
+
+    // The invite link already proved the address; the first account is the
+    // operator setting up, who may have no mail server yet
+    let email_verified = first_user || invite.is_some();
+    let password_hash = auth::hash_password(&form.password)?;
+    let user = db::create_user(
+        &state.pool,
+        &email,
+        form.name.trim(),
+        &password_hash,
+        email_verified,
+    )
+    .await?;
+
+    let (organization_id, role) = if let Some((organization_id, role, _)) = invite {
+        (organization_id, role)
+    } else if first_user {
+        (OrganizationId::DEFAULT, Role::Owner)
+    } else {
+        let name = form
+            .organization
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .unwrap_or(&email)
+            .to_string();
+        let input = CreateOrganization {
+            name,
+            ..Default::default()
+        };
+        (
+            db::create_organization(&state.pool, input).await?.id,
+            Role::Owner,
+        )
+    };
+    db::add_membership(&state.pool, organization_id, user.id, role).await?;
+
+    if !email_verified {
+        send_verification(&state, i18n, &user).await?;
+        return Ok(render(
+            StatusCode::OK,
+            CheckEmailTemplate {
+                i18n,
+                message: i18n.t("check-email-verify").to_string(),
+            },
+        ));
+    }
+    let cookie = auth::start_login(&state, user.id).await?;
+    Ok((
+        AppendHeaders([
+            (header::SET_COOKIE, cookie),
+            (header::SET_COOKIE, org_cookie(organization_id)),
+        ]),
+        Redirect::to("/"),
+    )
+        .into_response())
+}
+
+/// GET /verify-email/:token
+pub async fn verify_email(State(state): State<AppState>, Path(token): Path<String>) -> PageResult {
+    let user = async {
+        let subject =
+            state
+                .signing_key
+                .verify(TokenPurpose::VerifyEmail, &token, state.clock.now())?;
+        let (user_id, email) = subject.split_once(':')?;
+        let user = db::get_user(&state.pool, user_id.parse().ok()?)
+            .await
+            .ok()?;
+        // The link is only good for the address it was sent to
+        (user.email == email).then_some(user)
+    }
+    .await;
+    let Some(user) = user else {
+        return Err(Error::BadRequest("This link is invalid or has expired".to_string()).into());
+    };
+
+    db::verify_user_email(&state.pool, user.id).await?;
+    let cookie = auth::start_login(&state, user.id).await?;
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, cookie)]),
+        Redirect::to("/"),
+    )
+        .into_response())
+}
+
+/// POST /verify-email
+pub async fn verify_email_resend(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<EmailForm>,
+) -> Response {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    // Same answer whether or not the account exists
+    match db::get_user_by_email(&state.pool, &form.email).await {
+        Ok(user) if user.email_verified_at.is_none() => {
+            if let Err(e) = send_verification(&state, i18n, &user).await {
+                error!("Error sending verification email: {}", e);
+            }
+        }
+        Ok(_) | Err(Error::UserNotFound) => {}
+        Err(e) => error!("Error fetching user: {}", e),
+    }
+    render(
+        StatusCode::OK,
+        CheckEmailTemplate {
+            i18n,
+            message: i18n.t("check-email-verify").to_string(),
+        },
+    )
+}
+
+/// The user a password reset token is for, if the token is valid and the
+/// password has not changed since it was sent
+async fn reset_user(state: &AppState, token: &str) -> Option<User> {
+    let subject =
+        state
+            .signing_key
+            .verify(TokenPurpose::PasswordReset, token, state.clock.now())?;
+    let (user_id, fingerprint) = subject.split_once(':')?;
+    let user = db::get_user(&state.pool, user_id.parse().ok()?)
+        .await
+        .ok()?;
+    (password_fingerprint(&user) == fingerprint).then_some(user)
+}
+
+fn password_reset_page(i18n: I18n, status: StatusCode, token: String, error: &str) -> Response {
+    render(
+        status,
+        PasswordResetTemplate {
+            i18n,
+            token,
+            error: if error.is_empty() {
+                String::new()
+            } else {
+                i18n.t(error).to_string()
+            },
+        },
+    )
+}
+
+/// GET /password-reset
+pub async fn password_reset_form(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    password_reset_page(i18n, StatusCode::OK, String::new(), "")
+}
+
+/// POST /password-reset
+pub async fn password_reset_request(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<EmailForm>,
+) -> Response {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    // Same answer whether or not the account exists
+    match db::get_user_by_email(&state.pool, &form.email).await {
+        Ok(user) => {
+            let subject = format!("{}:{}", user.id, password_fingerprint(&user));
+            let token =
+                state
+                    .signing_key
+                    .sign(TokenPurpose::PasswordReset, &subject, state.clock.now());
+            let link = state
+                .settings
+                .public_link(&format!("/password-reset/{}", token));
+            let sent = send_email(
+                &state,
+                &user.email,
+                i18n.t("email-password-reset-subject").to_string(),
+                PasswordResetEmailTemplate { i18n, link },
+            )
+            .await;
+            if let Err(e) = sent {
+                error!("Error sending password reset email: {}", e);
+            }
+        }
+        Err(Error::UserNotFound) => {}
+        Err(e) => error!("Error fetching user: {}", e),
+    }
+    render(
+        StatusCode::OK,
+        CheckEmailTemplate {
+            i18n,
+            message: i18n.t("check-email-password-reset").to_string(),
+        },
+    )
+}
+
+/// GET /password-reset/:token
+pub async fn password_reset_confirm_form(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> Response {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    match reset_user(&state, &token).await {
+        Some(_) => password_reset_page(i18n, StatusCode::OK, token, ""),
+        None => password_reset_page(
+            i18n,
+            StatusCode::BAD_REQUEST,
+            String::new(),
+            "password-reset-invalid",
+        ),
+    }
+}
+
+/// POST /password-reset/:token
+pub async fn password_reset_confirm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Form(form): Form<PasswordForm>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let Some(user) = reset_user(&state, &token).await else {
+        return Ok(password_reset_page(
+            i18n,
+            StatusCode::BAD_REQUEST,
+            String::new(),
+            "password-reset-invalid",
+        ));
+    };
+    if form.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Ok(password_reset_page(
+            i18n,
+            StatusCode::BAD_REQUEST,
+            token,
+            "signup-password-too-short",
+        ));
+    }
+
+    let password_hash = auth::hash_password(&form.password)?;
+    db::set_user_password(&state.pool, user.id, &password_hash).await?;
+    // Whoever knew the old password is logged out everywhere
+    db::delete_user_login_sessions(&state.pool, user.id).await?;
+    // Receiving the link proved the address
+    db::verify_user_email(&state.pool, user.id).await?;
+    Ok(Redirect::to("/login").into_response())
+}
+
+/// GET /invite/:token
+pub async fn invite_accept(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> PageResult {
+    if !state.settings.multi_tenant {
+        return Ok(Redirect::to("/").into_response());
+    }
+    let Some((organization_id, role, email)) = read_invite(&state, &token) else {
+        return Err(
+            Error::BadRequest("This invitation is invalid or has expired".to_string()).into(),
+        );
+    };
+
+    // Already logged in as the invited user: join right away
+    if let Some(user) = auth::current_user(&state, &headers).await {
+        if user.email == email {
+            db::add_membership(&state.pool, organization_id, user.id, role).await?;
+            return Ok((
+                AppendHeaders([(header::SET_COOKIE, org_cookie(organization_id))]),
+                Redirect::to("/"),
+            )
+                .into_response());
+        }
+    }
+
+    match db::get_user_by_email(&state.pool, &email).await {
+        Ok(_) => Ok(Redirect::to(&format!("/login?invite={}", token)).into_response()),
+        Err(Error::UserNotFound) => {
+            let i18n = I18n::from_headers(&headers, &state.settings.locale);
+            Ok(render(
+                StatusCode::OK,
+                SignupTemplate {
+                    i18n,
+                    name: String::new(),
+                    email,
+                    organization_name: String::new(),
+                    error: String::new(),
+                    first_user: false,
+                    invite: token,
+                    invite_organization: organization_name(&state, organization_id).await,
+                },
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// GET /auth/oidc/login
+pub async fn oidc_login(State(state): State<AppState>) -> Response {
+    let Some(client) = state.oidc.clone().filter(|_| state.settings.multi_tenant) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let login = PendingLogin::new();
+    match client.authorize_url(&login).await {
+        Ok(url) => {
+            let purpose = TokenPurpose::OidcLogin;
+            let token = state
+                .signing_key
+                .sign(purpose, &login.encode(), state.clock.now());
+            (
+                AppendHeaders([(
+                    header::SET_COOKIE,
+                    auth::set_cookie(oidc::LOGIN_COOKIE, &token, purpose.lifetime()),
+                )]),
+                Redirect::to(&url),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Error starting single sign-on: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                "The single sign-on provider is unavailable",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// GET /auth/oidc/callback
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<OidcCallbackQuery>,
+) -> PageResult {
+    let Some(client) = state.oidc.clone().filter(|_| state.settings.multi_tenant) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let login = auth::cookie(&headers, oidc::LOGIN_COOKIE)
+        .and_then(|token| {
+            state
+                .signing_key
+                .verify(TokenPurpose::OidcLogin, token, state.clock.now())
+        })
+        .and_then(|subject| PendingLogin::decode(&subject))
+        .filter(|login| query.state.as_deref() == Some(login.state.as_str()));
+    let (Some(login), Some(code)) = (login, query.code) else {
+        if let Some(e) = query.error {
+            error!("Single sign-on refused: {}", e);
+        }
+        return Err(Error::BadRequest("This login is invalid or has expired".to_string()).into());
+    };
+
+    let claims = match client.exchange(&code, &login).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            error!("Error finishing single sign-on: {}", e);
+            return Ok((StatusCode::BAD_GATEWAY, "Single sign-on failed").into_response());
+        }
+    };
+    let email = claims
+        .email
+        .as_deref()
+        .map(|e| e.trim().to_lowercase())
+        // Accounts are matched by email, so only an address the provider
+        // vouches for will do
+        .filter(|e| e.contains('@') && claims.email_verified == Some(true));
+    let Some(email) = email else {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "The single sign-on provider did not share a verified email address",
+        )
+            .into_response());
+    };
+    let groups = client
+        .groups_claim
+        .as_deref()
+        .map(|claim| claims.groups(claim))
+        .unwrap_or_default();
+
+    let user = auth::provision_user(&state, &email, &claims.display_name(), &groups).await?;
+    let cookie = auth::start_login(&state, user.id).await?;
+    Ok((
+        AppendHeaders([
+            (header::SET_COOKIE, cookie),
+            (header::SET_COOKIE, auth::clear_cookie(oidc::LOGIN_COOKIE)),
+        ]),
+        Redirect::to("/"),
+    )
+        .into_response())
+}
+
+/// Render the account page, optionally with an error or a confirmation
+/// (message keys, empty for none). Unless two-factor logins are on, it
+/// offers `secret`, or a new one, to turn them on with.
+async fn account_page(
+    state: &AppState,
+    headers: &HeaderMap,
+    status: StatusCode,
+    user_id: UserId,
+    secret: Option<String>,
+    error: &str,
+    notice: &str,
+) -> PageResult {
+    let i18n = I18n::from_headers(headers, &state.settings.locale);
+    let user = db::get_user(&state.pool, user_id).await?;
+    let attempts = db::list_login_attempts(&state.pool, &user.email, ACCOUNT_LOGIN_HISTORY).await?;
+    let settings = db::get_user_settings(&state.pool, user_id).await?;
+
+    let totp_secret = if user.totp_secret.is_some() {
+        String::new()
+    } else {
+        secret.unwrap_or_else(totp::generate_secret)
+    };
+    let totp_uri = if totp_secret.is_empty() {
+        String::new()
+    } else {
+        totp::provisioning_uri(&totp_secret, &user.email, TOTP_ISSUER)
+    };
+
+    Ok(render(
+        status,
+        AccountTemplate {
+            error: if error.is_empty() {
+                String::new()
+            } else {
+                i18n.t(error).to_string()
+            },
+            notice: if notice.is_empty() {
+                String::new()
+            } else {
+                i18n.t(notice).to_string()
+            },
+            i18n,
+            user,
+            totp_secret,
+            totp_uri,
+            attempts,
+            settings,
+        },
+    ))
+}
+
+/// GET /account
+pub async fn account(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> PageResult {
+    let Some(user) = tenant.user else {
+        return Ok(Redirect::to("/").into_response());
+    };
+    account_page(&state, &headers, StatusCode::OK, user.id, None, "", "").await
+}
+
+/// POST /account/settings
+pub async fn account_settings(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Form(form): Form<UserSettingsForm>,
+) -> PageResult {
+    let Some(user) = tenant.user else {
+        return Ok(Redirect::to("/").into_response());
+    };
+    let settings = UserSettings {
+        time_zone: parse_timezone_setting(form.time_zone.as_deref()),
+        week_start: form.week_start.as_deref().and_then(WeekStart::from_str),
+        hour_cycle: form.hour_cycle.as_deref().and_then(HourCycle::from_str),
+    };
+    db::save_user_settings(&state.pool, user.id, &settings).await?;
+    account_page(
+        &state,
+        &headers,
+        StatusCode::OK,
+        user.id,
+        None,
+        "",
+        "account-settings-saved",
+    )
+    .await
+}
+
+/// POST /account/two-factor
+pub async fn two_factor_enable(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Form(form): Form<TotpForm>,
+) -> PageResult {
+    let Some(user) = tenant.user else {
+        return Ok(Redirect::to("/").into_response());
+    };
+    let secret = form
+        .secret
+        .map(|s| s.trim().to_uppercase())
+        .filter(|s| totp::is_valid_secret(s));
+    let Some(secret) = secret else {
+        return account_page(
+            &state,
+            &headers,
+            StatusCode::BAD_REQUEST,
+            user.id,
+            None,
+            "two-factor-invalid-code",
+            "",
+        )
+        .await;
+    };
+    if user.totp_secret.is_some() {
+        return account_page(&state, &headers, StatusCode::OK, user.id, None, "", "").await;
+    }
+    // Turning it on takes a code, proving the app was set up right
+    if !totp::verify(&secret, &form.code, state.clock.now()) {
+        return account_page(
+            &state,
+            &headers,
+            StatusCode::BAD_REQUEST,
+            user.id,
+            Some(secret),
+            "two-factor-invalid-code",
+            "",
+        )
+        .await;
+    }
+
+    db::set_user_totp_secret(&state.pool, user.id, Some(&secret)).await?;
+    account_page(
+        &state,
+        &headers,
+        StatusCode::OK,
+        user.id,
+        None,
+        "",
+        "two-factor-enabled",
+    )
+    .await
+}
+
+/// POST /account/two-factor/disable
+pub async fn two_factor_disable(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Form(form): Form<TotpForm>,
+) -> PageResult {
+    let Some(user) = tenant.user else {
+        return Ok(Redirect::to("/").into_response());
+    };
+    let valid = user
+        .totp_secret
+        .as_deref()
+        .is_some_and(|secret| totp::verify(secret, &form.code, state.clock.now()));
+    if !valid {
+        return account_page(
+            &state,
+            &headers,
+            StatusCode::BAD_REQUEST,
+            user.id,
+            None,
+            "two-factor-invalid-code",
+            "",
+        )
+        .await;
+    }
+
+    db::set_user_totp_secret(&state.pool, user.id, None).await?;
+    account_page(
+        &state,
+        &headers,
+        StatusCode::OK,
+        user.id,
+        None,
+        "",
+        "two-factor-disabled",
+    )
+    .await
+}
+
+/// GET /organizations/switcher (HTMX partial)
+pub async fn org_switcher(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> Response {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    render(
+        StatusCode::OK,
+        OrgSwitcherTemplate {
+            i18n,
+            can_manage: tenant.role.allows(Permission::ManageOrganization),
+            organization: tenant.organization,
+            organizations: tenant.organizations,
+            user: tenant.user,
+        },
+    )
+}
+
+/// POST /organizations/switch
+pub async fn org_switch(tenant: Tenant, Form(form): Form<SwitchOrganizationForm>) -> Response {
+    let organization = form
+        .organization
+        .parse::<OrganizationId>()
+        .ok()
+        .and_then(|id| tenant.organizations.iter().find(|o| o.id == id));
+
+    match organization {
+        Some(organization) => (
+            AppendHeaders([(header::SET_COOKIE, org_cookie(organization.id))]),
+            Redirect::to("/"),
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "Organization not found").into_response(),
+    }
+}
+
+/// POST /organizations
+pub async fn org_create(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Form(form): Form<NewOrganizationForm>,
+) -> PageResult {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest("Organization name is required".to_string()).into());
+    }
+
+    let input = CreateOrganization {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let organization = db::create_organization(&state.pool, input).await?;
+    if let Some(user) = &tenant.user {
+        db::add_membership(&state.pool, organization.id, user.id, Role::Owner).await?;
+    }
+    Ok((
+        AppendHeaders([(header::SET_COOKIE, org_cookie(organization.id))]),
+        Redirect::to("/organization"),
+    )
+        .into_response())
+}
+
+/// Render the organization settings page, optionally with a freshly created
+/// API token, an error or a confirmation (message keys, empty for none)
+async fn organization_page(
+    state: &AppState,
+    tenant: Tenant,
+    headers: &HeaderMap,
+    status: StatusCode,
+    new_token: String,
+    error: &str,
+    notice: &str,
+) -> PageResult {
+    let i18n = I18n::from_headers(headers, &state.settings.locale);
+    let organization_id = tenant.organization.id;
+    let month = ServiceUsage::month_of(state.clock.now());
+
+    let usage = db::get_organization_usage(&state.pool, organization_id, &month).await?;
+    let members = if state.settings.multi_tenant {
+        db::list_members(&state.pool, organization_id).await?
+    } else {
+        Vec::new()
+    };
+    let tokens = db::list_api_tokens(&state.pool, organization_id).await?;
+
+    Ok(render(
+        status,
+        OrganizationTemplate {
+            error: if error.is_empty() {
+                String::new()
+            } else {
+                i18n.t(error).to_string()
+            },
+            notice: if notice.is_empty() {
+                String::new()
+            } else {
+                i18n.t(notice).to_string()
+            },
+            i18n,
+            organization: tenant.organization,
+            usage,
+            members,
+            tokens,
+            new_token,
+            multi_tenant: state.settings.multi_tenant,
+            user: tenant.user,
+        },
+    ))
+}
+
+/// GET /organization
+pub async fn organization_settings(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+    organization_page(
+        &state,
+        tenant,
+        &headers,
+        StatusCode::OK,
+        String::new(),
+        "",
+        "",
+    )
+    .await
+}
+
+/// POST /organization
+pub async fn organization_update(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Form(form): Form<OrganizationForm>,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+    let name = form.name.trim();
+    // Empty goes back to the install's theme; anything else must be usable
+    let logo_url = form.logo_url.as_deref().unwrap_or_default().trim();
+    let logo_url = if logo_url.is_empty() {
+        Some(String::new())
+    } else {
+        theme::parse_logo_url(logo_url)
+    };
+    let accent_color = form.accent_color.as_deref().unwrap_or_default().trim();
+    let accent_color = if accent_color.is_empty() {
+        Some(String::new())
+    } else {
+        theme::parse_color(accent_color)
+    };
+    let (Some(logo_url), Some(accent_color)) = (logo_url, accent_color) else {
+        return organization_page(
+            &state,
+            tenant,
+            &headers,
+            StatusCode::BAD_REQUEST,
+            String::new(),
+            "organization-theme-invalid",
+            "",
+        )
+        .await;
+    };
+    let input = UpdateOrganization {
+        name: Some(name.to_string()).filter(|n| !n.is_empty()),
+        hit_quota: Some(parse_hit_quota(form.hit_quota.as_deref())),
+        quota_behavior: Some(parse_quota_behavior(form.quota_behavior.as_deref())),
+        logo_url: Some(logo_url),
+        accent_color: Some(accent_color),
+        theme_mode: Some(form.theme_mode.as_deref().and_then(ThemeMode::from_str)),
+    };
+
+    let organization = db::update_organization(&state.pool, tenant.organization.id, input).await?;
+    state.cache.invalidate_organization(organization.id).await;
+    Ok(Redirect::to("/organization").into_response())
+}
+
+/// POST /organization/members
+pub async fn member_add(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Form(form): Form<MemberForm>,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+    let role = parse_role(form.role.as_deref());
+    let user = match db::get_user_by_email(&state.pool, &form.email).await {
+        Ok(user) => user,
+        Err(Error::UserNotFound) => {
+            return member_invite(&state, tenant, &headers, &form.email, role).await
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    db::add_membership(&state.pool, tenant.organization.id, user.id, role).await?;
+    Ok(Redirect::to("/organization").into_response())
+}
+
+/// Email an invite to someone without an account
+async fn member_invite(
+    state: &AppState,
+    tenant: Tenant,
+    headers: &HeaderMap,
+    email: &str,
+    role: Role,
+) -> PageResult {
+    let email = email.trim().to_lowercase();
+    if !email.contains('@') {
+        return organization_page(
+            state,
+            tenant,
+            headers,
+            StatusCode::BAD_REQUEST,
+            String::new(),
+            "signup-invalid-email",
+            "",
+        )
+        .await;
+    }
+
+    let i18n = I18n::from_headers(headers, &state.settings.locale);
+    let organization = &tenant.organization;
+    let subject = format!("{}:{}:{}", organization.id, role.as_str(), email);
+    let token = state
+        .signing_key
+        .sign(TokenPurpose::Invite, &subject, state.clock.now());
+    let sent = send_email(
+        state,
+        &email,
+        i18n.t1("email-invite-subject", "name", &organization.name),
+        InviteEmailTemplate {
+            i18n,
+            organization_name: organization.name.clone(),
+            link: state.settings.public_link(&format!("/invite/{}", token)),
+        },
+    )
+    .await;
+
+    let (status, error, notice) = match sent {
+        Ok(()) => (StatusCode::OK, "", "organization-invite-sent"),
+        Err(e) => {
+            error!("Error sending invite: {}", e);
+            (StatusCode::BAD_GATEWAY, "mail-send-failed", "")
+        }
+    };
+    organization_page(state, tenant, headers, status, String::new(), error, notice).await
+}
+
+/// POST /organization/members/:user_id/delete
+pub async fn member_remove(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(user_id): Path<UserId>,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+
+    // Leaving would lock the user out of the page they are on
+    if tenant.user.as_ref().is_some_and(|u| u.id == user_id) {
+        return Err(Error::BadRequest("You cannot remove yourself".to_string()).into());
+    }
+
+    db::remove_membership(&state.pool, tenant.organization.id, user_id).await?;
+    Ok(Redirect::to("/organization").into_response())
+}
+
+/// POST /organization/members/:user_id/role
+pub async fn member_role(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(user_id): Path<UserId>,
+    Form(form): Form<RoleForm>,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+
+    // Keeps at least one owner: the one making the change
+    if tenant.user.as_ref().is_some_and(|u| u.id == user_id) {
+        return Err(Error::BadRequest("You cannot change your own role".to_string()).into());
+    }
+
+    let role = parse_role(form.role.as_deref());
+    db::set_membership_role(&state.pool, tenant.organization.id, user_id, role).await?;
+    Ok(Redirect::to("/organization").into_response())
+}
+
+/// POST /organization/tokens
+pub async fn api_token_create(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Form(form): Form<ApiTokenForm>,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest("Token name is required".to_string()).into());
+    }
+
+    let token = auth::generate_token(auth::API_TOKEN_PREFIX);
+    db::create_api_token(
+        &state.pool,
+        tenant.organization.id,
+        name,
+        &auth::hash_token(&token),
+        parse_role(form.role.as_deref()),
+        tenant.user.as_ref().map(|user| user.id),
+    )
+    .await?;
+    organization_page(&state, tenant, &headers, StatusCode::OK, token, "", "").await
+}
+
+/// POST /organization/tokens/:token_id/delete
+pub async fn api_token_delete(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(token_id): Path<ApiTokenId>,
+) -> PageResult {
+    tenant.authorize(Permission::ManageOrganization)?;
+
+    db::delete_api_token(&state.pool, tenant.organization.id, token_id).await?;
+    Ok(Redirect::to("/organization").into_response())
+}
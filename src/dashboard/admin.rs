@@ -0,0 +1,81 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{Html, IntoResponse, Redirect},
+};
+
+use crate::auth::Tenant;
+use crate::domain::MaintenanceTask;
+use crate::i18n::I18n;
+use crate::maintenance;
+use crate::state::AppState;
+use crate::status;
+
+use super::errors::PageResult;
+use super::templates::*;
+
+/// GET /admin/status
+pub async fn admin_status(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> PageResult {
+    tenant.authorize_admin()?;
+
+    let status = status::system_status(&state).await?;
+    let maintenance = MaintenanceTask::ALL
+        .into_iter()
+        .map(|task| {
+            let run = status.maintenance.iter().find(|run| run.task == task);
+            (task, run.cloned())
+        })
+        .collect();
+
+    let template = AdminStatusTemplate {
+        i18n: I18n::from_headers(&headers, &state.settings.locale),
+        maintenance_hour: state
+            .settings
+            .maintenance_hour
+            .map(|hour| format!("{:02}:00 UTC", hour))
+            .unwrap_or_default(),
+        maintenance,
+        database_size: filesize(status.database_bytes),
+        uptime: naturaldelta(Some(status.uptime_secs as f64)),
+        status,
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+/// GET /admin/update-banner (HTMX partial)
+///
+/// A note on every dashboard page when a newer release is out; empty for
+/// anyone but admins, and while the update check is off or finds nothing
+pub async fn update_banner(
+    State(state): State<AppState>,
+    tenant: Option<Tenant>,
+    headers: HeaderMap,
+) -> PageResult {
+    let release = state.updates.available();
+    let is_admin = tenant.is_some_and(|tenant| tenant.authorize_admin().is_ok());
+    let Some(release) = release.filter(|_| is_admin) else {
+        return Ok(Html(String::new()).into_response());
+    };
+
+    let template = UpdateBannerTemplate {
+        i18n: I18n::from_headers(&headers, &state.settings.locale),
+        release,
+    };
+    Ok(Html(template.render()?).into_response())
+}
+
+/// POST /admin/maintenance
+///
+/// Run the maintenance now instead of waiting for its hour
+pub async fn maintenance_run(State(state): State<AppState>, tenant: Tenant) -> PageResult {
+    tenant.authorize_admin()?;
+
+    maintenance::run_maintenance(&state).await;
+    Ok(Redirect::to("/admin/status").into_response())
+}
@@ -0,0 +1,110 @@
+//! Error pages. Dashboard handlers fail with a [`PageError`], which only
+//! notes the error on its response: the page is rendered by
+//! [`render_error_pages`], which knows the language the request asked for.
+
+use askama::Template;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::error::Error;
+use crate::extract::{self, FromPathParams};
+use crate::i18n::I18n;
+use crate::state::AppState;
+
+use super::templates::ErrorTemplate;
+
+/// An error as a dashboard page
+#[derive(Debug)]
+pub struct PageError(pub Error);
+
+/// What dashboard handlers return
+pub type PageResult = std::result::Result<Response, PageError>;
+
+impl<E: Into<Error>> From<E> for PageError {
+    fn from(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+/// Typed path parameters of a dashboard route; a malformed one gets the
+/// 400 page
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: FromPathParams,
+    S: Send + Sync,
+{
+    type Rejection = PageError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(extract::path_params(parts, state).await?))
+    }
+}
+
+/// The error a response is to show as a page
+#[derive(Debug, Clone)]
+struct ErrorPage {
+    status: StatusCode,
+    detail: String,
+}
+
+impl IntoResponse for PageError {
+    fn into_response(self) -> Response {
+        let page = ErrorPage {
+            status: self.0.status(),
+            detail: if self.0.status().is_client_error() {
+                self.0.public_message()
+            } else {
+                String::new()
+            },
+        };
+        let mut response = self.0.into_response();
+        response.extensions_mut().insert(page);
+        response
+    }
+}
+
+/// Render the page of a failed dashboard response, in the request's
+/// language. Other responses pass through untouched.
+pub async fn render_error_pages(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let i18n = I18n::from_headers(request.headers(), &state.settings.locale);
+    let response = next.run(request).await;
+    let Some(page) = response.extensions().get::<ErrorPage>().cloned() else {
+        return response;
+    };
+
+    let status_key = match page.status.as_u16() {
+        400 => "400",
+        401 => "401",
+        403 => "403",
+        404 => "404",
+        _ if page.status.is_client_error() => "400",
+        _ => "500",
+    };
+    let template = ErrorTemplate {
+        i18n,
+        status: page.status.as_u16(),
+        status_key,
+        detail: page.detail,
+    };
+    match template.render() {
+        Ok(html) => (page.status, Html(html)).into_response(),
+        Err(e) => {
+            error!("Template render error: {}", e);
+            response
+        }
+    }
+}
@@ -1,27 +1,52 @@
 use askama::Template;
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Redirect, Response},
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect},
     Form,
 };
 use chrono::{Duration, TimeZone, Utc};
 use chrono_tz::Tz;
 use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use tracing::error;
 
+use crate::auth::Tenant;
 use crate::db;
-use crate::domain::{CreateService, ServiceId, SessionId, UpdateService};
+use crate::domain::{
+    BounceRule, BreakdownFilters, CreateSavedView, CreateSegment, CreateService, CreateTrackedLink,
+    DailyTrend, DashboardPanel, DateRangePreset, Environment, LinkId, PanelLayout, Permission,
+    QuotaBehavior, SavedView, SavedViewId, Segment, SegmentCondition, SegmentField, SegmentId,
+    SegmentOp, Service, ServiceId, SessionId, TrackingId, UpdateService, UserSettings, WeekStart,
+    MAX_SEGMENT_CONDITIONS,
+};
+use crate::embed;
 use crate::error::Error;
+use crate::geo::countries;
+use crate::i18n::I18n;
+use crate::ingress::{self, IngestScript};
+use crate::install;
+use crate::milestones;
+use crate::monitor;
+use crate::privacy;
 use crate::state::AppState;
 
+use super::errors::{PageResult, Path};
 use super::templates::*;
 
 const PAGE_SIZE: i64 = 50;
 const RESULTS_LIMIT: i64 = 300;
-
-#[derive(Debug, Deserialize)]
+/// Months of history shown in the usage panel, including the current one
+const USAGE_MONTHS: u32 = 6;
+/// Days of sessions and hits in the index's sparklines
+const TREND_DAYS: usize = 7;
+/// Results of each kind a search shows
+const SEARCH_LIMIT: i64 = 20;
+/// Days of hits searched for page paths and referrers
+const SEARCH_DAYS: i64 = 30;
+
+#[derive(Debug, Default, Deserialize)]
 pub struct DateRangeQuery {
     #[serde(rename = "startDate")]
     pub start_date: Option<String>,
@@ -31,6 +56,19 @@ pub struct DateRangeQuery {
     pub url_pattern: Option<String>,
     /// Timezone for interpreting dates and displaying results (e.g., "America/New_York")
     pub tz: Option<String>,
+    /// Preset range ending now (e.g. "7d") for when no dates are given
+    pub range: Option<String>,
+    /// Saved view to apply (fills in whatever the query leaves unset)
+    pub view: Option<String>,
+    /// Comma-separated panel keys to show, in order
+    pub layout: Option<String>,
+    /// Environment to show ("all" for every one); production when unset
+    pub env: Option<String>,
+    /// Saved segment to narrow the stats to
+    pub segment_id: Option<String>,
+    /// Breakdown rows clicked to narrow the stats to
+    #[serde(flatten)]
+    pub filters: BreakdownFilters,
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,6 +82,10 @@ pub struct PaginationQuery {
     pub url_pattern: Option<String>,
     /// Timezone for interpreting dates and displaying results (e.g., "America/New_York")
     pub tz: Option<String>,
+    /// Environment to show ("all" for every one); production when unset
+    pub env: Option<String>,
+    /// Saved segment to narrow the stats to
+    pub segment_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,18 +94,193 @@ pub struct ServiceForm {
     pub link: Option<String>,
     pub origins: Option<String>,
     pub respect_dnt: Option<String>,
+    pub respect_gpc: Option<String>,
     pub ignore_robots: Option<String>,
     pub collect_ips: Option<String>,
     pub ignored_ips: Option<String>,
+    pub allowed_countries: Option<String>,
+    pub blocked_countries: Option<String>,
+    pub allowed_asns: Option<String>,
+    pub blocked_asns: Option<String>,
     pub hide_referrer_regex: Option<String>,
     pub script_inject: Option<String>,
+    pub collapse_tabs: Option<String>,
+    pub hit_quota: Option<String>,
+    pub quota_behavior: Option<String>,
+    pub heartbeat_frequency_ms: Option<String>,
+    pub idle_timeout_mins: Option<String>,
+    pub public_badge: Option<String>,
+    pub content_groups: Option<String>,
+    pub active_user_timeout_ms: Option<String>,
+    pub lowercase_paths: Option<String>,
+    pub strip_trailing_slash: Option<String>,
+    pub path_patterns: Option<String>,
+    pub time_zone: Option<String>,
+    pub default_range: Option<String>,
+    pub signed_pixel_ids: Option<String>,
+    pub bounce_rule: Option<String>,
+    pub bounce_threshold_secs: Option<String>,
+    pub session_timeout_mins: Option<String>,
+    pub week_start: Option<String>,
+    pub debug_log: Option<String>,
+    pub link_users: Option<String>,
+    pub hide_internal_referrers: Option<String>,
+    pub track_errors: Option<String>,
+}
+
+impl ServiceForm {
+    fn hit_quota(&self) -> i64 {
+        parse_hit_quota(self.hit_quota.as_deref())
+    }
+
+    fn quota_behavior(&self) -> QuotaBehavior {
+        parse_quota_behavior(self.quota_behavior.as_deref())
+    }
+
+    /// Blank or invalid input means the server default; anything else is
+    /// raised to the minimum interval
+    fn heartbeat_frequency_ms(&self) -> i64 {
+        match self
+            .heartbeat_frequency_ms
+            .as_deref()
+            .and_then(|f| f.trim().parse::<i64>().ok())
+        {
+            Some(ms) if ms > 0 => ms.max(Service::MIN_HEARTBEAT_FREQUENCY_MS),
+            _ => 0,
+        }
+    }
+
+    /// Blank or invalid input means the default; negative means never
+    fn idle_timeout_mins(&self) -> i64 {
+        self.idle_timeout_mins
+            .as_deref()
+            .and_then(|m| m.trim().parse::<i64>().ok())
+            .unwrap_or(Service::DEFAULT_IDLE_TIMEOUT_MINS)
+            .max(0)
+    }
+
+    /// Blank, invalid or non-positive input means twice the heartbeat interval
+    fn active_user_timeout_ms(&self) -> i64 {
+        self.active_user_timeout_ms
+            .as_deref()
+            .and_then(|t| t.trim().parse::<i64>().ok())
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    /// Unknown input means the default range
+    fn default_range(&self) -> DateRangePreset {
+        self.default_range
+            .as_deref()
+            .and_then(DateRangePreset::from_str)
+            .unwrap_or_default()
+    }
+
+    /// Unknown input means every single-page session is a bounce
+    fn bounce_rule(&self) -> BounceRule {
+        self.bounce_rule
+            .as_deref()
+            .and_then(BounceRule::from_str)
+            .unwrap_or_default()
+    }
+
+    /// Blank or invalid input means the default threshold
+    fn bounce_threshold_secs(&self) -> i64 {
+        self.bounce_threshold_secs
+            .as_deref()
+            .and_then(|t| t.trim().parse::<i64>().ok())
+            .unwrap_or(Service::DEFAULT_BOUNCE_THRESHOLD_SECS)
+            .max(0)
+    }
+
+    /// Blank, invalid or non-positive input means no timeout
+    fn session_timeout_mins(&self) -> i64 {
+        self.session_timeout_mins
+            .as_deref()
+            .and_then(|m| m.trim().parse::<i64>().ok())
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    /// Blank or unknown input leaves weeks to the viewer's language
+    fn week_start(&self) -> Option<WeekStart> {
+        self.week_start.as_deref().and_then(WeekStart::from_str)
+    }
+}
+
+/// Monthly hit quota from a form; blank or invalid input means unlimited
+pub(super) fn parse_hit_quota(value: Option<&str>) -> i64 {
+    value
+        .and_then(|q| q.trim().parse::<i64>().ok())
+        .unwrap_or(0)
+        .max(0)
+}
+
+pub(super) fn parse_quota_behavior(value: Option<&str>) -> QuotaBehavior {
+    value.and_then(QuotaBehavior::from_str).unwrap_or_default()
+}
+
+/// Time zone of reports when neither the request, the user nor the service
+/// names one
+const FALLBACK_TIMEZONE: Tz = chrono_tz::America::Los_Angeles;
+
+/// Parse a timezone string, defaulting to `default` if invalid or not provided
+fn parse_timezone(tz_str: Option<&str>, default: Tz) -> Tz {
+    tz_str.and_then(|s| s.parse::<Tz>().ok()).unwrap_or(default)
 }
 
-/// Parse a timezone string, defaulting to Pacific Time if invalid or not provided
-fn parse_timezone(tz_str: Option<&str>) -> Tz {
-    tz_str
-        .and_then(|s| s.parse::<Tz>().ok())
-        .unwrap_or(chrono_tz::America::Los_Angeles)
+/// A time zone setting from a form; blank or unknown input means none
+pub(super) fn parse_timezone_setting(value: Option<&str>) -> String {
+    value
+        .and_then(|tz| tz.trim().parse::<Tz>().ok())
+        .map(|tz| tz.name().to_string())
+        .unwrap_or_default()
+}
+
+/// What reports on a service use where the request leaves it open
+struct ReportDefaults {
+    tz: Tz,
+    range: DateRangePreset,
+    /// The viewer's, else the service's; `None` leaves it to the language
+    week_start: Option<WeekStart>,
+}
+
+impl ReportDefaults {
+    fn week_start(&self, i18n: &I18n) -> WeekStart {
+        self.week_start.unwrap_or(i18n.locale().week_start())
+    }
+}
+
+/// The viewer's saved preferences for reports on `service`, else the
+/// service's own defaults
+async fn report_defaults(state: &AppState, tenant: &Tenant, service: &Service) -> ReportDefaults {
+    let (settings, range) = match &tenant.user {
+        Some(user) => {
+            let (settings, range) = tokio::join!(
+                db::get_user_settings(&state.pool, user.id),
+                db::get_user_date_range(&state.pool, user.id, service.id),
+            );
+            let settings = settings.unwrap_or_else(|e| {
+                error!("Error fetching user settings: {}", e);
+                UserSettings::default()
+            });
+            let range = range.unwrap_or_else(|e| {
+                error!("Error fetching user date range: {}", e);
+                None
+            });
+            (settings, range)
+        }
+        None => (UserSettings::default(), None),
+    };
+
+    let tz = [settings.time_zone.as_str(), service.time_zone.as_str()]
+        .into_iter()
+        .find_map(|tz| tz.parse::<Tz>().ok());
+    ReportDefaults {
+        tz: tz.unwrap_or(FALLBACK_TIMEZONE),
+        range: range.unwrap_or(service.default_range),
+        week_start: settings.week_start.or(service.week_start),
+    }
 }
 
 /// Parse a date/datetime string, interpreting it in the given timezone,
@@ -111,10 +328,20 @@ fn parse_datetime_string(s: &str, is_end: bool, tz: Tz) -> Option<chrono::DateTi
     None
 }
 
-fn parse_date_range(query: &DateRangeQuery) -> (chrono::DateTime<Utc>, chrono::DateTime<Utc>, Tz) {
-    let tz = parse_timezone(query.tz.as_deref());
-    let now = Utc::now();
-    let default_start = now - Duration::days(30);
+/// The query's range, defaulting to its preset's or else the default range
+/// up to `now`; dates are read in the query's time zone, else the default
+fn parse_date_range(
+    query: &DateRangeQuery,
+    now: chrono::DateTime<Utc>,
+    defaults: &ReportDefaults,
+) -> (chrono::DateTime<Utc>, chrono::DateTime<Utc>, Tz) {
+    let tz = parse_timezone(query.tz.as_deref(), defaults.tz);
+    let range = query
+        .range
+        .as_deref()
+        .and_then(DateRangePreset::from_str)
+        .unwrap_or(defaults.range);
+    let default_start = now - range.duration();
 
     let start = query
         .start_date
@@ -139,220 +366,376 @@ fn parse_url_pattern(pattern: &Option<String>) -> Option<Regex> {
 }
 
 /// GET /
-pub async fn dashboard_index(State(state): State<AppState>) -> Response {
-    let services = match db::list_services(&state.pool).await {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Error listing services: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+pub async fn dashboard_index(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let services = db::list_services(&state.pool, tenant.organization.id).await?;
 
-    let now = Utc::now();
+    let now = state.clock.now();
     let day_ago = now - Duration::days(1);
 
+    // The overview only counts production traffic
+    let environment = Some(Environment::Production);
+    let service_ids: Vec<ServiceId> = services.iter().map(|s| s.id).collect();
+    let counts = db::get_basic_counts_bulk(&state.pool, &service_ids, day_ago, now, environment)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Error fetching counts: {}", e);
+            HashMap::new()
+        });
+    let mut trends = db::get_daily_trends(&state.pool, &service_ids, now, environment, TREND_DAYS)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Error fetching trends: {}", e);
+            HashMap::new()
+        });
+
     let mut services_with_stats = Vec::new();
     for service in services {
-        // Get basic daily stats
-        let (session_count, hit_count): (i64, i64) =
-            get_basic_counts(&state, service.id, day_ago, now)
-                .await
-                .unwrap_or_default();
+        let (session_count, hit_count) = counts.get(&service.id).copied().unwrap_or_default();
+        let trend = trends
+            .remove(&service.id)
+            .unwrap_or_else(|| DailyTrend::empty(TREND_DAYS));
+        let monitored = state.settings.monitor_interval_secs > 0 && !service.link.is_empty();
+        let (uptime, expiry_warnings) = if monitored {
+            let uptime = db::get_uptime(&state.pool, service.id, day_ago).await.ok();
+            let expiry_warnings = match db::get_expiry_check(&state.pool, service.id).await {
+                Ok(Some(check)) => check.warnings(now, monitor::EXPIRY_WARNING_DAYS),
+                _ => Vec::new(),
+            };
+            (uptime, expiry_warnings)
+        } else {
+            (None, Vec::new())
+        };
 
         services_with_stats.push(ServiceWithStats {
             service,
             session_count,
             hit_count,
+            trend,
+            uptime,
+            expiry_warnings,
         });
     }
 
     let template = DashboardIndexTemplate {
+        i18n,
+        can_edit: tenant.role.allows(Permission::EditServices),
+        organization: tenant.organization,
         services: services_with_stats,
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
-    }
-}
-
-async fn get_basic_counts(
-    state: &AppState,
-    service_id: ServiceId,
-    start: chrono::DateTime<Utc>,
-    end: chrono::DateTime<Utc>,
-) -> Result<(i64, i64), Error> {
-    #[cfg(feature = "postgres")]
-    {
-        let session_count: i64 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3"
-        )
-        .bind(service_id.0)
-        .bind(start)
-        .bind(end)
-        .fetch_one(&state.pool)
-        .await?;
-
-        let hit_count: i64 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3"
-        )
-        .bind(service_id.0)
-        .bind(start)
-        .bind(end)
-        .fetch_one(&state.pool)
-        .await?;
-
-        Ok((session_count, hit_count))
-    }
-
-    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    {
-        let session_count: i32 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ?"
-        )
-        .bind(service_id.0.to_string())
-        .bind(start.to_rfc3339())
-        .bind(end.to_rfc3339())
-        .fetch_one(&state.pool)
-        .await?;
-
-        let hit_count: i32 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ?",
-        )
-        .bind(service_id.0.to_string())
-        .bind(start.to_rfc3339())
-        .bind(end.to_rfc3339())
-        .fetch_one(&state.pool)
-        .await?;
-
-        Ok((session_count as i64, hit_count as i64))
-    }
+    Ok(Html(template.render()?).into_response())
 }
 
 /// GET /service/:id
 pub async fn service_detail(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
     Query(query): Query<DateRangeQuery>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let views = match db::list_saved_views(&state.pool, service_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Error fetching saved views: {}", e);
+            Vec::new()
+        }
     };
 
-    let service = match db::get_service(&state.pool, service_id).await {
+    let mut query = query;
+    let active_view = query
+        .view
+        .as_deref()
+        .and_then(|id| id.parse::<SavedViewId>().ok())
+        .and_then(|id| views.iter().find(|v| v.id == id));
+    if let Some(view) = active_view {
+        apply_saved_view(&mut query, view);
+    }
+    let active_view = active_view.map(|v| v.id.to_string()).unwrap_or_default();
+    let segments = match db::list_segments(&state.pool, service_id).await {
         Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (StatusCode::NOT_FOUND, "Service not found").into_response()
-        }
         Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+            error!("Error fetching segments: {}", e);
+            Vec::new()
         }
     };
+    let layout = query
+        .layout
+        .as_deref()
+        .map(PanelLayout::parse)
+        .unwrap_or_default();
+
+    // A preset picked here is remembered as the user's range for the service
+    let picked_range = query.range.as_deref().and_then(DateRangePreset::from_str);
+    if let (Some(range), Some(user)) = (picked_range, &tenant.user) {
+        if let Err(e) = db::save_user_date_range(&state.pool, user.id, service_id, range).await {
+            error!("Error saving user date range: {}", e);
+        }
+    }
 
-    let (start, end, tz) = parse_date_range(&query);
+    let now = state.clock.now();
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&query, now, &defaults);
     let url_pattern = parse_url_pattern(&query.url_pattern);
-
-    let hide_referrer_regex = if service.hide_referrer_regex.is_empty() {
-        None
+    let segment = query
+        .segment_id
+        .as_deref()
+        .and_then(|id| id.parse::<SegmentId>().ok())
+        .and_then(|id| segments.iter().find(|s| s.id == id))
+        .cloned();
+    let active_segment = segment
+        .as_ref()
+        .map(|s| s.id.to_string())
+        .unwrap_or_default();
+    let segment = Segment::with_filters(segment, service_id, &query.filters, now);
+    let environment = Environment::parse_filter(query.env.as_deref());
+    let range = if query.start_date.is_some() || query.end_date.is_some() {
+        ""
     } else {
-        Regex::new(&service.hide_referrer_regex).ok()
+        picked_range.unwrap_or(defaults.range).as_str()
     };
 
-    let stats = match db::get_core_stats(
-        &state.pool,
-        service_id,
-        start,
-        end,
-        hide_referrer_regex.as_ref(),
-        url_pattern.as_ref(),
-        state.settings.active_user_timeout_ms(),
-        tz,
-    )
-    .await
-    {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Error fetching stats: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+    let referrer_filter = service.get_referrer_filter();
 
-    let sessions = match db::list_sessions(
+    // Panels (chart, top pages, referrers, countries, sessions) load lazily
+    let stats = db::get_summary_stats(
         &state.pool,
         service_id,
         start,
         end,
+        environment,
+        now,
+        Some(&referrer_filter),
         url_pattern.as_ref(),
-        10,
-        0,
+        None,
+        segment.as_ref(),
+        state.settings.active_user_timeout_ms(&service),
+        tz,
+        defaults.week_start(&i18n),
+        // The dashboard shows no previous period
+        None,
     )
-    .await
-    {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Error fetching sessions: {}", e);
-            Vec::new()
-        }
-    };
+    .await?;
 
     // Format start/end dates in user's timezone for the form inputs
     let start_local = start.with_timezone(&tz);
     let end_local = end.with_timezone(&tz);
 
     let template = ServiceDetailTemplate {
+        i18n,
         service,
+        service_id: service_id.0.to_string(),
         stats,
-        sessions,
         start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
         end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
+        time_zone: tz.name().to_string(),
+        range: range.to_string(),
+        date_ranges: DateRangePreset::ALL.to_vec(),
         url_pattern: query.url_pattern.clone().unwrap_or_default(),
         results_limit: RESULTS_LIMIT,
+        layout,
+        views,
+        active_view,
+        active_segment,
+        filters: query.filters.clone(),
+        segments,
+        segment_fields: SegmentField::ALL.to_vec(),
+        segment_ops: SegmentOp::ALL.to_vec(),
+        environment: environment.map_or("all", |e| e.as_str()).to_string(),
+        environments: Environment::ALL.to_vec(),
+        all_panels: DashboardPanel::ALL.to_vec(),
+        can_edit: tenant.role.allows(Permission::EditServices),
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
+    Ok(Html(template.render()?).into_response())
+}
+
+/// Fill in the parts of the query left unset from a saved view
+fn apply_saved_view(query: &mut DateRangeQuery, view: &SavedView) {
+    fn non_empty(s: &str) -> Option<String> {
+        Some(s.to_string()).filter(|s| !s.is_empty())
+    }
+
+    query.start_date = query
+        .start_date
+        .take()
+        .or_else(|| non_empty(&view.start_date));
+    query.end_date = query.end_date.take().or_else(|| non_empty(&view.end_date));
+    query.url_pattern = query
+        .url_pattern
+        .take()
+        .or_else(|| non_empty(&view.url_pattern));
+    query.layout = query
+        .layout
+        .take()
+        .or_else(|| Some(view.layout.to_string()));
+}
+
+/// Build a panel layout from the save-view form: a `show_<panel>` checkbox
+/// and an `order_<panel>` position per panel
+fn layout_from_form(form: &HashMap<String, String>) -> PanelLayout {
+    let mut panels: Vec<(i64, usize, DashboardPanel)> = DashboardPanel::ALL
+        .into_iter()
+        .enumerate()
+        .filter(|(_, panel)| form.contains_key(&format!("show_{}", panel.as_str())))
+        .map(|(index, panel)| {
+            let order = form
+                .get(&format!("order_{}", panel.as_str()))
+                .and_then(|o| o.trim().parse().ok())
+                .unwrap_or(index as i64 + 1);
+            (order, index, panel)
+        })
+        .collect();
+    panels.sort_by_key(|(order, index, _)| (*order, *index));
+
+    PanelLayout(panels.into_iter().map(|(_, _, panel)| panel).collect())
+}
+
+/// POST /service/:id/views
+pub async fn saved_view_create(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(service_id): Path<ServiceId>,
+    Form(form): Form<HashMap<String, String>>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    let name = form.get("name").map(|n| n.trim()).unwrap_or_default();
+    if name.is_empty() {
+        return Err(Error::BadRequest("View name is required".to_string()).into());
+    }
+
+    let field = |key: &str| form.get(key).cloned().unwrap_or_default();
+    let input = CreateSavedView {
+        name: name.to_string(),
+        start_date: field("startDate"),
+        end_date: field("endDate"),
+        url_pattern: field("urlPattern"),
+        layout: layout_from_form(&form),
+    };
+
+    let view = db::create_saved_view(&state.pool, service_id, input).await?;
+    Ok(Redirect::to(&format!("/service/{}?view={}", service_id, view.id)).into_response())
+}
+
+/// POST /service/:id/views/:view_id/delete
+pub async fn saved_view_delete(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path((service_id, view_id)): Path<(ServiceId, SavedViewId)>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    if db::get_saved_view(&state.pool, view_id).await?.service_id != service_id {
+        return Err(Error::SavedViewNotFound.into());
+    }
+
+    db::delete_saved_view(&state.pool, view_id).await?;
+    Ok(Redirect::to(&format!("/service/{}", service_id)).into_response())
+}
+
+/// Build a segment from the segment builder form: a `field_<n>`, `op_<n>`
+/// and `value_<n>` per condition; rows left blank are skipped
+fn segment_from_form(form: &HashMap<String, String>) -> CreateSegment {
+    let conditions = (0..MAX_SEGMENT_CONDITIONS)
+        .filter_map(|n| {
+            let value = form.get(&format!("value_{n}"))?.trim();
+            if value.is_empty() {
+                return None;
+            }
+            Some(SegmentCondition {
+                field: SegmentField::from_str(form.get(&format!("field_{n}"))?)?,
+                op: SegmentOp::from_str(form.get(&format!("op_{n}"))?)?,
+                value: value.to_string(),
+            })
+        })
+        .collect();
+
+    CreateSegment {
+        name: form.get("name").cloned().unwrap_or_default(),
+        conditions,
+    }
+}
+
+/// POST /service/:id/segments
+pub async fn segment_create(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(service_id): Path<ServiceId>,
+    Form(form): Form<HashMap<String, String>>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    let input = segment_from_form(&form);
+    if let Some(problem) = input.problem() {
+        return Err(Error::BadRequest(problem.to_string()).into());
     }
+
+    let segment = db::create_segment(&state.pool, service_id, input).await?;
+    Ok(Redirect::to(&format!(
+        "/service/{}?segment_id={}",
+        service_id, segment.id
+    ))
+    .into_response())
+}
+
+/// POST /service/:id/segments/:segment_id/delete
+pub async fn segment_delete(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path((service_id, segment_id)): Path<(ServiceId, SegmentId)>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    if db::get_segment(&state.pool, segment_id).await?.service_id != service_id {
+        return Err(Error::SegmentNotFound.into());
+    }
+
+    db::delete_segment(&state.pool, segment_id).await?;
+    Ok(Redirect::to(&format!("/service/{}", service_id)).into_response())
 }
 
 /// GET /service/:id/sessions
 pub async fn session_list(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
     Query(query): Query<PaginationQuery>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
 
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (StatusCode::NOT_FOUND, "Service not found").into_response()
-        }
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+    let service = check_service(&state, &tenant, service_id).await?;
 
     let date_query = DateRangeQuery {
         start_date: query.start_date.clone(),
         end_date: query.end_date.clone(),
         url_pattern: query.url_pattern.clone(),
         tz: query.tz.clone(),
+        ..Default::default()
     };
-    let (start, end, tz) = parse_date_range(&date_query);
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&date_query, state.clock.now(), &defaults);
     let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = load_segment(&state, service_id, query.segment_id.as_deref()).await;
+    let environment = Environment::parse_filter(query.env.as_deref());
     let page = query.page.unwrap_or(1).max(1);
     let offset = (page - 1) * PAGE_SIZE;
 
@@ -361,7 +744,9 @@ pub async fn session_list(
         service_id,
         start,
         end,
+        environment,
         url_pattern.as_ref(),
+        segment.as_ref(),
         PAGE_SIZE + 1,
         offset,
     )
@@ -386,6 +771,7 @@ pub async fn session_list(
     let end_local = end.with_timezone(&tz);
 
     let template = SessionListTemplate {
+        i18n,
         service,
         sessions,
         page,
@@ -393,392 +779,1286 @@ pub async fn session_list(
         start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
         end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
         url_pattern: query.url_pattern.clone().unwrap_or_default(),
+        segment_id: segment.map(|s| s.id.to_string()).unwrap_or_default(),
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
-    }
+    Ok(Html(template.render()?).into_response())
 }
 
-/// Query parameters for timezone
 #[derive(Debug, Deserialize)]
-pub struct TzQuery {
+pub struct CampaignQuery {
+    #[serde(rename = "startDate")]
+    pub start_date: Option<String>,
+    #[serde(rename = "endDate")]
+    pub end_date: Option<String>,
     pub tz: Option<String>,
+    /// Campaign to report on in detail
+    pub campaign: Option<String>,
 }
 
-/// GET /service/:id/sessions/:session_id
-pub async fn session_detail(
+/// GET /service/:id/campaigns
+///
+/// Email campaigns opened in the range, from pixel hits whose identifier is
+/// `campaign:recipient`, and the opens and recipients of one of them.
+pub async fn campaign_list(
     State(state): State<AppState>,
-    Path((service_id, session_id)): Path<(String, String)>,
-    Query(query): Query<TzQuery>,
-) -> Response {
-    let tz = parse_timezone(query.tz.as_deref());
-
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<CampaignQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
 
-    let session_id: SessionId = match session_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid session ID").into_response(),
-    };
-
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (StatusCode::NOT_FOUND, "Service not found").into_response()
-        }
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+    let service = check_service(&state, &tenant, service_id).await?;
 
-    let session = match db::get_session(&state.pool, session_id).await {
-        Ok(s) => s,
-        Err(Error::SessionNotFound) => {
-            return (StatusCode::NOT_FOUND, "Session not found").into_response()
-        }
-        Err(e) => {
-            error!("Error fetching session: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
+    let date_query = DateRangeQuery {
+        start_date: query.start_date.clone(),
+        end_date: query.end_date.clone(),
+        tz: query.tz.clone(),
+        ..Default::default()
     };
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&date_query, state.clock.now(), &defaults);
+    let campaign = query.campaign.unwrap_or_default();
 
-    let hits = match db::list_hits_for_session(&state.pool, session_id, 100, 0).await {
-        Ok(h) => h,
-        Err(e) => {
-            error!("Error fetching hits: {}", e);
-            Vec::new()
-        }
+    let campaigns = db::list_campaigns(&state.pool, service_id, start, end).await?;
+    let report = if campaign.is_empty() {
+        None
+    } else {
+        db::get_campaign_report(&state.pool, service_id, &campaign, start, end).await?
     };
 
-    // Convert to display structs with formatted timestamps
-    let session_display = SessionDisplay::from_session(session, tz);
-    let hits_display: Vec<HitDisplay> = hits
-        .into_iter()
-        .map(|h| HitDisplay::from_hit(h, tz))
-        .collect();
+    let start_local = start.with_timezone(&tz);
+    let end_local = end.with_timezone(&tz);
 
-    let template = SessionDetailTemplate {
+    let template = CampaignsTemplate {
+        i18n,
         service,
-        session: session_display,
-        hits: hits_display,
+        campaigns: campaigns
+            .into_iter()
+            .map(|summary| CampaignDisplay::from_summary(summary, tz))
+            .collect(),
+        report: report.map(|report| CampaignReportDisplay::from_report(report, tz)),
+        campaign,
+        start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
+        end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
-    }
+    Ok(Html(template.render()?).into_response())
 }
 
-/// GET /service/:id/locations
-pub async fn location_list(
+/// GET /service/:id/links
+pub async fn link_list(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
     Query(query): Query<DateRangeQuery>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
-
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (StatusCode::NOT_FOUND, "Service not found").into_response()
-        }
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
 
-    let (start, end, tz) = parse_date_range(&query);
-    let url_pattern = parse_url_pattern(&query.url_pattern);
-
-    let hide_referrer_regex = if service.hide_referrer_regex.is_empty() {
-        None
-    } else {
-        Regex::new(&service.hide_referrer_regex).ok()
-    };
+    let service = check_service(&state, &tenant, service_id).await?;
 
-    let stats = match db::get_core_stats(
-        &state.pool,
-        service_id,
-        start,
-        end,
-        hide_referrer_regex.as_ref(),
-        url_pattern.as_ref(),
-        state.settings.active_user_timeout_ms(),
-        tz,
-    )
-    .await
-    {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Error fetching stats: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&query, state.clock.now(), &defaults);
+    let links = db::get_tracked_link_stats(&state.pool, service_id, start, end).await?;
 
-    // Format start/end dates in user's timezone for the form inputs
     let start_local = start.with_timezone(&tz);
     let end_local = end.with_timezone(&tz);
 
-    let template = LocationListTemplate {
+    let template = LinksTemplate {
+        i18n,
         service,
-        locations: stats.locations,
-        total_hits: stats.hit_count,
+        links: links
+            .into_iter()
+            .map(|stats| {
+                let url = ingress::tracked_link_url(&state, &stats.link);
+                TrackedLinkDisplay::from_stats(stats, url)
+            })
+            .collect(),
+        can_edit: tenant.role.allows(Permission::EditServices),
         start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
         end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
-    }
+    Ok(Html(template.render()?).into_response())
 }
 
-/// GET /service/new
-pub async fn service_create_form() -> Response {
-    let template = ServiceCreateTemplate {};
+/// GET /service/:id/users
+///
+/// Sessions and devices per user, for services linking sessions under hashed
+/// login identifiers
+pub async fn service_users(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&query, state.clock.now(), &defaults);
+    let stats = db::get_user_stats(&state.pool, service_id, start, end).await?;
+
+    let start_local = start.with_timezone(&tz);
+    let end_local = end.with_timezone(&tz);
+
+    let template = ServiceUsersTemplate {
+        i18n,
+        service,
+        stats,
+        start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
+        end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+/// GET /service/:id/retention
+///
+/// Weekly cohorts of linked users and the share of each that came back in
+/// the weeks after, by default over the last 90 days
+pub async fn service_retention(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    // Shorter ranges than a quarter hold hardly any weeks to return in
+    let defaults = ReportDefaults {
+        range: DateRangePreset::Quarter,
+        ..report_defaults(&state, &tenant, &service).await
+    };
+    let (start, end, tz) = parse_date_range(&query, state.clock.now(), &defaults);
+    let retention = db::get_retention(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        tz,
+        defaults.week_start(&i18n),
+    )
+    .await?;
+
+    let start_local = start.with_timezone(&tz);
+    let end_local = end.with_timezone(&tz);
+
+    let template = ServiceRetentionTemplate {
+        i18n,
+        service,
+        retention,
+        start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
+        end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+/// POST /service/:id/links
+pub async fn link_create(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(service_id): Path<ServiceId>,
+    Form(input): Form<CreateTrackedLink>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    if let Some(problem) = input.problem() {
+        return Err(Error::BadRequest(problem.to_string()).into());
+    }
+
+    db::create_tracked_link(&state.pool, service_id, input).await?;
+    Ok(Redirect::to(&format!("/service/{}/links", service_id)).into_response())
+}
+
+/// GET /service/:id/links/:link_id
+pub async fn link_detail(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path((service_id, link_id)): Path<(ServiceId, LinkId)>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&query, state.clock.now(), &defaults);
+    let report = db::get_tracked_link_report(&state.pool, service_id, link_id, start, end).await?;
+
+    let start_local = start.with_timezone(&tz);
+    let end_local = end.with_timezone(&tz);
+    let last_day = start_local + Duration::days(report.daily_clicks.len() as i64 - 1);
+    let url = ingress::tracked_link_url(&state, &report.stats.link);
+
+    let template = LinkDetailTemplate {
+        i18n,
+        service,
+        link: TrackedLinkDisplay::from_stats(report.stats, url),
+        daily_clicks: sparkline_points(&report.daily_clicks),
+        first_day: start_local.format("%Y-%m-%d").to_string(),
+        last_day: last_day.format("%Y-%m-%d").to_string(),
+        referrers: report.referrers,
+        countries: report.countries,
+        start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
+        end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+/// GET /service/:id/links/:link_id/qr.svg
+pub async fn link_qr_code(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path((service_id, link_id)): Path<(ServiceId, LinkId)>,
+) -> PageResult {
+    check_service(&state, &tenant, service_id).await?;
+
+    let link = db::get_tracked_link(&state.pool, link_id).await?;
+    if link.service_id != service_id {
+        return Err(Error::LinkNotFound.into());
+    }
+
+    let svg = ingress::tracked_link_qr_svg(&state, &link)?;
+    Ok((
+        [(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")],
+        svg,
+    )
+        .into_response())
+}
+
+/// POST /service/:id/links/:link_id/delete
+pub async fn link_delete(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path((service_id, link_id)): Path<(ServiceId, LinkId)>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    if db::get_tracked_link(&state.pool, link_id).await?.service_id != service_id {
+        return Err(Error::LinkNotFound.into());
+    }
+
+    db::delete_tracked_link(&state.pool, link_id).await?;
+    Ok(Redirect::to(&format!("/service/{}/links", service_id)).into_response())
+}
+
+/// Query parameters for timezone
+#[derive(Debug, Deserialize)]
+pub struct TzQuery {
+    pub tz: Option<String>,
+}
+
+/// GET /service/:id/sessions/:session_id
+pub async fn session_detail(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path((service_id, session_id)): Path<(ServiceId, SessionId)>,
+    Query(query): Query<TzQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let tz = parse_timezone(query.tz.as_deref(), defaults.tz);
+
+    let session = state.reveal_ip(db::get_session(&state.pool, session_id).await?);
+    if session.service_id != service.id {
+        return Err(Error::SessionNotFound.into());
+    }
+
+    let hits = match db::list_hits_for_session(&state.pool, session_id, 100, 0).await {
+        Ok(h) => h,
         Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+            error!("Error fetching hits: {}", e);
+            Vec::new()
         }
-    }
+    };
+
+    // Convert to display structs with formatted timestamps
+    let session_display = SessionDisplay::from_session(session, tz);
+    let hits_display: Vec<HitDisplay> = hits
+        .into_iter()
+        .map(|h| HitDisplay::from_hit(h, tz))
+        .collect();
+
+    let template = SessionDetailTemplate {
+        i18n,
+        service,
+        session: session_display,
+        hits: hits_display,
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+/// GET /service/:id/locations
+pub async fn location_list(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let now = state.clock.now();
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&query, now, &defaults);
+    let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = Segment::with_filters(
+        load_segment(&state, service_id, query.segment_id.as_deref()).await,
+        service_id,
+        &query.filters,
+        now,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
+
+    let referrer_filter = service.get_referrer_filter();
+
+    let mut stats = db::get_core_stats(
+        &state.pool,
+        service_id,
+        start,
+        end,
+        environment,
+        now,
+        Some(&referrer_filter),
+        url_pattern.as_ref(),
+        None,
+        segment.as_ref(),
+        state.settings.active_user_timeout_ms(&service),
+        tz,
+        defaults.week_start(&i18n),
+        // The dashboard shows no previous period
+        None,
+    )
+    .await?;
+    state.hooks.adjust_stats(&state, &service, &mut stats).await;
+
+    let orphan_pages = db::get_orphan_pages(&state.pool, service_id, start, end, environment)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Error fetching orphan pages: {}", e);
+            Vec::new()
+        });
+
+    // Format start/end dates in user's timezone for the form inputs
+    let start_local = start.with_timezone(&tz);
+    let end_local = end.with_timezone(&tz);
+
+    let template = LocationListTemplate {
+        i18n,
+        service,
+        locations: stats.locations,
+        total_hits: stats.hit_count,
+        orphan_pages,
+        start_date: start_local.format("%Y-%m-%dT%H:%M").to_string(),
+        end_date: end_local.format("%Y-%m-%dT%H:%M").to_string(),
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+}
+
+/// GET /search
+///
+/// Services, page paths, referrer domains and sessions of the organization
+/// matching `q`. Pages and referrers are looked up in recent hits only.
+pub async fn search(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Query(query): Query<SearchQuery>,
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let q = query.q.unwrap_or_default().trim().to_string();
+
+    let results = if q.is_empty() {
+        Vec::new()
+    } else {
+        let organization_id = tenant.organization.id;
+        let since = state.clock.now() - Duration::days(SEARCH_DAYS);
+        // Encrypted IPs can only be found by their keyed hash
+        let ip_hash = state
+            .ip_cipher
+            .as_ref()
+            .filter(|_| q.parse::<std::net::IpAddr>().is_ok())
+            .map(|cipher| cipher.hash(&q));
+        let found = tokio::try_join!(
+            db::search_services(&state.pool, organization_id, &q, SEARCH_LIMIT),
+            db::search_pages(&state.pool, organization_id, &q, since, SEARCH_LIMIT),
+            db::search_referrer_domains(&state.pool, organization_id, &q, since, SEARCH_LIMIT),
+            db::search_sessions(
+                &state.pool,
+                organization_id,
+                &q,
+                ip_hash.as_deref(),
+                SEARCH_LIMIT
+            ),
+        );
+        let (services, pages, referrers, sessions) = found?;
+        services
+            .into_iter()
+            .chain(pages)
+            .chain(referrers)
+            .chain(sessions)
+            .collect()
+    };
+
+    let template = SearchTemplate {
+        i18n,
+        query: q,
+        results,
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+/// GET /service/new
+pub async fn service_create_form(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let template = ServiceCreateTemplate { i18n };
+
+    Ok(Html(template.render()?).into_response())
 }
 
 /// POST /service/new
 pub async fn service_create(
     State(state): State<AppState>,
+    tenant: Tenant,
     Form(form): Form<ServiceForm>,
-) -> Response {
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+    let hit_quota = form.hit_quota();
+    let quota_behavior = form.quota_behavior();
+    let heartbeat_frequency_ms = form.heartbeat_frequency_ms();
+    let idle_timeout_mins = form.idle_timeout_mins();
+    let active_user_timeout_ms = form.active_user_timeout_ms();
+    let default_range = form.default_range();
+    let bounce_rule = form.bounce_rule();
+    let bounce_threshold_secs = form.bounce_threshold_secs();
+    let session_timeout_mins = form.session_timeout_mins();
+    let week_start = form.week_start();
     let input = CreateService {
+        organization_id: Some(tenant.organization.id),
         name: form.name,
         link: form.link.unwrap_or_default(),
         origins: form.origins.unwrap_or_else(|| "*".to_string()),
         respect_dnt: form.respect_dnt.is_some(),
+        respect_gpc: form.respect_gpc.is_some(),
         ignore_robots: form.ignore_robots.is_some(),
         collect_ips: form.collect_ips.is_some(),
         ignored_ips: form.ignored_ips.unwrap_or_default(),
+        allowed_countries: form.allowed_countries.unwrap_or_default(),
+        blocked_countries: form.blocked_countries.unwrap_or_default(),
+        allowed_asns: form.allowed_asns.unwrap_or_default(),
+        blocked_asns: form.blocked_asns.unwrap_or_default(),
         hide_referrer_regex: form.hide_referrer_regex.unwrap_or_default(),
         script_inject: form.script_inject.unwrap_or_default(),
+        collapse_tabs: form.collapse_tabs.is_some(),
+        hit_quota,
+        quota_behavior,
+        heartbeat_frequency_ms,
+        idle_timeout_mins,
+        public_badge: form.public_badge.is_some(),
+        content_groups: form.content_groups.unwrap_or_default(),
+        active_user_timeout_ms,
+        lowercase_paths: form.lowercase_paths.is_some(),
+        strip_trailing_slash: form.strip_trailing_slash.is_some(),
+        path_patterns: form.path_patterns.unwrap_or_default(),
+        time_zone: parse_timezone_setting(form.time_zone.as_deref()),
+        default_range,
+        signed_pixel_ids: form.signed_pixel_ids.is_some(),
+        bounce_rule,
+        bounce_threshold_secs,
+        session_timeout_mins,
+        week_start,
+        debug_log: form.debug_log.is_some(),
+        link_users: form.link_users.is_some(),
+        hide_internal_referrers: form.hide_internal_referrers.is_some(),
+        track_errors: form.track_errors.is_some(),
     };
 
-    match db::create_service(&state.pool, input).await {
-        Ok(service) => Redirect::to(&format!("/service/{}", service.id)).into_response(),
-        Err(e) => {
-            error!("Error creating service: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to create service",
-            )
-                .into_response()
-        }
-    }
+    let service = db::create_service(&state.pool, input).await?;
+    Ok(Redirect::to(&format!("/service/{}", service.id)).into_response())
 }
 
 /// GET /service/:id/manage
 pub async fn service_update_form(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
-
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (StatusCode::NOT_FOUND, "Service not found").into_response()
-        }
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let template = ServiceUpdateTemplate {
+        i18n,
+        feed_url: milestones::feed_url(&state, service.id),
+        embed_chart_url: embed::embed_url(&state, service.id, "chart"),
+        embed_counters_url: embed::embed_url(&state, service.id, "counters"),
+        ingest_script: db::get_ingest_script(&state.pool, service.id)
+            .await?
+            .unwrap_or_default(),
+        ingest_script_error: None,
+        service,
+        can_delete: tenant.role.allows(Permission::DeleteServices),
     };
 
-    let template = ServiceUpdateTemplate { service };
-
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
-    }
+    Ok(Html(template.render()?).into_response())
 }
 
 /// POST /service/:id/manage
 pub async fn service_update(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: Tenant,
+    Path(service_id): Path<ServiceId>,
     Form(form): Form<ServiceForm>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
-
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    check_service(&state, &tenant, service_id).await?;
+
+    let hit_quota = form.hit_quota();
+    let quota_behavior = form.quota_behavior();
+    let heartbeat_frequency_ms = form.heartbeat_frequency_ms();
+    let idle_timeout_mins = form.idle_timeout_mins();
+    let active_user_timeout_ms = form.active_user_timeout_ms();
+    let default_range = form.default_range();
+    let bounce_rule = form.bounce_rule();
+    let bounce_threshold_secs = form.bounce_threshold_secs();
+    let session_timeout_mins = form.session_timeout_mins();
+    let week_start = form.week_start();
     let input = UpdateService {
         name: Some(form.name),
         link: form.link,
         origins: form.origins,
         status: None,
         respect_dnt: Some(form.respect_dnt.is_some()),
+        respect_gpc: Some(form.respect_gpc.is_some()),
         ignore_robots: Some(form.ignore_robots.is_some()),
         collect_ips: Some(form.collect_ips.is_some()),
         ignored_ips: form.ignored_ips,
+        allowed_countries: form.allowed_countries,
+        blocked_countries: form.blocked_countries,
+        allowed_asns: form.allowed_asns,
+        blocked_asns: form.blocked_asns,
         hide_referrer_regex: form.hide_referrer_regex,
         script_inject: form.script_inject,
+        collapse_tabs: Some(form.collapse_tabs.is_some()),
+        hit_quota: Some(hit_quota),
+        quota_behavior: Some(quota_behavior),
+        heartbeat_frequency_ms: Some(heartbeat_frequency_ms),
+        idle_timeout_mins: Some(idle_timeout_mins),
+        public_badge: Some(form.public_badge.is_some()),
+        content_groups: form.content_groups,
+        active_user_timeout_ms: Some(active_user_timeout_ms),
+        lowercase_paths: Some(form.lowercase_paths.is_some()),
+        strip_trailing_slash: Some(form.strip_trailing_slash.is_some()),
+        path_patterns: form.path_patterns,
+        time_zone: Some(parse_timezone_setting(form.time_zone.as_deref())),
+        default_range: Some(default_range),
+        signed_pixel_ids: Some(form.signed_pixel_ids.is_some()),
+        bounce_rule: Some(bounce_rule),
+        bounce_threshold_secs: Some(bounce_threshold_secs),
+        session_timeout_mins: Some(session_timeout_mins),
+        week_start: Some(week_start),
+        debug_log: Some(form.debug_log.is_some()),
+        link_users: Some(form.link_users.is_some()),
+        hide_internal_referrers: Some(form.hide_internal_referrers.is_some()),
+        track_errors: Some(form.track_errors.is_some()),
     };
 
-    match db::update_service(&state.pool, service_id, input).await {
-        Ok(_) => {
-            // Invalidate cache
-            state.cache.invalidate_service(service_id).await;
-            Redirect::to(&format!("/service/{}", service_id)).into_response()
-        }
-        Err(e) => {
-            error!("Error updating service: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to update service",
-            )
-                .into_response()
-        }
+    db::update_service(&state.pool, service_id, input).await?;
+    if form.debug_log.is_none() {
+        state.ingress_log.clear(service_id);
+    }
+    if form.link_users.is_none() {
+        db::clear_user_keys(&state.pool, service_id).await?;
     }
+    // Invalidate cache
+    state.cache.invalidate_service(service_id).await;
+    Ok(Redirect::to(&format!("/service/{}", service_id)).into_response())
 }
 
-/// GET /service/:id/delete
-pub async fn service_delete_form(
+/// GET /service/:id/debug
+///
+/// What ingress made of the service's latest page views, while the service
+/// keeps a debug log
+pub async fn service_debug(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+    let tz = report_defaults(&state, &tenant, &service).await.tz;
+
+    let template = ServiceDebugTemplate {
+        i18n,
+        entries: state
+            .ingress_log
+            .entries(service_id)
+            .into_iter()
+            .map(|entry| IngressLogDisplay::from_entry(entry, tz))
+            .collect(),
+        service,
     };
 
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(Error::ServiceNotFound) => {
-            return (StatusCode::NOT_FOUND, "Service not found").into_response()
-        }
-        Err(e) => {
-            error!("Error fetching service: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+    Ok(Html(template.render()?).into_response())
+}
 
-    let template = ServiceDeleteTemplate { service };
+#[derive(Debug, Deserialize)]
+pub struct IngestScriptForm {
+    #[serde(default)]
+    pub source: String,
+}
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-        }
+/// POST /service/:id/ingest-script
+///
+/// Saves the service's ingest script once it compiles; an empty script
+/// removes it. A script that doesn't compile is shown again with the error.
+pub async fn ingest_script_update(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Form(form): Form<IngestScriptForm>,
+) -> PageResult {
+    tenant.authorize(Permission::EditServices)?;
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    if let Err(e) = IngestScript::compile(&form.source) {
+        let template = ServiceUpdateTemplate {
+            i18n: I18n::from_headers(&headers, &state.settings.locale),
+            feed_url: milestones::feed_url(&state, service.id),
+            embed_chart_url: embed::embed_url(&state, service.id, "chart"),
+            embed_counters_url: embed::embed_url(&state, service.id, "counters"),
+            ingest_script: form.source,
+            ingest_script_error: Some(e.to_string()),
+            service,
+            can_delete: tenant.role.allows(Permission::DeleteServices),
+        };
+        return Ok((StatusCode::BAD_REQUEST, Html(template.render()?)).into_response());
     }
+
+    db::save_ingest_script(&state.pool, service_id, &form.source).await?;
+    state.cache.invalidate_service(service_id).await;
+    Ok(Redirect::to(&format!("/service/{}/manage", service_id)).into_response())
+}
+
+/// GET /service/:id/delete
+pub async fn service_delete_form(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+) -> PageResult {
+    tenant.authorize(Permission::DeleteServices)?;
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let template = ServiceDeleteTemplate { i18n, service };
+
+    Ok(Html(template.render()?).into_response())
 }
 
 /// POST /service/:id/delete
 pub async fn service_delete(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
+    tenant: Tenant,
+    Path(service_id): Path<ServiceId>,
+) -> PageResult {
+    tenant.authorize(Permission::DeleteServices)?;
 
-    match db::delete_service(&state.pool, service_id).await {
-        Ok(_) => {
-            state.cache.invalidate_service(service_id).await;
-            Redirect::to("/").into_response()
-        }
-        Err(e) => {
-            error!("Error deleting service: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to delete service",
-            )
-                .into_response()
-        }
+    check_service(&state, &tenant, service_id).await?;
+
+    db::delete_service(&state.pool, service_id).await?;
+    state.cache.invalidate_service(service_id).await;
+    Ok(Redirect::to("/").into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExcludeMeForm {
+    /// Whether to exclude this browser (`true`) or include it again
+    pub exclude: bool,
+}
+
+/// The organization's service with the given tracking ID
+async fn check_tracked_service(
+    state: &AppState,
+    tenant: &Tenant,
+    tracking_id: &str,
+) -> Result<Service, Error> {
+    let service = db::get_service_by_tracking_id(&state.pool, tracking_id).await?;
+    if service.organization_id != tenant.organization.id {
+        return Err(Error::ServiceNotFound);
     }
+    Ok(service)
+}
+
+/// GET /exclude-me/:tracking_id
+pub async fn exclude_me_form(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(TrackingId(tracking_id)): Path<TrackingId>,
+) -> PageResult {
+    let service = check_tracked_service(&state, &tenant, &tracking_id).await?;
+
+    render_partial(ExcludeMeTemplate {
+        i18n: I18n::from_headers(&headers, &state.settings.locale),
+        excluded: privacy::is_excluded(&headers, &tracking_id),
+        service,
+    })
+}
+
+/// POST /exclude-me/:tracking_id
+///
+/// Sets or clears the cookie that makes the tracker skip this browser. Any
+/// member may exclude themselves, as it only affects their own visits.
+pub async fn exclude_me(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(TrackingId(tracking_id)): Path<TrackingId>,
+    Form(form): Form<ExcludeMeForm>,
+) -> PageResult {
+    check_tracked_service(&state, &tenant, &tracking_id).await?;
+
+    let secure = state.settings.public_url.starts_with("https://");
+    Ok((
+        [(
+            header::SET_COOKIE,
+            privacy::exclusion_set_cookie(&tracking_id, form.exclude, secure),
+        )],
+        Redirect::to(&format!("/exclude-me/{}", tracking_id)),
+    )
+        .into_response())
 }
 
 /// GET /service/:id/stats (HTMX partial)
 pub async fn stats_partial(
     State(state): State<AppState>,
-    Path(service_id): Path<String>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
     Query(query): Query<DateRangeQuery>,
-) -> Response {
-    let service_id: ServiceId = match service_id.parse() {
-        Ok(id) => id,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid service ID").into_response(),
-    };
+) -> PageResult {
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
 
-    let service = match db::get_service(&state.pool, service_id).await {
-        Ok(s) => s,
-        Err(_) => return (StatusCode::NOT_FOUND, "Service not found").into_response(),
-    };
+    let service = check_service(&state, &tenant, service_id).await?;
 
-    let (start, end, tz) = parse_date_range(&query);
+    let now = state.clock.now();
+    let defaults = report_defaults(&state, &tenant, &service).await;
+    let (start, end, tz) = parse_date_range(&query, now, &defaults);
     let url_pattern = parse_url_pattern(&query.url_pattern);
+    let segment = Segment::with_filters(
+        load_segment(&state, service_id, query.segment_id.as_deref()).await,
+        service_id,
+        &query.filters,
+        now,
+    );
+    let environment = Environment::parse_filter(query.env.as_deref());
 
-    let hide_referrer_regex = if service.hide_referrer_regex.is_empty() {
-        None
-    } else {
-        Regex::new(&service.hide_referrer_regex).ok()
-    };
+    let referrer_filter = service.get_referrer_filter();
 
-    let stats = match db::get_core_stats(
+    let stats = db::get_summary_stats(
         &state.pool,
         service_id,
         start,
         end,
-        hide_referrer_regex.as_ref(),
+        environment,
+        now,
+        Some(&referrer_filter),
         url_pattern.as_ref(),
-        state.settings.active_user_timeout_ms(),
+        None,
+        segment.as_ref(),
+        state.settings.active_user_timeout_ms(&service),
         tz,
+        defaults.week_start(&i18n),
+        // The dashboard shows no previous period
+        None,
     )
-    .await
-    {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Error fetching stats: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
-        }
-    };
+    .await?;
 
     let template = StatsPartialTemplate {
+        i18n,
         stats,
         service_id: service_id.0.to_string(),
+        filters: query.filters,
+        layout: query
+            .layout
+            .as_deref()
+            .map(PanelLayout::parse)
+            .unwrap_or_default(),
     };
 
-    match template.render() {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => {
-            error!("Template render error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+    Ok(Html(template.render()?).into_response())
+}
+
+/// Service and filters shared by the dashboard panel partials
+struct PanelContext {
+    i18n: I18n,
+    service: Service,
+    start: chrono::DateTime<Utc>,
+    end: chrono::DateTime<Utc>,
+    /// The clock's time when the request came in
+    now: chrono::DateTime<Utc>,
+    tz: Tz,
+    week_start: WeekStart,
+    url_pattern: Option<Regex>,
+    segment: Option<Segment>,
+    environment: Option<Environment>,
+}
+
+impl PanelContext {
+    async fn load(
+        state: &AppState,
+        tenant: &Tenant,
+        headers: &HeaderMap,
+        service_id: ServiceId,
+        query: &DateRangeQuery,
+    ) -> Result<Self, Error> {
+        let service = check_service(state, tenant, service_id).await?;
+
+        let now = state.clock.now();
+        let defaults = report_defaults(state, tenant, &service).await;
+        let (start, end, tz) = parse_date_range(query, now, &defaults);
+        let url_pattern = parse_url_pattern(&query.url_pattern);
+        let segment = Segment::with_filters(
+            load_segment(state, service_id, query.segment_id.as_deref()).await,
+            service_id,
+            &query.filters,
+            now,
+        );
+        let environment = Environment::parse_filter(query.env.as_deref());
+        let i18n = I18n::from_headers(headers, &state.settings.locale);
+
+        Ok(Self {
+            week_start: defaults.week_start(&i18n),
+            i18n,
+            service,
+            start,
+            end,
+            now,
+            tz,
+            url_pattern,
+            segment,
+            environment,
+        })
+    }
+}
+
+/// The service's segment a query names; unknown ids and those of other
+/// services leave the stats unsegmented
+async fn load_segment(
+    state: &AppState,
+    service_id: ServiceId,
+    segment_id: Option<&str>,
+) -> Option<Segment> {
+    let id: SegmentId = segment_id?.parse().ok()?;
+    db::get_segment(&state.pool, id)
+        .await
+        .ok()
+        .filter(|segment| segment.service_id == service_id)
+}
+
+/// Fetch a service of the tenant's organization; other organizations'
+/// services are not found
+async fn check_service(
+    state: &AppState,
+    tenant: &Tenant,
+    service_id: ServiceId,
+) -> Result<Service, Error> {
+    db::get_organization_service(&state.pool, tenant.organization.id, service_id).await
+}
+
+fn render_partial<T: Template>(template: T) -> PageResult {
+    Ok(Html(template.render()?).into_response())
+}
+
+/// GET /service/:id/install-status (HTMX partial)
+///
+/// Badge saying whether the service's site carries its tracker. The site is
+/// only fetched when no recent check is cached.
+pub async fn install_status(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+) -> PageResult {
+    let service = check_service(&state, &tenant, service_id).await?;
+
+    let check = match state.cache.get_install_check(service_id).await {
+        Some(check) => check,
+        None => {
+            let check = install::verify_install(&service, state.clock.now()).await;
+            state
+                .cache
+                .set_install_check(service_id, check.clone())
+                .await;
+            check
         }
+    };
+
+    render_partial(InstallStatusTemplate {
+        i18n: I18n::from_headers(&headers, &state.settings.locale),
+        check,
+    })
+}
+
+/// GET /service/:id/panels/sessions (HTMX partial)
+pub async fn sessions_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let sessions = db::list_sessions(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+        ctx.url_pattern.as_ref(),
+        ctx.segment.as_ref(),
+        10,
+        0,
+    )
+    .await?;
+
+    render_partial(SessionTableTemplate {
+        i18n: ctx.i18n,
+        sessions,
+        service_id: ctx.service.id.0.to_string(),
+    })
+}
+
+/// GET /service/:id/panels/locations (HTMX partial)
+pub async fn locations_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let locations = db::get_top_locations(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+        ctx.url_pattern.as_ref(),
+        ctx.segment.as_ref(),
+    )
+    .await?;
+
+    render_partial(LocationsPanelTemplate {
+        i18n: ctx.i18n,
+        locations,
+    })
+}
+
+/// GET /service/:id/panels/content-groups (HTMX partial)
+pub async fn content_groups_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let groups = ctx.service.get_content_groups();
+    if groups.is_empty() {
+        return render_partial(ContentGroupsPanelTemplate {
+            i18n: ctx.i18n,
+            configured: false,
+            groups: Vec::new(),
+            service_id: ctx.service.id.0.to_string(),
+        });
+    }
+
+    let counts = db::get_content_group_counts(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+        &groups,
+        ctx.url_pattern.as_ref(),
+        ctx.segment.as_ref(),
+    )
+    .await?;
+
+    render_partial(ContentGroupsPanelTemplate {
+        i18n: ctx.i18n,
+        configured: true,
+        groups: counts,
+        service_id: ctx.service.id.0.to_string(),
+    })
+}
+
+/// The dimension key the dimensions panel shows
+#[derive(Debug, Default, Deserialize)]
+pub struct DimensionQuery {
+    pub key: Option<String>,
+}
+
+/// GET /service/:id/panels/dimensions (HTMX partial)
+pub async fn dimensions_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+    Query(selection): Query<DimensionQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let keys = db::get_dimension_keys(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+    )
+    .await?;
+    // The busiest key unless another one was picked
+    let key = selection
+        .key
+        .filter(|key| keys.iter().any(|k| &k.value == key))
+        .or_else(|| keys.first().map(|k| k.value.clone()))
+        .unwrap_or_default();
+    let values = if key.is_empty() {
+        Vec::new()
+    } else {
+        db::get_dimension_counts(
+            &state.pool,
+            ctx.service.id,
+            ctx.start,
+            ctx.end,
+            ctx.environment,
+            &key,
+            ctx.url_pattern.as_ref(),
+            ctx.segment.as_ref(),
+        )
+        .await?
+    };
+
+    render_partial(DimensionsPanelTemplate {
+        i18n: ctx.i18n,
+        keys,
+        key,
+        values,
+        service_id: ctx.service.id.0.to_string(),
+    })
+}
+
+/// GET /service/:id/panels/referrers (HTMX partial)
+pub async fn referrers_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let referrer_filter = ctx.service.get_referrer_filter();
+
+    let referrers = db::get_top_referrers(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+        Some(&referrer_filter),
+        ctx.url_pattern.as_ref(),
+        ctx.segment.as_ref(),
+    )
+    .await?;
+
+    render_partial(ReferrersPanelTemplate {
+        i18n: ctx.i18n,
+        referrers,
+    })
+}
+
+/// GET /service/:id/panels/countries (HTMX partial)
+pub async fn countries_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let countries = db::get_top_countries(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+        ctx.url_pattern.as_ref(),
+        ctx.segment.as_ref(),
+    )
+    .await?;
+
+    let continents = countries::continent_rollup(&countries, &ctx.i18n);
+    render_partial(CountriesPanelTemplate {
+        i18n: ctx.i18n,
+        countries,
+        continents,
+    })
+}
+
+/// GET /service/:id/panels/chart (HTMX partial)
+pub async fn chart_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let (chart_data, _, _) = db::get_chart(
+        &state.pool,
+        ctx.service.id,
+        ctx.start,
+        ctx.end,
+        ctx.environment,
+        ctx.now,
+        ctx.url_pattern.as_ref(),
+        ctx.segment.as_ref(),
+        ctx.tz,
+        ctx.week_start,
+    )
+    .await?;
+
+    render_partial(ChartPanelTemplate {
+        i18n: ctx.i18n,
+        chart_data,
+    })
+}
+
+/// GET /service/:id/panels/data-quality (HTMX partial)
+///
+/// Page views ingress turned away in the range and why, counted per UTC day
+pub async fn data_quality_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let dropped = db::get_dropped_hits(
+        &state.pool,
+        ctx.service.id,
+        ctx.start.date_naive(),
+        ctx.end.date_naive(),
+    )
+    .await?;
+    let flagged_spam =
+        db::count_referrer_spam(&state.pool, ctx.service.id, ctx.start, ctx.end).await?;
+    render_partial(DataQualityPanelTemplate {
+        i18n: ctx.i18n,
+        total: dropped.iter().map(|d| d.count).sum(),
+        dropped,
+        flagged_spam,
+    })
+}
+
+/// GET /service/:id/panels/errors (HTMX partial)
+///
+/// JavaScript errors reported on the service's pages in the range, counted
+/// per UTC day
+pub async fn errors_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let mut errors = db::get_page_errors(
+        &state.pool,
+        ctx.service.id,
+        ctx.start.date_naive(),
+        ctx.end.date_naive(),
+        ctx.environment,
+    )
+    .await?;
+    if let Some(pattern) = &ctx.url_pattern {
+        errors.retain(|error| pattern.is_match(&error.location));
     }
+    render_partial(ErrorsPanelTemplate {
+        i18n: ctx.i18n,
+        service_id: ctx.service.id.to_string(),
+        tracking: ctx.service.track_errors,
+        errors,
+    })
+}
+
+/// GET /service/:id/panels/usage (HTMX partial)
+pub async fn usage_panel(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Path(service_id): Path<ServiceId>,
+    Query(query): Query<DateRangeQuery>,
+) -> PageResult {
+    let ctx = PanelContext::load(&state, &tenant, &headers, service_id, &query).await?;
+
+    let usage = db::get_quota_usage(&state.pool, &ctx.service, ctx.now, USAGE_MONTHS).await?;
+    render_partial(UsagePanelTemplate {
+        i18n: ctx.i18n,
+        bar_width: usage.percent_used().unwrap_or(0).min(100),
+        usage,
+    })
 }
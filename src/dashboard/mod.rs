@@ -1,5 +1,11 @@
+mod accounts;
+mod admin;
+mod errors;
 mod handlers;
 mod templates;
 
+pub use accounts::*;
+pub use admin::*;
+pub use errors::*;
 pub use handlers::*;
 pub use templates::*;
@@ -2,51 +2,162 @@ use askama::Template;
 use chrono::{DateTime, Utc};
 use chrono_tz::Tz;
 
-use crate::domain::{CoreStats, CountedItem, Hit, Service, Session, TrackerType};
+use crate::domain::{
+    ApiToken, BreakdownFilters, CampaignRecipient, CampaignReport, CampaignSummary, ChartData,
+    ContinentCount, CoreStats, CountedItem, DailyTrend, DashboardPanel, DateRangePreset,
+    DroppedHits, Environment, ExpiryWarning, HistogramBucket, Hit, IngressDecision, InstallCheck,
+    LoginAttempt, MaintenanceRun, MaintenanceTask, Member, Organization, PageError, PanelLayout,
+    QuotaUsage, Retention, SavedView, SearchResult, Segment, SegmentField, SegmentOp, Service,
+    ServiceUsage, Session, TrackedLinkStats, TrackerType, Uptime, User, UserSettings, UserStats,
+};
+use crate::i18n::I18n;
+use crate::ingress::IngressLogEntry;
+use crate::status::SystemStatus;
+use crate::updates::Release;
 
 #[derive(Template)]
 #[template(path = "dashboard/index.html")]
 pub struct DashboardIndexTemplate {
+    pub i18n: I18n,
+    pub organization: Organization,
     pub services: Vec<ServiceWithStats>,
+    /// The user's role lets them create services
+    pub can_edit: bool,
 }
 
 pub struct ServiceWithStats {
     pub service: Service,
+    /// Sessions in the past 24 hours
     pub session_count: i64,
+    /// Hits in the past 24 hours
     pub hit_count: i64,
+    /// Daily sessions and hits of the past week
+    pub trend: DailyTrend,
+    /// Over the last 24 hours, when the uptime monitor is on
+    pub uptime: Option<Uptime>,
+    /// Certificate and domain running out soon, when the monitor is on
+    pub expiry_warnings: Vec<ExpiryWarning>,
 }
 
 #[derive(Template)]
 #[template(path = "dashboard/service.html")]
 pub struct ServiceDetailTemplate {
+    pub i18n: I18n,
     pub service: Service,
+    pub service_id: String,
     pub stats: CoreStats,
-    pub sessions: Vec<Session>,
+    /// Start and end of the range, in `time_zone`
     pub start_date: String,
     pub end_date: String,
+    /// Time zone the range is read and shown in
+    pub time_zone: String,
+    /// Preset the range follows, empty for custom dates
+    pub range: String,
+    pub date_ranges: Vec<DateRangePreset>,
     pub url_pattern: String,
     pub results_limit: i64,
+    pub layout: PanelLayout,
+    pub views: Vec<SavedView>,
+    /// ID of the saved view being shown, empty if none
+    pub active_view: String,
+    pub segments: Vec<Segment>,
+    /// ID of the segment the stats are narrowed to, empty if none
+    pub active_segment: String,
+    /// Breakdown rows the stats are narrowed to
+    pub filters: BreakdownFilters,
+    /// Fields and operators for the segment builder
+    pub segment_fields: Vec<SegmentField>,
+    pub segment_ops: Vec<SegmentOp>,
+    /// Key of the environment being shown, or "all"
+    pub environment: String,
+    pub environments: Vec<Environment>,
+    /// Every panel, for the save-view form
+    pub all_panels: Vec<DashboardPanel>,
+    /// The user's role lets them change the service and its saved views
+    pub can_edit: bool,
 }
 
 #[derive(Template)]
 #[template(path = "dashboard/service_create.html")]
-pub struct ServiceCreateTemplate {}
+pub struct ServiceCreateTemplate {
+    pub i18n: I18n,
+}
 
 #[derive(Template)]
 #[template(path = "dashboard/service_update.html")]
 pub struct ServiceUpdateTemplate {
+    pub i18n: I18n,
     pub service: Service,
+    /// The user's role lets them delete the service
+    pub can_delete: bool,
+    /// Signed URL of the service's milestone feed
+    pub feed_url: String,
+    /// Signed URLs of the service's embeddable widgets
+    pub embed_chart_url: String,
+    pub embed_counters_url: String,
+    /// Source of the service's ingest script, empty if it has none
+    pub ingest_script: String,
+    /// Why the submitted ingest script was refused
+    pub ingest_script_error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/exclude_me.html")]
+pub struct ExcludeMeTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+    /// This browser's visits are left out of the service's stats
+    pub excluded: bool,
 }
 
 #[derive(Template)]
 #[template(path = "dashboard/service_delete.html")]
 pub struct ServiceDeleteTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/service_debug.html")]
+pub struct ServiceDebugTemplate {
+    pub i18n: I18n,
     pub service: Service,
+    /// Newest first
+    pub entries: Vec<IngressLogDisplay>,
+}
+
+/// An ingress debug log entry with its time pre-formatted
+pub struct IngressLogDisplay {
+    pub time: String,
+    pub tracker: TrackerType,
+    pub decision: IngressDecision,
+    pub location: String,
+    pub user_agent: String,
+    /// Origin a rejected request came from, empty otherwise
+    pub origin: String,
+}
+
+impl IngressLogDisplay {
+    pub fn from_entry(entry: IngressLogEntry, tz: Tz) -> Self {
+        Self {
+            time: entry
+                .time
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            tracker: entry.tracker,
+            decision: entry.decision,
+            location: entry.location,
+            user_agent: entry.user_agent,
+            origin: entry.origin.unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(Template)]
 #[template(path = "dashboard/session_list.html")]
 pub struct SessionListTemplate {
+    pub i18n: I18n,
     pub service: Service,
     pub sessions: Vec<SessionDisplay>,
     pub page: i64,
@@ -54,6 +165,162 @@ pub struct SessionListTemplate {
     pub start_date: String,
     pub end_date: String,
     pub url_pattern: String,
+    /// ID of the segment the sessions are narrowed to, empty if none
+    pub segment_id: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/campaigns.html")]
+pub struct CampaignsTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+    pub campaigns: Vec<CampaignDisplay>,
+    /// The campaign picked with `?campaign=`, if it was opened in the range
+    pub report: Option<CampaignReportDisplay>,
+    /// The campaign asked for, empty if none
+    pub campaign: String,
+    pub start_date: String,
+    pub end_date: String,
+}
+
+/// Opens of a campaign, or of one of its recipients, with pre-formatted
+/// timestamps
+pub struct CampaignDisplay {
+    /// Campaign or recipient
+    pub name: String,
+    pub opens: i64,
+    pub unique_opens: i64,
+    pub first_open: String,
+    pub last_open: String,
+}
+
+impl CampaignDisplay {
+    pub fn from_summary(summary: CampaignSummary, tz: Tz) -> Self {
+        Self {
+            name: summary.campaign,
+            opens: summary.opens,
+            unique_opens: summary.unique_opens,
+            first_open: format_open(summary.first_open, tz),
+            last_open: format_open(summary.last_open, tz),
+        }
+    }
+
+    pub fn from_recipient(recipient: CampaignRecipient, tz: Tz) -> Self {
+        Self {
+            name: recipient.recipient,
+            opens: recipient.opens,
+            unique_opens: 1,
+            first_open: format_open(recipient.first_open, tz),
+            last_open: format_open(recipient.last_open, tz),
+        }
+    }
+}
+
+fn format_open(time: DateTime<Utc>, tz: Tz) -> String {
+    time.with_timezone(&tz)
+        .format("%Y-%m-%d %H:%M:%S %Z")
+        .to_string()
+}
+
+pub struct CampaignReportDisplay {
+    pub summary: CampaignDisplay,
+    pub open_delays: Vec<HistogramBucket>,
+    pub recipients: Vec<CampaignDisplay>,
+}
+
+impl CampaignReportDisplay {
+    pub fn from_report(report: CampaignReport, tz: Tz) -> Self {
+        Self {
+            summary: CampaignDisplay::from_summary(report.summary, tz),
+            open_delays: report.open_delays,
+            recipients: report
+                .recipients
+                .into_iter()
+                .map(|recipient| CampaignDisplay::from_recipient(recipient, tz))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/links.html")]
+pub struct LinksTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+    pub links: Vec<TrackedLinkDisplay>,
+    /// The user's role lets them add and delete links
+    pub can_edit: bool,
+    pub start_date: String,
+    pub end_date: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/service_users.html")]
+pub struct ServiceUsersTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+    pub stats: UserStats,
+    pub start_date: String,
+    pub end_date: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/service_retention.html")]
+pub struct ServiceRetentionTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+    pub retention: Retention,
+    pub start_date: String,
+    pub end_date: String,
+}
+
+/// A tracked link's clicks, formatted for display
+pub struct TrackedLinkDisplay {
+    pub id: String,
+    /// The `/r/:token` URL to share
+    pub url: String,
+    pub name: String,
+    pub campaign: String,
+    pub target_url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+    /// e.g. `12.5%`, empty without a campaign that was opened
+    pub click_through_rate: String,
+}
+
+impl TrackedLinkDisplay {
+    pub fn from_stats(stats: TrackedLinkStats, url: String) -> Self {
+        Self {
+            id: stats.link.id.to_string(),
+            url,
+            name: stats.link.name,
+            campaign: stats.link.campaign,
+            target_url: stats.link.target_url,
+            clicks: stats.clicks,
+            unique_clicks: stats.unique_clicks,
+            click_through_rate: stats
+                .click_through_rate
+                .map(|rate| format!("{:.1}%", rate * 100.0))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/link_detail.html")]
+pub struct LinkDetailTemplate {
+    pub i18n: I18n,
+    pub service: Service,
+    pub link: TrackedLinkDisplay,
+    /// `points` of the clicks-per-day sparkline
+    pub daily_clicks: String,
+    /// Dates of the sparkline's first and last day
+    pub first_day: String,
+    pub last_day: String,
+    pub referrers: Vec<CountedItem>,
+    pub countries: Vec<CountedItem>,
+    pub start_date: String,
+    pub end_date: String,
 }
 
 /// A Hit with pre-formatted timestamps for display in templates
@@ -64,6 +331,8 @@ pub struct HitDisplay {
     pub load_time: Option<f64>,
     pub heartbeats: i32,
     pub initial: bool,
+    pub prefetched: bool,
+    pub referrer_spam: bool,
     /// Formatted start time in user's timezone
     pub start_time: String,
     /// Formatted last seen time in user's timezone
@@ -82,6 +351,8 @@ impl HitDisplay {
             load_time: hit.load_time,
             heartbeats: hit.heartbeats,
             initial: hit.initial,
+            prefetched: hit.prefetched,
+            referrer_spam: hit.referrer_spam,
             start_time: start_local.format("%m/%d %H:%M:%S").to_string(),
             last_seen: last_seen_local.format("%m/%d %H:%M:%S").to_string(),
         }
@@ -91,6 +362,7 @@ impl HitDisplay {
 #[derive(Template)]
 #[template(path = "dashboard/session_detail.html")]
 pub struct SessionDetailTemplate {
+    pub i18n: I18n,
     pub service: Service,
     pub session: SessionDisplay,
     pub hits: Vec<HitDisplay>,
@@ -102,6 +374,7 @@ pub struct SessionDisplay {
     pub identifier: String,
     pub start_time: String,
     pub last_seen: String,
+    pub ended_at: Option<String>,
     pub user_agent: String,
     pub browser: String,
     pub device: String,
@@ -112,6 +385,7 @@ pub struct SessionDisplay {
     pub country: String,
     pub time_zone: String,
     pub is_bounce: bool,
+    pub props: Vec<(String, String)>,
 }
 
 impl SessionDisplay {
@@ -124,6 +398,12 @@ impl SessionDisplay {
             identifier: session.identifier,
             start_time: start_local.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
             last_seen: last_seen_local.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+            ended_at: session.ended_at.map(|ended_at| {
+                ended_at
+                    .with_timezone(&tz)
+                    .format("%Y-%m-%d %H:%M:%S %Z")
+                    .to_string()
+            }),
             user_agent: session.user_agent,
             browser: session.browser,
             device: session.device,
@@ -134,6 +414,7 @@ impl SessionDisplay {
             country: session.country,
             time_zone: session.time_zone,
             is_bounce: session.is_bounce,
+            props: session.props.into_iter().collect(),
         }
     }
 }
@@ -141,32 +422,333 @@ impl SessionDisplay {
 #[derive(Template)]
 #[template(path = "dashboard/location_list.html")]
 pub struct LocationListTemplate {
+    pub i18n: I18n,
     pub service: Service,
     pub locations: Vec<CountedItem>,
     pub total_hits: i64,
+    /// Pages from the sitemap without a hit in the range
+    pub orphan_pages: Vec<String>,
     pub start_date: String,
     pub end_date: String,
 }
 
+#[derive(Template)]
+#[template(path = "dashboard/search.html")]
+pub struct SearchTemplate {
+    pub i18n: I18n,
+    pub query: String,
+    pub results: Vec<SearchResult>,
+}
+
 #[derive(Template)]
 #[template(path = "components/stats_partial.html")]
 pub struct StatsPartialTemplate {
+    pub i18n: I18n,
     pub stats: CoreStats,
     pub service_id: String,
+    /// Breakdown rows the stats are narrowed to, shown as chips
+    pub filters: BreakdownFilters,
+    pub layout: PanelLayout,
 }
 
 #[derive(Template)]
 #[template(path = "components/session_table.html")]
 pub struct SessionTableTemplate {
+    pub i18n: I18n,
     pub sessions: Vec<Session>,
     pub service_id: String,
 }
 
+#[derive(Template)]
+#[template(path = "components/locations_panel.html")]
+pub struct LocationsPanelTemplate {
+    pub i18n: I18n,
+    pub locations: Vec<CountedItem>,
+}
+
+#[derive(Template)]
+#[template(path = "components/content_groups_panel.html")]
+pub struct ContentGroupsPanelTemplate {
+    pub i18n: I18n,
+    /// Whether the service has any content group rules
+    pub configured: bool,
+    pub groups: Vec<CountedItem>,
+    pub service_id: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/dimensions_panel.html")]
+pub struct DimensionsPanelTemplate {
+    pub i18n: I18n,
+    /// Keys of the range's dimensions, with the hits carrying each
+    pub keys: Vec<CountedItem>,
+    /// The key whose values are shown; empty when there are none
+    pub key: String,
+    pub values: Vec<CountedItem>,
+    pub service_id: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/referrers_panel.html")]
+pub struct ReferrersPanelTemplate {
+    pub i18n: I18n,
+    pub referrers: Vec<CountedItem>,
+}
+
+#[derive(Template)]
+#[template(path = "components/countries_panel.html")]
+pub struct CountriesPanelTemplate {
+    pub i18n: I18n,
+    pub countries: Vec<CountedItem>,
+    pub continents: Vec<ContinentCount>,
+}
+
+#[derive(Template)]
+#[template(path = "components/install_status.html")]
+pub struct InstallStatusTemplate {
+    pub i18n: I18n,
+    pub check: InstallCheck,
+}
+
+#[derive(Template)]
+#[template(path = "components/usage_panel.html")]
+pub struct UsagePanelTemplate {
+    pub i18n: I18n,
+    pub usage: QuotaUsage,
+    /// Width of the quota bar, in percent (capped at 100)
+    pub bar_width: i64,
+}
+
+#[derive(Template)]
+#[template(path = "components/data_quality_panel.html")]
+pub struct DataQualityPanelTemplate {
+    pub i18n: I18n,
+    /// Page views turned away in the range, by reason, most first
+    pub dropped: Vec<DroppedHits>,
+    pub total: i64,
+    /// Hits recorded in the range but flagged as referrer spam
+    pub flagged_spam: i64,
+}
+
+#[derive(Template)]
+#[template(path = "components/errors_panel.html")]
+pub struct ErrorsPanelTemplate {
+    pub i18n: I18n,
+    pub service_id: String,
+    /// Whether the service's tracker reports errors
+    pub tracking: bool,
+    /// Errors reported in the range by page, most first
+    pub errors: Vec<PageError>,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/login.html")]
+pub struct LoginTemplate {
+    pub i18n: I18n,
+    pub email: String,
+    /// Message shown above the form, empty if none
+    pub error: String,
+    pub signup_open: bool,
+    /// Invite accepted by logging in, empty if none
+    pub invite: String,
+    /// The password was right but the email address is not verified yet
+    pub unverified: bool,
+    /// Offer logging in through the single sign-on provider
+    pub sso: bool,
+}
+
+/// Second login step for users with two-factor logins on
+#[derive(Template)]
+#[template(path = "dashboard/login_two_factor.html")]
+pub struct TwoFactorLoginTemplate {
+    pub i18n: I18n,
+    /// Signed proof that the password was right
+    pub token: String,
+    /// Message shown above the form, empty if none
+    pub error: String,
+    /// Invite accepted by logging in, empty if none
+    pub invite: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/account.html")]
+pub struct AccountTemplate {
+    pub i18n: I18n,
+    pub user: User,
+    /// Secret offered for turning two-factor logins on; empty when they are on
+    pub totp_secret: String,
+    /// `otpauth://` link for `totp_secret`
+    pub totp_uri: String,
+    /// The latest logins to the account
+    pub attempts: Vec<LoginAttempt>,
+    pub settings: UserSettings,
+    /// Message shown above the forms, empty if none
+    pub error: String,
+    /// Confirmation shown above the forms, empty if none
+    pub notice: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/signup.html")]
+pub struct SignupTemplate {
+    pub i18n: I18n,
+    pub name: String,
+    pub email: String,
+    pub organization_name: String,
+    /// Message shown above the form, empty if none
+    pub error: String,
+    /// The first account joins the default organization instead of creating one
+    pub first_user: bool,
+    /// Invite accepted by signing up, empty if none
+    pub invite: String,
+    /// Name of the organization the invite is for
+    pub invite_organization: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/check_email.html")]
+pub struct CheckEmailTemplate {
+    pub i18n: I18n,
+    pub message: String,
+}
+
+/// The page shown for an error response
+#[derive(Template)]
+#[template(path = "dashboard/error.html")]
+pub struct ErrorTemplate {
+    pub i18n: I18n,
+    pub status: u16,
+    /// Which `error-` message describes the status
+    pub status_key: &'static str,
+    /// What went wrong, for client errors
+    pub detail: String,
+}
+
+/// Asks for an email address to send a reset link to, or with `token` set,
+/// for the new password
+#[derive(Template)]
+#[template(path = "dashboard/password_reset.html")]
+pub struct PasswordResetTemplate {
+    pub i18n: I18n,
+    pub token: String,
+    /// Message shown above the form, empty if none
+    pub error: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/organization.html")]
+pub struct OrganizationTemplate {
+    pub i18n: I18n,
+    pub organization: Organization,
+    /// This month's usage summed over the organization's services
+    pub usage: ServiceUsage,
+    pub members: Vec<Member>,
+    pub tokens: Vec<ApiToken>,
+    /// A token just created, shown this once; empty otherwise
+    pub new_token: String,
+    /// Message shown above the forms, empty if none
+    pub error: String,
+    /// Confirmation shown above the forms, empty if none
+    pub notice: String,
+    /// Members are only managed when logins are required
+    pub multi_tenant: bool,
+    pub user: Option<User>,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard/admin_status.html")]
+pub struct AdminStatusTemplate {
+    pub i18n: I18n,
+    /// When the maintenance runs each day, empty if it is off
+    pub maintenance_hour: String,
+    /// Every maintenance task with its last run, if it has run
+    pub maintenance: Vec<(MaintenanceTask, Option<MaintenanceRun>)>,
+    pub status: SystemStatus,
+    pub database_size: String,
+    pub uptime: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/update_banner.html")]
+pub struct UpdateBannerTemplate {
+    pub i18n: I18n,
+    pub release: Release,
+}
+
+#[derive(Template)]
+#[template(path = "components/org_switcher.html")]
+pub struct OrgSwitcherTemplate {
+    pub i18n: I18n,
+    pub organization: Organization,
+    pub organizations: Vec<Organization>,
+    pub user: Option<User>,
+    /// The user's role lets them manage the organization
+    pub can_manage: bool,
+}
+
+#[derive(Template)]
+#[template(path = "email/invite.txt")]
+pub struct InviteEmailTemplate {
+    pub i18n: I18n,
+    pub organization_name: String,
+    pub link: String,
+}
+
+#[derive(Template)]
+#[template(path = "email/verify_email.txt")]
+pub struct VerifyEmailTemplate {
+    pub i18n: I18n,
+    pub link: String,
+}
+
+#[derive(Template)]
+#[template(path = "email/password_reset.txt")]
+pub struct PasswordResetEmailTemplate {
+    pub i18n: I18n,
+    pub link: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/chart_panel.html")]
+pub struct ChartPanelTemplate {
+    pub i18n: I18n,
+    pub chart_data: ChartData,
+}
+
 // Template helper functions - use as methods in templates
 impl ServiceWithStats {
     pub fn format_count(count: i64) -> String {
         intcomma(count)
     }
+
+    pub fn session_sparkline(&self) -> String {
+        sparkline_points(&self.trend.sessions)
+    }
+
+    pub fn hit_sparkline(&self) -> String {
+        sparkline_points(&self.trend.hits)
+    }
+}
+
+/// Width and height of the sparkline's SVG view box
+const SPARKLINE_WIDTH: f64 = 100.0;
+const SPARKLINE_HEIGHT: f64 = 24.0;
+
+/// `points` of an SVG polyline drawing `values` across the sparkline's view
+/// box, scaled so the largest value touches the top
+pub fn sparkline_points(values: &[i64]) -> String {
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let step = SPARKLINE_WIDTH / values.len().saturating_sub(1).max(1) as f64;
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            // Keep a pixel free at the edges so the stroke isn't clipped
+            let y = SPARKLINE_HEIGHT - 1.0 - (value as f64 / max) * (SPARKLINE_HEIGHT - 2.0);
+            format!("{:.1},{:.1}", i as f64 * step, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 // Helper functions for templates
@@ -184,6 +766,22 @@ pub fn intcomma(value: i64) -> String {
     result
 }
 
+/// Byte count in the largest unit that keeps it at or above 1, e.g. "3.4 MB"
+pub fn filesize(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 pub fn floatformat(value: Option<f64>, precision: i32) -> String {
     match value {
         Some(v) => {
@@ -254,43 +852,6 @@ pub fn urldisplay(url: &str) -> String {
     url.to_string()
 }
 
-pub fn country_name(code: &str) -> String {
-    match code.to_uppercase().as_str() {
-        "US" => "United States",
-        "GB" => "United Kingdom",
-        "DE" => "Germany",
-        "FR" => "France",
-        "CA" => "Canada",
-        "AU" => "Australia",
-        "JP" => "Japan",
-        "CN" => "China",
-        "IN" => "India",
-        "BR" => "Brazil",
-        "RU" => "Russia",
-        "IT" => "Italy",
-        "ES" => "Spain",
-        "MX" => "Mexico",
-        "KR" => "South Korea",
-        "NL" => "Netherlands",
-        "SE" => "Sweden",
-        "CH" => "Switzerland",
-        "PL" => "Poland",
-        "BE" => "Belgium",
-        "AT" => "Austria",
-        "NO" => "Norway",
-        "DK" => "Denmark",
-        "FI" => "Finland",
-        "IE" => "Ireland",
-        "NZ" => "New Zealand",
-        "SG" => "Singapore",
-        "HK" => "Hong Kong",
-        "TW" => "Taiwan",
-        "" => "Unknown",
-        _ => code,
-    }
-    .to_string()
-}
-
 pub fn timeago(dt: &DateTime<Utc>) -> String {
     let now = Utc::now();
     let duration = now.signed_duration_since(*dt);
@@ -1,15 +1,30 @@
-use chrono::{DateTime, Duration, TimeZone, Timelike, Utc};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
 use chrono_tz::Tz;
+use futures_util::TryStreamExt;
 use regex::Regex;
-use std::collections::HashMap;
+use sqlx::QueryBuilder;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use url::Url;
 
 use crate::domain::{
-    ChartData, CoreStats, CountedItem, CreateHit, CreateService, CreateSession, DeviceType, Hit,
-    HitId, Service, ServiceId, ServiceStatus, Session, SessionId, TrackerType, TrackingId,
-    UpdateService,
+    ApiToken, ApiTokenId, BadgeCounts, BounceRule, CampaignRecipient, CampaignReport,
+    CampaignSummary, ChartData, ChartGranularity, CompareMode, ComparedPeriod, ContentGroups,
+    CoreStats, CountedItem, CreateHit, CreateLinkClick, CreateOrganization, CreatePageError,
+    CreateSavedView, CreateSegment, CreateService, CreateSession, CreateTrackedLink, DailyTrend,
+    DateRangePreset, DeviceType, DroppedHits, Environment, ExpiryCheck, ExportedHit,
+    HistogramBucket, Hit, HitId, HourCycle, IngressDecision, LinkId, LiveCounters, LoginAttempt,
+    LoginFailures, LoginOutcome, MaintenanceRun, MaintenanceTask, Member, MonitorCheck,
+    Organization, OrganizationId, PageError, PageTransition, PanelLayout, QuotaBehavior,
+    QuotaUsage, ReferrerFilter, Retention, Role, SavedView, SavedViewId, SearchResult,
+    SearchResultKind, Segment, SegmentCondition, SegmentField, SegmentId, SegmentOp, Service,
+    ServiceId, ServiceStatus, ServiceUsage, Session, SessionHistogram, SessionId,
+    SessionPropFilter, ThemeMode, TimeSeries, TrackedLink, TrackedLinkReport, TrackedLinkStats,
+    TrackerType, TrackingId, UpdateOrganization, UpdateService, Uptime, User, UserId, UserSettings,
+    UserStats, WeekStart,
 };
 use crate::error::{Error, Result};
+use crate::partitions::HitPartition;
+use crate::sketch::VisitorSketch;
 
 #[cfg(feature = "postgres")]
 pub type Pool = sqlx::PgPool;
@@ -21,7 +36,36 @@ pub type Pool = sqlx::SqlitePool;
 #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
 pub type PoolOptions = sqlx::sqlite::SqlitePoolOptions;
 
+#[cfg(feature = "postgres")]
+type Db = sqlx::Postgres;
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+type Db = sqlx::Sqlite;
+
 const RESULTS_LIMIT: i64 = 300;
+/// Characters in the token of a tracked link's `/r/:token` URL
+const LINK_TOKEN_LENGTH: usize = 10;
+
+/// Columns selected into a `ServiceRow`, shared by every service query
+const SERVICE_COLUMNS: &str = "id, tracking_id, name, link, origins, status, respect_dnt, \
+     ignore_robots, collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at, \
+     collapse_tabs, hit_quota, quota_behavior, organization_id, heartbeat_frequency_ms, \
+     idle_timeout_mins, public_badge, content_groups, active_user_timeout_ms, lowercase_paths, \
+     strip_trailing_slash, path_patterns, time_zone, default_range, signed_pixel_ids, \
+     bounce_rule, bounce_threshold_secs, session_timeout_mins, week_start, debug_log, respect_gpc, \
+     link_users, hide_internal_referrers, track_errors, allowed_countries, blocked_countries, \
+     allowed_asns, blocked_asns";
+
+/// Columns selected into an `OrganizationRow`
+const ORGANIZATION_COLUMNS: &str =
+    "id, name, slug, hit_quota, quota_behavior, logo_url, accent_color, theme_mode, created_at";
+
+/// Columns selected into a `UserRow`
+const USER_COLUMNS: &str =
+    "id, email, name, password_hash, email_verified_at, totp_secret, created_at";
+
+/// Columns selected into an `ApiTokenRow`
+const API_TOKEN_COLUMNS: &str =
+    "id, organization_id, name, token_hash, role, user_id, created_at, last_used_at";
 
 /// Normalize a location URL by stripping query parameters and fragments.
 /// Returns just the hostname (if present) and pathname.
@@ -70,65 +114,298 @@ pub async fn create_pool(url: &str) -> Result<Pool> {
     Ok(pool)
 }
 
-pub async fn run_migrations(pool: &Pool) -> Result<()> {
-    #[cfg(feature = "postgres")]
-    {
-        let sql = include_str!("../../migrations/postgres/001_initial.sql");
-        sqlx::raw_sql(sql).execute(pool).await?;
+#[cfg(feature = "postgres")]
+macro_rules! migration {
+    ($file:literal) => {
+        include_str!(concat!("../../migrations/postgres/", $file))
+    };
+}
 
-        // Check if tracking_id column already exists
-        let has_tracking_id: bool = sqlx::query_scalar(
-            "SELECT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'services' AND column_name = 'tracking_id')"
-        )
-        .fetch_one(pool)
-        .await?;
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+macro_rules! migration {
+    ($file:literal) => {
+        include_str!(concat!("../../migrations/sqlite/", $file))
+    };
+}
 
-        if !has_tracking_id {
-            let sql = include_str!("../../migrations/postgres/002_tracking_id.sql");
-            sqlx::raw_sql(sql).execute(pool).await?;
-        }
-    }
+/// A migration applied after `001_initial.sql`. Scripts that add a column are
+/// guarded by a column check because `ALTER TABLE ... ADD COLUMN` is not
+/// idempotent on SQLite; all other scripts must be idempotent themselves
+/// (`CREATE TABLE IF NOT EXISTS`, ...). Each script runs in a transaction, so
+/// one adding several columns can't stop halfway with its guard column added
+/// and the rest missing.
+struct Migration {
+    sql: &'static str,
+    /// `(table, column)` added by this script, if any
+    adds_column: Option<(&'static str, &'static str)>,
+}
 
-    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    {
-        let sql = include_str!("../../migrations/sqlite/001_initial.sql");
-        sqlx::raw_sql(sql).execute(pool).await?;
+/// Applied in order after `001_initial.sql`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        sql: migration!("002_tracking_id.sql"),
+        adds_column: Some(("services", "tracking_id")),
+    },
+    Migration {
+        sql: migration!("003_collapse_tabs.sql"),
+        adds_column: Some(("services", "collapse_tabs")),
+    },
+    Migration {
+        sql: migration!("004_saved_views.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("005_hit_quota.sql"),
+        adds_column: Some(("services", "hit_quota")),
+    },
+    Migration {
+        sql: migration!("006_service_usage.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("007_organizations.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("008_service_organization.sql"),
+        adds_column: Some(("services", "organization_id")),
+    },
+    Migration {
+        sql: migration!("009_email_verification.sql"),
+        adds_column: Some(("users", "email_verified_at")),
+    },
+    Migration {
+        sql: migration!("010_roles.sql"),
+        adds_column: Some(("memberships", "role")),
+    },
+    Migration {
+        sql: migration!("011_login_attempts.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("012_two_factor.sql"),
+        adds_column: Some(("users", "totp_secret")),
+    },
+    Migration {
+        sql: migration!("013_heartbeats.sql"),
+        adds_column: Some(("services", "heartbeat_frequency_ms")),
+    },
+    Migration {
+        sql: migration!("014_session_end.sql"),
+        adds_column: Some(("sessions", "ended_at")),
+    },
+    Migration {
+        sql: migration!("015_monitor_checks.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("016_expiry_checks.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("017_public_badge.sql"),
+        adds_column: Some(("services", "public_badge")),
+    },
+    Migration {
+        sql: migration!("018_pages.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("019_content_groups.sql"),
+        adds_column: Some(("services", "content_groups")),
+    },
+    Migration {
+        sql: migration!("020_active_user_timeout.sql"),
+        adds_column: Some(("services", "active_user_timeout_ms")),
+    },
+    Migration {
+        sql: migration!("021_environments.sql"),
+        adds_column: Some(("hits", "environment")),
+    },
+    Migration {
+        sql: migration!("022_path_normalization.sql"),
+        adds_column: Some(("services", "path_patterns")),
+    },
+    Migration {
+        sql: migration!("023_top_locations_daily.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("024_time_zones.sql"),
+        adds_column: Some(("services", "time_zone")),
+    },
+    Migration {
+        sql: migration!("025_default_date_range.sql"),
+        adds_column: Some(("services", "default_range")),
+    },
+    Migration {
+        sql: migration!("026_visitor_sketches.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("027_hit_filters.sql"),
+        adds_column: Some(("service_usage", "duplicates")),
+    },
+    Migration {
+        sql: migration!("028_prefetched_hits.sql"),
+        adds_column: Some(("hits", "prefetched")),
+    },
+    Migration {
+        sql: migration!("029_hit_dimensions.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("030_session_props.sql"),
+        adds_column: Some(("sessions", "props")),
+    },
+    Migration {
+        sql: migration!("031_segments.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("032_maintenance_runs.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("033_ingest_scripts.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("034_signed_pixel_ids.sql"),
+        adds_column: Some(("services", "signed_pixel_ids")),
+    },
+    Migration {
+        sql: migration!("035_tracked_links.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("036_short_links.sql"),
+        adds_column: Some(("link_clicks", "country")),
+    },
+    Migration {
+        sql: migration!("037_bounce_rules.sql"),
+        adds_column: Some(("sessions", "interacted")),
+    },
+    Migration {
+        sql: migration!("038_session_timeout.sql"),
+        adds_column: Some(("services", "session_timeout_mins")),
+    },
+    Migration {
+        sql: migration!("039_hit_dimension_times.sql"),
+        adds_column: Some(("hit_dimensions", "hit_start_time")),
+    },
+    Migration {
+        sql: migration!("040_organization_themes.sql"),
+        adds_column: Some(("organizations", "logo_url")),
+    },
+    Migration {
+        sql: migration!("041_format_preferences.sql"),
+        adds_column: Some(("user_settings", "week_start")),
+    },
+    Migration {
+        sql: migration!("042_service_week_start.sql"),
+        adds_column: Some(("services", "week_start")),
+    },
+    Migration {
+        sql: migration!("043_ingest_debug_log.sql"),
+        adds_column: Some(("services", "debug_log")),
+    },
+    Migration {
+        sql: migration!("044_dropped_hits.sql"),
+        adds_column: None,
+    },
+    Migration {
+        sql: migration!("045_service_respect_gpc.sql"),
+        adds_column: Some(("services", "respect_gpc")),
+    },
+    Migration {
+        sql: migration!("046_user_linking.sql"),
+        adds_column: Some(("services", "link_users")),
+    },
+    Migration {
+        sql: migration!("047_ip_encryption.sql"),
+        adds_column: Some(("sessions", "ip_encrypted")),
+    },
+    Migration {
+        sql: migration!("048_hide_internal_referrers.sql"),
+        adds_column: Some(("services", "hide_internal_referrers")),
+    },
+    Migration {
+        sql: migration!("049_referrer_spam.sql"),
+        adds_column: Some(("hits", "referrer_spam")),
+    },
+    Migration {
+        sql: migration!("050_errors.sql"),
+        adds_column: Some(("services", "track_errors")),
+    },
+    Migration {
+        sql: migration!("051_geo_rules.sql"),
+        adds_column: Some(("services", "blocked_asns")),
+    },
+];
 
-        // Check if tracking_id column already exists
-        let columns: Vec<(String,)> = sqlx::query_as(
-            "SELECT name FROM pragma_table_info('services') WHERE name = 'tracking_id'",
-        )
-        .fetch_all(pool)
+pub async fn run_migrations(pool: &Pool) -> Result<()> {
+    sqlx::raw_sql(migration!("001_initial.sql"))
+        .execute(pool)
         .await?;
+    apply_migrations(pool, MIGRATIONS).await
+}
 
-        if columns.is_empty() {
-            let sql = include_str!("../../migrations/sqlite/002_tracking_id.sql");
-            sqlx::raw_sql(sql).execute(pool).await?;
+async fn apply_migrations(pool: &Pool, migrations: &[Migration]) -> Result<()> {
+    for migration in migrations {
+        if let Some((table, column)) = migration.adds_column {
+            if has_column(pool, table, column).await? {
+                continue;
+            }
         }
+        let mut tx = pool.begin().await?;
+        sqlx::raw_sql(migration.sql).execute(&mut *tx).await?;
+        tx.commit().await?;
     }
 
     Ok(())
 }
 
+/// Check whether `table` already has `column`
+async fn has_column(pool: &Pool, table: &str, column: &str) -> Result<bool> {
+    #[cfg(feature = "postgres")]
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = $1 AND column_name = $2)",
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let exists: bool = {
+        let columns: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM pragma_table_info(?) WHERE name = ?")
+                .bind(table)
+                .bind(column)
+                .fetch_all(pool)
+                .await?;
+        !columns.is_empty()
+    };
+
+    Ok(exists)
+}
+
 // Service queries
 pub async fn get_service(pool: &Pool, id: ServiceId) -> Result<Service> {
     #[cfg(feature = "postgres")]
-    let row: ServiceRow = sqlx::query_as(
-        r#"SELECT id, tracking_id, name, link, origins, status, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at
-           FROM services WHERE id = $1"#,
-    )
+    let row: ServiceRow = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE id = $1"
+    ))
     .bind(id.0)
     .fetch_optional(pool)
     .await?
     .ok_or(Error::ServiceNotFound)?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let row: ServiceRow = sqlx::query_as(
-        r#"SELECT id, tracking_id, name, link, origins, status, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at
-           FROM services WHERE id = ?"#,
-    )
+    let row: ServiceRow = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE id = ?"
+    ))
     .bind(id.0.to_string())
     .fetch_optional(pool)
     .await?
@@ -174,24 +451,34 @@ pub async fn get_active_service(pool: &Pool, id: ServiceId) -> Result<Service> {
     Ok(service)
 }
 
+/// A service owned by the given organization; services of other
+/// organizations are reported as not found
+pub async fn get_organization_service(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    id: ServiceId,
+) -> Result<Service> {
+    let service = get_service(pool, id).await?;
+    if service.organization_id != organization_id {
+        return Err(Error::ServiceNotFound);
+    }
+    Ok(service)
+}
+
 pub async fn get_service_by_tracking_id(pool: &Pool, tracking_id: &str) -> Result<Service> {
     #[cfg(feature = "postgres")]
-    let row: ServiceRow = sqlx::query_as(
-        r#"SELECT id, tracking_id, name, link, origins, status, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at
-           FROM services WHERE tracking_id = $1"#,
-    )
+    let row: ServiceRow = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE tracking_id = $1"
+    ))
     .bind(tracking_id)
     .fetch_optional(pool)
     .await?
     .ok_or(Error::ServiceNotFound)?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let row: ServiceRow = sqlx::query_as(
-        r#"SELECT id, tracking_id, name, link, origins, status, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at
-           FROM services WHERE tracking_id = ?"#,
-    )
+    let row: ServiceRow = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE tracking_id = ?"
+    ))
     .bind(tracking_id)
     .fetch_optional(pool)
     .await?
@@ -208,22 +495,21 @@ pub async fn get_active_service_by_tracking_id(pool: &Pool, tracking_id: &str) -
     Ok(service)
 }
 
-pub async fn list_services(pool: &Pool) -> Result<Vec<Service>> {
+/// Services owned by an organization
+pub async fn list_services(pool: &Pool, organization_id: OrganizationId) -> Result<Vec<Service>> {
     #[cfg(feature = "postgres")]
-    let rows: Vec<ServiceRow> = sqlx::query_as(
-        r#"SELECT id, tracking_id, name, link, origins, status, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at
-           FROM services ORDER BY name, id"#,
-    )
+    let rows: Vec<ServiceRow> = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE organization_id = $1 ORDER BY name, id"
+    ))
+    .bind(organization_id.0)
     .fetch_all(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let rows: Vec<ServiceRow> = sqlx::query_as(
-        r#"SELECT id, tracking_id, name, link, origins, status, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at
-           FROM services ORDER BY name, id"#,
-    )
+    let rows: Vec<ServiceRow> = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE organization_id = ? ORDER BY name, id"
+    ))
+    .bind(organization_id.0.to_string())
     .fetch_all(pool)
     .await?;
 
@@ -254,13 +540,22 @@ pub async fn list_services(pool: &Pool) -> Result<Vec<Service>> {
 pub async fn create_service(pool: &Pool, input: CreateService) -> Result<Service> {
     let id = ServiceId::new();
     let tracking_id = TrackingId::new();
+    let organization_id = input.organization_id.unwrap_or(OrganizationId::DEFAULT);
     let now = Utc::now();
 
     #[cfg(feature = "postgres")]
     sqlx::query(
         r#"INSERT INTO services (id, tracking_id, name, link, origins, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at)
-           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#,
+           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at, collapse_tabs,
+           hit_quota, quota_behavior, organization_id, heartbeat_frequency_ms, idle_timeout_mins,
+           public_badge, content_groups, active_user_timeout_ms, lowercase_paths,
+           strip_trailing_slash, path_patterns, time_zone, default_range, signed_pixel_ids,
+           bounce_rule, bounce_threshold_secs, session_timeout_mins, week_start, debug_log,
+           respect_gpc, link_users, hide_internal_referrers, track_errors, allowed_countries,
+           blocked_countries, allowed_asns, blocked_asns)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18,
+           $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32, $33, $34, $35, $36,
+           $37, $38, $39, $40)"#,
     )
     .bind(id.0)
     .bind(&tracking_id.0)
@@ -274,14 +569,49 @@ pub async fn create_service(pool: &Pool, input: CreateService) -> Result<Service
     .bind(&input.hide_referrer_regex)
     .bind(&input.script_inject)
     .bind(now)
+    .bind(input.collapse_tabs)
+    .bind(input.hit_quota)
+    .bind(input.quota_behavior.as_str())
+    .bind(organization_id.0)
+    .bind(input.heartbeat_frequency_ms)
+    .bind(input.idle_timeout_mins)
+    .bind(input.public_badge)
+    .bind(&input.content_groups)
+    .bind(input.active_user_timeout_ms)
+    .bind(input.lowercase_paths)
+    .bind(input.strip_trailing_slash)
+    .bind(&input.path_patterns)
+    .bind(&input.time_zone)
+    .bind(input.default_range.as_str())
+    .bind(input.signed_pixel_ids)
+    .bind(input.bounce_rule.as_str())
+    .bind(input.bounce_threshold_secs)
+    .bind(input.session_timeout_mins)
+    .bind(input.week_start.map_or("", |day| day.as_str()))
+    .bind(input.debug_log)
+    .bind(input.respect_gpc)
+    .bind(input.link_users)
+    .bind(input.hide_internal_referrers)
+    .bind(input.track_errors)
+    .bind(&input.allowed_countries)
+    .bind(&input.blocked_countries)
+    .bind(&input.allowed_asns)
+    .bind(&input.blocked_asns)
     .execute(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
     sqlx::query(
         r#"INSERT INTO services (id, tracking_id, name, link, origins, respect_dnt, ignore_robots,
-           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at)
-           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+           collect_ips, ignored_ips, hide_referrer_regex, script_inject, created_at, collapse_tabs,
+           hit_quota, quota_behavior, organization_id, heartbeat_frequency_ms, idle_timeout_mins,
+           public_badge, content_groups, active_user_timeout_ms, lowercase_paths,
+           strip_trailing_slash, path_patterns, time_zone, default_range, signed_pixel_ids,
+           bounce_rule, bounce_threshold_secs, session_timeout_mins, week_start, debug_log,
+           respect_gpc, link_users, hide_internal_referrers, track_errors, allowed_countries,
+           blocked_countries, allowed_asns, blocked_asns)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+           ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
     )
     .bind(id.0.to_string())
     .bind(&tracking_id.0)
@@ -295,6 +625,34 @@ pub async fn create_service(pool: &Pool, input: CreateService) -> Result<Service
     .bind(&input.hide_referrer_regex)
     .bind(&input.script_inject)
     .bind(now.to_rfc3339())
+    .bind(input.collapse_tabs)
+    .bind(input.hit_quota)
+    .bind(input.quota_behavior.as_str())
+    .bind(organization_id.0.to_string())
+    .bind(input.heartbeat_frequency_ms)
+    .bind(input.idle_timeout_mins)
+    .bind(input.public_badge)
+    .bind(&input.content_groups)
+    .bind(input.active_user_timeout_ms)
+    .bind(input.lowercase_paths)
+    .bind(input.strip_trailing_slash)
+    .bind(&input.path_patterns)
+    .bind(&input.time_zone)
+    .bind(input.default_range.as_str())
+    .bind(input.signed_pixel_ids)
+    .bind(input.bounce_rule.as_str())
+    .bind(input.bounce_threshold_secs)
+    .bind(input.session_timeout_mins)
+    .bind(input.week_start.map_or("", |day| day.as_str()))
+    .bind(input.debug_log)
+    .bind(input.respect_gpc)
+    .bind(input.link_users)
+    .bind(input.hide_internal_referrers)
+    .bind(input.track_errors)
+    .bind(&input.allowed_countries)
+    .bind(&input.blocked_countries)
+    .bind(&input.allowed_asns)
+    .bind(&input.blocked_asns)
     .execute(pool)
     .await?;
 
@@ -316,13 +674,63 @@ pub async fn update_service(pool: &Pool, id: ServiceId, input: UpdateService) ->
         .hide_referrer_regex
         .unwrap_or(service.hide_referrer_regex);
     let script_inject = input.script_inject.unwrap_or(service.script_inject);
+    let collapse_tabs = input.collapse_tabs.unwrap_or(service.collapse_tabs);
+    let hit_quota = input.hit_quota.unwrap_or(service.hit_quota);
+    let quota_behavior = input.quota_behavior.unwrap_or(service.quota_behavior);
+    let heartbeat_frequency_ms = input
+        .heartbeat_frequency_ms
+        .unwrap_or(service.heartbeat_frequency_ms);
+    let idle_timeout_mins = input.idle_timeout_mins.unwrap_or(service.idle_timeout_mins);
+    let public_badge = input.public_badge.unwrap_or(service.public_badge);
+    let content_groups = input.content_groups.unwrap_or(service.content_groups);
+    let active_user_timeout_ms = input
+        .active_user_timeout_ms
+        .unwrap_or(service.active_user_timeout_ms);
+    let lowercase_paths = input.lowercase_paths.unwrap_or(service.lowercase_paths);
+    let strip_trailing_slash = input
+        .strip_trailing_slash
+        .unwrap_or(service.strip_trailing_slash);
+    let path_patterns = input.path_patterns.unwrap_or(service.path_patterns);
+    let time_zone = input.time_zone.unwrap_or(service.time_zone);
+    let default_range = input.default_range.unwrap_or(service.default_range);
+    let signed_pixel_ids = input.signed_pixel_ids.unwrap_or(service.signed_pixel_ids);
+    let bounce_rule = input.bounce_rule.unwrap_or(service.bounce_rule);
+    let bounce_threshold_secs = input
+        .bounce_threshold_secs
+        .unwrap_or(service.bounce_threshold_secs);
+    let session_timeout_mins = input
+        .session_timeout_mins
+        .unwrap_or(service.session_timeout_mins);
+    let week_start = input
+        .week_start
+        .unwrap_or(service.week_start)
+        .map_or("", |day| day.as_str());
+    let debug_log = input.debug_log.unwrap_or(service.debug_log);
+    let respect_gpc = input.respect_gpc.unwrap_or(service.respect_gpc);
+    let link_users = input.link_users.unwrap_or(service.link_users);
+    let hide_internal_referrers = input
+        .hide_internal_referrers
+        .unwrap_or(service.hide_internal_referrers);
+    let track_errors = input.track_errors.unwrap_or(service.track_errors);
+    let allowed_countries = input.allowed_countries.unwrap_or(service.allowed_countries);
+    let blocked_countries = input.blocked_countries.unwrap_or(service.blocked_countries);
+    let allowed_asns = input.allowed_asns.unwrap_or(service.allowed_asns);
+    let blocked_asns = input.blocked_asns.unwrap_or(service.blocked_asns);
 
     #[cfg(feature = "postgres")]
     sqlx::query(
         r#"UPDATE services SET name = $1, link = $2, origins = $3, status = $4,
            respect_dnt = $5, ignore_robots = $6, collect_ips = $7, ignored_ips = $8,
-           hide_referrer_regex = $9, script_inject = $10
-           WHERE id = $11"#,
+           hide_referrer_regex = $9, script_inject = $10, collapse_tabs = $11,
+           hit_quota = $12, quota_behavior = $13, heartbeat_frequency_ms = $14,
+           idle_timeout_mins = $15, public_badge = $16, content_groups = $17,
+           active_user_timeout_ms = $18, lowercase_paths = $19, strip_trailing_slash = $20,
+           path_patterns = $21, time_zone = $22, default_range = $23, signed_pixel_ids = $24,
+           bounce_rule = $25, bounce_threshold_secs = $26, session_timeout_mins = $27,
+           week_start = $28, debug_log = $29, respect_gpc = $30,
+           link_users = $31, hide_internal_referrers = $32, track_errors = $33,
+           allowed_countries = $34, blocked_countries = $35, allowed_asns = $36,
+           blocked_asns = $37 WHERE id = $38"#,
     )
     .bind(&name)
     .bind(&link)
@@ -334,6 +742,33 @@ pub async fn update_service(pool: &Pool, id: ServiceId, input: UpdateService) ->
     .bind(&ignored_ips)
     .bind(&hide_referrer_regex)
     .bind(&script_inject)
+    .bind(collapse_tabs)
+    .bind(hit_quota)
+    .bind(quota_behavior.as_str())
+    .bind(heartbeat_frequency_ms)
+    .bind(idle_timeout_mins)
+    .bind(public_badge)
+    .bind(&content_groups)
+    .bind(active_user_timeout_ms)
+    .bind(lowercase_paths)
+    .bind(strip_trailing_slash)
+    .bind(&path_patterns)
+    .bind(&time_zone)
+    .bind(default_range.as_str())
+    .bind(signed_pixel_ids)
+    .bind(bounce_rule.as_str())
+    .bind(bounce_threshold_secs)
+    .bind(session_timeout_mins)
+    .bind(week_start)
+    .bind(debug_log)
+    .bind(respect_gpc)
+    .bind(link_users)
+    .bind(hide_internal_referrers)
+    .bind(track_errors)
+    .bind(&allowed_countries)
+    .bind(&blocked_countries)
+    .bind(&allowed_asns)
+    .bind(&blocked_asns)
     .bind(id.0)
     .execute(pool)
     .await?;
@@ -342,8 +777,16 @@ pub async fn update_service(pool: &Pool, id: ServiceId, input: UpdateService) ->
     sqlx::query(
         r#"UPDATE services SET name = ?, link = ?, origins = ?, status = ?,
            respect_dnt = ?, ignore_robots = ?, collect_ips = ?, ignored_ips = ?,
-           hide_referrer_regex = ?, script_inject = ?
-           WHERE id = ?"#,
+           hide_referrer_regex = ?, script_inject = ?, collapse_tabs = ?,
+           hit_quota = ?, quota_behavior = ?, heartbeat_frequency_ms = ?,
+           idle_timeout_mins = ?, public_badge = ?, content_groups = ?,
+           active_user_timeout_ms = ?, lowercase_paths = ?, strip_trailing_slash = ?,
+           path_patterns = ?, time_zone = ?, default_range = ?, signed_pixel_ids = ?,
+           bounce_rule = ?, bounce_threshold_secs = ?, session_timeout_mins = ?,
+           week_start = ?, debug_log = ?, respect_gpc = ?,
+           link_users = ?, hide_internal_referrers = ?, track_errors = ?,
+           allowed_countries = ?, blocked_countries = ?, allowed_asns = ?,
+           blocked_asns = ? WHERE id = ?"#,
     )
     .bind(&name)
     .bind(&link)
@@ -355,6 +798,33 @@ pub async fn update_service(pool: &Pool, id: ServiceId, input: UpdateService) ->
     .bind(&ignored_ips)
     .bind(&hide_referrer_regex)
     .bind(&script_inject)
+    .bind(collapse_tabs)
+    .bind(hit_quota)
+    .bind(quota_behavior.as_str())
+    .bind(heartbeat_frequency_ms)
+    .bind(idle_timeout_mins)
+    .bind(public_badge)
+    .bind(&content_groups)
+    .bind(active_user_timeout_ms)
+    .bind(lowercase_paths)
+    .bind(strip_trailing_slash)
+    .bind(&path_patterns)
+    .bind(&time_zone)
+    .bind(default_range.as_str())
+    .bind(signed_pixel_ids)
+    .bind(bounce_rule.as_str())
+    .bind(bounce_threshold_secs)
+    .bind(session_timeout_mins)
+    .bind(week_start)
+    .bind(debug_log)
+    .bind(respect_gpc)
+    .bind(link_users)
+    .bind(hide_internal_referrers)
+    .bind(track_errors)
+    .bind(&allowed_countries)
+    .bind(&blocked_countries)
+    .bind(&allowed_asns)
+    .bind(&blocked_asns)
     .bind(id.0.to_string())
     .execute(pool)
     .await?;
@@ -378,113 +848,108 @@ pub async fn delete_service(pool: &Pool, id: ServiceId) -> Result<()> {
     Ok(())
 }
 
-// Session queries
-pub async fn get_session(pool: &Pool, id: SessionId) -> Result<Session> {
+// Saved view queries
+pub async fn list_saved_views(pool: &Pool, service_id: ServiceId) -> Result<Vec<SavedView>> {
     #[cfg(feature = "postgres")]
-    let row: SessionRow = sqlx::query_as(
-        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
-           browser, device, device_type, os, ip::TEXT, asn, country, longitude,
-           latitude, time_zone, is_bounce
-           FROM sessions WHERE id = $1"#,
+    let rows: Vec<SavedViewRow> = sqlx::query_as(
+        r#"SELECT id, service_id, name, start_date, end_date, url_pattern, layout, created_at
+           FROM saved_views WHERE service_id = $1 ORDER BY name"#,
+    )
+    .bind(service_id.0)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<SavedViewRow> = sqlx::query_as(
+        r#"SELECT id, service_id, name, start_date, end_date, url_pattern, layout, created_at
+           FROM saved_views WHERE service_id = ? ORDER BY name"#,
+    )
+    .bind(service_id.0.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+pub async fn get_saved_view(pool: &Pool, id: SavedViewId) -> Result<SavedView> {
+    #[cfg(feature = "postgres")]
+    let row: SavedViewRow = sqlx::query_as(
+        r#"SELECT id, service_id, name, start_date, end_date, url_pattern, layout, created_at
+           FROM saved_views WHERE id = $1"#,
     )
     .bind(id.0)
     .fetch_optional(pool)
     .await?
-    .ok_or(Error::SessionNotFound)?;
+    .ok_or(Error::SavedViewNotFound)?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let row: SessionRow = sqlx::query_as(
-        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
-           browser, device, device_type, os, ip, asn, country, longitude,
-           latitude, time_zone, is_bounce
-           FROM sessions WHERE id = ?"#,
+    let row: SavedViewRow = sqlx::query_as(
+        r#"SELECT id, service_id, name, start_date, end_date, url_pattern, layout, created_at
+           FROM saved_views WHERE id = ?"#,
     )
     .bind(id.0.to_string())
     .fetch_optional(pool)
     .await?
-    .ok_or(Error::SessionNotFound)?;
+    .ok_or(Error::SavedViewNotFound)?;
 
     Ok(row.into())
 }
 
-pub async fn create_session(pool: &Pool, input: CreateSession) -> Result<Session> {
-    let id = SessionId::new();
+pub async fn create_saved_view(
+    pool: &Pool,
+    service_id: ServiceId,
+    input: CreateSavedView,
+) -> Result<SavedView> {
+    let id = SavedViewId::new();
+    let now = Utc::now();
+    let layout = input.layout.to_string();
 
     #[cfg(feature = "postgres")]
-    {
-        // Use a query that casts the IP string to INET type
-        sqlx::query(
-            r#"INSERT INTO sessions (id, service_id, identifier, start_time, last_seen,
-               user_agent, browser, device, device_type, os, ip, asn, country,
-               longitude, latitude, time_zone, is_bounce)
-               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11::INET, $12, $13, $14, $15, $16, $17)"#
-        )
-        .bind(id.0)
-        .bind(input.service_id.0)
-        .bind(&input.identifier)
-        .bind(input.start_time)
-        .bind(input.start_time)
-        .bind(&input.user_agent)
-        .bind(&input.browser)
-        .bind(&input.device)
-        .bind(input.device_type.as_str())
-        .bind(&input.os)
-        .bind(&input.ip)  // Pass as string, cast in query
-        .bind(&input.asn)
-        .bind(&input.country)
-        .bind(input.longitude)
-        .bind(input.latitude)
-        .bind(&input.time_zone)
-        .bind(true)
-        .execute(pool)
-        .await?;
-    }
+    sqlx::query(
+        r#"INSERT INTO saved_views (id, service_id, name, start_date, end_date, url_pattern,
+           layout, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+    )
+    .bind(id.0)
+    .bind(service_id.0)
+    .bind(&input.name)
+    .bind(&input.start_date)
+    .bind(&input.end_date)
+    .bind(&input.url_pattern)
+    .bind(&layout)
+    .bind(now)
+    .execute(pool)
+    .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
     sqlx::query(
-        r#"INSERT INTO sessions (id, service_id, identifier, start_time, last_seen,
-           user_agent, browser, device, device_type, os, ip, asn, country,
-           longitude, latitude, time_zone, is_bounce)
-           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+        r#"INSERT INTO saved_views (id, service_id, name, start_date, end_date, url_pattern,
+           layout, created_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#,
     )
     .bind(id.0.to_string())
-    .bind(input.service_id.0.to_string())
-    .bind(&input.identifier)
-    .bind(input.start_time.to_rfc3339())
-    .bind(input.start_time.to_rfc3339())
-    .bind(&input.user_agent)
-    .bind(&input.browser)
-    .bind(&input.device)
-    .bind(input.device_type.as_str())
-    .bind(&input.os)
-    .bind(&input.ip)
-    .bind(&input.asn)
-    .bind(&input.country)
-    .bind(input.longitude)
-    .bind(input.latitude)
-    .bind(&input.time_zone)
-    .bind(true)
+    .bind(service_id.0.to_string())
+    .bind(&input.name)
+    .bind(&input.start_date)
+    .bind(&input.end_date)
+    .bind(&input.url_pattern)
+    .bind(&layout)
+    .bind(now.to_rfc3339())
     .execute(pool)
     .await?;
 
-    get_session(pool, id).await
+    get_saved_view(pool, id).await
 }
 
-pub async fn update_session_last_seen(
-    pool: &Pool,
-    id: SessionId,
-    last_seen: DateTime<Utc>,
-) -> Result<()> {
+pub async fn delete_saved_view(pool: &Pool, id: SavedViewId) -> Result<()> {
     #[cfg(feature = "postgres")]
-    sqlx::query("UPDATE sessions SET last_seen = $1 WHERE id = $2")
-        .bind(last_seen)
+    sqlx::query("DELETE FROM saved_views WHERE id = $1")
         .bind(id.0)
         .execute(pool)
         .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    sqlx::query("UPDATE sessions SET last_seen = ? WHERE id = ?")
-        .bind(last_seen.to_rfc3339())
+    sqlx::query("DELETE FROM saved_views WHERE id = ?")
         .bind(id.0.to_string())
         .execute(pool)
         .await?;
@@ -492,482 +957,488 @@ pub async fn update_session_last_seen(
     Ok(())
 }
 
-pub async fn update_session_identifier(pool: &Pool, id: SessionId, identifier: &str) -> Result<()> {
+// Segment queries
+pub async fn list_segments(pool: &Pool, service_id: ServiceId) -> Result<Vec<Segment>> {
     #[cfg(feature = "postgres")]
-    sqlx::query("UPDATE sessions SET identifier = $1 WHERE id = $2")
-        .bind(identifier)
-        .bind(id.0)
-        .execute(pool)
-        .await?;
+    let rows: Vec<SegmentRow> = sqlx::query_as(
+        r#"SELECT id, service_id, name, conditions, created_at
+           FROM segments WHERE service_id = $1 ORDER BY name"#,
+    )
+    .bind(service_id.0)
+    .fetch_all(pool)
+    .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    sqlx::query("UPDATE sessions SET identifier = ? WHERE id = ?")
-        .bind(identifier)
-        .bind(id.0.to_string())
-        .execute(pool)
-        .await?;
+    let rows: Vec<SegmentRow> = sqlx::query_as(
+        r#"SELECT id, service_id, name, conditions, created_at
+           FROM segments WHERE service_id = ? ORDER BY name"#,
+    )
+    .bind(service_id.0.to_string())
+    .fetch_all(pool)
+    .await?;
 
-    Ok(())
+    Ok(rows.into_iter().map(Into::into).collect())
 }
 
-pub async fn recalculate_session_bounce(pool: &Pool, session_id: SessionId) -> Result<()> {
+pub async fn get_segment(pool: &Pool, id: SegmentId) -> Result<Segment> {
     #[cfg(feature = "postgres")]
-    {
-        let hit_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE session_id = $1")
-            .bind(session_id.0)
-            .fetch_one(pool)
-            .await?;
-
-        let is_bounce = hit_count <= 1;
-        sqlx::query("UPDATE sessions SET is_bounce = $1 WHERE id = $2")
-            .bind(is_bounce)
-            .bind(session_id.0)
-            .execute(pool)
-            .await?;
-    }
+    let row: SegmentRow = sqlx::query_as(
+        r#"SELECT id, service_id, name, conditions, created_at
+           FROM segments WHERE id = $1"#,
+    )
+    .bind(id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::SegmentNotFound)?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    {
-        let hit_count: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE session_id = ?")
-            .bind(session_id.0.to_string())
-            .fetch_one(pool)
-            .await?;
-
-        let is_bounce = hit_count <= 1;
-        sqlx::query("UPDATE sessions SET is_bounce = ? WHERE id = ?")
-            .bind(is_bounce)
-            .bind(session_id.0.to_string())
-            .execute(pool)
-            .await?;
-    }
+    let row: SegmentRow = sqlx::query_as(
+        r#"SELECT id, service_id, name, conditions, created_at
+           FROM segments WHERE id = ?"#,
+    )
+    .bind(id.0.to_string())
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::SegmentNotFound)?;
 
-    Ok(())
+    Ok(row.into())
 }
 
-pub async fn list_sessions(
+pub async fn create_segment(
     pool: &Pool,
     service_id: ServiceId,
-    start: DateTime<Utc>,
-    end: DateTime<Utc>,
-    url_pattern: Option<&Regex>,
-    limit: i64,
-    offset: i64,
-) -> Result<Vec<Session>> {
-    // If URL pattern is provided, we need to filter sessions that have matching hits
-    if let Some(pattern) = url_pattern {
-        return list_sessions_with_url_filter(pool, service_id, start, end, pattern, limit, offset)
-            .await;
-    }
+    input: CreateSegment,
+) -> Result<Segment> {
+    let id = SegmentId::new();
+    let now = Utc::now();
+    let conditions = serde_json::to_string(&input.conditions)?;
 
     #[cfg(feature = "postgres")]
-    let rows: Vec<SessionRow> = sqlx::query_as(
-        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
-           browser, device, device_type, os, ip::TEXT, asn, country, longitude,
-           latitude, time_zone, is_bounce
-           FROM sessions
-           WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
-           ORDER BY start_time DESC
-           LIMIT $4 OFFSET $5"#,
+    sqlx::query(
+        r#"INSERT INTO segments (id, service_id, name, conditions, created_at)
+           VALUES ($1, $2, $3, $4, $5)"#,
     )
+    .bind(id.0)
     .bind(service_id.0)
-    .bind(start)
-    .bind(end)
-    .bind(limit)
-    .bind(offset)
-    .fetch_all(pool)
+    .bind(input.name.trim())
+    .bind(&conditions)
+    .bind(now)
+    .execute(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let rows: Vec<SessionRow> = sqlx::query_as(
-        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
-           browser, device, device_type, os, ip, asn, country, longitude,
-           latitude, time_zone, is_bounce
-           FROM sessions
-           WHERE service_id = ? AND start_time >= ? AND start_time < ?
-           ORDER BY start_time DESC
-           LIMIT ? OFFSET ?"#,
+    sqlx::query(
+        r#"INSERT INTO segments (id, service_id, name, conditions, created_at)
+           VALUES (?, ?, ?, ?, ?)"#,
     )
+    .bind(id.0.to_string())
     .bind(service_id.0.to_string())
-    .bind(start.to_rfc3339())
-    .bind(end.to_rfc3339())
-    .bind(limit)
-    .bind(offset)
-    .fetch_all(pool)
+    .bind(input.name.trim())
+    .bind(&conditions)
+    .bind(now.to_rfc3339())
+    .execute(pool)
     .await?;
 
-    Ok(rows.into_iter().map(Into::into).collect())
+    get_segment(pool, id).await
 }
 
-async fn list_sessions_with_url_filter(
+pub async fn delete_segment(pool: &Pool, id: SegmentId) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM segments WHERE id = $1")
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM segments WHERE id = ?")
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Usage queries
+
+/// Add to a service's write counters for `month` (`YYYY-MM`)
+pub async fn record_usage(
     pool: &Pool,
     service_id: ServiceId,
-    start: DateTime<Utc>,
-    end: DateTime<Utc>,
-    url_pattern: &Regex,
-    limit: i64,
-    offset: i64,
-) -> Result<Vec<Session>> {
-    // Get session IDs that have hits matching the URL pattern
+    month: &str,
+    hits: i64,
+    sessions: i64,
+    dropped: i64,
+    duplicates: i64,
+) -> Result<()> {
     #[cfg(feature = "postgres")]
-    let session_ids: Vec<(uuid::Uuid,)> = sqlx::query_as(
-        r#"SELECT DISTINCT session_id FROM hits
-           WHERE service_id = $1 AND start_time >= $2 AND start_time < $3"#,
+    sqlx::query(
+        r#"INSERT INTO service_usage (service_id, month, hits, sessions, dropped, duplicates)
+           VALUES ($1, $2, $3, $4, $5, $6)
+           ON CONFLICT (service_id, month) DO UPDATE SET
+           hits = service_usage.hits + excluded.hits,
+           sessions = service_usage.sessions + excluded.sessions,
+           dropped = service_usage.dropped + excluded.dropped,
+           duplicates = service_usage.duplicates + excluded.duplicates"#,
     )
     .bind(service_id.0)
-    .bind(start)
-    .bind(end)
-    .fetch_all(pool)
+    .bind(month)
+    .bind(hits)
+    .bind(sessions)
+    .bind(dropped)
+    .bind(duplicates)
+    .execute(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let session_ids: Vec<(String,)> = sqlx::query_as(
-        r#"SELECT DISTINCT session_id FROM hits
-           WHERE service_id = ? AND start_time >= ? AND start_time < ?"#,
+    sqlx::query(
+        r#"INSERT INTO service_usage (service_id, month, hits, sessions, dropped, duplicates)
+           VALUES (?, ?, ?, ?, ?, ?)
+           ON CONFLICT (service_id, month) DO UPDATE SET
+           hits = service_usage.hits + excluded.hits,
+           sessions = service_usage.sessions + excluded.sessions,
+           dropped = service_usage.dropped + excluded.dropped,
+           duplicates = service_usage.duplicates + excluded.duplicates"#,
     )
     .bind(service_id.0.to_string())
-    .bind(start.to_rfc3339())
-    .bind(end.to_rfc3339())
-    .fetch_all(pool)
+    .bind(month)
+    .bind(hits)
+    .bind(sessions)
+    .bind(dropped)
+    .bind(duplicates)
+    .execute(pool)
     .await?;
 
-    // For each session, check if it has hits matching the pattern
-    let mut matching_session_ids = Vec::new();
-    for (session_id,) in session_ids {
-        #[cfg(feature = "postgres")]
-        let hits: Vec<(String,)> =
-            sqlx::query_as("SELECT location FROM hits WHERE session_id = $1")
-                .bind(session_id)
-                .fetch_all(pool)
-                .await?;
+    Ok(())
+}
 
-        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-        let hits: Vec<(String,)> = sqlx::query_as("SELECT location FROM hits WHERE session_id = ?")
-            .bind(&session_id)
-            .fetch_all(pool)
-            .await?;
+/// Usage counters for each of the given months, newest first. Months with no
+/// recorded writes come back as zeroes.
+pub async fn list_usage(
+    pool: &Pool,
+    service_id: ServiceId,
+    months: &[String],
+) -> Result<Vec<ServiceUsage>> {
+    let Some(oldest) = months.iter().min() else {
+        return Ok(Vec::new());
+    };
 
-        if hits.iter().any(|(loc,)| url_pattern.is_match(loc)) {
-            matching_session_ids.push(session_id);
-        }
-    }
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(String, i64, i64, i64, i64)> = sqlx::query_as(
+        r#"SELECT month, hits, sessions, dropped, duplicates FROM service_usage
+           WHERE service_id = $1 AND month >= $2"#,
+    )
+    .bind(service_id.0)
+    .bind(oldest)
+    .fetch_all(pool)
+    .await?;
 
-    if matching_session_ids.is_empty() {
-        return Ok(Vec::new());
-    }
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(String, i64, i64, i64, i64)> = sqlx::query_as(
+        r#"SELECT month, hits, sessions, dropped, duplicates FROM service_usage
+           WHERE service_id = ? AND month >= ?"#,
+    )
+    .bind(service_id.0.to_string())
+    .bind(oldest)
+    .fetch_all(pool)
+    .await?;
 
-    // Fetch the actual sessions with pagination
-    // We need to do pagination in Rust since we filtered in-memory
-    let skip = offset as usize;
-    let take = limit as usize;
-    let paginated_ids: Vec<_> = matching_session_ids
+    let mut by_month: HashMap<String, ServiceUsage> = rows
         .into_iter()
-        .skip(skip)
-        .take(take)
+        .map(|(month, hits, sessions, dropped, duplicates)| {
+            let usage = ServiceUsage {
+                month: month.clone(),
+                hits,
+                sessions,
+                dropped,
+                duplicates,
+            };
+            (month, usage)
+        })
         .collect();
 
-    if paginated_ids.is_empty() {
-        return Ok(Vec::new());
-    }
-
-    let mut sessions = Vec::new();
-    for session_id in paginated_ids {
-        #[cfg(feature = "postgres")]
-        let row: Option<SessionRow> = sqlx::query_as(
-            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
-               browser, device, device_type, os, ip::TEXT, asn, country, longitude,
-               latitude, time_zone, is_bounce
-               FROM sessions WHERE id = $1"#,
-        )
-        .bind(session_id)
-        .fetch_optional(pool)
-        .await?;
-
-        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-        let row: Option<SessionRow> = sqlx::query_as(
-            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
-               browser, device, device_type, os, ip, asn, country, longitude,
-               latitude, time_zone, is_bounce
-               FROM sessions WHERE id = ?"#,
-        )
-        .bind(&session_id)
-        .fetch_optional(pool)
-        .await?;
-
-        if let Some(row) = row {
-            sessions.push(row.into());
-        }
-    }
-
-    // Sort by start_time DESC
-    sessions.sort_by(|a: &Session, b: &Session| b.start_time.cmp(&a.start_time));
-
-    Ok(sessions)
+    Ok(months
+        .iter()
+        .map(|month| {
+            by_month.remove(month).unwrap_or_else(|| ServiceUsage {
+                month: month.clone(),
+                ..Default::default()
+            })
+        })
+        .collect())
 }
 
-// Hit queries
-pub async fn get_hit(pool: &Pool, id: HitId) -> Result<Hit> {
-    #[cfg(feature = "postgres")]
-    let row: HitRow = sqlx::query_as(
-        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time
-           FROM hits WHERE id = $1"#,
-    )
-    .bind(id.0)
-    .fetch_optional(pool)
-    .await?
-    .ok_or(Error::Internal("Hit not found".to_string()))?;
-
-    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let row: HitRow = sqlx::query_as(
-        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time
-           FROM hits WHERE id = ?"#,
-    )
-    .bind(id.0)
-    .fetch_optional(pool)
-    .await?
-    .ok_or(Error::Internal("Hit not found".to_string()))?;
+/// Usage counters for a single month
+pub async fn get_usage(pool: &Pool, service_id: ServiceId, month: &str) -> Result<ServiceUsage> {
+    let usage = list_usage(pool, service_id, &[month.to_string()]).await?;
+    Ok(usage.into_iter().next().unwrap_or_default())
+}
 
-    Ok(row.into())
+/// A service's quota with this month's usage and the `months - 1` before it
+pub async fn get_quota_usage(
+    pool: &Pool,
+    service: &Service,
+    now: DateTime<Utc>,
+    months: u32,
+) -> Result<QuotaUsage> {
+    let months = ServiceUsage::recent_months(now, months.max(1));
+    let mut usage = list_usage(pool, service.id, &months).await?.into_iter();
+
+    Ok(QuotaUsage {
+        hit_quota: service.hit_quota,
+        quota_behavior: service.quota_behavior,
+        current: usage.next().unwrap_or_default(),
+        history: usage.collect(),
+    })
 }
 
-pub async fn create_hit(pool: &Pool, input: CreateHit) -> Result<Hit> {
+/// Usage summed over every service of an organization for a single month
+pub async fn get_organization_usage(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    month: &str,
+) -> Result<ServiceUsage> {
     #[cfg(feature = "postgres")]
-    let id: i64 = sqlx::query_scalar(
-        r#"INSERT INTO hits (session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time)
-           VALUES ($1, $2, $3, $4, $5, 0, $6, $7, $8, $9)
-           RETURNING id"#,
+    let (hits, sessions, dropped, duplicates): (i64, i64, i64, i64) = sqlx::query_as(
+        r#"SELECT COALESCE(SUM(u.hits), 0)::BIGINT, COALESCE(SUM(u.sessions), 0)::BIGINT,
+           COALESCE(SUM(u.dropped), 0)::BIGINT, COALESCE(SUM(u.duplicates), 0)::BIGINT
+           FROM service_usage u JOIN services s ON s.id = u.service_id
+           WHERE s.organization_id = $1 AND u.month = $2"#,
     )
-    .bind(input.session_id.0)
-    .bind(input.service_id.0)
-    .bind(input.initial)
-    .bind(input.start_time)
-    .bind(input.start_time)
-    .bind(input.tracker.as_str())
-    .bind(&input.location)
-    .bind(&input.referrer)
-    .bind(input.load_time)
+    .bind(organization_id.0)
+    .bind(month)
     .fetch_one(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let id: i64 = {
-        sqlx::query(
-            r#"INSERT INTO hits (session_id, service_id, initial, start_time, last_seen,
-               heartbeats, tracker, location, referrer, load_time)
-               VALUES (?, ?, ?, ?, ?, 0, ?, ?, ?, ?)"#,
-        )
-        .bind(input.session_id.0.to_string())
-        .bind(input.service_id.0.to_string())
-        .bind(input.initial)
-        .bind(input.start_time.to_rfc3339())
-        .bind(input.start_time.to_rfc3339())
-        .bind(input.tracker.as_str())
-        .bind(&input.location)
-        .bind(&input.referrer)
-        .bind(input.load_time)
-        .execute(pool)
-        .await?;
-
-        sqlx::query_scalar::<_, i64>("SELECT last_insert_rowid()")
-            .fetch_one(pool)
-            .await?
-    };
+    let (hits, sessions, dropped, duplicates): (i64, i64, i64, i64) = sqlx::query_as(
+        r#"SELECT COALESCE(SUM(u.hits), 0), COALESCE(SUM(u.sessions), 0),
+           COALESCE(SUM(u.dropped), 0), COALESCE(SUM(u.duplicates), 0)
+           FROM service_usage u JOIN services s ON s.id = u.service_id
+           WHERE s.organization_id = ? AND u.month = ?"#,
+    )
+    .bind(organization_id.0.to_string())
+    .bind(month)
+    .fetch_one(pool)
+    .await?;
 
-    get_hit(pool, HitId(id)).await
+    Ok(ServiceUsage {
+        month: month.to_string(),
+        hits,
+        sessions,
+        dropped,
+        duplicates,
+    })
 }
 
-pub async fn update_hit_heartbeat(pool: &Pool, id: HitId, last_seen: DateTime<Utc>) -> Result<()> {
+/// Count a page view of a service that ingress didn't record, under the UTC
+/// day of `time` and the reason
+pub async fn record_dropped_hit(
+    pool: &Pool,
+    service_id: ServiceId,
+    time: DateTime<Utc>,
+    reason: IngressDecision,
+) -> Result<()> {
     #[cfg(feature = "postgres")]
-    sqlx::query("UPDATE hits SET heartbeats = heartbeats + 1, last_seen = $1 WHERE id = $2")
-        .bind(last_seen)
-        .bind(id.0)
-        .execute(pool)
-        .await?;
+    sqlx::query(
+        r#"INSERT INTO dropped_hits (service_id, day, reason, count) VALUES ($1, $2, $3, 1)
+           ON CONFLICT (service_id, day, reason) DO UPDATE SET count = dropped_hits.count + 1"#,
+    )
+    .bind(service_id.0)
+    .bind(time.date_naive())
+    .bind(reason.as_str())
+    .execute(pool)
+    .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    sqlx::query("UPDATE hits SET heartbeats = heartbeats + 1, last_seen = ? WHERE id = ?")
-        .bind(last_seen.to_rfc3339())
-        .bind(id.0)
-        .execute(pool)
-        .await?;
+    sqlx::query(
+        r#"INSERT INTO dropped_hits (service_id, day, reason, count) VALUES (?, ?, ?, 1)
+           ON CONFLICT (service_id, day, reason) DO UPDATE SET count = dropped_hits.count + 1"#,
+    )
+    .bind(service_id.0.to_string())
+    .bind(time.date_naive().to_string())
+    .bind(reason.as_str())
+    .execute(pool)
+    .await?;
 
     Ok(())
 }
 
-pub async fn list_hits_for_session(
+/// A service's dropped page views of the UTC days from `first` to `last`
+/// (inclusive) by reason, most first
+pub async fn get_dropped_hits(
     pool: &Pool,
-    session_id: SessionId,
-    limit: i64,
-    offset: i64,
-) -> Result<Vec<Hit>> {
+    service_id: ServiceId,
+    first: NaiveDate,
+    last: NaiveDate,
+) -> Result<Vec<DroppedHits>> {
     #[cfg(feature = "postgres")]
-    let rows: Vec<HitRow> = sqlx::query_as(
-        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time
-           FROM hits WHERE session_id = $1
-           ORDER BY start_time DESC
-           LIMIT $2 OFFSET $3"#,
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT reason, SUM(count)::BIGINT FROM dropped_hits
+         WHERE service_id = $1 AND day >= $2 AND day <= $3
+         GROUP BY reason",
     )
-    .bind(session_id.0)
-    .bind(limit)
-    .bind(offset)
+    .bind(service_id.0)
+    .bind(first)
+    .bind(last)
     .fetch_all(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let rows: Vec<HitRow> = sqlx::query_as(
-        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time
-           FROM hits WHERE session_id = ?
-           ORDER BY start_time DESC
-           LIMIT ? OFFSET ?"#,
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT reason, SUM(count) FROM dropped_hits
+         WHERE service_id = ? AND day >= ? AND day <= ?
+         GROUP BY reason",
     )
-    .bind(session_id.0.to_string())
-    .bind(limit)
-    .bind(offset)
+    .bind(service_id.0.to_string())
+    .bind(first.to_string())
+    .bind(last.to_string())
     .fetch_all(pool)
     .await?;
 
-    Ok(rows.into_iter().map(Into::into).collect())
+    let mut dropped: Vec<DroppedHits> = rows
+        .into_iter()
+        .filter_map(|(reason, count)| {
+            IngressDecision::from_str(&reason).map(|reason| DroppedHits { reason, count })
+        })
+        .collect();
+    dropped.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(a.reason.as_str().cmp(b.reason.as_str()))
+    });
+    Ok(dropped)
 }
 
-/// Find the most recent hit for a session at a specific location.
-/// Used to deduplicate heartbeats when idempotency cache expires.
-pub async fn find_recent_hit_by_location(
-    pool: &Pool,
-    session_id: SessionId,
-    location: &str,
-) -> Result<Option<Hit>> {
+/// Count a JavaScript error report under the UTC day of its time, keeping
+/// its message and stack as the latest
+pub async fn record_page_error(pool: &Pool, input: CreatePageError) -> Result<()> {
     #[cfg(feature = "postgres")]
-    let row: Option<HitRow> = sqlx::query_as(
-        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time
-           FROM hits WHERE session_id = $1 AND location = $2
-           ORDER BY start_time DESC
-           LIMIT 1"#,
+    sqlx::query(
+        r#"INSERT INTO errors (service_id, day, environment, location, fingerprint, message,
+           stack, count, last_seen) VALUES ($1, $2, $3, $4, $5, $6, $7, 1, $8)
+           ON CONFLICT (service_id, day, environment, location, fingerprint) DO UPDATE
+           SET count = errors.count + 1, message = excluded.message, stack = excluded.stack,
+           last_seen = excluded.last_seen"#,
     )
-    .bind(session_id.0)
-    .bind(location)
-    .fetch_optional(pool)
+    .bind(input.service_id.0)
+    .bind(input.time.date_naive())
+    .bind(input.environment.as_str())
+    .bind(&input.location)
+    .bind(&input.fingerprint)
+    .bind(&input.message)
+    .bind(&input.stack)
+    .bind(input.time)
+    .execute(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let row: Option<HitRow> = sqlx::query_as(
-        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
-           heartbeats, tracker, location, referrer, load_time
-           FROM hits WHERE session_id = ? AND location = ?
-           ORDER BY start_time DESC
-           LIMIT 1"#,
+    sqlx::query(
+        r#"INSERT INTO errors (service_id, day, environment, location, fingerprint, message,
+           stack, count, last_seen) VALUES (?, ?, ?, ?, ?, ?, ?, 1, ?)
+           ON CONFLICT (service_id, day, environment, location, fingerprint) DO UPDATE
+           SET count = errors.count + 1, message = excluded.message, stack = excluded.stack,
+           last_seen = excluded.last_seen"#,
     )
-    .bind(session_id.0.to_string())
-    .bind(location)
-    .fetch_optional(pool)
+    .bind(input.service_id.0.to_string())
+    .bind(input.time.date_naive().to_string())
+    .bind(input.environment.as_str())
+    .bind(&input.location)
+    .bind(&input.fingerprint)
+    .bind(&input.message)
+    .bind(&input.stack)
+    .bind(input.time.to_rfc3339())
+    .execute(pool)
     .await?;
 
-    Ok(row.map(Into::into))
+    Ok(())
 }
 
-// Stats queries
-#[allow(clippy::too_many_arguments)]
-pub async fn get_core_stats(
+/// A service's JavaScript errors of the UTC days from `first` to `last`
+/// (inclusive) by page and fingerprint, most reported first
+pub async fn get_page_errors(
     pool: &Pool,
     service_id: ServiceId,
-    start: DateTime<Utc>,
-    end: DateTime<Utc>,
-    hide_referrer_regex: Option<&Regex>,
-    url_pattern: Option<&Regex>,
-    active_user_timeout_ms: u64,
-    tz: Tz,
-) -> Result<CoreStats> {
-    let main_stats = get_relative_stats(
-        pool,
-        service_id,
-        start,
-        end,
-        hide_referrer_regex,
-        url_pattern,
-        active_user_timeout_ms,
-        tz,
-    )
-    .await?;
-
-    let duration = end - start;
-    let compare_start = start - duration;
-    let compare_stats = get_relative_stats(
-        pool,
-        service_id,
-        compare_start,
-        start,
-        hide_referrer_regex,
-        url_pattern,
-        active_user_timeout_ms,
-        tz,
-    )
-    .await?;
-
-    Ok(CoreStats {
-        compare: Some(Box::new(compare_stats)),
-        ..main_stats
-    })
-}
-
-#[allow(clippy::too_many_arguments)]
-async fn get_relative_stats(
-    pool: &Pool,
-    service_id: ServiceId,
-    start: DateTime<Utc>,
-    end: DateTime<Utc>,
-    hide_referrer_regex: Option<&Regex>,
-    url_pattern: Option<&Regex>,
-    active_user_timeout_ms: u64,
-    tz: Tz,
-) -> Result<CoreStats> {
-    // If URL pattern is provided, use filtered stats
-    if let Some(pattern) = url_pattern {
-        return get_relative_stats_with_url_filter(
-            pool,
-            service_id,
-            start,
-            end,
-            hide_referrer_regex,
-            pattern,
-            active_user_timeout_ms,
-            tz,
-        )
-        .await;
-    }
-
-    let now = Utc::now();
-    let active_cutoff = now - Duration::milliseconds(active_user_timeout_ms as i64);
-
-    // Currently online count
+    first: NaiveDate,
+    last: NaiveDate,
+    environment: Option<Environment>,
+) -> Result<Vec<PageError>> {
+    let env = environment_filter(environment, "environment");
     #[cfg(feature = "postgres")]
-    let currently_online: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND last_seen > $2",
-    )
+    let rows: Vec<(String, String, String, String, i64, DateTime<Utc>)> = sqlx::query_as(&format!(
+        "SELECT location, fingerprint, message, stack, count, last_seen FROM errors
+             WHERE service_id = $1 AND day >= $2 AND day <= $3 {env}
+             ORDER BY last_seen"
+    ))
     .bind(service_id.0)
-    .bind(active_cutoff)
-    .fetch_one(pool)
+    .bind(first)
+    .bind(last)
+    .fetch_all(pool)
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let currently_online: i64 = {
-        let count: i32 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM sessions WHERE service_id = ? AND last_seen > ?",
-        )
+    let rows: Vec<(String, String, String, String, i64, DateTime<Utc>)> = {
+        let rows: Vec<(String, String, String, String, i64, String)> = sqlx::query_as(&format!(
+            "SELECT location, fingerprint, message, stack, count, last_seen FROM errors
+             WHERE service_id = ? AND day >= ? AND day <= ? {env}
+             ORDER BY last_seen"
+        ))
         .bind(service_id.0.to_string())
-        .bind(active_cutoff.to_rfc3339())
-        .fetch_one(pool)
+        .bind(first.to_string())
+        .bind(last.to_string())
+        .fetch_all(pool)
         .await?;
-        count as i64
+        rows.into_iter()
+            .map(
+                |(location, fingerprint, message, stack, count, last_seen)| {
+                    let last_seen = parse_sqlite_time(&last_seen);
+                    (location, fingerprint, message, stack, count, last_seen)
+                },
+            )
+            .collect()
     };
 
-    // Session count
+    // Days and environments add up; the latest report's text wins
+    let mut errors: HashMap<(String, String), PageError> = HashMap::new();
+    for (location, fingerprint, message, stack, count, last_seen) in rows {
+        let error = errors
+            .entry((location.clone(), fingerprint.clone()))
+            .or_insert_with(|| PageError {
+                location,
+                fingerprint,
+                message: String::new(),
+                stack: String::new(),
+                count: 0,
+                last_seen,
+            });
+        error.count += count;
+        if last_seen >= error.last_seen {
+            error.message = message;
+            error.stack = stack;
+            error.last_seen = last_seen;
+        }
+    }
+    let mut errors: Vec<PageError> = errors.into_values().collect();
+    errors.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(b.last_seen.cmp(&a.last_seen))
+            .then(a.location.cmp(&b.location))
+    });
+    errors.truncate(RESULTS_LIMIT as usize);
+    Ok(errors)
+}
+
+/// Hits recorded in the range with their referrer flagged as spam
+pub async fn count_referrer_spam(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<i64> {
     #[cfg(feature = "postgres")]
-    let session_count: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3"
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM hits
+         WHERE service_id = $1 AND referrer_spam AND start_time >= $2 AND start_time < $3",
     )
     .bind(service_id.0)
     .bind(start)
@@ -976,250 +1447,5162 @@ async fn get_relative_stats(
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let session_count: i64 = {
-        let count: i32 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ?"
-        )
-        .bind(service_id.0.to_string())
-        .bind(start.to_rfc3339())
-        .bind(end.to_rfc3339())
-        .fetch_one(pool)
-        .await?;
-        count as i64
-    };
-
-    // Hit count
-    #[cfg(feature = "postgres")]
-    let hit_count: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3",
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM hits
+         WHERE service_id = ? AND referrer_spam AND start_time >= ? AND start_time < ?",
     )
-    .bind(service_id.0)
-    .bind(start)
-    .bind(end)
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
     .fetch_one(pool)
     .await?;
 
-    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let hit_count: i64 = {
-        let count: i32 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ?",
-        )
-        .bind(service_id.0.to_string())
-        .bind(start.to_rfc3339())
-        .bind(end.to_rfc3339())
-        .fetch_one(pool)
-        .await?;
-        count as i64
-    };
+    Ok(count)
+}
 
-    // Has any hits ever
+// Organization queries
+pub async fn get_organization(pool: &Pool, id: OrganizationId) -> Result<Organization> {
     #[cfg(feature = "postgres")]
-    let has_hits: bool = {
-        let count: i64 =
-            sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE service_id = $1 LIMIT 1")
-                .bind(service_id.0)
-                .fetch_one(pool)
-                .await?;
-        count > 0
-    };
+    let row: OrganizationRow = sqlx::query_as(&format!(
+        "SELECT {ORGANIZATION_COLUMNS} FROM organizations WHERE id = $1"
+    ))
+    .bind(id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::OrganizationNotFound)?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let has_hits: bool = {
-        let count: i32 =
-            sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE service_id = ? LIMIT 1")
-                .bind(service_id.0.to_string())
-                .fetch_one(pool)
-                .await?;
-        count > 0
-    };
+    let row: OrganizationRow = sqlx::query_as(&format!(
+        "SELECT {ORGANIZATION_COLUMNS} FROM organizations WHERE id = ?"
+    ))
+    .bind(id.0.to_string())
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::OrganizationNotFound)?;
 
-    // Bounce count
+    Ok(row.into())
+}
+
+pub async fn get_organization_by_slug(pool: &Pool, slug: &str) -> Result<Organization> {
     #[cfg(feature = "postgres")]
-    let bounce_count: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 AND is_bounce = true"
-    )
-    .bind(service_id.0)
-    .bind(start)
-    .bind(end)
-    .fetch_one(pool)
-    .await?;
+    let row: OrganizationRow = sqlx::query_as(&format!(
+        "SELECT {ORGANIZATION_COLUMNS} FROM organizations WHERE slug = $1"
+    ))
+    .bind(slug)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::OrganizationNotFound)?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let bounce_count: i64 = {
-        let count: i32 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? AND is_bounce = 1"
-        )
-        .bind(service_id.0.to_string())
-        .bind(start.to_rfc3339())
-        .bind(end.to_rfc3339())
-        .fetch_one(pool)
-        .await?;
-        count as i64
-    };
+    let row: OrganizationRow = sqlx::query_as(&format!(
+        "SELECT {ORGANIZATION_COLUMNS} FROM organizations WHERE slug = ?"
+    ))
+    .bind(slug)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::OrganizationNotFound)?;
 
-    let bounce_rate_pct = if session_count > 0 {
-        Some(((bounce_count as f64 / session_count as f64) * 1000.0).round() / 10.0)
-    } else {
-        None
-    };
+    Ok(row.into())
+}
 
-    // Average load time
+/// Every organization, default first
+pub async fn list_organizations(pool: &Pool) -> Result<Vec<Organization>> {
+    let rows: Vec<OrganizationRow> = sqlx::query_as(&format!(
+        "SELECT {ORGANIZATION_COLUMNS} FROM organizations ORDER BY slug != 'default', name, id"
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Organizations the user is a member of
+pub async fn list_user_organizations(pool: &Pool, user_id: UserId) -> Result<Vec<Organization>> {
     #[cfg(feature = "postgres")]
-    let avg_load_time: Option<f64> = {
-        let raw: Option<f64> = sqlx::query_scalar(
-            "SELECT AVG(load_time) FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 AND load_time IS NOT NULL"
-        )
-        .bind(service_id.0)
-        .bind(start)
-        .bind(end)
-        .fetch_one(pool)
-        .await?;
-        raw.map(|v| v.round())
-    };
+    let rows: Vec<OrganizationRow> = sqlx::query_as(
+        r#"SELECT o.id, o.name, o.slug, o.hit_quota, o.quota_behavior, o.logo_url,
+                  o.accent_color, o.theme_mode, o.created_at
+           FROM organizations o JOIN memberships m ON m.organization_id = o.id
+           WHERE m.user_id = $1 ORDER BY o.name, o.id"#,
+    )
+    .bind(user_id.0)
+    .fetch_all(pool)
+    .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let avg_load_time: Option<f64> = sqlx::query_scalar(
-        "SELECT AVG(load_time) FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ? AND load_time IS NOT NULL"
+    let rows: Vec<OrganizationRow> = sqlx::query_as(
+        r#"SELECT o.id, o.name, o.slug, o.hit_quota, o.quota_behavior, o.logo_url,
+                  o.accent_color, o.theme_mode, o.created_at
+           FROM organizations o JOIN memberships m ON m.organization_id = o.id
+           WHERE m.user_id = ? ORDER BY o.name, o.id"#,
     )
-    .bind(service_id.0.to_string())
-    .bind(start.to_rfc3339())
-    .bind(end.to_rfc3339())
-    .fetch_one(pool)
+    .bind(user_id.0.to_string())
+    .fetch_all(pool)
     .await?;
 
-    // Round avg_load_time to integer
-    let avg_load_time = avg_load_time.map(|v| v.round());
+    Ok(rows.into_iter().map(Into::into).collect())
+}
 
-    let avg_hits_per_session = if session_count > 0 {
-        Some(((hit_count as f64 / session_count as f64) * 10.0).round() / 10.0)
+/// Create an organization. A slug that is already taken gets a short suffix.
+pub async fn create_organization(pool: &Pool, input: CreateOrganization) -> Result<Organization> {
+    let id = OrganizationId::new();
+    let now = Utc::now();
+    let mut slug = Organization::slugify(if input.slug.is_empty() {
+        &input.name
     } else {
-        None
-    };
+        &input.slug
+    });
+    match get_organization_by_slug(pool, &slug).await {
+        Ok(_) => slug = format!("{}-{}", slug, &id.0.simple().to_string()[..6]),
+        Err(Error::OrganizationNotFound) => {}
+        Err(e) => return Err(e),
+    }
 
-    // Average session duration (in seconds)
     #[cfg(feature = "postgres")]
-    let avg_session_duration: Option<f64> = {
-        let raw: Option<f64> = sqlx::query_scalar(
-            r#"SELECT AVG(EXTRACT(EPOCH FROM (last_seen - start_time)))
-               FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3"#,
-        )
-        .bind(service_id.0)
-        .bind(start)
-        .bind(end)
-        .fetch_one(pool)
-        .await?;
-        raw.map(|v| v.round())
-    };
+    sqlx::query(
+        r#"INSERT INTO organizations (id, name, slug, hit_quota, quota_behavior, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(id.0)
+    .bind(&input.name)
+    .bind(&slug)
+    .bind(input.hit_quota)
+    .bind(input.quota_behavior.as_str())
+    .bind(now)
+    .execute(pool)
+    .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let avg_session_duration: Option<f64> = {
-        // SQLite doesn't have easy date arithmetic, compute manually
-        let durations: Vec<(String, String)> = sqlx::query_as(
-            "SELECT start_time, last_seen FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ?"
-        )
-        .bind(service_id.0.to_string())
-        .bind(start.to_rfc3339())
-        .bind(end.to_rfc3339())
-        .fetch_all(pool)
-        .await?;
-
-        if durations.is_empty() {
-            None
-        } else {
-            let total: f64 = durations
-                .iter()
-                .filter_map(|(s, e)| {
-                    let start_dt = DateTime::parse_from_rfc3339(s).ok()?.with_timezone(&Utc);
-                    let end_dt = DateTime::parse_from_rfc3339(e).ok()?.with_timezone(&Utc);
-                    Some((end_dt - start_dt).num_seconds() as f64)
-                })
-                .sum();
-            Some((total / durations.len() as f64).round())
-        }
-    };
-
-    // Locations (top pages) - normalized to strip query params
-    let locations = get_counted_locations(pool, service_id, start, end, RESULTS_LIMIT).await?;
-
-    // Referrers (filter by regex if provided)
-    let mut referrers = get_counted_field_initial(
-        pool,
-        "hits",
-        "referrer",
-        service_id,
-        start,
-        end,
-        RESULTS_LIMIT,
+    sqlx::query(
+        r#"INSERT INTO organizations (id, name, slug, hit_quota, quota_behavior, created_at)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
     )
+    .bind(id.0.to_string())
+    .bind(&input.name)
+    .bind(&slug)
+    .bind(input.hit_quota)
+    .bind(input.quota_behavior.as_str())
+    .bind(now.to_rfc3339())
+    .execute(pool)
     .await?;
 
-    if let Some(regex) = hide_referrer_regex {
-        referrers.retain(|r| !regex.is_match(&r.value));
-    }
+    get_organization(pool, id).await
+}
 
-    // Countries
-    let countries = get_counted_field(
-        pool,
-        "sessions",
-        "country",
-        service_id,
-        start,
+pub async fn update_organization(
+    pool: &Pool,
+    id: OrganizationId,
+    input: UpdateOrganization,
+) -> Result<Organization> {
+    let organization = get_organization(pool, id).await?;
+
+    let name = input.name.unwrap_or(organization.name);
+    let hit_quota = input.hit_quota.unwrap_or(organization.hit_quota);
+    let quota_behavior = input.quota_behavior.unwrap_or(organization.quota_behavior);
+    let logo_url = input.logo_url.unwrap_or(organization.logo_url);
+    let accent_color = input.accent_color.unwrap_or(organization.accent_color);
+    let theme_mode = input
+        .theme_mode
+        .unwrap_or(organization.theme_mode)
+        .map_or("", |mode| mode.as_str());
+
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"UPDATE organizations SET name = $1, hit_quota = $2, quota_behavior = $3,
+           logo_url = $4, accent_color = $5, theme_mode = $6 WHERE id = $7"#,
+    )
+    .bind(&name)
+    .bind(hit_quota)
+    .bind(quota_behavior.as_str())
+    .bind(&logo_url)
+    .bind(&accent_color)
+    .bind(theme_mode)
+    .bind(id.0)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"UPDATE organizations SET name = ?, hit_quota = ?, quota_behavior = ?,
+           logo_url = ?, accent_color = ?, theme_mode = ? WHERE id = ?"#,
+    )
+    .bind(&name)
+    .bind(hit_quota)
+    .bind(quota_behavior.as_str())
+    .bind(&logo_url)
+    .bind(&accent_color)
+    .bind(theme_mode)
+    .bind(id.0.to_string())
+    .execute(pool)
+    .await?;
+
+    get_organization(pool, id).await
+}
+
+// User queries
+pub async fn get_user(pool: &Pool, id: UserId) -> Result<User> {
+    #[cfg(feature = "postgres")]
+    let row: UserRow = sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = $1"))
+        .bind(id.0)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: UserRow = sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = ?"))
+        .bind(id.0.to_string())
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+
+    Ok(row.into())
+}
+
+/// Look up a user by email address (case-insensitive)
+pub async fn get_user_by_email(pool: &Pool, email: &str) -> Result<User> {
+    let email = email.trim().to_lowercase();
+
+    #[cfg(feature = "postgres")]
+    let row: UserRow = sqlx::query_as(&format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE email = $1"
+    ))
+    .bind(&email)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::UserNotFound)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: UserRow = sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE email = ?"))
+        .bind(&email)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+
+    Ok(row.into())
+}
+
+pub async fn count_users(pool: &Pool) -> Result<i64> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+/// Create a user; `password_hash` must already be hashed. `email_verified`
+/// is set when the address was already proven, e.g. by an emailed invite.
+pub async fn create_user(
+    pool: &Pool,
+    email: &str,
+    name: &str,
+    password_hash: &str,
+    email_verified: bool,
+) -> Result<User> {
+    let id = UserId::new();
+    let email = email.trim().to_lowercase();
+    let now = Utc::now();
+    let email_verified_at = email_verified.then_some(now);
+
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO users (id, email, name, password_hash, email_verified_at, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(id.0)
+    .bind(&email)
+    .bind(name)
+    .bind(password_hash)
+    .bind(email_verified_at)
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO users (id, email, name, password_hash, email_verified_at, created_at)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(id.0.to_string())
+    .bind(&email)
+    .bind(name)
+    .bind(password_hash)
+    .bind(email_verified_at.map(|t| t.to_rfc3339()))
+    .bind(now.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    get_user(pool, id).await
+}
+
+/// Mark the user's email address as verified, keeping the first verification
+/// time
+pub async fn verify_user_email(pool: &Pool, id: UserId) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, $2) WHERE id = $1",
+    )
+    .bind(id.0)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE users SET email_verified_at = COALESCE(email_verified_at, ?) WHERE id = ?")
+        .bind(Utc::now().to_rfc3339())
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Replace the user's password; `password_hash` must already be hashed
+pub async fn set_user_password(pool: &Pool, id: UserId, password_hash: &str) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+        .bind(id.0)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+        .bind(password_hash)
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Turn two-factor logins on with a base32 TOTP secret, or off with `None`
+pub async fn set_user_totp_secret(pool: &Pool, id: UserId, secret: Option<&str>) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE users SET totp_secret = $2 WHERE id = $1")
+        .bind(id.0)
+        .bind(secret)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE users SET totp_secret = ? WHERE id = ?")
+        .bind(secret)
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// The user's preferences; the defaults if they never saved any
+pub async fn get_user_settings(pool: &Pool, user_id: UserId) -> Result<UserSettings> {
+    #[cfg(feature = "postgres")]
+    let row: Option<(String, String, String)> = sqlx::query_as(
+        "SELECT time_zone, week_start, hour_cycle FROM user_settings WHERE user_id = $1",
+    )
+    .bind(user_id.0)
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: Option<(String, String, String)> = sqlx::query_as(
+        "SELECT time_zone, week_start, hour_cycle FROM user_settings WHERE user_id = ?",
+    )
+    .bind(user_id.0.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row
+        .map(|(time_zone, week_start, hour_cycle)| UserSettings {
+            time_zone,
+            week_start: WeekStart::from_str(&week_start),
+            hour_cycle: HourCycle::from_str(&hour_cycle),
+        })
+        .unwrap_or_default())
+}
+
+pub async fn save_user_settings(
+    pool: &Pool,
+    user_id: UserId,
+    settings: &UserSettings,
+) -> Result<()> {
+    let week_start = settings.week_start.map_or("", |w| w.as_str());
+    let hour_cycle = settings.hour_cycle.map_or("", |h| h.as_str());
+
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO user_settings (user_id, time_zone, week_start, hour_cycle)
+           VALUES ($1, $2, $3, $4)
+           ON CONFLICT (user_id) DO UPDATE SET time_zone = EXCLUDED.time_zone,
+           week_start = EXCLUDED.week_start, hour_cycle = EXCLUDED.hour_cycle"#,
+    )
+    .bind(user_id.0)
+    .bind(&settings.time_zone)
+    .bind(week_start)
+    .bind(hour_cycle)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO user_settings (user_id, time_zone, week_start, hour_cycle)
+           VALUES (?, ?, ?, ?)
+           ON CONFLICT (user_id) DO UPDATE SET time_zone = excluded.time_zone,
+           week_start = excluded.week_start, hour_cycle = excluded.hour_cycle"#,
+    )
+    .bind(user_id.0.to_string())
+    .bind(&settings.time_zone)
+    .bind(week_start)
+    .bind(hour_cycle)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The preset range the user last picked on the service's dashboard
+pub async fn get_user_date_range(
+    pool: &Pool,
+    user_id: UserId,
+    service_id: ServiceId,
+) -> Result<Option<DateRangePreset>> {
+    #[cfg(feature = "postgres")]
+    let row: Option<(String,)> = sqlx::query_as(
+        "SELECT date_range FROM user_service_settings WHERE user_id = $1 AND service_id = $2",
+    )
+    .bind(user_id.0)
+    .bind(service_id.0)
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: Option<(String,)> = sqlx::query_as(
+        "SELECT date_range FROM user_service_settings WHERE user_id = ? AND service_id = ?",
+    )
+    .bind(user_id.0.to_string())
+    .bind(service_id.0.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.and_then(|(range,)| DateRangePreset::from_str(&range)))
+}
+
+/// Remember the preset range the user picked on the service's dashboard
+pub async fn save_user_date_range(
+    pool: &Pool,
+    user_id: UserId,
+    service_id: ServiceId,
+    range: DateRangePreset,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO user_service_settings (user_id, service_id, date_range) VALUES ($1, $2, $3)
+           ON CONFLICT (user_id, service_id) DO UPDATE SET date_range = EXCLUDED.date_range"#,
+    )
+    .bind(user_id.0)
+    .bind(service_id.0)
+    .bind(range.as_str())
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO user_service_settings (user_id, service_id, date_range) VALUES (?, ?, ?)
+           ON CONFLICT (user_id, service_id) DO UPDATE SET date_range = excluded.date_range"#,
+    )
+    .bind(user_id.0.to_string())
+    .bind(service_id.0.to_string())
+    .bind(range.as_str())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Membership queries
+/// Make the user a member with the given role; an existing membership keeps
+/// its role
+pub async fn add_membership(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    user_id: UserId,
+    role: Role,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO memberships (organization_id, user_id, role, created_at) VALUES ($1, $2, $3, $4)
+           ON CONFLICT (organization_id, user_id) DO NOTHING"#,
+    )
+    .bind(organization_id.0)
+    .bind(user_id.0)
+    .bind(role.as_str())
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO memberships (organization_id, user_id, role, created_at) VALUES (?, ?, ?, ?)
+           ON CONFLICT (organization_id, user_id) DO NOTHING"#,
+    )
+    .bind(organization_id.0.to_string())
+    .bind(user_id.0.to_string())
+    .bind(role.as_str())
+    .bind(Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The user's role in the organization; `UserNotFound` if not a member
+pub async fn get_membership_role(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    user_id: UserId,
+) -> Result<Role> {
+    #[cfg(feature = "postgres")]
+    let role: String = sqlx::query_scalar(
+        "SELECT role FROM memberships WHERE organization_id = $1 AND user_id = $2",
+    )
+    .bind(organization_id.0)
+    .bind(user_id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::UserNotFound)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let role: String = sqlx::query_scalar(
+        "SELECT role FROM memberships WHERE organization_id = ? AND user_id = ?",
+    )
+    .bind(organization_id.0.to_string())
+    .bind(user_id.0.to_string())
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::UserNotFound)?;
+
+    Ok(Role::from_str(&role).unwrap_or_default())
+}
+
+pub async fn set_membership_role(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    user_id: UserId,
+    role: Role,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE memberships SET role = $1 WHERE organization_id = $2 AND user_id = $3")
+        .bind(role.as_str())
+        .bind(organization_id.0)
+        .bind(user_id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE memberships SET role = ? WHERE organization_id = ? AND user_id = ?")
+        .bind(role.as_str())
+        .bind(organization_id.0.to_string())
+        .bind(user_id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn remove_membership(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    user_id: UserId,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM memberships WHERE organization_id = $1 AND user_id = $2")
+        .bind(organization_id.0)
+        .bind(user_id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM memberships WHERE organization_id = ? AND user_id = ?")
+        .bind(organization_id.0.to_string())
+        .bind(user_id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Members of an organization, by email
+pub async fn list_members(pool: &Pool, organization_id: OrganizationId) -> Result<Vec<Member>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<MemberRow> = sqlx::query_as(
+        r#"SELECT u.id, u.email, u.name, u.password_hash, u.email_verified_at, u.totp_secret,
+           u.created_at, m.role
+           FROM users u JOIN memberships m ON m.user_id = u.id
+           WHERE m.organization_id = $1 ORDER BY u.email"#,
+    )
+    .bind(organization_id.0)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<MemberRow> = sqlx::query_as(
+        r#"SELECT u.id, u.email, u.name, u.password_hash, u.email_verified_at, u.totp_secret,
+           u.created_at, m.role
+           FROM users u JOIN memberships m ON m.user_id = u.id
+           WHERE m.organization_id = ? ORDER BY u.email"#,
+    )
+    .bind(organization_id.0.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+// API token queries
+pub async fn list_api_tokens(
+    pool: &Pool,
+    organization_id: OrganizationId,
+) -> Result<Vec<ApiToken>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<ApiTokenRow> = sqlx::query_as(&format!(
+        "SELECT {API_TOKEN_COLUMNS} FROM api_tokens WHERE organization_id = $1 ORDER BY created_at"
+    ))
+    .bind(organization_id.0)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<ApiTokenRow> = sqlx::query_as(&format!(
+        "SELECT {API_TOKEN_COLUMNS} FROM api_tokens WHERE organization_id = ? ORDER BY created_at"
+    ))
+    .bind(organization_id.0.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Find the token with the given hash and record that it was used
+pub async fn use_api_token(pool: &Pool, token_hash: &str) -> Result<ApiToken> {
+    let now = Utc::now();
+
+    #[cfg(feature = "postgres")]
+    let row: ApiTokenRow = sqlx::query_as(&format!(
+        "UPDATE api_tokens SET last_used_at = $1 WHERE token_hash = $2 RETURNING {API_TOKEN_COLUMNS}"
+    ))
+    .bind(now)
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: ApiTokenRow = sqlx::query_as(&format!(
+        "UPDATE api_tokens SET last_used_at = ? WHERE token_hash = ? RETURNING {API_TOKEN_COLUMNS}"
+    ))
+    .bind(now.to_rfc3339())
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    Ok(row.into())
+}
+
+/// Store a new API token; `token_hash` is the hash of the secret shown once
+/// to the user
+pub async fn create_api_token(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    name: &str,
+    token_hash: &str,
+    role: Role,
+    user_id: Option<UserId>,
+) -> Result<ApiToken> {
+    let id = ApiTokenId::new();
+    let now = Utc::now();
+
+    #[cfg(feature = "postgres")]
+    let row: ApiTokenRow = sqlx::query_as(&format!(
+        r#"INSERT INTO api_tokens (id, organization_id, name, token_hash, role, user_id, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {API_TOKEN_COLUMNS}"#
+    ))
+    .bind(id.0)
+    .bind(organization_id.0)
+    .bind(name)
+    .bind(token_hash)
+    .bind(role.as_str())
+    .bind(user_id.map(|id| id.0))
+    .bind(now)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: ApiTokenRow = sqlx::query_as(&format!(
+        r#"INSERT INTO api_tokens (id, organization_id, name, token_hash, role, user_id, created_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {API_TOKEN_COLUMNS}"#
+    ))
+    .bind(id.0.to_string())
+    .bind(organization_id.0.to_string())
+    .bind(name)
+    .bind(token_hash)
+    .bind(role.as_str())
+    .bind(user_id.map(|id| id.0.to_string()))
+    .bind(now.to_rfc3339())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.into())
+}
+
+pub async fn delete_api_token(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    id: ApiTokenId,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND organization_id = $2")
+        .bind(id.0)
+        .bind(organization_id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM api_tokens WHERE id = ? AND organization_id = ?")
+        .bind(id.0.to_string())
+        .bind(organization_id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Login session queries
+pub async fn create_login_session(
+    pool: &Pool,
+    token_hash: &str,
+    user_id: UserId,
+    expires_at: DateTime<Utc>,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        "INSERT INTO login_sessions (token_hash, user_id, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(token_hash)
+    .bind(user_id.0)
+    .bind(Utc::now())
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        "INSERT INTO login_sessions (token_hash, user_id, created_at, expires_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(token_hash)
+    .bind(user_id.0.to_string())
+    .bind(Utc::now().to_rfc3339())
+    .bind(expires_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The user logged in with the session cookie hashing to `token_hash`, if the
+/// login has not expired
+pub async fn get_login_session_user(
+    pool: &Pool,
+    token_hash: &str,
+    now: DateTime<Utc>,
+) -> Result<User> {
+    #[cfg(feature = "postgres")]
+    let row: UserRow = sqlx::query_as(
+        r#"SELECT u.id, u.email, u.name, u.password_hash, u.email_verified_at, u.totp_secret,
+           u.created_at
+           FROM users u JOIN login_sessions l ON l.user_id = u.id
+           WHERE l.token_hash = $1 AND l.expires_at > $2"#,
+    )
+    .bind(token_hash)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: UserRow = sqlx::query_as(
+        r#"SELECT u.id, u.email, u.name, u.password_hash, u.email_verified_at, u.totp_secret,
+           u.created_at
+           FROM users u JOIN login_sessions l ON l.user_id = u.id
+           WHERE l.token_hash = ? AND l.expires_at > ?"#,
+    )
+    .bind(token_hash)
+    .bind(now.to_rfc3339())
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    Ok(row.into())
+}
+
+pub async fn delete_login_session(pool: &Pool, token_hash: &str) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM login_sessions WHERE token_hash = $1")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM login_sessions WHERE token_hash = ?")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// End all of a user's dashboard logins, e.g. after a password reset
+pub async fn delete_user_login_sessions(pool: &Pool, user_id: UserId) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM login_sessions WHERE user_id = $1")
+        .bind(user_id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM login_sessions WHERE user_id = ?")
+        .bind(user_id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Login audit log queries
+pub async fn record_login_attempt(
+    pool: &Pool,
+    email: &str,
+    ip: Option<&str>,
+    outcome: LoginOutcome,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let email = email.trim().to_lowercase();
+
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        "INSERT INTO login_attempts (email, ip, outcome, created_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&email)
+    .bind(ip)
+    .bind(outcome.as_str())
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("INSERT INTO login_attempts (email, ip, outcome, created_at) VALUES (?, ?, ?, ?)")
+        .bind(&email)
+        .bind(ip)
+        .bind(outcome.as_str())
+        .bind(now.to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Failed logins where `column` equals `value`, after `since` and after the
+/// last successful login. `column` is `email` or `ip`.
+async fn get_login_failures(
+    pool: &Pool,
+    column: &str,
+    value: &str,
+    since: DateTime<Utc>,
+) -> Result<LoginFailures> {
+    // The outcomes `LoginOutcome::is_failure` counts
+    #[cfg(feature = "postgres")]
+    let (count, last_at): (i64, Option<DateTime<Utc>>) = sqlx::query_as(&format!(
+        r#"SELECT COUNT(*), MAX(created_at) FROM login_attempts
+           WHERE {column} = $1 AND outcome IN ('wrong_password', 'wrong_code')
+           AND created_at > COALESCE(
+               (SELECT MAX(created_at) FROM login_attempts
+                WHERE {column} = $1 AND outcome = 'success' AND created_at > $2),
+               $2)"#
+    ))
+    .bind(value)
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let (count, last_at): (i64, Option<DateTime<Utc>>) = {
+        let since = since.to_rfc3339();
+        let (count, last_at): (i64, Option<String>) = sqlx::query_as(&format!(
+            r#"SELECT COUNT(*), MAX(created_at) FROM login_attempts
+               WHERE {column} = ? AND outcome IN ('wrong_password', 'wrong_code')
+               AND created_at > COALESCE(
+                   (SELECT MAX(created_at) FROM login_attempts
+                    WHERE {column} = ? AND outcome = 'success' AND created_at > ?),
+                   ?)"#
+        ))
+        .bind(value)
+        .bind(value)
+        .bind(&since)
+        .bind(&since)
+        .fetch_one(pool)
+        .await?;
+        (count, last_at.as_deref().map(parse_sqlite_time))
+    };
+
+    Ok(LoginFailures { count, last_at })
+}
+
+/// Recent failed logins to an account
+pub async fn get_login_failures_for_email(
+    pool: &Pool,
+    email: &str,
+    since: DateTime<Utc>,
+) -> Result<LoginFailures> {
+    get_login_failures(pool, "email", &email.trim().to_lowercase(), since).await
+}
+
+/// Recent failed logins from an IP address, to any account
+pub async fn get_login_failures_for_ip(
+    pool: &Pool,
+    ip: &str,
+    since: DateTime<Utc>,
+) -> Result<LoginFailures> {
+    get_login_failures(pool, "ip", ip, since).await
+}
+
+/// The latest logins to an account, newest first
+pub async fn list_login_attempts(
+    pool: &Pool,
+    email: &str,
+    limit: i64,
+) -> Result<Vec<LoginAttempt>> {
+    let email = email.trim().to_lowercase();
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<LoginAttemptRow> = sqlx::query_as(
+        r#"SELECT email, ip, outcome, created_at FROM login_attempts
+           WHERE email = $1 ORDER BY created_at DESC, id DESC LIMIT $2"#,
+    )
+    .bind(&email)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<LoginAttemptRow> = sqlx::query_as(
+        r#"SELECT email, ip, outcome, created_at FROM login_attempts
+           WHERE email = ? ORDER BY created_at DESC, id DESC LIMIT ?"#,
+    )
+    .bind(&email)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+// Uptime monitor queries
+/// Active services with a link to their site, in every organization
+pub async fn list_monitored_services(pool: &Pool) -> Result<Vec<Service>> {
+    let rows: Vec<ServiceRow> = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE status = 'AC' AND link <> '' ORDER BY name, id"
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Active services in every organization
+pub async fn list_active_services(pool: &Pool) -> Result<Vec<Service>> {
+    let rows: Vec<ServiceRow> = sqlx::query_as(&format!(
+        "SELECT {SERVICE_COLUMNS} FROM services WHERE status = 'AC' ORDER BY name, id"
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+pub async fn record_monitor_check(pool: &Pool, check: &MonitorCheck) -> Result<()> {
+    let status_code = check.status_code.map(i32::from);
+
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO monitor_checks (service_id, checked_at, status_code, latency_ms, up, error)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(check.service_id.0)
+    .bind(check.checked_at)
+    .bind(status_code)
+    .bind(check.latency_ms)
+    .bind(check.up)
+    .bind(&check.error)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO monitor_checks (service_id, checked_at, status_code, latency_ms, up, error)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(check.service_id.0.to_string())
+    .bind(check.checked_at.to_rfc3339())
+    .bind(status_code)
+    .bind(check.latency_ms)
+    .bind(check.up)
+    .bind(&check.error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The monitor's most recent check of a service's site
+pub async fn get_latest_monitor_check(
+    pool: &Pool,
+    service_id: ServiceId,
+) -> Result<Option<MonitorCheck>> {
+    #[cfg(feature = "postgres")]
+    let row: Option<MonitorCheckRow> = sqlx::query_as(
+        r#"SELECT service_id, checked_at, status_code, latency_ms, up, error
+           FROM monitor_checks WHERE service_id = $1
+           ORDER BY checked_at DESC, id DESC LIMIT 1"#,
+    )
+    .bind(service_id.0)
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: Option<MonitorCheckRow> = sqlx::query_as(
+        r#"SELECT service_id, checked_at, status_code, latency_ms, up, error
+           FROM monitor_checks WHERE service_id = ?
+           ORDER BY checked_at DESC, id DESC LIMIT 1"#,
+    )
+    .bind(service_id.0.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Into::into))
+}
+
+/// How a service's site fared in the checks since `since`
+pub async fn get_uptime(
+    pool: &Pool,
+    service_id: ServiceId,
+    since: DateTime<Utc>,
+) -> Result<Uptime> {
+    #[cfg(feature = "postgres")]
+    let (checks, up_checks, avg_latency_ms): (i64, i64, Option<f64>) = sqlx::query_as(
+        r#"SELECT COUNT(*), COALESCE(SUM(CASE WHEN up THEN 1 ELSE 0 END), 0)::BIGINT,
+           AVG(latency_ms)::FLOAT8
+           FROM monitor_checks WHERE service_id = $1 AND checked_at >= $2"#,
+    )
+    .bind(service_id.0)
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let (checks, up_checks, avg_latency_ms): (i64, i64, Option<f64>) = sqlx::query_as(
+        r#"SELECT COUNT(*), COALESCE(SUM(CASE WHEN up THEN 1 ELSE 0 END), 0),
+           AVG(latency_ms)
+           FROM monitor_checks WHERE service_id = ? AND checked_at >= ?"#,
+    )
+    .bind(service_id.0.to_string())
+    .bind(since.to_rfc3339())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Uptime {
+        checks,
+        up_checks,
+        avg_latency_ms,
+        latest: get_latest_monitor_check(pool, service_id).await?,
+    })
+}
+
+/// Drop checks older than `before`, returning how many went
+pub async fn delete_monitor_checks_before(pool: &Pool, before: DateTime<Utc>) -> Result<u64> {
+    #[cfg(feature = "postgres")]
+    let result = sqlx::query("DELETE FROM monitor_checks WHERE checked_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let result = sqlx::query("DELETE FROM monitor_checks WHERE checked_at < ?")
+        .bind(before.to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Page inventory queries
+/// Make `urls` the service's known pages: new ones are added, ones still
+/// listed are marked seen at `now`, and ones no longer listed are dropped
+pub async fn replace_pages(
+    pool: &Pool,
+    service_id: ServiceId,
+    urls: &[String],
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    for url in urls {
+        #[cfg(feature = "postgres")]
+        sqlx::query(
+            r#"INSERT INTO pages (service_id, url, first_seen, last_seen) VALUES ($1, $2, $3, $3)
+               ON CONFLICT (service_id, url) DO UPDATE SET last_seen = excluded.last_seen"#,
+        )
+        .bind(service_id.0)
+        .bind(url)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+        sqlx::query(
+            r#"INSERT INTO pages (service_id, url, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+               ON CONFLICT (service_id, url) DO UPDATE SET last_seen = excluded.last_seen"#,
+        )
+        .bind(service_id.0.to_string())
+        .bind(url)
+        .bind(now.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM pages WHERE service_id = $1 AND last_seen < $2")
+        .bind(service_id.0)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM pages WHERE service_id = ? AND last_seen < ?")
+        .bind(service_id.0.to_string())
+        .bind(now.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Known pages without a hit between `start` and `end`, by URL. A hit at the
+/// page's URL with a query string or fragment counts as a hit on the page.
+pub async fn get_orphan_pages(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+) -> Result<Vec<String>> {
+    let env = environment_filter(environment, "h.environment");
+    let flagged = flagged_filter("h");
+    #[cfg(feature = "postgres")]
+    let urls: Vec<String> = sqlx::query_scalar(&format!(
+        r#"SELECT p.url FROM pages p
+           WHERE p.service_id = $1 AND NOT EXISTS (
+               SELECT 1 FROM hits h
+               WHERE h.service_id = p.service_id AND h.start_time >= $2 AND h.start_time < $3 {env} {flagged}
+               AND (h.location = p.url
+                    OR substr(h.location, 1, length(p.url) + 1) IN (p.url || '?', p.url || '#')))
+           ORDER BY p.url LIMIT $4"#
+    ))
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let urls: Vec<String> = sqlx::query_scalar(&format!(
+        r#"SELECT p.url FROM pages p
+           WHERE p.service_id = ? AND NOT EXISTS (
+               SELECT 1 FROM hits h
+               WHERE h.service_id = p.service_id AND h.start_time >= ? AND h.start_time < ? {env} {flagged}
+               AND (h.location = p.url
+                    OR substr(h.location, 1, length(p.url) + 1) IN (p.url || '?', p.url || '#')))
+           ORDER BY p.url LIMIT ?"#
+    ))
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(urls)
+}
+
+/// Start of the UTC day `day`
+pub fn day_start(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(NaiveTime::MIN).and_utc()
+}
+
+/// The days from `first` up to (not including) `last` whose top pages are
+/// materialized
+pub async fn list_materialized_days(
+    pool: &Pool,
+    service_id: ServiceId,
+    first: NaiveDate,
+    last: NaiveDate,
+) -> Result<Vec<NaiveDate>> {
+    #[cfg(feature = "postgres")]
+    let days: Vec<NaiveDate> = sqlx::query_scalar(
+        "SELECT day FROM top_locations_days
+         WHERE service_id = $1 AND day >= $2 AND day < $3 ORDER BY day",
+    )
+    .bind(service_id.0)
+    .bind(first)
+    .bind(last)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let days: Vec<NaiveDate> = {
+        let days: Vec<String> = sqlx::query_scalar(
+            "SELECT day FROM top_locations_days
+             WHERE service_id = ? AND day >= ? AND day < ? ORDER BY day",
+        )
+        .bind(service_id.0.to_string())
+        .bind(first.to_string())
+        .bind(last.to_string())
+        .fetch_all(pool)
+        .await?;
+        days.iter().filter_map(|day| day.parse().ok()).collect()
+    };
+
+    Ok(days)
+}
+
+/// Count the hits per page of the UTC day `day` into the top pages table,
+/// replacing what it held for the day, and mark the day materialized
+pub async fn materialize_top_locations(
+    pool: &Pool,
+    service_id: ServiceId,
+    day: NaiveDate,
+) -> Result<()> {
+    let (start, end) = (day_start(day), day_start(day + Duration::days(1)));
+    let flagged = flagged_filter("hits");
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(String, String, i64)> = sqlx::query_as(&format!(
+        "SELECT environment, location, COUNT(*) FROM hits
+         WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {flagged}
+         GROUP BY environment, location"
+    ))
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(String, String, i64)> = sqlx::query_as(&format!(
+        "SELECT environment, location, COUNT(*) FROM hits
+         WHERE service_id = ? AND start_time >= ? AND start_time < ? {flagged}
+         GROUP BY environment, location"
+    ))
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    // Stored normalized, as the top pages show them
+    let mut counts: HashMap<(String, String), i64> = HashMap::new();
+    for (environment, location, count) in rows {
+        *counts
+            .entry((environment, normalize_location(&location)))
+            .or_insert(0) += count;
+    }
+
+    let mut tx = pool.begin().await?;
+
+    #[cfg(feature = "postgres")]
+    {
+        sqlx::query("DELETE FROM top_locations_daily WHERE service_id = $1 AND day = $2")
+            .bind(service_id.0)
+            .bind(day)
+            .execute(&mut *tx)
+            .await?;
+        for ((environment, location), hits) in &counts {
+            sqlx::query(
+                r#"INSERT INTO top_locations_daily (service_id, day, environment, location, hits)
+                   VALUES ($1, $2, $3, $4, $5)"#,
+            )
+            .bind(service_id.0)
+            .bind(day)
+            .bind(environment)
+            .bind(location)
+            .bind(hits)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query(
+            "INSERT INTO top_locations_days (service_id, day) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(service_id.0)
+        .bind(day)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        sqlx::query("DELETE FROM top_locations_daily WHERE service_id = ? AND day = ?")
+            .bind(service_id.0.to_string())
+            .bind(day.to_string())
+            .execute(&mut *tx)
+            .await?;
+        for ((environment, location), hits) in &counts {
+            sqlx::query(
+                r#"INSERT INTO top_locations_daily (service_id, day, environment, location, hits)
+                   VALUES (?, ?, ?, ?, ?)"#,
+            )
+            .bind(service_id.0.to_string())
+            .bind(day.to_string())
+            .bind(environment)
+            .bind(location)
+            .bind(hits)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query(
+            "INSERT INTO top_locations_days (service_id, day) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        )
+        .bind(service_id.0.to_string())
+        .bind(day.to_string())
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Delete every service's daily top pages of the days before `day`, which
+/// stats then count live. Returns the number of rows deleted.
+pub async fn delete_top_locations_before(pool: &Pool, day: NaiveDate) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+
+    // The day markers go first, so no range is read from partly deleted days
+    #[cfg(feature = "postgres")]
+    let result = {
+        sqlx::query("DELETE FROM top_locations_days WHERE day < $1")
+            .bind(day)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM top_locations_daily WHERE day < $1")
+            .bind(day)
+            .execute(&mut *tx)
+            .await?
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let result = {
+        sqlx::query("DELETE FROM top_locations_days WHERE day < ?")
+            .bind(day.to_string())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM top_locations_daily WHERE day < ?")
+            .bind(day.to_string())
+            .execute(&mut *tx)
+            .await?
+    };
+
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+// Hit idempotency filter queries
+/// The saved idempotency filter of a UTC day, if any
+pub async fn get_hit_filter(pool: &Pool, day: NaiveDate) -> Result<Option<Vec<u8>>> {
+    #[cfg(feature = "postgres")]
+    let bits: Option<Vec<u8>> = sqlx::query_scalar("SELECT bits FROM hit_filters WHERE day = $1")
+        .bind(day)
+        .fetch_optional(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let bits: Option<Vec<u8>> = sqlx::query_scalar("SELECT bits FROM hit_filters WHERE day = ?")
+        .bind(day.to_string())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(bits)
+}
+
+/// Save the idempotency filter of a UTC day, replacing the old one
+pub async fn save_hit_filter(pool: &Pool, day: NaiveDate, bits: &[u8]) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO hit_filters (day, bits) VALUES ($1, $2)
+           ON CONFLICT (day) DO UPDATE SET bits = EXCLUDED.bits"#,
+    )
+    .bind(day)
+    .bind(bits)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO hit_filters (day, bits) VALUES (?, ?)
+           ON CONFLICT (day) DO UPDATE SET bits = excluded.bits"#,
+    )
+    .bind(day.to_string())
+    .bind(bits)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete the idempotency filters of the days before `day`
+pub async fn delete_hit_filters_before(pool: &Pool, day: NaiveDate) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM hit_filters WHERE day < $1")
+        .bind(day)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM hit_filters WHERE day < ?")
+        .bind(day.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Visitor sketch queries
+/// The stored visitor sketch of a service's UTC day, if any
+pub async fn get_visitor_sketch(
+    pool: &Pool,
+    service_id: ServiceId,
+    day: NaiveDate,
+    environment: Environment,
+) -> Result<Option<VisitorSketch>> {
+    #[cfg(feature = "postgres")]
+    let bytes: Option<Vec<u8>> = sqlx::query_scalar(
+        "SELECT sketch FROM visitor_sketches
+         WHERE service_id = $1 AND day = $2 AND environment = $3",
+    )
+    .bind(service_id.0)
+    .bind(day)
+    .bind(environment.as_str())
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let bytes: Option<Vec<u8>> = sqlx::query_scalar(
+        "SELECT sketch FROM visitor_sketches
+         WHERE service_id = ? AND day = ? AND environment = ?",
+    )
+    .bind(service_id.0.to_string())
+    .bind(day.to_string())
+    .bind(environment.as_str())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(bytes.and_then(|bytes| VisitorSketch::from_bytes(&bytes)))
+}
+
+/// Store the visitor sketch of a service's UTC day, replacing the old one
+pub async fn save_visitor_sketch(
+    pool: &Pool,
+    service_id: ServiceId,
+    day: NaiveDate,
+    environment: Environment,
+    sketch: &VisitorSketch,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO visitor_sketches (service_id, day, environment, sketch) VALUES ($1, $2, $3, $4)
+           ON CONFLICT (service_id, day, environment) DO UPDATE SET sketch = EXCLUDED.sketch"#,
+    )
+    .bind(service_id.0)
+    .bind(day)
+    .bind(environment.as_str())
+    .bind(sketch.to_bytes())
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO visitor_sketches (service_id, day, environment, sketch) VALUES (?, ?, ?, ?)
+           ON CONFLICT (service_id, day, environment) DO UPDATE SET sketch = excluded.sketch"#,
+    )
+    .bind(service_id.0.to_string())
+    .bind(day.to_string())
+    .bind(environment.as_str())
+    .bind(sketch.to_bytes())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The visitors of the UTC days from `first` to `last` (inclusive) merged
+/// into one sketch, or `None` if none of the days has one
+pub async fn get_merged_visitor_sketch(
+    pool: &Pool,
+    service_id: ServiceId,
+    first: NaiveDate,
+    last: NaiveDate,
+    environment: Option<Environment>,
+) -> Result<Option<VisitorSketch>> {
+    let env = environment_filter(environment, "environment");
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<Vec<u8>> = sqlx::query_scalar(&format!(
+        "SELECT sketch FROM visitor_sketches
+         WHERE service_id = $1 AND day >= $2 AND day <= $3 {env}"
+    ))
+    .bind(service_id.0)
+    .bind(first)
+    .bind(last)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<Vec<u8>> = sqlx::query_scalar(&format!(
+        "SELECT sketch FROM visitor_sketches
+         WHERE service_id = ? AND day >= ? AND day <= ? {env}"
+    ))
+    .bind(service_id.0.to_string())
+    .bind(first.to_string())
+    .bind(last.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    let mut sketches = rows
+        .iter()
+        .filter_map(|bytes| VisitorSketch::from_bytes(bytes));
+    let Some(mut merged) = sketches.next() else {
+        return Ok(None);
+    };
+    for sketch in sketches {
+        merged.merge(&sketch);
+    }
+    Ok(Some(merged))
+}
+
+/// `?first, ?first+1, ...`: `count` numbered SQLite parameters for an `IN` list
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+fn numbered_placeholders(first: usize, count: usize) -> String {
+    (first..first + count)
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Sessions started and hits recorded between `start` and `end` for each
+/// service, in one grouped query per table. Services without any are left out.
+pub async fn get_basic_counts_bulk(
+    pool: &Pool,
+    service_ids: &[ServiceId],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+) -> Result<HashMap<ServiceId, (i64, i64)>> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    let mut counts: HashMap<ServiceId, (i64, i64)> = HashMap::new();
+    if service_ids.is_empty() {
+        return Ok(counts);
+    }
+
+    #[cfg(feature = "postgres")]
+    {
+        let ids: Vec<uuid::Uuid> = service_ids.iter().map(|id| id.0).collect();
+        let sessions: Vec<(uuid::Uuid, i64)> = sqlx::query_as(&format!(
+            r#"SELECT service_id, COUNT(*) FROM sessions
+               WHERE service_id = ANY($1) AND start_time >= $2 AND start_time < $3 {env}
+               GROUP BY service_id"#
+        ))
+        .bind(&ids)
+        .bind(start)
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+
+        let hits: Vec<(uuid::Uuid, i64)> = sqlx::query_as(&format!(
+            r#"SELECT service_id, COUNT(*) FROM hits
+               WHERE service_id = ANY($1) AND start_time >= $2 AND start_time < $3 {env} {flagged}
+               GROUP BY service_id"#
+        ))
+        .bind(&ids)
+        .bind(start)
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+
+        for (id, count) in sessions {
+            counts.entry(ServiceId(id)).or_default().0 = count;
+        }
+        for (id, count) in hits {
+            counts.entry(ServiceId(id)).or_default().1 = count;
+        }
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        let ids = numbered_placeholders(3, service_ids.len());
+        let mut grouped = Vec::with_capacity(2);
+        for (table, flagged) in [("sessions", ""), ("hits", flagged.as_str())] {
+            let sql = format!(
+                r#"SELECT service_id, COUNT(*) FROM {table}
+                   WHERE service_id IN ({ids}) AND start_time >= ?1 AND start_time < ?2 {env} {flagged}
+                   GROUP BY service_id"#
+            );
+            let mut query = sqlx::query_as::<_, (String, i64)>(&sql)
+                .bind(start.to_rfc3339())
+                .bind(end.to_rfc3339());
+            for id in service_ids {
+                query = query.bind(id.0.to_string());
+            }
+            grouped.push(query.fetch_all(pool).await?);
+        }
+
+        for (id, count) in &grouped[0] {
+            let id = ServiceId(id.parse().unwrap_or_default());
+            counts.entry(id).or_default().0 = *count;
+        }
+        for (id, count) in &grouped[1] {
+            let id = ServiceId(id.parse().unwrap_or_default());
+            counts.entry(id).or_default().1 = *count;
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Sessions and hits of each service per day over the `days` days up to
+/// `end`, in one query. Days are 24-hour windows ending at `end`, so the last
+/// one is the past 24 hours.
+pub async fn get_daily_trends(
+    pool: &Pool,
+    service_ids: &[ServiceId],
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    days: usize,
+) -> Result<HashMap<ServiceId, DailyTrend>> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    let mut trends: HashMap<ServiceId, DailyTrend> = service_ids
+        .iter()
+        .map(|&id| (id, DailyTrend::empty(days)))
+        .collect();
+    if service_ids.is_empty() || days == 0 {
+        return Ok(trends);
+    }
+    let start = end - Duration::days(days as i64);
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(uuid::Uuid, String, i64, i64)> = sqlx::query_as(
+        &format!(r#"SELECT service_id, 'sessions', FLOOR(EXTRACT(EPOCH FROM start_time - $2) / 86400)::BIGINT AS day, COUNT(*)
+           FROM sessions WHERE service_id = ANY($1) AND start_time >= $2 AND start_time < $3 {env}
+           GROUP BY service_id, day
+           UNION ALL
+           SELECT service_id, 'hits', FLOOR(EXTRACT(EPOCH FROM start_time - $2) / 86400)::BIGINT AS day, COUNT(*)
+           FROM hits WHERE service_id = ANY($1) AND start_time >= $2 AND start_time < $3 {env} {flagged}
+           GROUP BY service_id, day"#),
+    )
+    .bind(service_ids.iter().map(|id| id.0).collect::<Vec<_>>())
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(String, String, i64, i64)> = {
+        let ids = numbered_placeholders(4, service_ids.len());
+        let sql = format!(
+            r#"SELECT service_id, 'sessions', (CAST(strftime('%s', start_time) AS INTEGER) - ?1) / 86400 AS day, COUNT(*)
+               FROM sessions WHERE service_id IN ({ids}) AND start_time >= ?2 AND start_time < ?3 {env}
+               GROUP BY service_id, day
+               UNION ALL
+               SELECT service_id, 'hits', (CAST(strftime('%s', start_time) AS INTEGER) - ?1) / 86400 AS day, COUNT(*)
+               FROM hits WHERE service_id IN ({ids}) AND start_time >= ?2 AND start_time < ?3 {env} {flagged}
+               GROUP BY service_id, day"#
+        );
+        let mut query = sqlx::query_as(&sql)
+            .bind(start.timestamp())
+            .bind(start.to_rfc3339())
+            .bind(end.to_rfc3339());
+        for id in service_ids {
+            query = query.bind(id.0.to_string());
+        }
+        query.fetch_all(pool).await?
+    };
+
+    for (service_id, table, day, count) in rows {
+        #[cfg(feature = "postgres")]
+        let service_id = ServiceId(service_id);
+        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+        let service_id = ServiceId(service_id.parse().unwrap_or_default());
+
+        let (Some(trend), Ok(day)) = (trends.get_mut(&service_id), usize::try_from(day)) else {
+            continue;
+        };
+        let series = if table == "sessions" {
+            &mut trend.sessions
+        } else {
+            &mut trend.hits
+        };
+        if let Some(slot) = series.get_mut(day) {
+            *slot = count;
+        }
+    }
+
+    Ok(trends)
+}
+
+/// Sessions and hits of a service per `bucket_secs` from `start` until
+/// `end`, the last bucket possibly cut short
+pub async fn get_time_series(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    bucket_secs: i64,
+) -> Result<TimeSeries> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    let bucket_secs = bucket_secs.max(1);
+    let seconds = u64::try_from((end - start).num_seconds()).unwrap_or_default();
+    let buckets = usize::try_from(seconds.div_ceil(bucket_secs as u64)).unwrap_or_default();
+    let mut series = TimeSeries {
+        start,
+        bucket_secs,
+        sessions: vec![0; buckets],
+        hits: vec![0; buckets],
+    };
+    if buckets == 0 {
+        return Ok(series);
+    }
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(&format!(
+        r#"SELECT 'sessions', FLOOR(EXTRACT(EPOCH FROM start_time - $2) / $4::BIGINT)::BIGINT AS bucket, COUNT(*)
+           FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}
+           GROUP BY bucket
+           UNION ALL
+           SELECT 'hits', FLOOR(EXTRACT(EPOCH FROM start_time - $2) / $4::BIGINT)::BIGINT AS bucket, COUNT(*)
+           FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged}
+           GROUP BY bucket"#
+    ))
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .bind(bucket_secs)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(&format!(
+        r#"SELECT 'sessions', (CAST(strftime('%s', start_time) AS INTEGER) - ?4) / ?5 AS bucket, COUNT(*)
+           FROM sessions WHERE service_id = ?1 AND start_time >= ?2 AND start_time < ?3 {env}
+           GROUP BY bucket
+           UNION ALL
+           SELECT 'hits', (CAST(strftime('%s', start_time) AS INTEGER) - ?4) / ?5 AS bucket, COUNT(*)
+           FROM hits WHERE service_id = ?1 AND start_time >= ?2 AND start_time < ?3 {env} {flagged}
+           GROUP BY bucket"#
+    ))
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(start.timestamp())
+    .bind(bucket_secs)
+    .fetch_all(pool)
+    .await?;
+
+    for (table, bucket, count) in rows {
+        let counts = if table == "sessions" {
+            &mut series.sessions
+        } else {
+            &mut series.hits
+        };
+        if let Some(slot) = usize::try_from(bucket).ok().and_then(|i| counts.get_mut(i)) {
+            *slot = count;
+        }
+    }
+    Ok(series)
+}
+
+/// What a service's public badge shows: sessions started since `since` and
+/// sessions active after `active_cutoff`
+pub async fn get_badge_counts(
+    pool: &Pool,
+    service_id: ServiceId,
+    since: DateTime<Utc>,
+    active_cutoff: DateTime<Utc>,
+) -> Result<BadgeCounts> {
+    #[cfg(feature = "postgres")]
+    let (visitors, online): (i64, i64) = sqlx::query_as(
+        r#"SELECT
+           (SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND environment = 'production'),
+           (SELECT COUNT(*) FROM sessions
+            WHERE service_id = $1 AND last_seen > $3 AND ended_at IS NULL
+            AND environment = 'production')"#,
+    )
+    .bind(service_id.0)
+    .bind(since)
+    .bind(active_cutoff)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let (visitors, online): (i64, i64) = sqlx::query_as(
+        r#"SELECT
+           (SELECT COUNT(*) FROM sessions WHERE service_id = ?1 AND start_time >= ?2 AND environment = 'production'),
+           (SELECT COUNT(*) FROM sessions
+            WHERE service_id = ?1 AND last_seen > ?3 AND ended_at IS NULL
+            AND environment = 'production')"#,
+    )
+    .bind(service_id.0.to_string())
+    .bind(since.to_rfc3339())
+    .bind(active_cutoff.to_rfc3339())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(BadgeCounts { visitors, online })
+}
+
+/// Live counters of a service: sessions active since `active_cutoff`, and
+/// sessions and hits since `day_start`
+pub async fn get_live_counters(
+    pool: &Pool,
+    service_id: ServiceId,
+    day_start: DateTime<Utc>,
+    active_cutoff: DateTime<Utc>,
+) -> Result<LiveCounters> {
+    let flagged = flagged_filter("hits");
+    #[cfg(feature = "postgres")]
+    let (online, sessions_today, hits_today): (i64, i64, i64) = sqlx::query_as(&format!(
+        r#"SELECT
+           (SELECT COUNT(*) FROM sessions
+            WHERE service_id = $1 AND last_seen > $3 AND ended_at IS NULL
+            AND environment = 'production'),
+           (SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND environment = 'production'),
+           (SELECT COUNT(*) FROM hits WHERE service_id = $1 AND start_time >= $2 AND environment = 'production' {flagged})"#
+    ))
+    .bind(service_id.0)
+    .bind(day_start)
+    .bind(active_cutoff)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let (online, sessions_today, hits_today): (i64, i64, i64) = sqlx::query_as(&format!(
+        r#"SELECT
+           (SELECT COUNT(*) FROM sessions
+            WHERE service_id = ?1 AND last_seen > ?3 AND ended_at IS NULL
+            AND environment = 'production'),
+           (SELECT COUNT(*) FROM sessions WHERE service_id = ?1 AND start_time >= ?2 AND environment = 'production'),
+           (SELECT COUNT(*) FROM hits WHERE service_id = ?1 AND start_time >= ?2 AND environment = 'production' {flagged})"#
+    ))
+    .bind(service_id.0.to_string())
+    .bind(day_start.to_rfc3339())
+    .bind(active_cutoff.to_rfc3339())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(LiveCounters {
+        online,
+        sessions_today,
+        hits_today,
+    })
+}
+
+/// The monitor's last certificate and domain lookup for a service
+pub async fn get_expiry_check(pool: &Pool, service_id: ServiceId) -> Result<Option<ExpiryCheck>> {
+    #[cfg(feature = "postgres")]
+    let row: Option<ExpiryCheckRow> = sqlx::query_as(
+        r#"SELECT service_id, checked_at, certificate_expires_at, domain_expires_at
+           FROM expiry_checks WHERE service_id = $1"#,
+    )
+    .bind(service_id.0)
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: Option<ExpiryCheckRow> = sqlx::query_as(
+        r#"SELECT service_id, checked_at, certificate_expires_at, domain_expires_at
+           FROM expiry_checks WHERE service_id = ?"#,
+    )
+    .bind(service_id.0.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Into::into))
+}
+
+/// Store a lookup, replacing the service's previous one
+pub async fn save_expiry_check(pool: &Pool, check: &ExpiryCheck) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO expiry_checks (service_id, checked_at, certificate_expires_at, domain_expires_at)
+           VALUES ($1, $2, $3, $4)
+           ON CONFLICT (service_id) DO UPDATE SET
+           checked_at = excluded.checked_at,
+           certificate_expires_at = excluded.certificate_expires_at,
+           domain_expires_at = excluded.domain_expires_at"#,
+    )
+    .bind(check.service_id.0)
+    .bind(check.checked_at)
+    .bind(check.certificate_expires_at)
+    .bind(check.domain_expires_at)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO expiry_checks (service_id, checked_at, certificate_expires_at, domain_expires_at)
+           VALUES (?, ?, ?, ?)
+           ON CONFLICT (service_id) DO UPDATE SET
+           checked_at = excluded.checked_at,
+           certificate_expires_at = excluded.certificate_expires_at,
+           domain_expires_at = excluded.domain_expires_at"#,
+    )
+    .bind(check.service_id.0.to_string())
+    .bind(check.checked_at.to_rfc3339())
+    .bind(check.certificate_expires_at.map(|time| time.to_rfc3339()))
+    .bind(check.domain_expires_at.map(|time| time.to_rfc3339()))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Ingest script queries
+/// Source of a service's ingest script, if it has one
+pub async fn get_ingest_script(pool: &Pool, service_id: ServiceId) -> Result<Option<String>> {
+    #[cfg(feature = "postgres")]
+    let source: Option<String> =
+        sqlx::query_scalar("SELECT source FROM ingest_scripts WHERE service_id = $1")
+            .bind(service_id.0)
+            .fetch_optional(pool)
+            .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let source: Option<String> =
+        sqlx::query_scalar("SELECT source FROM ingest_scripts WHERE service_id = ?")
+            .bind(service_id.0.to_string())
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(source)
+}
+
+/// Replace a service's ingest script; an empty source removes it
+pub async fn save_ingest_script(pool: &Pool, service_id: ServiceId, source: &str) -> Result<()> {
+    let now = Utc::now();
+
+    #[cfg(feature = "postgres")]
+    {
+        if source.trim().is_empty() {
+            sqlx::query("DELETE FROM ingest_scripts WHERE service_id = $1")
+                .bind(service_id.0)
+                .execute(pool)
+                .await?;
+        } else {
+            sqlx::query(
+                r#"INSERT INTO ingest_scripts (service_id, source, updated_at)
+                   VALUES ($1, $2, $3)
+                   ON CONFLICT (service_id) DO UPDATE SET
+                   source = excluded.source,
+                   updated_at = excluded.updated_at"#,
+            )
+            .bind(service_id.0)
+            .bind(source)
+            .bind(now)
+            .execute(pool)
+            .await?;
+        }
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        if source.trim().is_empty() {
+            sqlx::query("DELETE FROM ingest_scripts WHERE service_id = ?")
+                .bind(service_id.0.to_string())
+                .execute(pool)
+                .await?;
+        } else {
+            sqlx::query(
+                r#"INSERT INTO ingest_scripts (service_id, source, updated_at)
+                   VALUES (?, ?, ?)
+                   ON CONFLICT (service_id) DO UPDATE SET
+                   source = excluded.source,
+                   updated_at = excluded.updated_at"#,
+            )
+            .bind(service_id.0.to_string())
+            .bind(source)
+            .bind(now.to_rfc3339())
+            .execute(pool)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Maintenance queries
+/// Tables rows are added to and removed from the most, which Postgres's
+/// autovacuum is told to visit sooner than its defaults would
+#[cfg(feature = "postgres")]
+const BUSY_TABLES: [&str; 3] = ["hits", "sessions", "top_locations_daily"];
+
+/// Refresh the query planner's statistics. SQLite also rebuilds the file
+/// to give back the space of deleted rows; Postgres leaves that to
+/// autovacuum, which is tuned to keep up with the busiest tables.
+pub async fn optimize_database(pool: &Pool) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    {
+        // A partitioned table has no storage of its own; its partitions do
+        let mut tables: Vec<String> = BUSY_TABLES.map(String::from).to_vec();
+        if hits_partitioned(pool).await? {
+            tables.retain(|table| table != "hits");
+            tables.push("hits_default".to_string());
+            tables.extend(list_hit_partitions(pool).await?.iter().map(|p| p.name()));
+        }
+        for table in tables {
+            sqlx::query(&format!(
+                "ALTER TABLE {table} SET (autovacuum_vacuum_scale_factor = 0.05, autovacuum_analyze_scale_factor = 0.02)"
+            ))
+            .execute(pool)
+            .await?;
+        }
+        sqlx::query("ANALYZE").execute(pool).await?;
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        sqlx::query("ANALYZE").execute(pool).await?;
+        sqlx::query("VACUUM").execute(pool).await?;
+    }
+
+    Ok(())
+}
+
+/// Record how a maintenance task went, replacing its previous run
+pub async fn save_maintenance_run(pool: &Pool, run: &MaintenanceRun) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO maintenance_runs (task, started_at, duration_ms, error)
+           VALUES ($1, $2, $3, $4)
+           ON CONFLICT (task) DO UPDATE SET
+           started_at = excluded.started_at,
+           duration_ms = excluded.duration_ms,
+           error = excluded.error"#,
+    )
+    .bind(run.task.as_str())
+    .bind(run.started_at)
+    .bind(run.duration_ms)
+    .bind(run.error.as_deref())
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO maintenance_runs (task, started_at, duration_ms, error)
+           VALUES (?, ?, ?, ?)
+           ON CONFLICT (task) DO UPDATE SET
+           started_at = excluded.started_at,
+           duration_ms = excluded.duration_ms,
+           error = excluded.error"#,
+    )
+    .bind(run.task.as_str())
+    .bind(run.started_at.to_rfc3339())
+    .bind(run.duration_ms)
+    .bind(run.error.as_deref())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The last run of each maintenance task that has run, in the order tasks
+/// run
+pub async fn list_maintenance_runs(pool: &Pool) -> Result<Vec<MaintenanceRun>> {
+    let rows: Vec<MaintenanceRunRow> =
+        sqlx::query_as("SELECT task, started_at, duration_ms, error FROM maintenance_runs")
+            .fetch_all(pool)
+            .await?;
+
+    let mut runs: Vec<MaintenanceRun> = rows.into_iter().filter_map(|r| r.into_run()).collect();
+    runs.sort_by_key(|run| MaintenanceTask::ALL.iter().position(|t| *t == run.task));
+    Ok(runs)
+}
+
+// Status queries
+/// Bytes the database takes up on disk
+pub async fn get_database_size(pool: &Pool) -> Result<i64> {
+    #[cfg(feature = "postgres")]
+    let size: i64 = sqlx::query_scalar("SELECT pg_database_size(current_database())")
+        .fetch_one(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let size: i64 = sqlx::query_scalar(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(size)
+}
+
+/// Rows in each table, by table name. Postgres gives its planner's
+/// estimates, which are cheap to read but only as fresh as the last
+/// `ANALYZE` or autovacuum.
+pub async fn get_table_row_counts(pool: &Pool) -> Result<Vec<(String, i64)>> {
+    #[cfg(feature = "postgres")]
+    let counts: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT relname::TEXT, n_live_tup FROM pg_stat_user_tables ORDER BY relname",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let counts: Vec<(String, i64)> = {
+        let tables: Vec<String> = sqlx::query_scalar(
+            r#"SELECT name FROM sqlite_master
+               WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+               ORDER BY name"#,
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut counts = Vec::with_capacity(tables.len());
+        for table in tables {
+            let count: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM \"{}\"",
+                table.replace('"', "\"\"")
+            ))
+            .fetch_one(pool)
+            .await?;
+            counts.push((table, count));
+        }
+        counts
+    };
+
+    Ok(counts)
+}
+
+/// Hits and sessions started since `since`, across all services
+pub async fn count_recent_ingress(pool: &Pool, since: DateTime<Utc>) -> Result<(i64, i64)> {
+    #[cfg(feature = "postgres")]
+    let counts: (i64, i64) = sqlx::query_as(
+        r#"SELECT (SELECT COUNT(*) FROM hits WHERE start_time >= $1),
+                  (SELECT COUNT(*) FROM sessions WHERE start_time >= $1)"#,
+    )
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let counts: (i64, i64) = sqlx::query_as(
+        r#"SELECT (SELECT COUNT(*) FROM hits WHERE start_time >= ?1),
+                  (SELECT COUNT(*) FROM sessions WHERE start_time >= ?1)"#,
+    )
+    .bind(since.to_rfc3339())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(counts)
+}
+
+// Search queries
+/// `LIKE` pattern matching `query` anywhere, with its wildcards escaped
+fn contains_pattern(query: &str) -> String {
+    let escaped = query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// The organization's services whose name contains `query`
+pub async fn search_services(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<SearchResult>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<SearchRow> = sqlx::query_as(
+        r#"SELECT id AS service_id, name AS service_name, name AS value FROM services
+           WHERE organization_id = $1 AND name ILIKE $2 ESCAPE '\'
+           ORDER BY name LIMIT $3"#,
+    )
+    .bind(organization_id.0)
+    .bind(contains_pattern(query))
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<SearchRow> = sqlx::query_as(
+        r#"SELECT id AS service_id, name AS service_name, name AS value FROM services
+           WHERE organization_id = ? AND name LIKE ? ESCAPE '\'
+           ORDER BY name LIMIT ?"#,
+    )
+    .bind(organization_id.0.to_string())
+    .bind(contains_pattern(query))
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| row.into_result(SearchResultKind::Service))
+        .collect())
+}
+
+/// Distinct values of a hits column containing `query`, in hits of the
+/// organization's services since `since`. Bounding the scan by time keeps it
+/// on the hits' `(service_id, start_time)` index.
+async fn search_hit_column(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    column: &str,
+    query: &str,
+    since: DateTime<Utc>,
+) -> Result<Vec<SearchRow>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<SearchRow> = sqlx::query_as(&format!(
+        r#"SELECT DISTINCT h.service_id, s.name AS service_name, h.{column} AS value
+           FROM hits h JOIN services s ON s.id = h.service_id
+           WHERE s.organization_id = $1 AND h.start_time >= $2
+           AND h.{column} ILIKE $3 ESCAPE '\'
+           LIMIT $4"#
+    ))
+    .bind(organization_id.0)
+    .bind(since)
+    .bind(contains_pattern(query))
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<SearchRow> = sqlx::query_as(&format!(
+        r#"SELECT DISTINCT h.service_id, s.name AS service_name, h.{column} AS value
+           FROM hits h JOIN services s ON s.id = h.service_id
+           WHERE s.organization_id = ? AND h.start_time >= ?
+           AND h.{column} LIKE ? ESCAPE '\'
+           LIMIT ?"#
+    ))
+    .bind(organization_id.0.to_string())
+    .bind(since.to_rfc3339())
+    .bind(contains_pattern(query))
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Turn hit column matches into results, one per service and display value
+fn distinct_results(
+    rows: Vec<SearchRow>,
+    kind: SearchResultKind,
+    limit: i64,
+    display: impl Fn(&str) -> Option<String>,
+) -> Vec<SearchResult> {
+    let mut results: Vec<SearchResult> = Vec::new();
+    for row in rows {
+        let Some(value) = display(&row.value) else {
+            continue;
+        };
+        let mut result = row.into_result(kind);
+        result.value = value;
+        if !results
+            .iter()
+            .any(|r| r.service_id == result.service_id && r.value == result.value)
+        {
+            results.push(result);
+        }
+    }
+    results.sort_by(|a, b| a.value.cmp(&b.value));
+    results.truncate(usize::try_from(limit).unwrap_or(0));
+    results
+}
+
+/// Page paths hit since `since` containing `query`, without their query
+/// strings and fragments
+pub async fn search_pages(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    query: &str,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<SearchResult>> {
+    let rows = search_hit_column(pool, organization_id, "location", query, since).await?;
+    Ok(distinct_results(
+        rows,
+        SearchResultKind::Page,
+        limit,
+        |location| Some(normalize_location(location)),
+    ))
+}
+
+/// Domains of referrers since `since` containing `query`
+pub async fn search_referrer_domains(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    query: &str,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<SearchResult>> {
+    let rows = search_hit_column(pool, organization_id, "referrer", query, since).await?;
+    let query = query.to_lowercase();
+    Ok(distinct_results(
+        rows,
+        SearchResultKind::Referrer,
+        limit,
+        |referrer| {
+            Url::parse(referrer)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase))
+                // The match may have been in the referrer's path
+                .filter(|domain| domain.contains(&query))
+        },
+    ))
+}
+
+/// Sessions of the organization's services whose identifier contains
+/// `query`, or whose ID or IP is `query`, most recently seen first. IPs
+/// stored encrypted are found by `ip_hash`, the query's keyed hash.
+pub async fn search_sessions(
+    pool: &Pool,
+    organization_id: OrganizationId,
+    query: &str,
+    ip_hash: Option<&str>,
+    limit: i64,
+) -> Result<Vec<SearchResult>> {
+    let ip = query
+        .parse::<std::net::IpAddr>()
+        .ok()
+        .map(|ip| ip.to_string());
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<SearchRow> = sqlx::query_as(
+        r#"SELECT se.service_id, s.name AS service_name,
+           COALESCE(NULLIF(se.identifier, ''), CAST(se.id AS TEXT)) AS value,
+           se.id AS session_id
+           FROM sessions se JOIN services s ON s.id = se.service_id
+           WHERE s.organization_id = $1
+           AND (se.identifier ILIKE $2 ESCAPE '\' OR CAST(se.id AS TEXT) = $3
+                OR se.ip = CAST($4 AS INET) OR se.ip_hash = $5)
+           ORDER BY se.last_seen DESC LIMIT $6"#,
+    )
+    .bind(organization_id.0)
+    .bind(contains_pattern(query))
+    .bind(query.to_lowercase())
+    .bind(&ip)
+    .bind(ip_hash)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<SearchRow> = sqlx::query_as(
+        r#"SELECT se.service_id, s.name AS service_name,
+           COALESCE(NULLIF(se.identifier, ''), se.id) AS value,
+           se.id AS session_id
+           FROM sessions se JOIN services s ON s.id = se.service_id
+           WHERE s.organization_id = ?
+           AND (se.identifier LIKE ? ESCAPE '\' OR se.id = ?
+                OR se.ip = ? OR se.ip_hash = ?)
+           ORDER BY se.last_seen DESC LIMIT ?"#,
+    )
+    .bind(organization_id.0.to_string())
+    .bind(contains_pattern(query))
+    .bind(query.to_lowercase())
+    .bind(&ip)
+    .bind(ip_hash)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| row.into_result(SearchResultKind::Session))
+        .collect())
+}
+
+// Campaign queries
+/// SQL for the campaign and recipient halves of a session's identifier
+#[cfg(feature = "postgres")]
+const CAMPAIGN_EXPR: &str = "split_part(se.identifier, ':', 1)";
+#[cfg(feature = "postgres")]
+const RECIPIENT_EXPR: &str = "substr(se.identifier, position(':' in se.identifier) + 1)";
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+const CAMPAIGN_EXPR: &str = "substr(se.identifier, 1, instr(se.identifier, ':') - 1)";
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+const RECIPIENT_EXPR: &str = "substr(se.identifier, instr(se.identifier, ':') + 1)";
+
+/// Pixel hits in a range whose identifier names a campaign, joined as
+/// `h` and `se`
+#[cfg(feature = "postgres")]
+const CAMPAIGN_HITS: &str = "hits h JOIN sessions se ON se.id = h.session_id
+     WHERE h.service_id = $1 AND h.start_time >= $2 AND h.start_time < $3
+     AND h.tracker = 'PIXEL' AND NOT h.prefetched AND position(':' in se.identifier) > 1";
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+const CAMPAIGN_HITS: &str = "hits h JOIN sessions se ON se.id = h.session_id
+     WHERE h.service_id = ? AND h.start_time >= ? AND h.start_time < ?
+     AND h.tracker = 'PIXEL' AND NOT h.prefetched AND instr(se.identifier, ':') > 1";
+
+/// Email campaigns opened in a range, most recently opened first
+pub async fn list_campaigns(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<CampaignSummary>> {
+    get_campaign_summaries(pool, service_id, start, end, None).await
+}
+
+async fn get_campaign_summaries(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    campaign: Option<&str>,
+) -> Result<Vec<CampaignSummary>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<CampaignRow> = sqlx::query_as(&format!(
+        "SELECT {CAMPAIGN_EXPR} AS campaign, COUNT(*) AS opens,
+                COUNT(DISTINCT se.identifier) AS unique_opens,
+                MIN(h.start_time) AS first_open, MAX(h.start_time) AS last_open
+         FROM {CAMPAIGN_HITS} AND ($4::text IS NULL OR {CAMPAIGN_EXPR} = $4)
+         GROUP BY 1 ORDER BY last_open DESC LIMIT $5"
+    ))
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .bind(campaign)
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<CampaignRow> = sqlx::query_as(&format!(
+        "SELECT {CAMPAIGN_EXPR} AS campaign, COUNT(*) AS opens,
+                COUNT(DISTINCT se.identifier) AS unique_opens,
+                MIN(h.start_time) AS first_open, MAX(h.start_time) AS last_open
+         FROM {CAMPAIGN_HITS} AND (?4 IS NULL OR {CAMPAIGN_EXPR} = ?4)
+         GROUP BY 1 ORDER BY last_open DESC LIMIT ?5"
+    ))
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(campaign)
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Opens, open times and recipients of one campaign in a range; `None` when
+/// it was not opened
+pub async fn get_campaign_report(
+    pool: &Pool,
+    service_id: ServiceId,
+    campaign: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Option<CampaignReport>> {
+    let Some(summary) = get_campaign_summaries(pool, service_id, start, end, Some(campaign))
+        .await?
+        .into_iter()
+        .next()
+    else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "postgres")]
+    let (recipient_rows, delay_rows): (Vec<CampaignRecipientRow>, Vec<BucketRow>) = {
+        let recipients = sqlx::query_as(&format!(
+            "SELECT {RECIPIENT_EXPR} AS recipient, COUNT(*) AS opens,
+                    MIN(h.start_time) AS first_open, MAX(h.start_time) AS last_open
+             FROM {CAMPAIGN_HITS} AND {CAMPAIGN_EXPR} = $4
+             GROUP BY 1 ORDER BY first_open, recipient LIMIT $5"
+        ))
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .bind(campaign)
+        .bind(RESULTS_LIMIT)
+        .fetch_all(pool)
+        .await?;
+        let delays = sqlx::query_as(&format!(
+            "SELECT {} AS bucket, COUNT(*) AS count FROM (
+                 SELECT EXTRACT(EPOCH FROM (h.start_time - $5)) AS delay
+                 FROM {CAMPAIGN_HITS} AND {CAMPAIGN_EXPR} = $4
+             ) delays
+             GROUP BY bucket",
+            bucket_case("delay", SessionHistogram::OpenDelay)
+        ))
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .bind(campaign)
+        .bind(summary.first_open)
+        .fetch_all(pool)
+        .await?;
+        (recipients, delays)
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let (recipient_rows, delay_rows): (Vec<CampaignRecipientRow>, Vec<BucketRow>) = {
+        let recipients = sqlx::query_as(&format!(
+            "SELECT {RECIPIENT_EXPR} AS recipient, COUNT(*) AS opens,
+                    MIN(h.start_time) AS first_open, MAX(h.start_time) AS last_open
+             FROM {CAMPAIGN_HITS} AND {CAMPAIGN_EXPR} = ?
+             GROUP BY 1 ORDER BY first_open, recipient LIMIT ?"
+        ))
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .bind(campaign)
+        .bind(RESULTS_LIMIT)
+        .fetch_all(pool)
+        .await?;
+        let delays = sqlx::query_as(&format!(
+            "SELECT {} AS bucket, COUNT(*) AS count FROM (
+                 SELECT ROUND((julianday(h.start_time) - julianday(?)) * 86400) AS delay
+                 FROM {CAMPAIGN_HITS} AND {CAMPAIGN_EXPR} = ?
+             ) delays
+             GROUP BY bucket",
+            bucket_case("delay", SessionHistogram::OpenDelay)
+        ))
+        .bind(summary.first_open.to_rfc3339())
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .bind(campaign)
+        .fetch_all(pool)
+        .await?;
+        (recipients, delays)
+    };
+
+    Ok(Some(CampaignReport {
+        summary,
+        open_delays: SessionHistogram::OpenDelay
+            .buckets(delay_rows.into_iter().map(BucketRow::into_pair)),
+        recipients: recipient_rows.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Sessions per user and devices per user, telling devices apart by type,
+/// OS and browser, of the linked users with a session starting in a range
+pub async fn get_user_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<UserStats> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+        "SELECT sessions, devices, COUNT(*) AS users FROM (
+             SELECT COUNT(*) AS sessions,
+                    COUNT(DISTINCT device_type || '|' || os || '|' || browser) AS devices
+             FROM sessions
+             WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
+               AND user_key <> ''
+             GROUP BY user_key
+         ) users
+         GROUP BY sessions, devices",
+    )
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+        "SELECT sessions, devices, COUNT(*) AS users FROM (
+             SELECT COUNT(*) AS sessions,
+                    COUNT(DISTINCT device_type || '|' || os || '|' || browser) AS devices
+             FROM sessions
+             WHERE service_id = ? AND start_time >= ? AND start_time < ?
+               AND user_key <> ''
+             GROUP BY user_key
+         ) users
+         GROUP BY sessions, devices",
+    )
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(UserStats::from_counts(rows))
+}
+
+/// Weekly retention of the users first seen in the range: weeks are `tz`'s
+/// and begin on `week_start`, and returns count up to the range's end
+pub async fn get_retention(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    tz: Tz,
+    week_start: WeekStart,
+) -> Result<Retention> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(String, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT user_key, start_time FROM sessions
+         WHERE service_id = $1 AND start_time < $3 AND user_key IN (
+             SELECT user_key FROM sessions
+             WHERE service_id = $1 AND user_key <> ''
+             GROUP BY user_key
+             HAVING MIN(start_time) >= $2 AND MIN(start_time) < $3
+         )",
+    )
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(String, DateTime<Utc>)> = {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT user_key, start_time FROM sessions
+             WHERE service_id = ?1 AND start_time < ?3 AND user_key IN (
+                 SELECT user_key FROM sessions
+                 WHERE service_id = ?1 AND user_key <> ''
+                 GROUP BY user_key
+                 HAVING MIN(start_time) >= ?2 AND MIN(start_time) < ?3
+             )",
+        )
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_all(pool)
+        .await?;
+        rows.into_iter()
+            .map(|(user_key, start_time)| (user_key, parse_sqlite_time(&start_time)))
+            .collect()
+    };
+
+    let week_of = |time: DateTime<Utc>| week_start.week_of(time.with_timezone(&tz).date_naive());
+    let mut users: HashMap<String, BTreeSet<NaiveDate>> = HashMap::new();
+    for (user_key, start_time) in rows {
+        users
+            .entry(user_key)
+            .or_default()
+            .insert(week_of(start_time));
+    }
+    Ok(Retention::from_user_weeks(
+        week_of(start),
+        week_of(end - Duration::seconds(1)),
+        users.into_values(),
+    ))
+}
+
+// Tracked link queries
+const LINK_COLUMNS: &str = "id, service_id, token, name, campaign, target_url, created_at";
+
+pub async fn list_tracked_links(pool: &Pool, service_id: ServiceId) -> Result<Vec<TrackedLink>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<TrackedLinkRow> = sqlx::query_as(&format!(
+        "SELECT {LINK_COLUMNS} FROM tracked_links WHERE service_id = $1 ORDER BY created_at DESC"
+    ))
+    .bind(service_id.0)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<TrackedLinkRow> = sqlx::query_as(&format!(
+        "SELECT {LINK_COLUMNS} FROM tracked_links WHERE service_id = ? ORDER BY created_at DESC"
+    ))
+    .bind(service_id.0.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+pub async fn get_tracked_link(pool: &Pool, id: LinkId) -> Result<TrackedLink> {
+    #[cfg(feature = "postgres")]
+    let row: TrackedLinkRow = sqlx::query_as(&format!(
+        "SELECT {LINK_COLUMNS} FROM tracked_links WHERE id = $1"
+    ))
+    .bind(id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::LinkNotFound)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: TrackedLinkRow = sqlx::query_as(&format!(
+        "SELECT {LINK_COLUMNS} FROM tracked_links WHERE id = ?"
+    ))
+    .bind(id.0.to_string())
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::LinkNotFound)?;
+
+    Ok(row.into())
+}
+
+/// The link a `/r/:token` URL redirects through
+pub async fn get_tracked_link_by_token(pool: &Pool, token: &str) -> Result<Option<TrackedLink>> {
+    #[cfg(feature = "postgres")]
+    let row: Option<TrackedLinkRow> = sqlx::query_as(&format!(
+        "SELECT {LINK_COLUMNS} FROM tracked_links WHERE token = $1"
+    ))
+    .bind(token)
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: Option<TrackedLinkRow> = sqlx::query_as(&format!(
+        "SELECT {LINK_COLUMNS} FROM tracked_links WHERE token = ?"
+    ))
+    .bind(token)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Into::into))
+}
+
+pub async fn create_tracked_link(
+    pool: &Pool,
+    service_id: ServiceId,
+    input: CreateTrackedLink,
+) -> Result<TrackedLink> {
+    let id = LinkId::new();
+    let token = if input.slug.is_empty() {
+        TrackingId::with_length(LINK_TOKEN_LENGTH).0
+    } else if get_tracked_link_by_token(pool, &input.slug)
+        .await?
+        .is_some()
+    {
+        return Err(Error::BadRequest(format!(
+            "The short link {} is taken",
+            input.slug
+        )));
+    } else {
+        input.slug
+    };
+    let now = Utc::now();
+
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO tracked_links (id, service_id, token, name, campaign, target_url, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+    )
+    .bind(id.0)
+    .bind(service_id.0)
+    .bind(&token)
+    .bind(input.name.trim())
+    .bind(input.campaign.trim())
+    .bind(input.target_url.trim())
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO tracked_links (id, service_id, token, name, campaign, target_url, created_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(id.0.to_string())
+    .bind(service_id.0.to_string())
+    .bind(&token)
+    .bind(input.name.trim())
+    .bind(input.campaign.trim())
+    .bind(input.target_url.trim())
+    .bind(now.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    get_tracked_link(pool, id).await
+}
+
+pub async fn delete_tracked_link(pool: &Pool, id: LinkId) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("DELETE FROM tracked_links WHERE id = $1")
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("DELETE FROM tracked_links WHERE id = ?")
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn record_link_click(pool: &Pool, click: CreateLinkClick) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"INSERT INTO link_clicks (link_id, session_id, visitor, referrer, country, time)
+           VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(click.link_id.0)
+    .bind(click.session_id.map(|id| id.0))
+    .bind(&click.visitor)
+    .bind(&click.referrer)
+    .bind(&click.country)
+    .bind(click.time)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO link_clicks (link_id, session_id, visitor, referrer, country, time)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(click.link_id.0.to_string())
+    .bind(click.session_id.map(|id| id.0.to_string()))
+    .bind(&click.visitor)
+    .bind(&click.referrer)
+    .bind(&click.country)
+    .bind(click.time.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// A service's links with their clicks in a range, newest link first. The
+/// click-through rate compares unique clicks with the unique opens of the
+/// link's campaign in the same range.
+pub async fn get_tracked_link_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<TrackedLinkStats>> {
+    tracked_link_stats(pool, service_id, None, start, end).await
+}
+
+/// `get_tracked_link_stats`, of one link if `link_id` is set
+async fn tracked_link_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    link_id: Option<LinkId>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<TrackedLinkStats>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<TrackedLinkStatsRow> = sqlx::query_as(
+        r#"SELECT l.id, l.service_id, l.token, l.name, l.campaign, l.target_url, l.created_at,
+                  COUNT(c.id) AS clicks, COUNT(DISTINCT c.visitor) AS unique_clicks
+           FROM tracked_links l
+           LEFT JOIN link_clicks c ON c.link_id = l.id AND c.time >= $2 AND c.time < $3
+           WHERE l.service_id = $1 AND ($4::uuid IS NULL OR l.id = $4)
+           GROUP BY l.id
+           ORDER BY l.created_at DESC"#,
+    )
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .bind(link_id.map(|id| id.0))
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<TrackedLinkStatsRow> = sqlx::query_as(
+        r#"SELECT l.id, l.service_id, l.token, l.name, l.campaign, l.target_url, l.created_at,
+                  COUNT(c.id) AS clicks, COUNT(DISTINCT c.visitor) AS unique_clicks
+           FROM tracked_links l
+           LEFT JOIN link_clicks c ON c.link_id = l.id AND c.time >= ?2 AND c.time < ?3
+           WHERE l.service_id = ?1 AND (?4 IS NULL OR l.id = ?4)
+           GROUP BY l.id
+           ORDER BY l.created_at DESC"#,
+    )
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(link_id.map(|id| id.0.to_string()))
+    .fetch_all(pool)
+    .await?;
+
+    let unique_opens: HashMap<String, i64> = if rows.iter().any(|row| !row.link.campaign.is_empty())
+    {
+        list_campaigns(pool, service_id, start, end)
+            .await?
+            .into_iter()
+            .map(|campaign| (campaign.campaign, campaign.unique_opens))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let link: TrackedLink = row.link.into();
+            let click_through_rate = unique_opens
+                .get(&link.campaign)
+                .filter(|opens| **opens > 0)
+                .map(|opens| row.unique_clicks as f64 / *opens as f64);
+            TrackedLinkStats {
+                link,
+                clicks: row.clicks,
+                unique_clicks: row.unique_clicks,
+                click_through_rate,
+            }
+        })
+        .collect())
+}
+
+/// A link's clicks in a range with their days, referrers and countries
+pub async fn get_tracked_link_report(
+    pool: &Pool,
+    service_id: ServiceId,
+    link_id: LinkId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<TrackedLinkReport> {
+    let stats = tracked_link_stats(pool, service_id, Some(link_id), start, end)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(Error::LinkNotFound)?;
+
+    let seconds = u64::try_from((end - start).num_seconds()).unwrap_or_default();
+    let days = usize::try_from(seconds.max(1).div_ceil(86_400)).unwrap_or_default();
+    let mut daily_clicks = vec![0; days];
+
+    #[cfg(feature = "postgres")]
+    let day_rows: Vec<(i64, i64)> = sqlx::query_as(
+        r#"SELECT FLOOR(EXTRACT(EPOCH FROM time - $2) / 86400)::BIGINT AS day, COUNT(*)
+           FROM link_clicks WHERE link_id = $1 AND time >= $2 AND time < $3
+           GROUP BY day"#,
+    )
+    .bind(link_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let day_rows: Vec<(i64, i64)> = sqlx::query_as(
+        r#"SELECT (CAST(strftime('%s', time) AS INTEGER) - ?4) / 86400 AS day, COUNT(*)
+           FROM link_clicks WHERE link_id = ?1 AND time >= ?2 AND time < ?3
+           GROUP BY day"#,
+    )
+    .bind(link_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(start.timestamp())
+    .fetch_all(pool)
+    .await?;
+
+    for (day, count) in day_rows {
+        if let Some(slot) = usize::try_from(day)
+            .ok()
+            .and_then(|day| daily_clicks.get_mut(day))
+        {
+            *slot = count;
+        }
+    }
+
+    Ok(TrackedLinkReport {
+        stats,
+        daily_clicks,
+        referrers: get_counted_link_clicks(pool, link_id, "referrer", start, end).await?,
+        countries: get_counted_link_clicks(pool, link_id, "country", start, end).await?,
+    })
+}
+
+/// A link's clicks in a range counted by a `link_clicks` column
+async fn get_counted_link_clicks(
+    pool: &Pool,
+    link_id: LinkId,
+    field: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<CountedItem>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<CountedRow> = sqlx::query_as(&format!(
+        "SELECT {field} AS value, COUNT(*) AS count
+         FROM link_clicks WHERE link_id = $1 AND time >= $2 AND time < $3
+         GROUP BY {field} ORDER BY count DESC, value LIMIT $4"
+    ))
+    .bind(link_id.0)
+    .bind(start)
+    .bind(end)
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<CountedRow> = sqlx::query_as(&format!(
+        "SELECT {field} AS value, COUNT(*) AS count
+         FROM link_clicks WHERE link_id = ? AND time >= ? AND time < ?
+         GROUP BY {field} ORDER BY count DESC, value LIMIT ?"
+    ))
+    .bind(link_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+// Session queries
+pub async fn get_session(pool: &Pool, id: SessionId) -> Result<Session> {
+    #[cfg(feature = "postgres")]
+    let row: SessionRow = sqlx::query_as(
+        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+           browser, device, device_type, os, ip::TEXT, ip_encrypted, asn, country, longitude,
+           latitude, time_zone, is_bounce, ended_at, environment, props
+           FROM sessions WHERE id = $1"#,
+    )
+    .bind(id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::SessionNotFound)?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: SessionRow = sqlx::query_as(
+        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+           browser, device, device_type, os, ip, ip_encrypted, asn, country, longitude,
+           latitude, time_zone, is_bounce, ended_at, environment, props
+           FROM sessions WHERE id = ?"#,
+    )
+    .bind(id.0.to_string())
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::SessionNotFound)?;
+
+    Ok(row.into())
+}
+
+pub async fn create_session(pool: &Pool, input: CreateSession) -> Result<Session> {
+    let id = SessionId::new();
+
+    #[cfg(feature = "postgres")]
+    {
+        // Use a query that casts the IP string to INET type
+        sqlx::query(
+            r#"INSERT INTO sessions (id, service_id, identifier, start_time, last_seen,
+               user_agent, browser, device, device_type, os, ip, ip_encrypted, ip_hash,
+               asn, country, longitude, latitude, time_zone, is_bounce, environment)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11::INET, $12, $13, $14, $15, $16, $17, $18, $19, $20)"#
+        )
+        .bind(id.0)
+        .bind(input.service_id.0)
+        .bind(&input.identifier)
+        .bind(input.start_time)
+        .bind(input.start_time)
+        .bind(&input.user_agent)
+        .bind(&input.browser)
+        .bind(&input.device)
+        .bind(input.device_type.as_str())
+        .bind(&input.os)
+        .bind(&input.ip)  // Pass as string, cast in query
+        .bind(&input.ip_encrypted)
+        .bind(&input.ip_hash)
+        .bind(&input.asn)
+        .bind(&input.country)
+        .bind(input.longitude)
+        .bind(input.latitude)
+        .bind(&input.time_zone)
+        .bind(true)
+        .bind(input.environment.as_str())
+        .execute(pool)
+        .await?;
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"INSERT INTO sessions (id, service_id, identifier, start_time, last_seen,
+           user_agent, browser, device, device_type, os, ip, ip_encrypted, ip_hash,
+           asn, country, longitude, latitude, time_zone, is_bounce, environment)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(id.0.to_string())
+    .bind(input.service_id.0.to_string())
+    .bind(&input.identifier)
+    .bind(input.start_time.to_rfc3339())
+    .bind(input.start_time.to_rfc3339())
+    .bind(&input.user_agent)
+    .bind(&input.browser)
+    .bind(&input.device)
+    .bind(input.device_type.as_str())
+    .bind(&input.os)
+    .bind(&input.ip)
+    .bind(&input.ip_encrypted)
+    .bind(&input.ip_hash)
+    .bind(&input.asn)
+    .bind(&input.country)
+    .bind(input.longitude)
+    .bind(input.latitude)
+    .bind(&input.time_zone)
+    .bind(true)
+    .bind(input.environment.as_str())
+    .execute(pool)
+    .await?;
+
+    get_session(pool, id).await
+}
+
+/// Record activity at `time`. Backdated hits may arrive out of order, so the
+/// session only ever grows to include it.
+pub async fn update_session_last_seen(
+    pool: &Pool,
+    id: SessionId,
+    time: DateTime<Utc>,
+) -> Result<()> {
+    // Activity after an end signal means the visitor came back
+    #[cfg(feature = "postgres")]
+    sqlx::query(
+        r#"UPDATE sessions SET start_time = LEAST(start_time, $1),
+           last_seen = GREATEST(last_seen, $1),
+           ended_at = CASE WHEN ended_at < $1 THEN NULL ELSE ended_at END
+           WHERE id = $2"#,
+    )
+    .bind(time)
+    .bind(id.0)
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query(
+        r#"UPDATE sessions SET start_time = MIN(start_time, ?1),
+           last_seen = MAX(last_seen, ?1),
+           ended_at = CASE WHEN ended_at < ?1 THEN NULL ELSE ended_at END
+           WHERE id = ?2"#,
+    )
+    .bind(time.to_rfc3339())
+    .bind(id.0.to_string())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark a session as ended at `ended_at`, or at its last activity when
+/// `None` (the end signal came after the heartbeat cap)
+pub async fn end_session(
+    pool: &Pool,
+    id: SessionId,
+    ended_at: Option<DateTime<Utc>>,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE sessions SET ended_at = COALESCE($1, last_seen) WHERE id = $2")
+        .bind(ended_at)
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE sessions SET ended_at = COALESCE(?, last_seen) WHERE id = ?")
+        .bind(ended_at.map(|t| t.to_rfc3339()))
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_session_identifier(pool: &Pool, id: SessionId, identifier: &str) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE sessions SET identifier = $1 WHERE id = $2")
+        .bind(identifier)
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE sessions SET identifier = ? WHERE id = ?")
+        .bind(identifier)
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// File a session under a linked user's hashed key
+pub async fn set_session_user_key(pool: &Pool, id: SessionId, user_key: &str) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE sessions SET user_key = $1 WHERE id = $2")
+        .bind(user_key)
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE sessions SET user_key = ? WHERE id = ?")
+        .bind(user_key)
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Unlink every session of a service from its user, once the service stops
+/// linking users
+pub async fn clear_user_keys(pool: &Pool, service_id: ServiceId) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE sessions SET user_key = '' WHERE service_id = $1 AND user_key <> ''")
+        .bind(service_id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE sessions SET user_key = '' WHERE service_id = ? AND user_key <> ''")
+        .bind(service_id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Session properties as stored; a malformed value reads as none
+fn parse_session_props(json: &str) -> BTreeMap<String, String> {
+    serde_json::from_str(json).unwrap_or_default()
+}
+
+/// Set properties on a session, keeping the ones not given. New keys are
+/// dropped once the session has `max` of them; known keys are still updated.
+pub async fn merge_session_props(
+    pool: &Pool,
+    id: SessionId,
+    props: &[(String, String)],
+    max: usize,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    let stored: String = sqlx::query_scalar("SELECT props FROM sessions WHERE id = $1")
+        .bind(id.0)
+        .fetch_one(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let stored: String = sqlx::query_scalar("SELECT props FROM sessions WHERE id = ?")
+        .bind(id.0.to_string())
+        .fetch_one(pool)
+        .await?;
+
+    let mut merged = parse_session_props(&stored);
+    for (key, value) in props {
+        if merged.contains_key(key) || merged.len() < max {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+    let json = serde_json::to_string(&merged)?;
+
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE sessions SET props = $1 WHERE id = $2")
+        .bind(&json)
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE sessions SET props = ? WHERE id = ?")
+        .bind(&json)
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Note that the visitor scrolled, clicked, tapped or typed during a session
+pub async fn mark_session_interacted(pool: &Pool, id: SessionId) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query("UPDATE sessions SET interacted = TRUE WHERE id = $1")
+        .bind(id.0)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    sqlx::query("UPDATE sessions SET interacted = 1 WHERE id = ?")
+        .bind(id.0.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Decide again whether a session is a bounce, under its service's rule
+pub async fn recalculate_session_bounce(
+    pool: &Pool,
+    session_id: SessionId,
+    service: &Service,
+) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    {
+        let hit_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE session_id = $1")
+            .bind(session_id.0)
+            .fetch_one(pool)
+            .await?;
+
+        let (start_time, last_seen, interacted): (DateTime<Utc>, DateTime<Utc>, bool) =
+            sqlx::query_as("SELECT start_time, last_seen, interacted FROM sessions WHERE id = $1")
+                .bind(session_id.0)
+                .fetch_one(pool)
+                .await?;
+
+        let is_bounce = service.bounce_rule.is_bounce(
+            hit_count,
+            (last_seen - start_time).num_seconds(),
+            service.bounce_threshold_secs,
+            interacted,
+        );
+        sqlx::query("UPDATE sessions SET is_bounce = $1 WHERE id = $2")
+            .bind(is_bounce)
+            .bind(session_id.0)
+            .execute(pool)
+            .await?;
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        let hit_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE session_id = ?")
+            .bind(session_id.0.to_string())
+            .fetch_one(pool)
+            .await?;
+
+        let (start_time, last_seen, interacted): (String, String, bool) =
+            sqlx::query_as("SELECT start_time, last_seen, interacted FROM sessions WHERE id = ?")
+                .bind(session_id.0.to_string())
+                .fetch_one(pool)
+                .await?;
+
+        let is_bounce = service.bounce_rule.is_bounce(
+            hit_count,
+            (parse_sqlite_time(&last_seen) - parse_sqlite_time(&start_time)).num_seconds(),
+            service.bounce_threshold_secs,
+            interacted,
+        );
+        sqlx::query("UPDATE sessions SET is_bounce = ? WHERE id = ?")
+            .bind(is_bounce)
+            .bind(session_id.0.to_string())
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn list_sessions(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Session>> {
+    // If URL pattern is provided, we need to filter sessions that have matching hits
+    if let Some(pattern) = url_pattern {
+        return list_sessions_with_url_filter(
+            pool,
+            service_id,
+            start,
+            end,
+            environment,
+            pattern,
+            segment,
+            limit,
+            offset,
+        )
+        .await;
+    }
+
+    let env = environment_filter(environment, "environment");
+
+    #[cfg(feature = "postgres")]
+    let mut query = QueryBuilder::new(
+        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+           browser, device, device_type, os, ip::TEXT, ip_encrypted, asn, country, longitude,
+           latitude, time_zone, is_bounce, ended_at, environment, props
+           FROM sessions
+           WHERE "#,
+    );
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let mut query = QueryBuilder::new(
+        r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+           browser, device, device_type, os, ip, ip_encrypted, asn, country, longitude,
+           latitude, time_zone, is_bounce, ended_at, environment, props
+           FROM sessions
+           WHERE "#,
+    );
+    push_service_range(&mut query, "", service_id, start, end);
+    query.push(format!(" {env}"));
+    push_segment_filter(&mut query, segment, "id");
+    query
+        .push(" ORDER BY start_time DESC LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let rows: Vec<SessionRow> = query.build_query_as().fetch_all(pool).await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn list_sessions_with_url_filter(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    url_pattern: &Regex,
+    segment: Option<&Segment>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Session>> {
+    let env = environment_filter(environment, "environment");
+    // Get session IDs that have hits matching the URL pattern
+    let mut query = QueryBuilder::new("SELECT DISTINCT session_id FROM hits WHERE ");
+    push_service_range(&mut query, "", service_id, start, end);
+    query.push(format!(" {env}"));
+    push_segment_filter(&mut query, segment, "session_id");
+
+    #[cfg(feature = "postgres")]
+    let session_ids: Vec<(uuid::Uuid,)> = query.build_query_as().fetch_all(pool).await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let session_ids: Vec<(String,)> = query.build_query_as().fetch_all(pool).await?;
+
+    // For each session, check if it has hits matching the pattern
+    let mut matching_session_ids = Vec::new();
+    for (session_id,) in session_ids {
+        #[cfg(feature = "postgres")]
+        let hits: Vec<(String,)> =
+            sqlx::query_as("SELECT location FROM hits WHERE session_id = $1")
+                .bind(session_id)
+                .fetch_all(pool)
+                .await?;
+
+        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+        let hits: Vec<(String,)> = sqlx::query_as("SELECT location FROM hits WHERE session_id = ?")
+            .bind(&session_id)
+            .fetch_all(pool)
+            .await?;
+
+        if hits.iter().any(|(loc,)| url_pattern.is_match(loc)) {
+            matching_session_ids.push(session_id);
+        }
+    }
+
+    if matching_session_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Fetch the actual sessions with pagination
+    // We need to do pagination in Rust since we filtered in-memory
+    let skip = offset as usize;
+    let take = limit as usize;
+    let paginated_ids: Vec<_> = matching_session_ids
+        .into_iter()
+        .skip(skip)
+        .take(take)
+        .collect();
+
+    if paginated_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut sessions = Vec::new();
+    for session_id in paginated_ids {
+        #[cfg(feature = "postgres")]
+        let row: Option<SessionRow> = sqlx::query_as(
+            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+               browser, device, device_type, os, ip::TEXT, ip_encrypted, asn, country, longitude,
+               latitude, time_zone, is_bounce, ended_at, environment, props
+               FROM sessions WHERE id = $1"#,
+        )
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await?;
+
+        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+        let row: Option<SessionRow> = sqlx::query_as(
+            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+               browser, device, device_type, os, ip, ip_encrypted, asn, country, longitude,
+               latitude, time_zone, is_bounce, ended_at, environment, props
+               FROM sessions WHERE id = ?"#,
+        )
+        .bind(&session_id)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(row) = row {
+            sessions.push(row.into());
+        }
+    }
+
+    // Sort by start_time DESC
+    sessions.sort_by_key(|session: &Session| std::cmp::Reverse(session.start_time));
+
+    Ok(sessions)
+}
+
+/// Send the sessions `list_sessions` would list, all of them and newest
+/// first, to `sink` row by row as the database returns them, so exports of
+/// any size don't have to fit in memory. With a URL pattern, each session
+/// carries its hits' locations to match against. Stops when the receiver
+/// is gone; returns how many sessions were sent.
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_sessions(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+    sink: &tokio::sync::mpsc::Sender<Result<Session>>,
+) -> Result<u64> {
+    let env = environment_filter(environment, "environment");
+
+    // As in `list_sessions_with_url_filter`, a pattern matches sessions by
+    // their hits in the range
+    #[cfg(feature = "postgres")]
+    let mut query = QueryBuilder::new(match url_pattern {
+        None => {
+            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+               browser, device, device_type, os, ip::TEXT, ip_encrypted, asn, country, longitude,
+               latitude, time_zone, is_bounce, ended_at, environment, props,
+               NULL::TEXT AS locations
+               FROM sessions
+               WHERE "#
+        }
+        Some(_) => {
+            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+               browser, device, device_type, os, ip::TEXT, ip_encrypted, asn, country, longitude,
+               latitude, time_zone, is_bounce, ended_at, environment, props,
+               (SELECT string_agg(location, E'\n') FROM hits WHERE hits.session_id = sessions.id) AS locations
+               FROM sessions
+               WHERE id IN (SELECT session_id FROM hits WHERE "#
+        }
+    });
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let mut query = QueryBuilder::new(match url_pattern {
+        None => {
+            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+               browser, device, device_type, os, ip, ip_encrypted, asn, country, longitude,
+               latitude, time_zone, is_bounce, ended_at, environment, props,
+               NULL AS locations
+               FROM sessions
+               WHERE "#
+        }
+        Some(_) => {
+            r#"SELECT id, service_id, identifier, start_time, last_seen, user_agent,
+               browser, device, device_type, os, ip, ip_encrypted, asn, country, longitude,
+               latitude, time_zone, is_bounce, ended_at, environment, props,
+               (SELECT group_concat(location, char(10)) FROM hits WHERE hits.session_id = sessions.id) AS locations
+               FROM sessions
+               WHERE id IN (SELECT session_id FROM hits WHERE "#
+        }
+    });
+    push_service_range(&mut query, "", service_id, start, end);
+    query.push(format!(" {env}"));
+    if url_pattern.is_some() {
+        query.push(")");
+    }
+    push_segment_filter(&mut query, segment, "id");
+    query.push(" ORDER BY start_time DESC");
+    let mut rows = query.build_query_as::<ExportedSessionRow>().fetch(pool);
+
+    let mut sent = 0;
+    while let Some(row) = rows.try_next().await? {
+        if let Some(pattern) = url_pattern {
+            let locations = row.locations.as_deref().unwrap_or_default();
+            if !locations.lines().any(|location| pattern.is_match(location)) {
+                continue;
+            }
+        }
+        if sink.send(Ok(row.session.into())).await.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Send a service's hits in a range, oldest first and each with its
+/// session's country, browser, OS and device type, to `sink` row by row as
+/// the database returns them. Stops when the receiver is gone; returns how
+/// many hits were sent.
+pub async fn stream_hits(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    sink: &tokio::sync::mpsc::Sender<Result<ExportedHit>>,
+) -> Result<u64> {
+    let env = environment_filter(environment, "h.environment");
+
+    #[cfg(feature = "postgres")]
+    let sql = format!(
+        r#"SELECT h.id, h.session_id, h.service_id, h.initial, h.start_time, h.last_seen,
+           h.heartbeats, h.tracker, h.location, h.referrer, h.load_time, h.environment,
+           h.prefetched, h.referrer_spam, s.country, s.browser, s.os, s.device_type
+           FROM hits h JOIN sessions s ON s.id = h.session_id
+           WHERE h.service_id = $1 AND h.start_time >= $2 AND h.start_time < $3 {env}
+           ORDER BY h.start_time, h.id"#
+    );
+    #[cfg(feature = "postgres")]
+    let mut rows = sqlx::query_as::<_, ExportedHitRow>(&sql)
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .fetch(pool);
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let sql = format!(
+        r#"SELECT h.id, h.session_id, h.service_id, h.initial, h.start_time, h.last_seen,
+           h.heartbeats, h.tracker, h.location, h.referrer, h.load_time, h.environment,
+           h.prefetched, h.referrer_spam, s.country, s.browser, s.os, s.device_type
+           FROM hits h JOIN sessions s ON s.id = h.session_id
+           WHERE h.service_id = ? AND h.start_time >= ? AND h.start_time < ? {env}
+           ORDER BY h.start_time, h.id"#
+    );
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let mut rows = sqlx::query_as::<_, ExportedHitRow>(&sql)
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch(pool);
+
+    let mut sent = 0;
+    while let Some(row) = rows.try_next().await? {
+        if sink.send(Ok(row.into())).await.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+// Hit queries
+pub async fn get_hit(pool: &Pool, id: HitId) -> Result<Hit> {
+    #[cfg(feature = "postgres")]
+    let row: HitRow = sqlx::query_as(
+        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam
+           FROM hits WHERE id = $1"#,
+    )
+    .bind(id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Internal("Hit not found".to_string()))?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: HitRow = sqlx::query_as(
+        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam
+           FROM hits WHERE id = ?"#,
+    )
+    .bind(id.0)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Internal("Hit not found".to_string()))?;
+
+    Ok(row.into())
+}
+
+pub async fn create_hit(pool: &Pool, input: CreateHit) -> Result<Hit> {
+    // The row comes back with the insert: looking it up by id alone would
+    // search every month of a partitioned table
+    #[cfg(feature = "postgres")]
+    let hit: Hit = sqlx::query_as::<_, HitRow>(
+        r#"INSERT INTO hits (session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam)
+           VALUES ($1, $2, $3, $4, $5, 0, $6, $7, $8, $9, $10, $11, $12)
+           RETURNING id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam"#,
+    )
+    .bind(input.session_id.0)
+    .bind(input.service_id.0)
+    .bind(input.initial)
+    .bind(input.start_time)
+    .bind(input.start_time)
+    .bind(input.tracker.as_str())
+    .bind(&input.location)
+    .bind(&input.referrer)
+    .bind(input.load_time)
+    .bind(input.environment.as_str())
+    .bind(input.prefetched)
+    .bind(input.referrer_spam)
+    .fetch_one(pool)
+    .await?
+    .into();
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let hit = {
+        sqlx::query(
+            r#"INSERT INTO hits (session_id, service_id, initial, start_time, last_seen,
+               heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam)
+               VALUES (?, ?, ?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(input.session_id.0.to_string())
+        .bind(input.service_id.0.to_string())
+        .bind(input.initial)
+        .bind(input.start_time.to_rfc3339())
+        .bind(input.start_time.to_rfc3339())
+        .bind(input.tracker.as_str())
+        .bind(&input.location)
+        .bind(&input.referrer)
+        .bind(input.load_time)
+        .bind(input.environment.as_str())
+        .bind(input.prefetched)
+        .bind(input.referrer_spam)
+        .execute(pool)
+        .await?;
+
+        let id: i64 = sqlx::query_scalar("SELECT last_insert_rowid()")
+            .fetch_one(pool)
+            .await?;
+        get_hit(pool, HitId(id)).await?
+    };
+
+    Ok(hit)
+}
+
+/// Attach the tracker's custom dimensions to a hit
+pub async fn add_hit_dimensions(
+    pool: &Pool,
+    hit: &Hit,
+    dimensions: &[(String, String)],
+) -> Result<()> {
+    for (key, value) in dimensions {
+        #[cfg(feature = "postgres")]
+        sqlx::query(
+            "INSERT INTO hit_dimensions (hit_id, hit_start_time, key, value) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (hit_id, key) DO NOTHING",
+        )
+        .bind(hit.id.0)
+        .bind(hit.start_time)
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+
+        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+        sqlx::query(
+            "INSERT INTO hit_dimensions (hit_id, hit_start_time, key, value) VALUES (?, ?, ?, ?)
+             ON CONFLICT (hit_id, key) DO NOTHING",
+        )
+        .bind(hit.id.0)
+        .bind(hit.start_time.to_rfc3339())
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Count a heartbeat for a hit and move its `last_seen` up, unless the hit
+/// already has `max_heartbeats` (0 = no limit). Returns whether it counted.
+pub async fn update_hit_heartbeat(
+    pool: &Pool,
+    id: HitId,
+    last_seen: DateTime<Utc>,
+    max_heartbeats: u32,
+) -> Result<bool> {
+    #[cfg(feature = "postgres")]
+    let result = sqlx::query(
+        r#"UPDATE hits SET heartbeats = heartbeats + 1, last_seen = GREATEST(last_seen, $1)
+           WHERE id = $2 AND ($3 = 0 OR heartbeats < $3)"#,
+    )
+    .bind(last_seen)
+    .bind(id.0)
+    .bind(i64::from(max_heartbeats))
+    .execute(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let result = sqlx::query(
+        r#"UPDATE hits SET heartbeats = heartbeats + 1, last_seen = MAX(last_seen, ?1)
+           WHERE id = ?2 AND (?3 = 0 OR heartbeats < ?3)"#,
+    )
+    .bind(last_seen.to_rfc3339())
+    .bind(id.0)
+    .bind(i64::from(max_heartbeats))
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_hits_for_session(
+    pool: &Pool,
+    session_id: SessionId,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Hit>> {
+    #[cfg(feature = "postgres")]
+    let rows: Vec<HitRow> = sqlx::query_as(
+        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam
+           FROM hits WHERE session_id = $1
+           ORDER BY start_time DESC
+           LIMIT $2 OFFSET $3"#,
+    )
+    .bind(session_id.0)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<HitRow> = sqlx::query_as(
+        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam
+           FROM hits WHERE session_id = ?
+           ORDER BY start_time DESC
+           LIMIT ? OFFSET ?"#,
+    )
+    .bind(session_id.0.to_string())
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Find the most recent hit for a session at a specific location.
+/// Used to deduplicate heartbeats when idempotency cache expires.
+pub async fn find_recent_hit_by_location(
+    pool: &Pool,
+    session_id: SessionId,
+    location: &str,
+) -> Result<Option<Hit>> {
+    #[cfg(feature = "postgres")]
+    let row: Option<HitRow> = sqlx::query_as(
+        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam
+           FROM hits WHERE session_id = $1 AND location = $2
+           ORDER BY start_time DESC
+           LIMIT 1"#,
+    )
+    .bind(session_id.0)
+    .bind(location)
+    .fetch_optional(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let row: Option<HitRow> = sqlx::query_as(
+        r#"SELECT id, session_id, service_id, initial, start_time, last_seen,
+           heartbeats, tracker, location, referrer, load_time, environment, prefetched,
+           referrer_spam
+           FROM hits WHERE session_id = ? AND location = ?
+           ORDER BY start_time DESC
+           LIMIT 1"#,
+    )
+    .bind(session_id.0.to_string())
+    .bind(location)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Into::into))
+}
+
+// Hit partitions
+
+/// Statements turning `hits` into a table partitioned by month, with the
+/// old one kept as `hits_unpartitioned` until its rows are copied. The
+/// primary key has to include the partition key, and so does the
+/// reference from `hit_dimensions`.
+#[cfg(feature = "postgres")]
+const PARTITION_HITS: [&str; 13] = [
+    "LOCK TABLE hits IN ACCESS EXCLUSIVE MODE",
+    "ALTER TABLE hits RENAME TO hits_unpartitioned",
+    "ALTER INDEX hits_pkey RENAME TO hits_unpartitioned_pkey",
+    "ALTER INDEX idx_hits_session_start RENAME TO idx_hits_unpartitioned_session_start",
+    "ALTER INDEX idx_hits_service_start RENAME TO idx_hits_unpartitioned_service_start",
+    "CREATE TABLE hits (LIKE hits_unpartitioned INCLUDING DEFAULTS) PARTITION BY RANGE (start_time)",
+    "ALTER SEQUENCE hits_id_seq OWNED BY hits.id",
+    "ALTER TABLE hits ADD CONSTRAINT hits_pkey PRIMARY KEY (id, start_time)",
+    "ALTER TABLE hits ADD CONSTRAINT hits_session_id_fkey
+     FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE",
+    "ALTER TABLE hits ADD CONSTRAINT hits_service_id_fkey
+     FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE",
+    "CREATE INDEX idx_hits_session_start ON hits(session_id, start_time DESC)",
+    "CREATE INDEX idx_hits_service_start ON hits(service_id, start_time DESC)",
+    "CREATE TABLE hits_default PARTITION OF hits DEFAULT",
+];
+
+#[cfg(feature = "postgres")]
+fn create_hit_partition_sql(partition: HitPartition) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} PARTITION OF hits FOR VALUES FROM ('{}') TO ('{}')",
+        partition.name(),
+        partition.start().to_rfc3339(),
+        partition.end().to_rfc3339()
+    )
+}
+
+/// Whether the hits table is partitioned by month. Never on SQLite.
+pub async fn hits_partitioned(pool: &Pool) -> Result<bool> {
+    #[cfg(feature = "postgres")]
+    let partitioned: bool =
+        sqlx::query_scalar("SELECT relkind = 'p' FROM pg_class WHERE oid = 'hits'::regclass")
+            .fetch_one(pool)
+            .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let partitioned = {
+        let _ = pool;
+        false
+    };
+
+    Ok(partitioned)
+}
+
+/// Partition the hits table by month, with partitions from the month of
+/// the oldest hit through `last`, and copy the hits over. Returns how many
+/// were copied. Ingestion waits on the table until it is done. Hits can't
+/// be partitioned on SQLite, where this and the other partition functions
+/// do nothing.
+pub async fn partition_hits(pool: &Pool, last: HitPartition) -> Result<u64> {
+    #[cfg(feature = "postgres")]
+    let copied = {
+        let mut tx = pool.begin().await?;
+        for statement in PARTITION_HITS {
+            sqlx::query(statement).execute(&mut *tx).await?;
+        }
+
+        let oldest: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT MIN(start_time) FROM hits_unpartitioned")
+                .fetch_one(&mut *tx)
+                .await?;
+        let first = oldest
+            .map(|time| HitPartition::containing(time.date_naive()))
+            .map_or(last, |first| first.min(last));
+        for partition in first.through(last) {
+            sqlx::query(&create_hit_partition_sql(partition))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let copied = sqlx::query("INSERT INTO hits SELECT * FROM hits_unpartitioned")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query("ALTER TABLE hit_dimensions DROP CONSTRAINT hit_dimensions_hit_id_fkey")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DROP TABLE hits_unpartitioned")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "ALTER TABLE hit_dimensions ADD CONSTRAINT hit_dimensions_hit_id_fkey
+             FOREIGN KEY (hit_id, hit_start_time) REFERENCES hits(id, start_time) ON DELETE CASCADE",
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        copied
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let copied = {
+        let _ = (pool, last);
+        0
+    };
+
+    Ok(copied)
+}
+
+/// Monthly partitions of the hits table, oldest first; none while it isn't
+/// partitioned
+pub async fn list_hit_partitions(pool: &Pool) -> Result<Vec<HitPartition>> {
+    #[cfg(feature = "postgres")]
+    let names: Vec<String> = sqlx::query_scalar(
+        "SELECT c.relname::TEXT FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid
+         WHERE i.inhparent = 'hits'::regclass",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let names: Vec<String> = {
+        let _ = pool;
+        Vec::new()
+    };
+
+    let mut partitions: Vec<HitPartition> = names
+        .iter()
+        .filter_map(|name| HitPartition::parse(name))
+        .collect();
+    partitions.sort();
+    Ok(partitions)
+}
+
+/// Add the partition of a month to the partitioned hits table
+pub async fn create_hit_partition(pool: &Pool, partition: HitPartition) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    sqlx::query(&create_hit_partition_sql(partition))
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let _ = (pool, partition);
+
+    Ok(())
+}
+
+/// Drop a month of hits along with their dimensions, which have to go
+/// first for Postgres to let go of the partition
+pub async fn drop_hit_partition(pool: &Pool, partition: HitPartition) -> Result<()> {
+    #[cfg(feature = "postgres")]
+    {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM hit_dimensions WHERE hit_start_time >= $1 AND hit_start_time < $2",
+        )
+        .bind(partition.start())
+        .bind(partition.end())
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "ALTER TABLE hits DETACH PARTITION {}",
+            partition.name()
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!("DROP TABLE {}", partition.name()))
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let _ = (pool, partition);
+
+    Ok(())
+}
+
+/// Delete the hits that started before `before`, with their dimensions.
+/// Returns how many were deleted.
+pub async fn delete_hits_before(pool: &Pool, before: DateTime<Utc>) -> Result<u64> {
+    #[cfg(feature = "postgres")]
+    let result = sqlx::query("DELETE FROM hits WHERE start_time < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let result = sqlx::query("DELETE FROM hits WHERE start_time < ?")
+        .bind(before.to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Stats queries
+
+/// SQL narrowing a stats query to one environment's traffic (`None` = every
+/// environment). Environment keys are fixed, so they are safe to inline.
+fn environment_filter(environment: Option<Environment>, column: &str) -> String {
+    match environment {
+        Some(environment) => format!("AND {} = '{}'", column, environment.as_str()),
+        None => String::new(),
+    }
+}
+
+/// SQL leaving hits flagged at ingress out of a stats query on `table` (the
+/// hits table or its alias): prefetched pages may never have been opened,
+/// and referrer spam only shows on the Data Quality panel
+fn flagged_filter(table: &str) -> String {
+    format!("AND NOT {table}.prefetched AND NOT {table}.referrer_spam")
+}
+
+/// Push `service_id = .. AND start_time >= .. AND start_time < ..` on the
+/// columns of `table` (a table alias with its dot, or `""`), with the
+/// service and the range bound
+fn push_service_range(
+    query: &mut QueryBuilder<'_, Db>,
+    table: &str,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) {
+    query.push(format!("{table}service_id = "));
+    #[cfg(feature = "postgres")]
+    query
+        .push_bind(service_id.0)
+        .push(format!(" AND {table}start_time >= "))
+        .push_bind(start)
+        .push(format!(" AND {table}start_time < "))
+        .push_bind(end);
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    query
+        .push_bind(service_id.0.to_string())
+        .push(format!(" AND {table}start_time >= "))
+        .push_bind(start.to_rfc3339())
+        .push(format!(" AND {table}start_time < "))
+        .push_bind(end.to_rfc3339());
+}
+
+/// Narrow a query to a segment's sessions by the session ID in `column`
+/// (`None` = every session). Condition values are visitor data typed by a
+/// user, so they go in as bound parameters.
+fn push_segment_filter(query: &mut QueryBuilder<'_, Db>, segment: Option<&Segment>, column: &str) {
+    let Some(segment) = segment else {
+        return;
+    };
+    query.push(format!(
+        " AND {column} IN (SELECT seg.id FROM sessions seg WHERE seg.service_id = "
+    ));
+    #[cfg(feature = "postgres")]
+    query.push_bind(segment.service_id.0);
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    query.push_bind(segment.service_id.0.to_string());
+    for condition in &segment.conditions {
+        query.push(" AND ");
+        push_segment_condition(query, condition);
+    }
+    query.push(")");
+}
+
+/// `value` escaped for a `LIKE` pattern with `ESCAPE '\'`
+fn like_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Push a comparison of the text in `expr` with `value`
+fn push_compare(query: &mut QueryBuilder<'_, Db>, expr: &str, op: SegmentOp, value: &str) {
+    match op {
+        SegmentOp::Is => query
+            .push(format!("{expr} = "))
+            .push_bind(value.to_string()),
+        SegmentOp::IsNot => query
+            .push(format!("{expr} <> "))
+            .push_bind(value.to_string()),
+        SegmentOp::Contains => query
+            .push(format!("LOWER({expr}) LIKE "))
+            .push_bind(format!("%{}%", like_escape(&value.to_lowercase())))
+            .push(" ESCAPE '\\'"),
+    };
+}
+
+/// Push a condition holding when the location in `expr` is `value` as the
+/// pages breakdown shows it (`normalize_location`): the same but for the
+/// scheme, the query string and the fragment
+fn push_location_is(query: &mut QueryBuilder<'_, Db>, expr: &str, value: &str) {
+    query
+        .push(format!("({expr} = "))
+        .push_bind(value.to_string());
+    let escaped = like_escape(value);
+    for pattern in [
+        format!("{escaped}?%"),
+        format!("{escaped}#%"),
+        format!("%://{escaped}"),
+        format!("%://{escaped}?%"),
+        format!("%://{escaped}#%"),
+    ] {
+        query
+            .push(format!(" OR {expr} LIKE "))
+            .push_bind(pattern)
+            .push(" ESCAPE '\\'");
+    }
+    query.push(")");
+}
+
+/// Push one segment condition on the session `seg`. The referrer is the one
+/// the session arrived with; a page condition holds if any of its hits
+/// does, except `IsNot`, which holds if none is on the page. Pages are
+/// compared as the pages breakdown shows them.
+fn push_segment_condition(query: &mut QueryBuilder<'_, Db>, condition: &SegmentCondition) {
+    let value = condition.column_value();
+    match condition.field {
+        SegmentField::Country => push_compare(query, "seg.country", condition.op, &value),
+        SegmentField::Device => push_compare(query, "seg.device_type", condition.op, &value),
+        SegmentField::Browser => push_compare(query, "seg.browser", condition.op, &value),
+        SegmentField::Os => push_compare(query, "seg.os", condition.op, &value),
+        SegmentField::Referrer => {
+            query.push(
+                "EXISTS (SELECT 1 FROM hits entry WHERE entry.session_id = seg.id AND entry.initial AND ",
+            );
+            push_compare(query, "entry.referrer", condition.op, &value);
+            query.push(")");
+        }
+        SegmentField::Page if condition.op == SegmentOp::Contains => {
+            query.push("EXISTS (SELECT 1 FROM hits page WHERE page.session_id = seg.id AND ");
+            push_compare(query, "page.location", condition.op, &value);
+            query.push(")");
+        }
+        SegmentField::Page => {
+            if condition.op == SegmentOp::IsNot {
+                query.push("NOT ");
+            }
+            query.push("EXISTS (SELECT 1 FROM hits page WHERE page.session_id = seg.id AND ");
+            push_location_is(query, "page.location", &value);
+            query.push(")");
+        }
+    }
+}
+
+/// Core stats with the panel data, and with the period `compare` picks under
+/// `compare` unless it is `None`
+#[allow(clippy::too_many_arguments)]
+pub async fn get_core_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    now: DateTime<Utc>,
+    referrer_filter: Option<&ReferrerFilter>,
+    url_pattern: Option<&Regex>,
+    prop: Option<&SessionPropFilter>,
+    segment: Option<&Segment>,
+    active_user_timeout_ms: u64,
+    tz: Tz,
+    week_start: WeekStart,
+    compare: Option<CompareMode>,
+) -> Result<CoreStats> {
+    core_stats(
+        pool,
+        service_id,
+        start,
+        end,
+        environment,
+        now,
+        referrer_filter,
+        url_pattern,
+        prop,
+        segment,
+        active_user_timeout_ms,
+        tz,
+        week_start,
+        true,
+        compare,
+    )
+    .await
+}
+
+/// Core stats without the panel data (top pages, referrers, countries and
+/// chart), which the dashboard loads separately through its panel partials.
+#[allow(clippy::too_many_arguments)]
+pub async fn get_summary_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    now: DateTime<Utc>,
+    referrer_filter: Option<&ReferrerFilter>,
+    url_pattern: Option<&Regex>,
+    prop: Option<&SessionPropFilter>,
+    segment: Option<&Segment>,
+    active_user_timeout_ms: u64,
+    tz: Tz,
+    week_start: WeekStart,
+    compare: Option<CompareMode>,
+) -> Result<CoreStats> {
+    core_stats(
+        pool,
+        service_id,
+        start,
+        end,
+        environment,
+        now,
+        referrer_filter,
+        url_pattern,
+        prop,
+        segment,
+        active_user_timeout_ms,
+        tz,
+        week_start,
+        false,
+        compare,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn core_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    now: DateTime<Utc>,
+    referrer_filter: Option<&ReferrerFilter>,
+    url_pattern: Option<&Regex>,
+    prop: Option<&SessionPropFilter>,
+    segment: Option<&Segment>,
+    active_user_timeout_ms: u64,
+    tz: Tz,
+    week_start: WeekStart,
+    panels: bool,
+    compare: Option<CompareMode>,
+) -> Result<CoreStats> {
+    let main_stats = get_relative_stats(
+        pool,
+        service_id,
+        start,
+        end,
+        environment,
+        now,
+        referrer_filter,
+        url_pattern,
+        prop,
+        segment,
+        active_user_timeout_ms,
+        tz,
+        week_start,
+        panels,
+    );
+    let Some(mode) = compare else {
+        return main_stats.await;
+    };
+
+    let (compare_start, compare_end) = mode.period(start, end);
+    let compare_stats = get_relative_stats(
+        pool,
+        service_id,
+        compare_start,
+        compare_end,
+        environment,
+        now,
+        referrer_filter,
+        url_pattern,
+        prop,
+        segment,
+        active_user_timeout_ms,
+        tz,
+        week_start,
+        panels,
+    );
+
+    // Both periods are queried at once
+    let (main_stats, compare_stats) = tokio::try_join!(main_stats, compare_stats)?;
+    Ok(CoreStats {
+        compare: Some(Box::new(compare_stats)),
+        compared_period: Some(ComparedPeriod {
+            mode,
+            start: compare_start,
+            end: compare_end,
+        }),
+        ..main_stats
+    })
+}
+
+/// Top pages panel
+pub async fn get_top_locations(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+) -> Result<Vec<CountedItem>> {
+    if url_pattern.is_none() && segment.is_none() {
+        return get_counted_locations(pool, service_id, start, end, environment, RESULTS_LIMIT)
+            .await;
+    }
+    Ok(get_filtered_relative_stats(
+        pool,
+        service_id,
+        start,
+        end,
+        environment,
+        // Only the panel's own counts are used, none of which depend on the
+        // current time
+        end,
+        None,
+        url_pattern,
+        None,
+        segment,
+        0,
+        Tz::UTC,
+        WeekStart::Monday,
+    )
+    .await?
+    .locations)
+}
+
+/// Hits per content group, busiest first, with hits on pages outside every
+/// group under an empty group
+#[allow(clippy::too_many_arguments)]
+pub async fn get_content_group_counts(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    groups: &ContentGroups,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+) -> Result<Vec<CountedItem>> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    let mut query =
+        QueryBuilder::new("SELECT location as value, COUNT(*) as count FROM hits WHERE ");
+    push_service_range(&mut query, "", service_id, start, end);
+    query.push(format!(" {env} {flagged}"));
+    push_segment_filter(&mut query, segment, "session_id");
+    query.push(" GROUP BY location");
+    let rows: Vec<CountedRow> = query.build_query_as().fetch_all(pool).await?;
+
+    Ok(groups.count(
+        rows.iter()
+            .map(|row| (row.value.as_deref().unwrap_or_default(), row.count))
+            .filter(|(location, _)| url_pattern.is_none_or(|pattern| pattern.is_match(location))),
+    ))
+}
+
+/// Dimension keys of the hits in the range, with how many hits carry each,
+/// most used first
+pub async fn get_dimension_keys(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+) -> Result<Vec<CountedItem>> {
+    let env = environment_filter(environment, "h.environment");
+    let flagged = flagged_filter("h");
+    #[cfg(feature = "postgres")]
+    let rows: Vec<CountedRow> = sqlx::query_as(&format!(
+        "SELECT d.key as value, COUNT(*) as count
+         FROM hit_dimensions d JOIN hits h ON h.id = d.hit_id AND h.start_time = d.hit_start_time
+         WHERE h.service_id = $1 AND h.start_time >= $2 AND h.start_time < $3 {env} {flagged}
+         GROUP BY d.key ORDER BY count DESC, value LIMIT $4"
+    ))
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<CountedRow> = sqlx::query_as(&format!(
+        "SELECT d.key as value, COUNT(*) as count
+         FROM hit_dimensions d JOIN hits h ON h.id = d.hit_id
+         WHERE h.service_id = ? AND h.start_time >= ? AND h.start_time < ? {env} {flagged}
+         GROUP BY d.key ORDER BY count DESC, value LIMIT ?"
+    ))
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(RESULTS_LIMIT)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Hits per value of a dimension, busiest first
+#[allow(clippy::too_many_arguments)]
+pub async fn get_dimension_counts(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    key: &str,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+) -> Result<Vec<CountedItem>> {
+    let env = environment_filter(environment, "h.environment");
+    let flagged = flagged_filter("h");
+    #[cfg(feature = "postgres")]
+    let mut query = QueryBuilder::new(
+        "SELECT d.value, h.location, COUNT(*) as count
+         FROM hit_dimensions d JOIN hits h ON h.id = d.hit_id AND h.start_time = d.hit_start_time
+         WHERE ",
+    );
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let mut query = QueryBuilder::new(
+        "SELECT d.value, h.location, COUNT(*) as count
+         FROM hit_dimensions d JOIN hits h ON h.id = d.hit_id
+         WHERE ",
+    );
+    push_service_range(&mut query, "h.", service_id, start, end);
+    query
+        .push(" AND d.key = ")
+        .push_bind(key.to_string())
+        .push(format!(" {env} {flagged}"));
+    push_segment_filter(&mut query, segment, "h.session_id");
+    query.push(" GROUP BY d.value, h.location");
+    let rows: Vec<DimensionRow> = query.build_query_as().fetch_all(pool).await?;
+
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    for row in rows
+        .into_iter()
+        .filter(|row| url_pattern.is_none_or(|pattern| pattern.is_match(&row.location)))
+    {
+        *counts.entry(row.value).or_default() += row.count;
+    }
+    let mut items: Vec<CountedItem> = counts
+        .into_iter()
+        .map(|(value, count)| CountedItem::new(value, count))
+        .collect();
+    items.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    items.truncate(RESULTS_LIMIT as usize);
+    Ok(items)
+}
+
+/// Steps into a visit that page transitions are counted for
+pub const MAX_PATH_STEPS: i64 = 5;
+/// Busiest transitions kept per step
+pub const PATH_TRANSITIONS_PER_STEP: usize = 10;
+
+/// The most common page-to-page transitions within sessions, by step of the
+/// visit and with pages as the pages breakdown shows them. A `url_pattern`
+/// keeps transitions from or to a page it matches.
+#[allow(clippy::too_many_arguments)]
+pub async fn get_page_transitions(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+) -> Result<Vec<PageTransition>> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    #[cfg(feature = "postgres")]
+    let mut query =
+        QueryBuilder::new("SELECT step, source, target, COUNT(*)::BIGINT as count FROM (");
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let mut query = QueryBuilder::new("SELECT step, source, target, COUNT(*) as count FROM (");
+    query.push(
+        " SELECT ROW_NUMBER() OVER visit - 1 as step, LAG(location) OVER visit as source,
+                 location as target
+          FROM hits
+          WHERE ",
+    );
+    push_service_range(&mut query, "", service_id, start, end);
+    query.push(format!(" {env} {flagged}"));
+    push_segment_filter(&mut query, segment, "session_id");
+    query
+        .push(
+            " WINDOW visit AS (PARTITION BY session_id ORDER BY start_time, id)
+         ) transitions
+         WHERE source IS NOT NULL AND step <= ",
+        )
+        .push_bind(MAX_PATH_STEPS)
+        .push(" GROUP BY step, source, target");
+    let rows: Vec<TransitionRow> = query.build_query_as().fetch_all(pool).await?;
+
+    let mut counts: HashMap<(i64, String, String), i64> = HashMap::new();
+    for row in rows.into_iter().filter(|row| {
+        url_pattern
+            .is_none_or(|pattern| pattern.is_match(&row.source) || pattern.is_match(&row.target))
+    }) {
+        let key = (
+            row.step,
+            normalize_location(&row.source),
+            normalize_location(&row.target),
+        );
+        *counts.entry(key).or_default() += row.count;
+    }
+    let mut transitions: Vec<PageTransition> = counts
+        .into_iter()
+        .map(|((step, source, target), count)| PageTransition {
+            step,
+            source,
+            target,
+            count,
+        })
+        .collect();
+    transitions.sort_by(|a, b| {
+        a.step
+            .cmp(&b.step)
+            .then_with(|| b.count.cmp(&a.count))
+            .then_with(|| (&a.source, &a.target).cmp(&(&b.source, &b.target)))
+    });
+    let mut kept = 0;
+    let mut step = 0;
+    transitions.retain(|transition| {
+        if transition.step != step {
+            step = transition.step;
+            kept = 0;
+        }
+        kept += 1;
+        kept <= PATH_TRANSITIONS_PER_STEP
+    });
+    Ok(transitions)
+}
+
+/// Referrers panel
+#[allow(clippy::too_many_arguments)]
+pub async fn get_top_referrers(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    referrer_filter: Option<&ReferrerFilter>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+) -> Result<Vec<CountedItem>> {
+    if url_pattern.is_none() && segment.is_none() {
+        return get_counted_referrers(pool, service_id, start, end, environment, referrer_filter)
+            .await;
+    }
+    Ok(get_filtered_relative_stats(
+        pool,
+        service_id,
+        start,
         end,
-        RESULTS_LIMIT,
+        environment,
+        // Only the panel's own counts are used, none of which depend on the
+        // current time
+        end,
+        referrer_filter,
+        url_pattern,
+        None,
+        segment,
+        0,
+        Tz::UTC,
+        WeekStart::Monday,
+    )
+    .await?
+    .referrers)
+}
+
+/// Countries panel
+pub async fn get_top_countries(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+) -> Result<Vec<CountedItem>> {
+    if url_pattern.is_none() && segment.is_none() {
+        return get_counted_field(
+            pool,
+            "sessions",
+            "country",
+            service_id,
+            start,
+            end,
+            environment,
+            RESULTS_LIMIT,
+        )
+        .await;
+    }
+    Ok(get_filtered_relative_stats(
+        pool,
+        service_id,
+        start,
+        end,
+        environment,
+        // Only the panel's own counts are used, none of which depend on the
+        // current time
+        end,
+        None,
+        url_pattern,
+        None,
+        segment,
+        0,
+        Tz::UTC,
+        WeekStart::Monday,
+    )
+    .await?
+    .countries)
+}
+
+/// Chart panel: (data, tooltip format, granularity)
+#[allow(clippy::too_many_arguments)]
+pub async fn get_chart(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    now: DateTime<Utc>,
+    url_pattern: Option<&Regex>,
+    segment: Option<&Segment>,
+    tz: Tz,
+    week_start: WeekStart,
+) -> Result<(ChartData, String, String)> {
+    if url_pattern.is_none() && segment.is_none() {
+        return get_chart_data(
+            pool,
+            service_id,
+            start,
+            end,
+            environment,
+            now,
+            tz,
+            week_start,
+        )
+        .await;
+    }
+    let stats = get_filtered_relative_stats(
+        pool,
+        service_id,
+        start,
+        end,
+        environment,
+        now,
+        None,
+        url_pattern,
+        None,
+        segment,
+        0,
+        tz,
+        week_start,
+    )
+    .await?;
+    Ok((
+        stats.chart_data,
+        stats.chart_tooltip_format,
+        stats.chart_granularity,
+    ))
+}
+
+/// SQL numbering the `histogram` bucket the value of `expr` falls in
+fn bucket_case(expr: &str, histogram: SessionHistogram) -> String {
+    let bounds = histogram.bounds();
+    let whens: String = bounds
+        .iter()
+        .enumerate()
+        .map(|(index, bound)| format!(" WHEN {expr} < {bound} THEN {index}"))
+        .collect();
+    format!("CAST(CASE{whens} ELSE {} END AS BIGINT)", bounds.len())
+}
+
+/// Session duration and pages-per-session histograms of the sessions that
+/// started in a range
+async fn get_session_histograms(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+) -> Result<(Vec<HistogramBucket>, Vec<HistogramBucket>)> {
+    let env = environment_filter(environment, "environment");
+    let depth_env = environment_filter(environment, "s.environment");
+    let flagged = flagged_filter("h");
+    #[cfg(feature = "postgres")]
+    let (duration_rows, depth_rows): (Vec<BucketRow>, Vec<BucketRow>) = {
+        let duration_query = format!(
+            "SELECT {} AS bucket, COUNT(*) AS count FROM sessions
+             WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}
+             GROUP BY bucket",
+            bucket_case(
+                "EXTRACT(EPOCH FROM (COALESCE(ended_at, last_seen) - start_time))",
+                SessionHistogram::Duration,
+            )
+        );
+        let depth_query = format!(
+            "SELECT {} AS bucket, COUNT(*) AS count FROM (
+                 SELECT COUNT(*) AS depth FROM sessions s JOIN hits h ON h.session_id = s.id
+                 WHERE s.service_id = $1 AND s.start_time >= $2 AND s.start_time < $3 {depth_env} {flagged}
+                 GROUP BY s.id
+             ) depths
+             GROUP BY bucket",
+            bucket_case("depth", SessionHistogram::Depth)
+        );
+        let durations = sqlx::query_as(&duration_query)
+            .bind(service_id.0)
+            .bind(start)
+            .bind(end)
+            .fetch_all(pool)
+            .await?;
+        let depths = sqlx::query_as(&depth_query)
+            .bind(service_id.0)
+            .bind(start)
+            .bind(end)
+            .fetch_all(pool)
+            .await?;
+        (durations, depths)
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let (duration_rows, depth_rows): (Vec<BucketRow>, Vec<BucketRow>) = {
+        let duration_query = format!(
+            "SELECT {} AS bucket, COUNT(*) AS count FROM sessions
+             WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}
+             GROUP BY bucket",
+            bucket_case(
+                "ROUND((julianday(COALESCE(ended_at, last_seen)) - julianday(start_time)) * 86400)",
+                SessionHistogram::Duration,
+            )
+        );
+        let depth_query = format!(
+            "SELECT {} AS bucket, COUNT(*) AS count FROM (
+                 SELECT COUNT(*) AS depth FROM sessions s JOIN hits h ON h.session_id = s.id
+                 WHERE s.service_id = ? AND s.start_time >= ? AND s.start_time < ? {depth_env} {flagged}
+                 GROUP BY s.id
+             ) depths
+             GROUP BY bucket",
+            bucket_case("depth", SessionHistogram::Depth)
+        );
+        let durations = sqlx::query_as(&duration_query)
+            .bind(service_id.0.to_string())
+            .bind(start.to_rfc3339())
+            .bind(end.to_rfc3339())
+            .fetch_all(pool)
+            .await?;
+        let depths = sqlx::query_as(&depth_query)
+            .bind(service_id.0.to_string())
+            .bind(start.to_rfc3339())
+            .bind(end.to_rfc3339())
+            .fetch_all(pool)
+            .await?;
+        (durations, depths)
+    };
+
+    Ok((
+        SessionHistogram::Duration.buckets(duration_rows.into_iter().map(BucketRow::into_pair)),
+        SessionHistogram::Depth.buckets(depth_rows.into_iter().map(BucketRow::into_pair)),
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_relative_stats(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    now: DateTime<Utc>,
+    referrer_filter: Option<&ReferrerFilter>,
+    url_pattern: Option<&Regex>,
+    prop: Option<&SessionPropFilter>,
+    segment: Option<&Segment>,
+    active_user_timeout_ms: u64,
+    tz: Tz,
+    week_start: WeekStart,
+    panels: bool,
+) -> Result<CoreStats> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    // URL patterns, session properties and segments are applied hit by hit
+    if url_pattern.is_some() || prop.is_some() || segment.is_some() {
+        return get_filtered_relative_stats(
+            pool,
+            service_id,
+            start,
+            end,
+            environment,
+            now,
+            referrer_filter,
+            url_pattern,
+            prop,
+            segment,
+            active_user_timeout_ms,
+            tz,
+            week_start,
+        )
+        .await;
+    }
+
+    let active_cutoff = now - Duration::milliseconds(active_user_timeout_ms as i64);
+
+    // Currently online count
+    #[cfg(feature = "postgres")]
+    let currently_online: i64 = sqlx::query_scalar(
+        &format!("SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND last_seen > $2 AND ended_at IS NULL {env}"),
+    )
+    .bind(service_id.0)
+    .bind(active_cutoff)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let currently_online: i64 = {
+        let count: i32 = sqlx::query_scalar(
+            &format!("SELECT COUNT(*) FROM sessions WHERE service_id = ? AND last_seen > ? AND ended_at IS NULL {env}"),
+        )
+        .bind(service_id.0.to_string())
+        .bind(active_cutoff.to_rfc3339())
+        .fetch_one(pool)
+        .await?;
+        count as i64
+    };
+
+    // Session count
+    #[cfg(feature = "postgres")]
+    let session_count: i64 = sqlx::query_scalar(
+        &format!("SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}")
+    )
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_one(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let session_count: i64 = {
+        let count: i32 = sqlx::query_scalar(
+            &format!("SELECT COUNT(*) FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}")
+        )
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_one(pool)
+        .await?;
+        count as i64
+    };
+
+    // Hit count
+    #[cfg(feature = "postgres")]
+    let hit_count: i64 = sqlx::query_scalar(
+        &format!("SELECT COUNT(*) FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged}"),
     )
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_one(pool)
     .await?;
 
-    // Operating systems
-    let operating_systems = get_counted_field(
-        pool,
-        "sessions",
-        "os",
-        service_id,
-        start,
-        end,
-        RESULTS_LIMIT,
-    )
-    .await?;
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let hit_count: i64 = {
+        let count: i32 = sqlx::query_scalar(
+            &format!("SELECT COUNT(*) FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged}"),
+        )
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_one(pool)
+        .await?;
+        count as i64
+    };
+
+    // Has any hits ever
+    #[cfg(feature = "postgres")]
+    let has_hits: bool = {
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE service_id = $1 LIMIT 1")
+                .bind(service_id.0)
+                .fetch_one(pool)
+                .await?;
+        count > 0
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let has_hits: bool = {
+        let count: i32 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM hits WHERE service_id = ? LIMIT 1")
+                .bind(service_id.0.to_string())
+                .fetch_one(pool)
+                .await?;
+        count > 0
+    };
 
-    // Browsers
-    let browsers = get_counted_field(
-        pool,
-        "sessions",
-        "browser",
-        service_id,
-        start,
-        end,
-        RESULTS_LIMIT,
+    // Bounce count
+    #[cfg(feature = "postgres")]
+    let bounce_count: i64 = sqlx::query_scalar(
+        &format!("SELECT COUNT(*) FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} AND is_bounce = true")
     )
+    .bind(service_id.0)
+    .bind(start)
+    .bind(end)
+    .fetch_one(pool)
     .await?;
 
-    // Devices
-    let devices = get_counted_field(
-        pool,
-        "sessions",
-        "device",
-        service_id,
-        start,
-        end,
-        RESULTS_LIMIT,
-    )
-    .await?;
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let bounce_count: i64 = {
+        let count: i32 = sqlx::query_scalar(
+            &format!("SELECT COUNT(*) FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} AND is_bounce = 1")
+        )
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_one(pool)
+        .await?;
+        count as i64
+    };
 
-    // Device types
-    let device_types = get_counted_field(
-        pool,
-        "sessions",
-        "device_type",
-        service_id,
-        start,
-        end,
-        RESULTS_LIMIT,
+    let bounce_rate_pct = if session_count > 0 {
+        Some(((bounce_count as f64 / session_count as f64) * 1000.0).round() / 10.0)
+    } else {
+        None
+    };
+
+    // Average load time
+    #[cfg(feature = "postgres")]
+    let avg_load_time: Option<f64> = {
+        let raw: Option<f64> = sqlx::query_scalar(
+            &format!("SELECT AVG(load_time) FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged} AND load_time IS NOT NULL")
+        )
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .fetch_one(pool)
+        .await?;
+        raw.map(|v| v.round())
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let avg_load_time: Option<f64> = sqlx::query_scalar(
+        &format!("SELECT AVG(load_time) FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged} AND load_time IS NOT NULL")
     )
+    .bind(service_id.0.to_string())
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .fetch_one(pool)
     .await?;
 
-    // Chart data
-    let (chart_data, chart_tooltip_format, chart_granularity) =
-        get_chart_data(pool, service_id, start, end, now, tz).await?;
+    // Round avg_load_time to integer
+    let avg_load_time = avg_load_time.map(|v| v.round());
+
+    let avg_hits_per_session = if session_count > 0 {
+        Some(((hit_count as f64 / session_count as f64) * 10.0).round() / 10.0)
+    } else {
+        None
+    };
+
+    // Average session duration (in seconds), up to the end signal if the
+    // tracker sent one
+    #[cfg(feature = "postgres")]
+    let avg_session_duration: Option<f64> = {
+        let raw: Option<f64> = sqlx::query_scalar(&format!(
+            r#"SELECT AVG(EXTRACT(EPOCH FROM (COALESCE(ended_at, last_seen) - start_time)))
+               FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}"#
+        ))
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .fetch_one(pool)
+        .await?;
+        raw.map(|v| v.round())
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let avg_session_duration: Option<f64> = {
+        // SQLite doesn't have easy date arithmetic, compute manually
+        let durations: Vec<(String, String)> = sqlx::query_as(
+            &format!("SELECT start_time, COALESCE(ended_at, last_seen) FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}")
+        )
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_all(pool)
+        .await?;
+
+        if durations.is_empty() {
+            None
+        } else {
+            let total: f64 = durations
+                .iter()
+                .filter_map(|(s, e)| {
+                    let start_dt = DateTime::parse_from_rfc3339(s).ok()?.with_timezone(&Utc);
+                    let end_dt = DateTime::parse_from_rfc3339(e).ok()?.with_timezone(&Utc);
+                    Some((end_dt - start_dt).num_seconds() as f64)
+                })
+                .sum();
+            Some((total / durations.len() as f64).round())
+        }
+    };
+
+    // The breakdowns don't depend on each other, so they are queried at once
+    let counted_field = |field| {
+        get_counted_field(
+            pool,
+            "sessions",
+            field,
+            service_id,
+            start,
+            end,
+            environment,
+            RESULTS_LIMIT,
+        )
+    };
+    // Panel data is skipped when the caller loads the panels separately
+    let panel_data = async {
+        if !panels {
+            return Ok(Default::default());
+        }
+        // Locations (top pages) - normalized to strip query params
+        tokio::try_join!(
+            get_counted_locations(pool, service_id, start, end, environment, RESULTS_LIMIT),
+            get_counted_referrers(pool, service_id, start, end, environment, referrer_filter,),
+            counted_field("country"),
+        )
+    };
+    let chart = async {
+        if panels {
+            get_chart_data(
+                pool,
+                service_id,
+                start,
+                end,
+                environment,
+                now,
+                tz,
+                week_start,
+            )
+            .await
+        } else {
+            Ok(Default::default())
+        }
+    };
+    let (
+        (session_durations, session_depths),
+        (locations, referrers, countries),
+        operating_systems,
+        browsers,
+        devices,
+        device_types,
+        (chart_data, chart_tooltip_format, chart_granularity),
+        visitors,
+    ) = tokio::try_join!(
+        get_session_histograms(pool, service_id, start, end, environment),
+        panel_data,
+        counted_field("os"),
+        counted_field("browser"),
+        counted_field("device"),
+        counted_field("device_type"),
+        chart,
+        // Whole UTC days: sketches can't be split
+        get_merged_visitor_sketch(
+            pool,
+            service_id,
+            start.date_naive(),
+            (end - Duration::nanoseconds(1)).date_naive(),
+            environment,
+        ),
+    )?;
 
     Ok(CoreStats {
         currently_online,
@@ -1230,6 +6613,8 @@ async fn get_relative_stats(
         avg_session_duration,
         avg_load_time,
         avg_hits_per_session,
+        session_durations,
+        session_depths,
         locations,
         referrers,
         countries,
@@ -1237,28 +6622,47 @@ async fn get_relative_stats(
         browsers,
         devices,
         device_types,
+        continents: Vec::new(),
         chart_data,
         chart_tooltip_format,
         chart_granularity,
+        unique_visitors: visitors.map(|sketch| sketch.estimate()),
+        extra: BTreeMap::new(),
         compare: None,
+        compared_period: None,
     })
 }
 
 #[allow(clippy::type_complexity, clippy::too_many_arguments)]
-async fn get_relative_stats_with_url_filter(
+async fn get_filtered_relative_stats(
     pool: &Pool,
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
-    hide_referrer_regex: Option<&Regex>,
-    url_pattern: &Regex,
+    environment: Option<Environment>,
+    now: DateTime<Utc>,
+    referrer_filter: Option<&ReferrerFilter>,
+    url_pattern: Option<&Regex>,
+    prop: Option<&SessionPropFilter>,
+    segment: Option<&Segment>,
     active_user_timeout_ms: u64,
     tz: Tz,
+    week_start: WeekStart,
 ) -> Result<CoreStats> {
-    let now = Utc::now();
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
     let active_cutoff = now - Duration::milliseconds(active_user_timeout_ms as i64);
 
     // Get all hits in the date range
+    let mut query = QueryBuilder::new(
+        "SELECT id, session_id, location, load_time, initial, referrer, start_time
+         FROM hits
+         WHERE ",
+    );
+    push_service_range(&mut query, "", service_id, start, end);
+    query.push(format!(" {env} {flagged}"));
+    push_segment_filter(&mut query, segment, "session_id");
+
     #[cfg(feature = "postgres")]
     let all_hits: Vec<(
         i64,
@@ -1268,31 +6672,70 @@ async fn get_relative_stats_with_url_filter(
         bool,
         String,
         DateTime<Utc>,
-    )> = sqlx::query_as(
-        r#"SELECT id, session_id, location, load_time, initial, referrer, start_time
-           FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3"#,
-    )
-    .bind(service_id.0)
-    .bind(start)
-    .bind(end)
-    .fetch_all(pool)
-    .await?;
+    )> = query.build_query_as().fetch_all(pool).await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let all_hits: Vec<(i64, String, String, Option<f64>, bool, String, String)> = sqlx::query_as(
-        r#"SELECT id, session_id, location, load_time, initial, referrer, start_time
-           FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ?"#,
-    )
-    .bind(service_id.0.to_string())
-    .bind(start.to_rfc3339())
-    .bind(end.to_rfc3339())
-    .fetch_all(pool)
-    .await?;
+    let all_hits: Vec<(i64, String, String, Option<f64>, bool, String, String)> =
+        query.build_query_as().fetch_all(pool).await?;
 
-    // Filter hits by URL pattern
+    // Sessions in the range with the property asked for
+    #[cfg(feature = "postgres")]
+    let prop_sessions: Option<std::collections::HashSet<uuid::Uuid>> = match prop {
+        Some(filter) => {
+            let rows: Vec<(uuid::Uuid, String)> = sqlx::query_as(&format!(
+                r#"SELECT id, props FROM sessions
+                   WHERE service_id = $1 AND start_time < $2 AND last_seen >= $3
+                     AND props <> '{{}}' {env}"#
+            ))
+            .bind(service_id.0)
+            .bind(end)
+            .bind(start)
+            .fetch_all(pool)
+            .await?;
+            Some(
+                rows.into_iter()
+                    .filter(|(_, props)| filter.matches(&parse_session_props(props)))
+                    .map(|(id, _)| id)
+                    .collect(),
+            )
+        }
+        None => None,
+    };
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let prop_sessions: Option<std::collections::HashSet<String>> = match prop {
+        Some(filter) => {
+            let rows: Vec<(String, String)> = sqlx::query_as(&format!(
+                r#"SELECT id, props FROM sessions
+                   WHERE service_id = ? AND start_time < ? AND last_seen >= ?
+                     AND props <> '{{}}' {env}"#
+            ))
+            .bind(service_id.0.to_string())
+            .bind(end.to_rfc3339())
+            .bind(start.to_rfc3339())
+            .fetch_all(pool)
+            .await?;
+            Some(
+                rows.into_iter()
+                    .filter(|(_, props)| filter.matches(&parse_session_props(props)))
+                    .map(|(id, _)| id)
+                    .collect(),
+            )
+        }
+        None => None,
+    };
+
+    // Filter hits by URL pattern and session property
     let filtered_hits: Vec<_> = all_hits
         .into_iter()
-        .filter(|(_, _, location, _, _, _, _)| url_pattern.is_match(location))
+        .filter(|(_, _, location, _, _, _, _)| {
+            url_pattern.is_none_or(|pattern| pattern.is_match(location))
+        })
+        .filter(|(_, session_id, _, _, _, _, _)| {
+            prop_sessions
+                .as_ref()
+                .is_none_or(|sessions| sessions.contains(session_id))
+        })
         .collect();
 
     let hit_count = filtered_hits.len() as i64;
@@ -1360,9 +6803,9 @@ async fn get_relative_stats_with_url_filter(
     }
     let mut locations: Vec<CountedItem> = location_counts
         .into_iter()
-        .map(|(value, count)| CountedItem { value, count })
+        .map(|(value, count)| CountedItem::new(value, count))
         .collect();
-    locations.sort_by(|a, b| b.count.cmp(&a.count));
+    locations.sort_by_key(|item| std::cmp::Reverse(item.count));
     locations.truncate(RESULTS_LIMIT as usize);
 
     // Count referrers from filtered initial hits
@@ -1374,12 +6817,12 @@ async fn get_relative_stats_with_url_filter(
     }
     let mut referrers: Vec<CountedItem> = referrer_counts
         .into_iter()
-        .map(|(value, count)| CountedItem { value, count })
+        .map(|(value, count)| CountedItem::new(value, count))
         .collect();
-    if let Some(regex) = hide_referrer_regex {
-        referrers.retain(|r| !regex.is_match(&r.value));
+    if let Some(filter) = referrer_filter {
+        referrers.retain(|r| !filter.hides(&r.value));
     }
-    referrers.sort_by(|a, b| b.count.cmp(&a.count));
+    referrers.sort_by_key(|item| std::cmp::Reverse(item.count));
     referrers.truncate(RESULTS_LIMIT as usize);
 
     // Get session data for matching sessions to compute other stats
@@ -1403,8 +6846,10 @@ async fn get_relative_stats_with_url_filter(
             bool,
             DateTime<Utc>,
             DateTime<Utc>,
+            Option<DateTime<Utc>>,
         )> = sqlx::query_as(
-            r#"SELECT country, os, browser, device, device_type, is_bounce, start_time, last_seen
+            r#"SELECT country, os, browser, device, device_type, is_bounce, start_time, last_seen,
+               ended_at
                FROM sessions WHERE id = $1"#,
         )
         .bind(session_id)
@@ -1421,8 +6866,10 @@ async fn get_relative_stats_with_url_filter(
             bool,
             String,
             String,
+            Option<String>,
         )> = sqlx::query_as(
-            r#"SELECT country, os, browser, device, device_type, is_bounce, start_time, last_seen
+            r#"SELECT country, os, browser, device, device_type, is_bounce, start_time, last_seen,
+               ended_at
                FROM sessions WHERE id = ?"#,
         )
         .bind(session_id)
@@ -1438,6 +6885,7 @@ async fn get_relative_stats_with_url_filter(
             is_bounce,
             session_start,
             last_seen,
+            ended_at,
         )) = session
         {
             *countries.entry(country).or_insert(0) += 1;
@@ -1451,9 +6899,9 @@ async fn get_relative_stats_with_url_filter(
 
             #[cfg(feature = "postgres")]
             {
-                let duration = (last_seen - session_start).num_seconds() as f64;
+                let duration = (ended_at.unwrap_or(last_seen) - session_start).num_seconds() as f64;
                 session_durations.push(duration);
-                if last_seen > active_cutoff {
+                if last_seen > active_cutoff && ended_at.is_none() {
                     currently_online += 1;
                 }
             }
@@ -1462,12 +6910,12 @@ async fn get_relative_stats_with_url_filter(
             {
                 if let (Ok(start_dt), Ok(end_dt)) = (
                     DateTime::parse_from_rfc3339(&session_start),
-                    DateTime::parse_from_rfc3339(&last_seen),
+                    DateTime::parse_from_rfc3339(ended_at.as_deref().unwrap_or(&last_seen)),
                 ) {
                     let duration = (end_dt.with_timezone(&Utc) - start_dt.with_timezone(&Utc))
                         .num_seconds() as f64;
                     session_durations.push(duration);
-                    if end_dt.with_timezone(&Utc) > active_cutoff {
+                    if ended_at.is_none() && end_dt.with_timezone(&Utc) > active_cutoff {
                         currently_online += 1;
                     }
                 }
@@ -1487,13 +6935,29 @@ async fn get_relative_stats_with_url_filter(
         Some((session_durations.iter().sum::<f64>() / session_durations.len() as f64).round())
     };
 
+    // Histograms over the matching sessions, counting only matching hits
+    let mut session_depths: HashMap<_, i64> = HashMap::new();
+    for (_, session_id, _, _, _, _, _) in &filtered_hits {
+        *session_depths.entry(session_id).or_insert(0) += 1;
+    }
+    let session_depths = SessionHistogram::Depth.buckets(
+        session_depths
+            .into_values()
+            .map(|depth| (SessionHistogram::Depth.bucket(depth), 1)),
+    );
+    let session_durations = SessionHistogram::Duration.buckets(
+        session_durations
+            .iter()
+            .map(|&duration| (SessionHistogram::Duration.bucket(duration as i64), 1)),
+    );
+
     // Convert hashmaps to sorted vectors
     fn to_counted_items(map: HashMap<String, i64>, limit: i64) -> Vec<CountedItem> {
         let mut items: Vec<_> = map
             .into_iter()
-            .map(|(value, count)| CountedItem { value, count })
+            .map(|(value, count)| CountedItem::new(value, count))
             .collect();
-        items.sort_by(|a, b| b.count.cmp(&a.count));
+        items.sort_by_key(|item| std::cmp::Reverse(item.count));
         items.truncate(limit as usize);
         items
     }
@@ -1522,7 +6986,7 @@ async fn get_relative_stats_with_url_filter(
         .collect();
 
     let (chart_data, chart_tooltip_format, chart_granularity) =
-        get_chart_data_filtered_sync(start, end, now, &hit_times, session_count, tz);
+        get_chart_data_filtered_sync(start, end, now, &hit_times, session_count, tz, week_start);
 
     Ok(CoreStats {
         currently_online,
@@ -1533,6 +6997,8 @@ async fn get_relative_stats_with_url_filter(
         avg_session_duration,
         avg_load_time,
         avg_hits_per_session,
+        session_durations,
+        session_depths,
         locations,
         referrers,
         countries,
@@ -1540,13 +7006,19 @@ async fn get_relative_stats_with_url_filter(
         browsers,
         devices,
         device_types,
+        continents: Vec::new(),
         chart_data,
         chart_tooltip_format,
         chart_granularity,
+        // Sketches count a day's visitors on every page together
+        unique_visitors: None,
+        extra: BTreeMap::new(),
         compare: None,
+        compared_period: None,
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn get_counted_field(
     pool: &Pool,
     table: &str,
@@ -1554,13 +7026,15 @@ async fn get_counted_field(
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
+    environment: Option<Environment>,
     limit: i64,
 ) -> Result<Vec<CountedItem>> {
+    let env = environment_filter(environment, "environment");
     #[cfg(feature = "postgres")]
     let rows: Vec<CountedRow> = {
         let query = format!(
             "SELECT {field} as value, COUNT(*) as count FROM {table}
-             WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
+             WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}
              GROUP BY {field} ORDER BY count DESC LIMIT $4"
         );
         sqlx::query_as(&query)
@@ -1576,7 +7050,7 @@ async fn get_counted_field(
     let rows: Vec<CountedRow> = {
         let query = format!(
             "SELECT {field} as value, COUNT(*) as count FROM {table}
-             WHERE service_id = ? AND start_time >= ? AND start_time < ?
+             WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}
              GROUP BY {field} ORDER BY count DESC LIMIT ?"
         );
         sqlx::query_as(&query)
@@ -1598,15 +7072,86 @@ async fn get_counted_locations(
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
+    environment: Option<Environment>,
     limit: i64,
 ) -> Result<Vec<CountedItem>> {
+    // Whole UTC days come from the top pages table when the background job
+    // has materialized every one of them; the ends of the range (such as
+    // today) are counted live. Picked date ranges end on the last second of
+    // their final day, which is taken as the whole day.
+    let first_day = if start.time() == NaiveTime::MIN {
+        start.date_naive()
+    } else {
+        start.date_naive() + Duration::days(1)
+    };
+    let last_day = (end + Duration::seconds(1)).date_naive();
+    let materialized = if first_day < last_day {
+        get_materialized_locations(pool, service_id, first_day, last_day, environment).await?
+    } else {
+        None
+    };
+
+    let location_counts = match materialized {
+        Some(mut counts) => {
+            count_live_locations(
+                pool,
+                service_id,
+                start,
+                day_start(first_day),
+                environment,
+                &mut counts,
+            )
+            .await?;
+            count_live_locations(
+                pool,
+                service_id,
+                day_start(last_day),
+                end,
+                environment,
+                &mut counts,
+            )
+            .await?;
+            counts
+        }
+        None => {
+            let mut counts = HashMap::new();
+            count_live_locations(pool, service_id, start, end, environment, &mut counts).await?;
+            counts
+        }
+    };
+
+    // Convert to sorted vector
+    let mut items: Vec<CountedItem> = location_counts
+        .into_iter()
+        .map(|(value, count)| CountedItem::new(value, count))
+        .collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.count));
+    items.truncate(limit as usize);
+
+    Ok(items)
+}
+
+/// Add the hits per normalized location between `start` and `end` to `counts`
+async fn count_live_locations(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    counts: &mut HashMap<String, i64>,
+) -> Result<()> {
+    if start >= end {
+        return Ok(());
+    }
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
     // Fetch all location values with their counts
     #[cfg(feature = "postgres")]
-    let rows: Vec<CountedRow> = sqlx::query_as(
+    let rows: Vec<CountedRow> = sqlx::query_as(&format!(
         "SELECT location as value, COUNT(*) as count FROM hits
-         WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
-         GROUP BY location",
-    )
+         WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged}
+         GROUP BY location"
+    ))
     .bind(service_id.0)
     .bind(start)
     .bind(end)
@@ -1614,11 +7159,11 @@ async fn get_counted_locations(
     .await?;
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-    let rows: Vec<CountedRow> = sqlx::query_as(
+    let rows: Vec<CountedRow> = sqlx::query_as(&format!(
         "SELECT location as value, COUNT(*) as count FROM hits
-         WHERE service_id = ? AND start_time >= ? AND start_time < ?
-         GROUP BY location",
-    )
+         WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged}
+         GROUP BY location"
+    ))
     .bind(service_id.0.to_string())
     .bind(start.to_rfc3339())
     .bind(end.to_rfc3339())
@@ -1626,23 +7171,85 @@ async fn get_counted_locations(
     .await?;
 
     // Normalize locations (strip query params) and re-aggregate
-    let mut location_counts: HashMap<String, i64> = HashMap::new();
     for row in rows {
         let normalized = normalize_location(&row.value.unwrap_or_default());
-        *location_counts.entry(normalized).or_insert(0) += row.count;
+        *counts.entry(normalized).or_insert(0) += row.count;
     }
+    Ok(())
+}
 
-    // Convert to sorted vector
-    let mut items: Vec<CountedItem> = location_counts
-        .into_iter()
-        .map(|(value, count)| CountedItem { value, count })
-        .collect();
-    items.sort_by(|a, b| b.count.cmp(&a.count));
-    items.truncate(limit as usize);
+/// Hits per location over the UTC days from `first` up to (not including)
+/// `last` from the top pages table, or `None` unless all of them are
+/// materialized
+async fn get_materialized_locations(
+    pool: &Pool,
+    service_id: ServiceId,
+    first: NaiveDate,
+    last: NaiveDate,
+    environment: Option<Environment>,
+) -> Result<Option<HashMap<String, i64>>> {
+    let days = list_materialized_days(pool, service_id, first, last).await?;
+    if (days.len() as i64) < (last - first).num_days() {
+        return Ok(None);
+    }
+    let env = environment_filter(environment, "environment");
+
+    #[cfg(feature = "postgres")]
+    let rows: Vec<(String, i64)> = sqlx::query_as(&format!(
+        "SELECT location, SUM(hits)::BIGINT FROM top_locations_daily
+         WHERE service_id = $1 AND day >= $2 AND day < $3 {env}
+         GROUP BY location"
+    ))
+    .bind(service_id.0)
+    .bind(first)
+    .bind(last)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    let rows: Vec<(String, i64)> = sqlx::query_as(&format!(
+        "SELECT location, SUM(hits) FROM top_locations_daily
+         WHERE service_id = ? AND day >= ? AND day < ? {env}
+         GROUP BY location"
+    ))
+    .bind(service_id.0.to_string())
+    .bind(first.to_string())
+    .bind(last.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(rows.into_iter().collect()))
+}
+
+/// Referrers of initial hits, minus those the service's filter hides
+async fn get_counted_referrers(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    referrer_filter: Option<&ReferrerFilter>,
+) -> Result<Vec<CountedItem>> {
+    let mut referrers = get_counted_field_initial(
+        pool,
+        "hits",
+        "referrer",
+        service_id,
+        start,
+        end,
+        environment,
+        RESULTS_LIMIT,
+    )
+    .await?;
 
-    Ok(items)
+    if let Some(filter) = referrer_filter {
+        referrers.retain(|r| !filter.hides(&r.value));
+    }
+
+    Ok(referrers)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn get_counted_field_initial(
     pool: &Pool,
     table: &str,
@@ -1650,13 +7257,16 @@ async fn get_counted_field_initial(
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
+    environment: Option<Environment>,
     limit: i64,
 ) -> Result<Vec<CountedItem>> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter(table);
     #[cfg(feature = "postgres")]
     let rows: Vec<CountedRow> = {
         let query = format!(
             "SELECT {field} as value, COUNT(*) as count FROM {table}
-             WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 AND initial = true
+             WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged} AND initial = true
              GROUP BY {field} ORDER BY count DESC LIMIT $4"
         );
         sqlx::query_as(&query)
@@ -1672,7 +7282,7 @@ async fn get_counted_field_initial(
     let rows: Vec<CountedRow> = {
         let query = format!(
             "SELECT {field} as value, COUNT(*) as count FROM {table}
-             WHERE service_id = ? AND start_time >= ? AND start_time < ? AND initial = 1
+             WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged} AND initial = 1
              GROUP BY {field} ORDER BY count DESC LIMIT ?"
         );
         sqlx::query_as(&query)
@@ -1687,21 +7297,27 @@ async fn get_counted_field_initial(
     Ok(rows.into_iter().map(Into::into).collect())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn get_chart_data(
     pool: &Pool,
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
+    environment: Option<Environment>,
     now: DateTime<Utc>,
     tz: Tz,
+    week_start: WeekStart,
 ) -> Result<(ChartData, String, String)> {
-    let duration = end - start;
-    let use_hourly = duration.num_days() < 3;
-
-    if use_hourly {
-        get_hourly_chart_data(pool, service_id, start, end, now, tz).await
-    } else {
-        get_daily_chart_data(pool, service_id, start, end, now, tz).await
+    match ChartGranularity::for_range(end - start) {
+        ChartGranularity::Hourly => {
+            get_hourly_chart_data(pool, service_id, start, end, environment, now, tz).await
+        }
+        ChartGranularity::Daily => {
+            get_daily_chart_data(pool, service_id, start, end, environment, now, tz).await
+        }
+        ChartGranularity::Weekly => {
+            get_weekly_chart_data(pool, service_id, start, end, environment, week_start).await
+        }
     }
 }
 
@@ -1710,20 +7326,23 @@ async fn get_hourly_chart_data(
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
+    environment: Option<Environment>,
     now: DateTime<Utc>,
     tz: Tz,
 ) -> Result<(ChartData, String, String)> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
     // Use a sortable UTC key for internal tracking, convert to user TZ for display
     let mut data: HashMap<String, (i64, i64)> = HashMap::new();
 
     // Sessions per hour
     #[cfg(feature = "postgres")]
     {
-        let rows: Vec<(DateTime<Utc>, i64)> = sqlx::query_as(
+        let rows: Vec<(DateTime<Utc>, i64)> = sqlx::query_as(&format!(
             "SELECT date_trunc('hour', start_time) as hour, COUNT(*) as count
-             FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
-             GROUP BY hour ORDER BY hour",
-        )
+             FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}
+             GROUP BY hour ORDER BY hour"
+        ))
         .bind(service_id.0)
         .bind(start)
         .bind(end)
@@ -1737,11 +7356,11 @@ async fn get_hourly_chart_data(
         }
 
         // Hits per hour
-        let rows: Vec<(DateTime<Utc>, i64)> = sqlx::query_as(
+        let rows: Vec<(DateTime<Utc>, i64)> = sqlx::query_as(&format!(
             "SELECT date_trunc('hour', start_time) as hour, COUNT(*) as count
-             FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
-             GROUP BY hour ORDER BY hour",
-        )
+             FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged}
+             GROUP BY hour ORDER BY hour"
+        ))
         .bind(service_id.0)
         .bind(start)
         .bind(end)
@@ -1757,11 +7376,11 @@ async fn get_hourly_chart_data(
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
     {
         // Use ISO 8601 format for hour keys: 2026-01-19T11:00:00Z
-        let rows: Vec<(String, i32)> = sqlx::query_as(
+        let rows: Vec<(String, i32)> = sqlx::query_as(&format!(
             "SELECT strftime('%Y-%m-%dT%H:00:00Z', start_time) as hour, COUNT(*) as count
-             FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ?
-             GROUP BY hour ORDER BY hour",
-        )
+             FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}
+             GROUP BY hour ORDER BY hour"
+        ))
         .bind(service_id.0.to_string())
         .bind(start.to_rfc3339())
         .bind(end.to_rfc3339())
@@ -1772,11 +7391,11 @@ async fn get_hourly_chart_data(
             data.entry(hour).or_insert((0, 0)).0 = count as i64;
         }
 
-        let rows: Vec<(String, i32)> = sqlx::query_as(
+        let rows: Vec<(String, i32)> = sqlx::query_as(&format!(
             "SELECT strftime('%Y-%m-%dT%H:00:00Z', start_time) as hour, COUNT(*) as count
-             FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ?
-             GROUP BY hour ORDER BY hour",
-        )
+             FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged}
+             GROUP BY hour ORDER BY hour"
+        ))
         .bind(service_id.0.to_string())
         .bind(start.to_rfc3339())
         .bind(end.to_rfc3339())
@@ -1842,18 +7461,21 @@ async fn get_daily_chart_data(
     service_id: ServiceId,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
+    environment: Option<Environment>,
     now: DateTime<Utc>,
     tz: Tz,
 ) -> Result<(ChartData, String, String)> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
     let mut data: HashMap<String, (i64, i64)> = HashMap::new();
 
     #[cfg(feature = "postgres")]
     {
-        let rows: Vec<(chrono::NaiveDate, i64)> = sqlx::query_as(
+        let rows: Vec<(chrono::NaiveDate, i64)> = sqlx::query_as(&format!(
             "SELECT date_trunc('day', start_time)::date as day, COUNT(*) as count
-             FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
-             GROUP BY day ORDER BY day",
-        )
+             FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}
+             GROUP BY day ORDER BY day"
+        ))
         .bind(service_id.0)
         .bind(start)
         .bind(end)
@@ -1865,11 +7487,11 @@ async fn get_daily_chart_data(
             data.entry(key).or_insert((0, 0)).0 = count;
         }
 
-        let rows: Vec<(chrono::NaiveDate, i64)> = sqlx::query_as(
+        let rows: Vec<(chrono::NaiveDate, i64)> = sqlx::query_as(&format!(
             "SELECT date_trunc('day', start_time)::date as day, COUNT(*) as count
-             FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3
-             GROUP BY day ORDER BY day",
-        )
+             FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged}
+             GROUP BY day ORDER BY day"
+        ))
         .bind(service_id.0)
         .bind(start)
         .bind(end)
@@ -1884,11 +7506,11 @@ async fn get_daily_chart_data(
 
     #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
     {
-        let rows: Vec<(String, i64)> = sqlx::query_as(
+        let rows: Vec<(String, i64)> = sqlx::query_as(&format!(
             "SELECT date(start_time) as day, COUNT(*) as count
-             FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ?
-             GROUP BY day ORDER BY day",
-        )
+             FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}
+             GROUP BY day ORDER BY day"
+        ))
         .bind(service_id.0.to_string())
         .bind(start.to_rfc3339())
         .bind(end.to_rfc3339())
@@ -1899,11 +7521,11 @@ async fn get_daily_chart_data(
             data.entry(day).or_insert((0, 0)).0 = count;
         }
 
-        let rows: Vec<(String, i64)> = sqlx::query_as(
+        let rows: Vec<(String, i64)> = sqlx::query_as(&format!(
             "SELECT date(start_time) as day, COUNT(*) as count
-             FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ?
-             GROUP BY day ORDER BY day",
-        )
+             FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged}
+             GROUP BY day ORDER BY day"
+        ))
         .bind(service_id.0.to_string())
         .bind(start.to_rfc3339())
         .bind(end.to_rfc3339())
@@ -1958,6 +7580,158 @@ async fn get_daily_chart_data(
     Ok((chart_data, "MMM d".to_string(), "daily".to_string()))
 }
 
+/// SQL for the first day of the (UTC) week `start_time` is in
+fn week_bucket(week_start: WeekStart) -> String {
+    #[cfg(feature = "postgres")]
+    {
+        // DOW counts from Sunday = 0
+        let offset = match week_start {
+            WeekStart::Monday => 6,
+            WeekStart::Sunday => 0,
+        };
+        format!(
+            "date_trunc('day', start_time)::date - ((EXTRACT(DOW FROM start_time)::int + {offset}) % 7)"
+        )
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        // Back a week, then forward to the next start day on or after it
+        let weekday = match week_start {
+            WeekStart::Monday => 1,
+            WeekStart::Sunday => 0,
+        };
+        format!("date(start_time, '-6 days', 'weekday {weekday}')")
+    }
+}
+
+async fn get_weekly_chart_data(
+    pool: &Pool,
+    service_id: ServiceId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    environment: Option<Environment>,
+    week_start: WeekStart,
+) -> Result<(ChartData, String, String)> {
+    let env = environment_filter(environment, "environment");
+    let flagged = flagged_filter("hits");
+    let week = week_bucket(week_start);
+    let mut data: HashMap<chrono::NaiveDate, (i64, i64)> = HashMap::new();
+
+    #[cfg(feature = "postgres")]
+    {
+        let rows: Vec<(chrono::NaiveDate, i64)> = sqlx::query_as(&format!(
+            "SELECT {week} as week, COUNT(*) as count
+             FROM sessions WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env}
+             GROUP BY week ORDER BY week"
+        ))
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+
+        for (week, count) in rows {
+            data.entry(week).or_insert((0, 0)).0 = count;
+        }
+
+        let rows: Vec<(chrono::NaiveDate, i64)> = sqlx::query_as(&format!(
+            "SELECT {week} as week, COUNT(*) as count
+             FROM hits WHERE service_id = $1 AND start_time >= $2 AND start_time < $3 {env} {flagged}
+             GROUP BY week ORDER BY week"
+        ))
+        .bind(service_id.0)
+        .bind(start)
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+
+        for (week, count) in rows {
+            data.entry(week).or_insert((0, 0)).1 = count;
+        }
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    {
+        let rows: Vec<(String, i64)> = sqlx::query_as(&format!(
+            "SELECT {week} as week, COUNT(*) as count
+             FROM sessions WHERE service_id = ? AND start_time >= ? AND start_time < ? {env}
+             GROUP BY week ORDER BY week"
+        ))
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_all(pool)
+        .await?;
+
+        for (week, count) in rows {
+            if let Ok(week) = chrono::NaiveDate::parse_from_str(&week, "%Y-%m-%d") {
+                data.entry(week).or_insert((0, 0)).0 = count;
+            }
+        }
+
+        let rows: Vec<(String, i64)> = sqlx::query_as(&format!(
+            "SELECT {week} as week, COUNT(*) as count
+             FROM hits WHERE service_id = ? AND start_time >= ? AND start_time < ? {env} {flagged}
+             GROUP BY week ORDER BY week"
+        ))
+        .bind(service_id.0.to_string())
+        .bind(start.to_rfc3339())
+        .bind(end.to_rfc3339())
+        .fetch_all(pool)
+        .await?;
+
+        for (week, count) in rows {
+            if let Ok(week) = chrono::NaiveDate::parse_from_str(&week, "%Y-%m-%d") {
+                data.entry(week).or_insert((0, 0)).1 = count;
+            }
+        }
+    }
+
+    Ok(weekly_chart(data, start, end, week_start))
+}
+
+/// Chart of per-week (sessions, hits) keyed by the weeks' first days, with
+/// the range's empty weeks filled in and labeled by week number and first day
+fn weekly_chart(
+    mut data: HashMap<chrono::NaiveDate, (i64, i64)>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    week_start: WeekStart,
+) -> (ChartData, String, String) {
+    let mut week = week_start.week_of(start.date_naive());
+    while week <= end.date_naive() {
+        data.entry(week).or_insert((0, 0));
+        week += Duration::days(7);
+    }
+
+    let mut sorted: Vec<_> = data.into_iter().collect();
+    sorted.sort_by_key(|(week, _)| *week);
+
+    // INVARIANT: Every data point MUST have a corresponding label
+    let chart_data = ChartData {
+        labels: sorted
+            .iter()
+            .map(|(week, _)| {
+                format!(
+                    "W{:02}, {}",
+                    week_start.week_number(*week),
+                    week.format("%b %d")
+                )
+            })
+            .collect(),
+        sessions: sorted.iter().map(|(_, (s, _))| *s).collect(),
+        hits: sorted.iter().map(|(_, (_, h))| *h).collect(),
+    };
+
+    let granularity = ChartGranularity::Weekly;
+    (
+        chart_data,
+        granularity.tooltip_format().to_string(),
+        granularity.as_str().to_string(),
+    )
+}
+
 fn get_chart_data_filtered_sync(
     start: DateTime<Utc>,
     end: DateTime<Utc>,
@@ -1965,13 +7739,27 @@ fn get_chart_data_filtered_sync(
     hit_times: &[DateTime<Utc>],
     session_count: i64,
     tz: Tz,
+    week_start: WeekStart,
 ) -> (ChartData, String, String) {
-    let duration = end - start;
-    let use_hourly = duration.num_days() < 3;
+    let granularity = ChartGranularity::for_range(end - start);
+    if granularity == ChartGranularity::Weekly {
+        let mut data: HashMap<chrono::NaiveDate, (i64, i64)> = HashMap::new();
+        for hit_time in hit_times {
+            let week = week_start.week_of(hit_time.date_naive());
+            data.entry(week).or_insert((0, 0)).1 += 1;
+        }
+
+        // Distribute sessions across weeks with data
+        let weeks_with_data = data.len().max(1) as i64;
+        for counts in data.values_mut() {
+            counts.0 = session_count / weeks_with_data;
+        }
+        return weekly_chart(data, start, end, week_start);
+    }
 
     let mut data: HashMap<String, (i64, i64)> = HashMap::new();
 
-    if use_hourly {
+    if granularity == ChartGranularity::Hourly {
         // Count hits per hour using ISO 8601 / RFC 3339 format
         for hit_time in hit_times {
             // Truncate to hour boundary
@@ -2041,210 +7829,869 @@ fn get_chart_data_filtered_sync(
             data.entry(key).or_insert((0, 0)).1 += 1;
         }
 
-        // Distribute sessions across days with data
-        let days_with_data = data.len().max(1) as i64;
-        for key in data.keys().cloned().collect::<Vec<_>>() {
-            data.entry(key).or_insert((0, 0)).0 = session_count / days_with_data;
+        // Distribute sessions across days with data
+        let days_with_data = data.len().max(1) as i64;
+        for key in data.keys().cloned().collect::<Vec<_>>() {
+            data.entry(key).or_insert((0, 0)).0 = session_count / days_with_data;
+        }
+
+        // Fill in missing days - ensure at least 1 day even for same start/end
+        let days = (end - start).num_days().max(0) + 1;
+        for i in 0..days {
+            let day = (start + Duration::days(i)).date_naive();
+            // Include days up to 'end' or 'now', whichever is later
+            if day <= end.date_naive() || day <= now.date_naive() {
+                let key = day.format("%Y-%m-%d").to_string();
+                data.entry(key).or_insert((0, 0));
+            }
+        }
+
+        let mut sorted: Vec<_> = data.into_iter().collect();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Convert UTC date keys to user's timezone for display labels
+        // INVARIANT: Every data point MUST have a corresponding label
+        let entries: Vec<(String, i64, i64)> = sorted
+            .into_iter()
+            .filter_map(|(utc_key, (sessions, hits))| {
+                chrono::NaiveDate::parse_from_str(&utc_key, "%Y-%m-%d")
+                    .ok()
+                    .map(|naive_date| {
+                        let utc_dt = naive_date.and_hms_opt(12, 0, 0).unwrap();
+                        let utc_dt = Utc.from_utc_datetime(&utc_dt);
+                        let local_dt = utc_dt.with_timezone(&tz);
+                        let label = local_dt.format("%b %d").to_string();
+                        (label, sessions, hits)
+                    })
+            })
+            .collect();
+
+        let chart_data = ChartData {
+            labels: entries.iter().map(|(l, _, _)| l.clone()).collect(),
+            sessions: entries.iter().map(|(_, s, _)| *s).collect(),
+            hits: entries.iter().map(|(_, _, h)| *h).collect(),
+        };
+
+        (chart_data, "MMM d".to_string(), "daily".to_string())
+    }
+}
+
+// Row types for SQLx mapping - PostgreSQL versions
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct ServiceRow {
+    id: uuid::Uuid,
+    tracking_id: Option<String>,
+    name: String,
+    link: String,
+    origins: String,
+    status: String,
+    respect_dnt: bool,
+    respect_gpc: bool,
+    ignore_robots: bool,
+    collect_ips: bool,
+    ignored_ips: String,
+    hide_referrer_regex: String,
+    script_inject: String,
+    created_at: DateTime<Utc>,
+    collapse_tabs: bool,
+    hit_quota: i64,
+    quota_behavior: String,
+    organization_id: uuid::Uuid,
+    heartbeat_frequency_ms: i64,
+    idle_timeout_mins: i64,
+    public_badge: bool,
+    content_groups: String,
+    active_user_timeout_ms: i64,
+    lowercase_paths: bool,
+    strip_trailing_slash: bool,
+    path_patterns: String,
+    time_zone: String,
+    default_range: String,
+    signed_pixel_ids: bool,
+    bounce_rule: String,
+    bounce_threshold_secs: i64,
+    session_timeout_mins: i64,
+    week_start: String,
+    debug_log: bool,
+    link_users: bool,
+    hide_internal_referrers: bool,
+    track_errors: bool,
+    allowed_countries: String,
+    blocked_countries: String,
+    allowed_asns: String,
+    blocked_asns: String,
+}
+
+#[cfg(feature = "postgres")]
+impl From<ServiceRow> for Service {
+    fn from(row: ServiceRow) -> Self {
+        Self {
+            id: ServiceId(row.id),
+            organization_id: OrganizationId(row.organization_id),
+            tracking_id: TrackingId(row.tracking_id.unwrap_or_else(|| TrackingId::new().0)),
+            name: row.name,
+            link: row.link,
+            origins: row.origins,
+            status: ServiceStatus::from_str(&row.status).unwrap_or(ServiceStatus::Active),
+            respect_dnt: row.respect_dnt,
+            respect_gpc: row.respect_gpc,
+            link_users: row.link_users,
+            hide_internal_referrers: row.hide_internal_referrers,
+            track_errors: row.track_errors,
+            allowed_countries: row.allowed_countries,
+            blocked_countries: row.blocked_countries,
+            allowed_asns: row.allowed_asns,
+            blocked_asns: row.blocked_asns,
+            ignore_robots: row.ignore_robots,
+            collect_ips: row.collect_ips,
+            ignored_ips: row.ignored_ips,
+            hide_referrer_regex: row.hide_referrer_regex,
+            script_inject: row.script_inject,
+            created_at: row.created_at,
+            collapse_tabs: row.collapse_tabs,
+            hit_quota: row.hit_quota,
+            quota_behavior: QuotaBehavior::from_str(&row.quota_behavior).unwrap_or_default(),
+            heartbeat_frequency_ms: row.heartbeat_frequency_ms,
+            idle_timeout_mins: row.idle_timeout_mins,
+            public_badge: row.public_badge,
+            content_groups: row.content_groups,
+            active_user_timeout_ms: row.active_user_timeout_ms,
+            lowercase_paths: row.lowercase_paths,
+            strip_trailing_slash: row.strip_trailing_slash,
+            path_patterns: row.path_patterns,
+            time_zone: row.time_zone,
+            default_range: DateRangePreset::from_str(&row.default_range).unwrap_or_default(),
+            signed_pixel_ids: row.signed_pixel_ids,
+            bounce_rule: BounceRule::from_str(&row.bounce_rule).unwrap_or_default(),
+            bounce_threshold_secs: row.bounce_threshold_secs,
+            session_timeout_mins: row.session_timeout_mins,
+            week_start: WeekStart::from_str(&row.week_start),
+            debug_log: row.debug_log,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct OrganizationRow {
+    id: uuid::Uuid,
+    name: String,
+    slug: String,
+    hit_quota: i64,
+    quota_behavior: String,
+    logo_url: String,
+    accent_color: String,
+    theme_mode: String,
+    created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "postgres")]
+impl From<OrganizationRow> for Organization {
+    fn from(row: OrganizationRow) -> Self {
+        Self {
+            id: OrganizationId(row.id),
+            name: row.name,
+            slug: row.slug,
+            hit_quota: row.hit_quota,
+            quota_behavior: QuotaBehavior::from_str(&row.quota_behavior).unwrap_or_default(),
+            logo_url: row.logo_url,
+            accent_color: row.accent_color,
+            theme_mode: ThemeMode::from_str(&row.theme_mode),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: uuid::Uuid,
+    email: String,
+    name: String,
+    password_hash: String,
+    email_verified_at: Option<DateTime<Utc>>,
+    totp_secret: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "postgres")]
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        Self {
+            id: UserId(row.id),
+            email: row.email,
+            name: row.name,
+            password_hash: row.password_hash,
+            email_verified_at: row.email_verified_at,
+            totp_secret: row.totp_secret,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct ApiTokenRow {
+    id: uuid::Uuid,
+    organization_id: uuid::Uuid,
+    name: String,
+    token_hash: String,
+    role: String,
+    user_id: Option<uuid::Uuid>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(feature = "postgres")]
+impl From<ApiTokenRow> for ApiToken {
+    fn from(row: ApiTokenRow) -> Self {
+        Self {
+            id: ApiTokenId(row.id),
+            organization_id: OrganizationId(row.organization_id),
+            name: row.name,
+            token_hash: row.token_hash,
+            role: Role::from_str(&row.role).unwrap_or_default(),
+            user_id: row.user_id.map(UserId),
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct SavedViewRow {
+    id: uuid::Uuid,
+    service_id: uuid::Uuid,
+    name: String,
+    start_date: String,
+    end_date: String,
+    url_pattern: String,
+    layout: String,
+    created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "postgres")]
+impl From<SavedViewRow> for SavedView {
+    fn from(row: SavedViewRow) -> Self {
+        Self {
+            id: SavedViewId(row.id),
+            service_id: ServiceId(row.service_id),
+            name: row.name,
+            start_date: row.start_date,
+            end_date: row.end_date,
+            url_pattern: row.url_pattern,
+            layout: PanelLayout::parse(&row.layout),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct SegmentRow {
+    id: uuid::Uuid,
+    service_id: uuid::Uuid,
+    name: String,
+    conditions: String,
+    created_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "postgres")]
+impl From<SegmentRow> for Segment {
+    fn from(row: SegmentRow) -> Self {
+        Self {
+            id: SegmentId(row.id),
+            service_id: ServiceId(row.service_id),
+            name: row.name,
+            conditions: serde_json::from_str(&row.conditions).unwrap_or_default(),
+            created_at: row.created_at,
         }
+    }
+}
 
-        // Fill in missing days - ensure at least 1 day even for same start/end
-        let days = (end - start).num_days().max(0) + 1;
-        for i in 0..days {
-            let day = (start + Duration::days(i)).date_naive();
-            // Include days up to 'end' or 'now', whichever is later
-            if day <= end.date_naive() || day <= now.date_naive() {
-                let key = day.format("%Y-%m-%d").to_string();
-                data.entry(key).or_insert((0, 0));
-            }
-        }
+/// A hit with its session's attributes, for exports
+#[derive(sqlx::FromRow)]
+struct ExportedHitRow {
+    #[sqlx(flatten)]
+    hit: HitRow,
+    country: String,
+    browser: String,
+    os: String,
+    device_type: String,
+}
 
-        let mut sorted: Vec<_> = data.into_iter().collect();
-        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+impl From<ExportedHitRow> for ExportedHit {
+    fn from(row: ExportedHitRow) -> Self {
+        Self {
+            hit: row.hit.into(),
+            country: row.country,
+            browser: row.browser,
+            os: row.os,
+            device_type: DeviceType::from_str(&row.device_type),
+        }
+    }
+}
 
-        // Convert UTC date keys to user's timezone for display labels
-        // INVARIANT: Every data point MUST have a corresponding label
-        let entries: Vec<(String, i64, i64)> = sorted
-            .into_iter()
-            .filter_map(|(utc_key, (sessions, hits))| {
-                chrono::NaiveDate::parse_from_str(&utc_key, "%Y-%m-%d")
-                    .ok()
-                    .map(|naive_date| {
-                        let utc_dt = naive_date.and_hms_opt(12, 0, 0).unwrap();
-                        let utc_dt = Utc.from_utc_datetime(&utc_dt);
-                        let local_dt = utc_dt.with_timezone(&tz);
-                        let label = local_dt.format("%b %d").to_string();
-                        (label, sessions, hits)
-                    })
-            })
-            .collect();
+/// A session with its hits' locations, one per line, when they are needed
+#[derive(sqlx::FromRow)]
+struct ExportedSessionRow {
+    #[sqlx(flatten)]
+    session: SessionRow,
+    locations: Option<String>,
+}
 
-        let chart_data = ChartData {
-            labels: entries.iter().map(|(l, _, _)| l.clone()).collect(),
-            sessions: entries.iter().map(|(_, s, _)| *s).collect(),
-            hits: entries.iter().map(|(_, _, h)| *h).collect(),
-        };
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct SessionRow {
+    id: uuid::Uuid,
+    service_id: uuid::Uuid,
+    identifier: String,
+    start_time: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    user_agent: String,
+    browser: String,
+    device: String,
+    device_type: String,
+    os: String,
+    ip: Option<String>,
+    ip_encrypted: Option<String>,
+    asn: String,
+    country: String,
+    longitude: Option<f64>,
+    latitude: Option<f64>,
+    time_zone: String,
+    is_bounce: bool,
+    ended_at: Option<DateTime<Utc>>,
+    environment: String,
+    props: String,
+}
 
-        (chart_data, "MMM d".to_string(), "daily".to_string())
+#[cfg(feature = "postgres")]
+impl From<SessionRow> for Session {
+    fn from(row: SessionRow) -> Self {
+        Self {
+            id: SessionId(row.id),
+            service_id: ServiceId(row.service_id),
+            identifier: row.identifier,
+            start_time: row.start_time,
+            last_seen: row.last_seen,
+            user_agent: row.user_agent,
+            browser: row.browser,
+            device: row.device,
+            device_type: DeviceType::from_str(&row.device_type),
+            os: row.os,
+            ip: row.ip,
+            ip_encrypted: row.ip_encrypted,
+            asn: row.asn,
+            country: row.country,
+            longitude: row.longitude,
+            latitude: row.latitude,
+            time_zone: row.time_zone,
+            is_bounce: row.is_bounce,
+            ended_at: row.ended_at,
+            environment: Environment::from_str(&row.environment).unwrap_or_default(),
+            props: parse_session_props(&row.props),
+        }
     }
 }
 
-// Row types for SQLx mapping - PostgreSQL versions
 #[cfg(feature = "postgres")]
 #[derive(sqlx::FromRow)]
+struct HitRow {
+    id: i64,
+    session_id: uuid::Uuid,
+    service_id: uuid::Uuid,
+    initial: bool,
+    start_time: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    heartbeats: i32,
+    tracker: String,
+    location: String,
+    referrer: String,
+    load_time: Option<f64>,
+    environment: String,
+    prefetched: bool,
+    referrer_spam: bool,
+}
+
+#[cfg(feature = "postgres")]
+impl From<HitRow> for Hit {
+    fn from(row: HitRow) -> Self {
+        Self {
+            id: HitId(row.id),
+            session_id: SessionId(row.session_id),
+            service_id: ServiceId(row.service_id),
+            initial: row.initial,
+            start_time: row.start_time,
+            last_seen: row.last_seen,
+            heartbeats: row.heartbeats,
+            tracker: TrackerType::from_str(&row.tracker),
+            location: row.location,
+            referrer: row.referrer,
+            load_time: row.load_time,
+            environment: Environment::from_str(&row.environment).unwrap_or_default(),
+            prefetched: row.prefetched,
+            referrer_spam: row.referrer_spam,
+        }
+    }
+}
+
+// Row types for SQLx mapping - SQLite versions (UUIDs stored as TEXT)
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
 struct ServiceRow {
-    id: uuid::Uuid,
+    id: String,
     tracking_id: Option<String>,
     name: String,
     link: String,
     origins: String,
     status: String,
     respect_dnt: bool,
+    respect_gpc: bool,
     ignore_robots: bool,
     collect_ips: bool,
     ignored_ips: String,
     hide_referrer_regex: String,
     script_inject: String,
-    created_at: DateTime<Utc>,
+    created_at: String,
+    collapse_tabs: bool,
+    hit_quota: i64,
+    quota_behavior: String,
+    organization_id: String,
+    heartbeat_frequency_ms: i64,
+    idle_timeout_mins: i64,
+    public_badge: bool,
+    content_groups: String,
+    active_user_timeout_ms: i64,
+    lowercase_paths: bool,
+    strip_trailing_slash: bool,
+    path_patterns: String,
+    time_zone: String,
+    default_range: String,
+    signed_pixel_ids: bool,
+    bounce_rule: String,
+    bounce_threshold_secs: i64,
+    session_timeout_mins: i64,
+    week_start: String,
+    debug_log: bool,
+    link_users: bool,
+    hide_internal_referrers: bool,
+    track_errors: bool,
+    allowed_countries: String,
+    blocked_countries: String,
+    allowed_asns: String,
+    blocked_asns: String,
 }
 
-#[cfg(feature = "postgres")]
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
 impl From<ServiceRow> for Service {
     fn from(row: ServiceRow) -> Self {
         Self {
-            id: ServiceId(row.id),
+            id: ServiceId(row.id.parse().unwrap_or_default()),
+            organization_id: row
+                .organization_id
+                .parse()
+                .unwrap_or(OrganizationId::DEFAULT),
             tracking_id: TrackingId(row.tracking_id.unwrap_or_else(|| TrackingId::new().0)),
             name: row.name,
             link: row.link,
             origins: row.origins,
             status: ServiceStatus::from_str(&row.status).unwrap_or(ServiceStatus::Active),
             respect_dnt: row.respect_dnt,
+            respect_gpc: row.respect_gpc,
+            link_users: row.link_users,
+            hide_internal_referrers: row.hide_internal_referrers,
+            track_errors: row.track_errors,
+            allowed_countries: row.allowed_countries,
+            blocked_countries: row.blocked_countries,
+            allowed_asns: row.allowed_asns,
+            blocked_asns: row.blocked_asns,
             ignore_robots: row.ignore_robots,
             collect_ips: row.collect_ips,
             ignored_ips: row.ignored_ips,
             hide_referrer_regex: row.hide_referrer_regex,
             script_inject: row.script_inject,
+            created_at: DateTime::parse_from_rfc3339(&row.created_at)
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            collapse_tabs: row.collapse_tabs,
+            hit_quota: row.hit_quota,
+            quota_behavior: QuotaBehavior::from_str(&row.quota_behavior).unwrap_or_default(),
+            heartbeat_frequency_ms: row.heartbeat_frequency_ms,
+            idle_timeout_mins: row.idle_timeout_mins,
+            public_badge: row.public_badge,
+            content_groups: row.content_groups,
+            active_user_timeout_ms: row.active_user_timeout_ms,
+            lowercase_paths: row.lowercase_paths,
+            strip_trailing_slash: row.strip_trailing_slash,
+            path_patterns: row.path_patterns,
+            time_zone: row.time_zone,
+            default_range: DateRangePreset::from_str(&row.default_range).unwrap_or_default(),
+            signed_pixel_ids: row.signed_pixel_ids,
+            bounce_rule: BounceRule::from_str(&row.bounce_rule).unwrap_or_default(),
+            bounce_threshold_secs: row.bounce_threshold_secs,
+            session_timeout_mins: row.session_timeout_mins,
+            week_start: WeekStart::from_str(&row.week_start),
+            debug_log: row.debug_log,
+        }
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct OrganizationRow {
+    id: String,
+    name: String,
+    slug: String,
+    hit_quota: i64,
+    quota_behavior: String,
+    logo_url: String,
+    accent_color: String,
+    theme_mode: String,
+    created_at: String,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+impl From<OrganizationRow> for Organization {
+    fn from(row: OrganizationRow) -> Self {
+        Self {
+            id: row.id.parse().unwrap_or(OrganizationId::DEFAULT),
+            name: row.name,
+            slug: row.slug,
+            hit_quota: row.hit_quota,
+            quota_behavior: QuotaBehavior::from_str(&row.quota_behavior).unwrap_or_default(),
+            logo_url: row.logo_url,
+            accent_color: row.accent_color,
+            theme_mode: ThemeMode::from_str(&row.theme_mode),
+            created_at: parse_sqlite_time(&row.created_at),
+        }
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: String,
+    email: String,
+    name: String,
+    password_hash: String,
+    email_verified_at: Option<String>,
+    totp_secret: Option<String>,
+    created_at: String,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        Self {
+            id: UserId(row.id.parse().unwrap_or_default()),
+            email: row.email,
+            name: row.name,
+            password_hash: row.password_hash,
+            email_verified_at: row.email_verified_at.as_deref().map(parse_sqlite_time),
+            totp_secret: row.totp_secret,
+            created_at: parse_sqlite_time(&row.created_at),
+        }
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct ApiTokenRow {
+    id: String,
+    organization_id: String,
+    name: String,
+    token_hash: String,
+    role: String,
+    user_id: Option<String>,
+    created_at: String,
+    last_used_at: Option<String>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+impl From<ApiTokenRow> for ApiToken {
+    fn from(row: ApiTokenRow) -> Self {
+        Self {
+            id: ApiTokenId(row.id.parse().unwrap_or_default()),
+            organization_id: row
+                .organization_id
+                .parse()
+                .unwrap_or(OrganizationId::DEFAULT),
+            name: row.name,
+            token_hash: row.token_hash,
+            role: Role::from_str(&row.role).unwrap_or_default(),
+            user_id: row.user_id.and_then(|id| id.parse().ok()),
+            created_at: parse_sqlite_time(&row.created_at),
+            last_used_at: row.last_used_at.as_deref().map(parse_sqlite_time),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct LoginAttemptRow {
+    email: String,
+    ip: Option<String>,
+    outcome: String,
+    created_at: DateTime<Utc>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct LoginAttemptRow {
+    email: String,
+    ip: Option<String>,
+    outcome: String,
+    created_at: String,
+}
+
+impl From<LoginAttemptRow> for LoginAttempt {
+    fn from(row: LoginAttemptRow) -> Self {
+        Self {
+            email: row.email,
+            ip: row.ip,
+            outcome: LoginOutcome::from_str(&row.outcome).unwrap_or(LoginOutcome::WrongPassword),
+            #[cfg(feature = "postgres")]
             created_at: row.created_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            created_at: parse_sqlite_time(&row.created_at),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct MonitorCheckRow {
+    service_id: uuid::Uuid,
+    checked_at: DateTime<Utc>,
+    status_code: Option<i32>,
+    latency_ms: Option<i64>,
+    up: bool,
+    error: Option<String>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct MonitorCheckRow {
+    service_id: String,
+    checked_at: String,
+    status_code: Option<i32>,
+    latency_ms: Option<i64>,
+    up: bool,
+    error: Option<String>,
+}
+
+impl From<MonitorCheckRow> for MonitorCheck {
+    fn from(row: MonitorCheckRow) -> Self {
+        Self {
+            #[cfg(feature = "postgres")]
+            service_id: ServiceId(row.service_id),
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            service_id: ServiceId(row.service_id.parse().unwrap_or_default()),
+            #[cfg(feature = "postgres")]
+            checked_at: row.checked_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            checked_at: parse_sqlite_time(&row.checked_at),
+            status_code: row.status_code.and_then(|code| u16::try_from(code).ok()),
+            latency_ms: row.latency_ms,
+            up: row.up,
+            error: row.error,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    service_id: uuid::Uuid,
+    service_name: String,
+    value: String,
+    #[sqlx(default)]
+    session_id: Option<uuid::Uuid>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    service_id: String,
+    service_name: String,
+    value: String,
+    #[sqlx(default)]
+    session_id: Option<String>,
+}
+
+impl SearchRow {
+    fn into_result(self, kind: SearchResultKind) -> SearchResult {
+        SearchResult {
+            kind,
+            #[cfg(feature = "postgres")]
+            service_id: ServiceId(self.service_id),
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            service_id: ServiceId(self.service_id.parse().unwrap_or_default()),
+            service_name: self.service_name,
+            value: self.value,
+            #[cfg(feature = "postgres")]
+            session_id: self.session_id.map(SessionId),
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            session_id: self.session_id.and_then(|id| id.parse().ok()),
         }
     }
 }
 
 #[cfg(feature = "postgres")]
 #[derive(sqlx::FromRow)]
-struct SessionRow {
-    id: uuid::Uuid,
+struct ExpiryCheckRow {
     service_id: uuid::Uuid,
-    identifier: String,
-    start_time: DateTime<Utc>,
-    last_seen: DateTime<Utc>,
-    user_agent: String,
-    browser: String,
-    device: String,
-    device_type: String,
-    os: String,
-    ip: Option<String>,
-    asn: String,
-    country: String,
-    longitude: Option<f64>,
-    latitude: Option<f64>,
-    time_zone: String,
-    is_bounce: bool,
+    checked_at: DateTime<Utc>,
+    certificate_expires_at: Option<DateTime<Utc>>,
+    domain_expires_at: Option<DateTime<Utc>>,
 }
 
-#[cfg(feature = "postgres")]
-impl From<SessionRow> for Session {
-    fn from(row: SessionRow) -> Self {
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct ExpiryCheckRow {
+    service_id: String,
+    checked_at: String,
+    certificate_expires_at: Option<String>,
+    domain_expires_at: Option<String>,
+}
+
+impl From<ExpiryCheckRow> for ExpiryCheck {
+    fn from(row: ExpiryCheckRow) -> Self {
         Self {
-            id: SessionId(row.id),
+            #[cfg(feature = "postgres")]
             service_id: ServiceId(row.service_id),
-            identifier: row.identifier,
-            start_time: row.start_time,
-            last_seen: row.last_seen,
-            user_agent: row.user_agent,
-            browser: row.browser,
-            device: row.device,
-            device_type: DeviceType::from_str(&row.device_type),
-            os: row.os,
-            ip: row.ip,
-            asn: row.asn,
-            country: row.country,
-            longitude: row.longitude,
-            latitude: row.latitude,
-            time_zone: row.time_zone,
-            is_bounce: row.is_bounce,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            service_id: ServiceId(row.service_id.parse().unwrap_or_default()),
+            #[cfg(feature = "postgres")]
+            checked_at: row.checked_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            checked_at: parse_sqlite_time(&row.checked_at),
+            #[cfg(feature = "postgres")]
+            certificate_expires_at: row.certificate_expires_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            certificate_expires_at: row.certificate_expires_at.as_deref().map(parse_sqlite_time),
+            #[cfg(feature = "postgres")]
+            domain_expires_at: row.domain_expires_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            domain_expires_at: row.domain_expires_at.as_deref().map(parse_sqlite_time),
         }
     }
 }
 
 #[cfg(feature = "postgres")]
 #[derive(sqlx::FromRow)]
-struct HitRow {
-    id: i64,
-    session_id: uuid::Uuid,
-    service_id: uuid::Uuid,
-    initial: bool,
-    start_time: DateTime<Utc>,
-    last_seen: DateTime<Utc>,
-    heartbeats: i32,
-    tracker: String,
-    location: String,
-    referrer: String,
-    load_time: Option<f64>,
+struct MaintenanceRunRow {
+    task: String,
+    started_at: DateTime<Utc>,
+    duration_ms: i64,
+    error: Option<String>,
 }
 
-#[cfg(feature = "postgres")]
-impl From<HitRow> for Hit {
-    fn from(row: HitRow) -> Self {
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct MaintenanceRunRow {
+    task: String,
+    started_at: String,
+    duration_ms: i64,
+    error: Option<String>,
+}
+
+impl MaintenanceRunRow {
+    /// `None` for a task this version no longer has
+    fn into_run(self) -> Option<MaintenanceRun> {
+        Some(MaintenanceRun {
+            task: MaintenanceTask::from_str(&self.task)?,
+            #[cfg(feature = "postgres")]
+            started_at: self.started_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            started_at: parse_sqlite_time(&self.started_at),
+            duration_ms: self.duration_ms,
+            error: self.error,
+        })
+    }
+}
+
+/// A `UserRow` joined with the membership's role
+#[derive(sqlx::FromRow)]
+struct MemberRow {
+    #[sqlx(flatten)]
+    user: UserRow,
+    role: String,
+}
+
+impl From<MemberRow> for Member {
+    fn from(row: MemberRow) -> Self {
         Self {
-            id: HitId(row.id),
-            session_id: SessionId(row.session_id),
-            service_id: ServiceId(row.service_id),
-            initial: row.initial,
-            start_time: row.start_time,
-            last_seen: row.last_seen,
-            heartbeats: row.heartbeats,
-            tracker: TrackerType::from_str(&row.tracker),
-            location: row.location,
-            referrer: row.referrer,
-            load_time: row.load_time,
+            user: row.user.into(),
+            role: Role::from_str(&row.role).unwrap_or_default(),
         }
     }
 }
 
-// Row types for SQLx mapping - SQLite versions (UUIDs stored as TEXT)
+/// Timestamps written by the app are RFC 3339; column defaults use SQLite's
+/// `datetime('now')` format
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+fn parse_sqlite_time(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").map(|d| d.and_utc())
+        })
+        .unwrap_or_else(|_| Utc::now())
+}
+
 #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
 #[derive(sqlx::FromRow)]
-struct ServiceRow {
+struct SavedViewRow {
     id: String,
-    tracking_id: Option<String>,
+    service_id: String,
     name: String,
-    link: String,
-    origins: String,
-    status: String,
-    respect_dnt: bool,
-    ignore_robots: bool,
-    collect_ips: bool,
-    ignored_ips: String,
-    hide_referrer_regex: String,
-    script_inject: String,
+    start_date: String,
+    end_date: String,
+    url_pattern: String,
+    layout: String,
     created_at: String,
 }
 
 #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-impl From<ServiceRow> for Service {
-    fn from(row: ServiceRow) -> Self {
+impl From<SavedViewRow> for SavedView {
+    fn from(row: SavedViewRow) -> Self {
         Self {
-            id: ServiceId(row.id.parse().unwrap_or_default()),
-            tracking_id: TrackingId(row.tracking_id.unwrap_or_else(|| TrackingId::new().0)),
+            id: SavedViewId(row.id.parse().unwrap_or_default()),
+            service_id: ServiceId(row.service_id.parse().unwrap_or_default()),
             name: row.name,
-            link: row.link,
-            origins: row.origins,
-            status: ServiceStatus::from_str(&row.status).unwrap_or(ServiceStatus::Active),
-            respect_dnt: row.respect_dnt,
-            ignore_robots: row.ignore_robots,
-            collect_ips: row.collect_ips,
-            ignored_ips: row.ignored_ips,
-            hide_referrer_regex: row.hide_referrer_regex,
-            script_inject: row.script_inject,
+            start_date: row.start_date,
+            end_date: row.end_date,
+            url_pattern: row.url_pattern,
+            layout: PanelLayout::parse(&row.layout),
+            created_at: DateTime::parse_from_rfc3339(&row.created_at)
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+        }
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct SegmentRow {
+    id: String,
+    service_id: String,
+    name: String,
+    conditions: String,
+    created_at: String,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+impl From<SegmentRow> for Segment {
+    fn from(row: SegmentRow) -> Self {
+        Self {
+            id: SegmentId(row.id.parse().unwrap_or_default()),
+            service_id: ServiceId(row.service_id.parse().unwrap_or_default()),
+            name: row.name,
+            conditions: serde_json::from_str(&row.conditions).unwrap_or_default(),
             created_at: DateTime::parse_from_rfc3339(&row.created_at)
                 .map(|d| d.with_timezone(&Utc))
                 .unwrap_or_else(|_| Utc::now()),
@@ -2266,12 +8713,16 @@ struct SessionRow {
     device_type: String,
     os: String,
     ip: Option<String>,
+    ip_encrypted: Option<String>,
     asn: String,
     country: String,
     longitude: Option<f64>,
     latitude: Option<f64>,
     time_zone: String,
     is_bounce: bool,
+    ended_at: Option<String>,
+    environment: String,
+    props: String,
 }
 
 #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
@@ -2293,12 +8744,19 @@ impl From<SessionRow> for Session {
             device_type: DeviceType::from_str(&row.device_type),
             os: row.os,
             ip: row.ip,
+            ip_encrypted: row.ip_encrypted,
             asn: row.asn,
             country: row.country,
             longitude: row.longitude,
             latitude: row.latitude,
             time_zone: row.time_zone,
             is_bounce: row.is_bounce,
+            ended_at: row
+                .ended_at
+                .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                .map(|d| d.with_timezone(&Utc)),
+            environment: Environment::from_str(&row.environment).unwrap_or_default(),
+            props: parse_session_props(&row.props),
         }
     }
 }
@@ -2317,6 +8775,9 @@ struct HitRow {
     location: String,
     referrer: String,
     load_time: Option<f64>,
+    environment: String,
+    prefetched: bool,
+    referrer_spam: bool,
 }
 
 #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
@@ -2338,22 +8799,178 @@ impl From<HitRow> for Hit {
             location: row.location,
             referrer: row.referrer,
             load_time: row.load_time,
+            environment: Environment::from_str(&row.environment).unwrap_or_default(),
+            prefetched: row.prefetched,
+            referrer_spam: row.referrer_spam,
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct CampaignRow {
+    campaign: String,
+    opens: i64,
+    unique_opens: i64,
+    first_open: DateTime<Utc>,
+    last_open: DateTime<Utc>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct CampaignRow {
+    campaign: String,
+    opens: i64,
+    unique_opens: i64,
+    first_open: String,
+    last_open: String,
+}
+
+impl From<CampaignRow> for CampaignSummary {
+    fn from(row: CampaignRow) -> Self {
+        Self {
+            campaign: row.campaign,
+            opens: row.opens,
+            unique_opens: row.unique_opens,
+            #[cfg(feature = "postgres")]
+            first_open: row.first_open,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            first_open: parse_sqlite_time(&row.first_open),
+            #[cfg(feature = "postgres")]
+            last_open: row.last_open,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            last_open: parse_sqlite_time(&row.last_open),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct CampaignRecipientRow {
+    recipient: String,
+    opens: i64,
+    first_open: DateTime<Utc>,
+    last_open: DateTime<Utc>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct CampaignRecipientRow {
+    recipient: String,
+    opens: i64,
+    first_open: String,
+    last_open: String,
+}
+
+impl From<CampaignRecipientRow> for CampaignRecipient {
+    fn from(row: CampaignRecipientRow) -> Self {
+        Self {
+            recipient: row.recipient,
+            opens: row.opens,
+            #[cfg(feature = "postgres")]
+            first_open: row.first_open,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            first_open: parse_sqlite_time(&row.first_open),
+            #[cfg(feature = "postgres")]
+            last_open: row.last_open,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            last_open: parse_sqlite_time(&row.last_open),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[derive(sqlx::FromRow)]
+struct TrackedLinkRow {
+    id: uuid::Uuid,
+    service_id: uuid::Uuid,
+    token: String,
+    name: String,
+    campaign: String,
+    target_url: String,
+    created_at: DateTime<Utc>,
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[derive(sqlx::FromRow)]
+struct TrackedLinkRow {
+    id: String,
+    service_id: String,
+    token: String,
+    name: String,
+    campaign: String,
+    target_url: String,
+    created_at: String,
+}
+
+impl From<TrackedLinkRow> for TrackedLink {
+    fn from(row: TrackedLinkRow) -> Self {
+        Self {
+            #[cfg(feature = "postgres")]
+            id: LinkId(row.id),
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            id: LinkId(row.id.parse().unwrap_or_default()),
+            #[cfg(feature = "postgres")]
+            service_id: ServiceId(row.service_id),
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            service_id: ServiceId(row.service_id.parse().unwrap_or_default()),
+            token: row.token,
+            name: row.name,
+            campaign: row.campaign,
+            target_url: row.target_url,
+            #[cfg(feature = "postgres")]
+            created_at: row.created_at,
+            #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+            created_at: parse_sqlite_time(&row.created_at),
         }
     }
 }
 
+/// A `TrackedLinkRow` with its clicks in a range
+#[derive(sqlx::FromRow)]
+struct TrackedLinkStatsRow {
+    #[sqlx(flatten)]
+    link: TrackedLinkRow,
+    clicks: i64,
+    unique_clicks: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct BucketRow {
+    bucket: i64,
+    count: i64,
+}
+
+impl BucketRow {
+    fn into_pair(self) -> (usize, i64) {
+        (usize::try_from(self.bucket).unwrap_or_default(), self.count)
+    }
+}
+
 #[derive(sqlx::FromRow)]
 struct CountedRow {
     value: Option<String>,
     count: i64,
 }
 
+#[derive(sqlx::FromRow)]
+struct TransitionRow {
+    step: i64,
+    source: String,
+    target: String,
+    count: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct DimensionRow {
+    value: String,
+    location: String,
+    count: i64,
+}
+
 impl From<CountedRow> for CountedItem {
     fn from(row: CountedRow) -> Self {
-        Self {
-            value: row.value.unwrap_or_default(),
-            count: row.count,
-        }
+        Self::new(row.value.unwrap_or_default(), row.count)
     }
 }
 
@@ -2361,6 +8978,24 @@ impl From<CountedRow> for CountedItem {
 mod tests {
     use super::*;
 
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    #[tokio::test]
+    async fn test_failed_migration_adds_no_columns() {
+        let pool = create_pool("sqlite::memory:").await.unwrap();
+        sqlx::raw_sql("CREATE TABLE things (id INTEGER)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let broken = [Migration {
+            sql: "ALTER TABLE things ADD COLUMN first TEXT;
+                  ALTER TABLE missing ADD COLUMN second TEXT;",
+            adds_column: Some(("things", "first")),
+        }];
+        assert!(apply_migrations(&pool, &broken).await.is_err());
+        // Otherwise the guard would skip the script from now on
+        assert!(!has_column(&pool, "things", "first").await.unwrap());
+    }
+
     #[test]
     fn test_normalize_location_strips_query_params() {
         assert_eq!(normalize_location("/path?query=1"), "/path");
@@ -2400,4 +9035,83 @@ mod tests {
     fn test_normalize_location_handles_empty() {
         assert_eq!(normalize_location(""), "");
     }
+
+    /// SQL of one segment condition, with every placeholder as `?`
+    fn segment_condition_sql(field: SegmentField, op: SegmentOp, value: &str) -> String {
+        let mut query = QueryBuilder::<Db>::new("");
+        push_segment_condition(
+            &mut query,
+            &SegmentCondition {
+                field,
+                op,
+                value: value.to_string(),
+            },
+        );
+        Regex::new(r"\$\d+")
+            .unwrap()
+            .replace_all(query.sql(), "?")
+            .into_owned()
+    }
+
+    #[test]
+    fn test_segment_condition_binds_values() {
+        assert_eq!(
+            segment_condition_sql(SegmentField::Os, SegmentOp::Is, "it's"),
+            "seg.os = ?"
+        );
+        assert_eq!(
+            segment_condition_sql(SegmentField::Country, SegmentOp::IsNot, "de"),
+            "seg.country <> ?"
+        );
+        assert_eq!(
+            segment_condition_sql(SegmentField::Browser, SegmentOp::Contains, "100%_Fox"),
+            "LOWER(seg.browser) LIKE ? ESCAPE '\\'"
+        );
+        assert!(
+            segment_condition_sql(SegmentField::Page, SegmentOp::IsNot, "/blog")
+                .starts_with("NOT EXISTS")
+        );
+        let page = segment_condition_sql(SegmentField::Page, SegmentOp::Is, "example.com/a_b");
+        assert!(page.contains("page.location = ?"));
+        assert!(!page.contains("example.com"));
+
+        let mut query = QueryBuilder::<Db>::new("");
+        push_segment_filter(&mut query, None, "session_id");
+        assert_eq!(query.sql(), "");
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    #[tokio::test]
+    async fn test_segment_condition_values_match_as_typed() {
+        let pool = create_pool("sqlite::memory:").await.unwrap();
+        let count = |mut query: QueryBuilder<'static, Db>| {
+            let pool = pool.clone();
+            async move {
+                let (count,): (i64,) = query.build_query_as().fetch_one(&pool).await.unwrap();
+                count
+            }
+        };
+
+        let mut query = QueryBuilder::new(
+            "SELECT COUNT(*) FROM (SELECT 'it''s' AS os UNION ALL SELECT 'its') seg WHERE ",
+        );
+        push_compare(&mut query, "seg.os", SegmentOp::Is, "it's");
+        assert_eq!(count(query).await, 1);
+
+        // Pages match as the pages breakdown shows them, with `_` and `%`
+        // taken literally
+        let mut query = QueryBuilder::new(
+            "SELECT COUNT(*) FROM (SELECT 'https://example.com/a_b?x=1' AS location
+             UNION ALL SELECT 'example.com/a_b#top'
+             UNION ALL SELECT 'https://example.com/aXb') page WHERE ",
+        );
+        push_location_is(&mut query, "page.location", "example.com/a_b");
+        assert_eq!(count(query).await, 2);
+
+        let mut query = QueryBuilder::new(
+            "SELECT COUNT(*) FROM (SELECT 'Fox 100%' AS browser UNION ALL SELECT 'Fox 1000') seg WHERE ",
+        );
+        push_compare(&mut query, "seg.browser", SegmentOp::Contains, "100%");
+        assert_eq!(count(query).await, 1);
+    }
 }
@@ -1,29 +1,257 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+use url::Url;
 
 use super::types::{
-    ChartData, CountedItem, DeviceType, HitId, ServiceId, ServiceStatus, SessionId, TrackerType,
-    TrackingId,
+    ApiTokenId, BounceRule, BreakdownFilters, ChartData, CompareMode, ContentGroups,
+    ContinentCount, CountedItem, DateRangePreset, DeviceType, Environment, GeoRules, HitId,
+    HourCycle, IngressDecision, LinkId, LoginOutcome, MaintenanceTask, OrganizationId, PanelLayout,
+    PathNormalization, QuotaBehavior, ReferrerFilter, Role, SavedViewId, SegmentCondition,
+    SegmentId, ServiceId, ServiceStatus, SessionId, ThemeMode, TrackerType, TrackingId, UserId,
+    WeekStart,
 };
 
+/// A tenant: owns services, has member users and API tokens, and may cap the
+/// hits recorded across all of its services
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: OrganizationId,
+    pub name: String,
+    /// URL-friendly unique name
+    pub slug: String,
+    /// Soft limit on hits per calendar month (UTC) summed over every service; 0 means unlimited
+    pub hit_quota: i64,
+    pub quota_behavior: QuotaBehavior,
+    /// Dashboard logo in place of the install's `theme_logo_url`; empty
+    /// means none set
+    pub logo_url: String,
+    /// Dashboard accent color, `#rrggbb`, in place of `theme_accent`;
+    /// empty means none set
+    pub accent_color: String,
+    /// Light or dark dashboard in place of `theme_mode`
+    pub theme_mode: Option<ThemeMode>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Organization {
+    /// URL-friendly form of a name: lowercase ASCII letters and digits joined
+    /// by single dashes
+    pub fn slugify(name: &str) -> String {
+        let slug = name
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        if slug.is_empty() {
+            "org".to_string()
+        } else {
+            slug.chars()
+                .take(48)
+                .collect::<String>()
+                .trim_end_matches('-')
+                .to_string()
+        }
+    }
+
+    /// Whether `hits` recorded this month have used up the organization's quota
+    pub fn quota_exceeded(&self, hits: i64) -> bool {
+        self.hit_quota > 0 && hits >= self.hit_quota
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CreateOrganization {
+    pub name: String,
+    pub slug: String,
+    pub hit_quota: i64,
+    pub quota_behavior: QuotaBehavior,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOrganization {
+    pub name: Option<String>,
+    pub hit_quota: Option<i64>,
+    pub quota_behavior: Option<QuotaBehavior>,
+    pub logo_url: Option<String>,
+    pub accent_color: Option<String>,
+    /// `Some(None)` goes back to the install's `theme_mode`
+    pub theme_mode: Option<Option<ThemeMode>>,
+}
+
+/// A dashboard user; belongs to organizations through memberships
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: UserId,
+    pub email: String,
+    pub name: String,
+    #[serde(skip)]
+    pub password_hash: String,
+    /// When the user proved they own `email`
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Base32 TOTP secret; logging in with a password also takes a code from
+    /// the user's authenticator app when set
+    #[serde(skip)]
+    pub totp_secret: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A user's own preferences, the same in every organization
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserSettings {
+    /// IANA time zone the user's reports default to; empty if none
+    pub time_zone: String,
+    /// First day of the week; the language's custom if none
+    pub week_start: Option<WeekStart>,
+    /// 12- or 24-hour clock; the language's custom if none
+    pub hour_cycle: Option<HourCycle>,
+}
+
+/// An entry in the login audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginAttempt {
+    /// The address typed in, which need not belong to an account
+    pub email: String,
+    pub ip: Option<String>,
+    pub outcome: LoginOutcome,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Recent failed logins for an account or IP address, since its last
+/// successful one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoginFailures {
+    pub count: i64,
+    pub last_at: Option<DateTime<Utc>>,
+}
+
+/// A user as a member of an organization
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Member {
+    #[serde(flatten)]
+    pub user: User,
+    pub role: Role,
+}
+
+/// A bearer token for the JSON API, scoped to one organization. Only a hash of
+/// the token is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: ApiTokenId,
+    pub organization_id: OrganizationId,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    /// What requests made with the token may do
+    pub role: Role,
+    /// Who created the token, whose preferences shape its responses; none
+    /// for tokens made without logins or whose creator was deleted
+    pub user_id: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     pub id: ServiceId,
+    pub organization_id: OrganizationId,
     pub tracking_id: TrackingId,
     pub name: String,
     pub link: String,
     pub origins: String,
     pub status: ServiceStatus,
     pub respect_dnt: bool,
+    /// Honor Global Privacy Control, independently of Do Not Track
+    pub respect_gpc: bool,
     pub ignore_robots: bool,
     pub collect_ips: bool,
     pub ignored_ips: String,
     pub hide_referrer_regex: String,
     pub script_inject: String,
     pub created_at: DateTime<Utc>,
+    /// Coordinate tracker tabs and collapse concurrent identical page loads into one hit
+    pub collapse_tabs: bool,
+    /// Soft limit on hits recorded per calendar month (UTC); 0 means unlimited
+    pub hit_quota: i64,
+    /// What to do with new traffic once `hit_quota` is used up
+    pub quota_behavior: QuotaBehavior,
+    /// Tracker heartbeat interval; 0 means the server's default
+    pub heartbeat_frequency_ms: i64,
+    /// Minutes without interaction after which the tracker stops sending
+    /// heartbeats until the visitor is active again; 0 means never
+    pub idle_timeout_mins: i64,
+    /// Anyone may load the service's visitor badge
+    pub public_badge: bool,
+    /// Content group rules, one `pattern = Group` per line
+    pub content_groups: String,
+    /// How long after its last heartbeat a visitor still counts as online;
+    /// 0 means twice the heartbeat interval
+    pub active_user_timeout_ms: i64,
+    /// Record page paths lowercased
+    pub lowercase_paths: bool,
+    /// Record page paths without trailing slashes
+    pub strip_trailing_slash: bool,
+    /// Patterns collapsing dynamic path segments, one per line, e.g. `/users/:id`
+    pub path_patterns: String,
+    /// IANA time zone reports default to, e.g. `Europe/Berlin`; empty if none
+    pub time_zone: String,
+    /// Range reports cover unless the request or the viewer picks another
+    pub default_range: DateRangePreset,
+    /// Pixel hits with an identifier count only when its `sig` checks out
+    pub signed_pixel_ids: bool,
+    /// Which sessions count as bounces
+    pub bounce_rule: BounceRule,
+    /// Engaged seconds a single-page session needs not to count as a bounce
+    /// under `BounceRule::EngagedTime`
+    pub bounce_threshold_secs: i64,
+    /// Minutes without activity after which a returning visitor starts a new
+    /// session; 0 means sessions last as long as the server remembers them
+    pub session_timeout_mins: i64,
+    /// Day weekly charts start on for viewers who picked none; `None` leaves
+    /// it to their language
+    pub week_start: Option<WeekStart>,
+    /// Keep the latest ingress decisions for the service's debug page
+    pub debug_log: bool,
+    /// Link sessions that sent the same login identifier under a hashed user
+    /// key, for the users report
+    pub link_users: bool,
+    /// Leave referrers from the service's own site out of its referrer
+    /// breakdowns
+    pub hide_internal_referrers: bool,
+    /// Have the tracker report the page's JavaScript errors
+    pub track_errors: bool,
+    /// Comma-separated country codes and AS numbers visitors must be in, or
+    /// must not be in, to be recorded (see `GeoRules`)
+    pub allowed_countries: String,
+    pub blocked_countries: String,
+    pub allowed_asns: String,
+    pub blocked_asns: String,
 }
 
 impl Service {
+    /// Shortest heartbeat interval a service may set
+    pub const MIN_HEARTBEAT_FREQUENCY_MS: i64 = 1000;
+    pub const DEFAULT_IDLE_TIMEOUT_MINS: i64 = 30;
+    pub const DEFAULT_BOUNCE_THRESHOLD_SECS: i64 = 10;
+
+    /// `idle_timeout_mins` in milliseconds, as the tracker uses it
+    pub fn idle_timeout_ms(&self) -> u64 {
+        u64::try_from(self.idle_timeout_mins).unwrap_or(0) * 60_000
+    }
+
+    /// How long a session may go without activity, if the service limits it
+    pub fn session_timeout(&self) -> Option<Duration> {
+        Duration::try_minutes(self.session_timeout_mins).filter(|t| *t > Duration::zero())
+    }
+
+    /// Whether `hits` recorded this month have used up the service's quota
+    pub fn quota_exceeded(&self, hits: i64) -> bool {
+        self.hit_quota > 0 && hits >= self.hit_quota
+    }
+
     pub fn get_ignored_networks(&self) -> Vec<ipnetwork::IpNetwork> {
         if self.ignored_ips.trim().is_empty() {
             return Vec::new();
@@ -35,6 +263,39 @@ impl Service {
             .collect()
     }
 
+    pub fn get_content_groups(&self) -> ContentGroups {
+        ContentGroups::parse(&self.content_groups)
+    }
+
+    pub fn get_path_normalization(&self) -> PathNormalization {
+        PathNormalization::parse(
+            &self.path_patterns,
+            self.lowercase_paths,
+            self.strip_trailing_slash,
+        )
+    }
+
+    /// Referrers left out of the service's referrer breakdowns
+    pub fn get_referrer_filter(&self) -> ReferrerFilter {
+        ReferrerFilter::new(
+            &self.hide_referrer_regex,
+            self.hide_internal_referrers,
+            self.origins
+                .split(',')
+                .chain(std::iter::once(self.link.as_str())),
+        )
+    }
+
+    /// Countries and ASNs the service records visitors from
+    pub fn get_geo_rules(&self) -> GeoRules {
+        GeoRules::parse(
+            &self.allowed_countries,
+            &self.blocked_countries,
+            &self.allowed_asns,
+            &self.blocked_asns,
+        )
+    }
+
     pub fn get_origins_list(&self) -> Vec<String> {
         if self.origins == "*" {
             return vec!["*".to_string()];
@@ -68,13 +329,23 @@ pub struct Session {
     pub device: String,
     pub device_type: DeviceType,
     pub os: String,
+    /// Visitor IP, if stored in the clear or revealed with the key
     pub ip: Option<String>,
+    /// Visitor IP sealed with `ip_encryption_key`
+    #[serde(skip)]
+    pub ip_encrypted: Option<String>,
     pub asn: String,
     pub country: String,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
     pub time_zone: String,
     pub is_bounce: bool,
+    /// When the tracker last reported the visitor leaving, unless they were
+    /// active again afterwards
+    pub ended_at: Option<DateTime<Utc>>,
+    pub environment: Environment,
+    /// Properties the tracker attached with `setProps`, e.g. a plan
+    pub props: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,19 +361,65 @@ pub struct Hit {
     pub location: String,
     pub referrer: String,
     pub load_time: Option<f64>,
+    pub environment: Environment,
+    /// Sent for a page the browser prefetched or prerendered, kept under
+    /// `PrefetchBehavior::Flag`
+    pub prefetched: bool,
+    /// Referred from a referrer spam domain, kept under
+    /// `ReferrerSpamBehavior::Flag`
+    pub referrer_spam: bool,
+}
+
+/// A hit with the attributes of its session that exports carry along
+#[derive(Debug, Clone)]
+pub struct ExportedHit {
+    pub hit: Hit,
+    pub country: String,
+    pub browser: String,
+    pub os: String,
+    pub device_type: DeviceType,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct CreateService {
+    /// Owning organization; the default organization when `None`
+    pub organization_id: Option<OrganizationId>,
     pub name: String,
     pub link: String,
     pub origins: String,
     pub respect_dnt: bool,
+    pub respect_gpc: bool,
     pub ignore_robots: bool,
     pub collect_ips: bool,
     pub ignored_ips: String,
     pub hide_referrer_regex: String,
     pub script_inject: String,
+    pub collapse_tabs: bool,
+    pub hit_quota: i64,
+    pub quota_behavior: QuotaBehavior,
+    pub heartbeat_frequency_ms: i64,
+    pub idle_timeout_mins: i64,
+    pub public_badge: bool,
+    pub content_groups: String,
+    pub active_user_timeout_ms: i64,
+    pub lowercase_paths: bool,
+    pub strip_trailing_slash: bool,
+    pub path_patterns: String,
+    pub time_zone: String,
+    pub default_range: DateRangePreset,
+    pub signed_pixel_ids: bool,
+    pub bounce_rule: BounceRule,
+    pub bounce_threshold_secs: i64,
+    pub session_timeout_mins: i64,
+    pub week_start: Option<WeekStart>,
+    pub debug_log: bool,
+    pub link_users: bool,
+    pub hide_internal_referrers: bool,
+    pub track_errors: bool,
+    pub allowed_countries: String,
+    pub blocked_countries: String,
+    pub allowed_asns: String,
+    pub blocked_asns: String,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -112,11 +429,127 @@ pub struct UpdateService {
     pub origins: Option<String>,
     pub status: Option<ServiceStatus>,
     pub respect_dnt: Option<bool>,
+    pub respect_gpc: Option<bool>,
     pub ignore_robots: Option<bool>,
     pub collect_ips: Option<bool>,
     pub ignored_ips: Option<String>,
     pub hide_referrer_regex: Option<String>,
     pub script_inject: Option<String>,
+    pub collapse_tabs: Option<bool>,
+    pub hit_quota: Option<i64>,
+    pub quota_behavior: Option<QuotaBehavior>,
+    pub heartbeat_frequency_ms: Option<i64>,
+    pub idle_timeout_mins: Option<i64>,
+    pub public_badge: Option<bool>,
+    pub content_groups: Option<String>,
+    pub active_user_timeout_ms: Option<i64>,
+    pub lowercase_paths: Option<bool>,
+    pub strip_trailing_slash: Option<bool>,
+    pub path_patterns: Option<String>,
+    pub time_zone: Option<String>,
+    pub default_range: Option<DateRangePreset>,
+    pub signed_pixel_ids: Option<bool>,
+    pub bounce_rule: Option<BounceRule>,
+    pub bounce_threshold_secs: Option<i64>,
+    pub session_timeout_mins: Option<i64>,
+    /// `Some(None)` clears the service's week start
+    pub week_start: Option<Option<WeekStart>>,
+    pub debug_log: Option<bool>,
+    pub link_users: Option<bool>,
+    pub hide_internal_referrers: Option<bool>,
+    pub track_errors: Option<bool>,
+    pub allowed_countries: Option<String>,
+    pub blocked_countries: Option<String>,
+    pub allowed_asns: Option<String>,
+    pub blocked_asns: Option<String>,
+}
+
+/// A named dashboard view: date range, URL filter and panel layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedView {
+    pub id: SavedViewId,
+    pub service_id: ServiceId,
+    pub name: String,
+    /// Start of the date range as entered (empty for the default range)
+    pub start_date: String,
+    /// End of the date range as entered (empty for "now")
+    pub end_date: String,
+    pub url_pattern: String,
+    pub layout: PanelLayout,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CreateSavedView {
+    pub name: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub url_pattern: String,
+    pub layout: PanelLayout,
+}
+
+/// Most conditions a segment may have
+pub const MAX_SEGMENT_CONDITIONS: usize = 10;
+
+/// A named set of conditions a session must all meet, which narrows stats,
+/// the chart and session lists to the sessions meeting them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub id: SegmentId,
+    pub service_id: ServiceId,
+    pub name: String,
+    pub conditions: Vec<SegmentCondition>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Segment {
+    /// `segment` with the breakdown filters added to its conditions; the
+    /// filters alone make an unsaved segment of the service
+    pub fn with_filters(
+        segment: Option<Self>,
+        service_id: ServiceId,
+        filters: &BreakdownFilters,
+        now: DateTime<Utc>,
+    ) -> Option<Self> {
+        let conditions = filters.conditions();
+        if conditions.is_empty() {
+            return segment;
+        }
+        let mut segment = segment.unwrap_or_else(|| Self {
+            id: SegmentId::new(),
+            service_id,
+            name: String::new(),
+            conditions: Vec::new(),
+            created_at: now,
+        });
+        segment.conditions.extend(conditions);
+        Some(segment)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CreateSegment {
+    pub name: String,
+    pub conditions: Vec<SegmentCondition>,
+}
+
+impl CreateSegment {
+    /// Why the segment can't be saved, if it can't
+    pub fn problem(&self) -> Option<&'static str> {
+        if self.name.trim().is_empty() {
+            Some("Name is required")
+        } else if self.conditions.is_empty() {
+            Some("A segment needs at least one condition")
+        } else if self.conditions.len() > MAX_SEGMENT_CONDITIONS {
+            Some("Too many conditions")
+        } else if self.conditions.iter().any(|c| c.value.trim().is_empty()) {
+            Some("Every condition needs a value")
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -129,12 +562,18 @@ pub struct CreateSession {
     pub device: String,
     pub device_type: DeviceType,
     pub os: String,
+    /// Visitor IP to store in the clear
     pub ip: Option<String>,
+    /// Visitor IP sealed with `ip_encryption_key`, to store instead
+    pub ip_encrypted: Option<String>,
+    /// Keyed hash of the sealed IP, to look it up by
+    pub ip_hash: Option<String>,
     pub asn: String,
     pub country: String,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
     pub time_zone: String,
+    pub environment: Environment,
 }
 
 #[derive(Debug, Clone)]
@@ -147,6 +586,533 @@ pub struct CreateHit {
     pub location: String,
     pub referrer: String,
     pub load_time: Option<f64>,
+    pub environment: Environment,
+    pub prefetched: bool,
+    pub referrer_spam: bool,
+}
+
+/// Writes recorded for a service during one calendar month (UTC)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceUsage {
+    /// `YYYY-MM`
+    pub month: String,
+    pub hits: i64,
+    pub sessions: i64,
+    /// Ingress requests not recorded because the quota was used up
+    pub dropped: i64,
+    /// Repeated page loads the idempotency filter matched to their hit after
+    /// the cache had forgotten their key
+    pub duplicates: i64,
+}
+
+impl ServiceUsage {
+    /// Usage bucket key for the month containing `time`
+    pub fn month_of(time: DateTime<Utc>) -> String {
+        time.format("%Y-%m").to_string()
+    }
+
+    /// Keys for the `count` months ending with the one containing `time`,
+    /// newest first
+    pub fn recent_months(time: DateTime<Utc>, count: u32) -> Vec<String> {
+        let first_of_month = time.with_day(1).unwrap_or(time);
+        (0..count)
+            .filter_map(|i| first_of_month.checked_sub_months(Months::new(i)))
+            .map(Self::month_of)
+            .collect()
+    }
+}
+
+/// Sessions started and hits recorded each day, oldest day first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DailyTrend {
+    pub sessions: Vec<i64>,
+    pub hits: Vec<i64>,
+}
+
+impl DailyTrend {
+    /// `days` days without any sessions or hits
+    pub fn empty(days: usize) -> Self {
+        Self {
+            sessions: vec![0; days],
+            hits: vec![0; days],
+        }
+    }
+}
+
+/// Sessions started and hits recorded per bucket of `bucket_secs`, the
+/// first starting at `start`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimeSeries {
+    pub start: DateTime<Utc>,
+    pub bucket_secs: i64,
+    pub sessions: Vec<i64>,
+    pub hits: Vec<i64>,
+}
+
+impl TimeSeries {
+    /// When the bucket at `index` starts
+    pub fn bucket_start(&self, index: usize) -> DateTime<Utc> {
+        self.start + chrono::Duration::seconds(self.bucket_secs * index as i64)
+    }
+}
+
+/// What a service's public badge shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BadgeCounts {
+    /// Sessions started this calendar month (UTC)
+    pub visitors: i64,
+    pub online: i64,
+}
+
+/// Counters a live subscription to a service gets, of production traffic
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LiveCounters {
+    /// Sessions seen within the service's active user timeout
+    pub online: i64,
+    /// Sessions started today, in the service's time zone
+    pub sessions_today: i64,
+    pub hits_today: i64,
+}
+
+/// Page views of a service ingress turned away for one reason
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DroppedHits {
+    pub reason: IngressDecision,
+    pub count: i64,
+}
+
+/// A JavaScript error reported by a service's tracker
+#[derive(Debug, Clone)]
+pub struct CreatePageError {
+    pub service_id: ServiceId,
+    pub time: DateTime<Utc>,
+    pub environment: Environment,
+    pub location: String,
+    /// Hash of the message and the top of the stack, telling errors apart
+    pub fingerprint: String,
+    pub message: String,
+    pub stack: String,
+}
+
+/// How often an error was reported on a page in a date range
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageError {
+    pub location: String,
+    pub fingerprint: String,
+    /// Message and stack of the latest report
+    pub message: String,
+    pub stack: String,
+    pub count: i64,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// A service's quota settings alongside its current and past monthly usage
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    pub hit_quota: i64,
+    pub quota_behavior: QuotaBehavior,
+    pub current: ServiceUsage,
+    /// Earlier months, newest first
+    pub history: Vec<ServiceUsage>,
+}
+
+impl QuotaUsage {
+    pub fn exceeded(&self) -> bool {
+        self.hit_quota > 0 && self.current.hits >= self.hit_quota
+    }
+
+    /// Share of this month's quota used so far, if the service has one
+    pub fn percent_used(&self) -> Option<i64> {
+        (self.hit_quota > 0).then(|| self.current.hits * 100 / self.hit_quota)
+    }
+}
+
+/// A distribution of sessions, hits or linked users shown as a histogram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionHistogram {
+    /// Seconds from a session's start to its end signal or last heartbeat
+    Duration,
+    /// Hits per session
+    Depth,
+    /// Seconds from an email campaign's first open to each open
+    OpenDelay,
+    /// Sessions per linked user
+    UserSessions,
+    /// Devices per linked user
+    UserDevices,
+}
+
+impl SessionHistogram {
+    /// Exclusive upper bounds of every bucket but the last, which is open-ended
+    pub fn bounds(self) -> &'static [i64] {
+        match self {
+            Self::Duration => &[10, 30, 60, 180, 600, 1800],
+            Self::Depth => &[2, 3, 4, 5, 6, 11],
+            Self::OpenDelay => &[3600, 7200, 14400, 28800, 86400, 172800, 604800],
+            Self::UserSessions => &[2, 3, 4, 5, 6, 11],
+            Self::UserDevices => &[2, 3, 4, 5],
+        }
+    }
+
+    fn labels(self) -> &'static [&'static str] {
+        match self {
+            Self::Duration => &[
+                "<10s", "10–30s", "30s–1m", "1–3m", "3–10m", "10–30m", "30m+",
+            ],
+            Self::Depth | Self::UserSessions => &["1", "2", "3", "4", "5", "6–10", "11+"],
+            Self::OpenDelay => &[
+                "<1h", "1–2h", "2–4h", "4–8h", "8–24h", "1–2d", "2–7d", "7d+",
+            ],
+            Self::UserDevices => &["1", "2", "3", "4", "5+"],
+        }
+    }
+
+    /// Index of the bucket a value falls in
+    pub fn bucket(self, value: i64) -> usize {
+        self.bounds()
+            .iter()
+            .take_while(|&&bound| value >= bound)
+            .count()
+    }
+
+    /// Every bucket in order, adding up the given `(bucket index, count)` pairs
+    pub fn buckets(self, counts: impl IntoIterator<Item = (usize, i64)>) -> Vec<HistogramBucket> {
+        let mut buckets: Vec<HistogramBucket> = self
+            .labels()
+            .iter()
+            .map(|label| HistogramBucket {
+                label: label.to_string(),
+                count: 0,
+            })
+            .collect();
+        for (index, count) in counts {
+            if let Some(bucket) = buckets.get_mut(index) {
+                bucket.count += count;
+            }
+        }
+        buckets
+    }
+}
+
+/// Sessions in one bucket of a histogram
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistogramBucket {
+    /// The bucket's range, e.g. `10–30s`
+    pub label: String,
+    pub count: i64,
+}
+
+/// Sessions linked across devices under hashed login identifiers, kept apart
+/// from the anonymous analytics. Only services that link users have any.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UserStats {
+    /// Users with a session in the range
+    pub users: i64,
+    /// Their sessions in the range
+    pub sessions: i64,
+    /// Users seen on more than one device
+    pub multi_device_users: i64,
+    /// Users per `SessionHistogram::UserSessions` bucket
+    pub sessions_per_user: Vec<HistogramBucket>,
+    /// Users per `SessionHistogram::UserDevices` bucket
+    pub devices_per_user: Vec<HistogramBucket>,
+}
+
+impl UserStats {
+    /// Totals and histograms from `(sessions, devices, users)` rows, each
+    /// counting the users with that many sessions and devices
+    pub fn from_counts(counts: impl IntoIterator<Item = (i64, i64, i64)>) -> Self {
+        let counts: Vec<(i64, i64, i64)> = counts.into_iter().collect();
+        let sessions = SessionHistogram::UserSessions;
+        let devices = SessionHistogram::UserDevices;
+        Self {
+            users: counts.iter().map(|&(_, _, users)| users).sum(),
+            sessions: counts
+                .iter()
+                .map(|&(sessions, _, users)| sessions * users)
+                .sum(),
+            multi_device_users: counts
+                .iter()
+                .filter(|&&(_, devices, _)| devices > 1)
+                .map(|&(_, _, users)| users)
+                .sum(),
+            sessions_per_user: sessions.buckets(
+                counts
+                    .iter()
+                    .map(|&(count, _, users)| (sessions.bucket(count), users)),
+            ),
+            devices_per_user: devices.buckets(
+                counts
+                    .iter()
+                    .map(|&(_, count, users)| (devices.bucket(count), users)),
+            ),
+        }
+    }
+
+    /// Average sessions per user, to one decimal
+    pub fn sessions_per_user_avg(&self) -> f64 {
+        if self.users == 0 {
+            return 0.0;
+        }
+        (self.sessions as f64 / self.users as f64 * 10.0).round() / 10.0
+    }
+}
+
+/// Most weeks of cohorts a retention report has, the range's latest ones
+pub const MAX_COHORT_WEEKS: i64 = 26;
+
+/// Linked users grouped by the week of their first session, with how many of
+/// each week's came back in the weeks after. Only linked users have an
+/// identity that lasts from one week to the next.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Retention {
+    /// Oldest first
+    pub cohorts: Vec<RetentionCohort>,
+}
+
+/// Users first seen in one week
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RetentionCohort {
+    /// First day of the week
+    pub week: NaiveDate,
+    /// Users whose first session was in the week
+    pub users: i64,
+    /// Of them, those with a session 1, 2, ... weeks later, up to the range's
+    /// last week
+    pub returned: Vec<i64>,
+    /// `returned` as shares of `users`, 0.0-1.0
+    pub retention: Vec<f64>,
+}
+
+impl Retention {
+    /// Cohorts of the weeks from `first_week` through `last_week` (first days
+    /// of weeks), from the set of weeks each user had a session in, the
+    /// earliest being the week of their first. Users first seen outside those
+    /// weeks are left out.
+    pub fn from_user_weeks(
+        first_week: NaiveDate,
+        last_week: NaiveDate,
+        users: impl IntoIterator<Item = BTreeSet<NaiveDate>>,
+    ) -> Self {
+        let first_week = first_week.max(last_week - Duration::weeks(MAX_COHORT_WEEKS - 1));
+        let weeks = usize::try_from((last_week - first_week).num_weeks() + 1).unwrap_or_default();
+        let mut cohorts: Vec<RetentionCohort> = (0..weeks)
+            .map(|index| RetentionCohort {
+                week: first_week + Duration::weeks(index as i64),
+                users: 0,
+                returned: vec![0; weeks - index - 1],
+                retention: Vec::new(),
+            })
+            .collect();
+        for seen in users {
+            let Some(&first) = seen.first() else {
+                continue;
+            };
+            let Some(cohort) = usize::try_from((first - first_week).num_weeks())
+                .ok()
+                .filter(|_| first >= first_week)
+                .and_then(|index| cohorts.get_mut(index))
+            else {
+                continue;
+            };
+            cohort.users += 1;
+            for week in seen.iter().skip(1) {
+                let later = (*week - first).num_weeks() as usize;
+                if let Some(returned) = cohort.returned.get_mut(later - 1) {
+                    *returned += 1;
+                }
+            }
+        }
+        for cohort in &mut cohorts {
+            cohort.retention = cohort
+                .returned
+                .iter()
+                .map(|&returned| match cohort.users {
+                    0 => 0.0,
+                    users => returned as f64 / users as f64,
+                })
+                .collect();
+        }
+        Self { cohorts }
+    }
+
+    /// Whether no user was first seen in the cohorts' weeks
+    pub fn is_empty(&self) -> bool {
+        self.cohorts.iter().all(|cohort| cohort.users == 0)
+    }
+
+    /// Weeks after the first that the grid has columns for: 1, 2, ...
+    pub fn later_weeks(&self) -> Vec<usize> {
+        (1..self.cohorts.len()).collect()
+    }
+}
+
+impl RetentionCohort {
+    /// The cohort's row of the retention grid
+    pub fn cells(&self) -> Vec<RetentionCell> {
+        self.returned
+            .iter()
+            .zip(&self.retention)
+            .map(|(&returned, &share)| RetentionCell { returned, share })
+            .collect()
+    }
+}
+
+/// Users of a cohort back in one later week, as the grid shows them
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionCell {
+    pub returned: i64,
+    pub share: f64,
+}
+
+impl RetentionCell {
+    /// The share in whole percent
+    pub fn percent(&self) -> i64 {
+        (self.share * 100.0).round() as i64
+    }
+
+    /// Whether the cell's shade is dark enough for light text
+    pub fn dark(&self) -> bool {
+        self.share > 0.5
+    }
+}
+
+/// Separates the campaign from the recipient in a pixel identifier, as in
+/// `2024-spring:reader@example.com`
+pub const CAMPAIGN_SEPARATOR: char = ':';
+
+/// Opens of an email campaign: pixel hits whose identifier starts with the
+/// campaign and `CAMPAIGN_SEPARATOR`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CampaignSummary {
+    pub campaign: String,
+    pub opens: i64,
+    /// Recipients who opened at least once
+    pub unique_opens: i64,
+    pub first_open: DateTime<Utc>,
+    pub last_open: DateTime<Utc>,
+}
+
+/// Opens of a campaign by one recipient
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CampaignRecipient {
+    /// The identifier after the campaign, e.g. an email address
+    pub recipient: String,
+    pub opens: i64,
+    pub first_open: DateTime<Utc>,
+    pub last_open: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CampaignReport {
+    #[serde(flatten)]
+    pub summary: CampaignSummary,
+    /// Opens by time since the campaign's first open, which stands in for
+    /// when it was sent
+    pub open_delays: Vec<HistogramBucket>,
+    /// Recipients who opened, earliest first
+    pub recipients: Vec<CampaignRecipient>,
+}
+
+/// Longest target URL a tracked link may have
+pub const MAX_LINK_URL_CHARS: usize = 2048;
+/// Longest campaign name a tracked link may have
+pub const MAX_LINK_CAMPAIGN_CHARS: usize = 128;
+/// Longest name a tracked link may have
+pub const MAX_LINK_NAME_CHARS: usize = 128;
+/// Shortest and longest custom token of a short link
+pub const LINK_SLUG_CHARS: RangeInclusive<usize> = 3..=64;
+
+/// A redirect link, e.g. in a newsletter: `/r/:token` counts a click and
+/// sends the visitor on to `target_url`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackedLink {
+    pub id: LinkId,
+    pub service_id: ServiceId,
+    pub token: String,
+    /// Label shown instead of the target, e.g. `Spring flyer`; empty if none
+    pub name: String,
+    /// Campaign the link belongs to, as in pixel identifiers; empty if none
+    pub campaign: String,
+    pub target_url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CreateTrackedLink {
+    pub name: String,
+    /// Custom token for the link's URL; a random one when empty
+    pub slug: String,
+    pub campaign: String,
+    pub target_url: String,
+}
+
+impl CreateTrackedLink {
+    /// Why the link can't be saved, if it can't
+    pub fn problem(&self) -> Option<&'static str> {
+        let target = Url::parse(self.target_url.trim()).ok();
+        if !target.is_some_and(|url| matches!(url.scheme(), "http" | "https")) {
+            Some("The target must be an http or https URL")
+        } else if self.target_url.trim().len() > MAX_LINK_URL_CHARS {
+            Some("The target URL is too long")
+        } else if self.campaign.contains(CAMPAIGN_SEPARATOR) {
+            Some("Campaigns may not contain ':'")
+        } else if self.campaign.trim().len() > MAX_LINK_CAMPAIGN_CHARS {
+            Some("The campaign is too long")
+        } else if self.name.trim().len() > MAX_LINK_NAME_CHARS {
+            Some("The name is too long")
+        } else if !self.slug.is_empty() && !Self::is_valid_slug(&self.slug) {
+            Some("Short links use 3 to 64 letters, digits, '-' or '_'")
+        } else {
+            None
+        }
+    }
+
+    fn is_valid_slug(slug: &str) -> bool {
+        LINK_SLUG_CHARS.contains(&slug.len())
+            && slug
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+}
+
+/// A link click to store
+#[derive(Debug, Clone)]
+pub struct CreateLinkClick {
+    pub link_id: LinkId,
+    /// The visitor's session on the site, if one was open
+    pub session_id: Option<SessionId>,
+    /// Prefix of the session association hash
+    pub visitor: String,
+    pub referrer: String,
+    pub country: String,
+    pub time: DateTime<Utc>,
+}
+
+/// Clicks of a link in a range
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackedLinkStats {
+    #[serde(flatten)]
+    pub link: TrackedLink,
+    pub clicks: i64,
+    /// Distinct visitors who clicked
+    pub unique_clicks: i64,
+    /// Unique clicks per unique open of the link's campaign, when it has one
+    /// that was opened
+    pub click_through_rate: Option<f64>,
+}
+
+/// Where and when a link's clicks in a range came from
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackedLinkReport {
+    #[serde(flatten)]
+    pub stats: TrackedLinkStats,
+    /// Clicks per 24-hour window from the start of the range
+    pub daily_clicks: Vec<i64>,
+    /// Pages the clicks came from, empty for none
+    pub referrers: Vec<CountedItem>,
+    pub countries: Vec<CountedItem>,
 }
 
 #[derive(Debug, Clone, Default, Serialize)]
@@ -159,6 +1125,10 @@ pub struct CoreStats {
     pub avg_session_duration: Option<f64>,
     pub avg_load_time: Option<f64>,
     pub avg_hits_per_session: Option<f64>,
+    /// Sessions per `SessionHistogram::Duration` bucket
+    pub session_durations: Vec<HistogramBucket>,
+    /// Sessions per `SessionHistogram::Depth` bucket
+    pub session_depths: Vec<HistogramBucket>,
     pub locations: Vec<CountedItem>,
     pub referrers: Vec<CountedItem>,
     pub countries: Vec<CountedItem>,
@@ -166,37 +1136,467 @@ pub struct CoreStats {
     pub browsers: Vec<CountedItem>,
     pub devices: Vec<CountedItem>,
     pub device_types: Vec<CountedItem>,
+    /// Country counts summed per continent, filled in alongside the country
+    /// metadata by `geo::countries::localize_stats`
+    #[serde(default)]
+    pub continents: Vec<ContinentCount>,
     pub chart_data: ChartData,
     pub chart_tooltip_format: String,
     pub chart_granularity: String,
+    /// Distinct visitors on the UTC days the range touches, from the visitor
+    /// sketches; `None` when there are none, or under a URL filter
+    pub unique_visitors: Option<i64>,
+    /// Values added by stats hooks, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub compare: Option<Box<CoreStats>>,
+    /// The period `compare` covers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compared_period: Option<ComparedPeriod>,
+}
+
+/// The earlier period stats were compared with, and how it was picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ComparedPeriod {
+    pub mode: CompareMode,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// What fetching a service's site found out about its tracker
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallCheck {
+    /// The site loads this service's tracker and nothing keeps it from running
+    pub installed: bool,
+    /// Kinds of this service's tracker found on the site
+    pub trackers: Vec<TrackerType>,
+    pub issues: Vec<InstallIssue>,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl InstallCheck {
+    /// A check that couldn't look at the site at all
+    pub fn failed(issue: InstallIssue, checked_at: DateTime<Utc>) -> Self {
+        Self {
+            installed: false,
+            trackers: Vec::new(),
+            issues: vec![issue],
+            checked_at,
+        }
+    }
+}
+
+/// Something keeping a service's tracker from working on its site
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum InstallIssue {
+    /// The service has no link to its site
+    NoLink,
+    /// The site couldn't be fetched
+    Unreachable { error: String },
+    /// The site answered with an error status
+    HttpStatus { status: u16 },
+    /// Neither the script nor the pixel is on the page
+    TrackerMissing,
+    /// The page has shymini trackers, but only for other tracking IDs
+    WrongTrackingId { found: Vec<String> },
+    /// An HTTPS page loads the tracker over plain HTTP, which browsers block
+    MixedContent { url: String },
+}
+
+impl InstallIssue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoLink => "no_link",
+            Self::Unreachable { .. } => "unreachable",
+            Self::HttpStatus { .. } => "http_status",
+            Self::TrackerMissing => "tracker_missing",
+            Self::WrongTrackingId { .. } => "wrong_tracking_id",
+            Self::MixedContent { .. } => "mixed_content",
+        }
+    }
+}
+
+/// The last run of a maintenance task
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MaintenanceRun {
+    pub task: MaintenanceTask,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: i64,
+    /// Why the task failed, if it did
+    pub error: Option<String>,
+}
+
+/// One request to a service's site by the uptime monitor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorCheck {
+    pub service_id: ServiceId,
+    pub checked_at: DateTime<Utc>,
+    /// Status of the answer, if there was one
+    pub status_code: Option<u16>,
+    /// Time until the answer's headers arrived
+    pub latency_ms: Option<i64>,
+    /// The site answered without an error status
+    pub up: bool,
+    /// Why there was no answer
+    pub error: Option<String>,
+}
+
+/// A service's uptime over a period
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Uptime {
+    pub checks: i64,
+    pub up_checks: i64,
+    pub avg_latency_ms: Option<f64>,
+    /// The most recent check, from any time
+    pub latest: Option<MonitorCheck>,
+}
+
+impl Uptime {
+    /// Share of the checks that found the site up, in percent to one decimal
+    pub fn percent(&self) -> Option<f64> {
+        (self.checks > 0)
+            .then(|| (self.up_checks as f64 * 1000.0 / self.checks as f64).round() / 10.0)
+    }
+
+    /// The latest check found the site down
+    pub fn is_down(&self) -> bool {
+        self.latest.as_ref().is_some_and(|check| !check.up)
+    }
+}
+
+/// When a service's TLS certificate and domain registration run out, as of
+/// the monitor's last lookup. `None` where there is nothing to run out or the
+/// lookup failed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpiryCheck {
+    pub service_id: ServiceId,
+    pub checked_at: DateTime<Utc>,
+    pub certificate_expires_at: Option<DateTime<Utc>>,
+    pub domain_expires_at: Option<DateTime<Utc>>,
+}
+
+impl ExpiryCheck {
+    /// What runs out within `days` of `now`, or already has, soonest first
+    pub fn warnings(&self, now: DateTime<Utc>, days: i64) -> Vec<ExpiryWarning> {
+        let mut warnings: Vec<ExpiryWarning> = [
+            (ExpiryKind::Certificate, self.certificate_expires_at),
+            (ExpiryKind::Domain, self.domain_expires_at),
+        ]
+        .into_iter()
+        .filter_map(|(kind, expires_at)| {
+            let expires_at = expires_at?;
+            let left = expires_at - now;
+            (left < chrono::Duration::days(days)).then_some(ExpiryWarning {
+                kind,
+                expires_at,
+                days_left: left.num_days().max(0),
+                expired: expires_at <= now,
+            })
+        })
+        .collect();
+        warnings.sort_by_key(|warning| warning.expires_at);
+        warnings
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryKind {
+    Certificate,
+    Domain,
+}
+
+impl ExpiryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Certificate => "certificate",
+            Self::Domain => "domain",
+        }
+    }
+}
+
+/// A certificate or domain about to run out
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpiryWarning {
+    pub kind: ExpiryKind,
+    pub expires_at: DateTime<Utc>,
+    /// Whole days left
+    pub days_left: i64,
+    /// It has already run out
+    pub expired: bool,
+}
+
+/// What a dashboard search result is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchResultKind {
+    Service,
+    Page,
+    Referrer,
+    Session,
+}
+
+impl SearchResultKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Service => "service",
+            Self::Page => "page",
+            Self::Referrer => "referrer",
+            Self::Session => "session",
+        }
+    }
+}
+
+/// Something in one of an organization's services matching a dashboard search
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResult {
+    pub kind: SearchResultKind,
+    pub service_id: ServiceId,
+    pub service_name: String,
+    /// The matching service name, page path, referrer domain or session
+    /// identifier
+    pub value: String,
+    /// The session of a session result
+    pub session_id: Option<SessionId>,
+}
+
+impl SearchResult {
+    /// Dashboard page showing the result: pages open the service dashboard
+    /// filtered to them, sessions their detail page
+    pub fn link(&self) -> String {
+        match (self.kind, self.session_id) {
+            (SearchResultKind::Page, _) => {
+                let pattern: String =
+                    url::form_urlencoded::byte_serialize(regex::escape(&self.value).as_bytes())
+                        .collect();
+                format!("/service/{}?urlPattern={}", self.service_id, pattern)
+            }
+            (SearchResultKind::Session, Some(session_id)) => {
+                format!("/service/{}/sessions/{}", self.service_id, session_id)
+            }
+            _ => format!("/service/{}", self.service_id),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::Utc;
+    use chrono::{TimeZone, Utc};
+    use proptest::prelude::*;
     use uuid::Uuid;
 
+    #[test]
+    fn test_uptime_percent() {
+        let mut uptime = Uptime::default();
+        assert_eq!(uptime.percent(), None);
+        assert!(!uptime.is_down());
+
+        uptime.checks = 3;
+        uptime.up_checks = 2;
+        assert_eq!(uptime.percent(), Some(66.7));
+        uptime.up_checks = 3;
+        assert_eq!(uptime.percent(), Some(100.0));
+    }
+
+    #[test]
+    fn test_expiry_warnings() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let mut check = ExpiryCheck {
+            service_id: ServiceId(Uuid::new_v4()),
+            checked_at: now,
+            certificate_expires_at: Some(now + chrono::Duration::days(90)),
+            domain_expires_at: None,
+        };
+        assert!(check.warnings(now, 14).is_empty());
+
+        check.certificate_expires_at = Some(now + chrono::Duration::hours(10 * 24 + 5));
+        check.domain_expires_at = Some(now - chrono::Duration::hours(1));
+        let warnings = check.warnings(now, 14);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].kind, ExpiryKind::Domain);
+        assert!(warnings[0].expired);
+        assert_eq!(warnings[0].days_left, 0);
+        assert_eq!(warnings[1].kind, ExpiryKind::Certificate);
+        assert!(!warnings[1].expired);
+        assert_eq!(warnings[1].days_left, 10);
+    }
+
     fn test_service() -> Service {
         Service {
             id: ServiceId(Uuid::new_v4()),
+            organization_id: OrganizationId::DEFAULT,
             tracking_id: TrackingId("abc12345".to_string()),
             name: "Test Service".to_string(),
             link: "https://example.com".to_string(),
             origins: "*".to_string(),
             status: ServiceStatus::Active,
             respect_dnt: true,
+            respect_gpc: true,
             ignore_robots: false,
             collect_ips: true,
             ignored_ips: "".to_string(),
             hide_referrer_regex: "".to_string(),
             script_inject: "".to_string(),
             created_at: Utc::now(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: QuotaBehavior::Keep,
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: Service::DEFAULT_IDLE_TIMEOUT_MINS,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: DateRangePreset::default(),
+            signed_pixel_ids: false,
+            bounce_rule: BounceRule::SingleHit,
+            bounce_threshold_secs: Service::DEFAULT_BOUNCE_THRESHOLD_SECS,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: true,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
         }
     }
 
+    #[test]
+    fn test_service_quota_exceeded() {
+        let mut service = test_service();
+        assert!(!service.quota_exceeded(1_000_000));
+
+        service.hit_quota = 100;
+        assert!(!service.quota_exceeded(99));
+        assert!(service.quota_exceeded(100));
+    }
+
+    #[test]
+    fn test_service_idle_timeout_ms() {
+        let mut service = test_service();
+        assert_eq!(service.idle_timeout_ms(), 30 * 60_000);
+
+        service.idle_timeout_mins = 0;
+        assert_eq!(service.idle_timeout_ms(), 0);
+        service.idle_timeout_mins = -5;
+        assert_eq!(service.idle_timeout_ms(), 0);
+    }
+
+    #[test]
+    fn test_service_session_timeout() {
+        let mut service = test_service();
+        assert_eq!(service.session_timeout(), None);
+
+        service.session_timeout_mins = 30;
+        assert_eq!(service.session_timeout(), Some(Duration::minutes(30)));
+        service.session_timeout_mins = -5;
+        assert_eq!(service.session_timeout(), None);
+    }
+
+    #[test]
+    fn test_organization_quota_exceeded() {
+        let mut org = Organization {
+            id: OrganizationId::new(),
+            name: "Acme".to_string(),
+            slug: "acme".to_string(),
+            hit_quota: 0,
+            quota_behavior: QuotaBehavior::Drop,
+            logo_url: String::new(),
+            accent_color: String::new(),
+            theme_mode: None,
+            created_at: Utc::now(),
+        };
+        assert!(!org.quota_exceeded(1_000_000));
+
+        org.hit_quota = 10;
+        assert!(!org.quota_exceeded(9));
+        assert!(org.quota_exceeded(10));
+    }
+
+    #[test]
+    fn test_organization_slugify() {
+        assert_eq!(Organization::slugify("Acme Corp."), "acme-corp");
+        assert_eq!(Organization::slugify("  Über -- Café 2 "), "ber-caf-2");
+        assert_eq!(Organization::slugify("!!!"), "org");
+        assert_eq!(Organization::slugify(&"a-".repeat(40)).len(), 47);
+    }
+
+    #[test]
+    fn test_secrets_are_not_serialized() {
+        let user = User {
+            id: UserId::new(),
+            email: "a@example.com".to_string(),
+            name: "A".to_string(),
+            password_hash: "$argon2id$secret".to_string(),
+            email_verified_at: None,
+            totp_secret: Some("SECRETBASE32".to_string()),
+            created_at: Utc::now(),
+        };
+        let json = serde_json::to_string(&user).unwrap();
+        assert!(json.contains("a@example.com"));
+        assert!(!json.contains("secret"));
+        assert!(!json.contains("SECRETBASE32"));
+
+        let token = ApiToken {
+            id: ApiTokenId::new(),
+            organization_id: OrganizationId::DEFAULT,
+            name: "ci".to_string(),
+            token_hash: "deadbeef".to_string(),
+            role: Role::Viewer,
+            user_id: None,
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        let json = serde_json::to_string(&token).unwrap();
+        assert!(!json.contains("deadbeef"));
+        assert!(json.contains(r#""role":"viewer""#));
+    }
+
+    #[test]
+    fn test_usage_months() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+        assert_eq!(ServiceUsage::month_of(time), "2024-03");
+        assert_eq!(
+            ServiceUsage::recent_months(time, 4),
+            vec!["2024-03", "2024-02", "2024-01", "2023-12"]
+        );
+    }
+
+    #[test]
+    fn test_quota_usage() {
+        let mut usage = QuotaUsage {
+            hit_quota: 0,
+            quota_behavior: QuotaBehavior::Drop,
+            current: ServiceUsage {
+                month: "2024-03".to_string(),
+                hits: 150,
+                sessions: 40,
+                dropped: 0,
+                duplicates: 0,
+            },
+            history: Vec::new(),
+        };
+        assert!(!usage.exceeded());
+        assert_eq!(usage.percent_used(), None);
+
+        usage.hit_quota = 200;
+        assert!(!usage.exceeded());
+        assert_eq!(usage.percent_used(), Some(75));
+
+        usage.hit_quota = 100;
+        assert!(usage.exceeded());
+        assert_eq!(usage.percent_used(), Some(150));
+    }
+
     #[test]
     fn test_service_is_origin_allowed_wildcard() {
         let service = test_service();
@@ -291,12 +1691,16 @@ mod tests {
             device_type: DeviceType::Desktop,
             os: "Windows 10".to_string(),
             ip: Some("192.168.1.1".to_string()),
+            ip_encrypted: None,
             asn: "".to_string(),
             country: "US".to_string(),
             longitude: Some(-122.0),
             latitude: Some(37.0),
             time_zone: "America/Los_Angeles".to_string(),
             is_bounce: true,
+            ended_at: None,
+            environment: Environment::Production,
+            props: BTreeMap::new(),
         };
 
         assert_eq!(session.browser, "Chrome");
@@ -318,6 +1722,9 @@ mod tests {
             location: "/home".to_string(),
             referrer: "https://google.com".to_string(),
             load_time: Some(150.5),
+            environment: Environment::Production,
+            prefetched: false,
+            referrer_spam: false,
         };
 
         assert!(hit.initial);
@@ -333,6 +1740,7 @@ mod tests {
         assert!(create.link.is_empty());
         assert!(create.origins.is_empty());
         assert!(!create.respect_dnt);
+        assert!(!create.respect_gpc);
         assert!(!create.ignore_robots);
         assert!(!create.collect_ips);
     }
@@ -371,11 +1779,14 @@ mod tests {
             device_type: DeviceType::Desktop,
             os: "Linux".to_string(),
             ip: None,
+            ip_encrypted: None,
+            ip_hash: None,
             asn: "".to_string(),
             country: "".to_string(),
             longitude: None,
             latitude: None,
             time_zone: "".to_string(),
+            environment: Environment::Production,
         };
 
         assert_eq!(create.identifier, "user123");
@@ -394,10 +1805,179 @@ mod tests {
             location: "/about".to_string(),
             referrer: "".to_string(),
             load_time: None,
+            environment: Environment::Staging,
+            prefetched: false,
+            referrer_spam: false,
         };
 
         assert!(!create.initial);
         assert_eq!(create.tracker, TrackerType::Pixel);
         assert!(create.load_time.is_none());
     }
+
+    #[test]
+    fn test_search_result_link() {
+        let service_id = ServiceId(Uuid::nil());
+        let mut result = SearchResult {
+            kind: SearchResultKind::Service,
+            service_id,
+            service_name: "Blog".to_string(),
+            value: "Blog".to_string(),
+            session_id: None,
+        };
+        assert_eq!(result.link(), format!("/service/{}", service_id));
+
+        result.kind = SearchResultKind::Page;
+        result.value = "/posts/a.html".to_string();
+        assert_eq!(
+            result.link(),
+            format!("/service/{}?urlPattern=%2Fposts%2Fa%5C.html", service_id)
+        );
+
+        let session_id = SessionId(Uuid::nil());
+        result.kind = SearchResultKind::Session;
+        result.session_id = Some(session_id);
+        assert_eq!(
+            result.link(),
+            format!("/service/{}/sessions/{}", service_id, session_id)
+        );
+    }
+
+    #[test]
+    fn test_session_histogram_buckets() {
+        let durations = SessionHistogram::Duration;
+        assert_eq!(durations.bucket(0), 0);
+        assert_eq!(durations.bucket(9), 0);
+        assert_eq!(durations.bucket(10), 1);
+        assert_eq!(durations.bucket(59), 2);
+        assert_eq!(durations.bucket(1800), 6);
+        assert_eq!(durations.bucket(86_400), 6);
+        assert_eq!(SessionHistogram::Depth.bucket(1), 0);
+        assert_eq!(SessionHistogram::Depth.bucket(10), 5);
+        assert_eq!(SessionHistogram::OpenDelay.bucket(3599), 0);
+        assert_eq!(SessionHistogram::OpenDelay.bucket(7200), 2);
+        assert_eq!(SessionHistogram::OpenDelay.bucket(700_000), 7);
+
+        let buckets = SessionHistogram::Depth.buckets([(0, 3), (5, 1), (0, 2), (99, 4)]);
+        assert_eq!(buckets.len(), SessionHistogram::Depth.bounds().len() + 1);
+        assert_eq!((buckets[0].label.as_str(), buckets[0].count), ("1", 5));
+        assert_eq!((buckets[5].label.as_str(), buckets[5].count), ("6–10", 1));
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<i64>(), 6);
+    }
+
+    #[test]
+    fn test_retention_from_user_weeks() {
+        let week = |day: u32| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+        let weeks = |days: &[u32]| days.iter().map(|&day| week(day)).collect::<BTreeSet<_>>();
+        let retention = Retention::from_user_weeks(
+            week(1),
+            week(22),
+            [
+                weeks(&[1, 8, 22]),
+                weeks(&[1, 15]),
+                weeks(&[1]),
+                weeks(&[8, 15]),
+                // First seen before the report's weeks
+                BTreeSet::from([week(1) - Duration::weeks(1), week(8)]),
+            ],
+        );
+        assert!(!retention.is_empty());
+        assert_eq!(retention.later_weeks(), [1, 2, 3]);
+        let cohorts: Vec<(NaiveDate, i64, &[i64])> = retention
+            .cohorts
+            .iter()
+            .map(|cohort| (cohort.week, cohort.users, cohort.returned.as_slice()))
+            .collect();
+        assert_eq!(
+            cohorts,
+            [
+                (week(1), 3, &[1, 1, 1][..]),
+                (week(8), 1, &[1, 0][..]),
+                (week(15), 0, &[0][..]),
+                (week(22), 0, &[][..]),
+            ]
+        );
+        assert_eq!(retention.cohorts[0].cells()[0].percent(), 33);
+        assert_eq!(retention.cohorts[2].retention, [0.0]);
+
+        // Long ranges keep their latest weeks
+        let long = Retention::from_user_weeks(week(1) - Duration::weeks(100), week(1), []);
+        assert!(long.is_empty());
+        assert_eq!(long.cohorts.len(), MAX_COHORT_WEEKS as usize);
+        assert_eq!(long.cohorts.last().unwrap().week, week(1));
+    }
+
+    #[test]
+    fn test_user_stats_from_counts() {
+        let stats = UserStats::from_counts([(1, 1, 4), (3, 2, 2), (12, 5, 1)]);
+        assert_eq!(stats.users, 7);
+        assert_eq!(stats.sessions, 4 + 6 + 12);
+        assert_eq!(stats.multi_device_users, 3);
+        assert_eq!(stats.sessions_per_user_avg(), 3.1);
+        let counts =
+            |buckets: &[HistogramBucket]| buckets.iter().map(|b| b.count).collect::<Vec<_>>();
+        assert_eq!(counts(&stats.sessions_per_user), [4, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(counts(&stats.devices_per_user), [4, 2, 0, 0, 1]);
+
+        let empty = UserStats::from_counts([]);
+        assert_eq!(empty.users, 0);
+        assert_eq!(empty.sessions_per_user_avg(), 0.0);
+        assert_eq!(empty.devices_per_user.len(), 5);
+    }
+
+    #[test]
+    fn test_tracked_link_problem() {
+        let link = |target_url: &str, campaign: &str| CreateTrackedLink {
+            campaign: campaign.to_string(),
+            target_url: target_url.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(link("https://example.com/sale", "spring").problem(), None);
+        assert_eq!(link("http://example.com", "").problem(), None);
+        assert!(link("javascript:alert(1)", "").problem().is_some());
+        assert!(link("example.com", "").problem().is_some());
+        assert!(link("https://example.com", "spring:a").problem().is_some());
+        let long = format!("https://example.com/{}", "a".repeat(MAX_LINK_URL_CHARS));
+        assert!(link(&long, "").problem().is_some());
+
+        let short = |slug: &str| CreateTrackedLink {
+            slug: slug.to_string(),
+            ..link("https://example.com", "")
+        };
+        assert_eq!(short("spring-flyer_2").problem(), None);
+        assert!(short("ab").problem().is_some());
+        assert!(short("spring flyer").problem().is_some());
+        assert!(short("spring/flyer").problem().is_some());
+        assert!(short(&"a".repeat(65)).problem().is_some());
+        let named = CreateTrackedLink {
+            name: "n".repeat(MAX_LINK_NAME_CHARS + 1),
+            ..link("https://example.com", "")
+        };
+        assert!(named.problem().is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_listed_origins_allowed_in_any_case(
+            origins in proptest::collection::vec("https?://[a-z0-9.-]{1,20}(:[0-9]{1,5})?", 1..5),
+            pick in any::<proptest::sample::Index>(),
+            upper in any::<bool>(),
+        ) {
+            let mut service = test_service();
+            service.origins = origins.join(", ");
+            let origin = pick.get(&origins);
+            let origin = if upper { origin.to_uppercase() } else { origin.clone() };
+            prop_assert!(service.is_origin_allowed(&origin));
+            let evil = format!("{}/evil", origin);
+            prop_assert!(!service.is_origin_allowed(&evil));
+        }
+
+        #[test]
+        fn prop_origin_check_never_panics(origins in any::<String>(), origin in any::<String>()) {
+            let mut service = test_service();
+            service.origins = origins;
+            service.is_origin_allowed(&origin);
+            prop_assert!(!service.get_origins_list().is_empty());
+        }
+    }
 }
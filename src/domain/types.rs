@@ -1,8 +1,12 @@
-use chrono::Utc;
+use chrono::{Datelike, Utc};
 use rand::Rng;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::net::IpAddr;
+use url::Url;
 use uuid::Uuid;
 
 /// Short alphanumeric tracking ID for use in tracker URLs
@@ -120,6 +124,215 @@ impl std::str::FromStr for SessionId {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SavedViewId(pub Uuid);
+
+impl SavedViewId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for SavedViewId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for SavedViewId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for SavedViewId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SegmentId(pub Uuid);
+
+impl SegmentId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for SegmentId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for SegmentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for SegmentId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LinkId(pub Uuid);
+
+impl LinkId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for LinkId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for LinkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for LinkId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OrganizationId(pub Uuid);
+
+impl OrganizationId {
+    /// The organization every service belongs to unless placed elsewhere.
+    /// Created by the migrations, so it always exists.
+    pub const DEFAULT: Self = Self(Uuid::nil());
+
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+/// The default organization, unlike the other IDs whose default is a fresh one
+impl Default for OrganizationId {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for OrganizationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for OrganizationId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UserId(pub Uuid);
+
+impl UserId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for UserId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for UserId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ApiTokenId(pub Uuid);
+
+impl ApiTokenId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for ApiTokenId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for ApiTokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for ApiTokenId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct HitId(pub i64);
@@ -184,120 +397,1611 @@ impl DeviceType {
         }
     }
 
-    #[allow(clippy::should_implement_trait)]
-    pub fn from_str(s: &str) -> Self {
-        match s.to_uppercase().as_str() {
-            "PHONE" => Self::Phone,
-            "TABLET" => Self::Tablet,
-            "DESKTOP" => Self::Desktop,
-            "ROBOT" => Self::Robot,
-            _ => Self::Other,
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s.to_uppercase().as_str() {
+            "PHONE" => Self::Phone,
+            "TABLET" => Self::Tablet,
+            "DESKTOP" => Self::Desktop,
+            "ROBOT" => Self::Robot,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Phone => write!(f, "Phone"),
+            Self::Tablet => write!(f, "Tablet"),
+            Self::Desktop => write!(f, "Desktop"),
+            Self::Robot => write!(f, "Robot"),
+            Self::Other => write!(f, "Other"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrackerType {
+    Js,
+    Pixel,
+}
+
+impl TrackerType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Js => "JS",
+            Self::Pixel => "PIXEL",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s.to_uppercase().as_str() {
+            "JS" => Self::Js,
+            _ => Self::Pixel,
+        }
+    }
+}
+
+impl fmt::Display for TrackerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Js => write!(f, "JavaScript"),
+            Self::Pixel => write!(f, "Pixel"),
+        }
+    }
+}
+
+/// What ingress makes of a page view, as a service's debug log and
+/// `validate_hit` show it and its dropped hits are counted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngressDecision {
+    /// Recorded
+    Accepted,
+    /// The service is archived, so ingress answers as if it didn't exist
+    ServiceArchived,
+    /// The request came from an origin the service doesn't allow
+    OriginRejected,
+    /// The browser sent Do Not Track and the service respects it
+    DroppedDnt,
+    /// The browser sent Global Privacy Control and the service respects it
+    DroppedGpc,
+    /// The browser was excluded through `/exclude-me`
+    DroppedExcluded,
+    /// A pixel identifier came without a valid signature
+    DroppedUnsigned,
+    /// The page was prefetched and the server leaves prefetches out
+    DroppedPrefetch,
+    /// The visitor's address is among the service's ignored IPs
+    DroppedIp,
+    /// The service's ingest script or an ingress hook dropped it
+    DroppedScript,
+    /// The visitor is a bot and the service ignores robots
+    DroppedBot,
+    /// The visitor's country or network is excluded by the service's rules
+    DroppedGeo,
+    /// The visitor claimed to be a search engine's crawler, but DNS showed
+    /// its address isn't the search engine's
+    DroppedFakeBot,
+    /// The referrer is on the referrer spam list
+    DroppedSpam,
+    /// The hit quota is used up
+    DroppedQuota,
+    /// The page load repeats one already recorded
+    DroppedDuplicate,
+}
+
+impl IngressDecision {
+    pub const ALL: [Self; 16] = [
+        Self::Accepted,
+        Self::ServiceArchived,
+        Self::OriginRejected,
+        Self::DroppedDnt,
+        Self::DroppedGpc,
+        Self::DroppedExcluded,
+        Self::DroppedUnsigned,
+        Self::DroppedPrefetch,
+        Self::DroppedIp,
+        Self::DroppedScript,
+        Self::DroppedBot,
+        Self::DroppedGeo,
+        Self::DroppedFakeBot,
+        Self::DroppedSpam,
+        Self::DroppedQuota,
+        Self::DroppedDuplicate,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::ServiceArchived => "service_archived",
+            Self::OriginRejected => "origin_rejected",
+            Self::DroppedDnt => "dropped_dnt",
+            Self::DroppedGpc => "dropped_gpc",
+            Self::DroppedExcluded => "dropped_excluded",
+            Self::DroppedUnsigned => "dropped_unsigned",
+            Self::DroppedPrefetch => "dropped_prefetch",
+            Self::DroppedIp => "dropped_ip",
+            Self::DroppedScript => "dropped_script",
+            Self::DroppedBot => "dropped_bot",
+            Self::DroppedGeo => "dropped_geo",
+            Self::DroppedFakeBot => "dropped_fake_bot",
+            Self::DroppedSpam => "dropped_spam",
+            Self::DroppedQuota => "dropped_quota",
+            Self::DroppedDuplicate => "dropped_duplicate",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.as_str() == s.trim())
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        *self == Self::Accepted
+    }
+}
+
+/// Deployment a hit came from, so traffic from development and staging copies
+/// of a site can be kept out of its production stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Environment {
+    #[default]
+    Production,
+    Staging,
+    Dev,
+}
+
+impl Environment {
+    pub const ALL: [Self; 3] = [Self::Production, Self::Staging, Self::Dev];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Production => "production",
+            Self::Staging => "staging",
+            Self::Dev => "dev",
+        }
+    }
+
+    /// Parse an environment name, accepting common aliases (`prod`,
+    /// `stage`, `development`, ...)
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "production" | "prod" => Some(Self::Production),
+            "staging" | "stage" => Some(Self::Staging),
+            "dev" | "development" | "local" => Some(Self::Dev),
+            _ => None,
+        }
+    }
+
+    /// Guess the environment of a site from its host name: loopback and
+    /// private addresses and local-only domains are `Dev`, hosts with a
+    /// `staging` label (or a `-staging` suffix on one) are `Staging`,
+    /// anything else `Production`
+    pub fn detect(host: &str) -> Self {
+        let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            let local = match ip {
+                IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_unspecified(),
+                IpAddr::V6(ip) => ip.is_loopback() || ip.is_unspecified(),
+            };
+            return if local { Self::Dev } else { Self::Production };
+        }
+
+        let labels: Vec<&str> = host.split('.').collect();
+        let tld = labels.last().copied().unwrap_or_default();
+        if labels.len() == 1 || matches!(tld, "localhost" | "local" | "test" | "internal") {
+            return Self::Dev;
+        }
+        let staging = labels.iter().any(|label| {
+            matches!(*label, "staging" | "stage" | "stg")
+                || label.ends_with("-staging")
+                || label.starts_with("staging-")
+        });
+        if staging {
+            Self::Staging
+        } else {
+            Self::Production
+        }
+    }
+
+    /// Environment of the site an origin (`https://host:port`) belongs to
+    pub fn detect_origin(origin: &str) -> Self {
+        Url::parse(origin)
+            .ok()
+            .and_then(|url| url.host_str().map(Self::detect))
+            .unwrap_or_default()
+    }
+
+    /// Parse a stats filter: no value means production only, `all` every
+    /// environment (`None`), anything else that environment
+    pub fn parse_filter(s: Option<&str>) -> Option<Self> {
+        match s.map(str::trim) {
+            None | Some("") => Some(Self::Production),
+            Some(s) if s.eq_ignore_ascii_case("all") => None,
+            Some(s) => Some(Self::from_str(s).unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Production => write!(f, "Production"),
+            Self::Staging => write!(f, "Staging"),
+            Self::Dev => write!(f, "Development"),
+        }
+    }
+}
+
+/// A date range ending now, which reports cover unless the request gives
+/// dates of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum DateRangePreset {
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[default]
+    #[serde(rename = "30d")]
+    Month,
+    #[serde(rename = "90d")]
+    Quarter,
+    #[serde(rename = "365d")]
+    Year,
+}
+
+impl DateRangePreset {
+    pub const ALL: [Self; 5] = [
+        Self::Day,
+        Self::Week,
+        Self::Month,
+        Self::Quarter,
+        Self::Year,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "24h",
+            Self::Week => "7d",
+            Self::Month => "30d",
+            Self::Quarter => "90d",
+            Self::Year => "365d",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.as_str() == s.trim())
+    }
+
+    /// How far back the range starts
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            Self::Day => chrono::Duration::hours(24),
+            Self::Week => chrono::Duration::days(7),
+            Self::Month => chrono::Duration::days(30),
+            Self::Quarter => chrono::Duration::days(90),
+            Self::Year => chrono::Duration::days(365),
+        }
+    }
+}
+
+/// The earlier period stats are compared with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareMode {
+    /// The same length of time right before the range
+    #[default]
+    PreviousPeriod,
+    /// The same dates a month before, or as many months as the range needs
+    /// not to overlap itself
+    LastMonth,
+    /// The same weekdays a week before, or as many whole weeks as the range
+    /// needs not to overlap itself
+    LastWeek,
+}
+
+impl CompareMode {
+    pub const ALL: [Self; 3] = [Self::PreviousPeriod, Self::LastMonth, Self::LastWeek];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PreviousPeriod => "previous_period",
+            Self::LastMonth => "last_month",
+            Self::LastWeek => "last_week",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+
+    /// The (start, end) the range from `start` to `end` is compared with
+    pub fn period(
+        &self,
+        start: chrono::DateTime<Utc>,
+        end: chrono::DateTime<Utc>,
+    ) -> (chrono::DateTime<Utc>, chrono::DateTime<Utc>) {
+        let duration = end - start;
+        match self {
+            Self::PreviousPeriod => (start - duration, start),
+            Self::LastWeek => {
+                let week = chrono::Duration::weeks(1);
+                let weeks =
+                    ((duration.num_seconds() + week.num_seconds() - 1) / week.num_seconds()).max(1);
+                let shift = week * weeks as i32;
+                (start - shift, end - shift)
+            }
+            Self::LastMonth => {
+                let months_back = |months| {
+                    let months = chrono::Months::new(months);
+                    (
+                        start.checked_sub_months(months).unwrap_or(start),
+                        end.checked_sub_months(months).unwrap_or(start),
+                    )
+                };
+                let mut months = 1;
+                // A month can be as short as 28 days
+                while months_back(months).1 > start && months < 12 * 100 {
+                    months += 1;
+                }
+                months_back(months)
+            }
+        }
+    }
+}
+
+/// Stats narrowed to the sessions whose property `key` is `value`, given as
+/// `key:value` (e.g. `plan:pro`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionPropFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl SessionPropFilter {
+    /// Parse `key:value`; the value may contain further colons
+    pub fn parse(s: &str) -> Option<Self> {
+        let (key, value) = s.split_once(':')?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            return None;
+        }
+        Some(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    pub fn matches(&self, props: &BTreeMap<String, String>) -> bool {
+        props.get(&self.key) == Some(&self.value)
+    }
+}
+
+/// What a segment condition looks at: a property of the session, its entry
+/// referrer, or the pages it viewed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentField {
+    Country,
+    Device,
+    Browser,
+    Os,
+    Referrer,
+    Page,
+}
+
+impl SegmentField {
+    pub const ALL: [Self; 6] = [
+        Self::Country,
+        Self::Device,
+        Self::Browser,
+        Self::Os,
+        Self::Referrer,
+        Self::Page,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Country => "country",
+            Self::Device => "device",
+            Self::Browser => "browser",
+            Self::Os => "os",
+            Self::Referrer => "referrer",
+            Self::Page => "page",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|field| field.as_str() == s.trim())
+    }
+}
+
+/// How a segment condition compares its field with its value; `Contains`
+/// ignores case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentOp {
+    Is,
+    IsNot,
+    Contains,
+}
+
+impl SegmentOp {
+    pub const ALL: [Self; 3] = [Self::Is, Self::IsNot, Self::Contains];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Is => "is",
+            Self::IsNot => "is_not",
+            Self::Contains => "contains",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|op| op.as_str() == s.trim())
+    }
+}
+
+/// One condition of a segment, e.g. country is DE
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentCondition {
+    pub field: SegmentField,
+    pub op: SegmentOp,
+    pub value: String,
+}
+
+impl SegmentCondition {
+    /// The value as stored in the field's column: country codes and device
+    /// types are upper case there
+    pub fn column_value(&self) -> String {
+        let value = self.value.trim();
+        match self.field {
+            SegmentField::Country => value.to_ascii_uppercase(),
+            SegmentField::Device if self.op != SegmentOp::Contains => {
+                DeviceType::from_str(value).as_str().to_string()
+            }
+            _ => value.to_string(),
+        }
+    }
+}
+
+/// Stats narrowed to one value of each breakdown given, e.g.
+/// `?country=DE&deviceType=PHONE`, so a row of a breakdown can be drilled
+/// into without saving a segment. Each is a segment condition with `Is`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct BreakdownFilters {
+    pub country: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    #[serde(rename = "deviceType")]
+    pub device_type: Option<String>,
+    /// The referrer sessions arrived with
+    pub referrer: Option<String>,
+    /// A page sessions viewed
+    pub page: Option<String>,
+}
+
+impl BreakdownFilters {
+    /// Each filter's field, query parameter and value, empty when unset
+    pub fn entries(&self) -> [(SegmentField, &'static str, &str); 6] {
+        fn value(value: &Option<String>) -> &str {
+            value.as_deref().map_or("", str::trim)
+        }
+        [
+            (SegmentField::Country, "country", value(&self.country)),
+            (SegmentField::Browser, "browser", value(&self.browser)),
+            (SegmentField::Os, "os", value(&self.os)),
+            (SegmentField::Device, "deviceType", value(&self.device_type)),
+            (SegmentField::Referrer, "referrer", value(&self.referrer)),
+            (SegmentField::Page, "page", value(&self.page)),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().iter().all(|(_, _, value)| value.is_empty())
+    }
+
+    /// The filters given, as segment conditions; empty values are ignored
+    pub fn conditions(&self) -> Vec<SegmentCondition> {
+        self.entries()
+            .into_iter()
+            .filter(|(_, _, value)| !value.is_empty())
+            .map(|(field, _, value)| SegmentCondition {
+                field,
+                op: SegmentOp::Is,
+                value: value.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// What ingress does with new traffic once a service or its organization has
+/// used up its monthly hit quota. Ordered from most to least permissive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaBehavior {
+    /// Keep recording everything; the quota is informational only
+    #[default]
+    Keep,
+    /// Record a fixed share of visitors (see `quota_sample_rate`)
+    Sample,
+    /// Stop recording until the next month
+    Drop,
+}
+
+impl QuotaBehavior {
+    pub const ALL: [Self; 3] = [Self::Keep, Self::Sample, Self::Drop];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Sample => "sample",
+            Self::Drop => "drop",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|b| b.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+impl fmt::Display for QuotaBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keep => write!(f, "Keep recording"),
+            Self::Sample => write!(f, "Sample"),
+            Self::Drop => write!(f, "Drop"),
+        }
+    }
+}
+
+/// When a service counts a session as a bounce. A session that viewed more
+/// than one page never is; the rules differ on single-page sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BounceRule {
+    /// Every single-page session
+    #[default]
+    SingleHit,
+    /// Single-page sessions engaged for less than the service's threshold
+    EngagedTime,
+    /// Single-page sessions without a scroll, click, tap or key press
+    NoInteraction,
+}
+
+impl BounceRule {
+    pub const ALL: [Self; 3] = [Self::SingleHit, Self::EngagedTime, Self::NoInteraction];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::SingleHit => "single_hit",
+            Self::EngagedTime => "engaged_time",
+            Self::NoInteraction => "no_interaction",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|r| r.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+
+    /// Whether a session with `hits` page views, `engaged_secs` between its
+    /// first and last activity and, if `interacted`, some interaction is a
+    /// bounce; `threshold_secs` is the shortest engaged time that isn't
+    pub fn is_bounce(
+        &self,
+        hits: i64,
+        engaged_secs: i64,
+        threshold_secs: i64,
+        interacted: bool,
+    ) -> bool {
+        hits <= 1
+            && match self {
+                Self::SingleHit => true,
+                Self::EngagedTime => engaged_secs < threshold_secs,
+                Self::NoInteraction => !interacted,
+            }
+    }
+}
+
+/// What ingress does with hits from pages the browser prefetches or
+/// prerenders before (or without) the visitor opening them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefetchBehavior {
+    /// Don't record them
+    #[default]
+    Drop,
+    /// Record them, marked as prefetched
+    Flag,
+}
+
+/// What ingress does with page views referred from a referrer spam domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferrerSpamBehavior {
+    /// Don't record them
+    #[default]
+    Drop,
+    /// Record them, marked as spam
+    Flag,
+}
+
+/// The day weeks start on in calendars and weekly numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeekStart {
+    Monday,
+    Sunday,
+}
+
+impl WeekStart {
+    pub const ALL: [Self; 2] = [Self::Monday, Self::Sunday];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Monday => "monday",
+            Self::Sunday => "sunday",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|w| w.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+
+    /// The first day of the week `date` is in
+    pub fn week_of(&self, date: chrono::NaiveDate) -> chrono::NaiveDate {
+        let into_week = match self {
+            Self::Monday => date.weekday().num_days_from_monday(),
+            Self::Sunday => date.weekday().num_days_from_sunday(),
+        };
+        date - chrono::Duration::days(into_week as i64)
+    }
+
+    /// Number of the week starting on `week`: ISO 8601's for Monday weeks,
+    /// and for Sunday weeks the US one, where week 1 has January 1st
+    pub fn week_number(&self, week: chrono::NaiveDate) -> u32 {
+        match self {
+            Self::Monday => week.iso_week().week(),
+            Self::Sunday => {
+                let year = (week + chrono::Duration::days(6)).year();
+                let first = self.week_of(
+                    chrono::NaiveDate::from_ymd_opt(year, 1, 1).expect("January 1st exists"),
+                );
+                ((week - first).num_days() / 7 + 1) as u32
+            }
+        }
+    }
+}
+
+/// 12-hour (`3:00 PM`) or 24-hour (`15:00`) clock times, named as
+/// JavaScript's `Intl.DateTimeFormat` `hourCycle` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HourCycle {
+    H12,
+    H23,
+}
+
+impl HourCycle {
+    pub const ALL: [Self; 2] = [Self::H12, Self::H23];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::H12 => "h12",
+            Self::H23 => "h23",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|h| h.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+/// Whether the dashboard is light or dark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeMode {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl ThemeMode {
+    pub const ALL: [Self; 2] = [Self::Light, Self::Dark];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Light => "light",
+            Self::Dark => "dark",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|m| m.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+/// A job of the scheduled database maintenance, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceTask {
+    /// Remove daily top pages and idempotency filters past their retention
+    CompactRollups,
+    /// Evict expired entries from the in-memory caches
+    PruneCaches,
+    /// Refresh the query planner's statistics and reclaim space
+    Optimize,
+}
+
+impl MaintenanceTask {
+    pub const ALL: [Self; 3] = [Self::CompactRollups, Self::PruneCaches, Self::Optimize];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CompactRollups => "compact_rollups",
+            Self::PruneCaches => "prune_caches",
+            Self::Optimize => "optimize",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == s)
+    }
+}
+
+/// A job the server runs in the background
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundTask {
+    /// Uptime checks of the services' sites
+    Monitor,
+    /// Sitemap crawls for the services' page titles
+    Crawler,
+    /// Saving the idempotency filters
+    HitFilterFlush,
+    /// Materializing the daily top pages
+    TopPages,
+    /// The daily database maintenance
+    Maintenance,
+    /// The daily check for a newer release
+    UpdateCheck,
+    /// Creating and dropping the monthly hit partitions
+    HitPartitions,
+    /// Refreshing the referrer spam list
+    ReferrerSpam,
+}
+
+impl BackgroundTask {
+    pub const ALL: [Self; 8] = [
+        Self::Monitor,
+        Self::Crawler,
+        Self::HitFilterFlush,
+        Self::TopPages,
+        Self::Maintenance,
+        Self::UpdateCheck,
+        Self::HitPartitions,
+        Self::ReferrerSpam,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Monitor => "monitor",
+            Self::Crawler => "crawler",
+            Self::HitFilterFlush => "hit_filter_flush",
+            Self::TopPages => "top_pages",
+            Self::Maintenance => "maintenance",
+            Self::UpdateCheck => "update_check",
+            Self::HitPartitions => "hit_partitions",
+            Self::ReferrerSpam => "referrer_spam",
+        }
+    }
+}
+
+/// What a member or API token may do in an organization. Ordered from least
+/// to most privileged; each role has every permission of the roles below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Sees stats
+    #[default]
+    Viewer,
+    /// Also creates and edits services and saved views
+    Editor,
+    /// Also deletes services and manages the organization, its members and
+    /// API tokens
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Self; 3] = [Self::Viewer, Self::Editor, Self::Owner];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Owner => "owner",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|r| r.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+
+    /// The least privileged role allowed to do something
+    fn required_for(permission: Permission) -> Self {
+        match permission {
+            Permission::ViewStats => Self::Viewer,
+            Permission::EditServices => Self::Editor,
+            Permission::DeleteServices | Permission::ManageOrganization => Self::Owner,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        *self >= Self::required_for(permission)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Viewer => write!(f, "Viewer"),
+            Self::Editor => write!(f, "Editor"),
+            Self::Owner => write!(f, "Owner"),
+        }
+    }
+}
+
+/// Something a dashboard or API request does, checked against the caller's
+/// `Role`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read services, stats, sessions and saved views
+    ViewStats,
+    /// Create and change services and saved views
+    EditServices,
+    DeleteServices,
+    /// Change the organization's settings, members and API tokens
+    ManageOrganization,
+}
+
+/// How a dashboard login attempt ended, as kept in the login audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginOutcome {
+    Success,
+    WrongPassword,
+    /// The password was right but the two-factor code was not
+    WrongCode,
+    /// Refused without checking the password, after too many failures
+    Locked,
+}
+
+impl LoginOutcome {
+    pub const ALL: [Self; 4] = [
+        Self::Success,
+        Self::WrongPassword,
+        Self::WrongCode,
+        Self::Locked,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::WrongPassword => "wrong_password",
+            Self::WrongCode => "wrong_code",
+            Self::Locked => "locked",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|o| o.as_str() == s)
+    }
+
+    /// Counts towards a lockout. Refused attempts do not, so a locked account
+    /// unlocks on schedule even while someone keeps trying.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::WrongPassword | Self::WrongCode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionAssociationHash(pub String);
+
+impl SessionAssociationHash {
+    pub fn compute(
+        ip: &str,
+        user_agent: &str,
+        service_id: Option<&ServiceId>,
+        aggressive_salting: bool,
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(ip.as_bytes());
+        hasher.update(user_agent.as_bytes());
+
+        if aggressive_salting {
+            if let Some(sid) = service_id {
+                hasher.update(sid.0.as_bytes());
+            }
+            let date = Utc::now().format("%Y-%m-%d").to_string();
+            hasher.update(date.as_bytes());
+        }
+
+        let result = hasher.finalize();
+        Self(hex::encode(result))
+    }
+
+    /// Whether this visitor falls within a sample of the given rate (0.0-1.0).
+    /// Derived from the hash so every request of a visitor gets the same answer.
+    pub fn sampled(&self, rate: f64) -> bool {
+        let bucket = self
+            .0
+            .get(..8)
+            .and_then(|prefix| u32::from_str_radix(prefix, 16).ok())
+            .unwrap_or(0);
+        (bucket as f64) < rate * (u32::MAX as f64 + 1.0)
+    }
+
+    /// The visitor as a 64-bit number, for counting distinct visitors
+    pub fn visitor_key(&self) -> u64 {
+        self.0
+            .get(..16)
+            .and_then(|prefix| u64::from_str_radix(prefix, 16).ok())
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for SessionAssociationHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChartData {
+    pub sessions: Vec<i64>,
+    pub hits: Vec<i64>,
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartGranularity {
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl ChartGranularity {
+    /// Ranges from this many days on are charted week by week
+    pub const WEEKLY_MIN_DAYS: i64 = 120;
+
+    /// The buckets a chart of a range this long uses
+    pub fn for_range(duration: chrono::Duration) -> Self {
+        match duration.num_days() {
+            days if days < 3 => Self::Hourly,
+            days if days < Self::WEEKLY_MIN_DAYS => Self::Daily,
+            _ => Self::Weekly,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+
+    pub fn tooltip_format(&self) -> &'static str {
+        match self {
+            Self::Hourly => "MM/dd HH:mm",
+            Self::Daily | Self::Weekly => "MMM d",
+        }
+    }
+}
+
+/// A panel on the service dashboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DashboardPanel {
+    Chart,
+    Locations,
+    Countries,
+    Referrers,
+    Browsers,
+    OperatingSystems,
+    DeviceTypes,
+    Sessions,
+    Usage,
+    ContentGroups,
+    SessionDistribution,
+    Dimensions,
+    DataQuality,
+    Errors,
+}
+
+impl DashboardPanel {
+    /// Every panel, in the default dashboard order
+    pub const ALL: [Self; 14] = [
+        Self::Chart,
+        Self::Locations,
+        Self::Countries,
+        Self::Referrers,
+        Self::Browsers,
+        Self::OperatingSystems,
+        Self::DeviceTypes,
+        Self::Sessions,
+        Self::Usage,
+        Self::ContentGroups,
+        Self::SessionDistribution,
+        Self::Dimensions,
+        Self::DataQuality,
+        Self::Errors,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Chart => "chart",
+            Self::Locations => "locations",
+            Self::Countries => "countries",
+            Self::Referrers => "referrers",
+            Self::Browsers => "browsers",
+            Self::OperatingSystems => "operating_systems",
+            Self::DeviceTypes => "device_types",
+            Self::Sessions => "sessions",
+            Self::Usage => "usage",
+            Self::ContentGroups => "content_groups",
+            Self::SessionDistribution => "session_distribution",
+            Self::Dimensions => "dimensions",
+            Self::DataQuality => "data_quality",
+            Self::Errors => "errors",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == s.trim())
+    }
+}
+
+impl fmt::Display for DashboardPanel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Chart => write!(f, "Chart"),
+            Self::Locations => write!(f, "Top Pages"),
+            Self::Countries => write!(f, "Countries"),
+            Self::Referrers => write!(f, "Referrers"),
+            Self::Browsers => write!(f, "Browsers"),
+            Self::OperatingSystems => write!(f, "Operating Systems"),
+            Self::DeviceTypes => write!(f, "Device Types"),
+            Self::Sessions => write!(f, "Recent Sessions"),
+            Self::Usage => write!(f, "Usage"),
+            Self::ContentGroups => write!(f, "Content Groups"),
+            Self::SessionDistribution => write!(f, "Session Distribution"),
+            Self::Dimensions => write!(f, "Dimensions"),
+            Self::DataQuality => write!(f, "Data Quality"),
+            Self::Errors => write!(f, "Errors"),
+        }
+    }
+}
+
+/// Ordered list of the dashboard panels to show; panels not listed are hidden.
+/// Stored as comma-separated panel keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PanelLayout(pub Vec<DashboardPanel>);
+
+impl PanelLayout {
+    /// Parse comma-separated panel keys, skipping unknown and duplicate keys.
+    /// An empty layout falls back to the default (every panel).
+    pub fn parse(s: &str) -> Self {
+        let mut panels: Vec<DashboardPanel> = Vec::new();
+        for panel in s.split(',').filter_map(DashboardPanel::from_str) {
+            if !panels.contains(&panel) {
+                panels.push(panel);
+            }
+        }
+
+        if panels.is_empty() {
+            Self::default()
+        } else {
+            Self(panels)
+        }
+    }
+
+    pub fn panels(&self) -> &[DashboardPanel] {
+        &self.0
+    }
+
+    /// Whether the panel with the given key is visible
+    pub fn shows(&self, key: &str) -> bool {
+        self.0.iter().any(|p| p.as_str() == key)
+    }
+}
+
+impl Default for PanelLayout {
+    fn default() -> Self {
+        Self(DashboardPanel::ALL.to_vec())
+    }
+}
+
+impl fmt::Display for PanelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<&str> = self.0.iter().map(|p| p.as_str()).collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+/// How a content group rule recognizes a page, by its path
+#[derive(Debug, Clone)]
+pub enum ContentGroupPattern {
+    Prefix(String),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentGroupRule {
+    pub pattern: ContentGroupPattern,
+    pub group: String,
+}
+
+/// Rules sorting a service's pages into named groups, one `pattern = Group`
+/// per line, e.g. `/blog/* = Blog`. Patterns starting with `^` are regular
+/// expressions; others are path prefixes, with an optional trailing `*`.
+/// The first matching rule wins.
+#[derive(Debug, Clone, Default)]
+pub struct ContentGroups(pub Vec<ContentGroupRule>);
+
+impl ContentGroups {
+    /// Parse rules, skipping lines without a group and invalid regexes
+    pub fn parse(s: &str) -> Self {
+        let rules = s
+            .lines()
+            .filter_map(|line| {
+                let (pattern, group) = line.rsplit_once('=')?;
+                let (pattern, group) = (pattern.trim(), group.trim());
+                if pattern.is_empty() || group.is_empty() {
+                    return None;
+                }
+                let pattern = if pattern.starts_with('^') {
+                    ContentGroupPattern::Regex(Regex::new(pattern).ok()?)
+                } else {
+                    ContentGroupPattern::Prefix(pattern.trim_end_matches('*').to_string())
+                };
+                Some(ContentGroupRule {
+                    pattern,
+                    group: group.to_string(),
+                })
+            })
+            .collect();
+        Self(rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The group of the page at `location`, a full URL or a path
+    pub fn group(&self, location: &str) -> Option<&str> {
+        let url = Url::parse(location).ok();
+        let path = url.as_ref().map_or(location, |url| url.path());
+        self.0
+            .iter()
+            .find(|rule| match &rule.pattern {
+                ContentGroupPattern::Prefix(prefix) => path.starts_with(prefix.as_str()),
+                ContentGroupPattern::Regex(regex) => regex.is_match(path),
+            })
+            .map(|rule| rule.group.as_str())
+    }
+
+    /// Add up hits per location into hits per group, busiest first. Pages no
+    /// rule matches are counted under an empty group.
+    pub fn count<'a>(
+        &self,
+        locations: impl IntoIterator<Item = (&'a str, i64)>,
+    ) -> Vec<CountedItem> {
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for (location, count) in locations {
+            *counts
+                .entry(self.group(location).unwrap_or(""))
+                .or_insert(0) += count;
+        }
+        let mut items: Vec<CountedItem> = counts
+            .into_iter()
+            .map(|(group, count)| CountedItem::new(group.to_string(), count))
+            .collect();
+        items.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        items
+    }
+}
+
+/// How a service folds the URL variants of a page into one location at
+/// ingest: lowercased paths, trailing slashes stripped, and dynamic segments
+/// collapsed by patterns such as `/users/:id`, where a `:name` segment stands
+/// for any segment. The first pattern with the path's shape wins.
+#[derive(Debug, Clone, Default)]
+pub struct PathNormalization {
+    pub lowercase: bool,
+    pub strip_trailing_slash: bool,
+    pub patterns: Vec<Vec<String>>,
+}
+
+impl PathNormalization {
+    /// Parse patterns, one per line; lines not starting with `/` are skipped
+    pub fn parse(patterns: &str, lowercase: bool, strip_trailing_slash: bool) -> Self {
+        let patterns = patterns
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with('/'))
+            .map(|line| {
+                line.trim_end_matches('/')
+                    .split('/')
+                    .skip(1)
+                    .map(String::from)
+                    .collect()
+            })
+            .collect();
+        Self {
+            lowercase,
+            strip_trailing_slash,
+            patterns,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.lowercase && !self.strip_trailing_slash && self.patterns.is_empty()
+    }
+
+    /// `location`, a full URL or a path, with its path normalized. The query
+    /// and fragment are left alone.
+    pub fn apply(&self, location: &str) -> String {
+        if self.is_empty() {
+            return location.to_string();
+        }
+        match Url::parse(location) {
+            Ok(mut url) if url.has_host() => {
+                let path = self.normalize_path(url.path());
+                url.set_path(&path);
+                url.to_string()
+            }
+            _ if location.starts_with('/') => {
+                let end = location.find(['?', '#']).unwrap_or(location.len());
+                format!(
+                    "{}{}",
+                    self.normalize_path(&location[..end]),
+                    &location[end..]
+                )
+            }
+            _ => location.to_string(),
+        }
+    }
+
+    fn normalize_path(&self, path: &str) -> String {
+        let mut path = if self.lowercase {
+            path.to_lowercase()
+        } else {
+            path.to_string()
+        };
+        if self.strip_trailing_slash {
+            let trimmed = path.trim_end_matches('/');
+            path = if trimmed.is_empty() {
+                "/".to_string()
+            } else {
+                trimmed.to_string()
+            };
         }
-    }
-}
 
-impl fmt::Display for DeviceType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Phone => write!(f, "Phone"),
-            Self::Tablet => write!(f, "Tablet"),
-            Self::Desktop => write!(f, "Desktop"),
-            Self::Robot => write!(f, "Robot"),
-            Self::Other => write!(f, "Other"),
+        let trailing_slash = path.len() > 1 && path.ends_with('/');
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(1).collect();
+        let matched = self.patterns.iter().find(|pattern| {
+            pattern.len() == segments.len()
+                && pattern.iter().zip(&segments).all(|(part, segment)| {
+                    if part.starts_with(':') {
+                        !segment.is_empty()
+                    } else {
+                        part == segment
+                    }
+                })
+        });
+        match matched {
+            Some(pattern) => {
+                let slash = if trailing_slash { "/" } else { "" };
+                format!("/{}{}", pattern.join("/"), slash)
+            }
+            None => path,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub enum TrackerType {
-    Js,
-    Pixel,
+/// Which referrers a service's referrer breakdowns leave out: those matching
+/// its `hide_referrer_regex`, and internal ones, from the hosts of its own
+/// origins and link, i.e. navigations within the site. Empty referrers are
+/// direct visits and always kept.
+#[derive(Debug, Clone, Default)]
+pub struct ReferrerFilter {
+    pub hide: Option<Regex>,
+    /// Hosts of the service's own site, lowercased and without `www.`;
+    /// empty when internal referrers are kept
+    pub internal_hosts: Vec<String>,
 }
 
-impl TrackerType {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Self::Js => "JS",
-            Self::Pixel => "PIXEL",
+impl ReferrerFilter {
+    /// `sites` are origins or URLs; a `*` among them names no site. An
+    /// invalid `hide` pattern hides nothing.
+    pub fn new<'a>(
+        hide: &str,
+        hide_internal: bool,
+        sites: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let hide = if hide.is_empty() {
+            None
+        } else {
+            Regex::new(hide).ok()
+        };
+        let mut internal_hosts: Vec<String> = if hide_internal {
+            sites.into_iter().filter_map(site_host).collect()
+        } else {
+            Vec::new()
+        };
+        internal_hosts.sort();
+        internal_hosts.dedup();
+        Self {
+            hide,
+            internal_hosts,
         }
     }
 
-    #[allow(clippy::should_implement_trait)]
-    pub fn from_str(s: &str) -> Self {
-        match s.to_uppercase().as_str() {
-            "JS" => Self::Js,
-            _ => Self::Pixel,
-        }
+    /// A referrer from the service's own site
+    pub fn is_internal(&self, referrer: &str) -> bool {
+        !self.internal_hosts.is_empty()
+            && site_host(referrer).is_some_and(|host| self.internal_hosts.contains(&host))
     }
-}
 
-impl fmt::Display for TrackerType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Js => write!(f, "JavaScript"),
-            Self::Pixel => write!(f, "Pixel"),
-        }
+    pub fn hides(&self, referrer: &str) -> bool {
+        self.hide
+            .as_ref()
+            .is_some_and(|regex| regex.is_match(referrer))
+            || self.is_internal(referrer)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct SessionAssociationHash(pub String);
+/// The host of an origin or URL, lowercased and without `www.`
+fn site_host(site: &str) -> Option<String> {
+    let url = Url::parse(site.trim()).ok()?;
+    let host = url.host_str()?.to_lowercase();
+    Some(
+        host.strip_prefix("www.")
+            .map(str::to_string)
+            .unwrap_or(host),
+    )
+}
 
-impl SessionAssociationHash {
-    pub fn compute(
-        ip: &str,
-        user_agent: &str,
-        service_id: Option<&ServiceId>,
-        aggressive_salting: bool,
+/// Which visitors a service records by where they are: with allowed
+/// countries or ASNs set, only visitors in one of them; visitors in a
+/// blocked one never. A visitor whose country or ASN is unknown, e.g.
+/// without a GeoIP database, passes the rules on it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoRules {
+    /// ISO country codes, uppercase
+    pub allowed_countries: Vec<String>,
+    pub blocked_countries: Vec<String>,
+    pub allowed_asns: Vec<u32>,
+    pub blocked_asns: Vec<u32>,
+}
+
+impl GeoRules {
+    /// Lists are comma-separated; AS numbers may be written `AS13335`.
+    /// Entries that are neither a two-letter code nor a number are skipped.
+    pub fn parse(
+        allowed_countries: &str,
+        blocked_countries: &str,
+        allowed_asns: &str,
+        blocked_asns: &str,
     ) -> Self {
-        let mut hasher = Sha256::new();
-        hasher.update(ip.as_bytes());
-        hasher.update(user_agent.as_bytes());
+        Self {
+            allowed_countries: parse_countries(allowed_countries),
+            blocked_countries: parse_countries(blocked_countries),
+            allowed_asns: parse_asns(allowed_asns),
+            blocked_asns: parse_asns(blocked_asns),
+        }
+    }
 
-        if aggressive_salting {
-            if let Some(sid) = service_id {
-                hasher.update(sid.0.as_bytes());
+    pub fn is_empty(&self) -> bool {
+        self.allowed_countries.is_empty()
+            && self.blocked_countries.is_empty()
+            && self.allowed_asns.is_empty()
+            && self.blocked_asns.is_empty()
+    }
+
+    /// Whether a visitor from `country` (empty if unknown) and `asn` is
+    /// recorded
+    pub fn admits(&self, country: &str, asn: Option<u32>) -> bool {
+        let country = country.trim().to_uppercase();
+        if !country.is_empty() {
+            if self.blocked_countries.contains(&country) {
+                return false;
+            }
+            if !self.allowed_countries.is_empty() && !self.allowed_countries.contains(&country) {
+                return false;
             }
-            let date = Utc::now().format("%Y-%m-%d").to_string();
-            hasher.update(date.as_bytes());
         }
-
-        let result = hasher.finalize();
-        Self(hex::encode(result))
+        if let Some(asn) = asn {
+            if self.blocked_asns.contains(&asn) {
+                return false;
+            }
+            if !self.allowed_asns.is_empty() && !self.allowed_asns.contains(&asn) {
+                return false;
+            }
+        }
+        true
     }
 }
 
-impl fmt::Display for SessionAssociationHash {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+fn parse_countries(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|code| code.trim().to_uppercase())
+        .filter(|code| code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()))
+        .collect()
+}
+
+fn parse_asns(list: &str) -> Vec<u32> {
+    list.split(',')
+        .filter_map(|asn| {
+            let asn = asn.trim();
+            let number = asn
+                .strip_prefix("AS")
+                .or_else(|| asn.strip_prefix("as"))
+                .unwrap_or(asn);
+            number.parse().ok()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountedItem {
+    pub value: String,
+    pub count: i64,
+    /// Display metadata, set on country counts only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<CountryInfo>,
+}
+
+impl CountedItem {
+    pub fn new(value: String, count: i64) -> Self {
+        Self {
+            value,
+            count,
+            country: None,
+        }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct ChartData {
-    pub sessions: Vec<i64>,
-    pub hits: Vec<i64>,
-    pub labels: Vec<String>,
+/// Sessions going from one page to the next at a step of their visit, an
+/// edge of a Sankey diagram whose nodes are a page at a step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageTransition {
+    /// 1 for a session's first page to its second, and so on
+    pub step: i64,
+    pub source: String,
+    pub target: String,
+    pub count: i64,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ChartGranularity {
-    Hourly,
-    Daily,
+/// Continent, using the two-letter codes MaxMind reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Continent {
+    #[serde(rename = "AF")]
+    Africa,
+    #[serde(rename = "AN")]
+    Antarctica,
+    #[serde(rename = "AS")]
+    Asia,
+    #[serde(rename = "EU")]
+    Europe,
+    #[serde(rename = "NA")]
+    NorthAmerica,
+    #[serde(rename = "OC")]
+    Oceania,
+    #[serde(rename = "SA")]
+    SouthAmerica,
 }
 
-impl ChartGranularity {
-    pub fn tooltip_format(&self) -> &'static str {
+impl Continent {
+    pub const ALL: [Self; 7] = [
+        Self::Africa,
+        Self::Antarctica,
+        Self::Asia,
+        Self::Europe,
+        Self::NorthAmerica,
+        Self::Oceania,
+        Self::SouthAmerica,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Self::Hourly => "MM/dd HH:mm",
-            Self::Daily => "MMM d",
+            Self::Africa => "AF",
+            Self::Antarctica => "AN",
+            Self::Asia => "AS",
+            Self::Europe => "EU",
+            Self::NorthAmerica => "NA",
+            Self::Oceania => "OC",
+            Self::SouthAmerica => "SA",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+impl fmt::Display for Continent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Africa => write!(f, "Africa"),
+            Self::Antarctica => write!(f, "Antarctica"),
+            Self::Asia => write!(f, "Asia"),
+            Self::Europe => write!(f, "Europe"),
+            Self::NorthAmerica => write!(f, "North America"),
+            Self::Oceania => write!(f, "Oceania"),
+            Self::SouthAmerica => write!(f, "South America"),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CountedItem {
-    pub value: String,
+/// Localized name, flag and continent for an ISO 3166-1 country code
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountryInfo {
+    pub code: String,
+    pub name: String,
+    /// Emoji flag, empty if the code is not a two-letter code
+    pub flag: String,
+    pub continent: Option<Continent>,
+}
+
+/// Sessions per continent, rolled up from the country counts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContinentCount {
+    pub continent: Continent,
+    pub name: String,
     pub count: i64,
 }
 
@@ -305,6 +2009,72 @@ pub struct CountedItem {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_breakdown_filters() {
+        assert!(BreakdownFilters::default().conditions().is_empty());
+
+        let filters = BreakdownFilters {
+            country: Some("de".to_string()),
+            device_type: Some("phone".to_string()),
+            browser: Some(" ".to_string()),
+            ..Default::default()
+        };
+        let conditions = filters.conditions();
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(conditions[0].field, SegmentField::Country);
+        assert_eq!(conditions[0].column_value(), "DE");
+        assert_eq!(conditions[1].field, SegmentField::Device);
+        assert_eq!(conditions[1].column_value(), "PHONE");
+        assert!(conditions.iter().all(|c| c.op == SegmentOp::Is));
+    }
+
+    #[test]
+    fn test_geo_rules() {
+        let rules = GeoRules::parse("de, at,xx1,", "", "", "AS16509, 14618,bogus");
+        assert_eq!(rules.allowed_countries, ["DE", "AT"]);
+        assert_eq!(rules.blocked_asns, [16509, 14618]);
+        assert!(!rules.is_empty());
+
+        assert!(rules.admits("DE", Some(3320)));
+        assert!(rules.admits("at", None));
+        assert!(!rules.admits("US", Some(3320)));
+        assert!(!rules.admits("DE", Some(16509)));
+        // Unknown locations pass
+        assert!(rules.admits("", None));
+        assert!(!rules.admits("", Some(14618)));
+
+        let blocked = GeoRules::parse("", "RU,CN", "13335", "");
+        assert!(blocked.admits("US", Some(13335)));
+        assert!(!blocked.admits("RU", Some(13335)));
+        assert!(!blocked.admits("US", Some(3320)));
+
+        assert!(GeoRules::parse("", " ", "", "").is_empty());
+        assert!(GeoRules::default().admits("RU", Some(1)));
+    }
+
+    #[test]
+    fn test_referrer_filter() {
+        let filter = ReferrerFilter::new(
+            "^https://mail\\.",
+            true,
+            ["https://example.com", "https://shop.example.com/", "*"],
+        );
+        assert!(filter.is_internal("https://example.com/pricing"));
+        assert!(filter.is_internal("http://www.example.com/"));
+        assert!(filter.is_internal("https://Shop.Example.com/cart?id=1"));
+        assert!(!filter.is_internal("https://blog.example.com/"));
+        assert!(!filter.is_internal(""));
+        assert!(filter.hides("https://mail.google.com/"));
+        assert!(filter.hides("https://example.com/"));
+        assert!(!filter.hides("https://news.ycombinator.com/"));
+        // Direct visits stay
+        assert!(!filter.hides(""));
+
+        let keep_internal = ReferrerFilter::new("", false, ["https://example.com"]);
+        assert!(!keep_internal.hides("https://example.com/pricing"));
+        assert!(!ReferrerFilter::new("(", true, ["*"]).hides("https://example.com/"));
+    }
+
     #[test]
     fn test_service_id_new() {
         let id1 = ServiceId::new();
@@ -405,6 +2175,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_session_hash_sampled() {
+        let hash = SessionAssociationHash::compute("192.168.1.1", "Mozilla/5.0", None, false);
+        assert!(hash.sampled(1.0));
+        assert!(!hash.sampled(0.0));
+        assert_eq!(hash.sampled(0.5), hash.sampled(0.5));
+
+        assert!(SessionAssociationHash("00000000".to_string()).sampled(0.01));
+        assert!(!SessionAssociationHash("ffffffff".to_string()).sampled(0.99));
+
+        // Roughly the requested share of visitors is kept
+        let kept = (0..1000)
+            .map(|i| {
+                SessionAssociationHash::compute(
+                    &format!("10.0.{}.{}", i / 256, i % 256),
+                    "UA",
+                    None,
+                    false,
+                )
+            })
+            .filter(|h| h.sampled(0.1))
+            .count();
+        assert!((50..150).contains(&kept), "kept {} of 1000", kept);
+    }
+
+    #[test]
+    fn test_quota_behavior_roundtrip() {
+        for behavior in QuotaBehavior::ALL {
+            assert_eq!(QuotaBehavior::from_str(behavior.as_str()), Some(behavior));
+        }
+        assert_eq!(QuotaBehavior::from_str(" Drop "), Some(QuotaBehavior::Drop));
+        assert_eq!(QuotaBehavior::from_str("nope"), None);
+        assert_eq!(QuotaBehavior::default(), QuotaBehavior::Keep);
+        assert_eq!(
+            serde_json::to_string(&QuotaBehavior::Sample).unwrap(),
+            r#""sample""#
+        );
+    }
+
+    #[test]
+    fn test_bounce_rule() {
+        for rule in BounceRule::ALL {
+            assert_eq!(BounceRule::from_str(rule.as_str()), Some(rule));
+            // More than one page view is never a bounce
+            assert!(!rule.is_bounce(2, 0, 10, false));
+        }
+        assert_eq!(BounceRule::from_str("nope"), None);
+        assert_eq!(BounceRule::default(), BounceRule::SingleHit);
+
+        // One page with three minutes of heartbeats
+        assert!(BounceRule::SingleHit.is_bounce(1, 180, 10, true));
+        assert!(!BounceRule::EngagedTime.is_bounce(1, 180, 10, false));
+        assert!(BounceRule::EngagedTime.is_bounce(1, 9, 10, true));
+        assert!(!BounceRule::NoInteraction.is_bounce(1, 0, 10, true));
+        assert!(BounceRule::NoInteraction.is_bounce(1, 180, 10, false));
+    }
+
+    #[test]
+    fn test_role_roundtrip() {
+        for role in Role::ALL {
+            assert_eq!(Role::from_str(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::from_str(" Owner "), Some(Role::Owner));
+        assert_eq!(Role::from_str("admin"), None);
+        assert_eq!(Role::default(), Role::Viewer);
+        assert_eq!(serde_json::to_string(&Role::Editor).unwrap(), r#""editor""#);
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Viewer.allows(Permission::ViewStats));
+        assert!(!Role::Viewer.allows(Permission::EditServices));
+
+        assert!(Role::Editor.allows(Permission::EditServices));
+        assert!(!Role::Editor.allows(Permission::DeleteServices));
+        assert!(!Role::Editor.allows(Permission::ManageOrganization));
+
+        for permission in [
+            Permission::ViewStats,
+            Permission::EditServices,
+            Permission::DeleteServices,
+            Permission::ManageOrganization,
+        ] {
+            assert!(Role::Owner.allows(permission));
+        }
+    }
+
+    #[test]
+    fn test_login_outcome_roundtrip() {
+        for outcome in LoginOutcome::ALL {
+            assert_eq!(LoginOutcome::from_str(outcome.as_str()), Some(outcome));
+        }
+        assert_eq!(LoginOutcome::from_str("failed"), None);
+        assert!(LoginOutcome::WrongCode.is_failure());
+        assert!(!LoginOutcome::Locked.is_failure());
+        assert!(!LoginOutcome::Success.is_failure());
+    }
+
+    #[test]
+    fn test_dashboard_panel_roundtrip() {
+        for panel in DashboardPanel::ALL {
+            assert_eq!(DashboardPanel::from_str(panel.as_str()), Some(panel));
+        }
+        assert_eq!(DashboardPanel::from_str("nope"), None);
+    }
+
+    #[test]
+    fn test_panel_layout_parse() {
+        let layout = PanelLayout::parse("sessions, chart,bogus,chart");
+        assert_eq!(
+            layout.panels(),
+            &[DashboardPanel::Sessions, DashboardPanel::Chart]
+        );
+        assert_eq!(layout.to_string(), "sessions,chart");
+        assert!(layout.shows("chart"));
+        assert!(!layout.shows("locations"));
+
+        // Empty layouts fall back to every panel
+        assert_eq!(PanelLayout::parse(""), PanelLayout::default());
+        assert_eq!(
+            PanelLayout::default().panels().len(),
+            DashboardPanel::ALL.len()
+        );
+    }
+
+    #[test]
+    fn test_panel_layout_serde() {
+        let layout = PanelLayout(vec![DashboardPanel::OperatingSystems]);
+        let json = serde_json::to_string(&layout).unwrap();
+        assert_eq!(json, r#"["operating_systems"]"#);
+        assert_eq!(serde_json::from_str::<PanelLayout>(&json).unwrap(), layout);
+    }
+
     #[test]
     fn test_chart_data_default() {
         let data = ChartData::default();
@@ -418,8 +2321,289 @@ mod tests {
         let item = CountedItem {
             value: "test".to_string(),
             count: 42,
+            country: None,
         };
         assert_eq!(item.value, "test");
         assert_eq!(item.count, 42);
+
+        // Country metadata is only serialized when present
+        let json = serde_json::to_string(&CountedItem::new("US".to_string(), 1)).unwrap();
+        assert_eq!(json, r#"{"value":"US","count":1}"#);
+    }
+
+    #[test]
+    fn test_continent_roundtrip() {
+        for continent in Continent::ALL {
+            assert_eq!(Continent::from_str(continent.as_str()), Some(continent));
+        }
+        assert_eq!(Continent::from_str("eu"), Some(Continent::Europe));
+        assert_eq!(Continent::from_str("XX"), None);
+        assert_eq!(
+            serde_json::to_string(&Continent::NorthAmerica).unwrap(),
+            r#""NA""#
+        );
+        assert_eq!(Continent::SouthAmerica.to_string(), "South America");
+    }
+
+    #[test]
+    fn test_environment_detect() {
+        for host in [
+            "localhost",
+            "127.0.0.1",
+            "[::1]",
+            "10.0.0.5",
+            "shop.local",
+            "app.localhost",
+            "devbox",
+        ] {
+            assert_eq!(Environment::detect(host), Environment::Dev, "{}", host);
+        }
+        for host in [
+            "staging.example.com",
+            "example-staging.netlify.app",
+            "www.stage.example.com",
+        ] {
+            assert_eq!(Environment::detect(host), Environment::Staging, "{}", host);
+        }
+        for host in [
+            "example.com",
+            "www.example.com",
+            "stagingarea.com",
+            "8.8.8.8",
+        ] {
+            assert_eq!(
+                Environment::detect(host),
+                Environment::Production,
+                "{}",
+                host
+            );
+        }
+        assert_eq!(
+            Environment::detect_origin("http://localhost:3000"),
+            Environment::Dev
+        );
+        assert_eq!(
+            Environment::detect_origin("not a url"),
+            Environment::Production
+        );
+    }
+
+    #[test]
+    fn test_date_range_preset() {
+        for preset in DateRangePreset::ALL {
+            assert_eq!(DateRangePreset::from_str(preset.as_str()), Some(preset));
+        }
+        assert_eq!(
+            DateRangePreset::from_str(" 7d "),
+            Some(DateRangePreset::Week)
+        );
+        assert_eq!(DateRangePreset::from_str("8d"), None);
+        assert_eq!(DateRangePreset::default().duration().num_days(), 30);
+    }
+
+    #[test]
+    fn test_session_prop_filter() {
+        let filter = SessionPropFilter::parse(" plan : pro ").unwrap();
+        assert_eq!(filter.key, "plan");
+        assert_eq!(filter.value, "pro");
+        assert_eq!(
+            SessionPropFilter::parse("ref:a:b").map(|f| f.value),
+            Some("a:b".to_string())
+        );
+        assert_eq!(SessionPropFilter::parse("plan"), None);
+        assert_eq!(SessionPropFilter::parse("plan:"), None);
+        assert_eq!(SessionPropFilter::parse(":pro"), None);
+
+        let props = BTreeMap::from([("plan".to_string(), "pro".to_string())]);
+        assert!(filter.matches(&props));
+        assert!(!SessionPropFilter::parse("plan:free")
+            .unwrap()
+            .matches(&props));
+        assert!(!filter.matches(&BTreeMap::new()));
+    }
+
+    #[test]
+    fn test_environment_filter() {
+        assert_eq!(
+            Environment::parse_filter(None),
+            Some(Environment::Production)
+        );
+        assert_eq!(
+            Environment::parse_filter(Some("")),
+            Some(Environment::Production)
+        );
+        assert_eq!(Environment::parse_filter(Some("ALL")), None);
+        assert_eq!(
+            Environment::parse_filter(Some("stage")),
+            Some(Environment::Staging)
+        );
+        assert_eq!(
+            Environment::parse_filter(Some("development")),
+            Some(Environment::Dev)
+        );
+        for env in Environment::ALL {
+            assert_eq!(Environment::from_str(env.as_str()), Some(env));
+        }
+    }
+
+    #[test]
+    fn test_content_groups() {
+        let groups = ContentGroups::parse(
+            "/blog/* = Blog\n\
+             ^/docs/v[0-9]+/ = Docs\n\
+             /pricing = Sales\n\
+             no group here\n\
+             ^([ = Broken\n\
+             / = Other pages",
+        );
+        assert_eq!(groups.0.len(), 4);
+        assert_eq!(groups.group("https://example.com/blog/hello"), Some("Blog"));
+        assert_eq!(
+            groups.group("https://example.com/docs/v2/start"),
+            Some("Docs")
+        );
+        assert_eq!(
+            groups.group("https://example.com/docs/start"),
+            Some("Other pages")
+        );
+        assert_eq!(groups.group("/pricing?plan=pro"), Some("Sales"));
+        // The path is matched, not the host
+        assert_eq!(
+            groups.group("https://blog.example.com/"),
+            Some("Other pages")
+        );
+        assert_eq!(ContentGroups::parse("/blog = Blog").group("/about"), None);
+        assert!(ContentGroups::parse("").is_empty());
+    }
+
+    #[test]
+    fn test_path_normalization() {
+        let rules = PathNormalization::parse(
+            "/users/:id\n/users/:id/posts/:post/\nnot a pattern",
+            true,
+            true,
+        );
+        assert_eq!(rules.patterns.len(), 2);
+        assert_eq!(
+            rules.apply("https://example.com/Users/123/?tab=1#top"),
+            "https://example.com/users/:id?tab=1#top"
+        );
+        assert_eq!(rules.apply("/users/9/posts/42"), "/users/:id/posts/:post");
+        assert_eq!(rules.apply("/users/9/settings"), "/users/9/settings");
+        assert_eq!(rules.apply("/About/"), "/about");
+        assert_eq!(rules.apply("https://example.com/"), "https://example.com/");
+        assert_eq!(rules.apply("/"), "/");
+
+        // Each rule applies on its own
+        let patterns = PathNormalization::parse("/users/:id", false, false);
+        assert_eq!(patterns.apply("/Users/1/"), "/Users/1/");
+        assert_eq!(patterns.apply("/users/1/"), "/users/:id/");
+        let none = PathNormalization::parse("", false, false);
+        assert!(none.is_empty());
+        assert_eq!(none.apply("/About/"), "/About/");
+    }
+
+    #[test]
+    fn test_content_group_counts() {
+        let groups = ContentGroups::parse("/blog/* = Blog\n/docs/ = Docs");
+        let counts = groups.count([
+            ("https://example.com/blog/a", 3),
+            ("https://example.com/blog/b", 4),
+            ("https://example.com/docs/", 2),
+            ("https://example.com/", 5),
+        ]);
+        let counts: Vec<(&str, i64)> = counts
+            .iter()
+            .map(|item| (item.value.as_str(), item.count))
+            .collect();
+        assert_eq!(counts, vec![("Blog", 7), ("", 5), ("Docs", 2)]);
+    }
+
+    #[test]
+    fn test_week_start() {
+        let day = |m, d| chrono::NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+        // A Tuesday
+        assert_eq!(WeekStart::Monday.week_of(day(3, 17)), day(3, 16));
+        assert_eq!(WeekStart::Sunday.week_of(day(3, 17)), day(3, 15));
+        assert_eq!(WeekStart::Monday.week_of(day(3, 16)), day(3, 16));
+        assert_eq!(WeekStart::Monday.week_number(day(3, 16)), 12);
+        assert_eq!(WeekStart::Sunday.week_number(day(3, 15)), 12);
+
+        // January 1st, a Thursday, is in week 1 either way
+        let monday = WeekStart::Monday.week_of(day(1, 1));
+        let sunday = WeekStart::Sunday.week_of(day(1, 1));
+        assert_eq!(monday.to_string(), "2025-12-29");
+        assert_eq!(sunday.to_string(), "2025-12-28");
+        assert_eq!(WeekStart::Monday.week_number(monday), 1);
+        assert_eq!(WeekStart::Sunday.week_number(sunday), 1);
+        assert_eq!(WeekStart::Sunday.week_number(day(12, 20)), 52);
+    }
+
+    #[test]
+    fn test_chart_granularity() {
+        let days = chrono::Duration::days;
+        assert_eq!(
+            ChartGranularity::for_range(chrono::Duration::hours(24)),
+            ChartGranularity::Hourly
+        );
+        assert_eq!(
+            ChartGranularity::for_range(days(90)),
+            ChartGranularity::Daily
+        );
+        assert_eq!(
+            ChartGranularity::for_range(days(365)),
+            ChartGranularity::Weekly
+        );
+    }
+
+    #[test]
+    fn test_compare_mode() {
+        use chrono::TimeZone;
+        let at = |m, d| Utc.with_ymd_and_hms(2024, m, d, 0, 0, 0).unwrap();
+        // Monday to Monday, a week
+        let (start, end) = (at(6, 10), at(6, 17));
+        assert_eq!(
+            CompareMode::PreviousPeriod.period(start, end),
+            (at(6, 3), at(6, 10))
+        );
+        assert_eq!(
+            CompareMode::LastWeek.period(start, end),
+            (at(6, 3), at(6, 10))
+        );
+        assert_eq!(
+            CompareMode::LastMonth.period(start, end),
+            (at(5, 10), at(5, 17))
+        );
+
+        // Ten days go back two whole weeks, keeping the weekdays
+        let (start, end) = (at(6, 10), at(6, 20));
+        assert_eq!(
+            CompareMode::PreviousPeriod.period(start, end),
+            (at(5, 31), at(6, 10))
+        );
+        assert_eq!(
+            CompareMode::LastWeek.period(start, end),
+            (at(5, 27), at(6, 6))
+        );
+
+        // The end of March goes back to the end of February
+        let (start, end) = (at(3, 1), at(3, 31));
+        assert_eq!(
+            CompareMode::LastMonth.period(start, end),
+            (at(2, 1), at(2, 29))
+        );
+
+        // A range longer than a month goes back further
+        let (start, end) = (at(4, 1), at(6, 1));
+        assert_eq!(
+            CompareMode::LastMonth.period(start, end),
+            (at(2, 1), at(4, 1))
+        );
+
+        assert_eq!(
+            CompareMode::from_str("last_week"),
+            Some(CompareMode::LastWeek)
+        );
+        assert_eq!(CompareMode::from_str("yesterday"), None);
     }
 }
@@ -0,0 +1,256 @@
+//! Widgets site owners can put on their own pages in an iframe: the visitor
+//! chart and a row of counters, without any dashboard around them. Like the
+//! milestone feed, the URL carries a signed share token instead of a login,
+//! found on the service's settings page. `?theme=dark` suits dark pages,
+//! `?accent=` takes a hex color for the chart and numbers, and `?range=` a
+//! preset such as `7d` in place of the service's default range. Embeds may
+//! be framed by any site; every other page only by shymini itself.
+
+use askama::Template;
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use chrono::Duration;
+use serde::Deserialize;
+
+use crate::api::service_timezone;
+use crate::auth::TokenPurpose;
+use crate::dashboard::intcomma;
+use crate::db;
+use crate::domain::{ChartData, DateRangePreset, Environment, Service, ServiceId};
+use crate::error::{Error, Result};
+use crate::i18n::I18n;
+use crate::state::AppState;
+use crate::theme::{parse_color, Theme};
+
+/// Seconds browsers and proxies may keep a widget
+const MAX_AGE_SECS: u32 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmbedTheme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl EmbedTheme {
+    /// The theme named by `s`, light for anything else
+    pub fn parse(s: Option<&str>) -> Self {
+        match s.map(str::trim) {
+            Some(s) if s.eq_ignore_ascii_case("dark") => Self::Dark,
+            _ => Self::Light,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Light => "light",
+            Self::Dark => "dark",
+        }
+    }
+}
+
+/// Absolute URL of one of a service's widgets, `chart` or `counters`
+pub fn embed_url(state: &AppState, service_id: ServiceId, widget: &str) -> String {
+    let token = state.signing_key.sign(
+        TokenPurpose::Embed,
+        &service_id.to_string(),
+        state.clock.now(),
+    );
+    state
+        .settings
+        .public_link(&format!("/embed/{}/{}", token, widget))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbedQuery {
+    pub theme: Option<String>,
+    pub accent: Option<String>,
+    pub range: Option<String>,
+}
+
+/// What every widget shows around its numbers
+struct Widget {
+    service: Service,
+    i18n: I18n,
+    theme: EmbedTheme,
+    accent: String,
+    range: DateRangePreset,
+}
+
+impl Widget {
+    async fn load(
+        state: &AppState,
+        token: &str,
+        query: &EmbedQuery,
+        headers: &HeaderMap,
+    ) -> Result<Self> {
+        let service_id: ServiceId = state
+            .signing_key
+            .verify(TokenPurpose::Embed, token, state.clock.now())
+            .and_then(|subject| subject.parse().ok())
+            .ok_or(Error::ServiceNotFound)?;
+        let service = db::get_service(&state.pool, service_id).await?;
+        Ok(Self {
+            range: query
+                .range
+                .as_deref()
+                .and_then(DateRangePreset::from_str)
+                .unwrap_or(service.default_range),
+            service,
+            i18n: I18n::from_headers(headers, &state.settings.locale),
+            theme: EmbedTheme::parse(query.theme.as_deref()),
+            accent: query
+                .accent
+                .as_deref()
+                .and_then(parse_color)
+                .unwrap_or_else(|| Theme::new(&state.settings, None).accent),
+        })
+    }
+
+    fn range_label(&self) -> String {
+        self.i18n
+            .t(&format!("date-range-{}", self.range.as_str()))
+            .to_string()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "embed/chart.html")]
+pub struct EmbedChartTemplate {
+    pub i18n: I18n,
+    pub theme: EmbedTheme,
+    pub accent: String,
+    pub title: String,
+    pub range_label: String,
+    pub chart_data: ChartData,
+}
+
+#[derive(Template)]
+#[template(path = "embed/counters.html")]
+pub struct EmbedCountersTemplate {
+    pub i18n: I18n,
+    pub theme: EmbedTheme,
+    pub accent: String,
+    pub title: String,
+    pub range_label: String,
+    pub online: String,
+    pub sessions: String,
+    pub hits: String,
+}
+
+fn widget_response(html: String) -> Response {
+    (
+        [
+            (
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", MAX_AGE_SECS),
+            ),
+            // Any site may show the widget
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "frame-ancestors *".to_string(),
+            ),
+        ],
+        Html(html),
+    )
+        .into_response()
+}
+
+/// GET /embed/:token/chart
+pub async fn embed_chart(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Query(query): Query<EmbedQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let widget = Widget::load(&state, &token, &query, &headers).await?;
+    let now = state.clock.now();
+    let (chart_data, _, _) = db::get_chart(
+        &state.pool,
+        widget.service.id,
+        now - widget.range.duration(),
+        now,
+        Some(Environment::Production),
+        now,
+        None,
+        None,
+        service_timezone(&widget.service),
+        widget
+            .service
+            .week_start
+            .unwrap_or(widget.i18n.locale().week_start()),
+    )
+    .await?;
+
+    let template = EmbedChartTemplate {
+        range_label: widget.range_label(),
+        i18n: widget.i18n,
+        theme: widget.theme,
+        accent: widget.accent,
+        title: widget.service.name,
+        chart_data,
+    };
+    Ok(widget_response(template.render()?))
+}
+
+/// GET /embed/:token/counters
+pub async fn embed_counters(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Query(query): Query<EmbedQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let widget = Widget::load(&state, &token, &query, &headers).await?;
+    let now = state.clock.now();
+    let active_cutoff =
+        now - Duration::milliseconds(state.settings.active_user_timeout_ms(&widget.service) as i64);
+    let counters = db::get_live_counters(
+        &state.pool,
+        widget.service.id,
+        now - widget.range.duration(),
+        active_cutoff,
+    )
+    .await?;
+
+    let template = EmbedCountersTemplate {
+        range_label: widget.range_label(),
+        i18n: widget.i18n,
+        theme: widget.theme,
+        accent: widget.accent,
+        title: widget.service.name,
+        online: intcomma(counters.online),
+        sessions: intcomma(counters.sessions_today),
+        hits: intcomma(counters.hits_today),
+    };
+    Ok(widget_response(template.render()?))
+}
+
+/// Keep other sites from framing pages, against clickjacking, unless the
+/// response says who may frame it, as widgets do
+pub async fn frame_options(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if !headers.contains_key(header::CONTENT_SECURITY_POLICY) {
+        headers
+            .entry(header::X_FRAME_OPTIONS)
+            .or_insert(HeaderValue::from_static("SAMEORIGIN"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme() {
+        assert_eq!(EmbedTheme::parse(Some("dark")), EmbedTheme::Dark);
+        assert_eq!(EmbedTheme::parse(Some(" Dark ")), EmbedTheme::Dark);
+        assert_eq!(EmbedTheme::parse(Some("sepia")), EmbedTheme::Light);
+        assert_eq!(EmbedTheme::parse(None), EmbedTheme::Light);
+    }
+}
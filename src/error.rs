@@ -2,6 +2,7 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use tracing::error;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -14,6 +15,33 @@ pub enum Error {
     #[error("Session not found")]
     SessionNotFound,
 
+    #[error("Saved view not found")]
+    SavedViewNotFound,
+
+    #[error("Segment not found")]
+    SegmentNotFound,
+
+    #[error("Campaign not found")]
+    CampaignNotFound,
+
+    #[error("Link not found")]
+    LinkNotFound,
+
+    #[error("Organization not found")]
+    OrganizationNotFound,
+
+    #[error("User not found")]
+    UserNotFound,
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Your role does not allow this")]
+    Forbidden,
+
+    #[error("{0}")]
+    BadRequest(String),
+
     #[error("Invalid origin")]
     InvalidOrigin,
 
@@ -41,22 +69,59 @@ pub enum Error {
     #[error("Regex error: {0}")]
     Regex(#[from] regex::Error),
 
+    #[error("Template error: {0}")]
+    Template(#[from] askama::Error),
+
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[error("Mail error: {0}")]
+    Mail(String),
+
+    #[error("OIDC error: {0}")]
+    Oidc(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        let status = match &self {
-            Error::ServiceNotFound | Error::SessionNotFound => StatusCode::NOT_FOUND,
-            Error::InvalidOrigin => StatusCode::FORBIDDEN,
-            Error::InvalidUuid(_) | Error::InvalidIp(_) | Error::InvalidDateRange => {
-                StatusCode::BAD_REQUEST
-            }
+impl Error {
+    /// The HTTP status a handler answers this error with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::ServiceNotFound
+            | Error::SessionNotFound
+            | Error::SavedViewNotFound
+            | Error::SegmentNotFound
+            | Error::CampaignNotFound
+            | Error::LinkNotFound
+            | Error::OrganizationNotFound
+            | Error::UserNotFound => StatusCode::NOT_FOUND,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Forbidden | Error::InvalidOrigin => StatusCode::FORBIDDEN,
+            Error::InvalidUuid(_)
+            | Error::InvalidIp(_)
+            | Error::InvalidDateRange
+            | Error::BadRequest(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+        }
+    }
 
-        (status, self.to_string()).into_response()
+    /// What the client is told. Server errors are logged here and only
+    /// reported as such, keeping database and template details private.
+    pub fn public_message(&self) -> String {
+        if self.status().is_server_error() {
+            error!("{}", self);
+            "Internal error".to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.status(), self.public_message()).into_response()
     }
 }
 
@@ -117,6 +182,22 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_error_into_response_organization_not_found() {
+        let err = Error::OrganizationNotFound;
+        assert_eq!(err.to_string(), "Organization not found");
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_error_into_response_unauthorized() {
+        let err = Error::Unauthorized;
+        assert_eq!(err.to_string(), "Unauthorized");
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
     #[tokio::test]
     async fn test_error_into_response_forbidden() {
         let err = Error::InvalidOrigin;
@@ -153,6 +234,20 @@ mod tests {
         assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 
+    #[tokio::test]
+    async fn test_error_into_response_forbidden_role() {
+        let response = Error::Forbidden.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_error_public_message() {
+        let err = Error::BadRequest("Name is required".to_string());
+        assert_eq!(err.public_message(), "Name is required");
+        let err = Error::Internal("connection refused".to_string());
+        assert_eq!(err.public_message(), "Internal error");
+    }
+
     #[test]
     fn test_error_from_io_error() {
         let io_err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
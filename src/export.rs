@@ -0,0 +1,316 @@
+//! Raw hits as Parquet, for loading into DuckDB, Spark or pandas with their
+//! types intact. Hits are written in row groups as the database returns
+//! them and the file goes out as it is written, so exports of any size
+//! don't have to fit in memory.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use futures_util::Stream;
+use parquet::basic::Compression;
+use parquet::data_type::{
+    BoolType, ByteArray, ByteArrayType, DataType, DoubleType, Int32Type, Int64Type,
+};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use tokio::sync::mpsc;
+
+use crate::domain::ExportedHit;
+use crate::error::{Error, Result};
+
+/// Content type of Parquet files
+pub const PARQUET: &str = "application/vnd.apache.parquet";
+
+/// Hits per row group; each group's columns are buffered until it is full
+const ROW_GROUP_ROWS: usize = 8192;
+
+/// Chunks of the file buffered between the writer and a slow client
+const CHUNK_BUFFER: usize = 16;
+
+/// Columns of an exported hit, in the order they are written
+const HIT_SCHEMA: &str = "
+message hit {
+    REQUIRED INT64 id;
+    REQUIRED BYTE_ARRAY session_id (STRING);
+    REQUIRED INT64 start_time (TIMESTAMP(MILLIS, true));
+    REQUIRED INT64 last_seen (TIMESTAMP(MILLIS, true));
+    REQUIRED BOOLEAN initial;
+    REQUIRED INT32 heartbeats;
+    REQUIRED BYTE_ARRAY tracker (STRING);
+    REQUIRED BYTE_ARRAY location (STRING);
+    REQUIRED BYTE_ARRAY referrer (STRING);
+    OPTIONAL DOUBLE load_time;
+    REQUIRED BYTE_ARRAY environment (STRING);
+    REQUIRED BOOLEAN prefetched;
+    REQUIRED BYTE_ARRAY country (STRING);
+    REQUIRED BYTE_ARRAY browser (STRING);
+    REQUIRED BYTE_ARRAY os (STRING);
+    REQUIRED BYTE_ARRAY device_type (STRING);
+    REQUIRED BOOLEAN referrer_spam;
+}
+";
+
+/// The hits of a row group not written yet, column by column
+#[derive(Default)]
+struct HitColumns {
+    id: Vec<i64>,
+    session_id: Vec<ByteArray>,
+    start_time: Vec<i64>,
+    last_seen: Vec<i64>,
+    initial: Vec<bool>,
+    heartbeats: Vec<i32>,
+    tracker: Vec<ByteArray>,
+    location: Vec<ByteArray>,
+    referrer: Vec<ByteArray>,
+    /// Load times of the hits that have one
+    load_time: Vec<f64>,
+    /// 1 for each hit with a load time, 0 for each without
+    load_time_levels: Vec<i16>,
+    environment: Vec<ByteArray>,
+    prefetched: Vec<bool>,
+    country: Vec<ByteArray>,
+    browser: Vec<ByteArray>,
+    os: Vec<ByteArray>,
+    device_type: Vec<ByteArray>,
+    referrer_spam: Vec<bool>,
+}
+
+impl HitColumns {
+    fn len(&self) -> usize {
+        self.id.len()
+    }
+
+    fn push(&mut self, exported: ExportedHit) {
+        let hit = exported.hit;
+        self.id.push(hit.id.0);
+        self.session_id
+            .push(hit.session_id.0.to_string().into_bytes().into());
+        self.start_time.push(hit.start_time.timestamp_millis());
+        self.last_seen.push(hit.last_seen.timestamp_millis());
+        self.initial.push(hit.initial);
+        self.heartbeats.push(hit.heartbeats);
+        self.tracker.push(hit.tracker.as_str().into());
+        self.location.push(hit.location.into_bytes().into());
+        self.referrer.push(hit.referrer.into_bytes().into());
+        match hit.load_time {
+            Some(load_time) => {
+                self.load_time.push(load_time);
+                self.load_time_levels.push(1);
+            }
+            None => self.load_time_levels.push(0),
+        }
+        self.environment.push(hit.environment.as_str().into());
+        self.prefetched.push(hit.prefetched);
+        self.country.push(exported.country.into_bytes().into());
+        self.browser.push(exported.browser.into_bytes().into());
+        self.os.push(exported.os.into_bytes().into());
+        self.device_type.push(exported.device_type.as_str().into());
+        self.referrer_spam.push(hit.referrer_spam);
+    }
+}
+
+/// Writes hits to a Parquet file
+pub struct HitParquetWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    columns: HitColumns,
+}
+
+impl<W: Write + Send> HitParquetWriter<W> {
+    pub fn new(out: W) -> Result<Self> {
+        let schema = Arc::new(parse_message_type(HIT_SCHEMA)?);
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_created_by(format!("shymini {}", env!("CARGO_PKG_VERSION")))
+            .build();
+        Ok(Self {
+            writer: SerializedFileWriter::new(out, schema, Arc::new(properties))?,
+            columns: HitColumns::default(),
+        })
+    }
+
+    pub fn push(&mut self, hit: ExportedHit) -> Result<()> {
+        self.columns.push(hit);
+        if self.columns.len() >= ROW_GROUP_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the last row group and the footer, and hand back the output
+    pub fn finish(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer.into_inner()?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.columns.len() == 0 {
+            return Ok(());
+        }
+        let columns = std::mem::take(&mut self.columns);
+        let mut group = self.writer.next_row_group()?;
+        write_column::<_, Int64Type>(&mut group, &columns.id, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.session_id, None)?;
+        write_column::<_, Int64Type>(&mut group, &columns.start_time, None)?;
+        write_column::<_, Int64Type>(&mut group, &columns.last_seen, None)?;
+        write_column::<_, BoolType>(&mut group, &columns.initial, None)?;
+        write_column::<_, Int32Type>(&mut group, &columns.heartbeats, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.tracker, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.location, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.referrer, None)?;
+        write_column::<_, DoubleType>(
+            &mut group,
+            &columns.load_time,
+            Some(&columns.load_time_levels),
+        )?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.environment, None)?;
+        write_column::<_, BoolType>(&mut group, &columns.prefetched, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.country, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.browser, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.os, None)?;
+        write_column::<_, ByteArrayType>(&mut group, &columns.device_type, None)?;
+        write_column::<_, BoolType>(&mut group, &columns.referrer_spam, None)?;
+        group.close()?;
+        Ok(())
+    }
+}
+
+/// Write the row group's next column
+fn write_column<W: Write + Send, T: DataType>(
+    group: &mut SerializedRowGroupWriter<'_, W>,
+    values: &[T::T],
+    levels: Option<&[i16]>,
+) -> Result<()> {
+    let mut column = group
+        .next_column()?
+        .ok_or_else(|| Error::Internal("More hit columns than in the schema".to_string()))?;
+    column.typed::<T>().write_batch(values, levels, None)?;
+    column.close()?;
+    Ok(())
+}
+
+/// Sends what the Parquet writer writes on to the response body
+struct ChunkSender(mpsc::Sender<Result<Vec<u8>>>);
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Export client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The hits received, as a Parquet file in chunks. The file is written on a
+/// blocking thread; an error cuts it off before its footer, so clients can
+/// tell the export is incomplete.
+pub fn hits_parquet(
+    mut hits: mpsc::Receiver<Result<ExportedHit>>,
+) -> impl Stream<Item = Result<Vec<u8>>> {
+    let (chunks, received) = mpsc::channel(CHUNK_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let errors = chunks.clone();
+        let written = (|| {
+            let mut writer = HitParquetWriter::new(ChunkSender(chunks))?;
+            while let Some(hit) = hits.blocking_recv() {
+                writer.push(hit?)?;
+            }
+            writer.finish()
+        })();
+        if let Err(e) = written {
+            let _ = errors.blocking_send(Err(e));
+        }
+    });
+    futures_util::stream::unfold(received, |mut received| async move {
+        let chunk = received.recv().await?;
+        Some((chunk, received))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{DeviceType, Environment, Hit, HitId, ServiceId, SessionId, TrackerType};
+    use axum::body::Bytes;
+    use chrono::{TimeZone, Utc};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+    use uuid::Uuid;
+
+    fn exported(id: i64, load_time: Option<f64>) -> ExportedHit {
+        let time = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        ExportedHit {
+            hit: Hit {
+                id: HitId(id),
+                session_id: SessionId(Uuid::nil()),
+                service_id: ServiceId(Uuid::nil()),
+                initial: id == 1,
+                start_time: time,
+                last_seen: time,
+                heartbeats: 2,
+                tracker: TrackerType::Js,
+                location: format!("/page/{}", id),
+                referrer: String::new(),
+                load_time,
+                environment: Environment::Production,
+                prefetched: false,
+                referrer_spam: false,
+            },
+            country: "DE".to_string(),
+            browser: "Firefox".to_string(),
+            os: "Linux".to_string(),
+            device_type: DeviceType::Desktop,
+        }
+    }
+
+    #[test]
+    fn test_hit_parquet_writer() {
+        let mut writer = HitParquetWriter::new(Vec::new()).unwrap();
+        for id in 1..=ROW_GROUP_ROWS as i64 + 5 {
+            let load_time = (id % 2 == 0).then_some(id as f64);
+            writer.push(exported(id, load_time)).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 2);
+        assert_eq!(
+            metadata.file_metadata().num_rows(),
+            ROW_GROUP_ROWS as i64 + 5
+        );
+        assert_eq!(metadata.file_metadata().schema_descr().num_columns(), 17);
+
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .take(2)
+            .map(|row| row.unwrap())
+            .collect();
+        let first = &rows[0];
+        assert_eq!(first.get_long(0).unwrap(), 1);
+        assert!(first.get_bool(4).unwrap());
+        assert_eq!(first.get_string(6).unwrap(), "JS");
+        assert_eq!(first.get_string(7).unwrap(), "/page/1");
+        assert!(first.get_double(9).is_err());
+        assert_eq!(first.get_string(15).unwrap(), "DESKTOP");
+        assert_eq!(
+            first.get_timestamp_millis(2).unwrap(),
+            Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0)
+                .unwrap()
+                .timestamp_millis()
+        );
+        assert_eq!(rows[1].get_double(9).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_empty_hit_parquet() {
+        let file = HitParquetWriter::new(Vec::new()).unwrap().finish().unwrap();
+        let reader = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 0);
+    }
+}
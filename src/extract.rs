@@ -0,0 +1,149 @@
+//! Typed path parameters. Handlers take their IDs already parsed, as
+//! `Path<ServiceId>` or `Path<(ServiceId, SessionId)>`; a malformed ID is
+//! refused with a 400 before the handler runs. The dashboard and the API
+//! each wrap [`path_params`] in a `Path` extractor answering in their own
+//! format.
+
+use axum::{
+    extract::{FromRequestParts, RawPathParams},
+    http::request::Parts,
+};
+
+use crate::domain::{
+    ApiTokenId, LinkId, SavedViewId, SegmentId, ServiceId, SessionId, TrackingId, UserId,
+};
+use crate::error::{Error, Result};
+
+/// Longest tracking ID accepted in a path
+const MAX_TRACKING_ID_LENGTH: usize = 64;
+
+/// A value filling one path segment
+pub trait PathId: Sized {
+    /// What the value is called when it is malformed
+    const NAME: &'static str;
+
+    fn parse_segment(segment: &str) -> Option<Self>;
+}
+
+macro_rules! uuid_path_id {
+    ($($id:ty => $name:literal),* $(,)?) => {
+        $(impl PathId for $id {
+            const NAME: &'static str = $name;
+
+            fn parse_segment(segment: &str) -> Option<Self> {
+                segment.parse().ok()
+            }
+        })*
+    };
+}
+
+uuid_path_id! {
+    ServiceId => "service",
+    SessionId => "session",
+    SavedViewId => "view",
+    SegmentId => "segment",
+    UserId => "user",
+    ApiTokenId => "token",
+    LinkId => "link",
+}
+
+impl PathId for TrackingId {
+    const NAME: &'static str = "tracking";
+
+    fn parse_segment(segment: &str) -> Option<Self> {
+        let valid = !segment.is_empty()
+            && segment.len() <= MAX_TRACKING_ID_LENGTH
+            && segment.bytes().all(|b| b.is_ascii_alphanumeric());
+        valid.then(|| Self(segment.to_string()))
+    }
+}
+
+/// Tokens from emailed links; any segment is one
+impl PathId for String {
+    const NAME: &'static str = "link";
+
+    fn parse_segment(segment: &str) -> Option<Self> {
+        Some(segment.to_string())
+    }
+}
+
+/// The path parameters of a route: one value, or two
+pub trait FromPathParams: Sized {
+    fn from_params(values: &[&str]) -> Result<Self>;
+}
+
+fn parse<T: PathId>(segment: &str) -> Result<T> {
+    T::parse_segment(segment).ok_or_else(|| Error::BadRequest(format!("Invalid {} ID", T::NAME)))
+}
+
+impl<T: PathId> FromPathParams for T {
+    fn from_params(values: &[&str]) -> Result<Self> {
+        match values {
+            [value] => parse(value),
+            _ => Err(Error::Internal(format!(
+                "expected 1 path parameter, found {}",
+                values.len()
+            ))),
+        }
+    }
+}
+
+impl<A: PathId, B: PathId> FromPathParams for (A, B) {
+    fn from_params(values: &[&str]) -> Result<Self> {
+        match values {
+            [a, b] => Ok((parse(a)?, parse(b)?)),
+            _ => Err(Error::Internal(format!(
+                "expected 2 path parameters, found {}",
+                values.len()
+            ))),
+        }
+    }
+}
+
+/// Parse the path parameters of a request
+pub async fn path_params<T, S>(parts: &mut Parts, state: &S) -> Result<T>
+where
+    T: FromPathParams,
+    S: Send + Sync,
+{
+    let params = RawPathParams::from_request_parts(parts, state)
+        .await
+        .map_err(|e| Error::BadRequest(e.body_text()))?;
+    let values: Vec<&str> = params.iter().map(|(_, value)| value).collect();
+    T::from_params(&values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_uuid_ids() {
+        let id = ServiceId::new();
+        let parsed: ServiceId = FromPathParams::from_params(&[&id.to_string()]).unwrap();
+        assert_eq!(parsed, id);
+
+        let err = ServiceId::from_params(&["not-an-id"]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid service ID");
+    }
+
+    #[test]
+    fn test_parse_pair_names_the_bad_id() {
+        let service_id = ServiceId::new().to_string();
+        let err = <(ServiceId, SessionId)>::from_params(&[&service_id, "nope"]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid session ID");
+
+        let err = <(ServiceId, SessionId)>::from_params(&[&service_id]).unwrap_err();
+        assert!(err.status().is_server_error());
+    }
+
+    #[test]
+    fn test_parse_tracking_id() {
+        let parsed = TrackingId::from_params(&["abc12345"]).unwrap();
+        assert_eq!(parsed, TrackingId("abc12345".to_string()));
+
+        assert!(TrackingId::from_params(&[""]).is_err());
+        assert!(TrackingId::from_params(&["abc/../x"]).is_err());
+        assert!(TrackingId::from_params(&[&"a".repeat(65)]).is_err());
+    }
+}
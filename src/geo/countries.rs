@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+
+use crate::domain::{Continent, ContinentCount, CoreStats, CountedItem, CountryInfo};
+use crate::i18n::I18n;
+
+/// Continent of every ISO 3166-1 alpha-2 code, plus `XK` (Kosovo) which
+/// MaxMind reports. Sorted by code for binary search.
+const CONTINENTS: &[(&str, Continent)] = &[
+    ("AD", Continent::Europe),
+    ("AE", Continent::Asia),
+    ("AF", Continent::Asia),
+    ("AG", Continent::NorthAmerica),
+    ("AI", Continent::NorthAmerica),
+    ("AL", Continent::Europe),
+    ("AM", Continent::Asia),
+    ("AO", Continent::Africa),
+    ("AQ", Continent::Antarctica),
+    ("AR", Continent::SouthAmerica),
+    ("AS", Continent::Oceania),
+    ("AT", Continent::Europe),
+    ("AU", Continent::Oceania),
+    ("AW", Continent::NorthAmerica),
+    ("AX", Continent::Europe),
+    ("AZ", Continent::Asia),
+    ("BA", Continent::Europe),
+    ("BB", Continent::NorthAmerica),
+    ("BD", Continent::Asia),
+    ("BE", Continent::Europe),
+    ("BF", Continent::Africa),
+    ("BG", Continent::Europe),
+    ("BH", Continent::Asia),
+    ("BI", Continent::Africa),
+    ("BJ", Continent::Africa),
+    ("BL", Continent::NorthAmerica),
+    ("BM", Continent::NorthAmerica),
+    ("BN", Continent::Asia),
+    ("BO", Continent::SouthAmerica),
+    ("BQ", Continent::NorthAmerica),
+    ("BR", Continent::SouthAmerica),
+    ("BS", Continent::NorthAmerica),
+    ("BT", Continent::Asia),
+    ("BV", Continent::Antarctica),
+    ("BW", Continent::Africa),
+    ("BY", Continent::Europe),
+    ("BZ", Continent::NorthAmerica),
+    ("CA", Continent::NorthAmerica),
+    ("CC", Continent::Asia),
+    ("CD", Continent::Africa),
+    ("CF", Continent::Africa),
+    ("CG", Continent::Africa),
+    ("CH", Continent::Europe),
+    ("CI", Continent::Africa),
+    ("CK", Continent::Oceania),
+    ("CL", Continent::SouthAmerica),
+    ("CM", Continent::Africa),
+    ("CN", Continent::Asia),
+    ("CO", Continent::SouthAmerica),
+    ("CR", Continent::NorthAmerica),
+    ("CU", Continent::NorthAmerica),
+    ("CV", Continent::Africa),
+    ("CW", Continent::NorthAmerica),
+    ("CX", Continent::Asia),
+    ("CY", Continent::Asia),
+    ("CZ", Continent::Europe),
+    ("DE", Continent::Europe),
+    ("DJ", Continent::Africa),
+    ("DK", Continent::Europe),
+    ("DM", Continent::NorthAmerica),
+    ("DO", Continent::NorthAmerica),
+    ("DZ", Continent::Africa),
+    ("EC", Continent::SouthAmerica),
+    ("EE", Continent::Europe),
+    ("EG", Continent::Africa),
+    ("EH", Continent::Africa),
+    ("ER", Continent::Africa),
+    ("ES", Continent::Europe),
+    ("ET", Continent::Africa),
+    ("FI", Continent::Europe),
+    ("FJ", Continent::Oceania),
+    ("FK", Continent::SouthAmerica),
+    ("FM", Continent::Oceania),
+    ("FO", Continent::Europe),
+    ("FR", Continent::Europe),
+    ("GA", Continent::Africa),
+    ("GB", Continent::Europe),
+    ("GD", Continent::NorthAmerica),
+    ("GE", Continent::Asia),
+    ("GF", Continent::SouthAmerica),
+    ("GG", Continent::Europe),
+    ("GH", Continent::Africa),
+    ("GI", Continent::Europe),
+    ("GL", Continent::NorthAmerica),
+    ("GM", Continent::Africa),
+    ("GN", Continent::Africa),
+    ("GP", Continent::NorthAmerica),
+    ("GQ", Continent::Africa),
+    ("GR", Continent::Europe),
+    ("GS", Continent::Antarctica),
+    ("GT", Continent::NorthAmerica),
+    ("GU", Continent::Oceania),
+    ("GW", Continent::Africa),
+    ("GY", Continent::SouthAmerica),
+    ("HK", Continent::Asia),
+    ("HM", Continent::Antarctica),
+    ("HN", Continent::NorthAmerica),
+    ("HR", Continent::Europe),
+    ("HT", Continent::NorthAmerica),
+    ("HU", Continent::Europe),
+    ("ID", Continent::Asia),
+    ("IE", Continent::Europe),
+    ("IL", Continent::Asia),
+    ("IM", Continent::Europe),
+    ("IN", Continent::Asia),
+    ("IO", Continent::Asia),
+    ("IQ", Continent::Asia),
+    ("IR", Continent::Asia),
+    ("IS", Continent::Europe),
+    ("IT", Continent::Europe),
+    ("JE", Continent::Europe),
+    ("JM", Continent::NorthAmerica),
+    ("JO", Continent::Asia),
+    ("JP", Continent::Asia),
+    ("KE", Continent::Africa),
+    ("KG", Continent::Asia),
+    ("KH", Continent::Asia),
+    ("KI", Continent::Oceania),
+    ("KM", Continent::Africa),
+    ("KN", Continent::NorthAmerica),
+    ("KP", Continent::Asia),
+    ("KR", Continent::Asia),
+    ("KW", Continent::Asia),
+    ("KY", Continent::NorthAmerica),
+    ("KZ", Continent::Asia),
+    ("LA", Continent::Asia),
+    ("LB", Continent::Asia),
+    ("LC", Continent::NorthAmerica),
+    ("LI", Continent::Europe),
+    ("LK", Continent::Asia),
+    ("LR", Continent::Africa),
+    ("LS", Continent::Africa),
+    ("LT", Continent::Europe),
+    ("LU", Continent::Europe),
+    ("LV", Continent::Europe),
+    ("LY", Continent::Africa),
+    ("MA", Continent::Africa),
+    ("MC", Continent::Europe),
+    ("MD", Continent::Europe),
+    ("ME", Continent::Europe),
+    ("MF", Continent::NorthAmerica),
+    ("MG", Continent::Africa),
+    ("MH", Continent::Oceania),
+    ("MK", Continent::Europe),
+    ("ML", Continent::Africa),
+    ("MM", Continent::Asia),
+    ("MN", Continent::Asia),
+    ("MO", Continent::Asia),
+    ("MP", Continent::Oceania),
+    ("MQ", Continent::NorthAmerica),
+    ("MR", Continent::Africa),
+    ("MS", Continent::NorthAmerica),
+    ("MT", Continent::Europe),
+    ("MU", Continent::Africa),
+    ("MV", Continent::Asia),
+    ("MW", Continent::Africa),
+    ("MX", Continent::NorthAmerica),
+    ("MY", Continent::Asia),
+    ("MZ", Continent::Africa),
+    ("NA", Continent::Africa),
+    ("NC", Continent::Oceania),
+    ("NE", Continent::Africa),
+    ("NF", Continent::Oceania),
+    ("NG", Continent::Africa),
+    ("NI", Continent::NorthAmerica),
+    ("NL", Continent::Europe),
+    ("NO", Continent::Europe),
+    ("NP", Continent::Asia),
+    ("NR", Continent::Oceania),
+    ("NU", Continent::Oceania),
+    ("NZ", Continent::Oceania),
+    ("OM", Continent::Asia),
+    ("PA", Continent::NorthAmerica),
+    ("PE", Continent::SouthAmerica),
+    ("PF", Continent::Oceania),
+    ("PG", Continent::Oceania),
+    ("PH", Continent::Asia),
+    ("PK", Continent::Asia),
+    ("PL", Continent::Europe),
+    ("PM", Continent::NorthAmerica),
+    ("PN", Continent::Oceania),
+    ("PR", Continent::NorthAmerica),
+    ("PS", Continent::Asia),
+    ("PT", Continent::Europe),
+    ("PW", Continent::Oceania),
+    ("PY", Continent::SouthAmerica),
+    ("QA", Continent::Asia),
+    ("RE", Continent::Africa),
+    ("RO", Continent::Europe),
+    ("RS", Continent::Europe),
+    ("RU", Continent::Europe),
+    ("RW", Continent::Africa),
+    ("SA", Continent::Asia),
+    ("SB", Continent::Oceania),
+    ("SC", Continent::Africa),
+    ("SD", Continent::Africa),
+    ("SE", Continent::Europe),
+    ("SG", Continent::Asia),
+    ("SH", Continent::Africa),
+    ("SI", Continent::Europe),
+    ("SJ", Continent::Europe),
+    ("SK", Continent::Europe),
+    ("SL", Continent::Africa),
+    ("SM", Continent::Europe),
+    ("SN", Continent::Africa),
+    ("SO", Continent::Africa),
+    ("SR", Continent::SouthAmerica),
+    ("SS", Continent::Africa),
+    ("ST", Continent::Africa),
+    ("SV", Continent::NorthAmerica),
+    ("SX", Continent::NorthAmerica),
+    ("SY", Continent::Asia),
+    ("SZ", Continent::Africa),
+    ("TC", Continent::NorthAmerica),
+    ("TD", Continent::Africa),
+    ("TF", Continent::Antarctica),
+    ("TG", Continent::Africa),
+    ("TH", Continent::Asia),
+    ("TJ", Continent::Asia),
+    ("TK", Continent::Oceania),
+    ("TL", Continent::Asia),
+    ("TM", Continent::Asia),
+    ("TN", Continent::Africa),
+    ("TO", Continent::Oceania),
+    ("TR", Continent::Asia),
+    ("TT", Continent::NorthAmerica),
+    ("TV", Continent::Oceania),
+    ("TW", Continent::Asia),
+    ("TZ", Continent::Africa),
+    ("UA", Continent::Europe),
+    ("UG", Continent::Africa),
+    ("UM", Continent::Oceania),
+    ("US", Continent::NorthAmerica),
+    ("UY", Continent::SouthAmerica),
+    ("UZ", Continent::Asia),
+    ("VA", Continent::Europe),
+    ("VC", Continent::NorthAmerica),
+    ("VE", Continent::SouthAmerica),
+    ("VG", Continent::NorthAmerica),
+    ("VI", Continent::NorthAmerica),
+    ("VN", Continent::Asia),
+    ("VU", Continent::Oceania),
+    ("WF", Continent::Oceania),
+    ("WS", Continent::Oceania),
+    ("XK", Continent::Europe),
+    ("YE", Continent::Asia),
+    ("YT", Continent::Africa),
+    ("ZA", Continent::Africa),
+    ("ZM", Continent::Africa),
+    ("ZW", Continent::Africa),
+];
+
+/// Continent a country code belongs to
+pub fn continent(code: &str) -> Option<Continent> {
+    let code = code.trim().to_ascii_uppercase();
+    CONTINENTS
+        .binary_search_by(|(c, _)| (*c).cmp(code.as_str()))
+        .ok()
+        .map(|i| CONTINENTS[i].1)
+}
+
+/// Emoji flag for a two-letter country code, made of the matching regional
+/// indicator symbols. Empty for anything that isn't two ASCII letters.
+pub fn flag(code: &str) -> String {
+    let code = code.trim();
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return String::new();
+    }
+    code.to_ascii_uppercase()
+        .chars()
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+        .collect()
+}
+
+/// Name, flag and continent for a country code
+pub fn country_info(code: &str, i18n: &I18n) -> CountryInfo {
+    CountryInfo {
+        code: code.to_string(),
+        name: i18n.country(code),
+        flag: flag(code),
+        continent: continent(code),
+    }
+}
+
+/// Attach country metadata to country counts
+pub fn enrich_countries(items: &mut [CountedItem], i18n: &I18n) {
+    for item in items {
+        item.country = Some(country_info(&item.value, i18n));
+    }
+}
+
+/// Sum country counts per continent, largest first. Unknown countries are
+/// left out.
+pub fn continent_rollup(items: &[CountedItem], i18n: &I18n) -> Vec<ContinentCount> {
+    let mut totals: HashMap<Continent, i64> = HashMap::new();
+    for item in items {
+        if let Some(continent) = continent(&item.value) {
+            *totals.entry(continent).or_insert(0) += item.count;
+        }
+    }
+
+    let mut rollup: Vec<ContinentCount> = totals
+        .into_iter()
+        .map(|(continent, count)| ContinentCount {
+            continent,
+            name: i18n.variant("continent", continent.as_str()),
+            count,
+        })
+        .collect();
+    rollup.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.continent.as_str().cmp(b.continent.as_str()))
+    });
+    rollup
+}
+
+/// Enrich the country counts of stats (and their comparison period) and fill
+/// in the continent rollup
+pub fn localize_stats(stats: &mut CoreStats, i18n: &I18n) {
+    enrich_countries(&mut stats.countries, i18n);
+    stats.continents = continent_rollup(&stats.countries, i18n);
+    if let Some(compare) = stats.compare.as_mut() {
+        localize_stats(compare, i18n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Locale;
+
+    #[test]
+    fn test_table_is_sorted() {
+        assert!(CONTINENTS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_continent() {
+        assert_eq!(continent("US"), Some(Continent::NorthAmerica));
+        assert_eq!(continent("de"), Some(Continent::Europe));
+        assert_eq!(continent("BR"), Some(Continent::SouthAmerica));
+        assert_eq!(continent("XK"), Some(Continent::Europe));
+        assert_eq!(continent("ZZ"), None);
+        assert_eq!(continent(""), None);
+    }
+
+    #[test]
+    fn test_flag() {
+        assert_eq!(flag("US"), "🇺🇸");
+        assert_eq!(flag("de"), "🇩🇪");
+        assert_eq!(flag(""), "");
+        assert_eq!(flag("USA"), "");
+        assert_eq!(flag("1A"), "");
+    }
+
+    #[test]
+    fn test_every_code_has_a_name() {
+        for locale in Locale::ALL {
+            let i18n = I18n::new(locale);
+            for (code, _) in CONTINENTS {
+                assert_ne!(i18n.country(code), *code, "{} has no {} name", code, locale);
+            }
+        }
+    }
+
+    #[test]
+    fn test_enrich_and_rollup() {
+        let i18n = I18n::new(Locale::De);
+        let mut items = vec![
+            CountedItem::new("US".to_string(), 5),
+            CountedItem::new("DE".to_string(), 3),
+            CountedItem::new("FR".to_string(), 4),
+            CountedItem::new(String::new(), 2),
+        ];
+        enrich_countries(&mut items, &i18n);
+
+        let us = items[0].country.as_ref().unwrap();
+        assert_eq!(us.name, "Vereinigte Staaten");
+        assert_eq!(us.flag, "🇺🇸");
+        assert_eq!(us.continent, Some(Continent::NorthAmerica));
+        let unknown = items[3].country.as_ref().unwrap();
+        assert_eq!(unknown.name, "Unbekannt");
+        assert_eq!(unknown.continent, None);
+
+        let rollup = continent_rollup(&items, &i18n);
+        assert_eq!(rollup.len(), 2);
+        assert_eq!(rollup[0].continent, Continent::Europe);
+        assert_eq!(rollup[0].name, "Europa");
+        assert_eq!(rollup[0].count, 7);
+        assert_eq!(rollup[1].continent, Continent::NorthAmerica);
+        assert_eq!(rollup[1].count, 5);
+    }
+}
@@ -1,13 +1,19 @@
+pub mod countries;
+
 use maxminddb::{geoip2, Reader};
+use serde::Serialize;
 use std::net::IpAddr;
 use std::path::Path;
 use tracing::{debug, warn};
 
 use crate::error::Result;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct GeoIpData {
+    /// Name of the network's organization
     pub asn: String,
+    /// Number of the autonomous system the address is in
+    pub asn_number: Option<u32>,
     pub country: String,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
@@ -93,6 +99,7 @@ impl GeoIpLookup {
         // ASN lookup
         if let Some(ref reader) = self.asn_reader {
             if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip_addr) {
+                data.asn_number = asn.autonomous_system_number;
                 data.asn = asn
                     .autonomous_system_organization
                     .unwrap_or_default()
@@ -0,0 +1,208 @@
+//! Guards around every request, so one stuck or broken handler doesn't
+//! take its connection down with it: requests running past
+//! `request_timeout_secs` are answered with a 503, and a panicking handler
+//! with a 500 instead of a dropped connection. Both responses name the
+//! request's ID (`x-request-id`, from a proxy in front or made up here),
+//! which the log line about them carries too.
+
+use std::any::Any;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower::util::option_layer;
+use tower_http::catch_panic::CatchPanicLayer;
+use tracing::{error, warn, Span};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// A response given in place of a handler's
+#[derive(Debug, Clone)]
+struct Failure {
+    /// What the client is told
+    message: &'static str,
+    /// What the log is told
+    detail: String,
+}
+
+/// `router` with the guards around it. The request ID is set further out,
+/// where the trace span can see it too.
+pub fn guard<S>(router: Router<S>, timeout: Option<Duration>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(option_layer(timeout.map(|timeout| {
+            middleware::from_fn_with_state(timeout, request_timeout)
+        })))
+        .layer(CatchPanicLayer::custom(panic_response))
+        .layer(middleware::from_fn(report_failures))
+}
+
+/// The span a request's trace is logged in, as tower-http's default one
+/// with the request's ID added
+pub fn request_span(request: &Request) -> Span {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("-");
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        id,
+    )
+}
+
+async fn request_timeout(
+    State(timeout): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => failure(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The request took too long",
+            format!("{} {} took longer than {:?}", method, path, timeout),
+        ),
+    }
+}
+
+fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("no message");
+    failure(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal error",
+        format!("Handler panicked: {}", message),
+    )
+}
+
+fn failure(status: StatusCode, message: &'static str, detail: String) -> Response {
+    let mut response = (status, message).into_response();
+    response
+        .extensions_mut()
+        .insert(Failure { message, detail });
+    response
+}
+
+/// Log failures and tell the client the request's ID, to find it in the log
+async fn report_failures(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    let response = next.run(request).await;
+    let Some(failure) = response.extensions().get::<Failure>().cloned() else {
+        return response;
+    };
+
+    let status = response.status();
+    if status == StatusCode::SERVICE_UNAVAILABLE {
+        warn!("Request {}: {}", id, failure.detail);
+    } else {
+        error!("Request {}: {}", id, failure.detail);
+    }
+    let mut response = (status, format!("{} (request {})", failure.message, id)).into_response();
+    response.extensions_mut().insert(failure);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        guard(
+            Router::new()
+                .route("/ok", get(|| async { "fine" }))
+                .route(
+                    "/slow",
+                    get(|| async {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        "late"
+                    }),
+                )
+                .route(
+                    "/panic",
+                    get(|| async {
+                        if true {
+                            panic!("boom");
+                        }
+                        "unreachable"
+                    }),
+                ),
+            Some(Duration::from_millis(50)),
+        )
+    }
+
+    async fn get_text(router: Router, path: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .uri(path)
+            .header(REQUEST_ID_HEADER, "req-1")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_guards() {
+        assert_eq!(
+            get_text(router(), "/ok").await,
+            (StatusCode::OK, "fine".to_string())
+        );
+        assert_eq!(
+            get_text(router(), "/slow").await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The request took too long (request req-1)".to_string()
+            )
+        );
+        assert_eq!(
+            get_text(router(), "/panic").await,
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal error (request req-1)".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_timeout() {
+        let router = guard(
+            Router::new().route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    "late"
+                }),
+            ),
+            None,
+        );
+        assert_eq!(
+            get_text(router, "/slow").await,
+            (StatusCode::OK, "late".to_string())
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Extension points for forks and downstream builds. An `IngressHook` sees
+//! every tracker request before it is recorded, and may change or drop it,
+//! and hears about each recorded hit; a `StatsHook` may adjust the stats a
+//! service's dashboard, API and reports show, or add its own values under
+//! `CoreStats::extra`. Hooks are registered at compile time in
+//! `plugins::register`, built with the `plugins` feature, and run in the
+//! order they were added. A failing hook is logged and skipped, so it
+//! never costs a hit or a stats page.
+
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use tracing::error;
+
+use crate::domain::{CoreStats, HitId, Service, SessionId, TrackerType};
+use crate::error::Result;
+use crate::ingress::IngressPayload;
+use crate::state::AppState;
+
+/// What to do with a tracker request after an `IngressHook` saw it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngressAction {
+    /// Record it, with the payload as the hook left it
+    Record,
+    /// Record nothing
+    Drop,
+}
+
+/// The request a payload came with
+pub struct IngressContext<'a> {
+    pub service: &'a Service,
+    pub tracker: TrackerType,
+    pub time: DateTime<Utc>,
+    pub ip: &'a str,
+    pub user_agent: &'a str,
+    pub identifier: &'a str,
+}
+
+/// A hit as it was recorded
+pub struct RecordedHit<'a> {
+    pub service: &'a Service,
+    pub session_id: SessionId,
+    pub hit_id: HitId,
+    /// The hit started its session
+    pub initial: bool,
+    pub time: DateTime<Utc>,
+    pub payload: &'a IngressPayload,
+}
+
+#[async_trait]
+pub trait IngressHook: Send + Sync {
+    /// Called with the cleaned payload before anything is recorded,
+    /// including for the end-of-page-view signals
+    async fn before_record(
+        &self,
+        _state: &AppState,
+        _context: &IngressContext<'_>,
+        _payload: &mut IngressPayload,
+    ) -> Result<IngressAction> {
+        Ok(IngressAction::Record)
+    }
+
+    /// Called once a page view or heartbeat is stored
+    async fn after_record(&self, _state: &AppState, _hit: &RecordedHit<'_>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait StatsHook: Send + Sync {
+    /// Called with a service's stats before they are shown or returned
+    async fn adjust_stats(
+        &self,
+        state: &AppState,
+        service: &Service,
+        stats: &mut CoreStats,
+    ) -> Result<()>;
+}
+
+/// The registered hooks, shared through `AppState`
+#[derive(Default, Clone)]
+pub struct Hooks {
+    ingress: Vec<Arc<dyn IngressHook>>,
+    stats: Vec<Arc<dyn StatsHook>>,
+}
+
+impl Hooks {
+    pub fn with_ingress(mut self, hook: impl IngressHook + 'static) -> Self {
+        self.ingress.push(Arc::new(hook));
+        self
+    }
+
+    pub fn with_stats(mut self, hook: impl StatsHook + 'static) -> Self {
+        self.stats.push(Arc::new(hook));
+        self
+    }
+
+    /// The hooks this build registers: those of `plugins::register` with
+    /// the `plugins` feature, none without
+    pub fn registered() -> Self {
+        #[cfg(feature = "plugins")]
+        return crate::plugins::register(Self::default());
+        #[cfg(not(feature = "plugins"))]
+        Self::default()
+    }
+
+    /// Run the ingress hooks over a payload; `Drop` as soon as one drops it
+    pub async fn before_record(
+        &self,
+        state: &AppState,
+        context: &IngressContext<'_>,
+        payload: &mut IngressPayload,
+    ) -> IngressAction {
+        for hook in &self.ingress {
+            match hook.before_record(state, context, payload).await {
+                Ok(IngressAction::Record) => {}
+                Ok(IngressAction::Drop) => return IngressAction::Drop,
+                Err(e) => error!("Ingress hook failed: {}", e),
+            }
+        }
+        IngressAction::Record
+    }
+
+    pub async fn after_record(&self, state: &AppState, hit: &RecordedHit<'_>) {
+        for hook in &self.ingress {
+            if let Err(e) = hook.after_record(state, hit).await {
+                error!("Ingress hook failed after recording: {}", e);
+            }
+        }
+    }
+
+    pub async fn adjust_stats(&self, state: &AppState, service: &Service, stats: &mut CoreStats) {
+        for hook in &self.stats {
+            if let Err(e) = hook.adjust_stats(state, service, stats).await {
+                error!("Stats hook failed: {}", e);
+            }
+        }
+    }
+}
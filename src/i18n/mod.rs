@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use axum::http::{header, HeaderMap};
+
+use crate::domain::{HourCycle, WeekStart};
+use crate::geo;
+
+/// Dashboard languages with a translation catalog under `locales/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    pub const ALL: [Self; 2] = [Self::En, Self::De];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+        }
+    }
+
+    /// Parse a language tag, matching on its primary subtag (`de-AT` -> `De`)
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        let primary = s.trim().split(['-', '_']).next().unwrap_or("");
+        Self::ALL
+            .into_iter()
+            .find(|locale| locale.as_str().eq_ignore_ascii_case(primary))
+    }
+
+    /// Separator between a number's integer and fractional digits
+    pub fn decimal_separator(&self) -> &'static str {
+        match self {
+            Self::En => ".",
+            Self::De => ",",
+        }
+    }
+
+    /// Separator between groups of three integer digits
+    pub fn group_separator(&self) -> &'static str {
+        match self {
+            Self::En => ",",
+            Self::De => ".",
+        }
+    }
+
+    /// Customary first day of the week where the language is spoken
+    pub fn week_start(&self) -> WeekStart {
+        match self {
+            Self::En => WeekStart::Sunday,
+            Self::De => WeekStart::Monday,
+        }
+    }
+
+    /// Customary clock
+    pub fn hour_cycle(&self) -> HourCycle {
+        match self {
+            Self::En => HourCycle::H12,
+            Self::De => HourCycle::H23,
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        match self {
+            Self::En => include_str!("../../locales/en.ftl"),
+            Self::De => include_str!("../../locales/de.ftl"),
+        }
+    }
+
+    /// Pick the best supported locale from an Accept-Language header value,
+    /// falling back to `default` when nothing matches
+    pub fn negotiate(accept_language: Option<&str>, default: Locale) -> Locale {
+        let Some(accept) = accept_language else {
+            return default;
+        };
+
+        let mut best: Option<(f32, Locale)> = None;
+        for part in accept.split(',') {
+            let mut params = part.split(';').map(str::trim);
+            let tag = params.next().unwrap_or("");
+            let quality = params
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+            let locale = if tag == "*" {
+                Some(default)
+            } else {
+                Locale::from_str(tag)
+            };
+            let Some(locale) = locale else {
+                continue;
+            };
+            let better = match best {
+                Some((q, _)) => quality > q,
+                None => true,
+            };
+            if better {
+                best = Some((quality, locale));
+            }
+        }
+
+        best.map(|(_, locale)| locale).unwrap_or(default)
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+type Catalog = HashMap<&'static str, &'static str>;
+
+/// Parse a catalog written in a small subset of Fluent: one `key = value`
+/// message per line, `#` comments, and `{ $name }` placeholders
+fn parse_catalog(source: &'static str) -> Catalog {
+    source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
+
+fn catalogs() -> &'static HashMap<Locale, Catalog> {
+    static CATALOGS: OnceLock<HashMap<Locale, Catalog>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        Locale::ALL
+            .into_iter()
+            .map(|locale| (locale, parse_catalog(locale.source())))
+            .collect()
+    })
+}
+
+/// Message lookup for one request, passed to every dashboard template
+#[derive(Debug, Clone, Copy, Default)]
+pub struct I18n {
+    locale: Locale,
+}
+
+impl I18n {
+    pub fn new(locale: Locale) -> Self {
+        Self { locale }
+    }
+
+    /// Negotiate the locale from the request's Accept-Language header, using
+    /// the configured locale when the browser asks for nothing we ship
+    pub fn from_headers(headers: &HeaderMap, default_locale: &str) -> Self {
+        let default = Locale::from_str(default_locale).unwrap_or_default();
+        let accept = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok());
+        Self::new(Locale::negotiate(accept, default))
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+
+    /// Language tag for the `<html lang>` attribute
+    pub fn lang(&self) -> &'static str {
+        self.locale.as_str()
+    }
+
+    fn get(&self, key: &str) -> Option<&'static str> {
+        let catalogs = catalogs();
+        catalogs[&self.locale]
+            .get(key)
+            .or_else(|| catalogs[&Locale::En].get(key))
+            .copied()
+    }
+
+    /// Look up a message, falling back to English and then to the key itself
+    pub fn t<'a>(&self, key: &'a str) -> &'a str {
+        self.get(key).unwrap_or(key)
+    }
+
+    /// Look up a message and fill in its single `{ $name }` placeholder
+    pub fn t1(&self, key: &str, name: &str, value: impl fmt::Display) -> String {
+        self.t(key)
+            .replace(&format!("{{ ${} }}", name), &value.to_string())
+    }
+
+    /// Look up the message for an enum variant, e.g. `panel-chart`
+    pub fn variant(&self, prefix: &str, variant: &str) -> String {
+        self.t(&format!("{}-{}", prefix, variant)).to_string()
+    }
+
+    /// Localized name for an ISO country code: "Unknown" when empty, the code
+    /// itself when no catalog has a name for it
+    pub fn country(&self, code: &str) -> String {
+        let code = code.trim();
+        if code.is_empty() {
+            return self.t("common-unknown").to_string();
+        }
+        self.get(&format!("country-{}", code.to_ascii_uppercase()))
+            .unwrap_or(code)
+            .to_string()
+    }
+
+    /// Country name prefixed with its emoji flag, for table cells
+    pub fn country_label(&self, code: &str) -> String {
+        let flag = geo::countries::flag(code);
+        let name = self.country(code);
+        if flag.is_empty() {
+            name
+        } else {
+            format!("{} {}", flag, name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_locale_from_str() {
+        assert_eq!(Locale::from_str("en"), Some(Locale::En));
+        assert_eq!(Locale::from_str("de-AT"), Some(Locale::De));
+        assert_eq!(Locale::from_str("DE_ch"), Some(Locale::De));
+        assert_eq!(Locale::from_str("fr"), None);
+        assert_eq!(Locale::from_str(""), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Locale::negotiate(None, Locale::De), Locale::De);
+        assert_eq!(
+            Locale::negotiate(Some("de-DE,de;q=0.9,en;q=0.8"), Locale::En),
+            Locale::De
+        );
+        assert_eq!(
+            Locale::negotiate(Some("fr-FR, en;q=0.5, de;q=0.7"), Locale::En),
+            Locale::De
+        );
+        assert_eq!(Locale::negotiate(Some("fr-FR, ja"), Locale::De), Locale::De);
+        assert_eq!(
+            Locale::negotiate(Some("de;q=0, en;q=0.1"), Locale::De),
+            Locale::En
+        );
+        assert_eq!(Locale::negotiate(Some("*"), Locale::De), Locale::De);
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(I18n::from_headers(&headers, "de").locale(), Locale::De);
+        assert_eq!(I18n::from_headers(&headers, "xx").locale(), Locale::En);
+
+        headers.insert(header::ACCEPT_LANGUAGE, "de-CH".parse().unwrap());
+        assert_eq!(I18n::from_headers(&headers, "en").locale(), Locale::De);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let en = I18n::new(Locale::En);
+        let de = I18n::new(Locale::De);
+
+        assert_eq!(en.t("nav-dashboard"), "Dashboard");
+        assert_eq!(de.t("stat-sessions"), "Sitzungen");
+        assert_eq!(de.t("no-such-message"), "no-such-message");
+        assert_eq!(en.t1("pagination-page", "page", 3), "Page 3");
+        assert_eq!(de.t1("pagination-page", "page", 3), "Seite 3");
+        assert_eq!(en.variant("panel", "chart"), "Chart");
+        assert_eq!(en.country("gb"), "United Kingdom");
+        assert_eq!(de.country("GB"), "Vereinigtes Königreich");
+        assert_eq!(en.country(""), "Unknown");
+        assert_eq!(en.country("ZZ"), "ZZ");
+        assert_eq!(de.country_label("AT"), "🇦🇹 Österreich");
+        assert_eq!(en.country_label(""), "Unknown");
+        assert_eq!(de.variant("panel", "sessions"), "Letzte Sitzungen");
+    }
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog = parse_catalog("# comment\n\nfoo = Foo\n  bar=Bar = baz \nbroken\n");
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog["foo"], "Foo");
+        assert_eq!(catalog["bar"], "Bar = baz");
+    }
+
+    #[test]
+    fn test_catalogs_have_same_keys() {
+        let catalogs = catalogs();
+        let en: HashSet<_> = catalogs[&Locale::En].keys().collect();
+        for locale in Locale::ALL {
+            let keys: HashSet<_> = catalogs[&locale].keys().collect();
+            assert_eq!(keys, en, "catalog {} is out of sync with en", locale);
+        }
+    }
+
+    #[test]
+    fn test_templates_use_known_keys() {
+        let catalog = &catalogs()[&Locale::En];
+        let templates = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+        let mut dirs = vec![std::path::PathBuf::from(templates)];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let source = std::fs::read_to_string(&path).unwrap();
+                for call in ["i18n.t(\"", "i18n.t1(\""] {
+                    for rest in source.split(call).skip(1) {
+                        let key = rest.split('"').next().unwrap();
+                        assert!(
+                            catalog.contains_key(key),
+                            "{} uses unknown message {}",
+                            path.display(),
+                            key
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
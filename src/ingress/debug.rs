@@ -0,0 +1,126 @@
+//! Per-service log of the latest ingress decisions, for finding out why hits
+//! don't show up. Only services with `debug_log` turned on keep one; it lives
+//! in memory, holds the last `DEBUG_LOG_ENTRIES` page views of each service
+//! and starts over with each restart. Heartbeats and page leaves aren't
+//! logged, as they'd crowd the page views out.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::domain::{IngressDecision, Service, ServiceId, TrackerType};
+use crate::state::AppState;
+
+/// Decisions kept per service, newest replacing oldest
+pub const DEBUG_LOG_ENTRIES: usize = 100;
+
+/// One page view and what ingress made of it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IngressLogEntry {
+    pub time: DateTime<Utc>,
+    pub tracker: TrackerType,
+    pub decision: IngressDecision,
+    /// Page the hit was for, as sent
+    pub location: String,
+    pub user_agent: String,
+    /// `Origin` header of requests turned away for it
+    pub origin: Option<String>,
+}
+
+#[derive(Default)]
+pub struct IngressDebugLog {
+    services: Mutex<HashMap<ServiceId, VecDeque<IngressLogEntry>>>,
+}
+
+impl IngressDebugLog {
+    /// Log a decision of a service that keeps a debug log
+    pub fn record(&self, service_id: ServiceId, entry: IngressLogEntry) {
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = services.entry(service_id).or_default();
+        if entries.len() >= DEBUG_LOG_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// A service's logged decisions, newest first
+    pub fn entries(&self, service_id: ServiceId) -> Vec<IngressLogEntry> {
+        let services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        services
+            .get(&service_id)
+            .map(|entries| entries.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forget a service's decisions, e.g. once it stops keeping a log
+    pub fn clear(&self, service_id: ServiceId) {
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        services.remove(&service_id);
+    }
+}
+
+/// Log what ingress made of a page view, if the service keeps a debug log
+pub fn log_decision(
+    state: &AppState,
+    service: &Service,
+    tracker: TrackerType,
+    decision: IngressDecision,
+    location: &str,
+    user_agent: &str,
+) {
+    if service.debug_log {
+        state.ingress_log.record(
+            service.id,
+            IngressLogEntry {
+                time: state.clock.now(),
+                tracker,
+                decision,
+                location: location.to_string(),
+                user_agent: user_agent.to_string(),
+                origin: None,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn entry(location: &str, decision: IngressDecision) -> IngressLogEntry {
+        IngressLogEntry {
+            time: Utc::now(),
+            tracker: TrackerType::Js,
+            decision,
+            location: location.to_string(),
+            user_agent: String::new(),
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn test_debug_log() {
+        let log = IngressDebugLog::default();
+        let (service_id, other_id) = (ServiceId(Uuid::new_v4()), ServiceId(Uuid::new_v4()));
+        for i in 0..DEBUG_LOG_ENTRIES + 5 {
+            log.record(
+                service_id,
+                entry(&format!("/{}", i), IngressDecision::DroppedIp),
+            );
+        }
+        log.record(service_id, entry("/last", IngressDecision::DroppedBot));
+        log.record(other_id, entry("/other", IngressDecision::Accepted));
+
+        let entries = log.entries(service_id);
+        assert_eq!(entries.len(), DEBUG_LOG_ENTRIES);
+        assert_eq!(entries[0].location, "/last");
+        assert_eq!(entries[DEBUG_LOG_ENTRIES - 1].location, "/6");
+
+        log.clear(service_id);
+        assert!(log.entries(service_id).is_empty());
+        assert_eq!(log.entries(other_id).len(), 1);
+    }
+}
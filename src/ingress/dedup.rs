@@ -0,0 +1,258 @@
+//! A fallback for the hit idempotency cache. The cache is bounded and
+//! starts empty after a restart, so a page load repeated once its key is
+//! forgotten would be recorded as a second hit. Every idempotency key also
+//! goes into a Bloom filter of the UTC day, which is saved to the database
+//! every `FLUSH_INTERVAL` and read back after a restart; a key the cache
+//! misses but the filter of today or yesterday may hold is matched to the
+//! session's existing hit instead. A false positive (about 1 in 1,000 keys
+//! while a day stays within capacity) folds a page load into the session's
+//! last hit on the same page, if it has one, and otherwise records it as usual.
+
+use std::collections::{hash_map::Entry, HashMap};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, warn};
+
+use crate::db::{self, Pool};
+use crate::domain::BackgroundTask;
+use crate::error::Result;
+use crate::state::AppState;
+
+/// How often changed filters are saved; keys seen since the last save are
+/// lost if the server stops
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// Bits per key for a false positive rate of 0.1%
+const BITS_PER_KEY: f64 = 14.38;
+/// Bit positions each key sets
+const HASHES: u64 = 10;
+
+/// A fixed-size Bloom filter over strings
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// A filter keeping its false positive rate for up to `capacity` keys
+    pub fn with_capacity(capacity: usize) -> Self {
+        let words = ((capacity as f64 * BITS_PER_KEY) / 64.0).ceil().max(1.0) as usize;
+        Self {
+            bits: vec![0; words],
+        }
+    }
+
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let digest = Sha256::digest(key.as_bytes());
+        let h1 = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        let h2 = u64::from_be_bytes(digest[8..16].try_into().unwrap()) | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    /// Whether the key may have been inserted; never false for one that was
+    pub fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Insert a key, returning whether any bit changed
+    pub fn insert(&mut self, key: &str) -> bool {
+        let mut changed = false;
+        for bit in self.positions(key).collect::<Vec<_>>() {
+            let word = &mut self.bits[bit / 64];
+            changed |= *word & (1 << (bit % 64)) == 0;
+            *word |= 1 << (bit % 64);
+        }
+        changed
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bits
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+
+    /// Read a filter written by `to_bytes` for the same capacity; `None` if
+    /// its size differs, e.g. because the capacity setting changed
+    pub fn from_bytes(bytes: &[u8], capacity: usize) -> Option<Self> {
+        let mut filter = Self::with_capacity(capacity);
+        if bytes.len() != filter.bits.len() * 8 {
+            return None;
+        }
+        for (word, chunk) in filter.bits.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        Some(filter)
+    }
+}
+
+struct DayFilter {
+    filter: BloomFilter,
+    /// Changed since it was last saved
+    dirty: bool,
+}
+
+/// The idempotency keys of today and yesterday, loaded from the database
+/// when first needed
+pub struct IdempotencyFilter {
+    /// Keys a day's filter is sized for; 0 turns the filter off
+    capacity: usize,
+    days: Mutex<HashMap<NaiveDate, DayFilter>>,
+}
+
+impl IdempotencyFilter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            days: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// The filter of a day, read from the database the first time
+    async fn day<'a>(
+        &self,
+        days: &'a mut HashMap<NaiveDate, DayFilter>,
+        pool: &Pool,
+        day: NaiveDate,
+    ) -> Result<&'a mut DayFilter> {
+        let entry = match days.entry(day) {
+            Entry::Occupied(entry) => return Ok(entry.into_mut()),
+            Entry::Vacant(entry) => entry,
+        };
+        let stored = db::get_hit_filter(pool, day).await?;
+        let filter = stored.and_then(|bytes| {
+            let filter = BloomFilter::from_bytes(&bytes, self.capacity);
+            if filter.is_none() {
+                warn!(
+                    "Discarding the idempotency filter of {} sized for another capacity",
+                    day
+                );
+            }
+            filter
+        });
+        Ok(entry.insert(DayFilter {
+            filter: filter.unwrap_or_else(|| BloomFilter::with_capacity(self.capacity)),
+            dirty: false,
+        }))
+    }
+
+    /// Whether the key may have been seen today or yesterday
+    pub async fn contains(&self, pool: &Pool, key: &str, now: DateTime<Utc>) -> Result<bool> {
+        if !self.is_enabled() {
+            return Ok(false);
+        }
+        let today = now.date_naive();
+        let mut days = self.days.lock().await;
+        for day in [today, today.pred_opt().unwrap_or(today)] {
+            if self.day(&mut days, pool, day).await?.filter.contains(key) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Remember the key as seen today
+    pub async fn insert(&self, pool: &Pool, key: &str, now: DateTime<Utc>) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let mut days = self.days.lock().await;
+        let day = self.day(&mut days, pool, now.date_naive()).await?;
+        if day.filter.insert(key) {
+            day.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Save the filters changed since the last flush, and forget those of
+    /// days before yesterday
+    pub async fn flush(&self, pool: &Pool, now: DateTime<Utc>) -> Result<()> {
+        let today = now.date_naive();
+        let oldest = today.pred_opt().unwrap_or(today);
+        let changed: Vec<(NaiveDate, Vec<u8>)> = {
+            let mut days = self.days.lock().await;
+            days.retain(|day, _| *day >= oldest);
+            days.iter_mut()
+                .filter(|(_, day)| day.dirty)
+                .map(|(date, day)| {
+                    day.dirty = false;
+                    (*date, day.filter.to_bytes())
+                })
+                .collect()
+        };
+
+        for (day, bytes) in changed {
+            if let Err(e) = db::save_hit_filter(pool, day, &bytes).await {
+                // Saved again with the next flush
+                if let Some(filter) = self.days.lock().await.get_mut(&day) {
+                    filter.dirty = true;
+                }
+                return Err(e);
+            }
+        }
+        db::delete_hit_filters_before(pool, oldest).await
+    }
+}
+
+/// Save the filters every `FLUSH_INTERVAL`, if they are on
+pub fn spawn_hit_filter_flush(state: AppState) {
+    if !state.hit_filter.is_enabled() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let now = state.clock.now();
+            let result = state.hit_filter.flush(&state.pool, now).await;
+            state
+                .tasks
+                .record(BackgroundTask::HitFilterFlush, now, &result);
+            if let Err(e) = result {
+                error!("Saving the idempotency filters failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::with_capacity(1_000);
+        assert!(!filter.contains("hit_a"));
+        assert!(filter.insert("hit_a"));
+        assert!(!filter.insert("hit_a"));
+        assert!(filter.contains("hit_a"));
+
+        for i in 0..1_000 {
+            filter.insert(&format!("key-{i}"));
+        }
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(&format!("other-{i}")))
+            .count();
+        assert!(false_positives < 50, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_bloom_filter_bytes() {
+        let mut filter = BloomFilter::with_capacity(100);
+        filter.insert("hit_a");
+        let bytes = filter.to_bytes();
+        assert_eq!(BloomFilter::from_bytes(&bytes, 100), Some(filter));
+        assert_eq!(BloomFilter::from_bytes(&bytes, 200), None);
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::Write;
+
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap};
+use flate2::{write::GzEncoder, Compression};
+
+/// Content encodings the tracker script is precompressed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// Value for the Content-Encoding header, if any
+    pub fn header_value(&self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Identity => None,
+            ContentEncoding::Gzip => Some("gzip"),
+            ContentEncoding::Brotli => Some("br"),
+        }
+    }
+
+    /// Pick the best encoding the client accepts (brotli > gzip > identity).
+    /// Quality values are honoured only to the extent of `q=0` opting out.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let Some(accept) = headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return ContentEncoding::Identity;
+        };
+
+        let mut gzip = false;
+        let mut brotli = false;
+        for part in accept.split(',') {
+            let mut params = part.split(';').map(str::trim);
+            let coding = params.next().unwrap_or("").to_ascii_lowercase();
+            let disabled = params.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            if disabled {
+                continue;
+            }
+            match coding.as_str() {
+                "br" => brotli = true,
+                "gzip" => gzip = true,
+                "*" => {
+                    brotli = true;
+                    gzip = true;
+                }
+                _ => {}
+            }
+        }
+
+        if brotli {
+            ContentEncoding::Brotli
+        } else if gzip {
+            ContentEncoding::Gzip
+        } else {
+            ContentEncoding::Identity
+        }
+    }
+}
+
+/// A rendered tracker script along with its precompressed variants.
+/// Built once per distinct script (keyed by ETag) and shared from the cache.
+#[derive(Debug)]
+pub struct EncodedScript {
+    pub identity: Bytes,
+    pub gzip: Bytes,
+    pub brotli: Bytes,
+}
+
+impl EncodedScript {
+    pub fn new(script: &str) -> Self {
+        Self {
+            identity: Bytes::copy_from_slice(script.as_bytes()),
+            gzip: Bytes::from(compress_gzip(script.as_bytes())),
+            brotli: Bytes::from(compress_brotli(script.as_bytes())),
+        }
+    }
+
+    /// Body for the given encoding
+    pub fn body(&self, encoding: ContentEncoding) -> Bytes {
+        match encoding {
+            ContentEncoding::Identity => self.identity.clone(),
+            ContentEncoding::Gzip => self.gzip.clone(),
+            ContentEncoding::Brotli => self.brotli.clone(),
+        }
+    }
+}
+
+fn compress_gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    // Writing into a Vec cannot fail
+    encoder.write_all(data).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+fn compress_brotli(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+        writer.write_all(data).expect("brotli into memory");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(
+            ContentEncoding::negotiate(&HeaderMap::new()),
+            ContentEncoding::Identity
+        );
+        assert_eq!(
+            ContentEncoding::negotiate(&accept("gzip, deflate, br")),
+            ContentEncoding::Brotli
+        );
+        assert_eq!(
+            ContentEncoding::negotiate(&accept("gzip")),
+            ContentEncoding::Gzip
+        );
+        assert_eq!(
+            ContentEncoding::negotiate(&accept("br;q=0, gzip;q=0.8")),
+            ContentEncoding::Gzip
+        );
+        assert_eq!(
+            ContentEncoding::negotiate(&accept("*")),
+            ContentEncoding::Brotli
+        );
+        assert_eq!(
+            ContentEncoding::negotiate(&accept("identity")),
+            ContentEncoding::Identity
+        );
+    }
+
+    #[test]
+    fn test_encoded_script_roundtrip() {
+        let script = "var shymini = {};\n".repeat(100);
+        let encoded = EncodedScript::new(&script);
+
+        assert_eq!(encoded.body(ContentEncoding::Identity), script.as_bytes());
+        assert!(encoded.gzip.len() < script.len());
+        assert!(encoded.brotli.len() < script.len());
+
+        let mut decoded = String::new();
+        GzDecoder::new(&encoded.gzip[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, script);
+
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&encoded.brotli[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, script);
+    }
+}
@@ -0,0 +1,147 @@
+//! JavaScript errors. Trackers of services with `track_errors` catch the
+//! page's uncaught errors and rejected promises and post them, a few per
+//! page view, with `error: { message, stack }` in place of a page view.
+//! Reports are counted per page and fingerprint, a hash of the message and
+//! the top of the stack, so one bug firing on every visit stays one row.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{clean_text, MAX_URL_CHARS};
+use crate::db;
+use crate::domain::{CreatePageError, Environment, Service};
+use crate::error::Result;
+use crate::state::AppState;
+
+/// Longest error message stored, in characters
+pub const MAX_ERROR_MESSAGE_CHARS: usize = 512;
+/// Longest stack stored, in characters
+pub const MAX_ERROR_STACK_CHARS: usize = 2048;
+
+/// Stack lines that go into the fingerprint; the frames below them tell
+/// more about the caller than about the error
+const FINGERPRINT_FRAMES: usize = 3;
+
+/// Hex characters of the fingerprint
+const FINGERPRINT_CHARS: usize = 16;
+
+/// An error as the tracker reports it
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ErrorReport {
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub stack: String,
+}
+
+impl ErrorReport {
+    /// The report as it may be stored: cut to length, and the stack's lines
+    /// cleaned one by one so they stay lines
+    pub fn cleaned(self) -> Self {
+        let mut stack = String::new();
+        for line in self.stack.lines() {
+            let line = clean_text(line, MAX_ERROR_STACK_CHARS);
+            if line.is_empty() {
+                continue;
+            }
+            if !stack.is_empty() {
+                stack.push('\n');
+            }
+            stack.push_str(&line);
+        }
+        Self {
+            message: clean_text(&self.message, MAX_ERROR_MESSAGE_CHARS),
+            stack: stack.chars().take(MAX_ERROR_STACK_CHARS).collect(),
+        }
+    }
+
+    /// Hash of the message and the top stack frames
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.message.as_bytes());
+        for frame in self.stack.lines().take(FINGERPRINT_FRAMES) {
+            hasher.update(b"\n");
+            hasher.update(frame.as_bytes());
+        }
+        let mut fingerprint = hex::encode(hasher.finalize());
+        fingerprint.truncate(FINGERPRINT_CHARS);
+        fingerprint
+    }
+}
+
+/// Count an error reported on a page of the service
+pub async fn record_page_error(
+    state: &AppState,
+    service: &Service,
+    time: DateTime<Utc>,
+    environment: Environment,
+    location: &str,
+    report: ErrorReport,
+) -> Result<()> {
+    let report = report.cleaned();
+    if report.message.is_empty() && report.stack.is_empty() {
+        return Ok(());
+    }
+    let location = service
+        .get_path_normalization()
+        .apply(&clean_text(location, MAX_URL_CHARS));
+    db::record_page_error(
+        &state.pool,
+        CreatePageError {
+            service_id: service.id,
+            time,
+            environment,
+            location,
+            fingerprint: report.fingerprint(),
+            message: report.message,
+            stack: report.stack,
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(message: &str, stack: &str) -> ErrorReport {
+        ErrorReport {
+            message: message.to_string(),
+            stack: stack.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_error_report_cleaned() {
+        let cleaned = report(
+            " TypeError: x is undefined\u{0} ",
+            "at f (app.js:1:2)\r\n\n  at g (app.js:3:4)  \n",
+        )
+        .cleaned();
+        assert_eq!(cleaned.message, "TypeError: x is undefined");
+        assert_eq!(cleaned.stack, "at f (app.js:1:2)\nat g (app.js:3:4)");
+
+        let long = report(&"m".repeat(1000), &"at f\n".repeat(1000)).cleaned();
+        assert_eq!(long.message.chars().count(), MAX_ERROR_MESSAGE_CHARS);
+        assert_eq!(long.stack.chars().count(), MAX_ERROR_STACK_CHARS);
+    }
+
+    #[test]
+    fn test_error_fingerprint() {
+        let top = "at f (app.js:1:2)\nat g (app.js:3:4)\nat h (app.js:5:6)";
+        let fingerprint = report("boom", top).fingerprint();
+        assert_eq!(fingerprint.len(), FINGERPRINT_CHARS);
+
+        // Callers further down don't make it another error
+        assert_eq!(
+            report("boom", &format!("{}\nat main (app.js:7:8)", top)).fingerprint(),
+            fingerprint
+        );
+        assert_ne!(report("bang", top).fingerprint(), fingerprint);
+        assert_ne!(
+            report("boom", "at f (app.js:9:9)").fingerprint(),
+            fingerprint
+        );
+    }
+}
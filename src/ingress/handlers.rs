@@ -1,30 +1,62 @@
 use askama::Template;
 use axum::{
-    extract::{Path, State},
-    http::{header, HeaderMap, StatusCode},
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::Utc;
-use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info};
+use chrono::{DateTime, Duration, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, warn};
+use url::Url;
 
 use crate::db;
-use crate::domain::TrackerType;
-use crate::error::Error;
+use crate::domain::{
+    BounceRule, Environment, IngressDecision, PrefetchBehavior, Service, TrackerType,
+};
+use crate::error::{Error, Result};
 use crate::privacy::{
-    get_client_ip, get_origin, get_referrer, get_user_agent, is_dnt_enabled, is_ip_ignored,
+    get_client_ip, get_origin, get_referrer, get_user_agent, is_dnt_enabled, is_excluded,
+    is_gpc_enabled, is_ip_ignored, is_prefetch,
 };
 use crate::state::AppState;
 
-use super::{process_ingress, IngressPayload};
+use super::{
+    clean_text, count_dropped, minify_js, process_ingress, record_page_error, ContentEncoding,
+    EncodedScript, ErrorReport, IngressLogEntry, IngressPayload, MAX_FIELD_CHARS, MAX_URL_CHARS,
+};
 
+/// The tracker, as a classic script or as an ES module for bundlers
 #[derive(Template)]
 #[template(path = "ingress/tracker.js", escape = "none")]
 struct TrackerScriptTemplate<'a> {
     protocol: &'a str,
+    /// Server the module posts to; the classic script finds it on its own
+    origin: &'a str,
     endpoint: &'a str,
     heartbeat_frequency: u64,
+    idle_timeout: u64,
+    collapse_tabs: bool,
+    /// Report the visitor's first interaction, for `BounceRule::NoInteraction`
+    track_interaction: bool,
+    /// Report the page's JavaScript errors
+    track_errors: bool,
+    /// Share of page views whose errors are reported
+    error_sample_rate: f64,
+    /// Export `init()` and `track()` instead of starting on page load
+    module: bool,
+    /// The module checks DNT and GPC in the browser, as it isn't fetched by
+    /// it
+    respect_dnt: bool,
+    respect_gpc: bool,
+}
+
+/// The administrator's script inject, appended to the tracker as written
+#[derive(Template)]
+#[template(path = "ingress/tracker_inject.js", escape = "none")]
+struct TrackerInjectTemplate<'a> {
     script_inject: &'a str,
 }
 
@@ -85,6 +117,156 @@ pub struct ScriptPayload {
     pub referrer: Option<String>,
     #[serde(rename = "loadTime")]
     pub load_time: Option<f64>,
+    /// When the event happened, set when the tracker replays a POST that was
+    /// queued while the visitor was offline, or by an import
+    pub ts: Option<EventTime>,
+    /// Sent when the visitor leaves the page or switches away from it
+    #[serde(default)]
+    pub end: bool,
+    /// Sent once the visitor scrolled, clicked, tapped or typed, by trackers
+    /// of services whose bounce rule asks for it
+    #[serde(default)]
+    pub interacted: bool,
+    /// Custom dimensions of the page view, an object sent with its first
+    /// POST; anything else is ignored rather than failing the page view
+    pub dimensions: Option<serde_json::Value>,
+    /// Properties to set on the session, an object sent with the POST after
+    /// `setProps`; ignored like `dimensions` if it is anything else
+    pub props: Option<serde_json::Value>,
+    /// A JavaScript error on the page, reported by trackers of services
+    /// tracking errors instead of a page view
+    pub error: Option<ErrorReport>,
+}
+
+/// When a hit happened, according to its sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTime {
+    /// Milliseconds before the request, as the tracker's offline queue sends
+    /// it; immune to a wrong clock on the visitor's device
+    Age(i64),
+    /// An RFC 3339 time, e.g. from a server-side import
+    At(DateTime<Utc>),
+}
+
+impl EventTime {
+    /// Read a `ts` given as text: a whole number is an age, anything else
+    /// must be an RFC 3339 time
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        s.parse().map(Self::Age).ok().or_else(|| {
+            DateTime::parse_from_rfc3339(s)
+                .ok()
+                .map(|time| Self::At(time.with_timezone(&Utc)))
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for EventTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        // Form posts send every field as text
+        let time = match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Number(n) => n.as_i64().map(Self::Age),
+            serde_json::Value::String(s) => Self::parse(&s),
+            _ => None,
+        };
+        time.ok_or_else(|| de::Error::custom("ts must be milliseconds ago or an RFC 3339 time"))
+    }
+}
+
+impl ScriptPayload {
+    fn dimension_pairs(&self) -> Vec<(String, String)> {
+        text_pairs(self.dimensions.as_ref())
+    }
+
+    fn prop_pairs(&self) -> Vec<(String, String)> {
+        text_pairs(self.props.as_ref())
+    }
+
+    /// Whether the POST is for a page load rather than a heartbeat, a leave
+    /// or an error
+    fn is_page_load(&self) -> bool {
+        self.error.is_none()
+            && !self.end
+            && (self.load_time.is_some() || self.idempotency.is_none())
+    }
+}
+
+/// The entries of a JSON object as text; values that are neither strings,
+/// numbers nor booleans are dropped, as is anything but an object
+fn text_pairs(object: Option<&serde_json::Value>) -> Vec<(String, String)> {
+    let Some(serde_json::Value::Object(object)) = object else {
+        return Vec::new();
+    };
+    object
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+/// Query parameters of the tracker script
+#[derive(Debug, Default, Deserialize)]
+pub struct ScriptQuery {
+    /// `?debug=1` serves the script as written, with comments
+    pub debug: Option<String>,
+    /// `?env=staging` records the script's hits in that environment; the
+    /// script passes it on to its POSTs
+    pub env: Option<String>,
+}
+
+impl ScriptQuery {
+    pub fn readable(&self) -> bool {
+        matches!(self.debug.as_deref(), Some("1") | Some("true"))
+    }
+
+    /// The environment the embed code asked for, if it is a known one
+    pub fn environment(&self) -> Option<Environment> {
+        self.env.as_deref().and_then(Environment::from_str)
+    }
+}
+
+/// Query parameters of the pixel and of tracker POSTs
+#[derive(Debug, Default, Deserialize)]
+pub struct IngressQuery {
+    pub env: Option<String>,
+    /// When the pixel was loaded, as the script payload's `ts`
+    pub ts: Option<String>,
+    /// Signature of the pixel's identifier, see `signed_pixel_url`
+    pub sig: Option<String>,
+}
+
+/// Environment of a hit: the one the embed code set, else guessed from the
+/// host of the page sending it
+pub(super) fn hit_environment(env: Option<&str>, headers: &HeaderMap) -> Environment {
+    env.and_then(Environment::from_str)
+        .or_else(|| get_origin(headers).map(|origin| Environment::detect_origin(&origin)))
+        .unwrap_or_default()
+}
+
+/// Read a tracker POST whatever it is labelled: `sendBeacon` posts strings
+/// as `text/plain` and `URLSearchParams` as form data, so a body that looks
+/// like JSON is read as JSON, and a form as its fields
+pub fn parse_script_payload(headers: &HeaderMap, body: &[u8]) -> Option<ScriptPayload> {
+    let json = body.trim_ascii_start();
+    if json.starts_with(b"{") {
+        return serde_json::from_slice(json).ok();
+    }
+    let is_form = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+    if is_form && !body.is_empty() {
+        serde_urlencoded::from_bytes(body).ok()
+    } else {
+        None
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -92,20 +274,32 @@ pub struct ScriptResponse {
     pub status: String,
 }
 
+/// Whether a hit is from a prefetched page, or `None` if such hits are
+/// dropped and it is one
+pub(super) fn prefetch_flag(state: &AppState, headers: &HeaderMap) -> Option<bool> {
+    match (is_prefetch(headers), state.settings.prefetch_hits) {
+        (false, _) => Some(false),
+        (true, PrefetchBehavior::Drop) => None,
+        (true, PrefetchBehavior::Flag) => Some(true),
+    }
+}
+
 /// GET /trace/px_:tracking_id.gif
 pub async fn pixel_handler(
     State(state): State<AppState>,
     Path(tracking_id): Path<String>,
+    Query(query): Query<IngressQuery>,
     headers: HeaderMap,
 ) -> Response {
     let tracking_id = strip_extension(&tracking_id).to_string();
-    pixel_handler_internal(state, tracking_id, None, headers).await
+    pixel_handler_internal(state, tracking_id, None, query, headers).await
 }
 
 /// GET /trace/px_:tracking_id/:identifier.gif
 pub async fn pixel_with_id_handler(
     State(state): State<AppState>,
     Path((tracking_id, identifier)): Path<(String, String)>,
+    Query(query): Query<IngressQuery>,
     headers: HeaderMap,
 ) -> Response {
     // Strip .gif suffix if present
@@ -113,13 +307,14 @@ pub async fn pixel_with_id_handler(
         .strip_suffix(".gif")
         .unwrap_or(&identifier)
         .to_string();
-    pixel_handler_internal(state, tracking_id, Some(identifier), headers).await
+    pixel_handler_internal(state, tracking_id, Some(identifier), query, headers).await
 }
 
 async fn pixel_handler_internal(
     state: AppState,
     tracking_id: String,
     identifier: Option<String>,
+    query: IngressQuery,
     headers: HeaderMap,
 ) -> Response {
     info!("Pixel request for tracking_id={}", tracking_id);
@@ -138,34 +333,80 @@ async fn pixel_handler_internal(
     };
 
     info!("Found service: {} ({})", service.name, service.id);
+    let location = get_referrer(&headers);
+    let turn_away = |decision| {
+        log_turned_away(
+            &state,
+            &service,
+            TrackerType::Pixel,
+            decision,
+            &location,
+            &headers,
+        )
+    };
 
     // Validate origin
     let (allow_origin, origin_valid) = validate_origin(&headers, &service);
     if !origin_valid {
-        return (StatusCode::FORBIDDEN, "Invalid origin").into_response();
+        turn_away(IngressDecision::OriginRejected).await;
+        return reject_origin(&state, &service, &headers);
     }
 
-    // Check DNT
-    if is_dnt_enabled(&headers) && service.respect_dnt {
+    // Check DNT/GPC
+    if let Some(decision) = opt_out(&headers, &service) {
         debug!("Ignoring due to DNT/GPC");
+        turn_away(decision).await;
         return pixel_response(allow_origin);
     }
 
+    if is_excluded(&headers, &tracking_id) {
+        debug!("Ignoring excluded browser");
+        turn_away(IngressDecision::DroppedExcluded).await;
+        return pixel_response(allow_origin);
+    }
+
+    // Forged identifiers would pollute per-recipient stats
+    if let Some(identifier) = identifier.as_deref().filter(|_| service.signed_pixel_ids) {
+        let signed = query.sig.as_deref().is_some_and(|sig| {
+            state
+                .signing_key
+                .verify_pixel_id(&tracking_id, identifier, sig)
+        });
+        if !signed {
+            debug!("Ignoring pixel with an unsigned identifier");
+            turn_away(IngressDecision::DroppedUnsigned).await;
+            return pixel_response(allow_origin);
+        }
+    }
+
+    let Some(prefetched) = prefetch_flag(&state, &headers) else {
+        debug!("Ignoring prefetch");
+        turn_away(IngressDecision::DroppedPrefetch).await;
+        return pixel_response(allow_origin);
+    };
+
     let ip = get_client_ip(&headers).unwrap_or_else(|| "0.0.0.0".to_string());
     let user_agent = get_user_agent(&headers);
-    let location = get_referrer(&headers);
 
     // Check ignored IPs
     let ignored_networks = service.get_ignored_networks();
     if is_ip_ignored(&ip, &ignored_networks) {
         debug!("Ignoring due to ignored IP");
+        turn_away(IngressDecision::DroppedIp).await;
         return pixel_response(allow_origin);
     }
 
     // Process ingress asynchronously
     let identifier = identifier.unwrap_or_default();
+    let time = event_time(
+        state.clock.now(),
+        query.ts.as_deref().and_then(EventTime::parse),
+        state.settings.max_timestamp_skew_secs,
+    );
     let payload = IngressPayload {
         location,
+        environment: hit_environment(query.env.as_deref(), &headers),
+        prefetched,
         ..Default::default()
     };
 
@@ -175,7 +416,7 @@ async fn pixel_handler_internal(
             &state,
             &service,
             TrackerType::Pixel,
-            Utc::now(),
+            time,
             payload,
             &ip,
             &user_agent,
@@ -190,6 +431,102 @@ async fn pixel_handler_internal(
     pixel_response(allow_origin)
 }
 
+/// Absolute URL of a service's pixel recording `identifier`, e.g. a
+/// newsletter recipient, signed so it counts when the service requires
+/// signed identifiers
+pub fn signed_pixel_url(state: &AppState, service: &Service, identifier: &str) -> Result<String> {
+    let mut url = Url::parse(&state.settings.public_link("/trace/"))
+        .map_err(|e| Error::Internal(format!("Invalid public_url: {}", e)))?;
+    url.path_segments_mut()
+        .map_err(|_| Error::Internal("Invalid public_url".to_string()))?
+        .pop_if_empty()
+        .push(&format!("px_{}", service.tracking_id.0))
+        .push(&format!("{}.gif", identifier));
+    let signature = state
+        .signing_key
+        .sign_pixel_id(&service.tracking_id.0, identifier);
+    url.query_pairs_mut().append_pair("sig", &signature);
+    Ok(url.into())
+}
+
+/// Tracking ID of any ingress route, e.g. `abc` of `app_abc.esm.js`
+fn route_tracking_id(params: &[(String, String)]) -> &str {
+    let tracking_id = params
+        .first()
+        .map(|(_, value)| strip_extension(value))
+        .unwrap_or_default();
+    tracking_id.strip_suffix(".esm").unwrap_or(tracking_id)
+}
+
+/// The origin a service lets read its ingress responses, or the response
+/// refusing the request
+async fn ingress_allow_origin(
+    state: &AppState,
+    tracking_id: &str,
+    headers: &HeaderMap,
+) -> std::result::Result<String, Response> {
+    let service = match db::get_active_service_by_tracking_id(&state.pool, tracking_id).await {
+        Ok(s) => s,
+        Err(Error::ServiceNotFound) => {
+            return Err((StatusCode::NOT_FOUND, "Service not found").into_response());
+        }
+        Err(e) => {
+            error!("Error fetching service: {}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response());
+        }
+    };
+    match validate_origin(headers, &service) {
+        (allow_origin, true) => Ok(allow_origin),
+        (_, false) => Err(reject_origin(state, &service, headers)),
+    }
+}
+
+/// OPTIONS /trace/* - CORS preflight, answered with the service's allowed
+/// origin; the requested headers are allowed as asked, since ingress never
+/// reads credentials
+pub async fn ingress_options_handler(
+    State(state): State<AppState>,
+    Path(params): Path<Vec<(String, String)>>,
+    headers: HeaderMap,
+) -> Response {
+    let allow_origin =
+        match ingress_allow_origin(&state, route_tracking_id(&params), &headers).await {
+            Ok(allow_origin) => allow_origin,
+            Err(response) => return response,
+        };
+    let allow_headers = headers
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(INGRESS_ALLOW_HEADERS);
+
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (header::ALLOW, INGRESS_ALLOW_METHODS),
+            (header::ACCESS_CONTROL_ALLOW_ORIGIN, &allow_origin),
+            (header::ACCESS_CONTROL_ALLOW_METHODS, INGRESS_ALLOW_METHODS),
+            (header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers),
+            (header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE),
+            (header::VARY, PREFLIGHT_VARY),
+        ],
+    )
+        .into_response()
+}
+
+/// HEAD /trace/px_:tracking_id.gif - the pixel's headers, without recording
+/// a hit
+pub async fn pixel_head_handler(
+    State(state): State<AppState>,
+    Path(params): Path<Vec<(String, String)>>,
+    headers: HeaderMap,
+) -> Response {
+    match ingress_allow_origin(&state, route_tracking_id(&params), &headers).await {
+        // The body is dropped for HEAD requests
+        Ok(allow_origin) => pixel_response(allow_origin),
+        Err(response) => response,
+    }
+}
+
 fn pixel_response(allow_origin: String) -> Response {
     (
         StatusCode::OK,
@@ -203,20 +540,38 @@ fn pixel_response(allow_origin: String) -> Response {
         .into_response()
 }
 
+/// Methods the ingress routes answer
+const INGRESS_ALLOW_METHODS: &str = "GET,HEAD,OPTIONS,POST";
+/// Headers other origins may send when a preflight doesn't name its own
+const INGRESS_ALLOW_HEADERS: &str =
+    "Origin, X-Requested-With, Content-Type, Accept, Authorization, Referer";
+/// How long browsers may reuse a preflight answer
+const PREFLIGHT_MAX_AGE: &str = "86400";
+/// A preflight answer differs per requesting origin and requested headers
+const PREFLIGHT_VARY: &str =
+    "Origin, Access-Control-Request-Method, Access-Control-Request-Headers";
+
 /// GET /trace/app_:tracking_id.js
 pub async fn script_get_handler(
     State(state): State<AppState>,
     Path(tracking_id): Path<String>,
+    Query(query): Query<ScriptQuery>,
     headers: HeaderMap,
 ) -> Response {
-    let tracking_id = strip_extension(&tracking_id).to_string();
-    script_get_handler_internal(state, tracking_id, None, headers).await
+    let tracking_id = strip_extension(&tracking_id);
+    // app_:tracking_id.esm.js is the ES module build
+    let (tracking_id, module) = match tracking_id.strip_suffix(".esm") {
+        Some(tracking_id) => (tracking_id, true),
+        None => (tracking_id, false),
+    };
+    script_get_handler_internal(state, tracking_id.to_string(), None, module, query, headers).await
 }
 
 /// GET /trace/app_:tracking_id/:identifier.js
 pub async fn script_get_with_id_handler(
     State(state): State<AppState>,
     Path((tracking_id, identifier)): Path<(String, String)>,
+    Query(query): Query<ScriptQuery>,
     headers: HeaderMap,
 ) -> Response {
     // Strip .js suffix if present
@@ -224,13 +579,15 @@ pub async fn script_get_with_id_handler(
         .strip_suffix(".js")
         .unwrap_or(&identifier)
         .to_string();
-    script_get_handler_internal(state, tracking_id, Some(identifier), headers).await
+    script_get_handler_internal(state, tracking_id, Some(identifier), false, query, headers).await
 }
 
 async fn script_get_handler_internal(
     state: AppState,
     tracking_id: String,
     identifier: Option<String>,
+    module: bool,
+    query: ScriptQuery,
     headers: HeaderMap,
 ) -> Response {
     info!("Script GET request for tracking_id={}", tracking_id);
@@ -252,22 +609,49 @@ async fn script_get_handler_internal(
 
     // Validate origin
     let (allow_origin, origin_valid) = validate_origin(&headers, &service);
-    if !origin_valid {
-        return (StatusCode::FORBIDDEN, "Invalid origin").into_response();
+    // Bundlers and package managers fetch the module without an origin; its
+    // POSTs are checked like any others
+    let location = get_referrer(&headers);
+    let turn_away = |decision| {
+        log_turned_away(
+            &state,
+            &service,
+            TrackerType::Js,
+            decision,
+            &location,
+            &headers,
+        )
+    };
+    if !(origin_valid || module && get_origin(&headers).is_none()) {
+        turn_away(IngressDecision::OriginRejected).await;
+        return reject_origin(&state, &service, &headers);
     }
 
-    // Check DNT
-    let dnt = is_dnt_enabled(&headers) && service.respect_dnt;
+    // Check DNT/GPC (the module checks in the browser once it runs).
+    // Browsers excluded from the dashboard get the same inert script.
+    let opted_out = opt_out(&headers, &service).filter(|_| !module);
+    let dnt = if let Some(decision) = opted_out {
+        turn_away(decision).await;
+        true
+    } else if is_excluded(&headers, &tracking_id) {
+        turn_away(IngressDecision::DroppedExcluded).await;
+        true
+    } else {
+        false
+    };
 
     // Generate script - detect protocol from incoming request headers
     let protocol = detect_protocol(&headers, true);
 
-    let endpoint = match &identifier {
+    let mut endpoint = match &identifier {
         Some(id) => format!("/trace/app_{}/{}.js", tracking_id, id),
         None => format!("/trace/app_{}.js", tracking_id),
     };
+    if let Some(environment) = query.environment() {
+        endpoint.push_str(&format!("?env={}", environment.as_str()));
+    }
 
-    let heartbeat_frequency = state.settings.script_heartbeat_frequency_ms;
+    let heartbeat_frequency = state.settings.heartbeat_frequency_ms(&service);
 
     // Get script inject content
     let script_inject = state
@@ -276,59 +660,172 @@ async fn script_get_handler_internal(
         .await
         .unwrap_or_default();
 
-    let script = generate_tracker_script(
-        dnt,
+    let template = TrackerScriptTemplate {
         protocol,
-        &endpoint,
+        origin: state.settings.public_url.trim_end_matches('/'),
+        endpoint: &endpoint,
         heartbeat_frequency,
-        &script_inject,
-    );
+        idle_timeout: service.idle_timeout_ms(),
+        collapse_tabs: service.collapse_tabs,
+        track_interaction: service.bounce_rule == BounceRule::NoInteraction,
+        track_errors: service.track_errors,
+        error_sample_rate: state.settings.error_sample_rate.clamp(0.0, 1.0),
+        module,
+        respect_dnt: service.respect_dnt,
+        respect_gpc: service.respect_gpc,
+    };
+    let script = generate_tracker_script(dnt, &template, &script_inject, query.readable());
+
+    let script_tag = script_etag(&script);
+    let cache_control = script_cache_control(&script_inject);
+    let encoding = ContentEncoding::negotiate(&headers);
+    // Each encoding is a distinct representation, so it gets its own ETag
+    let etag = encoded_etag(&script_tag, encoding);
+
+    if etag_matches(&headers, &etag) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, etag.as_str()),
+                (header::CACHE_CONTROL, cache_control),
+                (header::VARY, SCRIPT_VARY),
+                (header::ACCESS_CONTROL_ALLOW_ORIGIN, &allow_origin),
+            ],
+        )
+            .into_response();
+    }
 
-    (
+    let encoded = state
+        .cache
+        .get_or_insert_tracker_script(&script_tag, || EncodedScript::new(&script))
+        .await;
+
+    let mut response = (
         StatusCode::OK,
         [
             (header::CONTENT_TYPE, "application/javascript"),
-            (header::CACHE_CONTROL, "public, max-age=31536000"),
+            (header::ETAG, etag.as_str()),
+            (header::CACHE_CONTROL, cache_control),
+            (header::VARY, SCRIPT_VARY),
             (header::ACCESS_CONTROL_ALLOW_ORIGIN, &allow_origin),
         ],
-        script,
+        encoded.body(encoding),
     )
-        .into_response()
+        .into_response();
+    if let Some(value) = encoding.header_value() {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static(value));
+    }
+    response
+}
+
+/// The tracker script differs per DNT/GPC header, exclusion cookie, requesting
+/// origin and encoding
+const SCRIPT_VARY: &str = "Origin, DNT, Sec-GPC, Cookie, Accept-Encoding";
+
+/// Strong ETag derived from the rendered script, so any change to the
+/// template, service settings or script inject yields a new tag
+fn script_etag(script: &str) -> String {
+    let digest = Sha256::digest(script.as_bytes());
+    format!("\"{}\"", hex::encode(&digest[..16]))
+}
+
+/// ETag for an encoded variant of the script, e.g. `"abc-br"`
+fn encoded_etag(etag: &str, encoding: ContentEncoding) -> String {
+    match encoding.header_value() {
+        Some(suffix) => format!("{}-{}\"", etag.trim_end_matches('"'), suffix),
+        None => etag.to_string(),
+    }
+}
+
+/// Check an If-None-Match header against the current ETag
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// Cache-Control for the tracker script.
+/// Without a script inject the script only changes on upgrades or settings
+/// changes, so browsers may reuse it for a day. Administrator-provided inject
+/// content can change at any time, so browsers must revalidate every load
+/// (cheap thanks to the ETag).
+fn script_cache_control(script_inject: &str) -> &'static str {
+    if script_inject.trim().is_empty() {
+        "public, max-age=86400, stale-while-revalidate=3600"
+    } else {
+        "public, no-cache"
+    }
+}
+
+/// Resolve when an event happened from its `ts`. The time is clamped so a
+/// client can't backdate hits further than the skew window, and can't date
+/// them in the future.
+fn event_time(now: DateTime<Utc>, ts: Option<EventTime>, max_age_secs: u64) -> DateTime<Utc> {
+    let max_age_ms = i64::try_from(max_age_secs)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(1000);
+    match ts {
+        Some(EventTime::Age(offset)) => now
+            .checked_sub_signed(Duration::milliseconds(offset.clamp(0, max_age_ms)))
+            .unwrap_or(now),
+        Some(EventTime::At(time)) => {
+            let earliest = now
+                .checked_sub_signed(Duration::milliseconds(max_age_ms))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            time.clamp(earliest, now)
+        }
+        None => now,
+    }
 }
 
 /// POST /trace/app_:tracking_id.js
 pub async fn script_post_handler(
     State(state): State<AppState>,
     Path(tracking_id): Path<String>,
+    Query(query): Query<IngressQuery>,
     headers: HeaderMap,
-    Json(payload): Json<ScriptPayload>,
+    body: Bytes,
 ) -> Response {
     let tracking_id = strip_extension(&tracking_id).to_string();
-    script_post_handler_internal(state, tracking_id, None, headers, payload).await
+    script_post_handler_internal(state, tracking_id, None, query, headers, body).await
 }
 
 /// POST /trace/app_:tracking_id/:identifier.js
 pub async fn script_post_with_id_handler(
     State(state): State<AppState>,
     Path((tracking_id, identifier)): Path<(String, String)>,
+    Query(query): Query<IngressQuery>,
     headers: HeaderMap,
-    Json(payload): Json<ScriptPayload>,
+    body: Bytes,
 ) -> Response {
     // Strip .js suffix if present
     let identifier = identifier
         .strip_suffix(".js")
         .unwrap_or(&identifier)
         .to_string();
-    script_post_handler_internal(state, tracking_id, Some(identifier), headers, payload).await
+    script_post_handler_internal(state, tracking_id, Some(identifier), query, headers, body).await
 }
 
 async fn script_post_handler_internal(
     state: AppState,
     tracking_id: String,
     identifier: Option<String>,
+    query: IngressQuery,
     headers: HeaderMap,
-    payload: ScriptPayload,
+    body: Bytes,
 ) -> Response {
+    let Some(payload) = parse_script_payload(&headers, &body) else {
+        return (StatusCode::BAD_REQUEST, "Invalid payload").into_response();
+    };
     info!(
         "Script POST request for tracking_id={} payload={:?}",
         tracking_id, payload
@@ -348,19 +845,50 @@ async fn script_post_handler_internal(
     };
 
     info!("Found service: {} ({})", service.name, service.id);
+    let page_load = payload.is_page_load();
+    let location = payload.location.as_deref().unwrap_or_default();
+    let turn_away = |decision| {
+        let turned_away = log_turned_away(
+            &state,
+            &service,
+            TrackerType::Js,
+            decision,
+            location,
+            &headers,
+        );
+        async move {
+            if page_load {
+                turned_away.await;
+            }
+        }
+    };
 
     // Validate origin
     let (allow_origin, origin_valid) = validate_origin(&headers, &service);
     if !origin_valid {
-        return (StatusCode::FORBIDDEN, "Invalid origin").into_response();
+        turn_away(IngressDecision::OriginRejected).await;
+        return reject_origin(&state, &service, &headers);
     }
 
-    // Check DNT
-    if is_dnt_enabled(&headers) && service.respect_dnt {
+    // Check DNT/GPC
+    if let Some(decision) = opt_out(&headers, &service) {
         debug!("Ignoring due to DNT/GPC");
+        turn_away(decision).await;
         return json_response(allow_origin);
     }
 
+    if is_excluded(&headers, &tracking_id) {
+        debug!("Ignoring excluded browser");
+        turn_away(IngressDecision::DroppedExcluded).await;
+        return json_response(allow_origin);
+    }
+
+    let Some(prefetched) = prefetch_flag(&state, &headers) else {
+        debug!("Ignoring prefetch");
+        turn_away(IngressDecision::DroppedPrefetch).await;
+        return json_response(allow_origin);
+    };
+
     let ip = get_client_ip(&headers).unwrap_or_else(|| "0.0.0.0".to_string());
     let user_agent = get_user_agent(&headers);
 
@@ -368,15 +896,43 @@ async fn script_post_handler_internal(
     let ignored_networks = service.get_ignored_networks();
     if is_ip_ignored(&ip, &ignored_networks) {
         debug!("Ignoring due to ignored IP");
+        turn_away(IngressDecision::DroppedIp).await;
+        return json_response(allow_origin);
+    }
+
+    let time = event_time(
+        state.clock.now(),
+        payload.ts,
+        state.settings.max_timestamp_skew_secs,
+    );
+    let environment = hit_environment(query.env.as_deref(), &headers);
+
+    if let Some(report) = payload.error {
+        if service.track_errors {
+            if let Err(e) =
+                record_page_error(&state, &service, time, environment, location, report).await
+            {
+                error!("Error recording a page error: {}", e);
+            }
+        }
         return json_response(allow_origin);
     }
 
     let identifier = identifier.unwrap_or_default();
+    let dimensions = payload.dimension_pairs();
+    let props = payload.prop_pairs();
     let ingress_payload = IngressPayload {
         idempotency: payload.idempotency,
         location: payload.location.unwrap_or_default(),
         referrer: payload.referrer.unwrap_or_default(),
         load_time: payload.load_time,
+        end: payload.end,
+        environment,
+        prefetched,
+        referrer_spam: false,
+        dimensions,
+        props,
+        interacted: payload.interacted,
     };
 
     // Process synchronously for POST requests
@@ -384,7 +940,7 @@ async fn script_post_handler_internal(
         &state,
         &service,
         TrackerType::Js,
-        Utc::now(),
+        time,
         ingress_payload,
         &ip,
         &user_agent,
@@ -398,20 +954,71 @@ async fn script_post_handler_internal(
     json_response(allow_origin)
 }
 
+/// Count a page view turned away before processing as dropped, and log it if
+/// the service keeps a debug log
+async fn log_turned_away(
+    state: &AppState,
+    service: &Service,
+    tracker: TrackerType,
+    decision: IngressDecision,
+    location: &str,
+    headers: &HeaderMap,
+) {
+    if service.debug_log {
+        state.ingress_log.record(
+            service.id,
+            IngressLogEntry {
+                time: state.clock.now(),
+                tracker,
+                decision,
+                location: clean_text(location, MAX_URL_CHARS),
+                user_agent: clean_text(&get_user_agent(headers), MAX_FIELD_CHARS),
+                origin: get_origin(headers).filter(|_| decision == IngressDecision::OriginRejected),
+            },
+        );
+    }
+    count_dropped(state, service.id, decision, state.clock.now()).await;
+}
+
+/// Body of the 403 for a request from an origin the service doesn't allow
+#[derive(Debug, Serialize)]
+pub struct OriginRejection {
+    pub error: String,
+    /// `Origin` header as received, if there was one
+    pub origin: Option<String>,
+    /// The service's allowed origins, only shown while it keeps a debug log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+/// Refuse a request from an origin the service doesn't allow, logging and
+/// counting the mismatch so site owners can find out why tracking stopped
+fn reject_origin(state: &AppState, service: &Service, headers: &HeaderMap) -> Response {
+    let origin = get_origin(headers);
+    warn!(
+        "Origin {} not allowed for service {}",
+        origin.as_deref().unwrap_or("(none)"),
+        service.id
+    );
+    state
+        .origin_mismatches
+        .record(service.id, origin.as_deref(), state.clock.now());
+    let body = OriginRejection {
+        error: "Invalid origin".to_string(),
+        origin,
+        allowed_origins: service.debug_log.then(|| service.get_origins_list()),
+    };
+    (StatusCode::FORBIDDEN, Json(body)).into_response()
+}
+
 fn json_response(allow_origin: String) -> Response {
     (
         StatusCode::OK,
         [
             (header::CONTENT_TYPE, "application/json"),
             (header::ACCESS_CONTROL_ALLOW_ORIGIN, &allow_origin),
-            (
-                header::ACCESS_CONTROL_ALLOW_METHODS,
-                "GET,HEAD,OPTIONS,POST",
-            ),
-            (
-                header::ACCESS_CONTROL_ALLOW_HEADERS,
-                "Origin, X-Requested-With, Content-Type, Accept, Authorization, Referer",
-            ),
+            (header::ACCESS_CONTROL_ALLOW_METHODS, INGRESS_ALLOW_METHODS),
+            (header::ACCESS_CONTROL_ALLOW_HEADERS, INGRESS_ALLOW_HEADERS),
         ],
         Json(ScriptResponse {
             status: "OK".to_string(),
@@ -420,7 +1027,22 @@ fn json_response(allow_origin: String) -> Response {
         .into_response()
 }
 
-fn validate_origin(headers: &HeaderMap, service: &crate::domain::Service) -> (String, bool) {
+/// The opt-out the visitor's browser sent that the service honors, if any:
+/// Global Privacy Control, else Do Not Track
+pub(super) fn opt_out(headers: &HeaderMap, service: &Service) -> Option<IngressDecision> {
+    if service.respect_gpc && is_gpc_enabled(headers) {
+        Some(IngressDecision::DroppedGpc)
+    } else if service.respect_dnt && is_dnt_enabled(headers) {
+        Some(IngressDecision::DroppedDnt)
+    } else {
+        None
+    }
+}
+
+pub(super) fn validate_origin(
+    headers: &HeaderMap,
+    service: &crate::domain::Service,
+) -> (String, bool) {
     if service.origins == "*" {
         return ("*".to_string(), true);
     }
@@ -439,12 +1061,13 @@ fn validate_origin(headers: &HeaderMap, service: &crate::domain::Service) -> (St
     }
 }
 
+/// Render the tracker script, minified unless `readable` is asked for or the
+/// `minify-tracker` feature is off. The script inject is left as written.
 fn generate_tracker_script(
     dnt: bool,
-    protocol: &str,
-    endpoint: &str,
-    heartbeat_frequency: u64,
+    template: &TrackerScriptTemplate,
     script_inject: &str,
+    readable: bool,
 ) -> String {
     if dnt {
         return TrackerScriptDntTemplate
@@ -452,22 +1075,29 @@ fn generate_tracker_script(
             .unwrap_or_else(|_| "var shymini = { dnt: true };".to_string());
     }
 
-    let template = TrackerScriptTemplate {
-        protocol,
-        endpoint,
-        heartbeat_frequency,
-        script_inject,
+    let mut script = match template.render() {
+        Ok(script) if readable || !cfg!(feature = "minify-tracker") => script,
+        Ok(script) => minify_js(&script),
+        Err(e) => {
+            error!("Failed to render tracker script template: {}", e);
+            return "console.error('Failed to load tracker script');".to_string();
+        }
     };
 
-    template.render().unwrap_or_else(|e| {
-        error!("Failed to render tracker script template: {}", e);
-        "console.error('Failed to load tracker script');".to_string()
-    })
+    if !script_inject.is_empty() {
+        match (TrackerInjectTemplate { script_inject }).render() {
+            Ok(inject) => script.push_str(&inject),
+            Err(e) => error!("Failed to render script inject: {}", e),
+        }
+    }
+    script
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
+    use proptest::prelude::*;
 
     #[test]
     fn test_pixel_gif_is_valid_gif() {
@@ -489,15 +1119,48 @@ mod tests {
         assert_eq!(height, 1);
     }
 
+    fn classic<'a>(
+        protocol: &'a str,
+        endpoint: &'a str,
+        heartbeat_frequency: u64,
+        idle_timeout: u64,
+        collapse_tabs: bool,
+    ) -> TrackerScriptTemplate<'a> {
+        TrackerScriptTemplate {
+            protocol,
+            origin: "",
+            endpoint,
+            heartbeat_frequency,
+            idle_timeout,
+            collapse_tabs,
+            track_interaction: false,
+            track_errors: false,
+            error_sample_rate: 1.0,
+            module: false,
+            respect_dnt: false,
+            respect_gpc: false,
+        }
+    }
+
     #[test]
     fn test_generate_tracker_script_dnt() {
-        let script = generate_tracker_script(true, "https", "/ingress/uuid/script.js", 5000, "");
+        let script = generate_tracker_script(
+            true,
+            &classic("https", "/ingress/uuid/script.js", 5000, 1_800_000, true),
+            "",
+            true,
+        );
         assert_eq!(script, r#"var shymini = { dnt: true };"#);
     }
 
     #[test]
     fn test_generate_tracker_script_normal() {
-        let script = generate_tracker_script(false, "https", "/ingress/uuid/script.js", 5000, "");
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/ingress/uuid/script.js", 5000, 1_800_000, true),
+            "",
+            true,
+        );
 
         assert!(script.contains("var shymini = (function()"));
         assert!(script.contains("dnt: false"));
@@ -506,11 +1169,19 @@ mod tests {
         assert!(script.contains("scriptOrigin")); // Uses script origin instead of window.location.host
         assert!(script.contains("/ingress/uuid/script.js"));
         assert!(script.contains("5000")); // heartbeat frequency
+        assert!(script.contains("end: true")); // session end signal
+        assert!(script.contains("\"pagehide\", shymini.sendEnd"));
+        assert!(script.contains("\"prerenderingchange\""));
     }
 
     #[test]
     fn test_generate_tracker_script_http() {
-        let script = generate_tracker_script(false, "http", "/ingress/test/script.js", 3000, "");
+        let script = generate_tracker_script(
+            false,
+            &classic("http", "/ingress/test/script.js", 3000, 1_800_000, true),
+            "",
+            true,
+        );
 
         assert!(script.contains("http://"));
         assert!(script.contains("3000")); // heartbeat frequency
@@ -520,10 +1191,9 @@ mod tests {
     fn test_generate_tracker_script_with_inject() {
         let script = generate_tracker_script(
             false,
-            "https",
-            "/ingress/uuid/script.js",
-            5000,
+            &classic("https", "/ingress/uuid/script.js", 5000, 1_800_000, true),
             "console.log('custom code');",
+            true,
         );
 
         assert!(script.contains("console.log('custom code');"));
@@ -534,13 +1204,251 @@ mod tests {
 
     #[test]
     fn test_generate_tracker_script_empty_inject() {
-        let script = generate_tracker_script(false, "https", "/test", 5000, "");
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
 
         // Should not contain inject markers
         assert!(!script.contains("// -- START --"));
         assert!(!script.contains("provided by this site's administrator"));
     }
 
+    #[test]
+    fn test_generate_tracker_script_collapse_tabs() {
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
+
+        // Tabs coordinate through a shared lock and join an existing hit on the same page
+        assert!(script.contains("BroadcastChannel"));
+        assert!(script.contains("localStorage"));
+        assert!(script.contains("claimLock"));
+        assert!(script.contains("lock.idempotency"));
+    }
+
+    #[test]
+    fn test_generate_tracker_script_idle_timeout() {
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
+        assert!(script.contains("var idleTimeout = 1800000;"));
+        assert!(script.contains("shymini.markActive"));
+
+        // Without a timeout nobody goes idle and nothing listens for activity
+        let script =
+            generate_tracker_script(false, &classic("https", "/test", 5000, 0, true), "", true);
+        assert!(script.contains("var idleTimeout = 0;"));
+        assert!(!script.contains("shymini.markActive"));
+    }
+
+    #[test]
+    fn test_generate_tracker_script_track_interaction() {
+        let template = TrackerScriptTemplate {
+            track_interaction: true,
+            ..classic("https", "/test", 5000, 0, true)
+        };
+        let script = generate_tracker_script(false, &template, "", true);
+        assert!(script.contains("shymini.markInteraction"));
+        assert!(script.contains("payload.interacted = true;"));
+
+        // Only services that count interaction get the listeners
+        let script =
+            generate_tracker_script(false, &classic("https", "/test", 5000, 0, true), "", true);
+        assert!(!script.contains("markInteraction"));
+    }
+
+    #[test]
+    fn test_generate_tracker_script_track_errors() {
+        let template = TrackerScriptTemplate {
+            track_errors: true,
+            error_sample_rate: 0.5,
+            ..classic("https", "/test", 5000, 0, true)
+        };
+        let script = generate_tracker_script(false, &template, "", true);
+        assert!(script.contains("var errorSampleRate = 0.5;"));
+        assert!(script.contains("window.addEventListener(\"unhandledrejection\""));
+        assert!(script.contains("error: { message: message, stack: stack }"));
+
+        let script =
+            generate_tracker_script(false, &classic("https", "/test", 5000, 0, true), "", true);
+        assert!(!script.contains("reportError"));
+    }
+
+    #[test]
+    fn test_generate_tracker_script_without_collapse_tabs() {
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, false),
+            "",
+            true,
+        );
+
+        assert!(!script.contains("BroadcastChannel"));
+        assert!(!script.contains("claimLock"));
+        assert!(script.contains("sendHeartbeat"));
+    }
+
+    /// Bytes the minified tracker may take with every option on; raise it
+    /// deliberately when the tracker grows
+    #[cfg(feature = "minify-tracker")]
+    const TRACKER_SIZE_BUDGET: usize = 7_250;
+
+    #[cfg(feature = "minify-tracker")]
+    #[test]
+    fn test_generate_tracker_script_minified() {
+        let endpoint = "/trace/app_0123456789abcdef0123456789abcdef.js";
+        let readable = generate_tracker_script(
+            false,
+            &classic("https", endpoint, 5000, 1_800_000, true),
+            "",
+            true,
+        );
+        let script = generate_tracker_script(
+            false,
+            &classic("https", endpoint, 5000, 1_800_000, true),
+            "",
+            false,
+        );
+
+        assert!(
+            script.len() <= TRACKER_SIZE_BUDGET,
+            "minified tracker is {} bytes, over the budget of {}",
+            script.len(),
+            TRACKER_SIZE_BUDGET
+        );
+        assert!(script.len() < readable.len() * 3 / 4);
+        assert!(script.starts_with("var shymini=(function(){"));
+        assert!(script.contains(&format!("\"{}\"", endpoint)));
+        assert!(script.contains("setInterval(shymini.sendHeartbeat,5000)"));
+        assert!(!script.contains("// "));
+        assert!(!script.contains("  "));
+    }
+
+    #[cfg(feature = "minify-tracker")]
+    #[test]
+    fn test_generate_tracker_script_minified_inject() {
+        // The administrator's code is appended as written
+        let inject = "// analytics\nconsole.log('custom  code');";
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            inject,
+            false,
+        );
+        assert!(script.contains("var shymini=(function(){"));
+        assert!(script.contains("// -- START --\n// analytics\nconsole.log('custom  code');"));
+    }
+
+    #[test]
+    fn test_generate_tracker_script_module() {
+        let template = TrackerScriptTemplate {
+            module: true,
+            origin: "https://stats.example.com",
+            respect_dnt: true,
+            respect_gpc: false,
+            ..classic("https", "/trace/app_abc.js", 5000, 1_800_000, true)
+        };
+        let script = generate_tracker_script(false, &template, "", true);
+
+        assert!(script.contains("export function init(options)"));
+        assert!(script.contains("export function track(dimensions)"));
+        assert!(script.contains("export function setProps(props)"));
+        assert!(script.contains("var scriptOrigin = \"https://stats.example.com\";"));
+        assert!(script.contains("navigator.doNotTrack"));
+        assert!(!script.contains("navigator.globalPrivacyControl"));
+        // Started by init(), not on page load
+        assert!(!script.contains("document.currentScript"));
+        assert!(!script.contains("\"load\", shymini.newPageLoad"));
+
+        let classic = generate_tracker_script(
+            false,
+            &classic("https", "/trace/app_abc.js", 5000, 1_800_000, true),
+            "",
+            true,
+        );
+        assert!(classic.contains("window.addEventListener(\"load\", function () {"));
+        assert!(classic.contains("shymini.whenActivated(shymini.newPageLoad);"));
+        assert!(!classic.contains("export "));
+        assert!(!classic.contains("navigator.doNotTrack"));
+    }
+
+    #[test]
+    fn test_script_query_readable() {
+        let query = |debug: Option<&str>| ScriptQuery {
+            debug: debug.map(String::from),
+            env: None,
+        };
+        assert!(query(Some("1")).readable());
+        assert!(query(Some("true")).readable());
+        assert!(!query(Some("0")).readable());
+        assert!(!query(None).readable());
+    }
+
+    #[test]
+    fn test_script_etag() {
+        let etag = script_etag("var shymini = 1;");
+
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(etag.len(), 34);
+        assert_eq!(etag, script_etag("var shymini = 1;"));
+        assert_ne!(etag, script_etag("var shymini = 2;"));
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = script_etag("var shymini = 1;");
+
+        let mut headers = HeaderMap::new();
+        assert!(!etag_matches(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, etag.parse().unwrap());
+        assert!(etag_matches(&headers, &etag));
+
+        headers.insert(
+            header::IF_NONE_MATCH,
+            format!("\"other\", W/{}", etag).parse().unwrap(),
+        );
+        assert!(etag_matches(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+        assert!(etag_matches(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        assert!(!etag_matches(&headers, &etag));
+    }
+
+    #[test]
+    fn test_encoded_etag() {
+        assert_eq!(
+            encoded_etag("\"abc\"", ContentEncoding::Identity),
+            "\"abc\""
+        );
+        assert_eq!(
+            encoded_etag("\"abc\"", ContentEncoding::Gzip),
+            "\"abc-gzip\""
+        );
+        assert_eq!(
+            encoded_etag("\"abc\"", ContentEncoding::Brotli),
+            "\"abc-br\""
+        );
+    }
+
+    #[test]
+    fn test_script_cache_control() {
+        assert!(script_cache_control("").contains("max-age=86400"));
+        assert!(script_cache_control("  ").contains("max-age=86400"));
+        assert_eq!(script_cache_control("console.log(1);"), "public, no-cache");
+    }
+
     #[test]
     fn test_script_payload_deserialization() {
         let json = r#"{"idempotency": "abc123", "location": "/home", "referrer": "https://google.com", "loadTime": 150.5}"#;
@@ -561,6 +1469,178 @@ mod tests {
         assert!(payload.location.is_none());
         assert!(payload.referrer.is_none());
         assert!(payload.load_time.is_none());
+        assert!(payload.ts.is_none());
+    }
+
+    #[test]
+    fn test_script_payload_deserialization_end() {
+        let json = r#"{"idempotency": "abc123", "location": "/home", "end": true}"#;
+        let payload: ScriptPayload = serde_json::from_str(json).unwrap();
+        assert!(payload.end);
+
+        let json = r#"{"idempotency": "abc123", "location": "/home"}"#;
+        let payload: ScriptPayload = serde_json::from_str(json).unwrap();
+        assert!(!payload.end);
+    }
+
+    #[test]
+    fn test_script_payload_deserialization_with_ts() {
+        let json = r#"{"idempotency": "abc123", "location": "/home", "ts": 4500}"#;
+        let payload: ScriptPayload = serde_json::from_str(json).unwrap();
+
+        assert_eq!(payload.ts, Some(EventTime::Age(4500)));
+    }
+
+    #[test]
+    fn test_script_payload_dimensions() {
+        let json = r#"{"location": "/home", "dimensions": {"author": "Ada", "words": 1200, "draft": false, "tags": ["a"], "none": null}}"#;
+        let payload: ScriptPayload = serde_json::from_str(json).unwrap();
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            payload.dimension_pairs(),
+            [
+                pair("author", "Ada"),
+                pair("draft", "false"),
+                pair("words", "1200")
+            ]
+        );
+
+        let json = r#"{"location": "/home", "dimensions": "author=Ada", "props": {"plan": "pro"}}"#;
+        let payload: ScriptPayload = serde_json::from_str(json).unwrap();
+        assert!(payload.dimension_pairs().is_empty());
+        assert_eq!(payload.prop_pairs(), [pair("plan", "pro")]);
+    }
+
+    #[test]
+    fn test_event_time() {
+        let now = Utc::now();
+
+        let age = |ms| Some(EventTime::Age(ms));
+        assert_eq!(event_time(now, None, 1800), now);
+        assert_eq!(
+            event_time(now, age(4500), 1800),
+            now - Duration::milliseconds(4500)
+        );
+        // Future offsets are ignored and old offsets are capped
+        assert_eq!(event_time(now, age(-1000), 1800), now);
+        assert_eq!(
+            event_time(now, age(i64::MAX), 1800),
+            now - Duration::seconds(1800)
+        );
+        // A window too long to subtract falls back to now
+        assert_eq!(event_time(now, age(i64::MAX), u64::MAX), now);
+
+        // Times are clamped to the same window
+        let at = |time| Some(EventTime::At(time));
+        let earlier = now - Duration::minutes(10);
+        assert_eq!(event_time(now, at(earlier), 1800), earlier);
+        assert_eq!(
+            event_time(now, at(now - Duration::days(2)), 1800),
+            now - Duration::seconds(1800)
+        );
+        assert_eq!(event_time(now, at(now + Duration::hours(1)), 1800), now);
+        assert_eq!(event_time(now, at(earlier), u64::MAX), earlier);
+    }
+
+    #[test]
+    fn test_event_time_parse() {
+        assert_eq!(EventTime::parse(" 4500 "), Some(EventTime::Age(4500)));
+        assert_eq!(
+            EventTime::parse("2026-01-19T11:00:00+01:00"),
+            Some(EventTime::At(
+                Utc.with_ymd_and_hms(2026, 1, 19, 10, 0, 0).unwrap()
+            ))
+        );
+        assert_eq!(EventTime::parse("yesterday"), None);
+
+        let payload: ScriptPayload =
+            serde_json::from_str(r#"{"ts": "2026-01-19T10:00:00Z"}"#).unwrap();
+        assert!(matches!(payload.ts, Some(EventTime::At(_))));
+        let payload: ScriptPayload = serde_urlencoded::from_str("ts=4500").unwrap();
+        assert_eq!(payload.ts, Some(EventTime::Age(4500)));
+        assert!(serde_json::from_str::<ScriptPayload>(r#"{"ts": true}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_script_payload_content_types() {
+        let headers = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+            headers
+        };
+        let json = br#" {"idempotency": "abc123", "location": "/home", "loadTime": 150.5}"#;
+        for content_type in ["application/json", "text/plain;charset=UTF-8", ""] {
+            let payload = parse_script_payload(&headers(content_type), json).unwrap();
+            assert_eq!(payload.location.as_deref(), Some("/home"));
+            assert_eq!(payload.load_time, Some(150.5));
+        }
+
+        let form = b"idempotency=abc123&location=%2Fhome&loadTime=150.5&end=true";
+        let payload =
+            parse_script_payload(&headers("application/x-www-form-urlencoded"), form).unwrap();
+        assert_eq!(payload.idempotency.as_deref(), Some("abc123"));
+        assert_eq!(payload.location.as_deref(), Some("/home"));
+        assert_eq!(payload.load_time, Some(150.5));
+        assert!(payload.end);
+
+        // Only forms are read as fields, and never empty ones
+        assert!(parse_script_payload(&headers("text/plain"), form).is_none());
+        assert!(parse_script_payload(&headers("application/x-www-form-urlencoded"), b"").is_none());
+        assert!(parse_script_payload(&headers("text/plain"), b"{not json").is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_script_payload_never_panics(body in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = serde_json::from_slice::<ScriptPayload>(&body);
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
+            let _ = parse_script_payload(&headers, &body);
+        }
+
+        #[test]
+        fn prop_script_payload_keeps_strings(
+            idempotency in any::<String>(),
+            location in any::<String>(),
+            referrer in any::<String>(),
+            ts in any::<i64>(),
+        ) {
+            let body = serde_json::json!({
+                "idempotency": idempotency,
+                "location": location,
+                "referrer": referrer,
+                "ts": ts,
+            });
+            let payload: ScriptPayload = serde_json::from_value(body).unwrap();
+            prop_assert_eq!(payload.idempotency, Some(idempotency));
+            prop_assert_eq!(payload.location, Some(location));
+            prop_assert_eq!(payload.referrer, Some(referrer));
+            prop_assert_eq!(payload.ts, Some(EventTime::Age(ts)));
+        }
+
+        #[test]
+        fn prop_event_time_within_window(ts in any::<Option<i64>>(), max_age_secs in 0u64..=31_536_000) {
+            let now = Utc::now();
+            let time = event_time(now, ts.map(EventTime::Age), max_age_secs);
+            prop_assert!(time <= now);
+            prop_assert!(now - time <= Duration::seconds(max_age_secs as i64));
+        }
+    }
+
+    #[test]
+    fn test_generate_tracker_script_offline_queue() {
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
+
+        assert!(script.contains("sessionStorage"));
+        assert!(script.contains("shymini_queue:/test"));
+        assert!(script.contains("navigator.sendBeacon"));
+        assert!(script.contains("payload.ts"));
+        assert!(script.contains("\"online\""));
     }
 
     #[test]
@@ -574,7 +1654,12 @@ mod tests {
 
     #[test]
     fn test_generate_tracker_script_contains_fetch() {
-        let script = generate_tracker_script(false, "https", "/test", 5000, "");
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
 
         // Script should use fetch API
         assert!(script.contains("fetch("));
@@ -585,7 +1670,12 @@ mod tests {
 
     #[test]
     fn test_generate_tracker_script_visibility_api() {
-        let script = generate_tracker_script(false, "https", "/test", 5000, "");
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
 
         // Script should check document visibility
         assert!(script.contains("document.hidden"));
@@ -593,7 +1683,12 @@ mod tests {
 
     #[test]
     fn test_generate_tracker_script_sends_correct_data() {
-        let script = generate_tracker_script(false, "https", "/test", 5000, "");
+        let script = generate_tracker_script(
+            false,
+            &classic("https", "/test", 5000, 1_800_000, true),
+            "",
+            true,
+        );
 
         // Script should send idempotency, referrer, location
         assert!(script.contains("idempotency: shymini.idempotency"));
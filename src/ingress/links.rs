@@ -0,0 +1,119 @@
+//! Click tracking. A service's tracked links redirect through
+//! `/r/:token`: each request counts a click, tied to the visitor's open
+//! session when there is one, and is sent on to the link's target. The
+//! redirect never waits for the click to be stored, and visitors the
+//! service does not track are redirected all the same. Links can also be
+//! printed as QR codes of their URL.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use qrcode::{render::svg, QrCode};
+use tracing::{debug, error};
+
+use super::{clean_text, opt_out, session_cache_key, visitor_hash, MAX_URL_CHARS};
+use crate::db;
+use crate::domain::{CreateLinkClick, DeviceType, Environment, ServiceStatus, TrackedLink};
+use crate::error::{Error, Result};
+use crate::privacy::{get_client_ip, get_user_agent, is_excluded, is_ip_ignored, is_prefetch};
+use crate::state::AppState;
+use crate::ua::parse_user_agent;
+
+/// Hex characters of the session association hash kept per click, enough
+/// to tell visitors apart
+const VISITOR_CHARS: usize = 16;
+
+/// Absolute URL a tracked link is shared as
+pub fn tracked_link_url(state: &AppState, link: &TrackedLink) -> String {
+    state.settings.public_link(&format!("/r/{}", link.token))
+}
+
+/// SVG QR code of a link's URL, for print
+pub fn tracked_link_qr_svg(state: &AppState, link: &TrackedLink) -> Result<String> {
+    let code = QrCode::new(tracked_link_url(state, link).as_bytes())
+        .map_err(|e| Error::Internal(format!("QR code: {}", e)))?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .quiet_zone(true)
+        .build())
+}
+
+/// GET /r/:token
+pub async fn link_redirect_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let link = match db::get_tracked_link_by_token(&state.pool, &token).await {
+        Ok(Some(link)) => link,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Link not found").into_response(),
+        Err(e) => {
+            error!("Error fetching link: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+        }
+    };
+
+    let target = link.target_url.clone();
+    // Link previews and prefetches are not clicks
+    if !is_prefetch(&headers) {
+        tokio::spawn(async move {
+            if let Err(e) = record_click(&state, &link, &headers).await {
+                error!("Error recording link click: {}", e);
+            }
+        });
+    }
+
+    (
+        StatusCode::FOUND,
+        [
+            (header::LOCATION, target.as_str()),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+    )
+        .into_response()
+}
+
+/// Store a click unless the service ignores this visitor, as it would their
+/// page views
+async fn record_click(state: &AppState, link: &TrackedLink, headers: &HeaderMap) -> Result<()> {
+    let service = db::get_service(&state.pool, link.service_id).await?;
+    if service.status != ServiceStatus::Active {
+        return Ok(());
+    }
+    if opt_out(headers, &service).is_some() || is_excluded(headers, &service.tracking_id.0) {
+        debug!("Not counting a click of link {}", link.id);
+        return Ok(());
+    }
+
+    let ip = get_client_ip(headers).unwrap_or_else(|| "0.0.0.0".to_string());
+    let user_agent = get_user_agent(headers);
+    if is_ip_ignored(&ip, &service.get_ignored_networks()) {
+        return Ok(());
+    }
+    if service.ignore_robots && parse_user_agent(&user_agent).device_type == DeviceType::Robot {
+        return Ok(());
+    }
+
+    let hash = visitor_hash(state, &service, &ip, &user_agent);
+    let session_id = state
+        .cache
+        .get_session_association(&session_cache_key(&service, Environment::Production, &hash))
+        .await
+        .map(|association| association.session_id);
+    let referrer = headers
+        .get(header::REFERER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let click = CreateLinkClick {
+        link_id: link.id,
+        session_id,
+        visitor: hash.0.get(..VISITOR_CHARS).unwrap_or(&hash.0).to_string(),
+        referrer: clean_text(referrer, MAX_URL_CHARS),
+        country: state.geo.lookup(&ip).country,
+        time: state.clock.now(),
+    };
+    db::record_link_click(&state.pool, click).await
+}
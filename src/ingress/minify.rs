@@ -0,0 +1,166 @@
+//! A small minifier for the tracker script: comments, indentation and
+//! whitespace JavaScript doesn't need are dropped. It knows string literals
+//! but not regular expression literals or `${}` in template literals, neither
+//! of which the tracker uses. Line breaks are kept wherever automatic
+//! semicolon insertion could depend on them.
+
+/// `source` without comments and without the whitespace it doesn't need
+pub fn minify_js(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut out = String::with_capacity(source.len() / 2);
+    // Whitespace (or a comment) since the last character written, and
+    // whether it contained a line break
+    let mut gap: Option<bool> = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '\'' | '`' => {
+                close_gap(&mut out, &mut gap, c);
+                let end = string_end(&chars, i);
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                // The line break ending the comment is whitespace like any other
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                gap = Some(gap.unwrap_or(false));
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let mut newline = false;
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    newline |= chars[i] == '\n';
+                    i += 1;
+                }
+                i = (i + 2).min(chars.len());
+                gap = Some(gap.unwrap_or(false) || newline);
+            }
+            c if c.is_whitespace() => {
+                gap = Some(gap.unwrap_or(false) || c == '\n');
+                i += 1;
+            }
+            c => {
+                close_gap(&mut out, &mut gap, c);
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Index just past the string literal starting at `start`
+fn string_end(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            // Unterminated; leave the rest of the line as it is
+            '\n' if quote != '`' => return i,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Write what is left of the whitespace before `next`
+fn close_gap(out: &mut String, gap: &mut Option<bool>, next: char) {
+    let Some(newline) = gap.take() else {
+        return;
+    };
+    let Some(prev) = out.chars().last() else {
+        return;
+    };
+    if newline && !joins_lines(prev, next) {
+        out.push('\n');
+    } else if needs_space(prev, next) {
+        out.push(' ');
+    }
+}
+
+/// Whether a line break between `prev` and `next` can go: the statement
+/// clearly continues, so no semicolon is inserted there either way
+fn joins_lines(prev: char, next: char) -> bool {
+    "{([,;:=&|?<>*%!".contains(prev) || "})],;:.?=&|<>*%".contains(next)
+}
+
+/// Whether `prev` and `next` would run together into a different token
+fn needs_space(prev: char, next: char) -> bool {
+    (is_word(prev) && is_word(next)) || ("+-".contains(prev) && "+-".contains(next))
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || !c.is_ascii()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments_and_whitespace() {
+        let source = r#"
+            // Say hello
+            var greeting = "hello";  /* inline */
+            function greet(name) {
+              return greeting + " " + name;
+            }
+        "#;
+        assert_eq!(
+            minify_js(source),
+            "var greeting=\"hello\";function greet(name){return greeting+\" \"+name;}"
+        );
+    }
+
+    #[test]
+    fn test_strings_are_kept() {
+        assert_eq!(
+            minify_js(r#"var a = "x  // not a comment";"#),
+            r#"var a="x  // not a comment";"#
+        );
+        assert_eq!(
+            minify_js(r#"var b = 'it\'s /* still */ a string';"#),
+            r#"var b='it\'s /* still */ a string';"#
+        );
+        assert_eq!(minify_js("var c = `a  b`;"), "var c=`a  b`;");
+        assert_eq!(
+            minify_js(r#"fetch(origin + "/trace/app_x.js");"#),
+            r#"fetch(origin+"/trace/app_x.js");"#
+        );
+    }
+
+    #[test]
+    fn test_tokens_stay_apart() {
+        assert_eq!(minify_js("return x"), "return x");
+        assert_eq!(minify_js("a + +b"), "a+ +b");
+        assert_eq!(minify_js("a - -b"), "a- -b");
+        assert_eq!(minify_js("i++ + 1"), "i++ +1");
+        assert_eq!(minify_js("typeof/**/x"), "typeof x");
+    }
+
+    #[test]
+    fn test_line_breaks() {
+        // Kept where semicolon insertion may rely on them
+        assert_eq!(minify_js("a = b\n(c)"), "a=b\n(c)");
+        assert_eq!(minify_js("i++\nj"), "i++\nj");
+        assert_eq!(minify_js("return\nx"), "return\nx");
+        // Dropped where the statement clearly goes on
+        assert_eq!(minify_js("f(a,\n  b)"), "f(a,b)");
+        assert_eq!(minify_js("x = {\n  a: 1\n};"), "x={a:1};");
+        assert_eq!(minify_js("p\n  .then(f)"), "p.then(f)");
+    }
+
+    #[test]
+    fn test_unterminated() {
+        assert_eq!(minify_js("var a = \"open\nb"), "var a=\"open\nb");
+        assert_eq!(minify_js("a /* open"), "a");
+        assert_eq!(minify_js(""), "");
+    }
+}
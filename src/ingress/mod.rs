@@ -1,5 +1,21 @@
+mod debug;
+mod dedup;
+mod encoding;
+mod errors;
 mod handlers;
+mod links;
+mod minify;
 mod processor;
+mod script;
+mod validate;
 
+pub use debug::*;
+pub use dedup::*;
+pub use encoding::*;
+pub use errors::*;
 pub use handlers::*;
+pub use links::*;
+pub use minify::*;
 pub use processor::*;
+pub use script::*;
+pub use validate::*;
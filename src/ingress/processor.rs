@@ -1,21 +1,163 @@
-use chrono::{DateTime, Utc};
-use tracing::debug;
+use std::sync::Arc;
 
-use crate::db::{self, Pool};
+use chrono::{DateTime, Duration, Utc};
+use tracing::{debug, warn};
+
+use super::{log_decision, IngestScript};
+use crate::cache::SessionAssociation;
+use crate::db;
 use crate::domain::{
-    CreateHit, CreateSession, DeviceType, HitId, Service, ServiceId, SessionAssociationHash,
+    BounceRule, CreateHit, CreateSession, DeviceType, Environment, HitId, IngressDecision,
+    QuotaBehavior, ReferrerSpamBehavior, Service, ServiceId, ServiceUsage, SessionAssociationHash,
     SessionId, TrackerType,
 };
 use crate::error::Result;
+use crate::hooks::{IngressAction, IngressContext, RecordedHit};
 use crate::state::AppState;
 use crate::ua::parse_user_agent;
 
+/// Longest location or referrer stored, in characters; longer ones are cut
+pub const MAX_URL_CHARS: usize = 2048;
+/// Longest user agent, identifier or idempotency key stored, in characters
+pub const MAX_FIELD_CHARS: usize = 512;
+/// Longest custom dimension or session property key, in characters; longer
+/// keys are dropped
+pub const MAX_DIMENSION_KEY_CHARS: usize = 32;
+
 #[derive(Debug, Default)]
 pub struct IngressPayload {
     pub idempotency: Option<String>,
     pub location: String,
     pub referrer: String,
     pub load_time: Option<f64>,
+    /// The visitor left the page view identified by `idempotency`
+    pub end: bool,
+    pub environment: Environment,
+    /// Sent for a page the browser prefetched or prerendered
+    pub prefetched: bool,
+    /// Set by ingress when the referrer is on the spam list and
+    /// `referrer_spam` is `flag`
+    pub referrer_spam: bool,
+    /// Custom key/value dimensions of the page view, in the order sent
+    pub dimensions: Vec<(String, String)>,
+    /// Properties to set on the session, in the order sent
+    pub props: Vec<(String, String)>,
+    /// The visitor scrolled, clicked, tapped or typed on the page
+    pub interacted: bool,
+}
+
+impl IngressPayload {
+    /// The payload as it may be stored: text cleaned and cut to length, and
+    /// load times that are not a positive number dropped
+    pub fn cleaned(self) -> Self {
+        Self {
+            idempotency: self
+                .idempotency
+                .map(|key| clean_text(&key, MAX_FIELD_CHARS))
+                .filter(|key| !key.is_empty()),
+            location: clean_text(&self.location, MAX_URL_CHARS),
+            referrer: clean_text(&self.referrer, MAX_URL_CHARS),
+            load_time: self.load_time.filter(|&t| t.is_finite() && t > 0.0),
+            end: self.end,
+            environment: self.environment,
+            prefetched: self.prefetched,
+            referrer_spam: self.referrer_spam,
+            dimensions: clean_dimensions(self.dimensions),
+            props: clean_dimensions(self.props),
+            interacted: self.interacted,
+        }
+    }
+
+    /// Whether the payload is for a page load rather than a heartbeat or a
+    /// leave
+    pub fn is_page_load(&self) -> bool {
+        !self.end && (self.load_time.is_some() || self.idempotency.is_none())
+    }
+}
+
+/// Dimensions or session properties as they may be stored: keys of ASCII
+/// letters, digits, `_` and `-` only, each key once, and values cleaned like
+/// other text. Pairs with another key or an empty value are dropped.
+pub(super) fn clean_dimensions(dimensions: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut cleaned: Vec<(String, String)> = Vec::new();
+    for (key, value) in dimensions {
+        let key = key.trim();
+        let value = clean_text(&value, MAX_FIELD_CHARS);
+        let valid_key = !key.is_empty()
+            && key.len() <= MAX_DIMENSION_KEY_CHARS
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if valid_key && !value.is_empty() && !cleaned.iter().any(|(k, _)| k == key) {
+            cleaned.push((key.to_string(), value));
+        }
+    }
+    cleaned
+}
+
+/// Visitor-supplied text made safe to store: control characters (Postgres
+/// refuses NUL in text columns) dropped, surrounding whitespace trimmed and
+/// the rest cut to `max_chars` characters
+pub fn clean_text(text: &str, max_chars: usize) -> String {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let cut: String = text.trim().chars().take(max_chars).collect();
+    cut.trim_end().to_string()
+}
+
+/// A service's ingest script, if it has one. A stored script that no longer
+/// compiles is logged and skipped.
+pub(super) async fn ingest_script(
+    state: &AppState,
+    service_id: ServiceId,
+) -> Result<Option<Arc<IngestScript>>> {
+    state
+        .cache
+        .get_or_load_ingest_script(service_id, async {
+            let source = db::get_ingest_script(&state.pool, service_id).await?;
+            Ok(
+                source.and_then(|source| match IngestScript::compile(&source) {
+                    Ok(script) => Some(script),
+                    Err(e) => {
+                        warn!(
+                            "Ingest script of service {} does not compile: {}",
+                            service_id, e
+                        );
+                        None
+                    }
+                }),
+            )
+        })
+        .await
+}
+
+/// The session association hash of a visitor to a service
+pub fn visitor_hash(
+    state: &AppState,
+    service: &Service,
+    ip: &str,
+    user_agent: &str,
+) -> SessionAssociationHash {
+    let aggressive_salting = state.settings.aggressive_hash_salting;
+    SessionAssociationHash::compute(
+        ip,
+        user_agent,
+        if aggressive_salting {
+            Some(&service.id)
+        } else {
+            None
+        },
+        aggressive_salting,
+    )
+}
+
+/// Cache key of a visitor's open session. The same visitor on a dev copy of
+/// the site gets a separate session.
+pub fn session_cache_key(
+    service: &Service,
+    environment: Environment,
+    hash: &SessionAssociationHash,
+) -> String {
+    format!("session_{}_{}_{}", service.id, environment.as_str(), hash)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -34,38 +176,127 @@ pub async fn process_ingress(
         service.id, tracker
     );
 
-    // Validate and clean payload
-    let load_time = payload.load_time.filter(|&t| t > 0.0);
+    // Validate and clean payload, folding URL variants of a page into one
+    let mut payload = payload.cleaned();
+    payload.location = service.get_path_normalization().apply(&payload.location);
+    let user_agent = &clean_text(user_agent, MAX_FIELD_CHARS);
+    let identifier = &clean_text(identifier, MAX_FIELD_CHARS);
+    let page_load = payload.is_page_load();
+    // Page views go in the debug log, and those turned away are counted
+    let log = move |decision: IngressDecision, location: &str| {
+        if page_load {
+            log_decision(state, service, tracker, decision, location, user_agent);
+        }
+        async move {
+            if page_load && !decision.is_accepted() {
+                count_dropped(state, service.id, decision, time).await;
+            }
+        }
+    };
 
-    // Compute session association hash
-    let aggressive_salting = state.settings.aggressive_hash_salting;
-    let hash = SessionAssociationHash::compute(
+    // The service's own script runs first, then the compiled-in hooks
+    if let Some(script) = ingest_script(state, service.id).await? {
+        match script.run(tracker, user_agent, &mut payload) {
+            Ok(IngressAction::Record) => {}
+            Ok(IngressAction::Drop) => {
+                debug!("Ingest script dropped a hit for service {}", service.id);
+                log(IngressDecision::DroppedScript, &payload.location).await;
+                return Ok(());
+            }
+            Err(e) => warn!("Service {}: {}", service.id, e),
+        }
+    }
+
+    let context = IngressContext {
+        service,
+        tracker,
+        time,
         ip,
         user_agent,
-        if aggressive_salting {
-            Some(&service.id)
-        } else {
-            None
-        },
-        aggressive_salting,
-    );
+        identifier,
+    };
+    if state
+        .hooks
+        .before_record(state, &context, &mut payload)
+        .await
+        == IngressAction::Drop
+    {
+        debug!("Ingress hook dropped a hit for service {}", service.id);
+        log(IngressDecision::DroppedScript, &payload.location).await;
+        return Ok(());
+    }
+    payload
+        .dimensions
+        .truncate(state.settings.max_hit_dimensions);
+    let load_time = payload.load_time;
+
+    let hash = visitor_hash(state, service, ip, user_agent);
+    let cache_key = session_cache_key(service, payload.environment, &hash);
+    if payload.end {
+        return end_page_view(state, service, &cache_key, &payload, time).await;
+    }
 
-    let cache_key = format!("session_{}_{}", service.id, hash);
+    if state.settings.verify_bots && !state.bots.admits(ip, user_agent).await {
+        debug!(
+            "Dropped a hit from a fake crawler for service {}",
+            service.id
+        );
+        log(IngressDecision::DroppedFakeBot, &payload.location).await;
+        return Ok(());
+    }
 
-    // Try to find existing session in cache
-    let (session_id, initial) = match state.cache.get_session_association(&cache_key).await {
-        Some(session_id) => {
-            debug!("Found existing session {} in cache", session_id);
-            state.cache.touch_session_association(&cache_key).await;
+    // Spam referrers only ever come with page loads
+    if page_load && state.referrer_spam.is_spam(&payload.referrer) {
+        match state.settings.referrer_spam {
+            ReferrerSpamBehavior::Drop => {
+                debug!(
+                    "Dropped a hit from referrer spam for service {}",
+                    service.id
+                );
+                log(IngressDecision::DroppedSpam, &payload.location).await;
+                return Ok(());
+            }
+            ReferrerSpamBehavior::Flag => payload.referrer_spam = true,
+        }
+    }
 
-            // Update session last_seen
-            db::update_session_last_seen(&state.pool, session_id, time).await?;
+    let month = ServiceUsage::month_of(time);
+    if !within_quota(state, service, &hash, &month).await? {
+        debug!(
+            "Hit quota used up for service {}, not recording",
+            service.id
+        );
+        db::record_usage(&state.pool, service.id, &month, 0, 0, 1, 0).await?;
+        log(IngressDecision::DroppedQuota, &payload.location).await;
+        return Ok(());
+    }
+
+    // Try to find existing session in cache. A hit dated before the window
+    // sessions are remembered for, e.g. from an import, gets one of its own.
+    let backdated = is_backdated(state, time);
+    let association = if backdated {
+        None
+    } else {
+        state.cache.get_session_association(&cache_key).await
+    };
+    // A visitor back after the service's session timeout starts a new one
+    let timed_out =
+        association.is_some_and(|association| session_timed_out(service, &association, time));
+    let association = association.filter(|_| !timed_out);
+    let (session_id, initial) = match association {
+        Some(SessionAssociation { session_id, .. }) => {
+            debug!("Found existing session {} in cache", session_id);
+            state
+                .cache
+                .touch_session_association(&cache_key, None)
+                .await;
 
             // Update identifier if provided and session doesn't have one
             if !identifier.is_empty() {
                 let session = db::get_session(&state.pool, session_id).await?;
                 if session.identifier.is_empty() {
                     db::update_session_identifier(&state.pool, session_id, identifier).await?;
+                    link_user(state, service, session_id, identifier).await?;
                 }
             }
 
@@ -85,14 +316,25 @@ pub async fn process_ingress(
             // Check if we should ignore robots
             if ua_data.device_type == DeviceType::Robot && service.ignore_robots {
                 debug!("Ignoring robot");
+                log(IngressDecision::DroppedBot, &payload.location).await;
                 return Ok(());
             }
 
-            // Determine IP to store
-            let stored_ip = if service.collect_ips && !state.settings.block_all_ips {
-                Some(ip.to_string())
-            } else {
-                None
+            // Check the service's country and network rules
+            if !service
+                .get_geo_rules()
+                .admits(&geo_data.country, geo_data.asn_number)
+            {
+                debug!("Ignoring visitor excluded by the geo rules");
+                log(IngressDecision::DroppedGeo, &payload.location).await;
+                return Ok(());
+            }
+
+            // Determine IP to store, sealed if there's a key for it
+            let stored_ip = (service.collect_ips && !state.settings.block_all_ips).then_some(ip);
+            let (plain_ip, ip_encrypted, ip_hash) = match (stored_ip, &state.ip_cipher) {
+                (Some(ip), Some(cipher)) => (None, Some(cipher.encrypt(ip)), Some(cipher.hash(ip))),
+                (ip, _) => (ip.map(str::to_string), None, None),
             };
 
             // Create session
@@ -100,116 +342,414 @@ pub async fn process_ingress(
                 &state.pool,
                 CreateSession {
                     service_id: service.id,
-                    identifier: identifier.trim().to_string(),
+                    identifier: identifier.to_string(),
                     start_time: time,
                     user_agent: user_agent.to_string(),
                     browser: ua_data.browser,
                     device: ua_data.device,
                     device_type: ua_data.device_type,
                     os: ua_data.os,
-                    ip: stored_ip,
+                    ip: plain_ip,
+                    ip_encrypted,
+                    ip_hash,
                     asn: geo_data.asn,
                     country: geo_data.country,
                     longitude: geo_data.longitude,
                     latitude: geo_data.latitude,
                     time_zone: geo_data.time_zone,
+                    environment: payload.environment,
                 },
             )
             .await?;
+            db::record_usage(&state.pool, service.id, &month, 0, 1, 0, 0).await?;
+            link_user(state, service, session.id, identifier).await?;
 
             // Cache the session association
-            state
-                .cache
-                .set_session_association(cache_key, session.id)
-                .await;
+            if !backdated {
+                let association = SessionAssociation {
+                    session_id: session.id,
+                    last_seen: time,
+                };
+                state
+                    .cache
+                    .set_session_association(cache_key.clone(), association)
+                    .await;
+            }
 
             (session.id, true)
         }
     };
 
-    // Handle hit creation/update
+    if !payload.props.is_empty() {
+        db::merge_session_props(
+            &state.pool,
+            session_id,
+            &payload.props,
+            state.settings.max_session_props,
+        )
+        .await?;
+    }
+    if payload.interacted {
+        db::mark_session_interacted(&state.pool, session_id).await?;
+    }
+
+    if state.settings.visitor_sketches {
+        count_visitor(state, service.id, time, payload.environment, &hash).await?;
+    }
+
+    // Handle hit creation/update. Heartbeats past the per-hit cap (a tab left
+    // open overnight) leave the hit and the session as they are.
+    let max_heartbeats = state.settings.max_heartbeats_per_hit;
+    let mut engaged = true;
+    let mut duplicate = false;
     let idempotency_key = payload.idempotency.as_ref().map(|k| format!("hit_{}", k));
 
     let hit_id = if let Some(ref key) = idempotency_key {
-        if let Some(existing_hit_id) = state.cache.get_hit_idempotency(key).await {
+        // A page left open past the timeout continues in a hit of the new session
+        let cached_hit = if timed_out {
+            None
+        } else {
+            state.cache.get_hit_idempotency(key).await
+        };
+        if let Some(existing_hit_id) = cached_hit {
             // Idempotency key in cache - this is a heartbeat for an existing hit
             debug!("Heartbeat for existing hit {}", existing_hit_id);
             state.cache.touch_hit_idempotency(key).await;
-            db::update_hit_heartbeat(&state.pool, existing_hit_id, time).await?;
+            engaged = db::update_hit_heartbeat(&state.pool, existing_hit_id, time, max_heartbeats)
+                .await?;
             existing_hit_id
-        } else if load_time.is_some() {
-            // Idempotency key not in cache, but has loadTime - genuine new page load
+        } else if load_time.is_some() && !state.hit_filter.contains(&state.pool, key, time).await? {
+            // Idempotency key not seen, but has loadTime - genuine new page load
             debug!("New page load for session {}", session_id);
-            create_new_hit(
-                &state.pool,
-                session_id,
-                service.id,
-                initial,
-                time,
-                tracker,
-                &payload,
-                load_time,
-            )
-            .await?
+            match collapse_into_recent_hit(state, service, session_id, &payload.location, time)
+                .await?
+            {
+                Some(hit_id) => hit_id,
+                None => {
+                    create_new_hit(
+                        state, session_id, service, initial, time, tracker, &payload, load_time,
+                    )
+                    .await?
+                }
+            }
         } else {
-            // Idempotency key not in cache, no loadTime - stale heartbeat after cache expiry
-            // Try to find and update existing hit for this location
+            // Idempotency key not in cache, but either no loadTime (a stale
+            // heartbeat after cache expiry) or a page load the filter may have
+            // seen. Try to find and update existing hit for this location
             debug!(
                 "Stale heartbeat for session {}, looking for existing hit",
                 session_id
             );
             match db::find_recent_hit_by_location(&state.pool, session_id, &payload.location).await
             {
+                Ok(Some(existing_hit)) if load_time.is_some() => {
+                    debug!("Repeated page load of hit {}", existing_hit.id);
+                    db::record_usage(&state.pool, service.id, &month, 0, 0, 0, 1).await?;
+                    duplicate = true;
+                    existing_hit.id
+                }
                 Ok(Some(existing_hit)) => {
                     debug!("Found existing hit {} to update", existing_hit.id);
-                    db::update_hit_heartbeat(&state.pool, existing_hit.id, time).await?;
+                    engaged = db::update_hit_heartbeat(
+                        &state.pool,
+                        existing_hit.id,
+                        time,
+                        max_heartbeats,
+                    )
+                    .await?;
                     existing_hit.id
                 }
                 _ => {
                     // No existing hit found - create new one (shouldn't happen often)
                     debug!("No existing hit found, creating new one");
                     create_new_hit(
-                        &state.pool,
-                        session_id,
-                        service.id,
-                        initial,
-                        time,
-                        tracker,
-                        &payload,
-                        load_time,
+                        state, session_id, service, initial, time, tracker, &payload, load_time,
                     )
                     .await?
                 }
             }
         }
     } else {
-        // No idempotency key, always create new hit (e.g., pixel tracker)
-        create_new_hit(
-            &state.pool,
-            session_id,
-            service.id,
-            initial,
-            time,
-            tracker,
-            &payload,
-            load_time,
-        )
-        .await?
+        // No idempotency key, create a new hit (e.g., pixel tracker) unless it
+        // duplicates one from another tab
+        match collapse_into_recent_hit(state, service, session_id, &payload.location, time).await? {
+            Some(hit_id) => hit_id,
+            None => {
+                create_new_hit(
+                    state, session_id, service, initial, time, tracker, &payload, load_time,
+                )
+                .await?
+            }
+        }
     };
 
     // Cache the hit idempotency if key was provided
     if let Some(key) = idempotency_key {
+        state.hit_filter.insert(&state.pool, &key, time).await?;
         state.cache.set_hit_idempotency(key, hit_id).await;
     }
 
+    let decision = if duplicate {
+        IngressDecision::DroppedDuplicate
+    } else {
+        IngressDecision::Accepted
+    };
+    log(decision, &payload.location).await;
+
+    let recorded = RecordedHit {
+        service,
+        session_id,
+        hit_id,
+        initial,
+        time,
+        payload: &payload,
+    };
+    state.hooks.after_record(state, &recorded).await;
+
+    if !engaged {
+        debug!("Heartbeat cap reached for hit {}, not counted", hit_id);
+    } else if !initial {
+        db::update_session_last_seen(&state.pool, session_id, time).await?;
+        state
+            .cache
+            .touch_session_association(&cache_key, Some(time))
+            .await;
+    }
+
+    // Staying longer or interacting can make a single-page session engaged
+    let engagement_changed = match service.bounce_rule {
+        BounceRule::SingleHit => false,
+        BounceRule::EngagedTime => engaged && !initial,
+        BounceRule::NoInteraction => payload.interacted,
+    };
+    if engagement_changed {
+        db::recalculate_session_bounce(&state.pool, session_id, service).await?;
+    }
+
+    Ok(())
+}
+
+/// Whether a hit is dated before the session memory window, so it can't
+/// belong to the session the visitor has now
+fn is_backdated(state: &AppState, time: DateTime<Utc>) -> bool {
+    let window = i64::try_from(state.settings.session_memory_timeout_secs)
+        .ok()
+        .and_then(Duration::try_seconds);
+    window
+        .and_then(|window| state.clock.now().checked_sub_signed(window))
+        .is_some_and(|cutoff| time < cutoff)
+}
+
+/// Whether a visitor's session went without activity for longer than the
+/// service's session timeout before `time`
+fn session_timed_out(
+    service: &Service,
+    association: &SessionAssociation,
+    time: DateTime<Utc>,
+) -> bool {
+    service
+        .session_timeout()
+        .is_some_and(|timeout| time - association.last_seen > timeout)
+}
+
+/// The tracker reported the visitor leaving a page view (closing the tab,
+/// navigating away or switching to another tab): the page view's last
+/// heartbeat, and the end of its session until there is further activity.
+/// End signals never start a session.
+async fn end_page_view(
+    state: &AppState,
+    service: &Service,
+    cache_key: &str,
+    payload: &IngressPayload,
+    time: DateTime<Utc>,
+) -> Result<()> {
+    let Some(association) = state.cache.get_session_association(cache_key).await else {
+        debug!("End signal for an unknown session, ignoring");
+        return Ok(());
+    };
+    let session_id = association.session_id;
+    // The visitor left long ago; their session ended at its last activity
+    if session_timed_out(service, &association, time) {
+        debug!("End signal for timed out session {}, ignoring", session_id);
+        return Ok(());
+    }
+    if payload.interacted {
+        db::mark_session_interacted(&state.pool, session_id).await?;
+        if service.bounce_rule == BounceRule::NoInteraction {
+            db::recalculate_session_bounce(&state.pool, session_id, service).await?;
+        }
+    }
+
+    let hit_id = match &payload.idempotency {
+        Some(key) => {
+            state
+                .cache
+                .get_hit_idempotency(&format!("hit_{}", key))
+                .await
+        }
+        None => None,
+    };
+    let hit_id = match hit_id {
+        Some(hit_id) => Some(hit_id),
+        None => db::find_recent_hit_by_location(&state.pool, session_id, &payload.location)
+            .await?
+            .map(|hit| hit.id),
+    };
+
+    // Past the heartbeat cap the session ends at its last counted activity
+    let engaged = match hit_id {
+        Some(hit_id) => {
+            db::update_hit_heartbeat(
+                &state.pool,
+                hit_id,
+                time,
+                state.settings.max_heartbeats_per_hit,
+            )
+            .await?
+        }
+        None => false,
+    };
+    debug!("Session {} ended", session_id);
+    db::end_session(&state.pool, session_id, engaged.then_some(time)).await
+}
+
+/// Whether this request may be recorded under the monthly hit quotas of the
+/// service and its organization. When both are used up the stricter behavior
+/// applies. Sampling is decided per visitor so a sampled visitor's session
+/// stays whole.
+/// Add the visitor to the sketch of the day, saving it only if it changed
+async fn count_visitor(
+    state: &AppState,
+    service_id: ServiceId,
+    time: DateTime<Utc>,
+    environment: Environment,
+    hash: &SessionAssociationHash,
+) -> Result<()> {
+    let day = time.date_naive();
+    let sketch = state
+        .cache
+        .get_or_load_visitor_sketch((service_id, day, environment), async {
+            let sketch = db::get_visitor_sketch(&state.pool, service_id, day, environment).await?;
+            Ok(sketch.unwrap_or_default())
+        })
+        .await?;
+
+    // Held until saved, so saves of the same sketch can't overtake each other
+    let mut sketch = sketch.lock().await;
+    if sketch.insert(hash.visitor_key()) {
+        db::save_visitor_sketch(&state.pool, service_id, day, environment, &sketch).await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn within_quota(
+    state: &AppState,
+    service: &Service,
+    hash: &SessionAssociationHash,
+    month: &str,
+) -> Result<bool> {
+    let mut behavior = None;
+
+    if service.hit_quota > 0 && service.quota_behavior != QuotaBehavior::Keep {
+        let usage = db::get_usage(&state.pool, service.id, month).await?;
+        if service.quota_exceeded(usage.hits) {
+            behavior = Some(service.quota_behavior);
+        }
+    }
+
+    let organization = state
+        .cache
+        .get_or_insert_organization(service.organization_id, || async {
+            db::get_organization(&state.pool, service.organization_id)
+                .await
+                .ok()
+        })
+        .await;
+    if let Some(organization) =
+        organization.filter(|o| o.hit_quota > 0 && o.quota_behavior != QuotaBehavior::Keep)
+    {
+        let usage = db::get_organization_usage(&state.pool, organization.id, month).await?;
+        if organization.quota_exceeded(usage.hits) {
+            behavior = behavior.max(Some(organization.quota_behavior));
+        }
+    }
+
+    Ok(match behavior {
+        None | Some(QuotaBehavior::Keep) => true,
+        Some(QuotaBehavior::Sample) => hash.sampled(state.settings.quota_sample_rate),
+        Some(QuotaBehavior::Drop) => false,
+    })
+}
+
+/// Count a page view turned away for the service's data quality panel; a
+/// failure to count it is only logged
+pub(super) async fn count_dropped(
+    state: &AppState,
+    service_id: ServiceId,
+    decision: IngressDecision,
+    time: DateTime<Utc>,
+) {
+    if let Err(e) = db::record_dropped_hit(&state.pool, service_id, time, decision).await {
+        warn!(
+            "Service {}: couldn't count a dropped hit: {}",
+            service_id, e
+        );
+    }
+}
+
+/// When the service links users, file the session under the hashed key of
+/// the login identifier the site sent
+async fn link_user(
+    state: &AppState,
+    service: &Service,
+    session_id: SessionId,
+    identifier: &str,
+) -> Result<()> {
+    if service.link_users && !identifier.is_empty() {
+        let user_key = state
+            .signing_key
+            .user_key(&service.tracking_id.0, identifier);
+        db::set_session_user_key(&state.pool, session_id, &user_key).await?;
+    }
     Ok(())
 }
 
+/// When the service collapses tabs, fold a page load into a hit the same session
+/// made at the same location within the active-user window (e.g. the same page
+/// opened in several tabs) by recording it as a heartbeat.
+async fn collapse_into_recent_hit(
+    state: &AppState,
+    service: &Service,
+    session_id: SessionId,
+    location: &str,
+    time: DateTime<Utc>,
+) -> Result<Option<HitId>> {
+    if !service.collapse_tabs {
+        return Ok(None);
+    }
+
+    let window = Duration::milliseconds(state.settings.active_user_timeout_ms(service) as i64);
+    match db::find_recent_hit_by_location(&state.pool, session_id, location).await? {
+        Some(hit) if hit.last_seen >= time - window => {
+            debug!("Collapsing duplicate page load into hit {}", hit.id);
+            db::update_hit_heartbeat(
+                &state.pool,
+                hit.id,
+                time,
+                state.settings.max_heartbeats_per_hit,
+            )
+            .await?;
+            Ok(Some(hit.id))
+        }
+        _ => Ok(None),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn create_new_hit(
-    pool: &Pool,
+    state: &AppState,
     session_id: SessionId,
-    service_id: ServiceId,
+    service: &Service,
     initial: bool,
     time: DateTime<Utc>,
     tracker: TrackerType,
@@ -218,30 +758,41 @@ async fn create_new_hit(
 ) -> Result<HitId> {
     debug!("Creating new hit for session {}", session_id);
 
+    let pool = &state.pool;
     let hit = db::create_hit(
         pool,
         CreateHit {
             session_id,
-            service_id,
+            service_id: service.id,
             initial,
             start_time: time,
             tracker,
             location: payload.location.clone(),
             referrer: payload.referrer.clone(),
             load_time,
+            environment: payload.environment,
+            prefetched: payload.prefetched,
+            referrer_spam: payload.referrer_spam,
         },
     )
     .await?;
+    if !payload.dimensions.is_empty() {
+        db::add_hit_dimensions(pool, &hit, &payload.dimensions).await?;
+    }
+    db::record_usage(pool, service.id, &ServiceUsage::month_of(time), 1, 0, 0, 0).await?;
 
     // Recalculate bounce status
-    db::recalculate_session_bounce(pool, session_id).await?;
+    db::recalculate_session_bounce(pool, session_id, service).await?;
 
+    state.live.publish(&hit);
+    state.realtime.record(&hit);
     Ok(hit.id)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_ingress_payload_default() {
@@ -259,6 +810,13 @@ mod tests {
             location: "/home".to_string(),
             referrer: "https://google.com".to_string(),
             load_time: Some(150.5),
+            end: false,
+            environment: Environment::Staging,
+            prefetched: false,
+            referrer_spam: false,
+            dimensions: Vec::new(),
+            props: Vec::new(),
+            interacted: false,
         };
 
         assert_eq!(payload.idempotency, Some("abc123".to_string()));
@@ -278,19 +836,91 @@ mod tests {
 
     #[test]
     fn test_load_time_filter() {
-        // Test that negative load times are filtered
-        let load_time: Option<f64> = Some(-100.0);
-        let filtered = load_time.filter(|&t| t > 0.0);
-        assert!(filtered.is_none());
-
-        // Test that zero is filtered
-        let load_time: Option<f64> = Some(0.0);
-        let filtered = load_time.filter(|&t| t > 0.0);
-        assert!(filtered.is_none());
-
-        // Test that positive values are kept
-        let load_time: Option<f64> = Some(100.0);
-        let filtered = load_time.filter(|&t| t > 0.0);
-        assert_eq!(filtered, Some(100.0));
+        let cleaned = |load_time| {
+            IngressPayload {
+                load_time,
+                ..Default::default()
+            }
+            .cleaned()
+            .load_time
+        };
+
+        // Negative, zero and non-finite load times are dropped
+        assert!(cleaned(Some(-100.0)).is_none());
+        assert!(cleaned(Some(0.0)).is_none());
+        assert!(cleaned(Some(f64::NAN)).is_none());
+        assert!(cleaned(Some(f64::INFINITY)).is_none());
+
+        // Positive values are kept
+        assert_eq!(cleaned(Some(100.0)), Some(100.0));
+    }
+
+    #[test]
+    fn test_clean_text() {
+        assert_eq!(clean_text("  /home ", 100), "/home");
+        assert_eq!(clean_text("/a\0b\r\n\u{7f}c", 100), "/abc");
+        assert_eq!(clean_text("/äöü", 3), "/äö");
+        assert_eq!(clean_text("\0", 100), "");
+
+        let payload = IngressPayload {
+            idempotency: Some("\0".to_string()),
+            location: "x".repeat(MAX_URL_CHARS + 10),
+            referrer: " https://example.com/ ".to_string(),
+            load_time: Some(12.0),
+            end: false,
+            environment: Environment::Production,
+            prefetched: false,
+            referrer_spam: false,
+            dimensions: Vec::new(),
+            props: Vec::new(),
+            interacted: false,
+        }
+        .cleaned();
+        assert!(payload.idempotency.is_none());
+        assert_eq!(payload.location.chars().count(), MAX_URL_CHARS);
+        assert_eq!(payload.referrer, "https://example.com/");
+    }
+
+    #[test]
+    fn test_clean_dimensions() {
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let cleaned = clean_dimensions(vec![
+            pair(" author ", " Ada\0 "),
+            pair("plan", "pro"),
+            pair("author", "Grace"),
+            pair("has space", "x"),
+            pair(&"k".repeat(MAX_DIMENSION_KEY_CHARS + 1), "x"),
+            pair("empty", " "),
+            pair("category-2", &"v".repeat(MAX_FIELD_CHARS + 1)),
+        ]);
+        assert_eq!(
+            cleaned,
+            [
+                pair("author", "Ada"),
+                pair("plan", "pro"),
+                pair("category-2", &"v".repeat(MAX_FIELD_CHARS)),
+            ]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_clean_text_is_storable(text in any::<String>(), max_chars in 0usize..64) {
+            let cleaned = clean_text(&text, max_chars);
+            prop_assert!(cleaned.chars().count() <= max_chars);
+            prop_assert!(!cleaned.chars().any(char::is_control));
+            prop_assert_eq!(cleaned.trim(), cleaned.as_str());
+        }
+
+        #[test]
+        fn prop_clean_text_is_idempotent(text in any::<String>()) {
+            let cleaned = clean_text(&text, MAX_FIELD_CHARS);
+            prop_assert_eq!(clean_text(&cleaned, MAX_FIELD_CHARS), cleaned);
+        }
+
+        #[test]
+        fn prop_clean_text_keeps_plain_text(text in "[a-zA-Z0-9/?=&._~-]{0,64}") {
+            prop_assert_eq!(clean_text(&text, MAX_URL_CHARS), text);
+        }
     }
 }
@@ -0,0 +1,255 @@
+//! Per-service ingest scripts. Operators may give a service a small
+//! [Rhai](https://rhai.rs) script, edited on its manage page, that sees each
+//! tracker request before it is recorded. The script reads and changes the
+//! `hit` map and drops the hit by evaluating to `false`:
+//!
+//! ```rhai
+//! if hit.location.contains("/admin") { return false; }
+//! hit.location.replace("/v1/", "/");
+//! hit.dimensions.plan = "beta";
+//! ```
+//!
+//! Scripts run sandboxed: no file, module or network access, no output, and
+//! bounded operations, call depth and sizes, so a runaway script fails
+//! instead of stalling ingestion. A script that fails leaves the hit as it
+//! was.
+
+use std::sync::OnceLock;
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use super::processor::{clean_dimensions, clean_text, IngressPayload, MAX_URL_CHARS};
+use crate::domain::TrackerType;
+use crate::error::{Error, Result};
+use crate::hooks::IngressAction;
+
+/// Most operations one run of a script may take
+pub const MAX_OPERATIONS: u64 = 50_000;
+/// Longest script accepted, in bytes
+pub const MAX_SCRIPT_BYTES: usize = 16 * 1024;
+
+/// The sandboxed engine all scripts compile and run on
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .set_max_modules(0)
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(16)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(MAX_URL_CHARS * 4)
+            .set_max_array_size(1_000)
+            .set_max_map_size(1_000)
+            .set_max_variables(256)
+            .set_max_functions(64)
+            .on_print(|_| {})
+            .on_debug(|_, _, _| {});
+        engine.disable_symbol("eval");
+        engine
+    })
+}
+
+/// A compiled ingest script
+pub struct IngestScript {
+    ast: AST,
+}
+
+impl IngestScript {
+    /// Compile a script, or say why it cannot be
+    pub fn compile(source: &str) -> Result<Self> {
+        if source.len() > MAX_SCRIPT_BYTES {
+            return Err(Error::BadRequest(format!(
+                "Scripts may be at most {} KiB",
+                MAX_SCRIPT_BYTES / 1024
+            )));
+        }
+        let ast = engine()
+            .compile(source)
+            .map_err(|e| Error::BadRequest(e.to_string()))?;
+        Ok(Self { ast })
+    }
+
+    /// Run the script over a cleaned payload. Changed fields are cleaned
+    /// again before they are written back; on an error the payload is left
+    /// as it was.
+    pub fn run(
+        &self,
+        tracker: TrackerType,
+        user_agent: &str,
+        payload: &mut IngressPayload,
+    ) -> Result<IngressAction> {
+        let mut hit = Map::new();
+        hit.insert("location".into(), payload.location.clone().into());
+        hit.insert("referrer".into(), payload.referrer.clone().into());
+        hit.insert(
+            "load_time".into(),
+            payload.load_time.map_or(Dynamic::UNIT, Dynamic::from_float),
+        );
+        hit.insert(
+            "environment".into(),
+            payload.environment.as_str().to_string().into(),
+        );
+        hit.insert("end".into(), payload.end.into());
+        hit.insert("dimensions".into(), to_map(&payload.dimensions).into());
+        hit.insert("props".into(), to_map(&payload.props).into());
+
+        let mut scope = Scope::new();
+        scope.push("hit", hit);
+        scope.push_constant("tracker", tracker.as_str().to_lowercase());
+        scope.push_constant("user_agent", user_agent.to_string());
+
+        let result: Dynamic = engine()
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| Error::Internal(format!("Ingest script failed: {}", e)))?;
+        if result.as_bool() == Ok(false) {
+            return Ok(IngressAction::Drop);
+        }
+
+        let hit = scope
+            .get_value::<Map>("hit")
+            .ok_or_else(|| Error::Internal("Ingest script replaced `hit`".to_string()))?;
+        if let Some(location) = text(&hit, "location") {
+            payload.location = clean_text(&location, MAX_URL_CHARS);
+        }
+        if let Some(referrer) = text(&hit, "referrer") {
+            payload.referrer = clean_text(&referrer, MAX_URL_CHARS);
+        }
+        if let Some(dimensions) = pairs(&hit, "dimensions") {
+            if !same_pairs(&dimensions, &payload.dimensions) {
+                payload.dimensions = clean_dimensions(dimensions);
+            }
+        }
+        if let Some(props) = pairs(&hit, "props") {
+            if !same_pairs(&props, &payload.props) {
+                payload.props = clean_dimensions(props);
+            }
+        }
+        Ok(IngressAction::Record)
+    }
+}
+
+fn to_map(pairs: &[(String, String)]) -> Map {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.into(), value.clone().into()))
+        .collect()
+}
+
+fn text(hit: &Map, key: &str) -> Option<String> {
+    hit.get(key).map(|value| value.to_string())
+}
+
+/// Pairs of a map in `hit` as the script left it, in key order; unset
+/// values are dropped and other values stored as text
+fn pairs(hit: &Map, key: &str) -> Option<Vec<(String, String)>> {
+    let map = hit.get(key)?.clone().try_cast::<Map>()?;
+    Some(
+        map.into_iter()
+            .filter(|(_, value)| !value.is_unit())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    )
+}
+
+/// Whether the script left the pairs as they were, so they keep the order
+/// they were sent in
+fn same_pairs(pairs: &[(String, String)], original: &[(String, String)]) -> bool {
+    let mut original = original.to_vec();
+    original.sort();
+    pairs == original
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, payload: &mut IngressPayload) -> Result<IngressAction> {
+        IngestScript::compile(source)?.run(TrackerType::Js, "Mozilla/5.0 Firefox/120.0", payload)
+    }
+
+    fn payload() -> IngressPayload {
+        IngressPayload {
+            location: "https://example.com/v1/pricing".to_string(),
+            referrer: "https://search.example/".to_string(),
+            dimensions: vec![
+                ("plan".to_string(), "free".to_string()),
+                ("ab".to_string(), "b".to_string()),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rewrite_and_tag() {
+        let mut payload = payload();
+        let action = run(
+            r#"
+                hit.location.replace("/v1/", "/");
+                hit.referrer = "";
+                hit.dimensions.plan = "pro";
+                hit.dimensions.tracker = tracker;
+                hit.props.visits = 3;
+            "#,
+            &mut payload,
+        )
+        .unwrap();
+        assert_eq!(action, IngressAction::Record);
+        assert_eq!(payload.location, "https://example.com/pricing");
+        assert_eq!(payload.referrer, "");
+        assert_eq!(
+            payload.dimensions,
+            vec![
+                ("ab".to_string(), "b".to_string()),
+                ("plan".to_string(), "pro".to_string()),
+                ("tracker".to_string(), "js".to_string()),
+            ]
+        );
+        assert_eq!(payload.props, vec![("visits".to_string(), "3".to_string())]);
+    }
+
+    #[test]
+    fn test_untouched_dimensions_keep_their_order() {
+        let mut payload = payload();
+        run("hit.location = \"/\";", &mut payload).unwrap();
+        assert_eq!(payload.dimensions[0].0, "plan");
+        assert_eq!(payload.location, "/");
+    }
+
+    #[test]
+    fn test_drop() {
+        let source = r#"if hit.location.contains("/v1/") { return false; }"#;
+        let mut payload = payload();
+        assert_eq!(run(source, &mut payload).unwrap(), IngressAction::Drop);
+
+        let mut payload = IngressPayload {
+            location: "/v2/".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(run(source, &mut payload).unwrap(), IngressAction::Record);
+    }
+
+    #[test]
+    fn test_sandbox_limits() {
+        let mut payload = payload();
+        let error = run("loop { hit.location += \"x\"; }", &mut payload).unwrap_err();
+        assert!(matches!(error, Error::Internal(_)));
+        assert_eq!(payload.location, "https://example.com/v1/pricing");
+
+        assert!(run("let x = 0; loop { x += 1; }", &mut payload).is_err());
+        assert!(run("import \"secrets\" as s;", &mut payload).is_err());
+        assert!(run("eval(\"1\")", &mut payload).is_err());
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert!(matches!(
+            IngestScript::compile("hit.location = ;"),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(IngestScript::compile(&"1;".repeat(MAX_SCRIPT_BYTES)).is_err());
+        assert!(IngestScript::compile("").is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+//! Dry runs of ingress for integration debugging: a page view goes through
+//! the checks a real one would, from the origin check to the hit quota, and
+//! comes back with the decision and what would be stored, while nothing is
+//! recorded. The ingress hooks aren't run, as they may act on what they're
+//! shown, and the visitor counts as new.
+
+use axum::http::HeaderMap;
+use serde::Serialize;
+
+use super::{
+    clean_text, hit_environment, ingest_script, opt_out, prefetch_flag, validate_origin,
+    visitor_hash, within_quota, IngressPayload, MAX_FIELD_CHARS,
+};
+use crate::domain::{
+    DeviceType, Environment, IngressDecision, ReferrerSpamBehavior, Service, ServiceStatus,
+    ServiceUsage, TrackerType,
+};
+use crate::error::Result;
+use crate::geo::GeoIpData;
+use crate::hooks::IngressAction;
+use crate::privacy::{get_client_ip, get_user_agent, is_excluded, is_ip_ignored};
+use crate::state::AppState;
+use crate::ua::{parse_user_agent, ParsedUserAgent};
+
+/// What ingress would make of a page view
+#[derive(Debug, Serialize)]
+pub struct HitValidation {
+    /// The first check the page view fails, else `accepted`
+    pub decision: IngressDecision,
+    pub tracker: TrackerType,
+    /// Location as it would be stored, after the path rules and the ingest
+    /// script
+    pub location: String,
+    pub referrer: String,
+    pub environment: Environment,
+    /// Whether the hit would be flagged as prefetched
+    pub prefetched: bool,
+    /// Whether the hit would be flagged as referrer spam
+    pub referrer_spam: bool,
+    pub ip: String,
+    /// Whether the session would keep the IP
+    pub stores_ip: bool,
+    pub user_agent: ParsedUserAgent,
+    pub geo: GeoIpData,
+}
+
+/// Run a page view sent with `headers` through ingress without recording
+/// it. Its `environment` is taken from the page's origin unless `env` names
+/// one.
+pub async fn validate_hit(
+    state: &AppState,
+    service: &Service,
+    tracker: TrackerType,
+    headers: &HeaderMap,
+    env: Option<&str>,
+    payload: IngressPayload,
+) -> Result<HitValidation> {
+    let ip = get_client_ip(headers).unwrap_or_else(|| "0.0.0.0".to_string());
+    let user_agent = clean_text(&get_user_agent(headers), MAX_FIELD_CHARS);
+    let prefetched = prefetch_flag(state, headers);
+
+    let mut payload = IngressPayload {
+        environment: hit_environment(env, headers),
+        prefetched: prefetched.unwrap_or(true),
+        ..payload
+    }
+    .cleaned();
+    payload.location = service.get_path_normalization().apply(&payload.location);
+
+    let (_, origin_valid) = validate_origin(headers, service);
+    let opted_out = opt_out(headers, service);
+    let mut decision = if service.status != ServiceStatus::Active {
+        Some(IngressDecision::ServiceArchived)
+    } else if !origin_valid {
+        Some(IngressDecision::OriginRejected)
+    } else if opted_out.is_some() {
+        opted_out
+    } else if is_excluded(headers, &service.tracking_id.0) {
+        Some(IngressDecision::DroppedExcluded)
+    } else if prefetched.is_none() {
+        Some(IngressDecision::DroppedPrefetch)
+    } else if is_ip_ignored(&ip, &service.get_ignored_networks()) {
+        Some(IngressDecision::DroppedIp)
+    } else {
+        None
+    };
+
+    if decision.is_none() {
+        if let Some(script) = ingest_script(state, service.id).await? {
+            // A failing script leaves the hit as it was, as in ingress
+            if let Ok(IngressAction::Drop) = script.run(tracker, &user_agent, &mut payload) {
+                decision = Some(IngressDecision::DroppedScript);
+            }
+        }
+    }
+
+    let parsed = parse_user_agent(&user_agent);
+    if decision.is_none() && parsed.device_type == DeviceType::Robot && service.ignore_robots {
+        decision = Some(IngressDecision::DroppedBot);
+    }
+    let geo = state.geo.lookup(&ip);
+    if decision.is_none() && !service.get_geo_rules().admits(&geo.country, geo.asn_number) {
+        decision = Some(IngressDecision::DroppedGeo);
+    }
+    if decision.is_none()
+        && state.settings.verify_bots
+        && !state.bots.admits(&ip, &user_agent).await
+    {
+        decision = Some(IngressDecision::DroppedFakeBot);
+    }
+    if decision.is_none() && state.referrer_spam.is_spam(&payload.referrer) {
+        match state.settings.referrer_spam {
+            ReferrerSpamBehavior::Drop => decision = Some(IngressDecision::DroppedSpam),
+            ReferrerSpamBehavior::Flag => payload.referrer_spam = true,
+        }
+    }
+    if decision.is_none() {
+        let hash = visitor_hash(state, service, &ip, &user_agent);
+        let month = ServiceUsage::month_of(state.clock.now());
+        if !within_quota(state, service, &hash, &month).await? {
+            decision = Some(IngressDecision::DroppedQuota);
+        }
+    }
+
+    Ok(HitValidation {
+        decision: decision.unwrap_or(IngressDecision::Accepted),
+        tracker,
+        location: payload.location,
+        referrer: payload.referrer,
+        environment: payload.environment,
+        prefetched: payload.prefetched,
+        referrer_spam: payload.referrer_spam,
+        stores_ip: service.collect_ips && !state.settings.block_all_ips,
+        geo,
+        ip,
+        user_agent: parsed,
+    })
+}
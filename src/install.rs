@@ -0,0 +1,208 @@
+//! Checking that a service's site carries its tracker. The service's `link`
+//! is fetched and its HTML searched for shymini script and pixel URLs, which
+//! catches a missing snippet, one copied from another service, and tracker
+//! URLs that browsers block as mixed content.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use url::Url;
+
+use crate::domain::{InstallCheck, InstallIssue, Service, TrackerType};
+
+/// How long the site gets to answer
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Only the start of a larger page is searched
+const MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Script and pixel URLs: `/trace/app_<id>.js`, `/trace/app_<id>.esm.js` and
+/// `/trace/px_<id>.gif`, optionally with an identifier, captured with the
+/// scheme and host in front of them if there are any
+fn tracker_url_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(
+            r#"(?i)((?:https?:)?//[^\s"'<>/]+)?/trace/(app|px)_([a-z0-9]+)(?:\.esm)?(?:/[^\s"'<>/]+)?\.(?:js|gif)"#,
+        )
+        .expect("tracker URL pattern is valid")
+    })
+}
+
+/// Fetch the service's site and look for its tracker
+pub async fn verify_install(service: &Service, now: DateTime<Utc>) -> InstallCheck {
+    let link = service.link.trim();
+    if link.is_empty() {
+        return InstallCheck::failed(InstallIssue::NoLink, now);
+    }
+    let page = match Url::parse(link) {
+        Ok(page) if matches!(page.scheme(), "http" | "https") => page,
+        _ => {
+            return InstallCheck::failed(
+                InstallIssue::Unreachable {
+                    error: format!("{} is not an HTTP(S) URL", link),
+                },
+                now,
+            )
+        }
+    };
+
+    match fetch_page(page).await {
+        Ok((page, html)) => inspect_page(&html, &page, &service.tracking_id.0, now),
+        Err(issue) => InstallCheck::failed(issue, now),
+    }
+}
+
+/// The page's final URL after redirects, and the start of its body
+async fn fetch_page(page: Url) -> Result<(Url, String), InstallIssue> {
+    let unreachable = |e: reqwest::Error| InstallIssue::Unreachable {
+        error: e.to_string(),
+    };
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent(concat!("shymini/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(unreachable)?;
+
+    let mut response = client.get(page).send().await.map_err(unreachable)?;
+    if !response.status().is_success() {
+        return Err(InstallIssue::HttpStatus {
+            status: response.status().as_u16(),
+        });
+    }
+
+    let page = response.url().clone();
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(unreachable)? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_PAGE_BYTES {
+            break;
+        }
+    }
+    Ok((page, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// What the HTML at `page` says about the tracker for `tracking_id`
+pub fn inspect_page(html: &str, page: &Url, tracking_id: &str, now: DateTime<Utc>) -> InstallCheck {
+    let mut trackers = Vec::new();
+    let mut other_ids: Vec<String> = Vec::new();
+    let mut issues = Vec::new();
+
+    for captures in tracker_url_pattern().captures_iter(html) {
+        let id = captures[3].to_ascii_lowercase();
+        if id != tracking_id {
+            if !other_ids.contains(&id) {
+                other_ids.push(id);
+            }
+            continue;
+        }
+
+        let tracker = if captures[2].eq_ignore_ascii_case("app") {
+            TrackerType::Js
+        } else {
+            TrackerType::Pixel
+        };
+        if !trackers.contains(&tracker) {
+            trackers.push(tracker);
+        }
+
+        let insecure = captures
+            .get(1)
+            .is_some_and(|prefix| prefix.as_str().to_ascii_lowercase().starts_with("http:"));
+        if page.scheme() == "https" && insecure {
+            let issue = InstallIssue::MixedContent {
+                url: captures[0].to_string(),
+            };
+            if !issues.contains(&issue) {
+                issues.push(issue);
+            }
+        }
+    }
+
+    if trackers.is_empty() {
+        issues.push(if other_ids.is_empty() {
+            InstallIssue::TrackerMissing
+        } else {
+            InstallIssue::WrongTrackingId { found: other_ids }
+        });
+    }
+
+    InstallCheck {
+        installed: issues.is_empty(),
+        trackers,
+        issues,
+        checked_at: now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inspect(html: &str, page: &str) -> InstallCheck {
+        inspect_page(html, &Url::parse(page).unwrap(), "abc12345", Utc::now())
+    }
+
+    #[test]
+    fn test_script_found() {
+        let check = inspect(
+            r#"<script defer src="https://stats.example.com/trace/app_abc12345.js"></script>"#,
+            "https://example.com/",
+        );
+        assert!(check.installed);
+        assert_eq!(check.trackers, vec![TrackerType::Js]);
+        assert!(check.issues.is_empty());
+    }
+
+    #[test]
+    fn test_tracker_variants() {
+        let check = inspect(
+            r#"<script type="module">import { init } from "/trace/app_ABC12345.esm.js";</script>
+            <img src='//stats.example.com/trace/px_abc12345/signup.gif'>"#,
+            "https://example.com/",
+        );
+        assert!(check.installed);
+        assert_eq!(check.trackers, vec![TrackerType::Js, TrackerType::Pixel]);
+    }
+
+    #[test]
+    fn test_tracker_missing() {
+        let check = inspect("<html><body>Hello</body></html>", "https://example.com/");
+        assert!(!check.installed);
+        assert_eq!(check.issues, vec![InstallIssue::TrackerMissing]);
+    }
+
+    #[test]
+    fn test_wrong_tracking_id() {
+        let check = inspect(
+            r#"<script src="https://stats.example.com/trace/app_zzz99999.js"></script>
+            <img src="https://stats.example.com/trace/px_zzz99999.gif">"#,
+            "https://example.com/",
+        );
+        assert!(!check.installed);
+        assert_eq!(
+            check.issues,
+            vec![InstallIssue::WrongTrackingId {
+                found: vec!["zzz99999".to_string()]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mixed_content() {
+        let html = r#"<script src="http://stats.example.com/trace/app_abc12345.js"></script>"#;
+        let check = inspect(html, "https://example.com/");
+        assert!(!check.installed);
+        assert_eq!(check.trackers, vec![TrackerType::Js]);
+        assert_eq!(
+            check.issues,
+            vec![InstallIssue::MixedContent {
+                url: "http://stats.example.com/trace/app_abc12345.js".to_string()
+            }]
+        );
+
+        // Fine on a plain HTTP page
+        assert!(inspect(html, "http://example.com/").installed);
+    }
+}
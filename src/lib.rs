@@ -1,12 +1,44 @@
 pub mod api;
+pub mod auth;
+pub mod badge;
+pub mod bots;
 pub mod cache;
+pub mod clock;
 pub mod config;
+pub mod crawler;
 pub mod dashboard;
 pub mod db;
 pub mod domain;
+pub mod embed;
 pub mod error;
+pub mod export;
+pub mod extract;
 pub mod geo;
+pub mod guards;
+pub mod hooks;
+pub mod i18n;
 pub mod ingress;
+pub mod install;
+pub mod live;
+pub mod mailer;
+pub mod maintenance;
+pub mod milestones;
+pub mod monitor;
+pub mod partitions;
+#[cfg(feature = "plugins")]
+pub mod plugins;
 pub mod privacy;
+pub mod query;
+pub mod realtime;
+pub mod report;
+pub mod secrets;
+pub mod server;
+pub mod sketch;
+pub mod spam;
 pub mod state;
+pub mod status;
+pub mod systemd;
+pub mod theme;
+pub mod top_pages;
 pub mod ua;
+pub mod updates;
@@ -0,0 +1,37 @@
+//! Feed of newly recorded page views for `/api/ws` subscribers. Ingress
+//! publishes each hit it creates; a subscriber that falls more than
+//! `FEED_CAPACITY` hits behind skips the ones it missed rather than hold
+//! ingestion up. Nothing is kept while no one is subscribed.
+
+use tokio::sync::broadcast;
+
+use crate::domain::Hit;
+
+/// Hits buffered for the slowest subscriber
+pub const FEED_CAPACITY: usize = 1024;
+
+pub struct LiveFeed {
+    sender: broadcast::Sender<Hit>,
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl LiveFeed {
+    /// Tell the subscribers about a new hit
+    pub fn publish(&self, hit: &Hit) {
+        if self.sender.receiver_count() > 0 {
+            // Only fails when the last subscriber just left
+            let _ = self.sender.send(hit.clone());
+        }
+    }
+
+    /// Hits published from now on, of every service
+    pub fn subscribe(&self) -> broadcast::Receiver<Hit> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Mutex;
+
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::info;
+
+use crate::error::{Error, Result};
+
+/// A plain-text email
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    /// No SMTP server configured: mail goes to the log, so a self-hosted
+    /// install can still follow emailed links
+    Log,
+    /// Keeps sent mail for tests to inspect
+    Memory(Mutex<Vec<Email>>),
+}
+
+/// Sends the dashboard's account emails
+pub struct Mailer {
+    from: Mailbox,
+    transport: Transport,
+}
+
+impl Mailer {
+    pub fn new(smtp_url: Option<&str>, from: &str) -> Result<Self> {
+        let from = from
+            .parse()
+            .map_err(|e| Error::Mail(format!("invalid mail_from {:?}: {}", from, e)))?;
+        let transport = match smtp_url.filter(|u| !u.is_empty()) {
+            Some(url) => Transport::Smtp(
+                AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+                    .map_err(|e| Error::Mail(format!("invalid smtp_url: {}", e)))?
+                    .build(),
+            ),
+            None => Transport::Log,
+        };
+        Ok(Self { from, transport })
+    }
+
+    /// A mailer that only records what it sends
+    pub fn memory() -> Self {
+        Self {
+            from: "shymini <shymini@localhost>".parse().unwrap(),
+            transport: Transport::Memory(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn is_smtp(&self) -> bool {
+        matches!(self.transport, Transport::Smtp(_))
+    }
+
+    /// Mail recorded by a `memory()` mailer
+    pub fn sent(&self) -> Vec<Email> {
+        match &self.transport {
+            Transport::Memory(sent) => sent.lock().unwrap().clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub async fn send(&self, email: Email) -> Result<()> {
+        match &self.transport {
+            Transport::Smtp(transport) => {
+                let to: Mailbox = email
+                    .to
+                    .parse()
+                    .map_err(|e| Error::Mail(format!("invalid recipient: {}", e)))?;
+                let message = Message::builder()
+                    .from(self.from.clone())
+                    .to(to)
+                    .subject(email.subject)
+                    .body(email.body)
+                    .map_err(|e| Error::Mail(e.to_string()))?;
+                transport
+                    .send(message)
+                    .await
+                    .map_err(|e| Error::Mail(e.to_string()))?;
+            }
+            Transport::Log => {
+                info!("Mail to {} ({}):\n{}", email.to, email.subject, email.body);
+            }
+            Transport::Memory(sent) => sent.lock().unwrap().push(email),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_validates_settings() {
+        assert!(!Mailer::new(None, "shymini <shymini@localhost>")
+            .unwrap()
+            .is_smtp());
+        assert!(Mailer::new(Some("smtp://localhost:2525"), "a@example.com")
+            .unwrap()
+            .is_smtp());
+        assert!(Mailer::new(None, "not an address").is_err());
+        assert!(Mailer::new(Some("http://nope"), "a@example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_mailer() {
+        let mailer = Mailer::memory();
+        let email = Email {
+            to: "a@example.com".to_string(),
+            subject: "Hi".to_string(),
+            body: "Hello".to_string(),
+        };
+        mailer.send(email.clone()).await.unwrap();
+        assert_eq!(mailer.sent(), vec![email]);
+    }
+}
@@ -1,19 +1,32 @@
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
-use std::net::SocketAddr;
+use std::time::Duration;
 use tower_http::{
+    compression::CompressionLayer,
     cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     services::ServeDir,
     trace::TraceLayer,
 };
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 use shymini::{
-    api, cache::AppCache, config::Settings, dashboard, db, geo::GeoIpLookup, ingress,
+    api, badge,
+    cache::AppCache,
+    config::Settings,
+    crawler, dashboard, db, embed,
+    geo::GeoIpLookup,
+    guards, ingress,
+    mailer::Mailer,
+    maintenance, milestones, monitor, partitions,
+    server::{self, ListenAddr, Listener, ServeOptions},
+    spam,
     state::AppState,
+    systemd, theme, top_pages, updates,
 };
 
 #[tokio::main]
@@ -29,7 +42,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     // Load configuration
-    let settings = Settings::new()?;
+    let settings = Settings::load().await?;
     info!("Configuration loaded");
 
     // Determine database URL
@@ -73,12 +86,105 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         info!("GeoIP lookup not available (no database files)");
     }
 
+    // Initialize mailer
+    let mailer = Mailer::new(settings.smtp_url.as_deref(), &settings.mail_from)?;
+    if mailer.is_smtp() {
+        info!("Mailer sending via SMTP");
+    } else {
+        info!("Mailer not configured (no smtp_url); mail is written to the log");
+    }
+
     // Initialize cache
     let cache = AppCache::new(&settings);
     info!("Cache initialized");
 
     // Create app state
-    let state = AppState::new(pool, cache, settings.clone(), geo);
+    let state = AppState::new(pool, cache, settings.clone(), geo, mailer);
+    if state.proxy_auth.is_some() {
+        if settings.proxy_trusted_networks.trim().is_empty() {
+            warn!("Proxy login enabled without proxy_trusted_networks; anyone reaching the server can set the user header");
+        } else {
+            info!("Proxy login enabled");
+        }
+    }
+    if settings.monitor_interval_secs > 0 {
+        monitor::spawn(state.clone());
+        info!(
+            "Uptime monitor checking sites every {}s",
+            settings.monitor_interval_secs
+        );
+    }
+    if settings.sitemap_crawl_interval_secs > 0 {
+        crawler::spawn(state.clone());
+        info!(
+            "Sitemap crawler running every {}s",
+            settings.sitemap_crawl_interval_secs
+        );
+    }
+    if settings.idempotency_filter_capacity > 0 {
+        ingress::spawn_hit_filter_flush(state.clone());
+        info!(
+            "Idempotency filter sized for {} keys a day",
+            settings.idempotency_filter_capacity
+        );
+    }
+    if settings.top_pages_refresh_secs > 0 {
+        top_pages::spawn(state.clone());
+        info!(
+            "Top pages refreshing every {}s",
+            settings.top_pages_refresh_secs
+        );
+    }
+    if let Some(hour) = settings.maintenance_hour {
+        maintenance::spawn(state.clone());
+        info!("Database maintenance running daily at {:02}:00 UTC", hour);
+    }
+    if settings.update_check {
+        updates::spawn(state.clone());
+        info!("Checking daily for new releases");
+    }
+    if !settings.referrer_spam_list_url.is_empty() {
+        spam::spawn(state.clone());
+        info!("Refreshing the referrer spam list daily");
+    }
+    if settings.hit_partitions {
+        if cfg!(feature = "postgres") {
+            partitions::spawn(state.clone());
+            info!("Hits partitioned by month");
+        } else {
+            warn!("hit_partitions needs Postgres; hits stay in one table");
+        }
+    }
+
+    // Ingress routes (using non-obvious paths to avoid ad blockers). They
+    // answer CORS preflights per service, so the CORS layer skips them.
+    // HEAD on the pixel records nothing; on the script it is the GET.
+    let ingress_routes = Router::new()
+        .route(
+            "/trace/px_:tracking_id.gif",
+            get(ingress::pixel_handler)
+                .head(ingress::pixel_head_handler)
+                .options(ingress::ingress_options_handler),
+        )
+        .route(
+            "/trace/px_:tracking_id/:identifier.gif",
+            get(ingress::pixel_with_id_handler)
+                .head(ingress::pixel_head_handler)
+                .options(ingress::ingress_options_handler),
+        )
+        // Also serves the ES module build, app_:tracking_id.esm.js
+        .route(
+            "/trace/app_:tracking_id.js",
+            get(ingress::script_get_handler)
+                .post(ingress::script_post_handler)
+                .options(ingress::ingress_options_handler),
+        )
+        .route(
+            "/trace/app_:tracking_id/:identifier.js",
+            get(ingress::script_get_with_id_handler)
+                .post(ingress::script_post_with_id_handler)
+                .options(ingress::ingress_options_handler),
+        );
 
     // CORS layer
     let cors = CorsLayer::new()
@@ -90,56 +196,303 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let app = Router::new()
         // Dashboard routes
         .route("/", get(dashboard::dashboard_index))
+        .route("/search", get(dashboard::search))
         .route("/service/new", get(dashboard::service_create_form))
         .route("/service/new", post(dashboard::service_create))
         .route("/service/:id", get(dashboard::service_detail))
         .route("/service/:id/stats", get(dashboard::stats_partial))
+        .route(
+            "/service/:id/panels/sessions",
+            get(dashboard::sessions_panel),
+        )
+        .route(
+            "/service/:id/panels/locations",
+            get(dashboard::locations_panel),
+        )
+        .route(
+            "/service/:id/panels/referrers",
+            get(dashboard::referrers_panel),
+        )
+        .route(
+            "/service/:id/panels/countries",
+            get(dashboard::countries_panel),
+        )
+        .route("/service/:id/panels/chart", get(dashboard::chart_panel))
+        .route("/service/:id/panels/usage", get(dashboard::usage_panel))
+        .route(
+            "/service/:id/panels/data-quality",
+            get(dashboard::data_quality_panel),
+        )
+        .route("/service/:id/panels/errors", get(dashboard::errors_panel))
+        .route(
+            "/service/:id/panels/content-groups",
+            get(dashboard::content_groups_panel),
+        )
+        .route(
+            "/service/:id/panels/dimensions",
+            get(dashboard::dimensions_panel),
+        )
+        .route(
+            "/service/:id/install-status",
+            get(dashboard::install_status),
+        )
         .route("/service/:id/sessions", get(dashboard::session_list))
         .route(
             "/service/:id/sessions/:session_id",
             get(dashboard::session_detail),
         )
         .route("/service/:id/locations", get(dashboard::location_list))
+        .route("/service/:id/campaigns", get(dashboard::campaign_list))
+        .route("/service/:id/users", get(dashboard::service_users))
+        .route("/service/:id/retention", get(dashboard::service_retention))
+        .route(
+            "/service/:id/links",
+            get(dashboard::link_list).post(dashboard::link_create),
+        )
+        .route("/service/:id/links/:link_id", get(dashboard::link_detail))
+        .route(
+            "/service/:id/links/:link_id/qr.svg",
+            get(dashboard::link_qr_code),
+        )
+        .route(
+            "/service/:id/links/:link_id/delete",
+            post(dashboard::link_delete),
+        )
         .route("/service/:id/manage", get(dashboard::service_update_form))
         .route("/service/:id/manage", post(dashboard::service_update))
+        .route(
+            "/service/:id/ingest-script",
+            post(dashboard::ingest_script_update),
+        )
+        .route("/service/:id/debug", get(dashboard::service_debug))
         .route("/service/:id/delete", get(dashboard::service_delete_form))
         .route("/service/:id/delete", post(dashboard::service_delete))
-        // Ingress routes (using non-obvious paths to avoid ad blockers)
-        .route("/trace/px_:tracking_id.gif", get(ingress::pixel_handler))
         .route(
-            "/trace/px_:tracking_id/:identifier.gif",
-            get(ingress::pixel_with_id_handler),
+            "/exclude-me/:tracking_id",
+            get(dashboard::exclude_me_form).post(dashboard::exclude_me),
         )
+        .route("/service/:id/views", post(dashboard::saved_view_create))
         .route(
-            "/trace/app_:tracking_id.js",
-            get(ingress::script_get_handler).post(ingress::script_post_handler),
+            "/service/:id/views/:view_id/delete",
+            post(dashboard::saved_view_delete),
         )
+        .route("/service/:id/segments", post(dashboard::segment_create))
         .route(
-            "/trace/app_:tracking_id/:identifier.js",
-            get(ingress::script_get_with_id_handler).post(ingress::script_post_with_id_handler),
+            "/service/:id/segments/:segment_id/delete",
+            post(dashboard::segment_delete),
+        )
+        // Account and organization routes
+        .route("/login", get(dashboard::login_form).post(dashboard::login))
+        .route("/login/two-factor", post(dashboard::login_two_factor))
+        .route("/logout", post(dashboard::logout))
+        .route(
+            "/signup",
+            get(dashboard::signup_form).post(dashboard::signup),
+        )
+        .route("/verify-email", post(dashboard::verify_email_resend))
+        .route("/verify-email/:token", get(dashboard::verify_email))
+        .route(
+            "/password-reset",
+            get(dashboard::password_reset_form).post(dashboard::password_reset_request),
+        )
+        .route(
+            "/password-reset/:token",
+            get(dashboard::password_reset_confirm_form).post(dashboard::password_reset_confirm),
+        )
+        .route("/invite/:token", get(dashboard::invite_accept))
+        .route("/auth/oidc/login", get(dashboard::oidc_login))
+        .route("/auth/oidc/callback", get(dashboard::oidc_callback))
+        .route("/account", get(dashboard::account))
+        .route("/account/settings", post(dashboard::account_settings))
+        .route("/account/two-factor", post(dashboard::two_factor_enable))
+        .route(
+            "/account/two-factor/disable",
+            post(dashboard::two_factor_disable),
+        )
+        .route(
+            "/organization",
+            get(dashboard::organization_settings).post(dashboard::organization_update),
+        )
+        .route("/organization/members", post(dashboard::member_add))
+        .route(
+            "/organization/members/:user_id/role",
+            post(dashboard::member_role),
         )
+        .route(
+            "/organization/members/:user_id/delete",
+            post(dashboard::member_remove),
+        )
+        .route("/organization/tokens", post(dashboard::api_token_create))
+        .route(
+            "/organization/tokens/:token_id/delete",
+            post(dashboard::api_token_delete),
+        )
+        .route("/organizations", post(dashboard::org_create))
+        .route("/organizations/switch", post(dashboard::org_switch))
+        .route("/organizations/switcher", get(dashboard::org_switcher))
+        .route("/admin/status", get(dashboard::admin_status))
+        .route("/admin/maintenance", post(dashboard::maintenance_run))
+        .route("/admin/update-banner", get(dashboard::update_banner))
+        // Public badges of services that allow them
+        .route(
+            "/badge/:tracking_id/visitors.svg",
+            get(badge::visitors_badge),
+        )
+        // Milestone feeds, behind a signed token instead of a login
+        .route(
+            "/feed/:token/milestones.xml",
+            get(milestones::milestone_feed),
+        )
+        // Widgets for other sites to frame, behind a signed token
+        .route("/embed/:token/chart", get(embed::embed_chart))
+        .route("/embed/:token/counters", get(embed::embed_counters))
+        .route("/static/theme.css", get(theme::theme_css))
+        // Tracked links, counting a click on the way to the target
+        .route("/r/:token", get(ingress::link_redirect_handler))
         // API routes
+        .route("/api/organization", get(api::get_organization))
         .route("/api/services", get(api::list_services))
+        .route("/api/services/bulk", post(api::bulk::update_services))
         .route("/api/services/:id", get(api::get_service))
         .route("/api/services/:id/stats", get(api::get_service_stats))
+        .route("/api/services/:id/report.pdf", get(api::get_service_report))
+        .route(
+            "/api/services/:id/export.parquet",
+            get(api::export_hits_parquet),
+        )
+        .route("/api/services/:id/usage", get(api::get_service_usage))
+        .route(
+            "/api/services/:id/content-groups",
+            get(api::get_content_groups),
+        )
+        .route(
+            "/api/services/:id/dimensions/:key",
+            get(api::get_dimension_counts),
+        )
+        .route("/api/services/:id/paths", get(api::get_page_paths))
+        .route("/api/services/:id/verify-install", get(api::verify_install))
         .route("/api/services/:id/sessions", get(api::list_sessions))
+        .route("/api/services/:id/pixel-urls", post(api::create_pixel_urls))
+        .route("/api/services/:id/validate-hit", post(api::validate_hit))
+        .route("/api/services/:id/campaigns", get(api::list_campaigns))
+        .route("/api/services/:id/users", get(api::get_users))
+        .route("/api/services/:id/retention", get(api::get_retention))
+        .route(
+            "/api/services/:id/campaigns/:campaign",
+            get(api::get_campaign),
+        )
+        .route(
+            "/api/services/:id/links",
+            get(api::list_links).post(api::create_link),
+        )
+        .route(
+            "/api/links/:id",
+            get(api::get_link).delete(api::delete_link),
+        )
+        .route("/api/links/:id/qr.svg", get(api::get_link_qr_code))
+        .route(
+            "/api/services/:id/views",
+            get(api::list_saved_views).post(api::create_saved_view),
+        )
+        .route(
+            "/api/services/:id/segments",
+            get(api::list_segments).post(api::create_segment),
+        )
+        .route(
+            "/api/views/:id",
+            get(api::get_saved_view).delete(api::delete_saved_view),
+        )
+        .route(
+            "/api/segments/:id",
+            get(api::get_segment).delete(api::delete_segment),
+        )
+        .route("/api/status", get(api::get_status))
+        .route("/api/query", post(api::run_query))
+        .route("/api/ws", get(api::live::connect))
+        .route("/grafana", get(api::grafana::test_connection))
+        .route("/grafana/search", post(api::grafana::search))
+        .route("/grafana/query", post(api::grafana::query))
         .route("/api/sessions/:id", get(api::get_session))
         .route("/api/sessions/:id/hits", get(api::list_session_hits))
         // Static files
         .nest_service("/static", ServeDir::new("static"))
-        // Middleware
-        .layer(TraceLayer::new_for_http())
         .layer(cors)
+        .merge(ingress_routes)
+        // Middleware
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            dashboard::render_error_pages,
+        ))
+        .layer(middleware::from_fn(embed::frame_options));
+    let request_timeout = (settings.request_timeout_secs > 0)
+        .then(|| Duration::from_secs(settings.request_timeout_secs));
+    let app = guards::guard(app, request_timeout)
+        // Compresses dashboard pages and API JSON; the tracker script is
+        // precompressed and carries Content-Encoding, so it is left alone
+        .layer(CompressionLayer::new())
+        .layer(TraceLayer::new_for_http().make_span_with(guards::request_span))
+        .layer(PropagateRequestIdLayer::new(guards::REQUEST_ID_HEADER))
+        .layer(SetRequestIdLayer::new(
+            guards::REQUEST_ID_HEADER,
+            MakeRequestUuid,
+        ))
         .with_state(state);
 
-    let addr = SocketAddr::new(
-        settings.host.parse().unwrap_or([0, 0, 0, 0].into()),
-        settings.port,
-    );
-    info!("Starting server on {}", addr);
+    // Under socket activation systemd holds the socket, otherwise bind it
+    let listener = match systemd::inherited_listener()? {
+        Some(listener) => listener,
+        None => {
+            let addr = ListenAddr::from_settings(&settings);
+            #[cfg(unix)]
+            let listener = Listener::bind(&addr, settings.listen_mode).await?;
+            #[cfg(not(unix))]
+            let listener = Listener::bind(&addr).await?;
+            listener
+        }
+    };
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    server::serve(
+        listener,
+        app,
+        ServeOptions::from_settings(&settings),
+        shutdown_signal(),
+    )
+    .await?;
 
     Ok(())
 }
+
+/// Ctrl-C or SIGTERM, after which requests in flight are finished before
+/// the server exits
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Can't listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("Can't listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down");
+    systemd::notify("STOPPING=1");
+}
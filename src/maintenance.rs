@@ -0,0 +1,145 @@
+//! Database maintenance, run once a day at `maintenance_hour` (UTC), when
+//! traffic is expected to be low. Daily top pages past
+//! `rollup_retention_days` and stale idempotency filters are removed, the
+//! in-memory caches drop their expired entries, and the database refreshes
+//! its planner statistics: SQLite with `ANALYZE` and `VACUUM`, Postgres with
+//! `ANALYZE` and autovacuum tuned for the busiest tables. How each task last
+//! went is kept in `maintenance_runs` for the admin status page.
+
+use std::time::Instant;
+
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use tracing::{error, info};
+
+use crate::db;
+use crate::domain::{BackgroundTask, MaintenanceRun, MaintenanceTask};
+use crate::error::{Error, Result};
+use crate::state::AppState;
+
+/// Time from `now` until the next start of `hour` (UTC); a full day when it
+/// is that time exactly
+pub fn until_next_run(now: DateTime<Utc>, hour: u32) -> Duration {
+    let start = NaiveTime::from_hms_opt(hour.min(23), 0, 0).unwrap_or(NaiveTime::MIN);
+    let today = now.date_naive().and_time(start).and_utc();
+    let next = if today > now {
+        today
+    } else {
+        today + Duration::days(1)
+    };
+    next - now
+}
+
+async fn run_task(state: &AppState, task: MaintenanceTask) -> Result<()> {
+    match task {
+        MaintenanceTask::CompactRollups => {
+            let today = state.clock.now().date_naive();
+            let retention_days = state.settings.rollup_retention_days;
+            if retention_days > 0 {
+                let oldest = today - Duration::days(retention_days as i64);
+                let removed = db::delete_top_locations_before(&state.pool, oldest).await?;
+                info!("Removed {} daily top pages before {}", removed, oldest);
+            }
+            // The filter flush does this too, but only while it is on
+            let yesterday = today.pred_opt().unwrap_or(today);
+            db::delete_hit_filters_before(&state.pool, yesterday).await
+        }
+        MaintenanceTask::PruneCaches => {
+            let entries = state.cache.prune().await;
+            info!("Caches pruned, {} entries left", entries);
+            Ok(())
+        }
+        MaintenanceTask::Optimize => db::optimize_database(&state.pool).await,
+    }
+}
+
+/// Run every maintenance task in turn, recording how each went. A failed
+/// task doesn't keep the others from running, but marks the maintenance as
+/// failing on the status page.
+pub async fn run_maintenance(state: &AppState) -> Vec<MaintenanceRun> {
+    let mut runs = Vec::new();
+    for task in MaintenanceTask::ALL {
+        let started_at = state.clock.now();
+        let timer = Instant::now();
+        let result = run_task(state, task).await;
+        if let Err(e) = &result {
+            error!("Maintenance task {} failed: {}", task.as_str(), e);
+        }
+
+        let run = MaintenanceRun {
+            task,
+            started_at,
+            duration_ms: timer.elapsed().as_millis() as i64,
+            error: result.err().map(|e| e.to_string()),
+        };
+        if let Err(e) = db::save_maintenance_run(&state.pool, &run).await {
+            error!("Saving the maintenance run failed: {}", e);
+        }
+        runs.push(run);
+    }
+
+    let failed = runs.iter().filter(|run| run.error.is_some()).count();
+    let result = match failed {
+        0 => Ok(()),
+        _ => Err(Error::Internal(format!(
+            "{} of {} tasks failed",
+            failed,
+            runs.len()
+        ))),
+    };
+    state
+        .tasks
+        .record(BackgroundTask::Maintenance, state.clock.now(), &result);
+    runs
+}
+
+/// Run the maintenance every day at `maintenance_hour`, if set
+pub fn spawn(state: AppState) {
+    let Some(hour) = state.settings.maintenance_hour else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        loop {
+            let wait = until_next_run(state.clock.now(), hour);
+            tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+            let runs = run_maintenance(&state).await;
+            let failed = runs.iter().filter(|run| run.error.is_some()).count();
+            info!(
+                "Maintenance done, {} of {} tasks failed",
+                failed,
+                runs.len()
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_until_next_run() {
+        assert_eq!(
+            until_next_run(time("2024-03-10T01:30:00Z"), 3),
+            Duration::minutes(90)
+        );
+        // Today's run has passed, so tomorrow's
+        assert_eq!(
+            until_next_run(time("2024-03-10T04:00:00Z"), 3),
+            Duration::hours(23)
+        );
+        assert_eq!(
+            until_next_run(time("2024-03-10T03:00:00Z"), 3),
+            Duration::days(1)
+        );
+        // Out of range hours run at the day's last hour
+        assert_eq!(
+            until_next_run(time("2024-03-10T22:00:00Z"), 30),
+            Duration::hours(1)
+        );
+    }
+}
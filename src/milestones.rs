@@ -0,0 +1,355 @@
+//! Atom feeds of a service's traffic milestones: monthly session counts
+//! crossing a round number, record days and unusual spikes or drops. The
+//! feed URL carries a signed token instead of a login, so it can be pasted
+//! into feed readers and chat apps; anyone with the URL can read the feed.
+//! Milestones are found anew on each request from the daily session counts
+//! of complete UTC days, so nothing is stored.
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+
+use crate::auth::TokenPurpose;
+use crate::dashboard::intcomma;
+use crate::db;
+use crate::domain::{Environment, ServiceId};
+use crate::error::{Error, Result};
+use crate::i18n::I18n;
+use crate::state::AppState;
+
+/// Days of counts milestones are found in; records are measured against all
+/// of them
+const HISTORY_DAYS: usize = 400;
+/// Days of milestones the feed lists
+const FEED_DAYS: i64 = 90;
+/// Monthly session counts worth announcing once crossed
+const MONTHLY_THRESHOLDS: [i64; 5] = [1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+/// Days before a day that it is compared with, for records and anomalies
+const BASELINE_DAYS: usize = 28;
+/// Fewest sessions a day averages before spikes and drops are looked for
+const ANOMALY_MIN_MEAN: f64 = 10.0;
+/// How many standard deviations from the mean a day must be to be unusual
+const ANOMALY_DEVIATIONS: f64 = 3.0;
+
+/// Something notable about a day's traffic
+#[derive(Debug, Clone, PartialEq)]
+pub enum Milestone {
+    /// The day's sessions took the month's total past `threshold`
+    MonthlySessions { day: NaiveDate, threshold: i64 },
+    /// More sessions than on any earlier day
+    RecordDay { day: NaiveDate, sessions: i64 },
+    /// Far more sessions than the weeks before
+    Spike {
+        day: NaiveDate,
+        sessions: i64,
+        expected: i64,
+    },
+    /// Far fewer sessions than the weeks before
+    Drop {
+        day: NaiveDate,
+        sessions: i64,
+        expected: i64,
+    },
+}
+
+impl Milestone {
+    pub fn day(&self) -> NaiveDate {
+        match self {
+            Self::MonthlySessions { day, .. }
+            | Self::RecordDay { day, .. }
+            | Self::Spike { day, .. }
+            | Self::Drop { day, .. } => *day,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::MonthlySessions { .. } => "monthly-sessions",
+            Self::RecordDay { .. } => "record-day",
+            Self::Spike { .. } => "spike",
+            Self::Drop { .. } => "drop",
+        }
+    }
+
+    fn title(&self, i18n: &I18n) -> String {
+        match self {
+            Self::MonthlySessions { threshold, .. } => {
+                i18n.t1("milestone-monthly-sessions", "count", intcomma(*threshold))
+            }
+            Self::RecordDay { sessions, .. } => {
+                i18n.t1("milestone-record-day", "count", intcomma(*sessions))
+            }
+            Self::Spike { sessions, .. } => {
+                i18n.t1("milestone-spike", "count", intcomma(*sessions))
+            }
+            Self::Drop { sessions, .. } => i18n.t1("milestone-drop", "count", intcomma(*sessions)),
+        }
+    }
+
+    fn summary(&self, i18n: &I18n) -> String {
+        match self {
+            Self::Spike { expected, .. } | Self::Drop { expected, .. } => {
+                i18n.t1("milestone-expected", "count", intcomma(*expected))
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+/// Milestones in the daily session counts `sessions`, the first of which is
+/// for `first_day`, oldest first
+pub fn find_milestones(first_day: NaiveDate, sessions: &[i64]) -> Vec<Milestone> {
+    let mut milestones = Vec::new();
+    let mut month_total = 0;
+    let mut best = 0;
+
+    for (i, &count) in sessions.iter().enumerate() {
+        let day = first_day + Duration::days(i as i64);
+
+        // Months the counts start partway through can't be totalled
+        if day.day() == 1 {
+            month_total = 0;
+        }
+        let counted_month = first_day <= day.with_day(1).unwrap_or(day);
+        let before = month_total;
+        month_total += count;
+        let crossed = MONTHLY_THRESHOLDS
+            .iter()
+            .rev()
+            .find(|&&t| before < t && t <= month_total);
+        if let (Some(&threshold), true) = (crossed, counted_month) {
+            milestones.push(Milestone::MonthlySessions { day, threshold });
+        }
+
+        let is_record = i >= BASELINE_DAYS && best > 0 && count > best;
+        if is_record {
+            milestones.push(Milestone::RecordDay {
+                day,
+                sessions: count,
+            });
+        }
+        best = best.max(count);
+
+        if i >= BASELINE_DAYS {
+            let baseline = &sessions[i - BASELINE_DAYS..i];
+            let mean = baseline.iter().sum::<i64>() as f64 / BASELINE_DAYS as f64;
+            let variance = baseline
+                .iter()
+                .map(|&c| (c as f64 - mean).powi(2))
+                .sum::<f64>()
+                / BASELINE_DAYS as f64;
+            // Counts vary at least as much as random arrivals would
+            let deviation = variance.sqrt().max(mean.sqrt()) * ANOMALY_DEVIATIONS;
+            let expected = mean.round() as i64;
+            if mean < ANOMALY_MIN_MEAN {
+                continue;
+            }
+            if count as f64 > mean + deviation && !is_record {
+                milestones.push(Milestone::Spike {
+                    day,
+                    sessions: count,
+                    expected,
+                });
+            } else if (count as f64) < mean - deviation {
+                milestones.push(Milestone::Drop {
+                    day,
+                    sessions: count,
+                    expected,
+                });
+            }
+        }
+    }
+
+    milestones
+}
+
+/// Absolute URL of a service's milestone feed
+pub fn feed_url(state: &AppState, service_id: ServiceId) -> String {
+    let token = state.signing_key.sign(
+        TokenPurpose::MilestoneFeed,
+        &service_id.to_string(),
+        state.clock.now(),
+    );
+    state
+        .settings
+        .public_link(&format!("/feed/{}/milestones.xml", token))
+}
+
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub summary: String,
+    /// End of the day the milestone is about
+    pub updated: String,
+}
+
+#[derive(Template)]
+#[template(path = "feed/milestones.xml")]
+pub struct MilestoneFeedTemplate {
+    pub id: String,
+    pub title: String,
+    /// Dashboard of the service
+    pub link: String,
+    pub updated: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+/// GET /feed/:token/milestones.xml
+pub async fn milestone_feed(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let now = state.clock.now();
+    let service_id: ServiceId = state
+        .signing_key
+        .verify(TokenPurpose::MilestoneFeed, &token, now)
+        .and_then(|subject| subject.parse().ok())
+        .ok_or(Error::ServiceNotFound)?;
+    let service = db::get_service(&state.pool, service_id).await?;
+
+    // Whole UTC days only, so a day in progress is never judged
+    let today = now.date_naive();
+    let end = Utc.from_utc_datetime(&today.and_time(Default::default()));
+    let trends = db::get_daily_trends(
+        &state.pool,
+        &[service_id],
+        end,
+        Some(Environment::Production),
+        HISTORY_DAYS,
+    )
+    .await?;
+    let sessions = trends
+        .get(&service_id)
+        .map(|trend| trend.sessions.as_slice())
+        .unwrap_or_default();
+    let first_day = today - Duration::days(HISTORY_DAYS as i64);
+
+    let i18n = I18n::from_headers(&headers, &state.settings.locale);
+    let since = today - Duration::days(FEED_DAYS);
+    let day_end = |day: NaiveDate| -> DateTime<Utc> {
+        Utc.from_utc_datetime(&(day + Duration::days(1)).and_time(Default::default()))
+    };
+    let entries: Vec<FeedEntry> = find_milestones(first_day, sessions)
+        .into_iter()
+        .rev()
+        .filter(|milestone| milestone.day() >= since)
+        .map(|milestone| FeedEntry {
+            id: format!(
+                "urn:shymini:{}:{}:{}",
+                service_id,
+                milestone.kind(),
+                milestone.day()
+            ),
+            title: milestone.title(&i18n),
+            summary: milestone.summary(&i18n),
+            updated: day_end(milestone.day()).to_rfc3339(),
+        })
+        .collect();
+
+    let template = MilestoneFeedTemplate {
+        id: format!("urn:shymini:{}:milestones", service_id),
+        title: i18n.t1("milestone-feed-title", "service", &service.name),
+        link: state
+            .settings
+            .public_link(&format!("/service/{}", service_id)),
+        updated: entries
+            .first()
+            .map(|entry| entry.updated.clone())
+            .unwrap_or_else(|| end.to_rfc3339()),
+        entries,
+    };
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        template.render()?,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_monthly_thresholds() {
+        // 400 sessions a day from May 30th to July 2nd: June crosses 1,000 on
+        // its 3rd and 10,000 on its 25th; May started before the counts and
+        // is skipped
+        let sessions = vec![400; 34];
+        let milestones = find_milestones(day("2024-05-30"), &sessions);
+        let monthly: Vec<_> = milestones
+            .iter()
+            .filter(|m| matches!(m, Milestone::MonthlySessions { .. }))
+            .collect();
+        assert_eq!(
+            monthly,
+            [
+                &Milestone::MonthlySessions {
+                    day: day("2024-06-03"),
+                    threshold: 1_000
+                },
+                &Milestone::MonthlySessions {
+                    day: day("2024-06-25"),
+                    threshold: 10_000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_days_and_anomalies() {
+        let mut sessions = vec![100; 40];
+        sessions[30] = 300;
+        sessions[35] = 250;
+        let milestones = find_milestones(day("2024-01-01"), &sessions);
+
+        assert!(milestones.contains(&Milestone::RecordDay {
+            day: day("2024-01-31"),
+            sessions: 300
+        }));
+        // Not a record, but far above the weeks before
+        assert!(milestones.contains(&Milestone::Spike {
+            day: day("2024-02-05"),
+            sessions: 250,
+            expected: 107
+        }));
+        // A record is not also reported as a spike
+        assert!(!milestones
+            .iter()
+            .any(|m| matches!(m, Milestone::Spike { day: d, .. } if *d == day("2024-01-31"))));
+
+        let mut sessions = vec![100; 40];
+        sessions[38] = 10;
+        let milestones = find_milestones(day("2024-01-01"), &sessions);
+        let anomalies: Vec<_> = milestones
+            .into_iter()
+            .filter(|m| matches!(m, Milestone::Spike { .. } | Milestone::Drop { .. }))
+            .collect();
+        assert_eq!(
+            anomalies,
+            [Milestone::Drop {
+                day: day("2024-02-08"),
+                sessions: 10,
+                expected: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn test_quiet_services_have_no_anomalies() {
+        let mut sessions = vec![2; 40];
+        sessions[35] = 9;
+        let milestones = find_milestones(day("2024-01-01"), &sessions);
+        assert!(!milestones
+            .iter()
+            .any(|m| matches!(m, Milestone::Spike { .. } | Milestone::Drop { .. })));
+    }
+}
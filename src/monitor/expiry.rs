@@ -0,0 +1,223 @@
+//! When a site's TLS certificate and domain registration run out. The
+//! certificate's `notAfter` is read straight from the DER the server sends,
+//! and the domain's expiration event is looked up over RDAP.
+
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::StatusCode;
+use tracing::warn;
+use url::{Host, Url};
+
+use crate::domain::{ExpiryCheck, ServiceId};
+
+/// How long the site and the RDAP server get to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// DER tags the certificate walk needs
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const EXPLICIT_VERSION: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+/// Look up when `link`'s certificate and domain run out. Either is `None`
+/// when there is none (plain HTTP, an IP address) or the lookup failed.
+pub async fn check_expiry(
+    client: &reqwest::Client,
+    rdap_url: &str,
+    service_id: ServiceId,
+    link: &str,
+    now: DateTime<Utc>,
+) -> ExpiryCheck {
+    let mut check = ExpiryCheck {
+        service_id,
+        checked_at: now,
+        certificate_expires_at: None,
+        domain_expires_at: None,
+    };
+    let Ok(url) = Url::parse(link) else {
+        return check;
+    };
+
+    if url.scheme() == "https" {
+        check.certificate_expires_at = certificate_expiry(&url).await.unwrap_or_else(|e| {
+            warn!("Failed to read the certificate of {}: {}", link, e);
+            None
+        });
+    }
+    if let Some(Host::Domain(host)) = url.host() {
+        check.domain_expires_at = domain_expiry(client, rdap_url, host)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to look up the domain of {}: {}", link, e);
+                None
+            });
+    }
+    check
+}
+
+/// The `notAfter` of the certificate `url` is served with. Certificates are
+/// not verified here so that expired ones can still be read.
+async fn certificate_expiry(url: &Url) -> Result<Option<DateTime<Utc>>, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("shymini/", env!("CARGO_PKG_VERSION")))
+        .tls_info(true)
+        .danger_accept_invalid_certs(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let response = client.get(url.clone()).send().await?;
+    Ok(response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .and_then(certificate_not_after))
+}
+
+/// When the registration of the domain `host` belongs to runs out. Leading
+/// labels are dropped until the registry knows the name, so
+/// `www.example.co.uk` finds `example.co.uk`.
+async fn domain_expiry(
+    client: &reqwest::Client,
+    rdap_url: &str,
+    host: &str,
+) -> Result<Option<DateTime<Utc>>, reqwest::Error> {
+    let labels: Vec<&str> = host.trim_end_matches('.').split('.').collect();
+    for start in 0..labels.len().saturating_sub(1) {
+        let url = format!(
+            "{}/domain/{}",
+            rdap_url.trim_end_matches('/'),
+            labels[start..].join(".")
+        );
+        let response = client.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            continue;
+        }
+        let json: serde_json::Value = response.error_for_status()?.json().await?;
+        return Ok(rdap_expiration(&json));
+    }
+    Ok(None)
+}
+
+/// The date of the `expiration` event in an RDAP domain response
+pub fn rdap_expiration(json: &serde_json::Value) -> Option<DateTime<Utc>> {
+    let event = json["events"]
+        .as_array()?
+        .iter()
+        .find(|event| event["eventAction"] == "expiration")?;
+    let date = DateTime::parse_from_rfc3339(event["eventDate"].as_str()?).ok()?;
+    Some(date.with_timezone(&Utc))
+}
+
+/// The `notAfter` of a DER-encoded X.509 certificate
+pub fn certificate_not_after(der: &[u8]) -> Option<DateTime<Utc>> {
+    let (certificate, _) = read(der, SEQUENCE)?;
+    let (mut tbs, _) = read(certificate, SEQUENCE)?;
+    if tbs.first() == Some(&EXPLICIT_VERSION) {
+        tbs = read(tbs, EXPLICIT_VERSION)?.1;
+    }
+    let (_serial, rest) = read(tbs, INTEGER)?;
+    let (_signature, rest) = read(rest, SEQUENCE)?;
+    let (_issuer, rest) = read(rest, SEQUENCE)?;
+    let (validity, _) = read(rest, SEQUENCE)?;
+    let (_not_before, rest) = read_time(validity)?;
+    let (not_after, _) = read_time(rest)?;
+    Some(not_after)
+}
+
+/// The contents of the element with `tag` at the start of `input`, and what
+/// follows it
+fn read(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&first, rest) = input.split_first()?;
+    if first != tag {
+        return None;
+    }
+    let (&length, rest) = rest.split_first()?;
+    let (length, rest) = if length < 0x80 {
+        (length as usize, rest)
+    } else {
+        let bytes = (length & 0x7f) as usize;
+        if bytes == 0 || bytes > 4 || rest.len() < bytes {
+            return None;
+        }
+        let (length, rest) = rest.split_at(bytes);
+        let length = length
+            .iter()
+            .fold(0usize, |acc, &byte| acc << 8 | byte as usize);
+        (length, rest)
+    };
+    (rest.len() >= length).then(|| rest.split_at(length))
+}
+
+/// A UTCTime or GeneralizedTime at the start of `input`, and what follows it
+fn read_time(input: &[u8]) -> Option<(DateTime<Utc>, &[u8])> {
+    let tag = *input.first()?;
+    let (value, rest) = match tag {
+        UTC_TIME | GENERALIZED_TIME => read(input, tag)?,
+        _ => return None,
+    };
+    let value = std::str::from_utf8(value).ok()?;
+    let value = if tag == UTC_TIME {
+        // Two-digit years 50-99 are in the 1900s (RFC 5280)
+        let century = if value.get(..2)? >= "50" { "19" } else { "20" };
+        format!("{}{}", century, value)
+    } else {
+        value.to_string()
+    };
+    let time = NaiveDateTime::parse_from_str(&value, "%Y%m%d%H%M%SZ").ok()?;
+    Some((time.and_utc(), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use chrono::TimeZone;
+
+    /// Self-signed, valid until 2024-06-30 12:00 (a UTCTime)
+    const CERT_UTC_TIME: &str = "MIIBgTCCASegAwIBAgIUfF/l7QqGwmxWb2taXbgl+QZliwswCgYIKoZIzj0EAwIwFjEUMBIGA1UEAwwLZXhhbXBsZS5jb20wHhcNMjQwMTAxMDAwMDAwWhcNMjQwNjMwMTIwMDAwWjAWMRQwEgYDVQQDDAtleGFtcGxlLmNvbTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABJcTouW4J5o1y1c2ECuGabm4XlMZ1wjr/VSJ/vpPYnS4KLAAvYFup5/q29cPSp4CwKprgg0dBWrFiRQFNq2hGuGjUzBRMB0GA1UdDgQWBBQ3XbOvmxvU6ghc1YeLJjNAPjnjvDAfBgNVHSMEGDAWgBQ3XbOvmxvU6ghc1YeLJjNAPjnjvDAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIEZbi5u4zzSmtwz1q0ZsBUXVg8vA5LIs7ISYlSb5NJjeAiEAkiyEKd/lvIuhv8Feeu0jRCA7LEZkRvN1NbRg2hHp6tM=";
+    /// Self-signed, valid until 2055-01-01 (a GeneralizedTime)
+    const CERT_GENERALIZED_TIME: &str = "MIIBgzCCASmgAwIBAgIUali3WGSv7Q5Oo8iU1Uav7wT4UnwwCgYIKoZIzj0EAwIwFjEUMBIGA1UEAwwLZXhhbXBsZS5jb20wIBcNMjQwMTAxMDAwMDAwWhgPMjA1NTAxMDEwMDAwMDBaMBYxFDASBgNVBAMMC2V4YW1wbGUuY29tMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEb1deRlWIGeObom0aBLDgAia3D3X1h2/gNWU7PYwaLs8fvEsa+ChU0pRbMcmR3xvm2rIdkl1WH0CtpCldPQOemaNTMFEwHQYDVR0OBBYEFCpNbbHlTJBWi2+VfpDTjQzeRGNOMB8GA1UdIwQYMBaAFCpNbbHlTJBWi2+VfpDTjQzeRGNOMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIgIhtoPnfd0pFhL7TG5wu0mHe8WBywfUG6R4ozbcE47DcCIQDAUp3JKm+uWZ3deSvR7+yI0wzoGKg1Dy2xUKrISwvg1Q==";
+
+    #[test]
+    fn test_certificate_not_after() {
+        let der = STANDARD.decode(CERT_UTC_TIME).unwrap();
+        assert_eq!(
+            certificate_not_after(&der),
+            Some(Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap())
+        );
+
+        let der = STANDARD.decode(CERT_GENERALIZED_TIME).unwrap();
+        assert_eq!(
+            certificate_not_after(&der),
+            Some(Utc.with_ymd_and_hms(2055, 1, 1, 0, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_certificate_garbage() {
+        let der = STANDARD.decode(CERT_UTC_TIME).unwrap();
+        assert_eq!(certificate_not_after(&der[..40]), None);
+        assert_eq!(certificate_not_after(b"not a certificate"), None);
+        assert_eq!(certificate_not_after(&[]), None);
+    }
+
+    #[test]
+    fn test_rdap_expiration() {
+        let json = serde_json::json!({
+            "ldhName": "example.com",
+            "events": [
+                {"eventAction": "registration", "eventDate": "1995-08-14T04:00:00Z"},
+                {"eventAction": "expiration", "eventDate": "2025-08-13T04:00:00Z"}
+            ]
+        });
+        assert_eq!(
+            rdap_expiration(&json),
+            Some(Utc.with_ymd_and_hms(2025, 8, 13, 4, 0, 0).unwrap())
+        );
+        assert_eq!(rdap_expiration(&serde_json::json!({"events": []})), None);
+        assert_eq!(rdap_expiration(&serde_json::json!({})), None);
+    }
+}
@@ -0,0 +1,284 @@
+//! Uptime monitoring of the sites services track. With
+//! `monitor_interval_secs` set, every active service's `link` is requested on
+//! that schedule. Each outcome is recorded in `monitor_checks`, and
+//! `monitor_webhook_url` hears about a site going down or coming back up.
+//! Once a week the site's TLS certificate and domain are also checked for
+//! running out soon.
+
+mod expiry;
+
+pub use expiry::*;
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+
+use crate::db;
+use crate::domain::{BackgroundTask, ExpiryKind, ExpiryWarning, MonitorCheck, Service, ServiceId};
+use crate::error::Result;
+use crate::state::AppState;
+
+/// How long a site gets to answer before it counts as down
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long checks are kept
+const RETENTION_DAYS: i64 = 30;
+/// How often certificates and domains are looked up
+const EXPIRY_CHECK_DAYS: i64 = 7;
+/// Certificates and domains running out sooner than this are warned about
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+/// A site going down or coming back, as posted to the webhook
+#[derive(Debug, Serialize)]
+pub struct MonitorAlert<'a> {
+    /// `down` or `up`
+    pub event: &'static str,
+    pub service_id: ServiceId,
+    pub service_name: &'a str,
+    pub link: &'a str,
+    pub check: &'a MonitorCheck,
+}
+
+/// A certificate or domain running out soon, as posted to the webhook
+#[derive(Debug, Serialize)]
+pub struct ExpiryAlert<'a> {
+    /// `certificate_expiring` or `domain_expiring`
+    pub event: &'static str,
+    pub service_id: ServiceId,
+    pub service_name: &'a str,
+    pub link: &'a str,
+    #[serde(flatten)]
+    pub warning: &'a ExpiryWarning,
+}
+
+/// The event to alert about when `current` follows `previous`. A site found
+/// down on its first check has gone down.
+pub fn transition(previous: Option<&MonitorCheck>, current: &MonitorCheck) -> Option<&'static str> {
+    match (previous.map(|check| check.up), current.up) {
+        (Some(true) | None, false) => Some("down"),
+        (Some(false), true) => Some("up"),
+        _ => None,
+    }
+}
+
+/// The HTTP client pings and alerts go through
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("shymini/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Request `link` once. Redirects are followed; the site is up if the final
+/// answer has no error status.
+pub async fn ping(
+    client: &reqwest::Client,
+    service_id: ServiceId,
+    link: &str,
+    now: DateTime<Utc>,
+) -> MonitorCheck {
+    let started = Instant::now();
+    match client.get(link).send().await {
+        Ok(response) => {
+            let status = response.status();
+            MonitorCheck {
+                service_id,
+                checked_at: now,
+                status_code: Some(status.as_u16()),
+                latency_ms: Some(started.elapsed().as_millis() as i64),
+                up: !status.is_client_error() && !status.is_server_error(),
+                error: None,
+            }
+        }
+        Err(e) => MonitorCheck {
+            service_id,
+            checked_at: now,
+            status_code: None,
+            latency_ms: None,
+            up: false,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// Check every monitored site once, all at the same time, and drop checks
+/// past retention. Certificates and domains last looked up a week ago or
+/// more are looked up again.
+pub async fn run_checks(state: &AppState, client: &reqwest::Client) -> Result<()> {
+    let now = state.clock.now();
+    let expiry_due = now - chrono::Duration::days(EXPIRY_CHECK_DAYS);
+
+    let mut pings = JoinSet::new();
+    for service in db::list_monitored_services(&state.pool).await? {
+        let expiry_stale = db::get_expiry_check(&state.pool, service.id)
+            .await?
+            .is_none_or(|last| last.checked_at <= expiry_due);
+        let client = client.clone();
+        let rdap_url = state.settings.monitor_rdap_url.clone();
+        pings.spawn(async move {
+            let check = ping(&client, service.id, &service.link, now).await;
+            let expiry = if expiry_stale {
+                Some(check_expiry(&client, &rdap_url, service.id, &service.link, now).await)
+            } else {
+                None
+            };
+            (service, check, expiry)
+        });
+    }
+
+    while let Some(joined) = pings.join_next().await {
+        let Ok((service, check, expiry)) = joined else {
+            continue;
+        };
+        let previous = db::get_latest_monitor_check(&state.pool, service.id).await?;
+        db::record_monitor_check(&state.pool, &check).await?;
+        if let Some(event) = transition(previous.as_ref(), &check) {
+            alert(state, client, &service, event, &check).await;
+        }
+
+        if let Some(expiry) = expiry {
+            db::save_expiry_check(&state.pool, &expiry).await?;
+            // Repeated with every weekly lookup until it is renewed
+            for warning in expiry.warnings(now, EXPIRY_WARNING_DAYS) {
+                alert_expiry(state, client, &service, &warning).await;
+            }
+        }
+    }
+
+    db::delete_monitor_checks_before(&state.pool, now - chrono::Duration::days(RETENTION_DAYS))
+        .await?;
+    Ok(())
+}
+
+/// Log a site going down or up, and tell the webhook if there is one
+async fn alert(
+    state: &AppState,
+    client: &reqwest::Client,
+    service: &Service,
+    event: &'static str,
+    check: &MonitorCheck,
+) {
+    if event == "down" {
+        warn!("{} ({}) is down", service.name, service.link);
+    } else {
+        info!("{} ({}) is back up", service.name, service.link);
+    }
+
+    let payload = MonitorAlert {
+        event,
+        service_id: service.id,
+        service_name: &service.name,
+        link: &service.link,
+        check,
+    };
+    post_webhook(state, client, service, &payload).await;
+}
+
+/// Log a certificate or domain running out soon, and tell the webhook
+async fn alert_expiry(
+    state: &AppState,
+    client: &reqwest::Client,
+    service: &Service,
+    warning: &ExpiryWarning,
+) {
+    let event = match warning.kind {
+        ExpiryKind::Certificate => "certificate_expiring",
+        ExpiryKind::Domain => "domain_expiring",
+    };
+    warn!(
+        "The {} of {} ({}) runs out {}",
+        warning.kind.as_str(),
+        service.name,
+        service.link,
+        warning.expires_at
+    );
+
+    let payload = ExpiryAlert {
+        event,
+        service_id: service.id,
+        service_name: &service.name,
+        link: &service.link,
+        warning,
+    };
+    post_webhook(state, client, service, &payload).await;
+}
+
+/// POST an alert to `monitor_webhook_url`, if there is one
+async fn post_webhook(
+    state: &AppState,
+    client: &reqwest::Client,
+    service: &Service,
+    payload: &impl Serialize,
+) {
+    let Some(url) = state
+        .settings
+        .monitor_webhook_url
+        .as_deref()
+        .filter(|url| !url.is_empty())
+    else {
+        return;
+    };
+    let sent = client
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = sent {
+        error!("Failed to send monitor alert for {}: {}", service.name, e);
+    }
+}
+
+/// Run the checks in the background every `monitor_interval_secs`, if set
+pub fn spawn(state: AppState) {
+    let interval_secs = state.settings.monitor_interval_secs;
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = client();
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = run_checks(&state, &client).await;
+            state
+                .tasks
+                .record(BackgroundTask::Monitor, state.clock.now(), &result);
+            if let Err(e) = result {
+                error!("Uptime checks failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(up: bool) -> MonitorCheck {
+        MonitorCheck {
+            service_id: ServiceId::new(),
+            checked_at: Utc::now(),
+            status_code: up.then_some(200),
+            latency_ms: up.then_some(42),
+            up,
+            error: (!up).then(|| "connection refused".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_transition() {
+        assert_eq!(transition(None, &check(true)), None);
+        assert_eq!(transition(None, &check(false)), Some("down"));
+        assert_eq!(transition(Some(&check(true)), &check(false)), Some("down"));
+        assert_eq!(transition(Some(&check(false)), &check(true)), Some("up"));
+        assert_eq!(transition(Some(&check(true)), &check(true)), None);
+        assert_eq!(transition(Some(&check(false)), &check(false)), None);
+    }
+}
@@ -0,0 +1,186 @@
+//! Monthly partitions of the hits table on Postgres. With `hit_partitions`
+//! set, the table is converted to one partitioned by `start_time` on the
+//! first run, copying every hit into the partition of its month. After that
+//! the task makes sure the current month and the next ones have a partition
+//! before hits arrive for them, and drops the months past
+//! `hit_retention_months`. Hits outside every partition land in a default
+//! one. Stats queries already bound `start_time`, so Postgres only reads
+//! the months of their range.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, NaiveTime, Utc};
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+use crate::db;
+use crate::domain::BackgroundTask;
+use crate::error::{Error, Result};
+use crate::state::AppState;
+
+/// Months after the current one that get their partition ahead of time
+pub const MONTHS_AHEAD: u32 = 2;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The hits of one calendar month (UTC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HitPartition {
+    /// First day of the month
+    month: NaiveDate,
+}
+
+impl HitPartition {
+    /// The partition holding hits of `date`
+    pub fn containing(date: NaiveDate) -> Self {
+        Self {
+            month: date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    /// Table name, e.g. `hits_2024_06`
+    pub fn name(&self) -> String {
+        format!("hits_{}", self.month.format("%Y_%m"))
+    }
+
+    /// Partition of a table named by `name`, if it is one
+    pub fn parse(name: &str) -> Option<Self> {
+        let month = name.strip_prefix("hits_")?;
+        let date = NaiveDate::parse_from_str(&format!("{month}_01"), "%Y_%m_%d").ok()?;
+        Some(Self::containing(date))
+    }
+
+    /// First instant of the month
+    pub fn start(&self) -> DateTime<Utc> {
+        self.month.and_time(NaiveTime::MIN).and_utc()
+    }
+
+    /// First instant of the next month
+    pub fn end(&self) -> DateTime<Utc> {
+        self.next().start()
+    }
+
+    pub fn next(&self) -> Self {
+        self.plus_months(1)
+    }
+
+    pub fn plus_months(&self, months: u32) -> Self {
+        Self {
+            month: self
+                .month
+                .checked_add_months(Months::new(months))
+                .unwrap_or(self.month),
+        }
+    }
+
+    pub fn minus_months(&self, months: u32) -> Self {
+        Self {
+            month: self
+                .month
+                .checked_sub_months(Months::new(months))
+                .unwrap_or(self.month),
+        }
+    }
+
+    /// Partitions from this one through `last`
+    pub fn through(self, last: Self) -> impl Iterator<Item = Self> {
+        std::iter::successors(Some(self), |partition| Some(partition.next()))
+            .take_while(move |partition| *partition <= last)
+    }
+}
+
+/// Partition the hits table if it isn't yet, create the partitions of the
+/// coming months and drop the ones past the retention
+pub async fn run_partitions(state: &AppState) -> Result<()> {
+    if !cfg!(feature = "postgres") {
+        return Err(Error::Internal("Hit partitions need Postgres".to_string()));
+    }
+    let current = HitPartition::containing(state.clock.now().date_naive());
+    let last = current.plus_months(MONTHS_AHEAD);
+
+    if !db::hits_partitioned(&state.pool).await? {
+        info!("Partitioning the hits table by month, this copies every hit");
+        let copied = db::partition_hits(&state.pool, last).await?;
+        info!("Hits table partitioned, {} hits copied", copied);
+    }
+
+    let existing = db::list_hit_partitions(&state.pool).await?;
+    for partition in current.through(last) {
+        if !existing.contains(&partition) {
+            db::create_hit_partition(&state.pool, partition).await?;
+            info!("Created hit partition {}", partition.name());
+        }
+    }
+
+    let retention = state.settings.hit_retention_months;
+    if retention > 0 {
+        let oldest = current.minus_months(retention);
+        for partition in existing.into_iter().filter(|p| *p < oldest) {
+            db::drop_hit_partition(&state.pool, partition).await?;
+            info!("Dropped hit partition {}", partition.name());
+        }
+        // Whatever the default partition caught from back then
+        db::delete_hits_before(&state.pool, oldest.start()).await?;
+    }
+    Ok(())
+}
+
+/// Look after the partitions at startup and once a day, if `hit_partitions`
+/// is set
+pub fn spawn(state: AppState) {
+    if !state.settings.hit_partitions {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = run_partitions(&state).await;
+            state
+                .tasks
+                .record(BackgroundTask::HitPartitions, state.clock.now(), &result);
+            if let Err(e) = result {
+                error!("Hit partitions failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_hit_partition() {
+        let june = HitPartition::containing(date("2024-06-15"));
+        assert_eq!(june.name(), "hits_2024_06");
+        assert_eq!(
+            june.start(),
+            "2024-06-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            june.end(),
+            "2024-07-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(june.plus_months(7).name(), "hits_2025_01");
+        assert_eq!(june.minus_months(6).name(), "hits_2023_12");
+
+        assert_eq!(HitPartition::parse("hits_2024_06"), Some(june));
+        assert_eq!(HitPartition::parse("hits_default"), None);
+        assert_eq!(HitPartition::parse("hits_2024_13"), None);
+        assert_eq!(HitPartition::parse("sessions"), None);
+
+        let names: Vec<String> = june
+            .through(june.plus_months(2))
+            .map(|p| p.name())
+            .collect();
+        assert_eq!(names, ["hits_2024_06", "hits_2024_07", "hits_2024_08"]);
+        assert_eq!(june.through(june.minus_months(1)).count(), 0);
+    }
+}
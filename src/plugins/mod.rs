@@ -0,0 +1,20 @@
+//! Hooks compiled into builds with the `plugins` feature. A fork adds its
+//! hooks here, in their own modules next to this one, and registers them in
+//! `register`:
+//!
+//! ```ignore
+//! mod enrich;
+//!
+//! pub fn register(hooks: Hooks) -> Hooks {
+//!     hooks.with_ingress(enrich::CampaignTagger)
+//! }
+//! ```
+//!
+//! See `crate::hooks` for what hooks can do.
+
+use crate::hooks::Hooks;
+
+/// Add this build's hooks to `hooks`
+pub fn register(hooks: Hooks) -> Hooks {
+    hooks
+}
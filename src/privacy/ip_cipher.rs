@@ -0,0 +1,168 @@
+//! Encryption of the visitor IPs sessions keep, so a leaked database doesn't
+//! give them away. With a key configured, a session stores its IP sealed
+//! with AES-256-GCM and next to it a keyed hash, which lookups by IP compare
+//! against instead, as the ciphertext differs each time. Both only open up
+//! with the key, so sessions stored under a lost key keep no usable IP.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::domain::Session;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const KEY_BYTES: usize = 32;
+const NONCE_BYTES: usize = 12;
+
+/// The configured key: 32 bytes, base64 encoded
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpEncryptionKey([u8; KEY_BYTES]);
+
+impl TryFrom<String> for IpEncryptionKey {
+    type Error = String;
+
+    fn try_from(encoded: String) -> Result<Self, Self::Error> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("IP encryption key isn't base64: {}", e))?;
+        let key = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            format!(
+                "IP encryption key has {} bytes instead of {}",
+                bytes.len(),
+                KEY_BYTES
+            )
+        })?;
+        Ok(Self(key))
+    }
+}
+
+impl fmt::Debug for IpEncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IpEncryptionKey(..)")
+    }
+}
+
+/// Seals, opens and hashes visitor IPs under the configured key
+pub struct IpCipher {
+    cipher: Aes256Gcm,
+    /// Derived from the key, so hashes don't reveal anything about it
+    hash_key: [u8; 32],
+}
+
+impl IpCipher {
+    pub fn new(key: &IpEncryptionKey) -> Self {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(&key.0).expect("HMAC accepts keys of any length");
+        mac.update(b"ip-hash");
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0)),
+            hash_key: mac.finalize().into_bytes().into(),
+        }
+    }
+
+    /// The IP sealed under a fresh nonce, as base64 of the nonce and
+    /// ciphertext
+    pub fn encrypt(&self, ip: &str) -> String {
+        let mut nonce = [0u8; NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), ip.as_bytes())
+            .expect("AES-GCM encrypts any IP");
+        base64::engine::general_purpose::STANDARD.encode([&nonce[..], &ciphertext].concat())
+    }
+
+    /// The IP a sealed one holds, unless it was sealed under another key
+    pub fn decrypt(&self, sealed: &str) -> Option<String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(sealed)
+            .ok()?;
+        if bytes.len() < NONCE_BYTES {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_BYTES);
+        let ip = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        String::from_utf8(ip).ok()
+    }
+
+    /// Keyed hash of an IP, the same for every way of writing it
+    pub fn hash(&self, ip: &str) -> String {
+        let ip = ip
+            .parse::<IpAddr>()
+            .map(|ip| ip.to_canonical().to_string())
+            .unwrap_or_else(|_| ip.to_string());
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.hash_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(ip.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Fill in a session's IP from its sealed one
+    pub fn reveal(&self, session: &mut Session) {
+        if session.ip.is_none() {
+            session.ip = session
+                .ip_encrypted
+                .as_deref()
+                .and_then(|sealed| self.decrypt(sealed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> IpEncryptionKey {
+        IpEncryptionKey::try_from(base64::engine::general_purpose::STANDARD.encode([byte; 32]))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ip_encryption_key() {
+        assert!(IpEncryptionKey::try_from("not base64!".to_string()).is_err());
+        assert!(IpEncryptionKey::try_from("c2hvcnQ=".to_string()).is_err());
+        assert_eq!(format!("{:?}", key(1)), "IpEncryptionKey(..)");
+    }
+
+    #[test]
+    fn test_ip_cipher() {
+        let cipher = IpCipher::new(&key(1));
+        let sealed = cipher.encrypt("203.0.113.7");
+        assert!(!sealed.contains("203.0.113.7"));
+        assert_ne!(sealed, cipher.encrypt("203.0.113.7"));
+        assert_eq!(cipher.decrypt(&sealed).as_deref(), Some("203.0.113.7"));
+
+        let other = IpCipher::new(&key(2));
+        assert_eq!(other.decrypt(&sealed), None);
+        assert_eq!(cipher.decrypt("garbage"), None);
+        assert_eq!(cipher.decrypt(""), None);
+    }
+
+    #[test]
+    fn test_ip_hash() {
+        let cipher = IpCipher::new(&key(1));
+        assert_eq!(cipher.hash("2001:db8::1"), cipher.hash("2001:0db8:0:0::1"));
+        assert_eq!(
+            cipher.hash("::ffff:203.0.113.7"),
+            cipher.hash("203.0.113.7")
+        );
+        assert_ne!(cipher.hash("203.0.113.7"), cipher.hash("203.0.113.8"));
+        assert_ne!(
+            cipher.hash("203.0.113.7"),
+            IpCipher::new(&key(2)).hash("203.0.113.7")
+        );
+        assert_eq!(cipher.hash("203.0.113.7").len(), 64);
+    }
+}
@@ -1,22 +1,54 @@
+pub mod ip_cipher;
+
 use axum::http::HeaderMap;
+use chrono::Duration;
 use ipnetwork::IpNetwork;
 use std::net::IpAddr;
 
-/// Check if DNT (Do Not Track) or GPC (Global Privacy Control) is enabled
+use crate::auth::cookie;
+
+/// How long a browser stays excluded from a service's stats
+const EXCLUSION_DAYS: i64 = 3650;
+
+/// Check if DNT (Do Not Track) is enabled
 pub fn is_dnt_enabled(headers: &HeaderMap) -> bool {
-    let dnt = headers
-        .get("dnt")
-        .and_then(|v| v.to_str().ok())
-        .map(|v| v.trim() == "1")
-        .unwrap_or(false);
+    is_header_set(headers, "dnt")
+}
 
-    let gpc = headers
-        .get("sec-gpc")
+/// Check if GPC (Global Privacy Control) is enabled
+pub fn is_gpc_enabled(headers: &HeaderMap) -> bool {
+    is_header_set(headers, "sec-gpc")
+}
+
+fn is_header_set(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
         .and_then(|v| v.to_str().ok())
         .map(|v| v.trim() == "1")
-        .unwrap_or(false);
+        .unwrap_or(false)
+}
+
+/// Whether the browser sent the request for a page it prefetches or
+/// prerenders, which the visitor may never open: `Sec-Purpose: prefetch`
+/// (also `prefetch;prerender`), or the older `Purpose`, `X-Purpose` and
+/// `X-Moz` headers
+pub fn is_prefetch(headers: &HeaderMap) -> bool {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_ascii_lowercase())
+    };
+
+    let sec_purpose = header("sec-purpose").is_some_and(|v| {
+        v.split(';')
+            .any(|token| token.trim() == "prefetch" || token.trim() == "prerender")
+    });
+    let purpose = ["purpose", "x-purpose", "x-moz"]
+        .iter()
+        .any(|name| header(name).is_some_and(|v| v == "prefetch" || v == "preview"));
 
-    dnt || gpc
+    sec_purpose || purpose
 }
 
 /// Check if an IP address should be ignored based on CIDR list
@@ -39,6 +71,36 @@ pub fn is_ip_ignored(ip: &str, ignored_networks: &[IpNetwork]) -> bool {
     false
 }
 
+/// Name of the cookie marking a browser whose visits a service ignores, set
+/// from the dashboard's exclude-me page
+pub fn exclusion_cookie_name(tracking_id: &str) -> String {
+    format!("shymini_exclude_{}", tracking_id)
+}
+
+/// Check if the browser excluded itself from the service's stats
+pub fn is_excluded(headers: &HeaderMap, tracking_id: &str) -> bool {
+    cookie(headers, &exclusion_cookie_name(tracking_id)) == Some("1")
+}
+
+/// `Set-Cookie` value excluding the browser from the service's stats, or
+/// including it again. Tracked sites load the tracker cross-site, so over
+/// HTTPS the cookie is `SameSite=None` to be sent along.
+pub fn exclusion_set_cookie(tracking_id: &str, exclude: bool, secure: bool) -> String {
+    let (value, max_age) = if exclude {
+        ("1", Duration::days(EXCLUSION_DAYS))
+    } else {
+        ("", Duration::zero())
+    };
+    let same_site = if secure { "None; Secure" } else { "Lax" };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite={}; Max-Age={}",
+        exclusion_cookie_name(tracking_id),
+        value,
+        same_site,
+        max_age.num_seconds()
+    )
+}
+
 /// Parse a comma-separated list of CIDR networks
 pub fn parse_ignored_networks(networks_str: &str) -> Vec<IpNetwork> {
     if networks_str.trim().is_empty() {
@@ -149,6 +211,26 @@ pub fn get_origin(headers: &HeaderMap) -> Option<String> {
 mod tests {
     use super::*;
     use axum::http::HeaderValue;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_exclusion_cookie() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_excluded(&headers, "abc12345"));
+
+        headers.insert(
+            "cookie",
+            HeaderValue::from_static("shymini_session=x; shymini_exclude_abc12345=1"),
+        );
+        assert!(is_excluded(&headers, "abc12345"));
+        assert!(!is_excluded(&headers, "zzz99999"));
+
+        assert_eq!(
+            exclusion_set_cookie("abc12345", true, true),
+            "shymini_exclude_abc12345=1; Path=/; HttpOnly; SameSite=None; Secure; Max-Age=315360000"
+        );
+        assert!(exclusion_set_cookie("abc12345", false, false).ends_with("SameSite=Lax; Max-Age=0"));
+    }
 
     #[test]
     fn test_dnt_disabled_by_default() {
@@ -173,8 +255,32 @@ mod tests {
     #[test]
     fn test_gpc_enabled() {
         let mut headers = HeaderMap::new();
+        assert!(!is_gpc_enabled(&headers));
         headers.insert("sec-gpc", HeaderValue::from_static("1"));
-        assert!(is_dnt_enabled(&headers));
+        assert!(is_gpc_enabled(&headers));
+        assert!(!is_dnt_enabled(&headers));
+    }
+
+    #[test]
+    fn test_prefetch_headers() {
+        assert!(!is_prefetch(&HeaderMap::new()));
+
+        for (name, value) in [
+            ("sec-purpose", "prefetch"),
+            ("sec-purpose", "prefetch;prerender"),
+            ("sec-purpose", "prefetch; anonymous-client-ip"),
+            ("purpose", "prefetch"),
+            ("x-purpose", "preview"),
+            ("x-moz", "prefetch"),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            assert!(is_prefetch(&headers), "{name}: {value}");
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert("purpose", HeaderValue::from_static("navigate"));
+        assert!(!is_prefetch(&headers));
     }
 
     #[test]
@@ -327,4 +433,58 @@ mod tests {
         let headers = HeaderMap::new();
         assert_eq!(get_origin(&headers), None);
     }
+
+    /// Headers with `name` set to `bytes`, when those make a valid header value
+    fn header(name: &'static str, bytes: &[u8]) -> Option<HeaderMap> {
+        let value = HeaderValue::from_bytes(bytes).ok()?;
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value);
+        Some(headers)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_header_values_never_panic(bytes in proptest::collection::vec(any::<u8>(), 0..1024)) {
+            for name in ["origin", "referer", "user-agent", "x-forwarded-for", "dnt", "sec-gpc"] {
+                if let Some(headers) = header(name, &bytes) {
+                    if let Some(origin) = get_origin(&headers) {
+                        prop_assert!(!origin.is_empty());
+                        prop_assert_eq!(origin.to_lowercase(), origin);
+                    }
+                    let referrer = get_referrer(&headers);
+                    prop_assert_eq!(referrer.trim(), referrer.as_str());
+                    let user_agent = get_user_agent(&headers);
+                    prop_assert_eq!(user_agent.trim(), user_agent.as_str());
+                    if let Some(ip) = get_client_ip(&headers) {
+                        prop_assert!(!ip.is_empty());
+                    }
+                    is_dnt_enabled(&headers);
+                    is_gpc_enabled(&headers);
+                }
+            }
+        }
+
+        #[test]
+        fn prop_origin_from_referer(
+            scheme in "https?",
+            host in "[a-zA-Z][a-zA-Z0-9]{0,19}(\\.[a-zA-Z]{2,6})?",
+            port in proptest::option::of(1u16..),
+            path in "(/[ -~]{0,40})?",
+        ) {
+            let port = port.map(|p| format!(":{}", p)).unwrap_or_default();
+            let referer = format!("{}://{}{}{}", scheme, host, port, path);
+            if let Some(headers) = header("referer", referer.as_bytes()) {
+                let origin = get_origin(&headers).unwrap();
+                let prefix = format!("{}://{}", scheme, host.to_lowercase());
+                prop_assert!(origin.starts_with(&prefix));
+                prop_assert!(!origin[scheme.len() + 3..].contains('/'));
+            }
+        }
+
+        #[test]
+        fn prop_ignored_networks_never_panic(networks in any::<String>(), ip in any::<String>()) {
+            let networks = parse_ignored_networks(&networks);
+            is_ip_ignored(&ip, &networks);
+        }
+    }
 }
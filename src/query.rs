@@ -0,0 +1,352 @@
+//! Ad-hoc read-only SQL for admins at `/api/query`, for analysis the stats
+//! don't cover without handing out access to the database. A query must be
+//! a single statement starting with one of `READ_STATEMENTS`, and the
+//! database is told to refuse writes as well: SQLite connections run it with
+//! `query_only` on, Postgres in a read-only transaction. Queries running
+//! longer than the configured timeout are interrupted.
+
+use std::time::{Duration, Instant};
+
+use futures_util::{StreamExt, TryStreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Column, Row, TypeInfo, ValueRef};
+
+use crate::db::Pool;
+use crate::error::{Error, Result};
+
+#[cfg(feature = "postgres")]
+type DbRow = sqlx::postgres::PgRow;
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+type DbRow = sqlx::sqlite::SqliteRow;
+
+/// Keywords a query may start with
+const READ_STATEMENTS: &[&str] = &["SELECT", "WITH", "VALUES", "EXPLAIN"];
+
+/// SQLite instructions run between checks of a query's deadline
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+const PROGRESS_OPS: i32 = 1000;
+
+/// What a query returned
+#[derive(Debug, Serialize)]
+pub struct QueryResult {
+    /// Column names, empty when no rows came back
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    /// Whether there were more rows than the limit
+    pub truncated: bool,
+    pub elapsed_ms: u64,
+}
+
+/// The statement of `sql`, without a trailing `;`, if it is a single one
+/// that reads. Quoted strings and names and comments are skipped, so a `;`
+/// in them doesn't end the statement.
+pub fn check_statement(sql: &str) -> Result<&str> {
+    let bytes = sql.as_bytes();
+    let mut first_word = None;
+    let mut end = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let next = bytes.get(i + 1).copied();
+        match bytes[i] {
+            b'-' if next == Some(b'-') => {
+                i = sql[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                i = sql[i + 2..]
+                    .find("*/")
+                    .map(|n| i + 2 + n + 2)
+                    .ok_or_else(|| query_error("Unterminated comment"))?;
+                continue;
+            }
+            b';' => {
+                end.get_or_insert(i);
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            _ if end.is_some() => {
+                return Err(query_error("Only one statement may be run at a time"));
+            }
+            quote @ (b'\'' | b'"' | b'`') => {
+                // A doubled quote closes the string and opens it again
+                i = sql[i + 1..]
+                    .find(quote as char)
+                    .map(|n| i + 1 + n + 1)
+                    .ok_or_else(|| query_error("Unterminated quotes"))?;
+            }
+            _ if first_word.is_none() => {
+                let len = sql[i..]
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(sql.len() - i);
+                first_word = Some(&sql[i..i + len]);
+                i += len.max(1);
+            }
+            _ => i += 1,
+        }
+        first_word.get_or_insert("");
+    }
+
+    let first_word = first_word.ok_or_else(|| query_error("The query is empty"))?;
+    if !READ_STATEMENTS
+        .iter()
+        .any(|keyword| first_word.eq_ignore_ascii_case(keyword))
+    {
+        return Err(query_error(format!(
+            "Only {} queries may be run",
+            READ_STATEMENTS.join(", ")
+        )));
+    }
+    Ok(&sql[..end.unwrap_or(sql.len())])
+}
+
+fn query_error(message: impl Into<String>) -> Error {
+    Error::BadRequest(message.into())
+}
+
+/// Run a read-only query, returning up to `max_rows` rows. It runs on its
+/// own task, so a client going away can't leave the connection read-only.
+pub async fn run_query(
+    pool: &Pool,
+    sql: &str,
+    timeout: Duration,
+    max_rows: usize,
+) -> Result<QueryResult> {
+    let sql = check_statement(sql)?.to_string();
+    let pool = pool.clone();
+    let started = Instant::now();
+    let fetched = tokio::spawn(async move { fetch_rows(&pool, &sql, timeout, max_rows + 1).await })
+        .await
+        .map_err(|e| Error::Internal(format!("Query task failed: {}", e)))?;
+    let elapsed = started.elapsed();
+
+    let mut rows = match fetched {
+        Ok(rows) => rows,
+        Err(_) if elapsed >= timeout => {
+            return Err(query_error(format!(
+                "The query took longer than {} seconds",
+                timeout.as_secs()
+            )));
+        }
+        Err(sqlx::Error::Database(e)) => {
+            return Err(query_error(format!("Query failed: {}", e.message())));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let truncated = rows.len() > max_rows;
+    rows.truncate(max_rows);
+
+    let columns = rows
+        .first()
+        .map(|row| row.columns().iter().map(|c| c.name().to_string()).collect())
+        .unwrap_or_default();
+    let rows = rows
+        .iter()
+        .map(|row| (0..row.len()).map(|i| json_value(row, i)).collect())
+        .collect::<Result<_>>()?;
+    Ok(QueryResult {
+        columns,
+        rows,
+        truncated,
+        elapsed_ms: elapsed.as_millis() as u64,
+    })
+}
+
+#[cfg(feature = "postgres")]
+async fn fetch_rows(
+    pool: &Pool,
+    sql: &str,
+    timeout: Duration,
+    limit: usize,
+) -> std::result::Result<Vec<DbRow>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(&format!(
+        "SET LOCAL statement_timeout = {}",
+        timeout.as_millis()
+    ))
+    .execute(&mut *tx)
+    .await?;
+    let rows = sqlx::query(sql)
+        .persistent(false)
+        .fetch(&mut *tx)
+        .take(limit)
+        .try_collect()
+        .await;
+    tx.rollback().await?;
+    rows
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+async fn fetch_rows(
+    pool: &Pool,
+    sql: &str,
+    timeout: Duration,
+    limit: usize,
+) -> std::result::Result<Vec<DbRow>, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    // Whatever fails once the connection is read-only, it is reset below
+    let rows = async {
+        sqlx::query("PRAGMA query_only = ON")
+            .execute(&mut *conn)
+            .await?;
+        let deadline = Instant::now() + timeout;
+        conn.lock_handle()
+            .await?
+            .set_progress_handler(PROGRESS_OPS, move || Instant::now() < deadline);
+
+        sqlx::query(sql)
+            .persistent(false)
+            .fetch(&mut *conn)
+            .take(limit)
+            .try_collect()
+            .await
+    }
+    .await;
+
+    let reset = async {
+        conn.lock_handle().await?.remove_progress_handler();
+        sqlx::query("PRAGMA query_only = OFF")
+            .execute(&mut *conn)
+            .await?;
+        Ok::<_, sqlx::Error>(())
+    }
+    .await;
+    if reset.is_err() {
+        // Don't hand a read-only connection back to the pool
+        conn.close_on_drop();
+    }
+    rows
+}
+
+/// A column of a row as JSON, going by the type of its value
+#[cfg(feature = "postgres")]
+fn json_value(row: &DbRow, index: usize) -> Result<Value> {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+    let raw = row.try_get_raw(index)?;
+    if raw.is_null() {
+        return Ok(Value::Null);
+    }
+    let type_name = raw.type_info().name().to_string();
+    Ok(match type_name.as_str() {
+        "BOOL" => row.try_get::<bool, _>(index)?.into(),
+        "INT2" => row.try_get::<i16, _>(index)?.into(),
+        "INT4" => row.try_get::<i32, _>(index)?.into(),
+        "INT8" => row.try_get::<i64, _>(index)?.into(),
+        "FLOAT4" => row.try_get::<f32, _>(index)?.into(),
+        "FLOAT8" => row.try_get::<f64, _>(index)?.into(),
+        "TIMESTAMPTZ" => row.try_get::<DateTime<Utc>, _>(index)?.to_rfc3339().into(),
+        "TIMESTAMP" => row.try_get::<NaiveDateTime, _>(index)?.to_string().into(),
+        "DATE" => row.try_get::<NaiveDate, _>(index)?.to_string().into(),
+        "UUID" => row.try_get::<uuid::Uuid, _>(index)?.to_string().into(),
+        "BYTEA" => hex::encode(row.try_get::<Vec<u8>, _>(index)?).into(),
+        "TEXT" | "VARCHAR" | "BPCHAR" | "CHAR" | "NAME" => row.try_get::<String, _>(index)?.into(),
+        _ => {
+            return Err(query_error(format!(
+                "Column {} is of type {}, which can't be returned; cast it to text or a number",
+                row.column(index).name(),
+                type_name
+            )));
+        }
+    })
+}
+
+/// A column of a row as JSON, going by the type of its value
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+fn json_value(row: &DbRow, index: usize) -> Result<Value> {
+    let raw = row.try_get_raw(index)?;
+    if raw.is_null() {
+        return Ok(Value::Null);
+    }
+    // Values are decoded by their own type, whatever the column is declared as
+    Ok(match raw.type_info().name() {
+        "INTEGER" => row.try_get_unchecked::<i64, _>(index)?.into(),
+        "REAL" => row.try_get_unchecked::<f64, _>(index)?.into(),
+        "BLOB" => hex::encode(row.try_get_unchecked::<Vec<u8>, _>(index)?).into(),
+        _ => row.try_get_unchecked::<String, _>(index)?.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_statement_accepts_reads() {
+        assert_eq!(check_statement("SELECT 1").unwrap(), "SELECT 1");
+        assert_eq!(check_statement("  select 1;  ").unwrap(), "  select 1");
+        assert!(check_statement("WITH x AS (SELECT 1) SELECT * FROM x").is_ok());
+        assert!(check_statement("EXPLAIN SELECT * FROM hits").is_ok());
+        assert!(check_statement("VALUES (1), (2)").is_ok());
+        assert!(check_statement("-- top pages\nSELECT location FROM hits;\n-- done").is_ok());
+        assert!(check_statement("/* a; b */ SELECT 1").is_ok());
+        assert_eq!(
+            check_statement("SELECT 'a;b', \"c;d\" FROM t;").unwrap(),
+            "SELECT 'a;b', \"c;d\" FROM t"
+        );
+        assert!(check_statement("SELECT 'it''s; fine'").is_ok());
+    }
+
+    #[test]
+    fn test_check_statement_rejects_writes() {
+        for sql in [
+            "DELETE FROM hits",
+            "UPDATE services SET name = 'x'",
+            "DROP TABLE hits",
+            "PRAGMA query_only = OFF",
+            "ATTACH DATABASE 'other.db' AS other",
+            "-- SELECT\nDELETE FROM hits",
+            "SELECTED",
+            "(SELECT 1)",
+        ] {
+            assert!(check_statement(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_check_statement_rejects_malformed() {
+        for sql in [
+            "",
+            "  ;  ",
+            "-- nothing",
+            "SELECT 1; DELETE FROM hits",
+            "SELECT 1; SELECT 2",
+            "SELECT 1; 'x'",
+            "SELECT 'unterminated",
+            "SELECT 1 /* unterminated",
+        ] {
+            assert!(check_statement(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+    #[tokio::test]
+    async fn test_failed_query_leaves_connection_writable() {
+        // One connection, so the failed query's is the one written with
+        let pool = crate::db::create_pool("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE things (id INTEGER)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert!(fetch_rows(&pool, "SELECT * FROM missing", timeout, 10)
+            .await
+            .is_err());
+        sqlx::query("INSERT INTO things VALUES (1)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rows = fetch_rows(&pool, "SELECT id FROM things", timeout, 10)
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+}
@@ -0,0 +1,343 @@
+//! The pages and referrers busiest over the last hour, per service, for live
+//! views. Ingress counts each production hit here in memory, so `/api/ws`
+//! subscribers get them without a query. Counts are approximate: each
+//! five-minute bucket keeps `COUNTERS` values with the space-saving
+//! algorithm, where a new value takes over the slot of the least counted one
+//! along with its count, so counts may be overstated by up to their `error`
+//! but a value counted more often than that is never missed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::domain::{Environment, Hit, ServiceId};
+
+/// Values reported of each kind
+pub const TOP_K: usize = 10;
+
+/// Values each bucket keeps counts of
+const COUNTERS: usize = 100;
+
+const BUCKET_SECS: i64 = 5 * 60;
+
+/// Buckets making up the hour counted
+const BUCKETS: i64 = 12;
+
+/// An approximately counted value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HotItem {
+    pub value: String,
+    pub count: u64,
+    /// How much `count` may overstate, 0 when it is exact
+    pub error: u64,
+}
+
+/// A service's busiest values over the last hour
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HotValues {
+    pub locations: Vec<HotItem>,
+    /// Referrers of visits' first hits, as in the stats
+    pub referrers: Vec<HotItem>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counter {
+    count: u64,
+    error: u64,
+}
+
+/// Counts of the most frequent values among those inserted, in at most
+/// `capacity` counters
+#[derive(Debug, Clone)]
+pub struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<String, Counter>,
+}
+
+impl SpaceSaving {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counters: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        if let Some(counter) = self.counters.get_mut(value) {
+            counter.count += 1;
+            return;
+        }
+        let mut counter = Counter { count: 1, error: 0 };
+        if self.counters.len() >= self.capacity {
+            let least = self
+                .counters
+                .iter()
+                .min_by_key(|(_, counter)| counter.count)
+                .map(|(value, counter)| (value.clone(), *counter));
+            if let Some((least_value, least)) = least {
+                self.counters.remove(&least_value);
+                counter = Counter {
+                    count: least.count + 1,
+                    error: least.count,
+                };
+            }
+        }
+        self.counters.insert(value.to_string(), counter);
+    }
+
+    fn counters(&self) -> impl Iterator<Item = (&String, &Counter)> {
+        self.counters.iter()
+    }
+}
+
+/// Counts of one kind of value in the buckets of the last hour
+#[derive(Debug, Default)]
+struct Window {
+    /// Oldest first, by bucket number
+    buckets: VecDeque<(i64, SpaceSaving)>,
+}
+
+impl Window {
+    fn insert(&mut self, bucket: i64, value: &str) {
+        if self.buckets.back().map(|(b, _)| *b) != Some(bucket) {
+            self.buckets.push_back((bucket, SpaceSaving::new(COUNTERS)));
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(b, _)| *b <= bucket - BUCKETS)
+        {
+            self.buckets.pop_front();
+        }
+        if let Some((_, counts)) = self.buckets.back_mut() {
+            counts.insert(value);
+        }
+    }
+
+    /// The `k` values counted most in the hour up to `bucket`
+    fn top(&self, bucket: i64, k: usize) -> Vec<HotItem> {
+        let mut merged: HashMap<&str, Counter> = HashMap::new();
+        for (_, counts) in self
+            .buckets
+            .iter()
+            .filter(|(b, _)| *b > bucket - BUCKETS && *b <= bucket)
+        {
+            for (value, counter) in counts.counters() {
+                let total = merged.entry(value).or_default();
+                total.count += counter.count;
+                total.error += counter.error;
+            }
+        }
+        let mut items: Vec<HotItem> = merged
+            .into_iter()
+            .map(|(value, counter)| HotItem {
+                value: value.to_string(),
+                count: counter.count,
+                error: counter.error,
+            })
+            .collect();
+        items.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        items.truncate(k);
+        items
+    }
+}
+
+#[derive(Debug, Default)]
+struct ServiceWindows {
+    locations: Window,
+    referrers: Window,
+    /// Bucket of the latest hit
+    latest: i64,
+}
+
+#[derive(Default)]
+struct Services {
+    windows: HashMap<ServiceId, ServiceWindows>,
+    /// Bucket services without hits in the hour before were last dropped in
+    pruned: i64,
+}
+
+/// The busiest values of every service, shared with ingress through
+/// `AppState`
+#[derive(Default)]
+pub struct Realtime {
+    services: Mutex<Services>,
+}
+
+impl Realtime {
+    /// Count a new hit
+    pub fn record(&self, hit: &Hit) {
+        if hit.environment != Environment::Production {
+            return;
+        }
+        let bucket = bucket_of(hit.start_time);
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        if services.pruned != bucket {
+            services
+                .windows
+                .retain(|_, windows| windows.latest > bucket - BUCKETS);
+            services.pruned = bucket;
+        }
+        let windows = services.windows.entry(hit.service_id).or_default();
+        windows.latest = windows.latest.max(bucket);
+        windows.locations.insert(bucket, &hit.location);
+        if hit.initial {
+            windows.referrers.insert(bucket, &hit.referrer);
+        }
+    }
+
+    /// A service's busiest values over the hour up to `now`
+    pub fn top(&self, service_id: ServiceId, now: DateTime<Utc>) -> HotValues {
+        let bucket = bucket_of(now);
+        let services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(windows) = services.windows.get(&service_id) else {
+            return HotValues::default();
+        };
+        HotValues {
+            locations: windows.locations.top(bucket, TOP_K),
+            referrers: windows.referrers.top(bucket, TOP_K),
+        }
+    }
+}
+
+fn bucket_of(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(BUCKET_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_space_saving_exact_under_capacity() {
+        let mut counts = SpaceSaving::new(3);
+        for value in ["/a", "/b", "/a", "/c", "/a", "/b"] {
+            counts.insert(value);
+        }
+        let mut window = Window::default();
+        window.buckets.push_back((0, counts));
+        assert_eq!(
+            window.top(0, 2),
+            vec![
+                HotItem {
+                    value: "/a".to_string(),
+                    count: 3,
+                    error: 0
+                },
+                HotItem {
+                    value: "/b".to_string(),
+                    count: 2,
+                    error: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_space_saving_keeps_frequent_values() {
+        let mut counts = SpaceSaving::new(10);
+        // Two pages each seen more often than a tenth of all, among many
+        // pages seen once
+        for i in 0..1000 {
+            counts.insert(&format!("/rare/{}", i));
+            if i % 4 == 0 {
+                counts.insert("/hot");
+            }
+            if i % 5 == 0 {
+                counts.insert("/warm");
+            }
+        }
+        let mut window = Window::default();
+        window.buckets.push_back((0, counts));
+        let top = window.top(0, 2);
+        assert_eq!(top[0].value, "/hot");
+        assert_eq!(top[1].value, "/warm");
+        // Overstated by no more than the error
+        assert!(top[0].count - top[0].error <= 250 && 250 <= top[0].count);
+        assert!(top[1].count - top[1].error <= 200 && 200 <= top[1].count);
+    }
+
+    fn hit(service_id: ServiceId, time: DateTime<Utc>, location: &str, initial: bool) -> Hit {
+        Hit {
+            id: crate::domain::HitId(1),
+            session_id: crate::domain::SessionId(uuid::Uuid::nil()),
+            service_id,
+            initial,
+            start_time: time,
+            last_seen: time,
+            heartbeats: 0,
+            tracker: crate::domain::TrackerType::Js,
+            location: location.to_string(),
+            referrer: if initial {
+                "https://news.example/".to_string()
+            } else {
+                String::new()
+            },
+            load_time: None,
+            environment: Environment::Production,
+            prefetched: false,
+            referrer_spam: false,
+        }
+    }
+
+    #[test]
+    fn test_realtime_window() {
+        let realtime = Realtime::default();
+        let service = ServiceId(uuid::Uuid::new_v4());
+        let start = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        realtime.record(&hit(service, start, "/old", true));
+        let later = start + Duration::minutes(30);
+        realtime.record(&hit(service, later, "/new", false));
+        realtime.record(&hit(service, later, "/new", false));
+
+        let top = realtime.top(service, later);
+        assert_eq!(
+            top.locations
+                .iter()
+                .map(|item| (item.value.as_str(), item.count))
+                .collect::<Vec<_>>(),
+            vec![("/new", 2), ("/old", 1)]
+        );
+        assert_eq!(top.referrers.len(), 1);
+        assert_eq!(top.referrers[0].value, "https://news.example/");
+
+        // An hour on, the first hit has dropped out
+        let top = realtime.top(service, start + Duration::minutes(61));
+        assert_eq!(top.locations.len(), 1);
+        assert_eq!(top.locations[0].value, "/new");
+        assert!(top.referrers.is_empty());
+
+        let mut staging = hit(service, later, "/staging", false);
+        staging.environment = Environment::Staging;
+        realtime.record(&staging);
+        assert!(realtime
+            .top(service, later)
+            .locations
+            .iter()
+            .all(|item| item.value != "/staging"));
+
+        assert_eq!(
+            realtime.top(ServiceId(uuid::Uuid::new_v4()), later),
+            HotValues::default()
+        );
+    }
+
+    #[test]
+    fn test_realtime_prunes_idle_services() {
+        let realtime = Realtime::default();
+        let idle = ServiceId(uuid::Uuid::new_v4());
+        let busy = ServiceId(uuid::Uuid::new_v4());
+        let start = Utc::now();
+        realtime.record(&hit(idle, start, "/", true));
+        realtime.record(&hit(busy, start + Duration::hours(2), "/", true));
+        let services = realtime.services.lock().unwrap();
+        assert!(!services.windows.contains_key(&idle));
+        assert!(services.windows.contains_key(&busy));
+    }
+}
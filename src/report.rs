@@ -0,0 +1,522 @@
+//! Printable one-page PDF summaries of a service's stats, for attaching to
+//! emails. The PDF is written by hand with the standard Helvetica fonts,
+//! which every viewer has, so no font files or rendering library are needed;
+//! characters the fonts' WinAnsi encoding lacks print as `?`.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::dashboard::intcomma;
+use crate::domain::{CoreStats, CountedItem};
+use crate::i18n::I18n;
+
+/// A4, in points
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 50.0;
+const CONTENT_WIDTH: f64 = PAGE_WIDTH - 2.0 * MARGIN;
+
+/// Rows in each top list
+const TOP_ROWS: usize = 10;
+
+type Rgb = (f64, f64, f64);
+
+/// The dashboard chart's colors
+const SESSIONS_COLOR: Rgb = (0.173, 0.286, 0.192);
+const HITS_COLOR: Rgb = (0.361, 0.761, 0.396);
+const TEXT_COLOR: Rgb = (0.067, 0.094, 0.153);
+const MUTED_COLOR: Rgb = (0.420, 0.447, 0.502);
+const RULE_COLOR: Rgb = (0.898, 0.906, 0.922);
+
+/// Widths of the printable ASCII characters in Helvetica, per 1000 units of
+/// font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// What a report covers
+pub struct Report<'a> {
+    /// Name of the service reported on
+    pub title: &'a str,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Time zone the range and chart are shown in
+    pub tz: Tz,
+    pub stats: &'a CoreStats,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl Report<'_> {
+    /// Name for the downloaded file, e.g. `example-com-2024-06-15.pdf`
+    pub fn filename(&self) -> String {
+        let slug = self
+            .title
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_ascii_lowercase();
+        let slug = if slug.is_empty() { "report" } else { &slug };
+        format!(
+            "{}-{}.pdf",
+            slug,
+            self.end.with_timezone(&self.tz).format("%Y-%m-%d")
+        )
+    }
+
+    pub fn render_pdf(&self, i18n: &I18n) -> Vec<u8> {
+        let mut page = Canvas::default();
+        let stats = self.stats;
+        let mut y = PAGE_HEIGHT - MARGIN - 20.0;
+
+        page.text(Font::Bold, 20.0, MARGIN, y, TEXT_COLOR, self.title);
+        y -= 20.0;
+        let range = format!(
+            "{} {} {} ({})",
+            self.start.with_timezone(&self.tz).format("%Y-%m-%d %H:%M"),
+            i18n.t("common-to"),
+            self.end.with_timezone(&self.tz).format("%Y-%m-%d %H:%M"),
+            self.tz.name()
+        );
+        page.text(Font::Regular, 10.0, MARGIN, y, MUTED_COLOR, &range);
+
+        // Headline numbers, as on the dashboard's stat cards
+        y -= 36.0;
+        let cards = [
+            ("stat-sessions", intcomma(stats.session_count)),
+            ("stat-hits", intcomma(stats.hit_count)),
+            ("stat-bounce-rate", optional(stats.bounce_rate_pct, "%")),
+            ("stat-duration", optional(stats.avg_session_duration, "s")),
+            ("stat-load-time", optional(stats.avg_load_time, "ms")),
+            (
+                "stat-hits-per-session",
+                optional(stats.avg_hits_per_session, ""),
+            ),
+        ];
+        let card_width = CONTENT_WIDTH / cards.len() as f64;
+        for (i, (label, value)) in cards.iter().enumerate() {
+            let x = MARGIN + i as f64 * card_width;
+            page.text(Font::Regular, 8.0, x, y, MUTED_COLOR, i18n.t(label));
+            page.text(Font::Bold, 14.0, x, y - 18.0, TEXT_COLOR, value);
+        }
+
+        y -= 56.0;
+        page.text(
+            Font::Bold,
+            12.0,
+            MARGIN,
+            y,
+            TEXT_COLOR,
+            i18n.t("panel-chart"),
+        );
+        self.draw_legend(&mut page, i18n, y);
+        y -= 12.0;
+        self.draw_chart(&mut page, y - 150.0, 150.0);
+
+        y -= 200.0;
+        let column_width = (CONTENT_WIDTH - 20.0) / 2.0;
+        draw_top_list(
+            &mut page,
+            MARGIN,
+            y,
+            column_width,
+            i18n.t("panel-locations"),
+            (i18n.t("column-location"), i18n.t("column-hits")),
+            &stats.locations,
+            i18n.t("common-unknown"),
+        );
+        draw_top_list(
+            &mut page,
+            MARGIN + column_width + 20.0,
+            y,
+            column_width,
+            i18n.t("panel-referrers"),
+            (i18n.t("column-source"), i18n.t("column-sessions")),
+            &stats.referrers,
+            i18n.t("common-direct"),
+        );
+
+        let generated = i18n.t1(
+            "report-generated",
+            "time",
+            self.generated_at
+                .with_timezone(&self.tz)
+                .format("%Y-%m-%d %H:%M"),
+        );
+        page.text(
+            Font::Regular,
+            7.0,
+            MARGIN,
+            MARGIN - 20.0,
+            MUTED_COLOR,
+            &generated,
+        );
+
+        write_document(&page.content, self.title)
+    }
+
+    fn draw_legend(&self, page: &mut Canvas, i18n: &I18n, y: f64) {
+        let mut x = PAGE_WIDTH - MARGIN;
+        for (label, color) in [
+            (i18n.t("stat-hits"), HITS_COLOR),
+            (i18n.t("stat-sessions"), SESSIONS_COLOR),
+        ] {
+            x -= text_width(label, 8.0);
+            page.text(Font::Regular, 8.0, x, y, TEXT_COLOR, label);
+            x -= 12.0;
+            page.rect(x, y, 8.0, 8.0, color);
+            x -= 12.0;
+        }
+    }
+
+    /// Hits per period as bars, with the sessions drawn over them
+    fn draw_chart(&self, page: &mut Canvas, bottom: f64, height: f64) {
+        let chart = &self.stats.chart_data;
+        page.rect(MARGIN, bottom, CONTENT_WIDTH, 0.5, RULE_COLOR);
+        if chart.labels.is_empty() {
+            return;
+        }
+
+        let max = chart
+            .hits
+            .iter()
+            .chain(&chart.sessions)
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let slot = CONTENT_WIDTH / chart.labels.len() as f64;
+        let bar = (slot * 0.8).max(0.5);
+        for (series, color) in [(&chart.hits, HITS_COLOR), (&chart.sessions, SESSIONS_COLOR)] {
+            for (i, &value) in series.iter().enumerate() {
+                if value > 0 {
+                    let x = MARGIN + i as f64 * slot + (slot - bar) / 2.0;
+                    let h = value as f64 / max as f64 * height;
+                    page.rect(x, bottom, bar, h, color);
+                }
+            }
+        }
+
+        let max_label = intcomma(max);
+        page.text(
+            Font::Regular,
+            7.0,
+            MARGIN,
+            bottom + height + 4.0,
+            MUTED_COLOR,
+            &max_label,
+        );
+        let first = &chart.labels[0];
+        page.text(
+            Font::Regular,
+            7.0,
+            MARGIN,
+            bottom - 10.0,
+            MUTED_COLOR,
+            first,
+        );
+        if let Some(last) = chart.labels.last().filter(|_| chart.labels.len() > 1) {
+            let x = PAGE_WIDTH - MARGIN - text_width(last, 7.0);
+            page.text(Font::Regular, 7.0, x, bottom - 10.0, MUTED_COLOR, last);
+        }
+    }
+}
+
+/// The first rows of a top list, with the counts aligned right
+#[allow(clippy::too_many_arguments)]
+fn draw_top_list(
+    page: &mut Canvas,
+    x: f64,
+    mut y: f64,
+    width: f64,
+    title: &str,
+    (name_column, count_column): (&str, &str),
+    items: &[CountedItem],
+    empty_value: &str,
+) {
+    page.text(Font::Bold, 12.0, x, y, TEXT_COLOR, title);
+    y -= 18.0;
+    page.text(Font::Regular, 8.0, x, y, MUTED_COLOR, name_column);
+    let right = x + width;
+    page.text(
+        Font::Regular,
+        8.0,
+        right - text_width(count_column, 8.0),
+        y,
+        MUTED_COLOR,
+        count_column,
+    );
+
+    for item in items.iter().take(TOP_ROWS) {
+        y -= 6.0;
+        page.rect(x, y, width, 0.5, RULE_COLOR);
+        y -= 11.0;
+        let count = intcomma(item.count);
+        let count_width = text_width(&count, 9.0);
+        page.text(
+            Font::Regular,
+            9.0,
+            right - count_width,
+            y,
+            MUTED_COLOR,
+            &count,
+        );
+        let value = if item.value.is_empty() {
+            empty_value
+        } else {
+            &item.value
+        };
+        let value = truncate(value, 9.0, width - count_width - 10.0);
+        page.text(Font::Regular, 9.0, x, y, TEXT_COLOR, &value);
+    }
+}
+
+/// A stat that may be missing, as the stat cards show it
+fn optional(value: Option<f64>, unit: &str) -> String {
+    match value {
+        Some(v) => format!("{}{}", v, unit),
+        None => "?".to_string(),
+    }
+}
+
+/// Width of `text` in Helvetica at `size` points
+fn text_width(text: &str, size: f64) -> f64 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c {
+            ' '..='~' => HELVETICA_WIDTHS[c as usize - 32] as u32,
+            _ => 556,
+        })
+        .sum();
+    units as f64 * size / 1000.0
+}
+
+/// `text`, shortened with "..." to fit in `max_width`
+fn truncate(text: &str, size: f64, max_width: f64) -> String {
+    if text_width(text, size) <= max_width {
+        return text.to_string();
+    }
+    let mut shortened = String::new();
+    for c in text.chars() {
+        shortened.push(c);
+        if text_width(&shortened, size) + text_width("...", size) > max_width {
+            shortened.pop();
+            break;
+        }
+    }
+    shortened + "..."
+}
+
+#[derive(Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    /// Name of the font in the page's resources
+    fn resource(self) -> &'static str {
+        match self {
+            Self::Regular => "F1",
+            Self::Bold => "F2",
+        }
+    }
+}
+
+/// Drawing operators of a page's content stream
+#[derive(Default)]
+struct Canvas {
+    content: Vec<u8>,
+}
+
+impl Canvas {
+    fn text(&mut self, font: Font, size: f64, x: f64, y: f64, (r, g, b): Rgb, text: &str) {
+        self.content.extend_from_slice(
+            format!(
+                "BT /{} {} Tf {:.3} {:.3} {:.3} rg {:.2} {:.2} Td ",
+                font.resource(),
+                size,
+                r,
+                g,
+                b,
+                x,
+                y
+            )
+            .as_bytes(),
+        );
+        push_string(&mut self.content, text);
+        self.content.extend_from_slice(b" Tj ET\n");
+    }
+
+    fn rect(&mut self, x: f64, y: f64, width: f64, height: f64, (r, g, b): Rgb) {
+        self.content.extend_from_slice(
+            format!(
+                "{:.3} {:.3} {:.3} rg {:.2} {:.2} {:.2} {:.2} re f\n",
+                r, g, b, x, y, width, height
+            )
+            .as_bytes(),
+        );
+    }
+}
+
+/// Append `text` as a PDF string literal in WinAnsi encoding
+fn push_string(out: &mut Vec<u8>, text: &str) {
+    out.push(b'(');
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => out.extend_from_slice(&[b'\\', c as u8]),
+            ' '..='~' => out.push(c as u8),
+            // WinAnsi matches Latin-1 here
+            '\u{a0}'..='\u{ff}' => out.push(c as u32 as u8),
+            '€' => out.push(0x80),
+            '…' => out.push(0x85),
+            '‘' => out.push(0x91),
+            '’' => out.push(0x92),
+            '“' => out.push(0x93),
+            '”' => out.push(0x94),
+            '•' => out.push(0x95),
+            '–' => out.push(0x96),
+            '—' => out.push(0x97),
+            _ => out.push(b'?'),
+        }
+    }
+    out.push(b')');
+}
+
+/// A one-page PDF drawing `content`, with the regular and bold Helvetica
+/// fonts as `F1` and `F2`
+fn write_document(content: &[u8], title: &str) -> Vec<u8> {
+    let mut info = b"<< /Title ".to_vec();
+    push_string(&mut info, title);
+    info.extend_from_slice(b" /Producer (shymini) >>");
+
+    let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+    stream.extend_from_slice(content);
+    stream.extend_from_slice(b"\nendstream");
+
+    let objects: [Vec<u8>; 7] = [
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 4 0 R /F2 5 0 R >> >> /Contents 6 0 R >>",
+            PAGE_WIDTH, PAGE_HEIGHT
+        )
+        .into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+        stream,
+        info,
+    ];
+
+    // The binary comment line tells transfer tools the file isn't text
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info 7 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ChartData;
+    use crate::i18n::Locale;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_push_string_escapes_and_encodes() {
+        let mut out = Vec::new();
+        push_string(&mut out, "a (b) \\ ü – 日");
+        assert_eq!(out, b"(a \\(b\\) \\\\ \xfc \x96 ?)");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("/about", 9.0, 100.0), "/about");
+        let long = "/a/very/long/path/that/does/not/fit/in/the/column";
+        let short = truncate(long, 9.0, 100.0);
+        assert!(short.ends_with("..."));
+        assert!(text_width(&short, 9.0) <= 100.0);
+        assert_eq!(text_width("0123", 10.0), text_width("9999", 10.0));
+    }
+
+    #[test]
+    fn test_render_pdf() {
+        let stats = CoreStats {
+            session_count: 1234,
+            hit_count: 5678,
+            locations: vec![CountedItem::new("/pricing".to_string(), 42)],
+            referrers: vec![CountedItem::new(String::new(), 7)],
+            chart_data: ChartData {
+                sessions: vec![1, 2],
+                hits: vec![3, 4],
+                labels: vec!["Jun 14".to_string(), "Jun 15".to_string()],
+            },
+            ..Default::default()
+        };
+        let end = "2024-06-15T12:00:00Z".parse().unwrap();
+        let report = Report {
+            title: "Example (Site)",
+            start: end - chrono::Duration::days(7),
+            end,
+            tz: chrono_tz::Europe::Berlin,
+            stats: &stats,
+            generated_at: end,
+        };
+        assert_eq!(report.filename(), "example-site-2024-06-15.pdf");
+
+        let pdf = report.render_pdf(&I18n::new(Locale::En));
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        assert!(contains(&pdf, b"(Example \\(Site\\)) Tj"));
+        assert!(contains(
+            &pdf,
+            b"(2024-06-08 14:00 to 2024-06-15 14:00 \\(Europe/Berlin\\)) Tj"
+        ));
+        assert!(contains(&pdf, b"(1,234) Tj"));
+        assert!(contains(&pdf, b"(/pricing) Tj"));
+        assert!(contains(&pdf, b"(Direct) Tj"));
+
+        // The cross-reference table points at each object
+        let text = String::from_utf8_lossy(&pdf);
+        let startxref = text.rsplit("startxref\n").next().unwrap();
+        let xref: usize = startxref.lines().next().unwrap().parse().unwrap();
+        assert!(pdf[xref..].starts_with(b"xref\n0 8\n"));
+        let table = String::from_utf8_lossy(&pdf[xref..]);
+        for (i, line) in table.lines().skip(3).take(7).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            let header = format!("{} 0 obj\n", i + 1);
+            assert!(pdf[offset..].starts_with(header.as_bytes()));
+        }
+    }
+}
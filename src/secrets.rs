@@ -0,0 +1,145 @@
+//! Secret settings from outside the plain environment. Any setting can be
+//! read from a file named by `SHYMINI__<NAME>_FILE`, as Docker and
+//! Kubernetes mount secrets, and with `vault_addr` set the settings in a
+//! Vault KV secret are read at startup, under the names they have in the
+//! environment without the prefix (e.g. `secret_key`, `smtp_url`). The
+//! environment and files win over Vault.
+
+use std::collections::HashMap;
+
+use config::ConfigError;
+use serde_json::Value;
+
+use crate::monitor;
+
+const PREFIX: &str = "SHYMINI__";
+const FILE_SUFFIX: &str = "_FILE";
+
+/// Settings read from the files the `*_FILE` variables among `vars` name,
+/// by their lowercase names. A setting may not be given both ways.
+pub fn secret_files(
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<(String, String)>, ConfigError> {
+    let vars: HashMap<String, String> = vars.into_iter().collect();
+    let mut secrets = Vec::new();
+    for (var, path) in &vars {
+        let Some(name) = var
+            .strip_prefix(PREFIX)
+            .and_then(|name| name.strip_suffix(FILE_SUFFIX))
+        else {
+            continue;
+        };
+        if vars.contains_key(&format!("{}{}", PREFIX, name)) {
+            return Err(ConfigError::Message(format!(
+                "Both {}{} and {} are set",
+                PREFIX, name, var
+            )));
+        }
+        let value = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Message(format!("Can't read {} ({}): {}", var, path, e)))?;
+        // Files written by editors and `echo` end in a newline
+        let value = value.trim_end_matches(['\n', '\r']).to_string();
+        secrets.push((name.to_lowercase(), value));
+    }
+    Ok(secrets)
+}
+
+/// Settings in the Vault KV secret at `path`, by their lowercase names
+pub async fn vault_secrets(
+    addr: &str,
+    token: &str,
+    path: &str,
+) -> Result<HashMap<String, String>, ConfigError> {
+    let url = format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    let vault_error = |e: &dyn std::fmt::Display| {
+        ConfigError::Message(format!("Can't read secrets from Vault at {}: {}", url, e))
+    };
+    let response = monitor::client()
+        .get(&url)
+        .header("X-Vault-Token", token)
+        .send()
+        .await
+        .map_err(|e| vault_error(&e))?;
+    if !response.status().is_success() {
+        return Err(vault_error(&response.status()));
+    }
+    let body: Value = response.json().await.map_err(|e| vault_error(&e))?;
+    Ok(vault_values(&body))
+}
+
+/// The values of a KV secret, from a version 2 engine's `data.data` or a
+/// version 1 engine's `data`
+fn vault_values(body: &Value) -> HashMap<String, String> {
+    let data = &body["data"];
+    let values = match data.get("metadata") {
+        Some(_) => &data["data"],
+        None => data,
+    };
+    values
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Number(_) | Value::Bool(_) => value.to_string(),
+                _ => return None,
+            };
+            Some((name.to_lowercase(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_secret_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "s3cret").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let secrets = secret_files([
+            var("SHYMINI__SECRET_KEY_FILE", path),
+            var("SHYMINI__PORT", "8080"),
+            var("OTHER_FILE", "/nowhere"),
+        ])
+        .unwrap();
+        assert_eq!(secrets, vec![var("secret_key", "s3cret")]);
+
+        assert!(secret_files([
+            var("SHYMINI__SECRET_KEY_FILE", path),
+            var("SHYMINI__SECRET_KEY", "plain"),
+        ])
+        .is_err());
+        assert!(secret_files([var("SHYMINI__SMTP_URL_FILE", "/nowhere/smtp")]).is_err());
+    }
+
+    #[test]
+    fn test_vault_values() {
+        let v2 = serde_json::json!({
+            "data": {
+                "data": {"SECRET_KEY": "s3cret", "smtp_url": "smtp://mail", "port": 8080, "nested": {}},
+                "metadata": {"version": 3}
+            }
+        });
+        let values = vault_values(&v2);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values["secret_key"], "s3cret");
+        assert_eq!(values["port"], "8080");
+
+        let v1 = serde_json::json!({"data": {"secret_key": "s3cret"}});
+        assert_eq!(vault_values(&v1)["secret_key"], "s3cret");
+        assert!(vault_values(&serde_json::json!({})).is_empty());
+    }
+}
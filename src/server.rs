@@ -0,0 +1,487 @@
+//! Where the server listens: TCP on `host`/`port` by default, or what
+//! `listen` names, either an address or `unix:/path` for a Unix socket a
+//! reverse proxy on the same machine connects to without going through TCP.
+//! Who may connect to the socket is up to its permissions (`listen_mode`),
+//! so connections over it count as coming from loopback. Connections speak
+//! HTTP/1 or HTTP/2 without TLS (h2c), whichever the client starts with.
+//! Unix sockets are only there on Unix systems.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tower::Service;
+use tracing::{debug, info, warn};
+
+use crate::config::Settings;
+
+/// A `listen` setting
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl TryFrom<String> for ListenAddr {
+    type Error = String;
+
+    fn try_from(listen: String) -> Result<Self, Self::Error> {
+        let listen = listen.trim();
+        if let Some(path) = listen.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("listen has no socket path after unix:".to_string());
+            }
+            #[cfg(unix)]
+            return Ok(Self::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(format!(
+                "listen can't be a Unix socket on this system: unix:{}",
+                path
+            ));
+        }
+        listen
+            .parse()
+            .map(Self::Tcp)
+            .map_err(|_| format!("listen is neither an address nor unix:/path: {}", listen))
+    }
+}
+
+impl ListenAddr {
+    /// `listen`, or else `host` and `port`
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings.listen.clone().unwrap_or_else(|| {
+            Self::Tcp(SocketAddr::new(
+                settings
+                    .host
+                    .parse()
+                    .unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
+                settings.port,
+            ))
+        })
+    }
+}
+
+/// Permissions of the Unix socket, given in octal like `chmod`'s
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SocketMode(pub u32);
+
+#[cfg(unix)]
+impl TryFrom<String> for SocketMode {
+    type Error = String;
+
+    fn try_from(mode: String) -> Result<Self, Self::Error> {
+        let digits = mode.trim().trim_start_matches("0o");
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(Self(mode)),
+            _ => Err(format!(
+                "listen_mode isn't an octal mode like 660: {}",
+                mode
+            )),
+        }
+    }
+}
+
+/// A bound socket to serve on
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        /// The socket's file, removed on shutdown when this process created
+        /// it rather than inheriting it from systemd
+        bound_path: Option<PathBuf>,
+    },
+}
+
+impl Listener {
+    pub async fn bind(addr: &ListenAddr, #[cfg(unix)] mode: SocketMode) -> io::Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                info!("Starting server on {}", addr);
+                Ok(Self::Tcp(TcpListener::bind(addr).await?))
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.0))?;
+                info!("Starting server on unix:{}", path.display());
+                Ok(Self::Unix {
+                    listener,
+                    bound_path: Some(path.clone()),
+                })
+            }
+        }
+    }
+}
+
+/// Remove the socket a previous run left behind, unless something still
+/// listens on it
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)
+        }
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// How connections are served
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub keep_alive: bool,
+    /// How long a connection may wait for a request's headers, idle
+    /// between requests included
+    pub keep_alive_timeout: Option<Duration>,
+    pub max_connections: Option<usize>,
+    /// HTTP/2 streams a connection may have open at once
+    pub max_concurrent_streams: Option<u32>,
+}
+
+impl ServeOptions {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            keep_alive: settings.keep_alive,
+            keep_alive_timeout: (settings.keep_alive_timeout_secs > 0)
+                .then(|| Duration::from_secs(settings.keep_alive_timeout_secs)),
+            max_connections: (settings.max_connections > 0).then_some(settings.max_connections),
+            max_concurrent_streams: (settings.max_concurrent_streams > 0)
+                .then_some(settings.max_concurrent_streams),
+        }
+    }
+
+    fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(self.keep_alive)
+            .header_read_timeout(self.keep_alive_timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.max_concurrent_streams);
+        builder
+    }
+}
+
+/// Serve `app` until `signal` completes, then finish the requests in flight
+pub async fn serve<F>(
+    listener: Listener,
+    app: Router,
+    options: ServeOptions,
+    signal: F,
+) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let builder = options.builder();
+    let connections = options
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+
+    // Connections watch for the signal's sender closing, and the server
+    // waits for theirs to close before returning
+    let (signal_tx, signal_rx) = watch::channel(());
+    let signal_tx = Arc::new(signal_tx);
+    tokio::spawn(async move {
+        signal.await;
+        drop(signal_rx);
+    });
+    let (close_tx, close_rx) = watch::channel(());
+
+    loop {
+        let permit = match &connections {
+            Some(connections) => tokio::select! {
+                permit = Arc::clone(connections).acquire_owned() => {
+                    Some(permit.expect("the connection semaphore is never closed"))
+                }
+                _ = signal_tx.closed() => break,
+            },
+            None => None,
+        };
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = signal_tx.closed() => break,
+        };
+        let connection = match accepted {
+            Ok(connection) => connection,
+            Err(e) if is_connection_error(&e) => continue,
+            Err(e) => {
+                // Out of file descriptors, most likely; give some back
+                warn!("Can't accept a connection: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let connection_tasks = ConnectionTasks {
+            builder: builder.clone(),
+            app: app.clone(),
+            signal_tx: Arc::clone(&signal_tx),
+            close_rx: close_rx.clone(),
+            permit,
+        };
+        match connection {
+            Connection::Tcp(stream, peer) => {
+                // Responses to beacons are small; don't hold them back
+                let _ = stream.set_nodelay(true);
+                connection_tasks.spawn(stream, peer);
+            }
+            // Who may connect is up to the socket's permissions, like
+            // loopback for TCP
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                connection_tasks.spawn(stream, SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            }
+        }
+    }
+
+    drop(close_rx);
+    #[cfg(unix)]
+    if let Listener::Unix {
+        bound_path: Some(path),
+        ..
+    } = &listener
+    {
+        let _ = std::fs::remove_file(path);
+    }
+    drop(listener);
+    close_tx.closed().await;
+    Ok(())
+}
+
+enum Connection {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok(Connection::Tcp(stream, peer))
+            }
+            #[cfg(unix)]
+            Self::Unix { listener, .. } => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Connection::Unix(stream))
+            }
+        }
+    }
+}
+
+/// Errors about one connection, which don't keep others from being accepted
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// What serving a connection needs from the server
+struct ConnectionTasks {
+    builder: auto::Builder<TokioExecutor>,
+    app: Router,
+    signal_tx: Arc<watch::Sender<()>>,
+    close_rx: watch::Receiver<()>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionTasks {
+    fn spawn<S>(self, stream: S, peer: SocketAddr)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let Self {
+            builder,
+            app,
+            signal_tx,
+            close_rx,
+            permit,
+        } = self;
+        // The peer address lets proxy logins be limited to trusted networks
+        let service = hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(peer));
+            let mut app = app.clone();
+            async move { Ok::<_, Infallible>(app.call(request).await.unwrap_or_else(|e| match e {})) }
+        });
+        tokio::spawn(async move {
+            // Upgrades are needed for the live feed's websockets
+            let conn = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(conn);
+            let mut closing = false;
+            loop {
+                tokio::select! {
+                    served = conn.as_mut() => {
+                        if let Err(e) = served {
+                            debug!("Connection from {} failed: {}", peer, e);
+                        }
+                        break;
+                    }
+                    _ = signal_tx.closed(), if !closing => {
+                        conn.as_mut().graceful_shutdown();
+                        closing = true;
+                    }
+                }
+            }
+            drop(permit);
+            drop(close_rx);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_addr() {
+        #[cfg(unix)]
+        assert_eq!(
+            ListenAddr::try_from("unix:/run/shymini.sock".to_string()),
+            Ok(ListenAddr::Unix(PathBuf::from("/run/shymini.sock")))
+        );
+        #[cfg(not(unix))]
+        assert!(ListenAddr::try_from("unix:/run/shymini.sock".to_string()).is_err());
+        assert_eq!(
+            ListenAddr::try_from("127.0.0.1:8080".to_string()),
+            Ok(ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 8080))))
+        );
+        assert!(matches!(
+            ListenAddr::try_from("[::1]:8080".to_string()),
+            Ok(ListenAddr::Tcp(_))
+        ));
+        assert!(ListenAddr::try_from("unix:".to_string()).is_err());
+        assert!(ListenAddr::try_from("/run/shymini.sock".to_string()).is_err());
+        assert!(ListenAddr::try_from("localhost".to_string()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_mode() {
+        assert_eq!(
+            SocketMode::try_from("660".to_string()),
+            Ok(SocketMode(0o660))
+        );
+        assert_eq!(
+            SocketMode::try_from("0660".to_string()),
+            Ok(SocketMode(0o660))
+        );
+        assert_eq!(
+            SocketMode::try_from("0o600".to_string()),
+            Ok(SocketMode(0o600))
+        );
+        assert!(SocketMode::try_from("680".to_string()).is_err());
+        assert!(SocketMode::try_from("7777".to_string()).is_err());
+        assert!(SocketMode::try_from("rw".to_string()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shymini.sock");
+        remove_stale_socket(&path).unwrap();
+
+        // Left behind by a run that ended
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+
+        let _listening = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(remove_stale_socket(&file).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_http1_and_http2() {
+        use http_body_util::Empty;
+        use hyper::body::Bytes;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        let options = ServeOptions {
+            keep_alive: true,
+            keep_alive_timeout: None,
+            max_connections: None,
+            max_concurrent_streams: Some(10),
+        };
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(Listener::Tcp(listener), app, options, async {
+            let _ = stop_rx.await;
+        }));
+
+        let request = || {
+            hyper::Request::get(format!("http://{}/", addr))
+                .body(Empty::<Bytes>::new())
+                .unwrap()
+        };
+
+        let stream = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(stream).await.unwrap();
+        tokio::spawn(conn);
+        let response = sender.send_request(request()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.version(), hyper::Version::HTTP_11);
+
+        // Prior knowledge, as h2c clients and proxies speak it
+        let stream = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+        let (mut sender, conn) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), stream)
+                .await
+                .unwrap();
+        tokio::spawn(conn);
+        let response = sender.send_request(request()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.version(), hyper::Version::HTTP_2);
+
+        drop(sender);
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
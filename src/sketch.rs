@@ -0,0 +1,231 @@
+//! Distinct visitor counts kept per service, day and environment, so the
+//! visitors of any range of days can be estimated without reading its
+//! sessions. A day's sketch lists its visitors' hashes exactly until there
+//! are `SPARSE_LIMIT` of them, then turns into a HyperLogLog of
+//! `REGISTERS` one-byte registers, which estimates within about 2%. Both
+//! forms are at most about 4 KB, and sketches of different days merge, so a
+//! visitor seen on several days of a range is counted once.
+
+/// Visitors a sketch lists exactly before it turns into a HyperLogLog
+pub const SPARSE_LIMIT: usize = 512;
+/// Bits of a hash that pick its register
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+const SPARSE_TAG: u8 = 0;
+const DENSE_TAG: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VisitorSketch {
+    /// Sorted hashes of every visitor
+    Sparse(Vec<u64>),
+    /// HyperLogLog registers
+    Dense(Box<[u8; REGISTERS]>),
+}
+
+impl Default for VisitorSketch {
+    fn default() -> Self {
+        Self::Sparse(Vec::new())
+    }
+}
+
+impl VisitorSketch {
+    /// Add a visitor's hash, returning whether the sketch changed
+    pub fn insert(&mut self, hash: u64) -> bool {
+        match self {
+            Self::Sparse(hashes) => match hashes.binary_search(&hash) {
+                Ok(_) => false,
+                Err(i) => {
+                    hashes.insert(i, hash);
+                    if hashes.len() > SPARSE_LIMIT {
+                        *self = Self::Dense(dense(hashes));
+                    }
+                    true
+                }
+            },
+            Self::Dense(registers) => set_register(registers, hash),
+        }
+    }
+
+    /// Add the visitors of another sketch
+    pub fn merge(&mut self, other: &VisitorSketch) {
+        match (&mut *self, other) {
+            (Self::Dense(registers), Self::Dense(others)) => {
+                for (register, other) in registers.iter_mut().zip(others.iter()) {
+                    *register = (*register).max(*other);
+                }
+            }
+            (Self::Sparse(_), Self::Dense(others)) => {
+                let mut merged = others.clone();
+                if let Self::Sparse(hashes) = self {
+                    for &hash in hashes.iter() {
+                        set_register(&mut merged, hash);
+                    }
+                }
+                *self = Self::Dense(merged);
+            }
+            (_, Self::Sparse(hashes)) => {
+                for &hash in hashes {
+                    self.insert(hash);
+                }
+            }
+        }
+    }
+
+    /// Number of distinct visitors: exact while sparse, estimated once dense
+    pub fn estimate(&self) -> i64 {
+        match self {
+            Self::Sparse(hashes) => hashes.len() as i64,
+            Self::Dense(registers) => {
+                let m = REGISTERS as f64;
+                let alpha = 0.7213 / (1.0 + 1.079 / m);
+                let sum: f64 = registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+                let raw = alpha * m * m / sum;
+                let empty = registers.iter().filter(|&&r| r == 0).count();
+                // Few visitors leave many registers empty, which counts better
+                let estimate = if raw <= 2.5 * m && empty > 0 {
+                    m * (m / empty as f64).ln()
+                } else {
+                    raw
+                };
+                estimate.round() as i64
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Sparse(hashes) => {
+                let mut bytes = Vec::with_capacity(1 + hashes.len() * 8);
+                bytes.push(SPARSE_TAG);
+                for hash in hashes {
+                    bytes.extend_from_slice(&hash.to_be_bytes());
+                }
+                bytes
+            }
+            Self::Dense(registers) => {
+                let mut bytes = Vec::with_capacity(1 + REGISTERS);
+                bytes.push(DENSE_TAG);
+                bytes.extend_from_slice(&registers[..]);
+                bytes
+            }
+        }
+    }
+
+    /// Read a sketch written by `to_bytes`; `None` if it is malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&tag, rest) = bytes.split_first()?;
+        match tag {
+            SPARSE_TAG if rest.len() % 8 == 0 => {
+                let hashes: Vec<u64> = rest
+                    .chunks_exact(8)
+                    .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+                    .collect();
+                hashes
+                    .windows(2)
+                    .all(|pair| pair[0] < pair[1])
+                    .then_some(Self::Sparse(hashes))
+            }
+            DENSE_TAG => {
+                let registers: [u8; REGISTERS] = rest.try_into().ok()?;
+                Some(Self::Dense(Box::new(registers)))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn dense(hashes: &[u64]) -> Box<[u8; REGISTERS]> {
+    let mut registers = Box::new([0; REGISTERS]);
+    for &hash in hashes {
+        set_register(&mut registers, hash);
+    }
+    registers
+}
+
+/// Record a hash in the register its top bits pick, returning whether the
+/// register grew
+fn set_register(registers: &mut [u8; REGISTERS], hash: u64) -> bool {
+    let index = (hash >> (64 - PRECISION)) as usize;
+    // Position of the first set bit after the index bits
+    let rank = ((hash << PRECISION).leading_zeros().min(64 - PRECISION) + 1) as u8;
+    if rank > registers[index] {
+        registers[index] = rank;
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    /// Well spread hashes, as visitor hashes are
+    fn hash(i: u64) -> u64 {
+        let digest = Sha256::digest(i.to_be_bytes());
+        u64::from_be_bytes(digest[..8].try_into().unwrap())
+    }
+
+    fn sketch(visitors: std::ops::Range<u64>) -> VisitorSketch {
+        let mut sketch = VisitorSketch::default();
+        for i in visitors {
+            sketch.insert(hash(i));
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_exact_below_the_limit() {
+        let mut sketch = sketch(0..SPARSE_LIMIT as u64);
+        assert!(matches!(sketch, VisitorSketch::Sparse(_)));
+        assert_eq!(sketch.estimate(), SPARSE_LIMIT as i64);
+        assert!(!sketch.insert(hash(3)));
+
+        assert!(sketch.insert(hash(SPARSE_LIMIT as u64)));
+        assert!(matches!(sketch, VisitorSketch::Dense(_)));
+    }
+
+    #[test]
+    fn test_estimates_within_a_few_percent() {
+        for visitors in [1_000, 20_000, 300_000] {
+            let estimate = sketch(0..visitors).estimate() as f64;
+            let error = (estimate - visitors as f64).abs() / visitors as f64;
+            assert!(
+                error < 0.05,
+                "{} visitors estimated as {}",
+                visitors,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_counts_shared_visitors_once() {
+        let mut merged = sketch(0..300);
+        merged.merge(&sketch(200..400));
+        assert_eq!(merged, sketch(0..400));
+
+        // Sparse into dense, dense into sparse and dense into dense
+        let mut merged = sketch(0..100);
+        merged.merge(&sketch(0..5_000));
+        assert_eq!(merged, sketch(0..5_000));
+        let mut merged = sketch(0..5_000);
+        merged.merge(&sketch(4_000..6_000));
+        assert_eq!(merged, sketch(0..6_000));
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        for sketch in [sketch(0..0), sketch(0..100), sketch(0..5_000)] {
+            assert_eq!(VisitorSketch::from_bytes(&sketch.to_bytes()), Some(sketch));
+        }
+        assert_eq!(sketch(0..5_000).to_bytes().len(), 1 + REGISTERS);
+
+        assert_eq!(VisitorSketch::from_bytes(&[]), None);
+        assert_eq!(VisitorSketch::from_bytes(&[SPARSE_TAG, 1, 2]), None);
+        assert_eq!(VisitorSketch::from_bytes(&[DENSE_TAG, 1]), None);
+        assert_eq!(VisitorSketch::from_bytes(&[7]), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Referrer spam: sites sending fake visits so their domain shows up among
+//! the referrers. Page views referred from a domain on the list (or one of
+//! its subdomains) are dropped or flagged, per `referrer_spam`. The list
+//! bundled in `assets/referrer_spam.txt` is used until, with
+//! `referrer_spam_list_url` set, a fresh one is fetched once a day.
+
+use std::collections::HashSet;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+use url::Url;
+
+use crate::domain::BackgroundTask;
+use crate::error::{Error, Result};
+use crate::monitor;
+use crate::state::AppState;
+
+const BUNDLED: &str = include_str!("../assets/referrer_spam.txt");
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The spam domains in use, shared through `AppState`
+pub struct ReferrerSpamList {
+    domains: RwLock<HashSet<String>>,
+}
+
+impl Default for ReferrerSpamList {
+    fn default() -> Self {
+        Self {
+            domains: RwLock::new(parse(BUNDLED)),
+        }
+    }
+}
+
+impl ReferrerSpamList {
+    /// Whether `referrer`, a URL, comes from a spam domain
+    pub fn is_spam(&self, referrer: &str) -> bool {
+        let Some(host) = Url::parse(referrer.trim())
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+        else {
+            return false;
+        };
+        let domains = self.domains.read().unwrap_or_else(|e| e.into_inner());
+        // The host itself or any domain it is under
+        let mut rest = host.as_str();
+        loop {
+            if domains.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => rest = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn replace(&self, domains: HashSet<String>) {
+        *self.domains.write().unwrap_or_else(|e| e.into_inner()) = domains;
+    }
+}
+
+/// Domains of a list with one per line; blank lines and `#` comments are
+/// skipped
+pub fn parse(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|domain| domain.trim_end_matches('.').to_lowercase())
+        .collect()
+}
+
+/// Fetch the list at `url`. An empty list is refused, as it more likely
+/// means a broken download than that spam is over.
+pub async fn fetch(url: &str, client: &reqwest::Client) -> Result<HashSet<String>> {
+    let list = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| Error::Internal(format!("Referrer spam list fetch failed: {}", e)))?
+        .text()
+        .await
+        .map_err(|e| Error::Internal(format!("Unreadable referrer spam list: {}", e)))?;
+    let domains = parse(&list);
+    if domains.is_empty() {
+        return Err(Error::Internal(format!(
+            "The referrer spam list at {} is empty",
+            url
+        )));
+    }
+    Ok(domains)
+}
+
+/// Refresh the list once a day, if `referrer_spam_list_url` is set
+pub fn spawn(state: AppState) {
+    if state.settings.referrer_spam_list_url.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = monitor::client();
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = fetch(&state.settings.referrer_spam_list_url, &client).await;
+            state
+                .tasks
+                .record(BackgroundTask::ReferrerSpam, state.clock.now(), &result);
+            match result {
+                Ok(domains) => {
+                    info!("Referrer spam list refreshed: {} domains", domains.len());
+                    state.referrer_spam.replace(domains);
+                }
+                // The list in use stays
+                Err(e) => error!("{}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_list() {
+        let list = ReferrerSpamList::default();
+        assert!(list.len() > 10);
+        assert!(list.is_spam("https://semalt.com/"));
+        assert!(list.is_spam("http://www.Semalt.com/crawler?x=1"));
+        assert!(list.is_spam("https://forum.buttons-for-website.com/"));
+        assert!(!list.is_spam("https://notsemalt.com/"));
+        assert!(!list.is_spam("https://news.example/"));
+        assert!(!list.is_spam(""));
+        assert!(!list.is_spam("semalt.com"));
+    }
+
+    #[test]
+    fn test_parse_and_replace() {
+        let domains = parse("# comment\nSpam.Example.\n\n  other.test  # trailing\n");
+        assert_eq!(
+            domains,
+            HashSet::from(["spam.example".to_string(), "other.test".to_string()])
+        );
+
+        let list = ReferrerSpamList::default();
+        list.replace(domains);
+        assert!(list.is_spam("https://a.spam.example/"));
+        assert!(!list.is_spam("https://semalt.com/"));
+    }
+}
@@ -1,9 +1,26 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+
+use crate::auth::oidc::OidcClient;
+use crate::auth::proxy::ProxyAuth;
+use crate::auth::SigningKey;
+use crate::bots::{BotVerifier, HostResolver};
 use crate::cache::AppCache;
+use crate::clock::{Clock, SystemClock};
 use crate::config::Settings;
 use crate::db::Pool;
+use crate::domain::Session;
 use crate::geo::GeoIpLookup;
+use crate::hooks::Hooks;
+use crate::ingress::{IdempotencyFilter, IngressDebugLog};
+use crate::live::LiveFeed;
+use crate::mailer::Mailer;
+use crate::privacy::ip_cipher::IpCipher;
+use crate::realtime::Realtime;
+use crate::spam::ReferrerSpamList;
+use crate::status::{OriginMismatchCounter, TaskRegistry};
+use crate::updates::UpdateCheck;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -11,15 +28,105 @@ pub struct AppState {
     pub cache: AppCache,
     pub settings: Arc<Settings>,
     pub geo: Arc<GeoIpLookup>,
+    pub mailer: Arc<Mailer>,
+    pub signing_key: Arc<SigningKey>,
+    /// Idempotency keys of today and yesterday, for when the cache misses
+    pub hit_filter: Arc<IdempotencyFilter>,
+    /// Single sign-on provider, if configured
+    pub oidc: Option<Arc<OidcClient>>,
+    /// Authenticating reverse proxy, if configured
+    pub proxy_auth: Option<Arc<ProxyAuth>>,
+    /// Encryption of stored visitor IPs, if a key is configured
+    pub ip_cipher: Option<Arc<IpCipher>>,
+    /// Source of the current time; the system clock outside of tests
+    pub clock: Arc<dyn Clock>,
+    /// How the background tasks' runs went
+    pub tasks: Arc<TaskRegistry>,
+    /// Latest release found by the update check
+    pub updates: Arc<UpdateCheck>,
+    /// Ingress and stats hooks of the `plugins` module
+    pub hooks: Arc<Hooks>,
+    /// New hits for live subscribers
+    pub live: Arc<LiveFeed>,
+    /// Busiest pages and referrers of the last hour, for live subscribers
+    pub realtime: Arc<Realtime>,
+    /// Referrer spam domains, bundled or last fetched
+    pub referrer_spam: Arc<ReferrerSpamList>,
+    /// Latest ingress decisions of services that keep a debug log
+    pub ingress_log: Arc<IngressDebugLog>,
+    /// Verdicts on visitors claiming to be search engine crawlers
+    pub bots: Arc<BotVerifier>,
+    /// Ingress requests turned away for their origin, per service
+    pub origin_mismatches: Arc<OriginMismatchCounter>,
+    pub started_at: DateTime<Utc>,
 }
 
 impl AppState {
-    pub fn new(pool: Pool, cache: AppCache, settings: Settings, geo: GeoIpLookup) -> Self {
+    pub fn new(
+        pool: Pool,
+        cache: AppCache,
+        settings: Settings,
+        geo: GeoIpLookup,
+        mailer: Mailer,
+    ) -> Self {
+        let signing_key = SigningKey::new(settings.secret_key.as_deref());
+        let oidc = OidcClient::from_settings(&settings).map(Arc::new);
+        let proxy_auth = ProxyAuth::from_settings(&settings).map(Arc::new);
+        let ip_cipher = settings
+            .ip_encryption_key
+            .as_ref()
+            .map(|key| Arc::new(IpCipher::new(key)));
+        let hit_filter = IdempotencyFilter::new(settings.idempotency_filter_capacity);
         Self {
             pool,
             cache,
             settings: Arc::new(settings),
             geo: Arc::new(geo),
+            mailer: Arc::new(mailer),
+            signing_key: Arc::new(signing_key),
+            hit_filter: Arc::new(hit_filter),
+            oidc,
+            proxy_auth,
+            ip_cipher,
+            clock: Arc::new(SystemClock),
+            tasks: Arc::default(),
+            updates: Arc::default(),
+            hooks: Arc::new(Hooks::registered()),
+            live: Arc::default(),
+            realtime: Arc::default(),
+            referrer_spam: Arc::default(),
+            ingress_log: Arc::default(),
+            bots: Arc::default(),
+            origin_mismatches: Arc::default(),
+            started_at: SystemClock.now(),
         }
     }
+
+    /// A session as its viewers see it, with its IP decrypted if it was
+    /// stored encrypted
+    pub fn reveal_ip(&self, mut session: Session) -> Session {
+        if let Some(cipher) = &self.ip_cipher {
+            cipher.reveal(&mut session);
+        }
+        session
+    }
+
+    /// Use other hooks than the registered ones
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = Arc::new(hooks);
+        self
+    }
+
+    /// Check claimed crawlers with another resolver, e.g. a fake one in tests
+    pub fn with_bot_resolver(mut self, resolver: Arc<dyn HostResolver>) -> Self {
+        self.bots = Arc::new(BotVerifier::new(resolver));
+        self
+    }
+
+    /// Use another clock, e.g. a `FakeClock` in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started_at = clock.now();
+        self.clock = clock;
+        self
+    }
 }
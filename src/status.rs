@@ -0,0 +1,253 @@
+//! Health of the running server for the admin status page and
+//! `/api/status`: database size and row counts, cache hit rates, recent
+//! ingestion, how the background tasks last went, what was built and
+//! whether a newer release is out. Task health lives in memory only and
+//! starts over with each restart.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::cache::CacheStats;
+use crate::config::Settings;
+use crate::db;
+use crate::domain::{BackgroundTask, MaintenanceRun, ServiceId};
+use crate::error::Result;
+use crate::state::AppState;
+use crate::updates::Release;
+
+/// Database backend the server was built for
+#[cfg(feature = "postgres")]
+pub const BACKEND: &str = "postgres";
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+pub const BACKEND: &str = "sqlite";
+
+/// How a background task's runs have gone since the server started
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TaskHealth {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    /// Error of the last failed run
+    pub last_error: Option<String>,
+    /// Failed runs in total
+    pub failures: u64,
+}
+
+impl TaskHealth {
+    /// Whether the latest run failed
+    pub fn is_failing(&self) -> bool {
+        self.last_failure > self.last_success
+    }
+}
+
+/// Outcomes of the background tasks' runs, shared with the tasks through
+/// `AppState`
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<HashMap<BackgroundTask, TaskHealth>>,
+}
+
+impl TaskRegistry {
+    /// Record how a run of `task` finished at `now`
+    pub fn record<T>(&self, task: BackgroundTask, now: DateTime<Utc>, result: &Result<T>) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let health = tasks.entry(task).or_default();
+        match result {
+            Ok(_) => health.last_success = Some(now),
+            Err(e) => {
+                health.last_failure = Some(now);
+                health.last_error = Some(e.to_string());
+                health.failures += 1;
+            }
+        }
+    }
+
+    pub fn health(&self, task: BackgroundTask) -> TaskHealth {
+        let tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.get(&task).cloned().unwrap_or_default()
+    }
+}
+
+/// Ingress requests a service turned away for their origin since the server
+/// started
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OriginMismatches {
+    pub service_id: ServiceId,
+    pub count: u64,
+    /// Origin of the latest one, if it named one
+    pub last_origin: Option<String>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Origin mismatches per service, counted by ingress through `AppState`
+#[derive(Default)]
+pub struct OriginMismatchCounter {
+    services: Mutex<HashMap<ServiceId, OriginMismatches>>,
+}
+
+impl OriginMismatchCounter {
+    /// Count a request to a service from an origin it doesn't allow
+    pub fn record(&self, service_id: ServiceId, origin: Option<&str>, now: DateTime<Utc>) {
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let mismatches = services.entry(service_id).or_insert(OriginMismatches {
+            service_id,
+            count: 0,
+            last_origin: None,
+            last_seen: now,
+        });
+        mismatches.count += 1;
+        mismatches.last_origin = origin.map(str::to_string);
+        mismatches.last_seen = now;
+    }
+
+    /// Every service's mismatches, most first
+    pub fn all(&self) -> Vec<OriginMismatches> {
+        let services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let mut all: Vec<_> = services.values().cloned().collect();
+        all.sort_by(|a, b| b.count.cmp(&a.count).then(b.last_seen.cmp(&a.last_seen)));
+        all
+    }
+}
+
+/// Whether the settings have the task run at all
+pub fn is_enabled(task: BackgroundTask, settings: &Settings) -> bool {
+    match task {
+        BackgroundTask::Monitor => settings.monitor_interval_secs > 0,
+        BackgroundTask::Crawler => settings.sitemap_crawl_interval_secs > 0,
+        BackgroundTask::HitFilterFlush => settings.idempotency_filter_capacity > 0,
+        BackgroundTask::TopPages => settings.top_pages_refresh_secs > 0,
+        BackgroundTask::Maintenance => settings.maintenance_hour.is_some(),
+        BackgroundTask::UpdateCheck => settings.update_check,
+        BackgroundTask::HitPartitions => settings.hit_partitions && BACKEND == "postgres",
+        BackgroundTask::ReferrerSpam => !settings.referrer_spam_list_url.is_empty(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableCount {
+    pub name: String,
+    pub rows: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub task: BackgroundTask,
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub health: TaskHealth,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemStatus {
+    pub version: &'static str,
+    /// `sqlite` or `postgres`
+    pub backend: &'static str,
+    /// Whether this is an optimized build
+    pub release_build: bool,
+    /// Latest release found by the update check, if it is on and has run
+    pub latest_release: Option<Release>,
+    /// Whether `latest_release` is newer than this server
+    pub update_available: bool,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: i64,
+    pub database_bytes: i64,
+    pub tables: Vec<TableCount>,
+    pub hits_last_hour: i64,
+    pub sessions_last_hour: i64,
+    pub caches: Vec<CacheStats>,
+    pub tasks: Vec<TaskStatus>,
+    /// Last run of each maintenance task that has run
+    pub maintenance: Vec<MaintenanceRun>,
+    /// Services that turned requests away for their origin, most first
+    pub origin_mismatches: Vec<OriginMismatches>,
+}
+
+/// Gather the server's status
+pub async fn system_status(state: &AppState) -> Result<SystemStatus> {
+    let now = state.clock.now();
+    let latest_release = state.updates.latest();
+    let database_bytes = db::get_database_size(&state.pool).await?;
+    let tables = db::get_table_row_counts(&state.pool)
+        .await?
+        .into_iter()
+        .map(|(name, rows)| TableCount { name, rows })
+        .collect();
+    let (hits_last_hour, sessions_last_hour) =
+        db::count_recent_ingress(&state.pool, now - Duration::hours(1)).await?;
+    let tasks = BackgroundTask::ALL
+        .into_iter()
+        .map(|task| TaskStatus {
+            task,
+            enabled: is_enabled(task, &state.settings),
+            health: state.tasks.health(task),
+        })
+        .collect();
+
+    Ok(SystemStatus {
+        version: env!("CARGO_PKG_VERSION"),
+        backend: BACKEND,
+        release_build: !cfg!(debug_assertions),
+        update_available: latest_release.as_ref().is_some_and(Release::is_newer),
+        latest_release,
+        started_at: state.started_at,
+        uptime_secs: (now - state.started_at).num_seconds().max(0),
+        database_bytes,
+        tables,
+        hits_last_hour,
+        sessions_last_hour,
+        caches: state.cache.stats(),
+        tasks,
+        maintenance: db::list_maintenance_runs(&state.pool).await?,
+        origin_mismatches: state.origin_mismatches.all(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_task_health() {
+        let registry = TaskRegistry::default();
+        let task = BackgroundTask::Crawler;
+        let t1: DateTime<Utc> = "2024-03-10T01:00:00Z".parse().unwrap();
+        let t2 = t1 + Duration::minutes(5);
+        assert_eq!(registry.health(task), TaskHealth::default());
+
+        registry.record(task, t1, &Ok(()));
+        registry.record::<()>(task, t2, &Err(Error::Internal("timed out".to_string())));
+        let health = registry.health(task);
+        assert!(health.is_failing());
+        assert_eq!(health.last_success, Some(t1));
+        assert_eq!(health.failures, 1);
+        assert!(health.last_error.unwrap().contains("timed out"));
+
+        registry.record(task, t2 + Duration::minutes(5), &Ok(()));
+        assert!(!registry.health(task).is_failing());
+        assert_eq!(registry.health(BackgroundTask::Monitor).failures, 0);
+    }
+
+    #[test]
+    fn test_origin_mismatch_counter() {
+        let counter = OriginMismatchCounter::default();
+        let (blog, shop) = (
+            ServiceId(uuid::Uuid::new_v4()),
+            ServiceId(uuid::Uuid::new_v4()),
+        );
+        let t1: DateTime<Utc> = "2024-03-10T01:00:00Z".parse().unwrap();
+        counter.record(shop, Some("https://evil.test"), t1);
+        counter.record(blog, Some("https://staging.blog.test"), t1);
+        counter.record(blog, None, t1 + Duration::minutes(1));
+
+        let all = counter.all();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].service_id, blog);
+        assert_eq!(all[0].count, 2);
+        assert_eq!(all[0].last_origin, None);
+        assert_eq!(all[0].last_seen, t1 + Duration::minutes(1));
+        assert_eq!(all[1].last_origin.as_deref(), Some("https://evil.test"));
+    }
+}
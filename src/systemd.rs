@@ -0,0 +1,208 @@
+//! Running as a systemd service. With socket activation the server listens
+//! on the socket systemd passes it (`LISTEN_FDS`), which stays open across
+//! restarts, so connections made meanwhile wait for the new process instead
+//! of being refused. With `Type=notify` it reports being ready and stopping
+//! (`sd_notify`), and with `WatchdogSec` it pings the watchdog. Outside
+//! systemd none of the variables are set and nothing happens.
+
+use std::env;
+use std::io;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::server::Listener;
+
+/// The descriptor of the first socket systemd passes
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: std::os::unix::io::RawFd = 3;
+
+/// The listening socket systemd passed, if it started the server for one.
+/// The variables passing it are removed, so nothing started later takes
+/// them for its own.
+pub fn inherited_listener() -> io::Result<Option<Listener>> {
+    let fds = passed_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    if fds == 0 {
+        return Ok(None);
+    }
+    if fds > 1 {
+        warn!("systemd passed {} sockets; listening on the first", fds);
+    }
+    first_passed_socket().map(Some)
+}
+
+#[cfg(target_os = "linux")]
+fn first_passed_socket() -> io::Result<Listener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // SAFETY: systemd passes its sockets open from LISTEN_FDS_START on, and
+    // since the variables naming them are gone nothing else takes this one
+    let fd = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+    // Asking a socket for its Unix address fails unless it is a Unix one
+    let unix = std::os::unix::net::UnixListener::from(fd);
+    let listener = match unix.local_addr() {
+        Ok(addr) => {
+            unix.set_nonblocking(true)?;
+            match addr.as_pathname() {
+                Some(path) => info!("Listening on unix:{} from systemd", path.display()),
+                None => info!("Listening on a Unix socket from systemd"),
+            }
+            // The socket's file stays for systemd to listen on across restarts
+            Listener::Unix {
+                listener: tokio::net::UnixListener::from_std(unix)?,
+                bound_path: None,
+            }
+        }
+        Err(_) => {
+            let tcp = std::net::TcpListener::from(OwnedFd::from(unix));
+            let addr = tcp.local_addr()?;
+            tcp.set_nonblocking(true)?;
+            info!("Listening on {} from systemd", addr);
+            Listener::Tcp(tokio::net::TcpListener::from_std(tcp)?)
+        }
+    };
+    Ok(listener)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn first_passed_socket() -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket activation needs Linux",
+    ))
+}
+
+/// How many sockets systemd passed this process: `LISTEN_FDS`, when
+/// `LISTEN_PID` names it rather than a parent the variables leaked from
+fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    match listen_pid.and_then(|p| p.trim().parse::<u32>().ok()) {
+        Some(listen_pid) if listen_pid == pid => {
+            listen_fds.and_then(|n| n.trim().parse().ok()).unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// Tell systemd about a change of state, e.g. `READY=1`, if it asked to be
+/// told (`NOTIFY_SOCKET`)
+pub fn notify(state: &str) {
+    let Ok(socket) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notification(&socket, state) {
+        warn!("Can't notify systemd at {}: {}", socket, e);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_notification(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    // A leading @ names a socket in the abstract namespace
+    let addr = match socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_notification(_socket: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sd_notify needs Linux",
+    ))
+}
+
+/// How often to ping the watchdog systemd set for this process: at half
+/// its timeout, as systemd recommends
+fn watchdog_interval(
+    watchdog_usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+    pid: u32,
+) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid {
+        if watchdog_pid.trim().parse::<u32>().ok() != Some(pid) {
+            return None;
+        }
+    }
+    let usec: u64 = watchdog_usec?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Ping the watchdog while the runtime keeps running tasks, if systemd set
+/// one. A wedged server stops pinging and systemd restarts it.
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    ) else {
+        return;
+    };
+    info!("Pinging the systemd watchdog every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passed_fds() {
+        assert_eq!(passed_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(passed_fds(Some("42"), Some("2"), 42), 2);
+        // Leaked from the process systemd started
+        assert_eq!(passed_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(passed_fds(None, Some("1"), 42), 0);
+        assert_eq!(passed_fds(Some("42"), None, 42), 0);
+        assert_eq!(passed_fds(Some("42"), Some("many"), 42), 0);
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_send_notification() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send_notification(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        assert!(send_notification("/nowhere/notify", "READY=1").is_err());
+    }
+}
@@ -0,0 +1,311 @@
+//! The dashboard's look: logo, accent color and light or dark mode, from the
+//! settings unless the organization sets its own. Every page links
+//! `/static/theme.css`, rendered here from the viewer's [`Theme`], so page
+//! templates don't carry it themselves.
+
+use askama::Template;
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use url::Url;
+
+use crate::auth::Tenant;
+use crate::config::Settings;
+use crate::domain::{Organization, ThemeMode};
+use crate::error::Result;
+use crate::state::AppState;
+
+/// The accent the dashboard was designed around, with its hand-picked shades
+const DEFAULT_ACCENT: &str = "#5cc265";
+const DEFAULT_PALETTE: Palette = Palette {
+    accent_hover: Rgb(0x4a, 0xa8, 0x54),
+    strong: Rgb(0x2c, 0x49, 0x31),
+    strong_hover: Rgb(0x3d, 0x5f, 0x42),
+};
+
+/// A `#rgb` or `#rrggbb` color, with or without the `#`
+pub fn parse_color(s: &str) -> Option<String> {
+    let hex = s.trim().trim_start_matches('#');
+    let valid = matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit());
+    valid.then(|| format!("#{}", hex.to_ascii_lowercase()))
+}
+
+/// An `http(s)` URL or a path on this server, safe to put in a stylesheet
+pub fn parse_logo_url(s: &str) -> Option<String> {
+    let s = s.trim();
+    let allowed = s.starts_with('/')
+        || Url::parse(s).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    let safe = s
+        .chars()
+        .all(|c| c.is_ascii_graphic() && !"\"'\\()<>".contains(c));
+    (allowed && safe).then(|| s.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rgb(u8, u8, u8);
+
+impl Rgb {
+    /// A color parsed by [`parse_color`]
+    fn parse(color: &str) -> Option<Self> {
+        let hex = color.trim_start_matches('#');
+        let hex = if hex.len() == 3 {
+            hex.chars().flat_map(|c| [c, c]).collect()
+        } else {
+            hex.to_string()
+        };
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Self(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// Each channel scaled by `factor`: darker below 1, lighter above
+    fn scale(self, factor: f64) -> Self {
+        let channel = |c: u8| (c as f64 * factor).round().clamp(0.0, 255.0) as u8;
+        Self(channel(self.0), channel(self.1), channel(self.2))
+    }
+
+    fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// Shades derived from the accent
+struct Palette {
+    accent_hover: Rgb,
+    /// Headings, navigation and buttons
+    strong: Rgb,
+    strong_hover: Rgb,
+}
+
+impl Palette {
+    fn new(accent: Rgb, mode: ThemeMode) -> Self {
+        match mode {
+            // Dark pages need headings lighter than the accent, not darker
+            ThemeMode::Dark => Self {
+                accent_hover: accent.scale(1.15),
+                strong: accent,
+                strong_hover: accent.scale(0.85),
+            },
+            ThemeMode::Light if accent.hex() == DEFAULT_ACCENT => DEFAULT_PALETTE,
+            ThemeMode::Light => Self {
+                accent_hover: accent.scale(0.85),
+                strong: accent.scale(0.45),
+                strong_hover: accent.scale(0.6),
+            },
+        }
+    }
+}
+
+/// What the dashboard looks like to one organization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// Shown in place of the name in the navigation bar; empty for the name
+    pub logo_url: String,
+    pub accent: String,
+    pub accent_hover: String,
+    pub strong: String,
+    pub strong_hover: String,
+    /// The accent's red, green and blue, for translucent fills
+    pub accent_rgb: String,
+    pub mode: ThemeMode,
+}
+
+impl Theme {
+    /// The organization's theme, each part falling back to the settings';
+    /// the settings' alone without an organization, e.g. on the login page
+    pub fn new(settings: &Settings, organization: Option<&Organization>) -> Self {
+        Self::resolve(
+            settings.theme_logo_url.as_deref().unwrap_or_default(),
+            &settings.theme_accent,
+            settings.theme_mode,
+            organization,
+        )
+    }
+
+    fn resolve(
+        logo_url: &str,
+        accent: &str,
+        mode: ThemeMode,
+        organization: Option<&Organization>,
+    ) -> Self {
+        let logo_url = organization
+            .and_then(|o| parse_logo_url(&o.logo_url))
+            .or_else(|| parse_logo_url(logo_url))
+            .unwrap_or_default();
+        let accent = organization
+            .and_then(|o| Rgb::parse(&parse_color(&o.accent_color)?))
+            .or_else(|| Rgb::parse(&parse_color(accent)?))
+            .or_else(|| Rgb::parse(DEFAULT_ACCENT))
+            .unwrap_or(Rgb(0, 0, 0));
+        let mode = organization.and_then(|o| o.theme_mode).unwrap_or(mode);
+        let palette = Palette::new(accent, mode);
+
+        Self {
+            logo_url,
+            accent: accent.hex(),
+            accent_hover: palette.accent_hover.hex(),
+            strong: palette.strong.hex(),
+            strong_hover: palette.strong_hover.hex(),
+            accent_rgb: format!("{}, {}, {}", accent.0, accent.1, accent.2),
+            mode,
+        }
+    }
+
+    pub fn dark(&self) -> bool {
+        self.mode == ThemeMode::Dark
+    }
+
+    /// Labels and other secondary text
+    pub fn text_secondary(&self) -> &'static str {
+        if self.dark() {
+            "#b3b8b4"
+        } else {
+            "#666"
+        }
+    }
+
+    /// Borders and grid lines
+    pub fn border(&self) -> &'static str {
+        if self.dark() {
+            "#3a403c"
+        } else {
+            "#e0e0e0"
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "theme/theme.css", escape = "none")]
+pub struct ThemeCssTemplate {
+    pub theme: Theme,
+}
+
+/// GET /static/theme.css
+pub async fn theme_css(State(state): State<AppState>, tenant: Option<Tenant>) -> Result<Response> {
+    let theme = Theme::new(
+        &state.settings,
+        tenant.as_ref().map(|tenant| &tenant.organization),
+    );
+    let css = ThemeCssTemplate { theme }.render()?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/css; charset=utf-8"),
+            // Differs per organization and changes with its settings
+            (header::CACHE_CONTROL, "private, no-cache"),
+        ],
+        css,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn organization() -> Organization {
+        Organization {
+            id: crate::domain::OrganizationId::new(),
+            name: "Acme".to_string(),
+            slug: "acme".to_string(),
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            logo_url: String::new(),
+            accent_color: String::new(),
+            theme_mode: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#FF8800"), Some("#ff8800".to_string()));
+        assert_eq!(parse_color("f80"), Some("#f80".to_string()));
+        assert_eq!(parse_color("red"), None);
+        assert_eq!(parse_color("#ff880"), None);
+        assert_eq!(parse_color("#ff8800;}body{"), None);
+        assert_eq!(parse_color(""), None);
+    }
+
+    #[test]
+    fn test_parse_logo_url() {
+        assert_eq!(
+            parse_logo_url(" https://example.com/logo.svg "),
+            Some("https://example.com/logo.svg".to_string())
+        );
+        assert_eq!(
+            parse_logo_url("/assets/logo.png"),
+            Some("/assets/logo.png".to_string())
+        );
+        assert_eq!(parse_logo_url("javascript:alert(1)"), None);
+        assert_eq!(parse_logo_url("logo.png"), None);
+        assert_eq!(parse_logo_url("/logo.png\");}body{"), None);
+        assert_eq!(parse_logo_url("/my logo.png"), None);
+        assert_eq!(parse_logo_url(""), None);
+    }
+
+    #[test]
+    fn test_rgb() {
+        assert_eq!(Rgb::parse("#f80"), Some(Rgb(0xff, 0x88, 0x00)));
+        assert_eq!(Rgb::parse("#102030").map(Rgb::hex), Some("#102030".into()));
+        assert_eq!(Rgb(100, 200, 250).scale(0.5), Rgb(50, 100, 125));
+        assert_eq!(Rgb(100, 200, 250).scale(2.0), Rgb(200, 255, 255));
+    }
+
+    #[test]
+    fn test_theme_defaults() {
+        let theme = Theme::resolve("", DEFAULT_ACCENT, ThemeMode::Light, None);
+        assert_eq!(theme.logo_url, "");
+        assert_eq!(theme.accent, "#5cc265");
+        assert_eq!(theme.accent_hover, "#4aa854");
+        assert_eq!(theme.strong, "#2c4931");
+        assert_eq!(theme.accent_rgb, "92, 194, 101");
+        assert!(!theme.dark());
+
+        // An organization without overrides looks like the install
+        let org = organization();
+        assert_eq!(
+            Theme::resolve("", DEFAULT_ACCENT, ThemeMode::Light, Some(&org)),
+            theme
+        );
+    }
+
+    #[test]
+    fn test_theme_overrides() {
+        let theme = Theme::resolve("/logo.svg", "#336699", ThemeMode::Dark, None);
+        assert_eq!(theme.logo_url, "/logo.svg");
+        assert_eq!(theme.accent, "#336699");
+        assert_eq!(theme.strong, "#336699");
+        assert!(theme.dark());
+
+        let mut org = organization();
+        org.logo_url = "https://acme.example/logo.png".to_string();
+        org.accent_color = "#C00".to_string();
+        org.theme_mode = Some(ThemeMode::Light);
+        let theme = Theme::resolve("/logo.svg", "#336699", ThemeMode::Dark, Some(&org));
+        assert_eq!(theme.logo_url, "https://acme.example/logo.png");
+        assert_eq!(theme.accent, "#cc0000");
+        assert_eq!(theme.accent_hover, "#ad0000");
+        assert!(!theme.dark());
+
+        // Invalid overrides fall back to the settings
+        org.logo_url = "javascript:alert(1)".to_string();
+        org.accent_color = "crimson".to_string();
+        let theme = Theme::resolve("/logo.svg", "#336699", ThemeMode::Dark, Some(&org));
+        assert_eq!(theme.logo_url, "/logo.svg");
+        assert_eq!(theme.accent, "#336699");
+    }
+
+    #[test]
+    fn test_theme_css() {
+        let css = ThemeCssTemplate {
+            theme: Theme::resolve("/logo.svg", DEFAULT_ACCENT, ThemeMode::Dark, None),
+        }
+        .render()
+        .unwrap();
+        assert!(css.contains("--color-accent: #5cc265;"));
+        assert!(css.contains("url(\"/logo.svg\")"));
+        assert!(css.contains("color-scheme: dark"));
+    }
+}
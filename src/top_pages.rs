@@ -0,0 +1,60 @@
+//! Daily top pages. With `top_pages_refresh_secs` set, each active service's
+//! hits per page are counted once per completed UTC day into
+//! `top_locations_daily`, so the top pages of services with many distinct
+//! URLs don't have to group every hit in the range on each request. Stats
+//! read whole days from that table once all of them are there and count the
+//! rest of the range, like today, live.
+
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+use crate::db;
+use crate::domain::BackgroundTask;
+use crate::error::Result;
+use crate::state::AppState;
+
+/// Days back from today kept materialized
+pub const BACKFILL_DAYS: i64 = 90;
+
+/// Materialize the completed days of the last `BACKFILL_DAYS` that each
+/// active service is missing. Days already done aren't counted again; hits
+/// arriving for them later only show in live counts of partial days.
+pub async fn run_refresh(state: &AppState) -> Result<()> {
+    let today = state.clock.now().date_naive();
+    let first = today - chrono::Duration::days(BACKFILL_DAYS);
+    for service in db::list_active_services(&state.pool).await? {
+        let done = db::list_materialized_days(&state.pool, service.id, first, today).await?;
+        for day in first.iter_days().take_while(|day| *day < today) {
+            if !done.contains(&day) {
+                db::materialize_top_locations(&state.pool, service.id, day).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Refresh in the background every `top_pages_refresh_secs`, if set
+pub fn spawn(state: AppState) {
+    let interval_secs = state.settings.top_pages_refresh_secs;
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = run_refresh(&state).await;
+            state
+                .tasks
+                .record(BackgroundTask::TopPages, state.clock.now(), &result);
+            match result {
+                Ok(()) => info!("Top pages refreshed"),
+                Err(e) => error!("Top pages refresh failed: {}", e),
+            }
+        }
+    });
+}
@@ -1,8 +1,9 @@
+use serde::Serialize;
 use woothee::parser::Parser;
 
 use crate::domain::DeviceType;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct ParsedUserAgent {
     pub browser: String,
     pub device: String,
@@ -52,6 +53,7 @@ pub fn parse_user_agent(user_agent: &str) -> ParsedUserAgent {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_parse_chrome_desktop() {
@@ -151,4 +153,26 @@ mod tests {
             assert!(!parsed.is_bot, "Should not be detected as bot: {}", ua);
         }
     }
+
+    proptest! {
+        #[test]
+        fn prop_parse_any_user_agent(ua in any::<String>()) {
+            let parsed = parse_user_agent(&ua);
+            prop_assert!(!parsed.is_bot || parsed.device_type == DeviceType::Robot);
+        }
+
+        #[test]
+        fn prop_parse_lossy_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            parse_user_agent(&String::from_utf8_lossy(&bytes));
+        }
+
+        #[test]
+        fn prop_parse_browser_like(
+            ua in "Mozilla/5\\.0 \\([ -~]{0,80}\\) [ -~]{0,80}",
+            padding in 0usize..5_000,
+        ) {
+            let parsed = parse_user_agent(&format!("{}{}", ua, " x".repeat(padding)));
+            prop_assert!(!parsed.is_bot || parsed.device_type == DeviceType::Robot);
+        }
+    }
 }
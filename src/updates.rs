@@ -0,0 +1,168 @@
+//! Opt-in check for newer releases. With `update_check` set, the latest
+//! release is fetched from `update_check_url` once a day; when it is newer
+//! than the running version, admins see a banner on the dashboard and
+//! `/api/status` reports it. Nothing is requested while the setting is off.
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+use crate::domain::BackgroundTask;
+use crate::error::{Error, Result};
+use crate::monitor;
+use crate::state::AppState;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A published release
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Release {
+    /// Version without a leading `v`, e.g. `0.3.1`
+    pub version: String,
+    /// Release notes page
+    pub url: String,
+}
+
+impl Release {
+    /// Whether this release is newer than the running server
+    pub fn is_newer(&self) -> bool {
+        match (
+            parse_version(&self.version),
+            parse_version(env!("CARGO_PKG_VERSION")),
+        ) {
+            (Some(latest), Some(running)) => latest > running,
+            _ => false,
+        }
+    }
+}
+
+/// The major, minor and patch numbers of `v1.2.3`, `1.2` or `1.2.3-rc.1`;
+/// missing numbers count as 0 and anything after `-` or `+` is ignored
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let core = version.split(['-', '+']).next()?;
+    let mut numbers = core.split('.').map(|part| part.parse::<u64>().ok());
+    let major = numbers.next()??;
+    let minor = numbers.next().unwrap_or(Some(0))?;
+    let patch = numbers.next().unwrap_or(Some(0))?;
+    Some((major, minor, patch))
+}
+
+/// The latest release found, shared through `AppState`
+#[derive(Default)]
+pub struct UpdateCheck {
+    latest: RwLock<Option<Release>>,
+}
+
+impl UpdateCheck {
+    /// The latest release, if one has been fetched
+    pub fn latest(&self) -> Option<Release> {
+        self.latest
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn set_latest(&self, release: Release) {
+        *self.latest.write().unwrap_or_else(|e| e.into_inner()) = Some(release);
+    }
+
+    /// The latest release, if it is newer than the running server
+    pub fn available(&self) -> Option<Release> {
+        self.latest().filter(Release::is_newer)
+    }
+}
+
+/// The fields of a GitHub release the check reads
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    html_url: String,
+}
+
+/// Fetch the latest release from `update_check_url`
+pub async fn fetch_latest(url: &str, client: &reqwest::Client) -> Result<Release> {
+    let response = client
+        .get(url)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| Error::Internal(format!("Update check failed: {}", e)))?;
+    let release: GithubRelease = response
+        .json()
+        .await
+        .map_err(|e| Error::Internal(format!("Unreadable release: {}", e)))?;
+    Ok(Release {
+        version: release.tag_name.trim_start_matches('v').to_string(),
+        url: release.html_url,
+    })
+}
+
+/// Look for a newer release once a day, if `update_check` is set
+pub fn spawn(state: AppState) {
+    if !state.settings.update_check {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = monitor::client();
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = fetch_latest(&state.settings.update_check_url, &client).await;
+            state
+                .tasks
+                .record(BackgroundTask::UpdateCheck, state.clock.now(), &result);
+            match result {
+                Ok(release) => {
+                    if release.is_newer() {
+                        info!("shymini {} is available: {}", release.version, release.url);
+                    }
+                    state.updates.set_latest(release);
+                }
+                Err(e) => error!("{}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version("0.10"), Some((0, 10, 0)));
+        assert_eq!(parse_version("2.0.0-rc.1"), Some((2, 0, 0)));
+        assert_eq!(parse_version("1.4.0+build.7"), Some((1, 4, 0)));
+        assert_eq!(parse_version("nightly"), None);
+        assert_eq!(parse_version(""), None);
+        assert!(parse_version("0.10.0") > parse_version("0.9.9"));
+    }
+
+    #[test]
+    fn test_available_only_when_newer() {
+        let check = UpdateCheck::default();
+        assert_eq!(check.available(), None);
+
+        check.set_latest(Release {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            url: String::new(),
+        });
+        assert!(check.latest().is_some());
+        assert_eq!(check.available(), None);
+
+        let newer = Release {
+            version: "999.0.0".to_string(),
+            url: "https://github.com/cdaringe/shymini/releases/tag/v999.0.0".to_string(),
+        };
+        check.set_latest(newer.clone());
+        assert_eq!(check.available(), Some(newer));
+    }
+}
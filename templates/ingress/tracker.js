@@ -1,4 +1,11 @@
+{% if module %}
+// The server this module was generated by; init({ origin }) overrides it
+var scriptOrigin = "{{ origin }}";
+var started = false;
+
+{% endif %}
 var shymini = (function() {
+{% if !module %}
   // Capture script origin at load time (before document.currentScript becomes null)
   var scriptSrc = document.currentScript ? document.currentScript.src : "";
   var scriptOrigin = "";
@@ -8,19 +15,173 @@ var shymini = (function() {
   } catch (e) {
     scriptOrigin = "{{ protocol }}://" + window.location.host;
   }
+{% endif %}
+
+  // Failed POSTs are kept in sessionStorage and retried with exponential backoff
+  var queueKey = "shymini_queue:{{ endpoint }}";
+  var maxQueueLength = 50;
+  var minRetryDelay = 1000;
+  var maxRetryDelay = 60000;
 
+  // Without interaction for this long the visitor counts as idle: heartbeats
+  // stop until they scroll, type, click or come back to the tab (0 = never)
+  var idleTimeout = {{ idle_timeout }};
+{% if track_errors %}
+  // Errors reported per page view, at most, and the share of page views
+  // reporting them
+  var maxErrors = 5;
+  var errorSampleRate = {{ error_sample_rate }};
+{% endif %}
+{% if collapse_tabs %}
+  // Multi-tab coordination: only the tab holding the lock sends heartbeats,
+  // and tabs opened on the same page share one idempotency key (one hit)
+  var tabId = Math.random().toString(36).substring(2, 12);
+  var lockKey = "shymini_lock:{{ endpoint }}";
+  var lockTimeout = {{ heartbeat_frequency }} * 2;
+  var channel = null;
+  try {
+    channel = new BroadcastChannel(lockKey);
+  } catch (e) {
+    channel = null;
+  }
+  if (channel) {
+    channel.onmessage = function (event) {
+      // The lock holder went away; a visible tab takes over right away
+      if (event.data && event.data.type === "release" && shymini.idempotency) {
+        shymini.sendHeartbeat();
+      }
+    };
+  }
+{% endif %}
   return {
   dnt: false,
   idempotency: null,
   heartbeatTaskId: null,
   skipHeartbeat: false,
   loadTimeSent: false,
+  flushing: false,
+  retryTaskId: null,
+  retryDelay: minRetryDelay,
+  lastActivity: Date.now(),
+  idle: false,
+  idleSince: null,
+  ended: false,
+{% if track_interaction %}
+  interacted: false,
+  interactionSent: false,
+{% endif %}
+{% if track_errors %}
+  errorsSampled: false,
+  errorsSent: 0,
+  errorsSeen: {},
+{% endif %}
+{% if collapse_tabs %}
+  readLock: function () {
+    try {
+      return JSON.parse(window.localStorage.getItem(lockKey) || "null");
+    } catch (e) {
+      return null;
+    }
+  },
+  writeLock: function () {
+    try {
+      window.localStorage.setItem(lockKey, JSON.stringify({
+        tab: tabId,
+        idempotency: shymini.idempotency,
+        location: window.location.href,
+        ts: Date.now()
+      }));
+    } catch (e) {}
+  },
+  claimLock: function (force) {
+    var lock = shymini.readLock();
+    if (!force && lock && lock.tab !== tabId && Date.now() - lock.ts < lockTimeout) {
+      return false;
+    }
+    shymini.writeLock();
+    if (channel && (!lock || lock.tab !== tabId)) {
+      channel.postMessage({ type: "claim", tab: tabId });
+    }
+    return true;
+  },
+  releaseLock: function () {
+    var lock = shymini.readLock();
+    if (lock && lock.tab === tabId) {
+      try {
+        window.localStorage.removeItem(lockKey);
+      } catch (e) {}
+      if (channel) {
+        channel.postMessage({ type: "release", tab: tabId });
+      }
+    }
+  },
+{% endif %}
+  // Properties of the visitor's session, e.g. { plan: "pro" }, sent with
+  // the next POST
+  setProps: function (props) {
+    shymini.props = Object.assign(shymini.props || {}, props);
+  },
+{% if track_interaction %}
+  markInteraction: function () {
+    shymini.interacted = true;
+  },
+  // Report the visitor's first interaction once, with whatever goes out next
+  addInteraction: function (payload) {
+    if (shymini.interacted && !shymini.interactionSent) {
+      payload.interacted = true;
+      shymini.interactionSent = true;
+    }
+  },
+{% endif %}
+{% if track_errors %}
+  // Report an uncaught error of the current page view, once per message and
+  // stack
+  reportError: function (message, stack) {
+    if (!shymini.idempotency || !shymini.errorsSampled || shymini.errorsSent >= maxErrors) {
+      return;
+    }
+    message = String(message || "").substring(0, 512);
+    stack = String(stack || "").substring(0, 2048);
+    var key = message + "\n" + stack;
+    if (!message && !stack || shymini.errorsSeen[key]) {
+      return;
+    }
+    shymini.errorsSeen[key] = true;
+    shymini.errorsSent++;
+    shymini.post({
+      idempotency: shymini.idempotency,
+      location: window.location.href,
+      error: { message: message, stack: stack }
+    }).catch(function() {});
+  },
+{% endif %}
+  markActive: function () {
+    var wasIdle = shymini.idle;
+    shymini.lastActivity = Date.now();
+    shymini.idle = false;
+    // Resume right away rather than at the next interval
+    if (wasIdle && shymini.idempotency) {
+      shymini.sendHeartbeat();
+    }
+  },
   sendHeartbeat: function () {
     if (document.hidden || shymini.skipHeartbeat) {
       return;
     }
-
+    if (idleTimeout > 0 && Date.now() - shymini.lastActivity > idleTimeout) {
+      if (!shymini.idle) {
+        shymini.idle = true;
+        shymini.idleSince = Date.now();
+      }
+      return;
+    }
+{% if collapse_tabs %}
+    if (!shymini.claimLock(false)) {
+      return;
+    }
+{% endif %}
     shymini.skipHeartbeat = true;
+    shymini.ended = false;
 
     // Only send loadTime on first request to avoid duplicate hits with same loadTime
     // when server-side idempotency cache expires
@@ -33,9 +194,33 @@ var shymini = (function() {
       payload.loadTime =
         window.performance.timing.domContentLoadedEventEnd -
         window.performance.timing.navigationStart;
+      // Custom dimensions of the page view, e.g. { author: "Ada" }
+      payload.dimensions = shymini.dimensions;
+    }
+    if (shymini.props) {
+      payload.props = shymini.props;
+      shymini.props = null;
     }
+{% if track_interaction %}
+    shymini.addInteraction(payload);
+{% endif %}
 
-    fetch(scriptOrigin + "{{ endpoint }}", {
+    shymini.post(payload)
+    .then(function() {
+      shymini.loadTimeSent = true;
+      shymini.skipHeartbeat = false;
+      shymini.flushQueue();
+    })
+    .catch(function() {
+      // Offline or flaky network: keep the event and retry later
+      shymini.enqueue(payload);
+      shymini.loadTimeSent = true;
+      shymini.skipHeartbeat = false;
+      shymini.scheduleRetry();
+    });
+  },
+  post: function (payload) {
+    return fetch(scriptOrigin + "{{ endpoint }}", {
       method: "POST",
       headers: {
         "Content-Type": "application/json"
@@ -43,14 +228,139 @@ var shymini = (function() {
       body: JSON.stringify(payload),
       keepalive: true
     })
+    .then(function(response) {
+      if (!response.ok && response.status >= 500) {
+        throw new Error("shymini: " + response.status);
+      }
+      return response;
+    });
+  },
+  readQueue: function () {
+    try {
+      return JSON.parse(window.sessionStorage.getItem(queueKey) || "[]");
+    } catch (e) {
+      return [];
+    }
+  },
+  writeQueue: function (queue) {
+    try {
+      if (queue.length) {
+        window.sessionStorage.setItem(queueKey, JSON.stringify(queue));
+      } else {
+        window.sessionStorage.removeItem(queueKey);
+      }
+    } catch (e) {}
+  },
+  enqueue: function (payload) {
+    var queue = shymini.readQueue();
+    var last = queue[queue.length - 1];
+    var entry = { payload: payload, queuedAt: Date.now() };
+    // Consecutive heartbeats for the same page only need the latest one
+    if (last && last.payload.idempotency === payload.idempotency &&
+        last.payload.loadTime == null && payload.loadTime == null) {
+      queue[queue.length - 1] = entry;
+    } else {
+      queue.push(entry);
+    }
+    shymini.writeQueue(queue.slice(-maxQueueLength));
+  },
+  withOffset: function (entry) {
+    // Tell the server how long ago the event happened so delayed hits keep their time
+    var payload = JSON.parse(JSON.stringify(entry.payload));
+    payload.ts = Math.max(0, Date.now() - entry.queuedAt);
+    return payload;
+  },
+  flushQueue: function () {
+    if (shymini.flushing) {
+      return;
+    }
+    var queue = shymini.readQueue();
+    if (!queue.length) {
+      shymini.retryDelay = minRetryDelay;
+      return;
+    }
+    shymini.flushing = true;
+    shymini.post(shymini.withOffset(queue[0]))
     .then(function() {
-      shymini.loadTimeSent = true;
-      shymini.skipHeartbeat = false;
+      shymini.writeQueue(shymini.readQueue().slice(1));
+      shymini.flushing = false;
+      shymini.retryDelay = minRetryDelay;
+      shymini.flushQueue();
     })
     .catch(function() {
-      shymini.skipHeartbeat = false;
+      shymini.flushing = false;
+      shymini.scheduleRetry();
     });
   },
+  scheduleRetry: function () {
+    if (shymini.retryTaskId != null) {
+      return;
+    }
+    shymini.retryTaskId = setTimeout(function () {
+      shymini.retryTaskId = null;
+      shymini.flushQueue();
+    }, shymini.retryDelay);
+    shymini.retryDelay = Math.min(shymini.retryDelay * 2, maxRetryDelay);
+  },
+  beacon: function (payload) {
+    var sent = false;
+    try {
+      // As text/plain a cross-origin beacon needs no preflight
+      sent = navigator.sendBeacon(scriptOrigin + "{{ endpoint }}",
+        new Blob([JSON.stringify(payload)], { type: "text/plain" }));
+    } catch (e) {
+      sent = false;
+    }
+    if (!sent) {
+      shymini.post(payload).catch(function() {});
+    }
+  },
+  beaconQueue: function () {
+    // The page is going away: hand whatever is left to the browser. While
+    // offline the queue stays in sessionStorage for the next page instead.
+    if (navigator.onLine === false) {
+      return;
+    }
+    var queue = shymini.readQueue();
+    for (var i = 0; i < queue.length; i++) {
+      shymini.beacon(shymini.withOffset(queue[i]));
+    }
+    shymini.writeQueue([]);
+  },
+  sendEnd: function () {
+    // The visitor is leaving or switching away: report when, so the session
+    // ends then rather than at whatever heartbeat came last
+    if (!shymini.idempotency || shymini.ended) {
+      return;
+    }
+    shymini.ended = true;
+    var payload = {
+      idempotency: shymini.idempotency,
+      location: window.location.href,
+      end: true
+    };
+{% if track_interaction %}
+    shymini.addInteraction(payload);
+{% endif %}
+    // An idle visitor left when they stopped interacting
+    if (shymini.idle && shymini.idleSince != null) {
+      payload.ts = Math.max(0, Date.now() - shymini.idleSince);
+    }
+    if (navigator.onLine === false) {
+      shymini.enqueue(payload);
+      return;
+    }
+    shymini.beacon(payload);
+  },
+  // A page the browser prerenders in the background counts once the visitor
+  // actually opens it; one never opened sends nothing
+  whenActivated: function (callback) {
+    if (document.prerendering) {
+      document.addEventListener("prerenderingchange", callback, { once: true });
+    } else {
+      callback();
+    }
+  },
   newPageLoad: function () {
     if (shymini.heartbeatTaskId != null) {
       clearInterval(shymini.heartbeatTaskId);
@@ -58,17 +368,129 @@ var shymini = (function() {
     shymini.idempotency = Math.random().toString(36).substring(2, 15) + Math.random().toString(36).substring(2, 15);
     shymini.skipHeartbeat = false;
     shymini.loadTimeSent = false;
+    shymini.lastActivity = Date.now();
+    shymini.idle = false;
+    shymini.ended = false;
+{% if track_errors %}
+    shymini.errorsSampled = Math.random() < errorSampleRate;
+    shymini.errorsSent = 0;
+    shymini.errorsSeen = {};
+{% endif %}
+{% if collapse_tabs %}
+    // Another live tab is already on this page: join its hit instead of creating a new one
+    var lock = shymini.readLock();
+    if (lock && lock.tab !== tabId && lock.location === window.location.href &&
+        Date.now() - lock.ts < lockTimeout) {
+      shymini.idempotency = lock.idempotency;
+      shymini.loadTimeSent = true;
+    }
+{% endif %}
     shymini.heartbeatTaskId = setInterval(shymini.sendHeartbeat, {{ heartbeat_frequency }});
     shymini.sendHeartbeat();
+  },
+  listen: function () {
+    window.addEventListener("online", shymini.flushQueue);
+    window.addEventListener("pagehide", shymini.beaconQueue);
+    window.addEventListener("pagehide", shymini.sendEnd);
+    document.addEventListener("visibilitychange", function () {
+      if (document.hidden) {
+        shymini.sendEnd();
+      }
+    });
+{% if track_interaction %}
+    // Whether the session counts as a bounce depends on this
+    ["mousedown", "keydown", "scroll", "touchstart"].forEach(function (type) {
+      window.addEventListener(type, shymini.markInteraction, { passive: true, once: true });
+    });
+{% endif %}
+{% if track_errors %}
+    window.addEventListener("error", function (event) {
+      shymini.reportError(event.message, event.error && event.error.stack);
+    });
+    window.addEventListener("unhandledrejection", function (event) {
+      var reason = event.reason;
+      shymini.reportError(reason && reason.message ? reason.message : reason,
+        reason && reason.stack);
+    });
+{% endif %}
+{% if idle_timeout > 0 %}
+    ["mousedown", "mousemove", "keydown", "scroll", "touchstart", "wheel"].forEach(function (type) {
+      window.addEventListener(type, shymini.markActive, { passive: true });
+    });
+    document.addEventListener("visibilitychange", function () {
+      if (!document.hidden) {
+        shymini.markActive();
+      }
+    });
+{% endif %}
+{% if collapse_tabs %}
+    document.addEventListener("visibilitychange", function () {
+      if (!document.hidden && shymini.idempotency) {
+        shymini.claimLock(true);
+      }
+    });
+    window.addEventListener("pagehide", shymini.releaseLock);
+{% endif %}
   }
 };
 })();
 
-window.addEventListener("load", shymini.newPageLoad);
-{% if !script_inject.is_empty() %}
-// The following script is not part of shymini, and was instead
-// provided by this site's administrator.
-// -- START --
-{{ script_inject }}
-// -- END --
+{% if module %}
+// Start tracking: count the current page view and follow the visitor from
+// here on. Options: `origin`, the shymini server (without a trailing slash),
+// and `dimensions` of the page view.
+export function init(options) {
+  if (started) {
+    return;
+  }
+  started = true;
+  if (options && options.origin) {
+    scriptOrigin = String(options.origin);
+  }
+  if (options && options.dimensions) {
+    shymini.dimensions = options.dimensions;
+  }
+  // Checked here rather than when the module is generated
+{% if respect_dnt %}
+  if (navigator.doNotTrack === "1") {
+    shymini.dnt = true;
+    return;
+  }
+{% endif %}
+{% if respect_gpc %}
+  if (navigator.globalPrivacyControl === true) {
+    shymini.dnt = true;
+    return;
+  }
+{% endif %}
+  shymini.listen();
+  shymini.flushQueue();
+  shymini.whenActivated(shymini.newPageLoad);
+}
+
+// Count a page view, e.g. after a client-side route change, with its
+// custom dimensions if any
+export function track(dimensions) {
+  shymini.dimensions = dimensions;
+  if (!started) {
+    init();
+  } else if (!shymini.dnt) {
+    shymini.whenActivated(shymini.newPageLoad);
+  }
+}
+
+// Set properties on the visitor's session, e.g. setProps({ plan: "pro" })
+export function setProps(props) {
+  shymini.setProps(props);
+}
+{% else %}
+// Dimensions of the page: data-dimensions='{"author":"Ada"}' on the script tag
+try {
+  shymini.dimensions = JSON.parse(document.currentScript.dataset.dimensions);
+} catch (e) {}
+shymini.listen();
+window.addEventListener("load", function () {
+  shymini.whenActivated(shymini.newPageLoad);
+});
+window.addEventListener("load", shymini.flushQueue);
 {% endif %}
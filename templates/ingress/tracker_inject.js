@@ -0,0 +1,6 @@
+
+// The following script is not part of shymini, and was instead
+// provided by this site's administrator.
+// -- START --
+{{ script_inject }}
+// -- END --
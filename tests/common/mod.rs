@@ -0,0 +1,525 @@
+//! Test harness shared by the integration tests: the full router on an
+//! in-memory SQLite database, a clock the test controls, and helpers for
+//! seeding services, sessions and hits at chosen times.
+
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    middleware,
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+
+use shymini::{
+    api, badge,
+    bots::HostResolver,
+    cache::AppCache,
+    clock::{Clock, FakeClock},
+    config::Settings,
+    dashboard, db,
+    domain::{
+        CreateHit, CreateService, CreateSession, DeviceType, Hit, Service, Session, TrackerType,
+    },
+    embed,
+    geo::GeoIpLookup,
+    hooks::Hooks,
+    ingress,
+    mailer::Mailer,
+    milestones,
+    state::AppState,
+    theme,
+};
+
+/// Where the harness clock starts
+pub fn start_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap()
+}
+
+/// Settings from the environment, or defaults when there are none
+pub fn test_settings() -> Settings {
+    Settings::new().unwrap_or_else(|_| {
+        // Fallback for tests
+        Settings {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            listen: None,
+            #[cfg(unix)]
+            listen_mode: shymini::server::SocketMode(0o660),
+            keep_alive: true,
+            keep_alive_timeout_secs: 60,
+            max_connections: 0,
+            max_concurrent_streams: 200,
+            request_timeout_secs: 30,
+            database_url: None,
+            database_path: None,
+            maxmind_city_db: None,
+            maxmind_asn_db: None,
+            block_all_ips: false,
+            aggressive_hash_salting: false,
+            script_heartbeat_frequency_ms: 5000,
+            max_heartbeats_per_hit: 720,
+            cache_max_entries: 1000,
+            cache_ttl_secs: 3600,
+            session_memory_timeout_secs: 1800,
+            max_timestamp_skew_secs: 1800,
+            locale: "en".to_string(),
+            quota_sample_rate: 0.1,
+            multi_tenant: false,
+            signup_enabled: false,
+            public_url: "http://localhost:8080".to_string(),
+            secret_key: None,
+            smtp_url: None,
+            mail_from: "shymini <shymini@localhost>".to_string(),
+            oidc_issuer: None,
+            oidc_client_id: None,
+            oidc_client_secret: None,
+            oidc_scopes: "openid email profile".to_string(),
+            oidc_groups_claim: None,
+            proxy_user_header: None,
+            proxy_email_header: "Remote-Email".to_string(),
+            proxy_name_header: "Remote-Name".to_string(),
+            proxy_groups_header: "Remote-Groups".to_string(),
+            proxy_trusted_networks: String::new(),
+            login_max_failures: 5,
+            login_max_failures_per_ip: 20,
+            login_lockout_secs: 60,
+            monitor_interval_secs: 0,
+            monitor_webhook_url: None,
+            monitor_rdap_url: "https://rdap.org".to_string(),
+            sitemap_crawl_interval_secs: 0,
+            top_pages_refresh_secs: 0,
+            visitor_sketches: false,
+            idempotency_filter_capacity: 1_000,
+            prefetch_hits: Default::default(),
+            verify_bots: false,
+            referrer_spam: Default::default(),
+            referrer_spam_list_url: String::new(),
+            error_sample_rate: 1.0,
+            max_hit_dimensions: 5,
+            max_session_props: 10,
+            maintenance_hour: None,
+            rollup_retention_days: 400,
+            hit_partitions: false,
+            hit_retention_months: 0,
+            ws_messages_per_min: 60,
+            theme_logo_url: None,
+            theme_accent: "#5cc265".to_string(),
+            theme_mode: Default::default(),
+            update_check: false,
+            update_check_url: "https://api.github.com/repos/cdaringe/shymini/releases/latest"
+                .to_string(),
+            ip_encryption_key: None,
+            query_api: false,
+            query_timeout_secs: 10,
+            query_max_rows: 1000,
+            vault_addr: None,
+            vault_token: None,
+            vault_path: "secret/data/shymini".to_string(),
+        }
+    })
+}
+
+/// App state on a fresh in-memory database, with the real clock
+pub async fn test_state(configure: impl FnOnce(&mut Settings)) -> AppState {
+    let pool = db::create_pool("sqlite::memory:").await.unwrap();
+    db::run_migrations(&pool).await.unwrap();
+
+    let mut settings = test_settings();
+    configure(&mut settings);
+
+    let cache = AppCache::new(&settings);
+    let geo = GeoIpLookup::new(None, None).unwrap();
+    AppState::new(pool, cache, settings, geo, Mailer::memory())
+}
+
+/// The application's routes
+pub fn test_router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(dashboard::dashboard_index))
+        .route("/search", get(dashboard::search))
+        .route("/service/new", get(dashboard::service_create_form))
+        .route("/service/new", post(dashboard::service_create))
+        .route("/service/:id", get(dashboard::service_detail))
+        .route("/service/:id/stats", get(dashboard::stats_partial))
+        .route(
+            "/service/:id/panels/sessions",
+            get(dashboard::sessions_panel),
+        )
+        .route(
+            "/service/:id/panels/locations",
+            get(dashboard::locations_panel),
+        )
+        .route(
+            "/service/:id/panels/referrers",
+            get(dashboard::referrers_panel),
+        )
+        .route(
+            "/service/:id/panels/countries",
+            get(dashboard::countries_panel),
+        )
+        .route("/service/:id/panels/chart", get(dashboard::chart_panel))
+        .route("/service/:id/panels/usage", get(dashboard::usage_panel))
+        .route(
+            "/service/:id/panels/data-quality",
+            get(dashboard::data_quality_panel),
+        )
+        .route("/service/:id/panels/errors", get(dashboard::errors_panel))
+        .route(
+            "/service/:id/panels/content-groups",
+            get(dashboard::content_groups_panel),
+        )
+        .route(
+            "/service/:id/panels/dimensions",
+            get(dashboard::dimensions_panel),
+        )
+        .route(
+            "/service/:id/sessions/:session_id",
+            get(dashboard::session_detail),
+        )
+        .route("/service/:id/locations", get(dashboard::location_list))
+        .route("/service/:id/campaigns", get(dashboard::campaign_list))
+        .route("/service/:id/users", get(dashboard::service_users))
+        .route("/service/:id/retention", get(dashboard::service_retention))
+        .route(
+            "/service/:id/links",
+            get(dashboard::link_list).post(dashboard::link_create),
+        )
+        .route("/service/:id/links/:link_id", get(dashboard::link_detail))
+        .route(
+            "/service/:id/links/:link_id/qr.svg",
+            get(dashboard::link_qr_code),
+        )
+        .route(
+            "/service/:id/links/:link_id/delete",
+            post(dashboard::link_delete),
+        )
+        .route(
+            "/service/:id/install-status",
+            get(dashboard::install_status),
+        )
+        .route("/service/:id/views", post(dashboard::saved_view_create))
+        .route(
+            "/service/:id/views/:view_id/delete",
+            post(dashboard::saved_view_delete),
+        )
+        .route("/service/:id/segments", post(dashboard::segment_create))
+        .route(
+            "/service/:id/segments/:segment_id/delete",
+            post(dashboard::segment_delete),
+        )
+        .route(
+            "/service/:id/manage",
+            get(dashboard::service_update_form).post(dashboard::service_update),
+        )
+        .route(
+            "/service/:id/ingest-script",
+            post(dashboard::ingest_script_update),
+        )
+        .route("/service/:id/debug", get(dashboard::service_debug))
+        .route("/service/:id/delete", post(dashboard::service_delete))
+        .route(
+            "/exclude-me/:tracking_id",
+            get(dashboard::exclude_me_form).post(dashboard::exclude_me),
+        )
+        .route("/login", get(dashboard::login_form).post(dashboard::login))
+        .route("/login/two-factor", post(dashboard::login_two_factor))
+        .route(
+            "/signup",
+            get(dashboard::signup_form).post(dashboard::signup),
+        )
+        .route("/verify-email/:token", get(dashboard::verify_email))
+        .route(
+            "/password-reset",
+            get(dashboard::password_reset_form).post(dashboard::password_reset_request),
+        )
+        .route(
+            "/password-reset/:token",
+            get(dashboard::password_reset_confirm_form).post(dashboard::password_reset_confirm),
+        )
+        .route("/invite/:token", get(dashboard::invite_accept))
+        .route("/auth/oidc/login", get(dashboard::oidc_login))
+        .route("/auth/oidc/callback", get(dashboard::oidc_callback))
+        .route("/account", get(dashboard::account))
+        .route("/account/settings", post(dashboard::account_settings))
+        .route("/account/two-factor", post(dashboard::two_factor_enable))
+        .route(
+            "/account/two-factor/disable",
+            post(dashboard::two_factor_disable),
+        )
+        .route(
+            "/organization",
+            get(dashboard::organization_settings).post(dashboard::organization_update),
+        )
+        .route("/organization/members", post(dashboard::member_add))
+        .route(
+            "/organization/members/:user_id/role",
+            post(dashboard::member_role),
+        )
+        .route("/organization/tokens", post(dashboard::api_token_create))
+        .route("/organizations/switcher", get(dashboard::org_switcher))
+        .route("/admin/status", get(dashboard::admin_status))
+        .route("/admin/maintenance", post(dashboard::maintenance_run))
+        .route("/admin/update-banner", get(dashboard::update_banner))
+        // New tracking routes
+        .route(
+            "/trace/px_:tracking_id.gif",
+            get(ingress::pixel_handler)
+                .head(ingress::pixel_head_handler)
+                .options(ingress::ingress_options_handler),
+        )
+        .route(
+            "/trace/px_:tracking_id/:identifier.gif",
+            get(ingress::pixel_with_id_handler)
+                .head(ingress::pixel_head_handler)
+                .options(ingress::ingress_options_handler),
+        )
+        .route(
+            "/trace/app_:tracking_id.js",
+            get(ingress::script_get_handler)
+                .post(ingress::script_post_handler)
+                .options(ingress::ingress_options_handler),
+        )
+        .route(
+            "/badge/:tracking_id/visitors.svg",
+            get(badge::visitors_badge),
+        )
+        // Milestone feeds, behind a signed token instead of a login
+        .route(
+            "/feed/:token/milestones.xml",
+            get(milestones::milestone_feed),
+        )
+        // Tracked links, counting a click on the way to the target
+        .route("/r/:token", get(ingress::link_redirect_handler))
+        .route("/api/organization", get(api::get_organization))
+        .route("/api/services", get(api::list_services))
+        .route("/api/services/bulk", post(api::bulk::update_services))
+        .route("/api/services/:id", get(api::get_service))
+        .route("/api/services/:id/stats", get(api::get_service_stats))
+        .route("/api/services/:id/report.pdf", get(api::get_service_report))
+        .route(
+            "/api/services/:id/export.parquet",
+            get(api::export_hits_parquet),
+        )
+        .route("/api/services/:id/usage", get(api::get_service_usage))
+        .route(
+            "/api/services/:id/content-groups",
+            get(api::get_content_groups),
+        )
+        .route(
+            "/api/services/:id/dimensions/:key",
+            get(api::get_dimension_counts),
+        )
+        .route("/api/services/:id/paths", get(api::get_page_paths))
+        .route("/api/services/:id/verify-install", get(api::verify_install))
+        .route("/api/services/:id/sessions", get(api::list_sessions))
+        .route("/api/services/:id/pixel-urls", post(api::create_pixel_urls))
+        .route("/api/services/:id/validate-hit", post(api::validate_hit))
+        .route("/api/services/:id/campaigns", get(api::list_campaigns))
+        .route("/api/services/:id/users", get(api::get_users))
+        .route("/api/services/:id/retention", get(api::get_retention))
+        .route(
+            "/api/services/:id/campaigns/:campaign",
+            get(api::get_campaign),
+        )
+        .route(
+            "/api/services/:id/links",
+            get(api::list_links).post(api::create_link),
+        )
+        .route(
+            "/api/links/:id",
+            get(api::get_link).delete(api::delete_link),
+        )
+        .route("/api/links/:id/qr.svg", get(api::get_link_qr_code))
+        .route(
+            "/api/services/:id/views",
+            get(api::list_saved_views).post(api::create_saved_view),
+        )
+        .route(
+            "/api/services/:id/segments",
+            get(api::list_segments).post(api::create_segment),
+        )
+        .route(
+            "/api/segments/:id",
+            get(api::get_segment).delete(api::delete_segment),
+        )
+        .route("/api/status", get(api::get_status))
+        .route("/api/query", post(api::run_query))
+        .route("/api/sessions/:id", get(api::get_session))
+        .route("/api/sessions/:id/hits", get(api::list_session_hits))
+        .route("/api/ws", get(api::live::connect))
+        .route("/grafana", get(api::grafana::test_connection))
+        .route("/grafana/search", post(api::grafana::search))
+        .route("/grafana/query", post(api::grafana::query))
+        .route("/embed/:token/chart", get(embed::embed_chart))
+        .route("/embed/:token/counters", get(embed::embed_counters))
+        .route("/static/theme.css", get(theme::theme_css))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            dashboard::render_error_pages,
+        ))
+        .layer(middleware::from_fn(embed::frame_options))
+        .with_state(state)
+}
+
+/// The full app with a fake clock, started at [`start_time`]
+pub struct TestApp {
+    pub router: Router,
+    pub state: AppState,
+    pub clock: Arc<FakeClock>,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with(|_| {}).await
+    }
+
+    pub async fn with(configure: impl FnOnce(&mut Settings)) -> Self {
+        let clock = Arc::new(FakeClock::new(start_time()));
+        let state = test_state(configure).await.with_clock(clock.clone());
+        Self {
+            router: test_router(state.clone()),
+            state,
+            clock,
+        }
+    }
+
+    /// The same app with other hooks than the registered ones
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.state = self.state.with_hooks(hooks);
+        self.router = test_router(self.state.clone());
+        self
+    }
+
+    /// The same app checking claimed crawlers with another resolver
+    pub fn with_bot_resolver(mut self, resolver: Arc<dyn HostResolver>) -> Self {
+        self.state = self.state.with_bot_resolver(resolver);
+        self.router = test_router(self.state.clone());
+        self
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub async fn send(&self, request: Request<Body>) -> Response {
+        self.router.clone().oneshot(request).await.unwrap()
+    }
+
+    pub async fn get(&self, uri: &str) -> Response {
+        self.send(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+    }
+
+    /// GET a JSON API route, expecting a successful response
+    pub async fn get_json(&self, uri: &str) -> serde_json::Value {
+        let response = self.get(uri).await;
+        assert_eq!(response.status(), StatusCode::OK, "GET {}", uri);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// A service accepting every origin, in the default organization
+    pub async fn service(&self, name: &str) -> Service {
+        db::create_service(
+            &self.state.pool,
+            CreateService {
+                name: name.to_string(),
+                link: "https://example.com".to_string(),
+                origins: "*".to_string(),
+                collect_ips: true,
+                collapse_tabs: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    /// A session starting at `start`, without hits yet
+    pub async fn session(
+        &self,
+        service: &Service,
+        identifier: &str,
+        start: DateTime<Utc>,
+    ) -> Session {
+        db::create_session(
+            &self.state.pool,
+            CreateSession {
+                service_id: service.id,
+                identifier: identifier.to_string(),
+                start_time: start,
+                user_agent: String::new(),
+                browser: "Firefox".to_string(),
+                device: String::new(),
+                device_type: DeviceType::Desktop,
+                os: "Linux".to_string(),
+                ip: None,
+                ip_encrypted: None,
+                ip_hash: None,
+                asn: String::new(),
+                country: "DE".to_string(),
+                longitude: None,
+                latitude: None,
+                time_zone: String::new(),
+                environment: Default::default(),
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    /// A page view at `time`, keeping the session's last-seen time and
+    /// bounce flag up to date as ingress does. Views at the session's start
+    /// are its initial ones.
+    pub async fn hit(&self, session: &Session, location: &str, time: DateTime<Utc>) -> Hit {
+        let pool = &self.state.pool;
+        let hit = db::create_hit(
+            pool,
+            CreateHit {
+                session_id: session.id,
+                service_id: session.service_id,
+                initial: time == session.start_time,
+                start_time: time,
+                tracker: TrackerType::Js,
+                location: location.to_string(),
+                referrer: String::new(),
+                load_time: None,
+                environment: session.environment,
+                prefetched: false,
+                referrer_spam: false,
+            },
+        )
+        .await
+        .unwrap();
+        db::update_session_last_seen(pool, session.id, time)
+            .await
+            .unwrap();
+        let service = db::get_service(pool, session.service_id).await.unwrap();
+        db::recalculate_session_bounce(pool, session.id, &service)
+            .await
+            .unwrap();
+        hit
+    }
+
+    /// A session viewing `pages` in order, starting with the first view
+    pub async fn visit(
+        &self,
+        service: &Service,
+        identifier: &str,
+        pages: &[(&str, DateTime<Utc>)],
+    ) -> Session {
+        let session = self.session(service, identifier, pages[0].1).await;
+        for (location, time) in pages {
+            self.hit(&session, location, *time).await;
+        }
+        session
+    }
+}
@@ -0,0 +1,312 @@
+//! Ingress and stats hooks, and per-service ingest scripts, run on the full
+//! ingress and API paths
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use shymini::{
+    db,
+    domain::{CoreStats, Service},
+    error::{Error, Result},
+    hooks::{Hooks, IngressAction, IngressContext, IngressHook, RecordedHit, StatsHook},
+    ingress::IngressPayload,
+    state::AppState,
+};
+
+mod common;
+
+use common::TestApp;
+
+/// Drops hits on `/internal`, moves `/old` to `/new` and tags every hit
+struct Rewriter {
+    recorded: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl IngressHook for Rewriter {
+    async fn before_record(
+        &self,
+        _state: &AppState,
+        _context: &IngressContext<'_>,
+        payload: &mut IngressPayload,
+    ) -> Result<IngressAction> {
+        if payload.location.contains("/internal") {
+            return Ok(IngressAction::Drop);
+        }
+        payload.location = payload.location.replace("/old", "/new");
+        payload
+            .dimensions
+            .push(("source".to_string(), "plugin".to_string()));
+        Ok(IngressAction::Record)
+    }
+
+    async fn after_record(&self, _state: &AppState, hit: &RecordedHit<'_>) -> Result<()> {
+        assert!(hit.payload.location.ends_with("/new"));
+        self.recorded.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Fails every time, which must not keep hits from being recorded
+struct Broken;
+
+#[async_trait]
+impl IngressHook for Broken {
+    async fn before_record(
+        &self,
+        _state: &AppState,
+        _context: &IngressContext<'_>,
+        _payload: &mut IngressPayload,
+    ) -> Result<IngressAction> {
+        Err(Error::Internal("broken plugin".to_string()))
+    }
+}
+
+/// Reports the hit count a second time, under its own name
+struct HitEcho;
+
+#[async_trait]
+impl StatsHook for HitEcho {
+    async fn adjust_stats(
+        &self,
+        _state: &AppState,
+        service: &Service,
+        stats: &mut CoreStats,
+    ) -> Result<()> {
+        stats
+            .extra
+            .insert("echo".to_string(), serde_json::json!(service.name));
+        stats
+            .extra
+            .insert("hits".to_string(), serde_json::json!(stats.hit_count));
+        Ok(())
+    }
+}
+
+async fn track(app: &TestApp, service: &Service, ip: &str, location: &str) {
+    let body = serde_json::json!({ "location": location, "referrer": "", "loadTime": 120 });
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Content-Type", "application/json")
+                .header("X-Forwarded-For", ip)
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+                )
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_ingress_hooks() {
+    let recorded = Arc::new(AtomicUsize::new(0));
+    let hooks = Hooks::default()
+        .with_ingress(Broken)
+        .with_ingress(Rewriter {
+            recorded: recorded.clone(),
+        });
+    let app = TestApp::new().await.with_hooks(hooks);
+    let service = app.service("Site").await;
+
+    track(&app, &service, "203.0.113.1", "https://example.com/old").await;
+    track(
+        &app,
+        &service,
+        "203.0.113.2",
+        "https://example.com/internal",
+    )
+    .await;
+
+    let start = app.now() - chrono::Duration::days(1);
+    let end = app.now() + chrono::Duration::seconds(1);
+    let sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start,
+        end,
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(sessions.len(), 1);
+    let hits = db::list_hits_for_session(&app.state.pool, sessions[0].id, 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].location, "https://example.com/new");
+    assert_eq!(recorded.load(Ordering::SeqCst), 1);
+
+    // Stats ranges end at the current time
+    app.clock.advance(chrono::Duration::minutes(1));
+    let json = app
+        .get_json(&format!("/api/services/{}/dimensions/source", service.id))
+        .await;
+    assert_eq!(json["data"][0]["value"], "plugin");
+}
+
+#[tokio::test]
+async fn test_stats_hooks() {
+    let app = TestApp::new()
+        .await
+        .with_hooks(Hooks::default().with_stats(HitEcho));
+    let service = app.service("Site").await;
+    let time = app.now() - chrono::Duration::minutes(5);
+    app.visit(&service, "a", &[("/", time), ("/pricing", time)])
+        .await;
+
+    let json = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(json["data"]["extra"]["echo"], "Site");
+    assert_eq!(json["data"]["extra"]["hits"], 2);
+
+    // Without hooks there is nothing extra
+    let app = TestApp::new().await;
+    let service = app.service("Site").await;
+    let json = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert!(json["data"].get("extra").is_none());
+}
+
+async fn save_script(app: &TestApp, service: &Service, source: &str) -> (StatusCode, String) {
+    let body = serde_urlencoded::to_string([("source", source)]).unwrap();
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/service/{}/ingest-script", service.id))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await;
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_ingest_script() {
+    let app = TestApp::new().await;
+    let service = app.service("Site").await;
+
+    // A script that does not compile is refused and shown again
+    let (status, html) = save_script(&app, &service, "hit.location = ;").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(html.contains("hit.location = ;"));
+    assert_eq!(
+        db::get_ingest_script(&app.state.pool, service.id)
+            .await
+            .unwrap(),
+        None
+    );
+
+    let script = r#"
+        if hit.location.contains("/internal") { return false; }
+        hit.location.replace("/old", "/new");
+        hit.dimensions.source = "script";
+        if hit.location.contains("/loop") { loop {} }
+    "#;
+    let (status, _) = save_script(&app, &service, script).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let response = app.get(&format!("/service/{}/manage", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("hit.dimensions.source"));
+
+    track(&app, &service, "203.0.113.1", "https://example.com/old").await;
+    track(
+        &app,
+        &service,
+        "203.0.113.2",
+        "https://example.com/internal",
+    )
+    .await;
+    // A script that runs out of operations leaves the hit as it was
+    track(&app, &service, "203.0.113.3", "https://example.com/loop").await;
+
+    let start = app.now() - chrono::Duration::days(1);
+    let end = app.now() + chrono::Duration::seconds(1);
+    let mut locations = Vec::new();
+    for session in db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start,
+        end,
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap()
+    {
+        for hit in db::list_hits_for_session(&app.state.pool, session.id, 10, 0)
+            .await
+            .unwrap()
+        {
+            locations.push(hit.location);
+        }
+    }
+    locations.sort();
+    assert_eq!(
+        locations,
+        vec!["https://example.com/loop", "https://example.com/new"]
+    );
+
+    app.clock.advance(chrono::Duration::minutes(1));
+    let json = app
+        .get_json(&format!("/api/services/{}/dimensions/source", service.id))
+        .await;
+    assert_eq!(json["data"][0]["value"], "script");
+    assert_eq!(json["data"].as_array().unwrap().len(), 1);
+
+    // Saving an empty script removes it
+    let (status, _) = save_script(&app, &service, "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    track(
+        &app,
+        &service,
+        "203.0.113.4",
+        "https://example.com/internal",
+    )
+    .await;
+    assert_eq!(
+        db::get_ingest_script(&app.state.pool, service.id)
+            .await
+            .unwrap(),
+        None
+    );
+    let end = app.now() + chrono::Duration::seconds(1);
+    let sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start,
+        end,
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(sessions.len(), 3);
+}
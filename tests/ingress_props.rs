@@ -0,0 +1,125 @@
+//! Property tests feeding malformed and oversized tracker payloads through the
+//! full ingress path
+
+use std::cell::Cell;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use chrono::Duration;
+use proptest::prelude::*;
+use shymini::{
+    db,
+    domain::Service,
+    ingress::{clean_text, MAX_FIELD_CHARS, MAX_URL_CHARS},
+};
+
+mod common;
+
+use common::TestApp;
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn post(service: &Service, ip: &str, user_agent: &str, body: Vec<u8>) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(format!("/trace/app_{}.js", service.tracking_id))
+        .header("Content-Type", "application/json")
+        .header("X-Forwarded-For", ip)
+        .header("User-Agent", user_agent)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Text as a tracker might send it, or as an attacker would
+fn visitor_text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[\\x00-\\x1f /a-z]{0,40}",
+        "\\PC{2000,2100}",
+        "(https?://)?[a-z.]{0,20}/[ -~]{0,200}",
+    ]
+}
+
+#[test]
+fn test_malformed_bodies_are_rejected() {
+    let rt = runtime();
+    let app = rt.block_on(TestApp::new());
+    let service = rt.block_on(app.service("Fuzz"));
+
+    proptest!(ProptestConfig::with_cases(64), |(body in proptest::collection::vec(any::<u8>(), 0..2048))| {
+        let status = rt.block_on(async {
+            app.send(post(&service, "203.0.113.1", "Mozilla/5.0", body)).await.status()
+        });
+        prop_assert!(!status.is_server_error(), "status {}", status);
+    });
+}
+
+#[test]
+fn test_payloads_are_stored_cleaned() {
+    let rt = runtime();
+    // Events can be backdated by at most the timestamp skew window
+    let app = rt.block_on(TestApp::with(|settings| {
+        settings.max_timestamp_skew_secs = 1800
+    }));
+    let service = rt.block_on(app.service("Fuzz"));
+    let visitor = Cell::new(0u32);
+
+    proptest!(ProptestConfig::with_cases(64), |(
+        location in visitor_text(),
+        referrer in visitor_text(),
+        user_agent in "[ -~]{0,700}",
+        load_time in any::<f64>(),
+        ts in any::<i64>(),
+    )| {
+        // A new visitor each case, an hour after the last, so each case
+        // makes the newest session, with one hit
+        visitor.set(visitor.get() + 1);
+        app.clock.advance(Duration::hours(1));
+        let n = visitor.get();
+        let ip = format!("10.{}.{}.{}", (n >> 16) & 255, (n >> 8) & 255, n & 255);
+        let body = serde_json::to_vec(&serde_json::json!({
+            "location": location,
+            "referrer": referrer,
+            "loadTime": load_time,
+            "ts": ts,
+        }))
+        .unwrap();
+
+        rt.block_on(async {
+            let response = app.send(post(&service, &ip, &user_agent, body)).await;
+            prop_assert_eq!(response.status(), StatusCode::OK);
+
+            let pool = &app.state.pool;
+            let sessions = db::list_sessions(
+                pool,
+                service.id,
+                app.now() - Duration::days(1),
+                app.now() + Duration::seconds(1),
+                None,
+                None,
+                None,
+                1,
+                0,
+            )
+            .await
+            .unwrap();
+            let session = &sessions[0];
+            prop_assert_eq!(&session.user_agent, &clean_text(&user_agent, MAX_FIELD_CHARS));
+
+            let hits = db::list_hits_for_session(pool, session.id, 10, 0).await.unwrap();
+            prop_assert_eq!(hits.len(), 1);
+            prop_assert_eq!(&hits[0].location, &clean_text(&location, MAX_URL_CHARS));
+            prop_assert_eq!(&hits[0].referrer, &clean_text(&referrer, MAX_URL_CHARS));
+            prop_assert!(hits[0].load_time.is_none_or(|t| t.is_finite() && t > 0.0));
+            prop_assert!(hits[0].start_time <= app.now());
+            Ok(())
+        })?;
+    });
+}
@@ -6,6 +6,8 @@ use axum::{
 use http_body_util::BodyExt;
 use tower::ServiceExt;
 
+mod common;
+
 // Helper to create test app with shared pool for multi-request tests
 async fn create_test_app() -> Router {
     let (router, _) = create_test_app_with_pool().await;
@@ -13,55 +15,23 @@ async fn create_test_app() -> Router {
 }
 
 async fn create_test_app_with_pool() -> (Router, shymini::db::Pool) {
-    use axum::routing::{get, post};
-    use shymini::{
-        api, cache::AppCache, config::Settings, dashboard, db, geo::GeoIpLookup, ingress,
-        state::AppState,
-    };
-
-    // Create in-memory SQLite database
-    let pool = db::create_pool("sqlite::memory:").await.unwrap();
-    db::run_migrations(&pool).await.unwrap();
-
-    // Create minimal settings
-    let settings = Settings::new().unwrap_or_else(|_| {
-        // Fallback for tests
-        Settings {
-            host: "127.0.0.1".to_string(),
-            port: 8080,
-            database_url: None,
-            database_path: None,
-            maxmind_city_db: None,
-            maxmind_asn_db: None,
-            block_all_ips: false,
-            aggressive_hash_salting: false,
-            script_heartbeat_frequency_ms: 5000,
-            cache_max_entries: 1000,
-            cache_ttl_secs: 3600,
-            session_memory_timeout_secs: 1800,
-        }
-    });
+    create_test_app_with(|_| {}).await
+}
 
-    let cache = AppCache::new(&settings);
-    let geo = GeoIpLookup::new(None, None).unwrap();
-    let state = AppState::new(pool.clone(), cache, settings, geo);
-
-    let router = Router::new()
-        .route("/", get(dashboard::dashboard_index))
-        .route("/service/new", get(dashboard::service_create_form))
-        .route("/service/new", post(dashboard::service_create))
-        .route("/service/:id", get(dashboard::service_detail))
-        // New tracking routes
-        .route("/trace/px_:tracking_id.gif", get(ingress::pixel_handler))
-        .route(
-            "/trace/app_:tracking_id.js",
-            get(ingress::script_get_handler).post(ingress::script_post_handler),
-        )
-        .route("/api/services", get(api::list_services))
-        .route("/api/services/:id", get(api::get_service))
-        .with_state(state);
+// Helper to create a test app with adjusted settings
+async fn create_test_app_with(
+    configure: impl FnOnce(&mut shymini::config::Settings),
+) -> (Router, shymini::db::Pool) {
+    let (router, state) = create_test_app_with_state(configure).await;
+    (router, state.pool)
+}
 
-    (router, pool)
+// Helper exposing the app state, e.g. to read mail sent by the test mailer
+async fn create_test_app_with_state(
+    configure: impl FnOnce(&mut shymini::config::Settings),
+) -> (Router, shymini::state::AppState) {
+    let state = common::test_state(configure).await;
+    (common::test_router(state.clone()), state)
 }
 
 #[tokio::test]
@@ -76,6 +46,43 @@ async fn test_dashboard_index() {
     assert_eq!(response.status(), StatusCode::OK);
 }
 
+#[tokio::test]
+async fn test_dashboard_accept_language() {
+    let app = create_test_app().await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .header("Accept-Language", "de-DE,de;q=0.9,en;q=0.8")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("<html lang=\"de\">"));
+    assert!(html.contains("Noch keine Dienste"));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .header("Accept-Language", "fr-FR")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("<html lang=\"en\">"));
+    assert!(html.contains("No services yet"));
+}
+
 #[tokio::test]
 async fn test_create_service_form() {
     let app = create_test_app().await;
@@ -127,6 +134,473 @@ async fn test_service_not_found() {
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn test_error_responses() {
+    let app = common::TestApp::new().await;
+    let missing = "00000000-0000-0000-0000-000000000000";
+
+    // The dashboard renders an error page
+    let response = app.get(&format!("/service/{}", missing)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Service not found"));
+    assert!(html.contains("Back to the dashboard"));
+
+    let response = app.get("/service/not-an-id").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Invalid service ID"));
+
+    let response = app.get("/exclude-me/not_a_tracking_id").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Invalid tracking ID"));
+
+    // Account pages too
+    let response = app.get("/verify-email/not-a-token").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("This link is invalid or has expired"));
+    assert!(html.contains("Back to the dashboard"));
+
+    // The API answers in JSON
+    let response = app.get(&format!("/api/services/{}", missing)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["success"], false);
+    assert_eq!(json["error"], "Service not found");
+
+    let response = app.get("/api/services/not-an-id").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"], "Invalid service ID");
+}
+
+#[tokio::test]
+async fn test_dashboard_panels() {
+    use shymini::db;
+    use shymini::domain::CreateService;
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Both the plain and the URL-filtered code paths
+    for query in ["", "?urlPattern=%2Fhome"] {
+        for panel in [
+            "sessions",
+            "locations",
+            "referrers",
+            "countries",
+            "chart",
+            "usage",
+            "data-quality",
+            "errors",
+            "content-groups",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/service/{}/panels/{}{}", service.id, panel, query))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.status(),
+                StatusCode::OK,
+                "panel {}{}",
+                panel,
+                query
+            );
+        }
+    }
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/service/00000000-0000-0000-0000-000000000000/panels/chart")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_saved_views() {
+    use shymini::db;
+    use shymini::domain::{CreateService, DashboardPanel};
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Sessions first, then the chart; everything else hidden
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/service/{}/views", service.id))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from(
+                    "name=Landing&urlPattern=%2Fhome&show_chart=on&order_chart=2&show_sessions=on&order_sessions=1",
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    let views = db::list_saved_views(&pool, service.id).await.unwrap();
+    assert_eq!(views.len(), 1);
+    let view = &views[0];
+    assert_eq!(view.name, "Landing");
+    assert_eq!(view.url_pattern, "/home");
+    assert_eq!(
+        view.layout.panels(),
+        &[DashboardPanel::Sessions, DashboardPanel::Chart]
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/service/{}?view={}", service.id, view.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/services/{}/views", service.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"][0]["layout"][0], "sessions");
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/service/{}/views/{}/delete", service.id, view.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(db::list_saved_views(&pool, service.id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_api_stats_country_enrichment() {
+    use shymini::db;
+    use shymini::domain::{CreateService, CreateSession, DeviceType};
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    for (identifier, country) in [("a", "DE"), ("b", "DE"), ("c", "JP")] {
+        db::create_session(
+            &pool,
+            CreateSession {
+                service_id: service.id,
+                identifier: identifier.to_string(),
+                start_time: chrono::Utc::now() - chrono::Duration::minutes(1),
+                user_agent: String::new(),
+                browser: "Firefox".to_string(),
+                device: String::new(),
+                device_type: DeviceType::Desktop,
+                os: "Linux".to_string(),
+                ip: None,
+                ip_encrypted: None,
+                ip_hash: None,
+                asn: String::new(),
+                country: country.to_string(),
+                longitude: None,
+                latitude: None,
+                time_zone: String::new(),
+                environment: Default::default(),
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/services/{}/stats", service.id))
+                .header("Accept-Language", "de")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let country = &json["data"]["countries"][0];
+    assert_eq!(country["value"], "DE");
+    assert_eq!(country["country"]["name"], "Deutschland");
+    assert_eq!(country["country"]["flag"], "🇩🇪");
+    assert_eq!(country["country"]["continent"], "EU");
+    assert_eq!(json["data"]["continents"][0]["continent"], "EU");
+    assert_eq!(json["data"]["continents"][0]["name"], "Europa");
+    assert_eq!(json["data"]["continents"][0]["count"], 2);
+}
+
+#[tokio::test]
+async fn test_hit_quota_drop() {
+    use shymini::db;
+    use shymini::domain::{CreateService, QuotaBehavior};
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Quota Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: false,
+            hit_quota: 2,
+            quota_behavior: QuotaBehavior::Drop,
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(service.hit_quota, 2);
+    assert_eq!(service.quota_behavior, QuotaBehavior::Drop);
+
+    // Three page loads; the third one is over the quota
+    for page in 1..=3 {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/trace/app_{}.js", service.tracking_id))
+                    .header("Content-Type", "application/json")
+                    .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0")
+                    .body(Body::from(format!(
+                        r#"{{"idempotency":"page{}","location":"https://example.com/{}","loadTime":100}}"#,
+                        page, page
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/services/{}/usage?months=3", service.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let usage = &json["data"];
+    assert_eq!(usage["hit_quota"], 2);
+    assert_eq!(usage["quota_behavior"], "drop");
+    assert_eq!(usage["current"]["hits"], 2);
+    assert_eq!(usage["current"]["sessions"], 1);
+    assert_eq!(usage["current"]["dropped"], 1);
+    assert_eq!(usage["history"].as_array().unwrap().len(), 2);
+}
+
 #[tokio::test]
 async fn test_pixel_service_not_found() {
     let app = create_test_app().await;
@@ -178,11 +652,39 @@ async fn test_tracker_script_served_for_valid_service() {
             link: "https://example.com".to_string(),
             origins: "*".to_string(),
             respect_dnt: false,
+            respect_gpc: false,
             ignore_robots: false,
             collect_ips: true,
             ignored_ips: String::new(),
             hide_referrer_regex: String::new(),
             script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
         },
     )
     .await
@@ -239,46 +741,441 @@ async fn test_tracker_script_served_for_valid_service() {
 }
 
 #[tokio::test]
-async fn test_pixel_served_for_valid_service() {
+async fn test_tracker_script_conditional_request() {
     use shymini::db;
     use shymini::domain::CreateService;
 
     let (app, pool) = create_test_app_with_pool().await;
 
-    // Create a service
     let service = db::create_service(
         &pool,
         CreateService {
-            name: "Pixel Test Service".to_string(),
+            name: "Test Service".to_string(),
             link: "https://example.com".to_string(),
             origins: "*".to_string(),
             respect_dnt: false,
+            respect_gpc: false,
             ignore_robots: false,
             collect_ips: true,
             ignored_ips: String::new(),
             hide_referrer_regex: String::new(),
             script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
         },
     )
     .await
     .unwrap();
 
-    // Request the pixel using the tracking_id
-    let uri = format!("/trace/px_{}.gif", service.tracking_id);
+    let uri = format!("/trace/app_{}.js", service.tracking_id);
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response
+        .headers()
+        .get("etag")
+        .and_then(|v| v.to_str().ok())
+        .expect("Script response should carry an ETag")
+        .to_string();
+    let cache_control = response
+        .headers()
+        .get("cache-control")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    assert!(
+        !cache_control.contains("31536000"),
+        "Script should not be cached for a year"
+    );
+
+    // A revalidation with the same ETag gets an empty 304
     let response = app
+        .clone()
         .oneshot(
             Request::builder()
                 .uri(&uri)
-                .header("Origin", "https://example.com")
+                .header("If-None-Match", &etag)
                 .body(Body::empty())
                 .unwrap(),
         )
         .await
         .unwrap();
 
-    assert_eq!(
-        response.status(),
-        StatusCode::OK,
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.is_empty());
+
+    // A stale ETag gets the full script
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(&uri)
+                .header("If-None-Match", "\"stale\"")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_tracker_script_debug() {
+    use shymini::db;
+    use shymini::domain::CreateService;
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut scripts = Vec::new();
+    for query in ["", "?debug=1"] {
+        let uri = format!("/trace/app_{}.js{}", service.tracking_id, query);
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get("etag").unwrap().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        scripts.push((etag, String::from_utf8_lossy(&body).to_string()));
+    }
+
+    // Minified by default; the readable script keeps its comments
+    let (minified_etag, minified) = &scripts[0];
+    let (readable_etag, readable) = &scripts[1];
+    assert!(minified.len() < readable.len());
+    assert!(!minified.contains("// Failed POSTs are kept in sessionStorage"));
+    assert!(readable.contains("// Failed POSTs are kept in sessionStorage"));
+    assert_ne!(minified_etag, readable_etag);
+}
+
+#[tokio::test]
+async fn test_tracker_script_module() {
+    use shymini::db;
+    use shymini::domain::CreateService;
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "https://example.com".to_string(),
+            respect_dnt: true,
+            respect_gpc: true,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // A bundler fetches the module without an Origin, and DNT is left to the browser
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/trace/app_{}.esm.js?debug=1", service.tracking_id))
+                .header("DNT", "1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let module = String::from_utf8_lossy(&body);
+    assert!(module.contains("export function init(options)"));
+    assert!(module.contains("export function track(dimensions)"));
+    assert!(module.contains("var scriptOrigin = \"http://localhost:8080\";"));
+    assert!(module.contains(&format!("/trace/app_{}.js", service.tracking_id)));
+    assert!(module.contains("navigator.doNotTrack"));
+
+    // A foreign page still can't load it
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/trace/app_{}.esm.js", service.tracking_id))
+                .header("Origin", "https://elsewhere.example")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // The classic script still needs an allowed origin
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_tracker_script_precompressed() {
+    use shymini::db;
+    use shymini::domain::CreateService;
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    let uri = format!("/trace/app_{}.js", service.tracking_id);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(&uri)
+                .header("Accept-Encoding", "gzip")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("content-encoding")
+            .and_then(|v| v.to_str().ok()),
+        Some("gzip")
+    );
+    let etag = response
+        .headers()
+        .get("etag")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    assert!(etag.ends_with("-gzip\""), "Unexpected ETag {}", etag);
+
+    // gzip magic bytes
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..2], &[0x1f, 0x8b]);
+}
+
+#[tokio::test]
+async fn test_pixel_served_for_valid_service() {
+    use shymini::db;
+    use shymini::domain::CreateService;
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    // Create a service
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Pixel Test Service".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Request the pixel using the tracking_id
+    let uri = format!("/trace/px_{}.gif", service.tracking_id);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(&uri)
+                .header("Origin", "https://example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
         "Expected 200 OK for pixel tracking_id {}, got {}",
         service.tracking_id,
         response.status()
@@ -305,7 +1202,7 @@ async fn test_pixel_served_for_valid_service() {
 #[tokio::test]
 async fn test_tracking_id_is_stable() {
     use shymini::db;
-    use shymini::domain::CreateService;
+    use shymini::domain::{CreateService, OrganizationId};
 
     let (_, pool) = create_test_app_with_pool().await;
 
@@ -317,11 +1214,39 @@ async fn test_tracking_id_is_stable() {
             link: String::new(),
             origins: "*".to_string(),
             respect_dnt: false,
+            respect_gpc: false,
             ignore_robots: false,
             collect_ips: true,
             ignored_ips: String::new(),
             hide_referrer_regex: String::new(),
             script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
         },
     )
     .await
@@ -339,7 +1264,9 @@ async fn test_tracking_id_is_stable() {
     }
 
     // Also test via list_services
-    let all_services = db::list_services(&pool).await.unwrap();
+    let all_services = db::list_services(&pool, OrganizationId::DEFAULT)
+        .await
+        .unwrap();
     let found = all_services.iter().find(|s| s.id == service.id).unwrap();
     assert_eq!(
         found.tracking_id.0, original_tracking_id.0,
@@ -373,11 +1300,39 @@ async fn test_migrations_are_idempotent() {
             link: String::new(),
             origins: "*".to_string(),
             respect_dnt: false,
+            respect_gpc: false,
             ignore_robots: false,
             collect_ips: true,
             ignored_ips: String::new(),
             hide_referrer_regex: String::new(),
             script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
         },
     )
     .await
@@ -389,3 +1344,6006 @@ async fn test_migrations_are_idempotent() {
         "Service should have a tracking_id"
     );
 }
+
+// Session cookie pair ("name=value") from a response's Set-Cookie headers
+fn session_cookie(response: &axum::response::Response) -> Option<String> {
+    response
+        .headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|v| v.split(';').next())
+        .find(|v| v.starts_with("shymini_session="))
+        .map(str::to_string)
+}
+
+#[tokio::test]
+async fn test_service_in_other_organization_not_found() {
+    use shymini::db;
+    use shymini::domain::{CreateOrganization, CreateService};
+
+    let (app, pool) = create_test_app_with_pool().await;
+
+    let organization = db::create_organization(
+        &pool,
+        CreateOrganization {
+            name: "Other Org".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(organization.slug, "other-org");
+
+    let service = db::create_service(
+        &pool,
+        CreateService {
+            name: "Other Service".to_string(),
+            link: String::new(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: Some(organization.id),
+        },
+    )
+    .await
+    .unwrap();
+
+    // The dashboard and API show the default organization
+    for uri in [
+        format!("/service/{}", service.id),
+        format!("/api/services/{}", service.id),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/services")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["data"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_multi_tenant_requires_login() {
+    let (app, _) = create_test_app_with(|settings| settings.multi_tenant = true).await;
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], "/login");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .header("HX-Request", "true")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["hx-redirect"], "/login");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/services")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/login")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_signup_and_login() {
+    let (app, _) = create_test_app_with(|settings| settings.multi_tenant = true).await;
+
+    // The first account can always be created
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Ada&email=Ada%40example.com&password=short",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Ada&email=Ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let cookie = session_cookie(&response).expect("signup should log in");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .header("Cookie", &cookie)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Default"));
+
+    // Further signups are closed unless enabled
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Bob&email=bob%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], "/login");
+    assert!(session_cookie(&response).is_none());
+
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/login",
+            "email=ada%40example.com&password=battery+staple",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .oneshot(post_form(
+            "/login",
+            "email=ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(session_cookie(&response).is_some());
+}
+
+#[tokio::test]
+async fn test_api_token_auth() {
+    use shymini::domain::{CreateOrganization, CreateService, Role};
+    use shymini::{auth, db};
+
+    let (app, pool) = create_test_app_with(|settings| settings.multi_tenant = true).await;
+
+    let organization = db::create_organization(
+        &pool,
+        CreateOrganization {
+            name: "Acme".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let token = auth::generate_token(auth::API_TOKEN_PREFIX);
+    db::create_api_token(
+        &pool,
+        organization.id,
+        "ci",
+        &auth::hash_token(&token),
+        Role::Viewer,
+        None,
+    )
+    .await
+    .unwrap();
+
+    for (name, organization_id) in [("Acme Site", Some(organization.id)), ("Default Site", None)] {
+        db::create_service(
+            &pool,
+            CreateService {
+                name: name.to_string(),
+                link: String::new(),
+                origins: "*".to_string(),
+                respect_dnt: false,
+                respect_gpc: false,
+                ignore_robots: false,
+                collect_ips: true,
+                ignored_ips: String::new(),
+                hide_referrer_regex: String::new(),
+                script_inject: String::new(),
+                collapse_tabs: true,
+                hit_quota: 0,
+                quota_behavior: Default::default(),
+                heartbeat_frequency_ms: 0,
+                idle_timeout_mins: 30,
+                public_badge: false,
+                content_groups: String::new(),
+                active_user_timeout_ms: 0,
+                lowercase_paths: false,
+                strip_trailing_slash: false,
+                path_patterns: String::new(),
+                time_zone: String::new(),
+                default_range: Default::default(),
+                signed_pixel_ids: false,
+                bounce_rule: Default::default(),
+                bounce_threshold_secs: 10,
+                session_timeout_mins: 0,
+                week_start: None,
+                debug_log: false,
+                link_users: false,
+                hide_internal_referrers: false,
+                track_errors: false,
+                allowed_countries: String::new(),
+                blocked_countries: String::new(),
+                allowed_asns: String::new(),
+                blocked_asns: String::new(),
+                organization_id,
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/services")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let services = json["data"].as_array().unwrap();
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0]["name"], "Acme Site");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/organization")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"]["slug"], "acme");
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/services")
+                .header("Authorization", "Bearer shy_not-a-token")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let tokens = db::list_api_tokens(&pool, organization.id).await.unwrap();
+    assert!(tokens[0].last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn test_org_switcher() {
+    let app = create_test_app().await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/organizations/switcher")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("href=\"/organization\""));
+    // No login in single-tenant mode, so nothing to log out of
+    assert!(!html.contains("/logout"));
+}
+
+// Path of the link (e.g. "/invite/...") in the last mail the test mailer sent
+fn mailed_link(state: &shymini::state::AppState, prefix: &str) -> String {
+    let sent = state.mailer.sent();
+    let body = &sent.last().expect("no mail was sent").body;
+    let start = body.find(prefix).expect("mail has no such link");
+    body[start..].split_whitespace().next().unwrap().to_string()
+}
+
+fn post_form(uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_invite_flow() {
+    use shymini::db;
+    use shymini::domain::{OrganizationId, Role};
+
+    let (app, state) = create_test_app_with_state(|settings| settings.multi_tenant = true).await;
+
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Ada&email=ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    let cookie = session_cookie(&response).unwrap();
+
+    let mut request = post_form(
+        "/organization/members",
+        "email=Bob%40example.com&role=editor",
+    );
+    request
+        .headers_mut()
+        .insert("Cookie", cookie.parse().unwrap());
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Invitation sent"));
+    assert_eq!(state.mailer.sent()[0].to, "bob@example.com");
+
+    let link = mailed_link(&state, "/invite/");
+    let token = link.trim_start_matches("/invite/");
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&link).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("bob@example.com"));
+
+    // Signup is closed, but the invite opens it for the invited address only
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            &format!(
+                "name=Eve&email=eve%40example.com&password=correct+horse&invite={}",
+                token
+            ),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .oneshot(post_form(
+            "/signup",
+            &format!(
+                "name=Bob&email=bob%40example.com&password=correct+horse&invite={}",
+                token
+            ),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(session_cookie(&response).is_some());
+
+    let bob = db::get_user_by_email(&state.pool, "bob@example.com")
+        .await
+        .unwrap();
+    assert!(bob.email_verified_at.is_some());
+    let organizations = db::list_user_organizations(&state.pool, bob.id)
+        .await
+        .unwrap();
+    assert_eq!(organizations.len(), 1);
+    assert_eq!(organizations[0].id, OrganizationId::DEFAULT);
+    assert_eq!(
+        db::get_membership_role(&state.pool, OrganizationId::DEFAULT, bob.id)
+            .await
+            .unwrap(),
+        Role::Editor
+    );
+}
+
+#[tokio::test]
+async fn test_signup_requires_email_verification() {
+    use shymini::{auth, db};
+
+    let (app, state) = create_test_app_with_state(|settings| {
+        settings.multi_tenant = true;
+        settings.signup_enabled = true;
+    })
+    .await;
+
+    let password_hash = auth::hash_password("correct horse").unwrap();
+    db::create_user(
+        &state.pool,
+        "admin@example.com",
+        "Admin",
+        &password_hash,
+        true,
+    )
+    .await
+    .unwrap();
+
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Carol&email=carol%40example.com&password=correct+horse&organization=Carol+Co",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(session_cookie(&response).is_none());
+
+    let login = "email=carol%40example.com&password=correct+horse";
+    let response = app
+        .clone()
+        .oneshot(post_form("/login", login))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let link = mailed_link(&state, "/verify-email/");
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&link).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(session_cookie(&response).is_some());
+
+    let response = app.oneshot(post_form("/login", login)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn test_password_reset() {
+    use shymini::{auth, db};
+
+    let (app, state) = create_test_app_with_state(|settings| settings.multi_tenant = true).await;
+
+    let password_hash = auth::hash_password("correct horse").unwrap();
+    db::create_user(&state.pool, "ada@example.com", "Ada", &password_hash, true)
+        .await
+        .unwrap();
+
+    // Unknown addresses get the same answer and no mail
+    let response = app
+        .clone()
+        .oneshot(post_form("/password-reset", "email=nobody%40example.com"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(state.mailer.sent().is_empty());
+
+    let response = app
+        .clone()
+        .oneshot(post_form("/password-reset", "email=ada%40example.com"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let link = mailed_link(&state, "/password-reset/");
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&link).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(post_form(&link, "password=battery+staple"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], "/login");
+
+    // The link works once
+    let response = app
+        .clone()
+        .oneshot(post_form(&link, "password=another+one"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .oneshot(post_form(
+            "/login",
+            "email=ada%40example.com&password=battery+staple",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn test_oidc_endpoints() {
+    // Without a provider there is no single sign-on
+    let (app, _) = create_test_app_with(|settings| settings.multi_tenant = true).await;
+    for uri in ["/auth/oidc/login", "/auth/oidc/callback?code=c&state=s"] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/login")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(!String::from_utf8_lossy(&body).contains("/auth/oidc/login"));
+
+    let (app, _) = create_test_app_with(|settings| {
+        settings.multi_tenant = true;
+        settings.oidc_issuer = Some("http://127.0.0.1:9".to_string());
+        settings.oidc_client_id = Some("shymini".to_string());
+    })
+    .await;
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/login")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("/auth/oidc/login"));
+
+    // A callback without the login cookie is refused before the provider is
+    // contacted
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/auth/oidc/callback?code=c&state=s")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_proxy_auth() {
+    use axum::extract::ConnectInfo;
+    use shymini::db;
+    use std::net::SocketAddr;
+
+    let (app, pool) = create_test_app_with(|settings| {
+        settings.multi_tenant = true;
+        settings.proxy_user_header = Some("Remote-User".to_string());
+        settings.proxy_trusted_networks = "10.0.0.0/8".to_string();
+    })
+    .await;
+    let request = |peer: Option<&str>, user: Option<&str>| {
+        let mut builder = Request::builder().uri("/");
+        if let Some(peer) = peer {
+            let addr: SocketAddr = peer.parse().unwrap();
+            builder = builder.extension(ConnectInfo(addr));
+        }
+        if let Some(user) = user {
+            builder = builder
+                .header("Remote-User", user)
+                .header("Remote-Groups", "default, unknown");
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+
+    // Only the proxy's network may reach the dashboard
+    let response = app
+        .clone()
+        .oneshot(request(Some("192.168.1.10:5000"), Some("ada@example.com")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .clone()
+        .oneshot(request(None, Some("ada@example.com")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Without the header, the usual login applies
+    let response = app
+        .clone()
+        .oneshot(request(Some("10.0.0.2:5000"), None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], "/login");
+
+    // A named user is created and joins the organizations from the groups
+    // header that exist
+    let response = app
+        .clone()
+        .oneshot(request(Some("10.0.0.2:5000"), Some("Ada@Example.com")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let user = db::get_user_by_email(&pool, "ada@example.com")
+        .await
+        .unwrap();
+    assert!(user.email_verified_at.is_some());
+    let organizations = db::list_user_organizations(&pool, user.id).await.unwrap();
+    assert_eq!(organizations.len(), 1);
+    assert_eq!(organizations[0].slug, "default");
+
+    // Later requests find the same user
+    let response = app
+        .oneshot(request(Some("10.0.0.2:5000"), Some("ada@example.com")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(db::count_users(&pool).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_roles() {
+    use shymini::domain::{CreateService, OrganizationId, Role};
+    use shymini::{auth, db};
+
+    let (app, state) = create_test_app_with_state(|settings| settings.multi_tenant = true).await;
+
+    // The first account owns the default organization
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Ada&email=ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    let owner = session_cookie(&response).unwrap();
+
+    let bob = db::create_user(&state.pool, "bob@example.com", "Bob", "", true)
+        .await
+        .unwrap();
+    db::add_membership(&state.pool, OrganizationId::DEFAULT, bob.id, Role::Viewer)
+        .await
+        .unwrap();
+    let viewer = auth::start_login(&state, bob.id).await.unwrap();
+    let viewer = viewer.split(';').next().unwrap().to_string();
+
+    let service = db::create_service(
+        &state.pool,
+        CreateService {
+            name: "Site".to_string(),
+            link: String::new(),
+            origins: "*".to_string(),
+            respect_dnt: false,
+            respect_gpc: false,
+            ignore_robots: false,
+            collect_ips: true,
+            ignored_ips: String::new(),
+            hide_referrer_regex: String::new(),
+            script_inject: String::new(),
+            collapse_tabs: true,
+            hit_quota: 0,
+            quota_behavior: Default::default(),
+            heartbeat_frequency_ms: 0,
+            idle_timeout_mins: 30,
+            public_badge: false,
+            content_groups: String::new(),
+            active_user_timeout_ms: 0,
+            lowercase_paths: false,
+            strip_trailing_slash: false,
+            path_patterns: String::new(),
+            time_zone: String::new(),
+            default_range: Default::default(),
+            signed_pixel_ids: false,
+            bounce_rule: Default::default(),
+            bounce_threshold_secs: 10,
+            session_timeout_mins: 0,
+            week_start: None,
+            debug_log: false,
+            link_users: false,
+            hide_internal_referrers: false,
+            track_errors: false,
+            allowed_countries: String::new(),
+            blocked_countries: String::new(),
+            allowed_asns: String::new(),
+            blocked_asns: String::new(),
+            organization_id: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    let get = |uri: &str, cookie: &str| {
+        Request::builder()
+            .uri(uri)
+            .header("Cookie", cookie)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let post = |uri: &str, body: &str, cookie: &str| {
+        let mut request = post_form(uri, body);
+        request
+            .headers_mut()
+            .insert("Cookie", cookie.parse().unwrap());
+        request
+    };
+
+    // Viewers see stats but change nothing
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/service/{}", service.id), &viewer))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(!String::from_utf8_lossy(&body).contains("/manage"));
+    for request in [
+        get("/service/new", &viewer),
+        post(
+            &format!("/service/{}/views", service.id),
+            "name=Mine",
+            &viewer,
+        ),
+        get("/organization", &viewer),
+        post("/organization/members", "email=eve%40example.com", &viewer),
+    ] {
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    // Owners change roles, but not their own
+    let response = app
+        .clone()
+        .oneshot(post(
+            &format!("/organization/members/{}/role", bob.id),
+            "role=editor",
+            &owner,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let ada = db::get_user_by_email(&state.pool, "ada@example.com")
+        .await
+        .unwrap();
+    let response = app
+        .clone()
+        .oneshot(post(
+            &format!("/organization/members/{}/role", ada.id),
+            "role=viewer",
+            &owner,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Editors edit but do not delete
+    let response = app
+        .clone()
+        .oneshot(get("/service/new", &viewer))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(post(
+            &format!("/service/{}/delete", service.id),
+            "",
+            &viewer,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .clone()
+        .oneshot(post(&format!("/service/{}/delete", service.id), "", &owner))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // API tokens carry a role too
+    let token = auth::generate_token(auth::API_TOKEN_PREFIX);
+    db::create_api_token(
+        &state.pool,
+        OrganizationId::DEFAULT,
+        "dashboards",
+        &auth::hash_token(&token),
+        Role::Viewer,
+        None,
+    )
+    .await
+    .unwrap();
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/api/services/{}/views", service.id))
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"name":"Mine"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_login_throttling() {
+    use shymini::db;
+    use shymini::domain::LoginOutcome;
+
+    let (app, pool) = create_test_app_with(|settings| {
+        settings.multi_tenant = true;
+        settings.login_max_failures = 3;
+    })
+    .await;
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Ada&email=ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    for _ in 0..3 {
+        let response = app
+            .clone()
+            .oneshot(post_form(
+                "/login",
+                "email=ada%40example.com&password=battery+staple",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // Locked out, even with the right password and from another address
+    let mut request = post_form("/login", "email=ada%40example.com&password=correct+horse");
+    request
+        .headers_mut()
+        .insert("X-Forwarded-For", "203.0.113.9".parse().unwrap());
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(session_cookie(&response).is_none());
+
+    // Other accounts are unaffected
+    let response = app
+        .oneshot(post_form(
+            "/login",
+            "email=bob%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let attempts = db::list_login_attempts(&pool, "ada@example.com", 10)
+        .await
+        .unwrap();
+    let outcomes: Vec<_> = attempts.iter().map(|a| a.outcome).collect();
+    assert_eq!(
+        outcomes,
+        [
+            LoginOutcome::Locked,
+            LoginOutcome::WrongPassword,
+            LoginOutcome::WrongPassword,
+            LoginOutcome::WrongPassword,
+        ]
+    );
+    assert_eq!(attempts[0].ip.as_deref(), Some("203.0.113.9"));
+}
+
+#[tokio::test]
+async fn test_two_factor_login() {
+    use chrono::Utc;
+    use shymini::auth::totp;
+
+    let (app, _) = create_test_app_with(|settings| settings.multi_tenant = true).await;
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/signup",
+            "name=Ada&email=ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    let cookie = session_cookie(&response).unwrap();
+    let post = |uri: &str, body: &str| {
+        let mut request = post_form(uri, body);
+        request
+            .headers_mut()
+            .insert("Cookie", cookie.parse().unwrap());
+        request
+    };
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/account")
+                .header("Cookie", &cookie)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("otpauth://totp/"));
+
+    // Turning it on takes a matching code
+    let secret = totp::generate_secret();
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/account/two-factor",
+            &format!("secret={}&code=000000", secret),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let code = totp::code_at(&secret, Utc::now()).unwrap();
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/account/two-factor",
+            &format!("secret={}&code={}", secret, code),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Two-factor authentication is on."));
+
+    // The password alone only gets to the code form
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/login",
+            "email=ada%40example.com&password=correct+horse",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(session_cookie(&response).is_none());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    let start = html.find(r#"name="token" value=""#).unwrap() + 20;
+    let token = html[start..].split('"').next().unwrap().to_string();
+
+    let wrong = if code == "000000" { "111111" } else { "000000" };
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/login/two-factor",
+            &format!("token={}&code={}", token, wrong),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .clone()
+        .oneshot(post_form(
+            "/login/two-factor",
+            &format!("token={}&code={}", token, code),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(session_cookie(&response).is_some());
+
+    // A forged token gets nowhere
+    let response = app
+        .oneshot(post_form(
+            "/login/two-factor",
+            &format!("token=bogus&code={}", code),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Serve `html` at `/` on a local port, standing in for a service's site
+async fn serve_site(html: String) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let site = Router::new().route(
+        "/",
+        axum::routing::get(move || {
+            let html = html.clone();
+            async move { axum::response::Html(html) }
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, site).await.unwrap() });
+    format!("http://{}/", addr)
+}
+
+#[tokio::test]
+async fn test_verify_install() {
+    use shymini::db;
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let set_link = |link: String| {
+        db::update_service(
+            &app.state.pool,
+            service.id,
+            UpdateService {
+                link: Some(link),
+                ..Default::default()
+            },
+        )
+    };
+
+    let link = serve_site(format!(
+        r#"<html><head><script defer src="http://localhost:8080/trace/app_{}.js"></script></head></html>"#,
+        service.tracking_id
+    ))
+    .await;
+    set_link(link).await.unwrap();
+
+    let json = app
+        .get_json(&format!("/api/services/{}/verify-install", service.id))
+        .await;
+    assert_eq!(json["data"]["installed"], true);
+    assert_eq!(json["data"]["trackers"], serde_json::json!(["Js"]));
+    assert_eq!(json["data"]["issues"], serde_json::json!([]));
+
+    // The dashboard shows the latest check
+    let response = app
+        .get(&format!("/service/{}/install-status", service.id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Tracker installed"));
+
+    // A snippet copied from another service
+    let link = serve_site(
+        r#"<img src="http://localhost:8080/trace/px_zzz99999.gif" style="display:none">"#
+            .to_string(),
+    )
+    .await;
+    set_link(link).await.unwrap();
+    let json = app
+        .get_json(&format!("/api/services/{}/verify-install", service.id))
+        .await;
+    assert_eq!(json["data"]["installed"], false);
+    assert_eq!(
+        json["data"]["issues"],
+        serde_json::json!([{"issue": "wrong_tracking_id", "found": ["zzz99999"]}])
+    );
+
+    let response = app
+        .get(&format!("/service/{}/install-status", service.id))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Tracker of another service"));
+}
+
+/// A local webhook receiver, and the JSON bodies posted to it
+async fn webhook_receiver() -> (
+    String,
+    std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>,
+) {
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hook = Router::new().route(
+        "/hook",
+        axum::routing::post({
+            let received = received.clone();
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                received.lock().unwrap().push(body);
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, hook).await.unwrap() });
+    (format!("http://{}/hook", addr), received)
+}
+
+#[tokio::test]
+async fn test_uptime_monitor() {
+    use chrono::Duration;
+    use shymini::db;
+    use shymini::domain::UpdateService;
+    use shymini::monitor;
+
+    let (hook_url, received) = webhook_receiver().await;
+    let app = common::TestApp::with(|settings| {
+        settings.monitor_interval_secs = 60;
+        settings.monitor_webhook_url = Some(hook_url);
+    })
+    .await;
+    let service = app.service("Site").await;
+    let set_link = |link: String| {
+        db::update_service(
+            &app.state.pool,
+            service.id,
+            UpdateService {
+                link: Some(link),
+                ..Default::default()
+            },
+        )
+    };
+    let client = monitor::client();
+
+    let up_link = serve_site("<html></html>".to_string()).await;
+    // Nothing listens on a port that was just given back
+    let down_link = {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    };
+
+    // Up from the start: recorded, nothing to alert about
+    set_link(up_link.clone()).await.unwrap();
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    let latest = db::get_latest_monitor_check(&app.state.pool, service.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(latest.up);
+    assert_eq!(latest.status_code, Some(200));
+    assert!(latest.latency_ms.is_some());
+    assert!(received.lock().unwrap().is_empty());
+
+    // Going down alerts once
+    set_link(down_link).await.unwrap();
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    {
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["event"], "down");
+        assert_eq!(received[0]["service_name"], "Site");
+        assert_eq!(received[0]["check"]["up"], false);
+    }
+
+    // So does coming back
+    set_link(up_link).await.unwrap();
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    {
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1]["event"], "up");
+        assert_eq!(received[1]["check"]["status_code"], 200);
+    }
+
+    let uptime = db::get_uptime(&app.state.pool, service.id, app.now() - Duration::days(1))
+        .await
+        .unwrap();
+    assert_eq!((uptime.checks, uptime.up_checks), (4, 2));
+    assert_eq!(uptime.percent(), Some(50.0));
+    assert!(!uptime.is_down());
+
+    let response = app.get("/").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("50% up (24h)"));
+
+    // Checks past retention are dropped
+    app.clock.advance(Duration::days(31));
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    let uptime = db::get_uptime(&app.state.pool, service.id, common::start_time())
+        .await
+        .unwrap();
+    assert_eq!(uptime.checks, 1);
+}
+
+#[tokio::test]
+async fn test_expiry_monitor() {
+    use axum::response::IntoResponse;
+    use chrono::Duration;
+    use shymini::db;
+    use shymini::domain::UpdateService;
+    use shymini::monitor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // An RDAP server that only knows the registered domain
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let expires_at = common::start_time() + Duration::days(10);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let rdap_url = format!("http://{}", listener.local_addr().unwrap());
+    let rdap = Router::new().route(
+        "/domain/:name",
+        axum::routing::get({
+            let lookups = lookups.clone();
+            move |axum::extract::Path(name): axum::extract::Path<String>| async move {
+                lookups.fetch_add(1, Ordering::SeqCst);
+                if name != "example.test" {
+                    return StatusCode::NOT_FOUND.into_response();
+                }
+                axum::Json(serde_json::json!({
+                    "ldhName": name,
+                    "events": [{"eventAction": "expiration", "eventDate": expires_at.to_rfc3339()}]
+                }))
+                .into_response()
+            }
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, rdap).await.unwrap() });
+
+    let (hook_url, received) = webhook_receiver().await;
+    let app = common::TestApp::with(|settings| {
+        settings.monitor_interval_secs = 60;
+        settings.monitor_webhook_url = Some(hook_url);
+        settings.monitor_rdap_url = rdap_url;
+    })
+    .await;
+    let service = app.service("Site").await;
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            link: Some("http://www.example.test/".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let client = monitor::client();
+    let expiring = |received: &[serde_json::Value]| {
+        received
+            .iter()
+            .filter(|alert| alert["event"] == "domain_expiring")
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    // www.example.test, then example.test
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    let check = db::get_expiry_check(&app.state.pool, service.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(check.domain_expires_at, Some(expires_at));
+    // Plain HTTP has no certificate
+    assert_eq!(check.certificate_expires_at, None);
+    {
+        let alerts = expiring(&received.lock().unwrap());
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0]["kind"], "domain");
+        assert_eq!(alerts[0]["days_left"], 10);
+    }
+
+    let response = app.get("/").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Domain expires in 10 days"));
+
+    // Looked up again only a week later
+    app.clock.advance(Duration::days(1));
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    assert_eq!(expiring(&received.lock().unwrap()).len(), 1);
+
+    app.clock.advance(Duration::days(6));
+    monitor::run_checks(&app.state, &client).await.unwrap();
+    assert_eq!(lookups.load(Ordering::SeqCst), 4);
+    let alerts = expiring(&received.lock().unwrap());
+    assert_eq!(alerts.len(), 2);
+    assert_eq!(alerts[1]["days_left"], 3);
+}
+
+#[tokio::test]
+async fn test_visitors_badge() {
+    use chrono::Duration;
+    use shymini::db;
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let now = app.now();
+    let uri = format!("/badge/{}/visitors.svg", service.tracking_id);
+    let badge = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "image/svg+xml; charset=utf-8"
+        );
+        assert_eq!(response.headers()["cache-control"], "public, max-age=60");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    };
+
+    // Off unless the service allows it, and then just like an unknown ID
+    assert_eq!(app.get(&uri).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.get("/badge/zzz99999/visitors.svg").await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            public_badge: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    // Last month, earlier this month, and online right now
+    app.visit(&service, "may", &[("/", now - Duration::days(20))])
+        .await;
+    app.visit(&service, "june", &[("/", now - Duration::days(3))])
+        .await;
+    app.visit(&service, "now", &[("/", now)]).await;
+
+    let svg = badge(app.get(&uri).await).await;
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains(">2 this month · 1 online</text>"));
+
+    // Counts are cached for a while
+    app.visit(&service, "later", &[("/", now)]).await;
+    let svg = badge(app.get(&uri).await).await;
+    assert!(svg.contains(">2 this month · 1 online</text>"));
+}
+
+#[tokio::test]
+async fn test_sitemap_orphan_pages() {
+    use chrono::Duration;
+    use shymini::domain::UpdateService;
+    use shymini::{crawler, db};
+    use std::io::Write;
+
+    // A sitemap index pointing at a plain and a gzipped sitemap, plus one
+    // on another host that is skipped
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let index = format!(
+        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+             <sitemap><loc>{base}/sitemap-posts.xml</loc></sitemap>
+             <sitemap><loc>{base}/sitemap-pages.xml.gz</loc></sitemap>
+             <sitemap><loc>https://elsewhere.test/sitemap.xml</loc></sitemap>
+           </sitemapindex>"#
+    );
+    let posts = format!(
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+             <url><loc>{base}/blog/first</loc></url>
+             <url><loc>{base}/blog/second</loc></url>
+             <url><loc>https://elsewhere.test/blog</loc></url>
+           </urlset>"#
+    );
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    write!(
+        gzip,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+             <url><loc>{base}/</loc></url>
+             <url><loc>{base}/about</loc></url>
+           </urlset>"#
+    )
+    .unwrap();
+    let pages = gzip.finish().unwrap();
+    let site = Router::new()
+        .route("/sitemap.xml", axum::routing::get(move || async { index }))
+        .route(
+            "/sitemap-posts.xml",
+            axum::routing::get(move || async { posts }),
+        )
+        .route(
+            "/sitemap-pages.xml.gz",
+            axum::routing::get(move || async { pages }),
+        );
+    tokio::spawn(async move { axum::serve(listener, site).await.unwrap() });
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            link: Some(format!("{}/", base)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    crawler::run_crawl(&app.state, &crawler::client())
+        .await
+        .unwrap();
+
+    let now = app.now();
+    let first = format!("{}/blog/first", base);
+    let about = format!("{}/about?ref=nav", base);
+    app.visit(
+        &service,
+        "reader",
+        &[
+            (first.as_str(), now - Duration::hours(2)),
+            (about.as_str(), now - Duration::hours(1)),
+        ],
+    )
+    .await;
+
+    let orphans = db::get_orphan_pages(
+        &app.state.pool,
+        service.id,
+        now - Duration::days(1),
+        now + Duration::days(1),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        orphans,
+        vec![format!("{}/", base), format!("{}/blog/second", base)]
+    );
+
+    let response = app.get(&format!("/service/{}/locations", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("Orphan pages"));
+    assert!(body.contains(&format!("{}/blog/second", base)));
+
+    // A sitemap that can't be read keeps the pages from the last crawl
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            link: Some("http://127.0.0.1:9/".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    crawler::run_crawl(&app.state, &crawler::client())
+        .await
+        .unwrap();
+    let orphans = db::get_orphan_pages(
+        &app.state.pool,
+        service.id,
+        now - Duration::days(1),
+        now + Duration::days(1),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(orphans.len(), 2);
+}
+
+#[tokio::test]
+async fn test_search() {
+    use chrono::Duration;
+    use shymini::db;
+    use shymini::domain::{
+        CreateHit, CreateOrganization, CreateService, SearchResultKind, TrackerType,
+    };
+
+    let app = common::TestApp::new().await;
+    let pool = &app.state.pool;
+    let now = app.now();
+    let blog = app.service("Company Blog").await;
+    let session = app
+        .visit(
+            &blog,
+            "alice@example.com",
+            &[("/blog/hello?utm_source=x", now - Duration::hours(1))],
+        )
+        .await;
+    db::create_hit(
+        pool,
+        CreateHit {
+            session_id: session.id,
+            service_id: blog.id,
+            initial: false,
+            start_time: now,
+            tracker: TrackerType::Js,
+            location: "/blog/hello#comments".to_string(),
+            referrer: "https://news.blogfeed.test/item?id=1".to_string(),
+            load_time: None,
+            environment: Default::default(),
+            prefetched: false,
+            referrer_spam: false,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Nothing of another organization's is found
+    let other = db::create_organization(
+        pool,
+        CreateOrganization {
+            name: "Other".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db::create_service(
+        pool,
+        CreateService {
+            name: "Other Blog".to_string(),
+            organization_id: Some(other.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let found = db::search_services(pool, blog.organization_id, "BLOG", 20)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].service_id, blog.id);
+
+    // The query string and fragment are dropped, so both hits are one page
+    let since = now - Duration::days(30);
+    let found = db::search_pages(pool, blog.organization_id, "hello", since, 20)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].kind, SearchResultKind::Page);
+    assert_eq!(found[0].value, "/blog/hello");
+
+    // Only the domain is matched, not the rest of the referrer
+    let found = db::search_referrer_domains(pool, blog.organization_id, "blogfeed", since, 20)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].value, "news.blogfeed.test");
+    assert!(
+        db::search_referrer_domains(pool, blog.organization_id, "item", since, 20)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Wildcards are taken literally
+    assert!(
+        db::search_sessions(pool, blog.organization_id, "%", None, 20)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let found = db::search_sessions(pool, blog.organization_id, "alice", None, 20)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].session_id, Some(session.id));
+    let found = db::search_sessions(
+        pool,
+        blog.organization_id,
+        &session.id.to_string(),
+        None,
+        20,
+    )
+    .await
+    .unwrap();
+    assert_eq!(found[0].value, "alice@example.com");
+
+    let response = app.get("/search?q=blog").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains(&format!("href=\"/service/{}\"", blog.id)));
+    assert!(body.contains("/blog/hello"));
+    assert!(body.contains("news.blogfeed.test"));
+    assert!(!body.contains("Other Blog"));
+
+    let response = app.get("/search?q=alice").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains(&format!("/service/{}/sessions/{}", blog.id, session.id)));
+
+    let response = app.get("/search?q=nothing-like-this").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Nothing found"));
+}
+
+#[tokio::test]
+async fn test_dashboard_index_trends() {
+    use chrono::Duration;
+    use shymini::db;
+
+    let app = common::TestApp::new().await;
+    let now = app.now();
+    let blog = app.service("Blog").await;
+    let shop = app.service("Shop").await;
+    app.visit(
+        &blog,
+        "a",
+        &[
+            ("/", now - Duration::hours(1)),
+            ("/about", now - Duration::minutes(30)),
+        ],
+    )
+    .await;
+    app.visit(
+        &blog,
+        "b",
+        &[("/", now - Duration::days(3) - Duration::hours(1))],
+    )
+    .await;
+    // Too old for the trend
+    app.visit(&blog, "c", &[("/", now - Duration::days(8))])
+        .await;
+
+    let trends = db::get_daily_trends(&app.state.pool, &[blog.id, shop.id], now, None, 7)
+        .await
+        .unwrap();
+    assert_eq!(trends[&blog.id].sessions, vec![0, 0, 0, 1, 0, 0, 1]);
+    assert_eq!(trends[&blog.id].hits, vec![0, 0, 0, 1, 0, 0, 2]);
+    assert_eq!(trends[&shop.id].hits, vec![0; 7]);
+
+    let counts = db::get_basic_counts_bulk(
+        &app.state.pool,
+        &[blog.id, shop.id],
+        now - Duration::days(1),
+        now,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(counts.get(&blog.id), Some(&(1, 2)));
+    assert_eq!(counts.get(&shop.id), None);
+    assert!(
+        db::get_basic_counts_bulk(&app.state.pool, &[], now - Duration::days(1), now, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let response = app.get("/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(body.matches("<polyline").count(), 4);
+    assert!(body.contains("points=\"0.0,23.0 16.7,23.0 33.3,23.0 50.0,1.0"));
+}
+
+#[tokio::test]
+async fn test_exclude_me() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Own site").await;
+    let page = format!("/exclude-me/{}", service.tracking_id);
+
+    let response = app.get(&page).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("are counted"));
+    assert_eq!(
+        app.get("/exclude-me/zzz99999").await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri(&page)
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from("exclude=true"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let set_cookie = response.headers()["set-cookie"].to_str().unwrap();
+    assert!(set_cookie.starts_with(&format!("shymini_exclude_{}=1;", service.tracking_id)));
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    let response = app
+        .send(
+            Request::builder()
+                .uri(&page)
+                .header("Cookie", &cookie)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("are not counted"));
+
+    // The excluded browser gets the inert script, and its posts are dropped
+    let response = app
+        .send(
+            Request::builder()
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Origin", "https://example.com")
+                .header("Cookie", &cookie)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["vary"]
+        .to_str()
+        .unwrap()
+        .contains("Cookie"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"var shymini = { dnt: true };");
+
+    for cookie in [Some(cookie.as_str()), None] {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Origin", "https://example.com")
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            );
+        if let Some(cookie) = cookie {
+            request = request.header("Cookie", cookie);
+        }
+        let response = app
+            .send(
+                request
+                    .body(Body::from(
+                        r#"{"idempotency":"own","location":"https://example.com/","loadTime":100}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+}
+
+#[tokio::test]
+async fn test_environments() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Environments").await;
+
+    let response = app
+        .get(&format!(
+            "/trace/app_{}.js?env=staging",
+            service.tracking_id
+        ))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(&format!(
+        "/trace/app_{}.js?env=staging",
+        service.tracking_id
+    )));
+
+    // Production by default, dev when detected from the origin, or whatever
+    // the embed code asks for
+    for (query, origin) in [
+        ("", "https://example.com"),
+        ("", "http://localhost:3000"),
+        ("?env=staging", "https://example.com"),
+    ] {
+        let response = app
+            .send(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/trace/app_{}.js{}", service.tracking_id, query))
+                    .header("Origin", origin)
+                    .header("Content-Type", "application/json")
+                    .header(
+                        "User-Agent",
+                        "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+                    )
+                    .body(Body::from(format!(
+                        r#"{{"idempotency":"{origin}{query}","location":"{origin}/","loadTime":100}}"#
+                    )))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    for (env, hits) in [
+        ("", 1),
+        ("?env=dev", 1),
+        ("?env=staging", 1),
+        ("?env=all", 3),
+    ] {
+        let stats = app
+            .get_json(&format!("/api/services/{}/stats{}", service.id, env))
+            .await;
+        assert_eq!(stats["data"]["hit_count"], hits, "{env}");
+        assert_eq!(stats["data"]["session_count"], hits, "{env}");
+    }
+
+    let response = app
+        .get(&format!("/service/{}/stats?env=all", service.id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_path_normalization() {
+    use shymini::db;
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Normalized").await;
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            lowercase_paths: Some(true),
+            strip_trailing_slash: Some(true),
+            path_patterns: Some("/users/:id".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    for (i, location) in [
+        "https://example.com/users/1/",
+        "https://example.com/Users/2",
+        "https://example.com/About/?ref=x",
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let response = app
+            .send(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/trace/app_{}.js", service.tracking_id))
+                    .header("Origin", "https://example.com")
+                    .header("Content-Type", "application/json")
+                    // A visitor each, so no hit is collapsed into another
+                    .header(
+                        "User-Agent",
+                        format!("Mozilla/5.0 (X11; Linux x86_64) Firefox/12{i}.0"),
+                    )
+                    .body(Body::from(format!(
+                        r#"{{"idempotency":"page-{i}","location":"{location}","loadTime":100}}"#
+                    )))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    let locations = stats["data"]["locations"].as_array().unwrap();
+    assert_eq!(locations.len(), 2, "{locations:?}");
+    assert_eq!(locations[0]["value"], "example.com/users/:id");
+    assert_eq!(locations[0]["count"], 2);
+    assert_eq!(locations[1]["value"], "example.com/about");
+}
+
+#[tokio::test]
+async fn test_time_zone_defaults() {
+    use shymini::db;
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::with(|settings| settings.multi_tenant = true).await;
+    let response = app
+        .send(post_form(
+            "/signup",
+            "name=Ada&email=ada%40example.com&password=correct+horse",
+        ))
+        .await;
+    let cookie = session_cookie(&response).unwrap();
+    let page = |uri: String| {
+        let request = Request::builder()
+            .uri(uri)
+            .header("Cookie", &cookie)
+            .body(Body::empty())
+            .unwrap();
+        async {
+            let response = app.send(request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8_lossy(&body).to_string()
+        }
+    };
+    let save = |time_zone: &str| {
+        let mut request = post_form("/account/settings", &format!("time_zone={}", time_zone));
+        request
+            .headers_mut()
+            .insert("Cookie", cookie.parse().unwrap());
+        app.send(request)
+    };
+
+    let service = app.service("Site").await;
+    let uri = format!("/service/{}", service.id);
+
+    // Without any setting, Pacific Time: 12:00 UTC is 05:00
+    let html = page(uri.clone()).await;
+    assert!(html.contains("America/Los_Angeles"));
+    assert!(html.contains("2024-06-15T05:00"));
+
+    // The service's time zone
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            time_zone: Some("Europe/Berlin".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let html = page(uri.clone()).await;
+    assert!(html.contains("Europe/Berlin"));
+    assert!(html.contains("2024-06-15T14:00"));
+
+    // The user's preference wins over the service's
+    let response = save("Asia%2FTokyo").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(r#"value="Asia/Tokyo""#));
+    let html = page(uri.clone()).await;
+    assert!(html.contains("2024-06-15T21:00"));
+
+    // As does the request's own
+    let html = page(format!("{}?tz=UTC", uri)).await;
+    assert!(html.contains("2024-06-15T12:00"));
+
+    // An unknown zone is not saved
+    save("Mars%2FOlympus").await;
+    let html = page(uri).await;
+    assert!(html.contains("2024-06-15T14:00"));
+}
+
+#[tokio::test]
+async fn test_default_date_range() {
+    use shymini::db;
+    use shymini::domain::{DateRangePreset, UpdateService};
+
+    let app = common::TestApp::with(|settings| settings.multi_tenant = true).await;
+    let response = app
+        .send(post_form(
+            "/signup",
+            "name=Ada&email=ada%40example.com&password=correct+horse",
+        ))
+        .await;
+    let cookie = session_cookie(&response).unwrap();
+    let page = |uri: String| {
+        let request = Request::builder()
+            .uri(uri)
+            .header("Cookie", &cookie)
+            .body(Body::empty())
+            .unwrap();
+        async {
+            let response = app.send(request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8_lossy(&body).to_string()
+        }
+    };
+
+    let service = app.service("Site").await;
+    let uri = format!("/service/{}", service.id);
+
+    // The last 30 days, in Pacific Time
+    let html = page(uri.clone()).await;
+    assert!(html.contains(r#"value="2024-05-16T05:00""#));
+    assert!(html.contains(r#"<option value="30d" selected>"#));
+
+    // The service's default range
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            default_range: Some(DateRangePreset::Week),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let html = page(uri.clone()).await;
+    assert!(html.contains(r#"value="2024-06-08T05:00""#));
+    assert!(html.contains(r#"<option value="7d" selected>"#));
+
+    // A range the user picks is remembered for the service
+    let html = page(format!("{}?range=24h", uri)).await;
+    assert!(html.contains(r#"value="2024-06-14T05:00""#));
+    let html = page(uri.clone()).await;
+    assert!(html.contains(r#"value="2024-06-14T05:00""#));
+    assert!(html.contains(r#"<option value="24h" selected>"#));
+
+    // Explicit dates are a custom range, and are not remembered
+    let html = page(format!("{}?startDate=2024-06-01T00:00", uri)).await;
+    assert!(html.contains(r#"<option value="" selected>"#));
+    let html = page(uri.clone()).await;
+    assert!(html.contains(r#"value="2024-06-14T05:00""#));
+}
+
+#[tokio::test]
+async fn test_api_default_date_range() {
+    use shymini::db;
+    use shymini::domain::{DateRangePreset, UpdateService};
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    app.visit(
+        &service,
+        "visitor",
+        &[("/", app.now() - chrono::Duration::days(3))],
+    )
+    .await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats_uri = |query: &str| format!("/api/services/{}/stats{}", service.id, query);
+
+    let stats = app.get_json(&stats_uri("")).await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+
+    // The service's default range
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            default_range: Some(DateRangePreset::Day),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let stats = app.get_json(&stats_uri("")).await;
+    assert_eq!(stats["data"]["hit_count"], 0);
+
+    // Unless the query names one
+    let stats = app.get_json(&stats_uri("?range=7d")).await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+}
+
+#[tokio::test]
+async fn test_service_report_pdf() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Example Site").await;
+    app.visit(
+        &service,
+        "visitor",
+        &[("/pricing", app.now() - chrono::Duration::days(1))],
+    )
+    .await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let response = app
+        .get(&format!("/api/services/{}/report.pdf?tz=UTC", service.id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/pdf");
+    assert_eq!(
+        response.headers()["content-disposition"],
+        r#"attachment; filename="example-site-2024-06-15.pdf""#
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.starts_with(b"%PDF-"));
+    let text = String::from_utf8_lossy(&body);
+    assert!(text.contains("(Example Site) Tj"));
+    assert!(text.contains("(2024-05-16 12:00 to 2024-06-15 12:00 \\(UTC\\)) Tj"));
+    assert!(text.contains("(/pricing) Tj"));
+
+    let response = app
+        .get(&format!(
+            "/api/services/{}/report.pdf",
+            shymini::domain::ServiceId::new()
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_milestone_feed() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Example Site").await;
+
+    // A session a day for five weeks, then three on June 14th
+    for days in 2..37 {
+        let start = app.now() - chrono::Duration::days(days);
+        app.visit(&service, &format!("day-{days}"), &[("/", start)])
+            .await;
+    }
+    for i in 0..3 {
+        let start = app.now() - chrono::Duration::days(1);
+        app.visit(&service, &format!("record-{i}"), &[("/", start)])
+            .await;
+    }
+
+    // The manage page links to the feed
+    let response = app.get(&format!("/service/{}/manage", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    let feed_url = html
+        .split("value=\"")
+        .filter_map(|rest| rest.split('"').next())
+        .find(|value| value.ends_with("/milestones.xml"))
+        .unwrap();
+    let path = &feed_url[feed_url.find("/feed/").unwrap()..];
+
+    let response = app.get(path).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let feed = String::from_utf8_lossy(&body);
+    assert!(feed.contains("<title>Example Site milestones</title>"));
+    assert_eq!(feed.matches("<entry>").count(), 1, "{feed}");
+    assert!(feed.contains("<title>Record day: 3 sessions</title>"));
+    assert!(feed.contains(&format!(
+        "<id>urn:shymini:{}:record-day:2024-06-14</id>",
+        service.id
+    )));
+    assert!(feed.contains("<updated>2024-06-15T00:00:00+00:00</updated>"));
+
+    // A token that wasn't signed here
+    let tampered = path.replace("/milestones.xml", "0/milestones.xml");
+    let response = app.get(&tampered).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_embed_widgets() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Example Site").await;
+    let now = app.now();
+    app.visit(&service, "a", &[("/", now - chrono::Duration::days(2))])
+        .await;
+    app.visit(&service, "b", &[("/", now), ("/about", now)])
+        .await;
+
+    // The manage page has the iframes, and won't be framed itself
+    let response = app.get(&format!("/service/{}/manage", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-frame-options"], "SAMEORIGIN");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    let embed_path = |widget: &str| {
+        let url = html
+            .split("src=\"")
+            .filter_map(|rest| rest.split('"').next())
+            .find(|value| value.ends_with(&format!("/{widget}")))
+            .unwrap();
+        url[url.find("/embed/").unwrap()..].to_string()
+    };
+    let chart = embed_path("chart");
+    let counters = embed_path("counters");
+
+    let response = app.get(&format!("{counters}?range=24h&theme=dark")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("x-frame-options"));
+    assert_eq!(
+        response.headers()["content-security-policy"],
+        "frame-ancestors *"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("theme-dark"));
+    assert!(html.contains("Example Site · Last 24 hours"));
+    let values: Vec<&str> = html
+        .split("class=\"counter-value\">")
+        .skip(1)
+        .filter_map(|rest| rest.split('<').next())
+        .collect();
+    assert_eq!(values, ["1", "1", "2"]);
+
+    let response = app.get(&format!("{chart}?accent=ff8800;}}")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("theme-light"));
+    assert!(html.contains("--color-accent: #5cc265;"));
+    assert!(html.contains("Example Site · Last 30 days"));
+    let response = app.get(&format!("{chart}?accent=FF8800")).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("--color-accent: #ff8800;"));
+
+    // A token that wasn't signed here shows nothing
+    let response = app.get(&chart.replace("/chart", "0/chart")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_stats_presentation() {
+    use shymini::domain::{HourCycle, OrganizationId, Role, UserSettings, WeekStart};
+    use shymini::{auth, db};
+
+    let app = common::TestApp::with(|settings| settings.theme_accent = "#336699".to_string()).await;
+    let service = app.service("Example Site").await;
+    let uri = format!("/api/services/{}/stats", service.id);
+
+    // Without a token's user, the request's language decides
+    let response = app
+        .send(
+            Request::builder()
+                .uri(&uri)
+                .header("Accept-Language", "de")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["data"]["session_count"].is_number());
+    let hints = &json["data"]["presentation"];
+    assert_eq!(hints["locale"], "de");
+    assert_eq!(hints["decimal_separator"], ",");
+    assert_eq!(hints["group_separator"], ".");
+    assert_eq!(hints["week_start"], "monday");
+    assert_eq!(hints["hour_cycle"], "h23");
+    assert_eq!(hints["chart"]["mode"], "light");
+    assert_eq!(hints["chart"]["colors"][1], "#336699");
+
+    let hints = &app.get_json(&uri).await["data"]["presentation"];
+    assert_eq!(hints["locale"], "en");
+    assert_eq!(hints["week_start"], "sunday");
+    assert_eq!(hints["hour_cycle"], "h12");
+
+    // A token follows its creator's preferences
+    let pool = &app.state.pool;
+    let user = db::create_user(pool, "ada@example.com", "Ada", "", true)
+        .await
+        .unwrap();
+    db::save_user_settings(
+        pool,
+        user.id,
+        &UserSettings {
+            week_start: Some(WeekStart::Monday),
+            hour_cycle: Some(HourCycle::H23),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let token = auth::generate_token(auth::API_TOKEN_PREFIX);
+    db::create_api_token(
+        pool,
+        OrganizationId::DEFAULT,
+        "frontend",
+        &auth::hash_token(&token),
+        Role::Viewer,
+        Some(user.id),
+    )
+    .await
+    .unwrap();
+    let response = app
+        .send(
+            Request::builder()
+                .uri(&uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let hints = &json["data"]["presentation"];
+    assert_eq!(hints["decimal_separator"], ".");
+    assert_eq!(hints["week_start"], "monday");
+    assert_eq!(hints["hour_cycle"], "h23");
+}
+
+#[tokio::test]
+async fn test_dashboard_theme() {
+    let app = common::TestApp::with(|settings| {
+        settings.theme_logo_url = Some("/logo.svg".to_string());
+        settings.theme_accent = "#336699".to_string();
+    })
+    .await;
+    let theme_css = || async {
+        let response = app.get("/static/theme.css").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/css; charset=utf-8"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8_lossy(&body).into_owned()
+    };
+
+    let css = theme_css().await;
+    assert!(css.contains("--color-accent: #336699;"));
+    assert!(css.contains("url(\"/logo.svg\")"));
+    assert!(css.contains("color-scheme: light"));
+
+    let response = app.get("/").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("href=\"/static/theme.css\""));
+
+    // The organization's own theme wins
+    let response = app
+        .send(post_form(
+            "/organization",
+            "name=Default&logo_url=https%3A%2F%2Facme.example%2Flogo.png&accent_color=%23C00&theme_mode=dark",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let css = theme_css().await;
+    assert!(css.contains("--color-accent: #cc0000;"));
+    assert!(css.contains("url(\"https://acme.example/logo.png\")"));
+    assert!(css.contains("color-scheme: dark"));
+
+    let response = app.get("/organization").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("value=\"#c00\""));
+    assert!(html.contains("<option value=\"dark\" selected>"));
+
+    // Nothing that could break out of the stylesheet is kept
+    let response = app
+        .send(post_form(
+            "/organization",
+            "name=Default&logo_url=%2Flogo.png%22%29%3B%7D&accent_color=",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(theme_css().await.contains("--color-accent: #cc0000;"));
+
+    // Empty fields go back to the install's theme
+    let response = app
+        .send(post_form(
+            "/organization",
+            "name=Default&logo_url=&accent_color=&theme_mode=",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let css = theme_css().await;
+    assert!(css.contains("--color-accent: #336699;"));
+    assert!(css.contains("url(\"/logo.svg\")"));
+    assert!(css.contains("color-scheme: light"));
+}
+
+#[tokio::test]
+async fn test_visitor_sketches() {
+    use shymini::db;
+
+    let app = common::TestApp::with(|settings| settings.visitor_sketches = true).await;
+    let service = app.service("Site").await;
+    let track = |visitor: &'static str, page: &'static str| {
+        app.send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Content-Type", "application/json")
+                .header("User-Agent", format!("Mozilla/5.0 Firefox/120.0 {visitor}"))
+                .body(Body::from(format!(
+                    r#"{{"idempotency":"{visitor}{page}","location":"https://example.com{page}"}}"#
+                )))
+                .unwrap(),
+        )
+    };
+
+    // Two visitors today, and one of them again tomorrow with a third
+    let today = app.now().date_naive();
+    for (visitor, page) in [("a", "/"), ("a", "/about"), ("b", "/")] {
+        assert_eq!(track(visitor, page).await.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::days(1));
+    for (visitor, page) in [("a", "/pricing"), ("c", "/")] {
+        assert_eq!(track(visitor, page).await.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let sketch = db::get_visitor_sketch(
+        &app.state.pool,
+        service.id,
+        today,
+        shymini::domain::Environment::Production,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(sketch.estimate(), 2);
+
+    // Visitor a is counted once over both days
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats?range=7d", service.id))
+        .await;
+    assert_eq!(stats["data"]["unique_visitors"], 3);
+    // The week before had no sketches
+    assert!(stats["data"]["compare"]["unique_visitors"].is_null());
+    let response = app
+        .get(&format!("/service/{}/stats?range=7d", service.id))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("≈ 3 visitors"));
+
+    // Without sketches there is no count
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    app.visit(&service, "visitor", &[("/", app.now())]).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+    assert!(stats["data"]["unique_visitors"].is_null());
+}
+
+#[tokio::test]
+async fn test_idempotency_filter() {
+    use shymini::ingress::IdempotencyFilter;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let load =
+        |key: &'static str| {
+            app.send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Content-Type", "application/json")
+                .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0")
+                .body(Body::from(format!(
+                    r#"{{"idempotency":"{key}","location":"https://example.com/","loadTime":100}}"#
+                )))
+                .unwrap(),
+        )
+        };
+    let counts = || async {
+        let stats = app
+            .get_json(&format!("/api/services/{}/stats", service.id))
+            .await;
+        let usage = app
+            .get_json(&format!("/api/services/{}/usage", service.id))
+            .await;
+        (
+            stats["data"]["hit_count"].clone(),
+            usage["data"]["current"]["duplicates"].clone(),
+        )
+    };
+
+    assert_eq!(load("first").await.status(), StatusCode::OK);
+    // The cache forgets the key, and the page load comes again later than
+    // tabs are collapsed
+    app.state.cache.hit_idempotency.invalidate_all();
+    app.clock.advance(chrono::Duration::hours(1));
+    assert_eq!(load("first").await.status(), StatusCode::OK);
+    app.clock.advance(chrono::Duration::seconds(1));
+    assert_eq!(counts().await, (1.into(), 1.into()));
+
+    // A new page load of the same page is still a hit
+    assert_eq!(load("second").await.status(), StatusCode::OK);
+    app.clock.advance(chrono::Duration::seconds(1));
+    assert_eq!(counts().await, (2.into(), 1.into()));
+
+    // Saved filters are read back after a restart
+    app.state
+        .hit_filter
+        .flush(&app.state.pool, app.now())
+        .await
+        .unwrap();
+    let restarted = IdempotencyFilter::new(app.state.settings.idempotency_filter_capacity);
+    for (key, seen) in [
+        ("hit_first", true),
+        ("hit_second", true),
+        ("hit_third", false),
+    ] {
+        assert_eq!(
+            restarted
+                .contains(&app.state.pool, key, app.now())
+                .await
+                .unwrap(),
+            seen,
+            "{key}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_prefetch_hits() {
+    use shymini::db;
+    use shymini::domain::{PrefetchBehavior, Service};
+
+    async fn load(app: &common::TestApp, service: &Service, key: &str, prefetch: bool) {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            );
+        if prefetch {
+            request = request.header("Sec-Purpose", "prefetch;prerender");
+        }
+        let body = format!(
+            r#"{{"idempotency":"{key}","location":"https://example.com/{key}","loadTime":100}}"#
+        );
+        let response = app.send(request.body(Body::from(body)).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Dropped by default
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    load(&app, &service, "prefetched", true).await;
+    load(&app, &service, "opened", false).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+
+    // Or recorded and marked
+    let app =
+        common::TestApp::with(|settings| settings.prefetch_hits = PrefetchBehavior::Flag).await;
+    let service = app.service("Site").await;
+    load(&app, &service, "prefetched", true).await;
+    load(&app, &service, "opened", false).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let start = app.now() - chrono::Duration::days(1);
+    let sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start,
+        app.now(),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(sessions.len(), 1);
+    let hits = db::list_hits_for_session(&app.state.pool, sessions[0].id, 10, 0)
+        .await
+        .unwrap();
+    let mut flags: Vec<_> = hits
+        .iter()
+        .map(|hit| (hit.location.as_str(), hit.prefetched))
+        .collect();
+    flags.sort();
+    assert_eq!(
+        flags,
+        [
+            ("https://example.com/opened", false),
+            ("https://example.com/prefetched", true)
+        ]
+    );
+
+    // Kept out of the stats
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+    assert_eq!(stats["data"]["locations"].as_array().unwrap().len(), 1);
+
+    let response = app
+        .get(&format!(
+            "/service/{}/sessions/{}",
+            service.id, sessions[0].id
+        ))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("Prefetched"));
+}
+
+#[tokio::test]
+async fn test_page_errors() {
+    use shymini::db;
+    use shymini::domain::{Service, UpdateService};
+
+    async fn report(app: &common::TestApp, service: &Service, location: &str, message: &str) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "text/plain")
+            .body(Body::from(
+                serde_json::json!({
+                    "idempotency": "page",
+                    "location": location,
+                    "error": {"message": message, "stack": "at f (app.js:1:2)\nat g (app.js:3:4)"}
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let day = app.now().date_naive();
+
+    // Ignored unless the service tracks errors
+    report(&app, &service, "https://example.com/", "boom").await;
+    assert!(
+        db::get_page_errors(&app.state.pool, service.id, day, day, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let script = app
+        .get(&format!("/trace/app_{}.js?debug=1", service.tracking_id))
+        .await;
+    let body = script.into_body().collect().await.unwrap().to_bytes();
+    assert!(!String::from_utf8_lossy(&body).contains("reportError"));
+
+    let service = db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            track_errors: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let script = app
+        .get(&format!("/trace/app_{}.js?debug=1", service.tracking_id))
+        .await;
+    let body = script.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("reportError"));
+
+    report(&app, &service, "https://example.com/", "boom").await;
+    report(&app, &service, "https://example.com/", "boom").await;
+    report(
+        &app,
+        &service,
+        "https://example.com/cart",
+        "TypeError: <x> is null",
+    )
+    .await;
+    let errors = db::get_page_errors(&app.state.pool, service.id, day, day, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        errors
+            .iter()
+            .map(|e| (e.location.as_str(), e.message.as_str(), e.count))
+            .collect::<Vec<_>>(),
+        [
+            ("https://example.com/", "boom", 2),
+            ("https://example.com/cart", "TypeError: <x> is null", 1)
+        ]
+    );
+    assert_eq!(errors[0].stack, "at f (app.js:1:2)\nat g (app.js:3:4)");
+
+    // Error reports are no page views
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 0);
+
+    let response = app
+        .get(&format!(
+            "/service/{}/panels/errors?urlPattern=cart",
+            service.id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("TypeError: &lt;x&gt; is null"));
+    assert!(!html.contains("boom"));
+}
+
+#[tokio::test]
+async fn test_referrer_spam() {
+    use shymini::db;
+    use shymini::domain::{DroppedHits, IngressDecision, ReferrerSpamBehavior, Service};
+
+    async fn load(app: &common::TestApp, service: &Service, key: &str, referrer: &str) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .header("X-Forwarded-For", format!("203.0.113.{}", key.len()))
+            .body(Body::from(format!(
+                r#"{{"idempotency":"{key}","location":"https://example.com/{key}","referrer":"{referrer}","loadTime":100}}"#
+            )))
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Dropped by default, and counted
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    load(&app, &service, "spam", "https://www.semalt.com/").await;
+    load(&app, &service, "latest", "https://news.example/").await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+    let day = app.now().date_naive();
+    assert_eq!(
+        db::get_dropped_hits(&app.state.pool, service.id, day, day)
+            .await
+            .unwrap(),
+        vec![DroppedHits {
+            reason: IngressDecision::DroppedSpam,
+            count: 1
+        }]
+    );
+
+    // Or recorded and flagged
+    let app = common::TestApp::with(|settings| {
+        settings.referrer_spam = ReferrerSpamBehavior::Flag;
+    })
+    .await;
+    let service = app.service("Site").await;
+    load(&app, &service, "spam", "https://www.semalt.com/").await;
+    load(&app, &service, "latest", "https://news.example/").await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let start = app.now() - chrono::Duration::days(1);
+    assert_eq!(
+        db::count_referrer_spam(&app.state.pool, service.id, start, app.now())
+            .await
+            .unwrap(),
+        1
+    );
+    let response = app
+        .get(&format!("/service/{}/panels/data-quality", service.id))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("page views recorded in this period"));
+
+    // But kept out of the referrers and traffic
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+    let referrers: Vec<_> = stats["data"]["referrers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|referrer| referrer["value"].as_str().unwrap())
+        .collect();
+    assert_eq!(referrers, ["https://news.example/"]);
+}
+
+#[tokio::test]
+async fn test_geo_rules() {
+    use shymini::db;
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let service = db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            allowed_countries: Some("de, at".to_string()),
+            blocked_asns: Some("AS16509".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let rules = service.get_geo_rules();
+    assert_eq!(rules.allowed_countries, ["DE", "AT"]);
+    assert_eq!(rules.blocked_asns, [16509]);
+
+    // The rules show on the form
+    let response = app.get(&format!("/service/{}/manage", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("name=\"allowed_countries\" value=\"de, at\""));
+    assert!(html.contains("name=\"blocked_asns\" value=\"AS16509\""));
+
+    // Without GeoIP databases no visitor can be located, and all pass
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/trace/app_{}.js", service.tracking_id))
+        .header("Content-Type", "application/json")
+        .header(
+            "User-Agent",
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+        )
+        .header("X-Forwarded-For", "203.0.113.1")
+        .body(Body::from(
+            r#"{"idempotency":"a","location":"https://example.com/","loadTime":100}"#,
+        ))
+        .unwrap();
+    assert_eq!(app.send(request).await.status(), StatusCode::OK);
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+}
+
+#[tokio::test]
+async fn test_verify_bots() {
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    use shymini::bots::HostResolver;
+    use shymini::db;
+    use shymini::domain::{DroppedHits, IngressDecision, Service};
+
+    /// Google's name for one address only
+    struct GoogleResolver;
+
+    #[axum::async_trait]
+    impl HostResolver for GoogleResolver {
+        async fn reverse(&self, ip: IpAddr) -> Option<String> {
+            (ip.to_string() == "66.249.66.1").then(|| "crawl-66-249-66-1.googlebot.com".to_string())
+        }
+
+        async fn forward(&self, host: &str) -> Vec<IpAddr> {
+            match host {
+                "crawl-66-249-66-1.googlebot.com" => vec!["66.249.66.1".parse().unwrap()],
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    async fn load(app: &common::TestApp, service: &Service, key: &str, ip: &str) {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+            )
+            .header("X-Forwarded-For", ip)
+            .body(Body::from(format!(
+                r#"{{"idempotency":"{key}","location":"https://example.com/{key}","loadTime":100}}"#
+            )))
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let app = common::TestApp::with(|settings| settings.verify_bots = true)
+        .await
+        .with_bot_resolver(Arc::new(GoogleResolver));
+    let service = app.service("Site").await;
+
+    // Both pass while they are checked
+    load(&app, &service, "a", "66.249.66.1").await;
+    load(&app, &service, "b", "203.0.113.1").await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    // Then only the genuine crawler is recorded
+    load(&app, &service, "c", "66.249.66.1").await;
+    load(&app, &service, "d", "203.0.113.1").await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 3);
+    let day = app.now().date_naive();
+    assert_eq!(
+        db::get_dropped_hits(&app.state.pool, service.id, day, day)
+            .await
+            .unwrap(),
+        vec![DroppedHits {
+            reason: IngressDecision::DroppedFakeBot,
+            count: 1
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_page_paths() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+
+    // Two visits to / then /pricing, one of them on to /signup?plan=pro,
+    // and one to / then /blog
+    let visits: [(&str, &[&str]); 3] = [
+        ("203.0.113.1", &["/", "/pricing", "/signup?plan=pro"]),
+        ("203.0.113.2", &["/", "/pricing"]),
+        ("203.0.113.3", &["/", "/blog"]),
+    ];
+    for (ip, pages) in visits {
+        for page in pages {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Content-Type", "application/json")
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+                )
+                .header("X-Forwarded-For", ip)
+                .body(Body::from(format!(
+                    r#"{{"idempotency":"{ip}{page}","location":"https://example.com{page}","loadTime":100}}"#
+                )))
+                .unwrap();
+            assert_eq!(app.send(request).await.status(), StatusCode::OK);
+            app.clock.advance(chrono::Duration::seconds(10));
+        }
+    }
+
+    let paths = app
+        .get_json(&format!("/api/services/{}/paths", service.id))
+        .await;
+    assert_eq!(
+        paths["data"],
+        serde_json::json!([
+            {"step": 1, "source": "example.com/", "target": "example.com/pricing", "count": 2},
+            {"step": 1, "source": "example.com/", "target": "example.com/blog", "count": 1},
+            {"step": 2, "source": "example.com/pricing", "target": "example.com/signup", "count": 1},
+        ])
+    );
+
+    // A URL pattern keeps the transitions from or to the pages it matches
+    let paths = app
+        .get_json(&format!(
+            "/api/services/{}/paths?urlPattern=signup",
+            service.id
+        ))
+        .await;
+    assert_eq!(paths["data"].as_array().unwrap().len(), 1);
+    assert_eq!(paths["data"][0]["target"], "example.com/signup");
+}
+
+#[tokio::test]
+async fn test_stats_breakdown_filters() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+
+    let desktop = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+    let phone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+    for (ip, user_agent, path) in [
+        ("203.0.113.1", desktop, "pricing"),
+        ("203.0.113.2", desktop, "blog"),
+        ("203.0.113.3", phone, "pricing"),
+    ] {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header("User-Agent", user_agent)
+            .header("X-Forwarded-For", ip)
+            .body(Body::from(format!(
+                r#"{{"idempotency":"{ip}","location":"https://example.com/{path}","loadTime":100}}"#
+            )))
+            .unwrap();
+        assert_eq!(app.send(request).await.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let sessions = |filters: &str| {
+        let uri = format!("/api/services/{}/stats?{}", service.id, filters);
+        let app = &app;
+        async move { app.get_json(&uri).await["data"]["session_count"].clone() }
+    };
+    assert_eq!(sessions("").await, 3);
+    assert_eq!(sessions("browser=Firefox").await, 2);
+    assert_eq!(sessions("deviceType=PHONE").await, 1);
+    assert_eq!(
+        sessions("page=https%3A%2F%2Fexample.com%2Fpricing").await,
+        2
+    );
+    // Filters add up; pages also match as the pages breakdown shows them
+    assert_eq!(
+        sessions("browser=Firefox&page=example.com%2Fpricing").await,
+        1
+    );
+    assert_eq!(sessions("country=DE").await, 0);
+    // Empty values filter nothing
+    assert_eq!(sessions("country=&os=").await, 3);
+
+    // The dashboard's rows link to the same filters, shown as chips
+    let html = |uri: String| {
+        let app = &app;
+        async move {
+            let response = app.get(&uri).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8_lossy(&body).to_string()
+        }
+    };
+    let page = html(format!("/service/{}", service.id)).await;
+    assert!(page.contains(r#"data-filter="browser" data-value="Firefox""#));
+    assert!(page.contains(r#"id="filter-deviceType" name="deviceType" value="""#));
+    assert!(!page.contains("Filtered by"));
+
+    let partial = html(format!(
+        "/service/{}/stats?browser=Firefox&country=",
+        service.id
+    ))
+    .await;
+    assert!(partial.contains("Filtered by"));
+    assert!(partial.contains(r#"data-filter="browser" data-value="""#));
+    assert!(!partial.contains(r#"data-filter="country" data-value="""#));
+    assert!(partial.contains(">2</p>"));
+
+    let page = html(format!("/service/{}?deviceType=PHONE", service.id)).await;
+    assert!(page.contains(r#"id="filter-deviceType" name="deviceType" value="PHONE""#));
+    let locations = html(format!(
+        "/service/{}/panels/locations?deviceType=PHONE",
+        service.id
+    ))
+    .await;
+    assert!(locations.contains(r#"data-filter="page" data-value="example.com/pricing""#));
+    assert!(!locations.contains("example.com/blog"));
+}
+
+#[tokio::test]
+async fn test_ingress_preflight_and_head() {
+    use shymini::db;
+    use shymini::domain::CreateService;
+
+    let app = common::TestApp::new().await;
+    let service = db::create_service(
+        &app.state.pool,
+        CreateService {
+            name: "Site".to_string(),
+            link: "https://example.com".to_string(),
+            origins: "https://example.com".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let preflight = |uri: String, origin: &str| {
+        Request::builder()
+            .method("OPTIONS")
+            .uri(uri)
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "content-type, x-embed-id")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // The service's origin, not a wildcard, with the requested headers
+    let response = app
+        .send(preflight(
+            format!("/trace/app_{}.js", service.tracking_id),
+            "https://example.com",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let headers = response.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://example.com"
+    );
+    assert_eq!(
+        headers["access-control-allow-headers"],
+        "content-type, x-embed-id"
+    );
+    assert!(headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .contains("POST"));
+
+    let response = app
+        .send(preflight(
+            format!("/trace/px_{}.gif", service.tracking_id),
+            "https://elsewhere.example",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .send(preflight(
+            "/trace/app_unknown1.js".to_string(),
+            "https://example.com",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // A HEAD probe of the pixel gets its headers and records nothing
+    let response = app
+        .send(
+            Request::builder()
+                .method("HEAD")
+                .uri(format!("/trace/px_{}.gif", service.tracking_id))
+                .header("Origin", "https://example.com")
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/gif");
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "https://example.com"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.is_empty());
+
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let start = app.now() - chrono::Duration::days(1);
+    let sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start,
+        app.now(),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    assert!(sessions.is_empty());
+}
+
+#[tokio::test]
+async fn test_beacon_content_types() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+
+    let post = |content_type: &str, body: &str| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", content_type)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    // sendBeacon with a string, and with URLSearchParams
+    let response = app
+        .send(post(
+            "text/plain;charset=UTF-8",
+            r#"{"idempotency":"beacon1","location":"https://example.com/a","loadTime":100}"#,
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .send(post(
+            "application/x-www-form-urlencoded;charset=UTF-8",
+            "idempotency=beacon2&location=https%3A%2F%2Fexample.com%2Fb&loadTime=100",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.send(post("text/plain", "not a payload")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    app.clock.advance(chrono::Duration::seconds(1));
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 2);
+}
+
+#[tokio::test]
+async fn test_hit_dimensions() {
+    let app = common::TestApp::with(|settings| settings.max_hit_dimensions = 2).await;
+    let service = app.service("Site").await;
+
+    let load = |key: &str, path: &str, dimensions: &str| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .body(Body::from(format!(
+                r#"{{"idempotency":"{key}","location":"https://example.com{path}","loadTime":100,"dimensions":{dimensions}}}"#
+            )))
+            .unwrap()
+    };
+    // Past the limit of two, further dimensions are dropped
+    for request in [
+        load(
+            "a",
+            "/posts/1",
+            r#"{"author":"Ada","category":"news","plan":"pro"}"#,
+        ),
+        load("b", "/posts/2", r#"{"author":"Ada"}"#),
+        load("c", "/about", r#"{"author":"Grace","bad key":"x"}"#),
+    ] {
+        assert_eq!(app.send(request).await.status(), StatusCode::OK);
+    }
+    // Heartbeats don't add dimensions again
+    let heartbeat = Request::builder()
+        .method("POST")
+        .uri(format!("/trace/app_{}.js", service.tracking_id))
+        .header("Content-Type", "application/json")
+        .header(
+            "User-Agent",
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+        )
+        .body(Body::from(
+            r#"{"idempotency":"a","location":"https://example.com/posts/1","dimensions":{"author":"Ada"}}"#,
+        ))
+        .unwrap();
+    assert_eq!(app.send(heartbeat).await.status(), StatusCode::OK);
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let authors = app
+        .get_json(&format!("/api/services/{}/dimensions/author", service.id))
+        .await;
+    assert_eq!(
+        authors["data"],
+        serde_json::json!([
+            {"value": "Ada", "count": 2},
+            {"value": "Grace", "count": 1},
+        ])
+    );
+    let plans = app
+        .get_json(&format!("/api/services/{}/dimensions/plan", service.id))
+        .await;
+    assert_eq!(plans["data"], serde_json::json!([]));
+    let posts = app
+        .get_json(&format!(
+            "/api/services/{}/dimensions/author?urlPattern=/posts/",
+            service.id
+        ))
+        .await;
+    assert_eq!(
+        posts["data"],
+        serde_json::json!([{"value": "Ada", "count": 2}])
+    );
+
+    // The panel shows the busiest key, or the one picked
+    let panel = |query: &str| format!("/service/{}/panels/dimensions{}", service.id, query);
+    let response = app.get(&panel("")).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("Grace"));
+    assert!(body.contains("?key=category"));
+    let response = app.get(&panel("?key=category")).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("news"));
+    assert!(!body.contains("Grace"));
+}
+
+#[tokio::test]
+async fn test_session_props() {
+    let app = common::TestApp::with(|settings| settings.max_session_props = 2).await;
+    let service = app.service("Site").await;
+
+    let post = |agent: &str, body: String| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                format!("Mozilla/5.0 (X11; Linux x86_64) {agent}"),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let load = |agent: &str, key: &str, props: &str| {
+        post(
+            agent,
+            format!(
+                r#"{{"idempotency":"{key}","location":"https://example.com/","loadTime":100,"props":{props}}}"#
+            ),
+        )
+    };
+    // A later POST updates known keys; new ones past the limit are dropped
+    for request in [
+        load("Firefox/120.0", "a", r#"{"plan":"pro","seats":5}"#),
+        post(
+            "Firefox/120.0",
+            r#"{"idempotency":"a","location":"https://example.com/","props":{"role":"admin","plan":"team"}}"#
+                .to_string(),
+        ),
+        load("Chrome/120.0", "b", r#"{"plan":"free"}"#),
+        load("Safari/605.1", "c", "null"),
+    ] {
+        assert_eq!(app.send(request).await.status(), StatusCode::OK);
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let sessions = |prop: &str| {
+        let app = &app;
+        let url = format!("/api/services/{}/stats?prop={}", service.id, prop);
+        async move { app.get_json(&url).await["data"]["session_count"].clone() }
+    };
+    assert_eq!(sessions("plan:team").await, 1);
+    assert_eq!(sessions("plan:free").await, 1);
+    assert_eq!(sessions("plan:pro").await, 0);
+    assert_eq!(sessions("role:admin").await, 0);
+    // An unparseable filter is ignored
+    assert_eq!(sessions("plan").await, 3);
+
+    let list = app
+        .get_json(&format!("/api/services/{}/sessions", service.id))
+        .await;
+    let props: Vec<_> = list["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|session| session["props"].clone())
+        .filter(|props| props != &serde_json::json!({}))
+        .collect();
+    assert_eq!(props.len(), 2);
+    assert!(props.contains(&serde_json::json!({"plan": "team", "seats": "5"})));
+    assert!(props.contains(&serde_json::json!({"plan": "free"})));
+}
+
+#[tokio::test]
+async fn test_sessions_ndjson_export() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let now = app.now();
+    for i in 0..150 {
+        let page = if i % 10 == 0 { "/blog/post" } else { "/" };
+        let time = now - chrono::Duration::minutes(i);
+        app.visit(&service, &format!("visitor-{i}"), &[(page, time)])
+            .await;
+    }
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let export = |query: &str| {
+        let app = &app;
+        let uri = format!("/api/services/{}/sessions{}", service.id, query);
+        async move {
+            let response = app
+                .send(
+                    Request::builder()
+                        .uri(&uri)
+                        .header("Accept", "application/x-ndjson")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // Every session, not just the first page, newest first
+    let sessions = export("").await;
+    assert_eq!(sessions.len(), 150);
+    assert_eq!(sessions[0]["identifier"], "visitor-0");
+    assert_eq!(sessions[149]["identifier"], "visitor-149");
+    let blog = export("?urlPattern=/blog/").await;
+    assert_eq!(blog.len(), 15);
+    assert_eq!(blog[1]["identifier"], "visitor-10");
+
+    // Plain JSON still lists a page
+    let list = app
+        .get_json(&format!("/api/services/{}/sessions", service.id))
+        .await;
+    assert_eq!(list["data"].as_array().unwrap().len(), 100);
+}
+
+/// Send a masked client frame over a raw WebSocket connection
+async fn ws_send(stream: &mut tokio::net::TcpStream, opcode: u8, payload: &[u8]) {
+    use tokio::io::AsyncWriteExt;
+
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    stream.write_all(&frame).await.unwrap();
+}
+
+/// The next server frame: its opcode and payload
+async fn ws_read(stream: &mut tokio::net::TcpStream) -> (u8, Vec<u8>) {
+    use tokio::io::AsyncReadExt;
+
+    let read = async {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        let len = match head[1] {
+            126 => usize::from(stream.read_u16().await.unwrap()),
+            127 => stream.read_u64().await.unwrap() as usize,
+            len => usize::from(len),
+        };
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        (head[0] & 0x0F, payload)
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(10), read)
+        .await
+        .expect("no WebSocket frame")
+}
+
+/// The next JSON message of a type other than `counters`, unless that's
+/// the one asked for
+async fn ws_json(stream: &mut tokio::net::TcpStream, kind: &str) -> serde_json::Value {
+    loop {
+        let (opcode, payload) = ws_read(stream).await;
+        assert_eq!(opcode, 0x1, "expected a text frame");
+        let message: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        if message["type"] == kind {
+            return message;
+        }
+        assert!(
+            message["type"] == "counters" || message["type"] == "top",
+            "unexpected {message}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_live_websocket() {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let app = common::TestApp::with(|settings| settings.ws_messages_per_min = 4).await;
+    let service = app.service("Site").await;
+    let other = app.service("Other").await;
+    let now = app.now();
+    app.visit(&service, "earlier", &[("/", now), ("/about", now)])
+        .await;
+
+    // Plain requests aren't upgraded
+    let response = app.get("/api/ws").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = axum::serve(listener, app.router.clone());
+    tokio::spawn(async move { server.await.unwrap() });
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(
+            b"GET /api/ws HTTP/1.1\r\n\
+              Host: localhost\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut reader = BufReader::new(&mut stream);
+    let mut handshake = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        handshake.push_str(&line.to_lowercase());
+    }
+    assert!(handshake.starts_with("http/1.1 101"), "{handshake}");
+    assert!(
+        handshake.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo="),
+        "{handshake}"
+    );
+
+    // Subscribing sends the counters right away
+    let subscribe = format!(r#"{{"type":"subscribe","service_id":"{}"}}"#, service.id);
+    ws_send(&mut stream, 0x1, subscribe.as_bytes()).await;
+    let subscribed = ws_json(&mut stream, "subscribed").await;
+    assert_eq!(subscribed["service_id"], service.id.0.to_string());
+    let counters = ws_json(&mut stream, "counters").await;
+    assert_eq!(counters["sessions_today"], 1);
+    assert_eq!(counters["hits_today"], 2);
+    assert_eq!(counters["online"], 1);
+    // Only hits coming in count towards the busiest pages
+    let top = ws_json(&mut stream, "top").await;
+    assert_eq!(top["locations"], serde_json::json!([]));
+
+    ws_send(&mut stream, 0x1, b"hello").await;
+    let error = ws_json(&mut stream, "error").await;
+    assert_eq!(
+        error["message"],
+        "Expected a subscribe or unsubscribe message"
+    );
+
+    // New hits of subscribed services come through, others' don't
+    for (target, page) in [(&other, "other"), (&service, "pricing")] {
+        let response = app
+            .send(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/trace/app_{}.js", target.tracking_id))
+                    .header("Content-Type", "application/json")
+                    .header(
+                        "User-Agent",
+                        "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+                    )
+                    .body(Body::from(format!(
+                        r#"{{"idempotency":"{page}","location":"https://example.com/{page}"}}"#
+                    )))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let hit = ws_json(&mut stream, "hit").await;
+    assert_eq!(hit["hit"]["service_id"], service.id.0.to_string());
+    assert_eq!(hit["hit"]["location"], "https://example.com/pricing");
+
+    let subscribe = format!(r#"{{"type":"subscribe","service_id":"{}"}}"#, other.id);
+    ws_send(&mut stream, 0x1, subscribe.as_bytes()).await;
+    ws_json(&mut stream, "subscribed").await;
+    let top = ws_json(&mut stream, "top").await;
+    assert_eq!(top["service_id"], other.id.0.to_string());
+    assert_eq!(
+        top["locations"],
+        serde_json::json!([{"value": "https://example.com/other", "count": 1, "error": 0}])
+    );
+
+    // Pings count towards the limit of 4 messages a minute
+    ws_send(&mut stream, 0x9, b"1").await;
+    assert_eq!(ws_read(&mut stream).await, (0xA, b"1".to_vec()));
+    ws_send(&mut stream, 0x9, b"2").await;
+    let (opcode, payload) = ws_read(&mut stream).await;
+    assert_eq!(opcode, 0x8);
+    assert_eq!(u16::from_be_bytes([payload[0], payload[1]]), 1008);
+    assert_eq!(&payload[2..], b"Too many messages");
+}
+
+#[tokio::test]
+async fn test_live_websocket_auth() {
+    let app = common::TestApp::with(|settings| settings.multi_tenant = true).await;
+    let request = |uri: &str| {
+        Request::builder()
+            .uri(uri)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.send(request("/api/ws")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app.send(request("/api/ws?token=shy_wrong")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_grafana_datasource() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let now = app.now();
+    let start = now - chrono::Duration::hours(3);
+    app.visit(
+        &service,
+        "a",
+        &[
+            ("/", start),
+            ("/pricing", start + chrono::Duration::minutes(5)),
+        ],
+    )
+    .await;
+    app.visit(&service, "b", &[("/", start + chrono::Duration::hours(2))])
+        .await;
+
+    let response = app.get("/grafana").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let post = |uri: &'static str, body: serde_json::Value| {
+        let app = &app;
+        async move {
+            let response = app
+                .send(
+                    Request::builder()
+                        .method("POST")
+                        .uri(uri)
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await;
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+
+    let (status, targets) = post("/grafana/search", serde_json::json!({"target": "HIT"})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        targets,
+        serde_json::json!([{"text": "Site: hits", "value": format!("{}:hits", service.id.0)}])
+    );
+
+    let range = serde_json::json!({
+        "from": start.to_rfc3339(),
+        "to": (start + chrono::Duration::hours(3)).to_rfc3339(),
+    });
+    let (status, results) = post(
+        "/grafana/query",
+        serde_json::json!({
+            "range": range,
+            "intervalMs": 3_600_000,
+            "maxDataPoints": 500,
+            "targets": [
+                {"refId": "A", "target": format!("{}:hits", service.id.0)},
+                {"refId": "B", "target": format!("{}:pages", service.id.0)},
+                {"refId": "C", "target": format!("{}:sessions", service.id.0), "hide": true},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let results = results.as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["target"], "Site: hits");
+    let start_ms = start.timestamp_millis();
+    assert_eq!(
+        results[0]["datapoints"],
+        serde_json::json!([
+            [2, start_ms],
+            [0, start_ms + 3_600_000],
+            [1, start_ms + 7_200_000]
+        ])
+    );
+    assert_eq!(results[1]["type"], "table");
+    assert_eq!(results[1]["columns"][0]["text"], "Page");
+    assert_eq!(results[1]["rows"][0], serde_json::json!(["/", 2]));
+
+    // Targets of services the token can't see, or made up, are refused
+    let (status, _) = post(
+        "/grafana/query",
+        serde_json::json!({"range": range, "targets": [{"target": "Site: hits"}]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = post(
+        "/grafana/query",
+        serde_json::json!({
+            "range": range,
+            "targets": [{"target": format!("{}:hits", uuid::Uuid::new_v4())}],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_segments() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let other = app.service("Other").await;
+    let now = app.now();
+    app.visit(&service, "a", &[("/", now), ("/pricing", now)])
+        .await;
+    app.visit(&service, "b", &[("/", now)]).await;
+    app.visit(&service, "c", &[("/blog", now)]).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let create = |service_id: shymini::domain::ServiceId, body: &'static str| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/services/{}/segments", service_id))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let created = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        json["data"]["id"].as_str().unwrap().to_string()
+    };
+    let pricing = created(
+        app.send(create(
+            service.id,
+            r#"{"name":"Pricing","conditions":[{"field":"page","op":"contains","value":"PRIC"}]}"#,
+        ))
+        .await,
+    )
+    .await;
+    // Every condition must hold; the country is matched whatever its case
+    let german_readers = created(
+        app.send(create(
+            service.id,
+            r#"{"name":"Readers","conditions":[{"field":"country","op":"is","value":"de"},{"field":"page","op":"is_not","value":"/blog"}]}"#,
+        ))
+        .await,
+    )
+    .await;
+    let foreign = created(
+        app.send(create(
+            other.id,
+            r#"{"name":"Other","conditions":[{"field":"browser","op":"is","value":"Firefox"}]}"#,
+        ))
+        .await,
+    )
+    .await;
+    let response = app
+        .send(create(service.id, r#"{"name":" ","conditions":[]}"#))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let stats = |segment: &str| {
+        let app = &app;
+        let url = format!("/api/services/{}/stats?segment_id={}", service.id, segment);
+        async move { app.get_json(&url).await["data"].clone() }
+    };
+    let all = stats("").await;
+    assert_eq!(all["session_count"], 3);
+    let pricing_stats = stats(&pricing).await;
+    assert_eq!(pricing_stats["session_count"], 1);
+    assert_eq!(pricing_stats["hit_count"], 2);
+    let chart_sessions: i64 = pricing_stats["chart_data"]["sessions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|count| count.as_i64().unwrap())
+        .sum();
+    assert_eq!(chart_sessions, 1);
+    assert_eq!(stats(&german_readers).await["session_count"], 2);
+
+    let sessions = app
+        .get_json(&format!(
+            "/api/services/{}/sessions?segment_id={}",
+            service.id, pricing
+        ))
+        .await;
+    assert_eq!(sessions["data"].as_array().unwrap().len(), 1);
+
+    // Segments of other services, and unknown ones, are not found
+    for segment in [
+        foreign.as_str(),
+        "00000000-0000-0000-0000-000000000000",
+        "x",
+    ] {
+        let url = format!("/api/services/{}/stats?segment_id={}", service.id, segment);
+        assert_eq!(app.get(&url).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    let listed = app
+        .get_json(&format!("/api/services/{}/segments", service.id))
+        .await;
+    assert_eq!(listed["data"].as_array().unwrap().len(), 2);
+
+    // The dashboard builds segments from its form and narrows its panels
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/service/{}/segments", service.id))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from(
+                    "name=Blog&field_0=page&op_0=is&value_0=%2Fblog&field_1=os&op_1=is&value_1=",
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let segments = shymini::db::list_segments(&app.state.pool, service.id)
+        .await
+        .unwrap();
+    let blog = segments.iter().find(|s| s.name == "Blog").unwrap();
+    assert_eq!(blog.conditions.len(), 1);
+    let response = app
+        .get(&format!(
+            "/service/{}/panels/sessions?segment_id={}",
+            service.id, blog.id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert_eq!(html.matches("/sessions/").count(), 1);
+
+    let response = app
+        .send(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/api/segments/{}", pricing))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.get(&format!("/api/segments/{}", pricing)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_maintenance() {
+    use shymini::db;
+
+    let app = common::TestApp::with(|settings| settings.rollup_retention_days = 30).await;
+    let service = app.service("Site").await;
+    let pool = &app.state.pool;
+    let today = app.now().date_naive();
+    let (old, recent) = (
+        today - chrono::Duration::days(40),
+        today - chrono::Duration::days(2),
+    );
+    for day in [old, recent] {
+        let time = day.and_hms_opt(12, 0, 0).unwrap().and_utc();
+        app.visit(&service, &day.to_string(), &[("/", time)]).await;
+        db::materialize_top_locations(pool, service.id, day)
+            .await
+            .unwrap();
+    }
+
+    let response = app.get("/admin/status").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        String::from_utf8_lossy(&body).matches("Never run").count(),
+        3
+    );
+
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri("/admin/maintenance")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // Days past the retention are gone; stats count them live instead
+    let days = db::list_materialized_days(pool, service.id, old, today)
+        .await
+        .unwrap();
+    assert_eq!(days, [recent]);
+
+    let runs = db::list_maintenance_runs(pool).await.unwrap();
+    assert_eq!(runs.len(), 3);
+    assert!(runs.iter().all(|run| run.error.is_none()), "{:?}", runs);
+
+    let response = app.get("/admin/status").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(!html.contains("Never run"));
+    assert_eq!(html.matches(">OK<").count(), 3);
+}
+
+#[tokio::test]
+async fn test_system_status() {
+    use shymini::domain::BackgroundTask;
+    use shymini::error::Error;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let now = app.now();
+    app.visit(
+        &service,
+        "recent",
+        &[("/", now - chrono::Duration::minutes(10))],
+    )
+    .await;
+    app.visit(&service, "old", &[("/", now - chrono::Duration::hours(3))])
+        .await;
+    app.state.tasks.record::<()>(
+        BackgroundTask::HitFilterFlush,
+        now,
+        &Err(Error::Internal("disk full".to_string())),
+    );
+
+    let json = app.get_json("/api/status").await;
+    let status = &json["data"];
+    assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+    assert!(status["database_bytes"].as_i64().unwrap() > 0);
+    assert_eq!(status["hits_last_hour"], 1);
+    assert_eq!(status["sessions_last_hour"], 1);
+    let tables = status["tables"].as_array().unwrap();
+    let sessions = tables.iter().find(|t| t["name"] == "sessions").unwrap();
+    assert_eq!(sessions["rows"], 2);
+    assert_eq!(status["caches"].as_array().unwrap().len(), 10);
+
+    let tasks = status["tasks"].as_array().unwrap();
+    let flush = tasks
+        .iter()
+        .find(|t| t["task"] == "hit_filter_flush")
+        .unwrap();
+    assert_eq!(flush["failures"], 1);
+    assert!(flush["last_error"].as_str().unwrap().contains("disk full"));
+    let maintenance = tasks.iter().find(|t| t["task"] == "maintenance").unwrap();
+    assert_eq!(maintenance["enabled"], false);
+
+    let response = app.get("/admin/status").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Failing"));
+    assert!(html.contains("session_associations"));
+}
+
+#[tokio::test]
+async fn test_update_check() {
+    use shymini::updates;
+
+    let app = common::TestApp::new().await;
+
+    // Nothing known yet: no banner, nothing in the status
+    let response = app.get("/admin/update-banner").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.is_empty());
+    let json = app.get_json("/api/status").await;
+    assert_eq!(json["data"]["latest_release"], serde_json::Value::Null);
+    assert_eq!(json["data"]["update_available"], false);
+
+    let url = serve_site(
+        r#"{"tag_name": "v999.1.0", "html_url": "https://github.com/cdaringe/shymini/releases/tag/v999.1.0", "draft": false}"#
+            .to_string(),
+    )
+    .await;
+    let release = updates::fetch_latest(&url, &shymini::monitor::client())
+        .await
+        .unwrap();
+    assert_eq!(release.version, "999.1.0");
+    app.state.updates.set_latest(release);
+
+    let response = app.get("/admin/update-banner").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("shymini 999.1.0 is available"));
+    assert!(html.contains("releases/tag/v999.1.0"));
+
+    let json = app.get_json("/api/status").await;
+    assert_eq!(json["data"]["latest_release"]["version"], "999.1.0");
+    assert_eq!(json["data"]["update_available"], true);
+}
+
+#[tokio::test]
+async fn test_signed_pixel_ids() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Newsletter").await;
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            signed_pixel_ids: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let mint = |identifiers: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/services/{}/pixel-urls", service.id))
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::json!({ "identifiers": identifiers }).to_string(),
+            ))
+            .unwrap()
+    };
+    let response = app
+        .send(mint(serde_json::json!(["reader@example.com", "list 7/a"])))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let urls: Vec<String> = json["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|u| u["url"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(json["data"][1]["identifier"], "list 7/a");
+    assert!(urls[0].starts_with(&format!(
+        "http://localhost:8080/trace/px_{}/",
+        service.tracking_id
+    )));
+    assert!(urls[1].contains("/list%207%2Fa.gif?sig="));
+
+    let response = app.send(mint(serde_json::json!([""]))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app.send(mint(serde_json::json!(vec!["x"; 1001]))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let open = |uri: String, ip: &'static str| {
+        Request::builder()
+            .uri(uri)
+            .header("X-Forwarded-For", ip)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Thunderbird/115.0",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+    let path = |url: &str| url.trim_start_matches("http://localhost:8080").to_string();
+    let forged = format!("/trace/px_{}/someone@example.com.gif", service.tracking_id);
+    let resigned = urls[0].replace("reader@", "other@");
+    for (uri, ip) in [
+        (path(&urls[0]), "203.0.113.1"),
+        (path(&urls[1]), "203.0.113.2"),
+        (forged.clone(), "203.0.113.3"),
+        (format!("{}?sig=00", forged), "203.0.113.4"),
+        (path(&resigned), "203.0.113.5"),
+    ] {
+        let response = app.send(open(uri, ip)).await;
+        // Every request gets the pixel, counted or not
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/gif");
+    }
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let start = app.now() - chrono::Duration::days(1);
+    let sessions = shymini::db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start,
+        app.now(),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut identifiers: Vec<_> = sessions.iter().map(|s| s.identifier.clone()).collect();
+    identifiers.sort();
+    assert_eq!(identifiers, vec!["list 7/a", "reader@example.com"]);
+}
+
+#[tokio::test]
+async fn test_email_campaigns() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Newsletter").await;
+
+    let open = |identifier: &str, ip: &'static str| {
+        Request::builder()
+            .uri(format!(
+                "/trace/px_{}/{}.gif",
+                service.tracking_id, identifier
+            ))
+            .header("X-Forwarded-For", ip)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Thunderbird/115.0",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+    for (identifier, ip) in [
+        ("spring:a", "203.0.113.1"),
+        ("spring:b", "203.0.113.2"),
+        ("summer:c", "203.0.113.3"),
+        // Identifiers without a campaign are left out
+        ("plain", "203.0.113.4"),
+        (":nobody", "203.0.113.5"),
+    ] {
+        let response = app.send(open(identifier, ip)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    app.clock.advance(chrono::Duration::hours(3));
+    app.send(open("spring:a", "203.0.113.1")).await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    app.clock.advance(chrono::Duration::minutes(1));
+
+    let json = app
+        .get_json(&format!("/api/services/{}/campaigns", service.id))
+        .await;
+    let campaigns = json["data"].as_array().unwrap();
+    assert_eq!(campaigns.len(), 2);
+    assert_eq!(campaigns[0]["campaign"], "spring");
+    assert_eq!(campaigns[0]["opens"], 3);
+    assert_eq!(campaigns[0]["unique_opens"], 2);
+    assert_eq!(campaigns[1]["campaign"], "summer");
+
+    let json = app
+        .get_json(&format!("/api/services/{}/campaigns/spring", service.id))
+        .await;
+    let report = &json["data"];
+    assert_eq!(report["opens"], 3);
+    assert_eq!(report["recipients"][0]["recipient"], "a");
+    assert_eq!(report["recipients"][0]["opens"], 2);
+    assert_eq!(report["recipients"][1]["recipient"], "b");
+    let delays: Vec<(String, i64)> = report["open_delays"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|b| {
+            (
+                b["label"].as_str().unwrap().to_string(),
+                b["count"].as_i64().unwrap(),
+            )
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    assert_eq!(
+        delays,
+        vec![("<1h".to_string(), 2), ("2–4h".to_string(), 1)]
+    );
+
+    let response = app
+        .get(&format!("/api/services/{}/campaigns/winter", service.id))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .get(&format!(
+            "/service/{}/campaigns?campaign=spring",
+            service.id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("campaign=summer"));
+    assert!(html.contains("2–4h"));
+}
+
+#[tokio::test]
+async fn test_tracked_links() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Newsletter").await;
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            respect_dnt: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let create = |body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/services/{}/links", service.id))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    for bad in [
+        serde_json::json!({ "target_url": "javascript:alert(1)" }),
+        serde_json::json!({ "target_url": "/relative" }),
+        serde_json::json!({ "target_url": "https://example.com/", "campaign": "a:b" }),
+    ] {
+        let response = app.send(create(bad)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let response = app
+        .send(create(serde_json::json!({
+            "target_url": "https://example.com/sale?ref=mail",
+            "campaign": "spring",
+        })))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let link = &json["data"];
+    let url = link["url"].as_str().unwrap();
+    assert!(url.starts_with("http://localhost:8080/r/"));
+    let path = url.trim_start_matches("http://localhost:8080").to_string();
+
+    let request = |uri: &str, ip: &'static str, dnt: bool| {
+        let mut builder = Request::builder()
+            .uri(uri)
+            .header("X-Forwarded-For", ip)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Firefox/120.0",
+            );
+        if dnt {
+            builder = builder.header("DNT", "1");
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+    // Two recipients open the campaign's email
+    for (identifier, ip) in [("spring:a", "203.0.113.1"), ("spring:b", "203.0.113.2")] {
+        let uri = format!("/trace/px_{}/{}.gif", service.tracking_id, identifier);
+        app.send(request(&uri, ip, false)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // One clicks twice, and a visitor asking not to be tracked is only sent on
+    for (ip, dnt) in [
+        ("203.0.113.1", false),
+        ("203.0.113.1", false),
+        ("203.0.113.3", true),
+    ] {
+        let response = app.send(request(&path, ip, dnt)).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()["location"],
+            "https://example.com/sale?ref=mail"
+        );
+    }
+    let response = app.send(request("/r/missing", "203.0.113.1", false)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    app.clock.advance(chrono::Duration::minutes(1));
+
+    let json = app
+        .get_json(&format!("/api/services/{}/links", service.id))
+        .await;
+    let stats = &json["data"][0];
+    assert_eq!(stats["clicks"], 2);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["click_through_rate"], 0.5);
+    assert_eq!(stats["url"], url);
+
+    // The clicks belong to the session the pixel opened
+    let associated: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM link_clicks WHERE session_id IS NOT NULL")
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+    assert_eq!(associated, 2);
+
+    let response = app.get(&format!("/service/{}/links", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains(url));
+    assert!(html.contains("50.0%"));
+
+    let response = app
+        .send(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/api/links/{}", link["id"].as_str().unwrap()))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.send(request(&path, "203.0.113.1", false)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_short_links() {
+    let app = common::TestApp::new().await;
+    let service = app.service("Print").await;
+
+    let create = |body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/api/services/{}/links", service.id))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let response = app
+        .send(create(serde_json::json!({
+            "target_url": "https://example.com/menu",
+            "name": "Spring flyer",
+            "slug": "spring-flyer",
+        })))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let link_id = json["data"]["id"].as_str().unwrap().to_string();
+    assert_eq!(json["data"]["name"], "Spring flyer");
+    assert_eq!(json["data"]["url"], "http://localhost:8080/r/spring-flyer");
+
+    // Short links are unique and URL-safe
+    for bad in [
+        serde_json::json!({ "target_url": "https://example.com/", "slug": "spring-flyer" }),
+        serde_json::json!({ "target_url": "https://example.com/", "slug": "spring flyer" }),
+    ] {
+        let response = app.send(create(bad)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    for (ip, referrer) in [
+        ("203.0.113.1", Some("https://news.example/")),
+        ("203.0.113.2", Some("https://news.example/")),
+        ("203.0.113.3", None),
+    ] {
+        let mut builder = Request::builder()
+            .uri("/r/spring-flyer")
+            .header("X-Forwarded-For", ip)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Firefox/120.0",
+            );
+        if let Some(referrer) = referrer {
+            builder = builder.header("Referer", referrer);
+        }
+        let response = app.send(builder.body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    app.clock.advance(chrono::Duration::minutes(1));
+
+    let json = app.get_json(&format!("/api/links/{}", link_id)).await;
+    let report = &json["data"];
+    assert_eq!(report["clicks"], 3);
+    assert_eq!(report["unique_clicks"], 3);
+    let daily: Vec<i64> = report["daily_clicks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|count| count.as_i64().unwrap())
+        .collect();
+    assert_eq!(daily.iter().sum::<i64>(), 3);
+    assert_eq!(daily.last(), Some(&3));
+    assert_eq!(report["referrers"][0]["value"], "https://news.example/");
+    assert_eq!(report["referrers"][0]["count"], 2);
+    assert_eq!(report["referrers"][1]["value"], "");
+    assert_eq!(report["countries"][0]["count"], 3);
+
+    for uri in [
+        format!("/api/links/{}/qr.svg", link_id),
+        format!("/service/{}/links/{}/qr.svg", service.id, link_id),
+    ] {
+        let response = app.get(&uri).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("image/svg+xml"));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("<svg"));
+    }
+
+    let response = app
+        .get(&format!("/service/{}/links/{}", service.id, link_id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Spring flyer"));
+    assert!(html.contains("https://news.example/"));
+
+    // Links of another service are not found under this one
+    let other = app.service("Other").await;
+    let response = app
+        .get(&format!("/service/{}/links/{}", other.id, link_id))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_backdated_hits() {
+    use shymini::db;
+
+    let app = common::TestApp::with(|settings| settings.max_timestamp_skew_secs = 86400).await;
+    let service = app.service("Imports").await;
+    let post = |body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header("X-Forwarded-For", "203.0.113.9")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let now = app.now();
+    let at = |time: chrono::DateTime<chrono::Utc>| {
+        time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    };
+
+    // An import from three hours ago, one from before the skew window, and
+    // one from a clock running ahead
+    for (page, ts) in [
+        ("old", at(now - chrono::Duration::hours(3))),
+        ("ancient", at(now - chrono::Duration::days(5))),
+        ("future", at(now + chrono::Duration::hours(2))),
+    ] {
+        let response = app
+            .send(post(serde_json::json!({
+                "idempotency": page,
+                "location": format!("https://example.com/{}", page),
+                "loadTime": 100,
+                "ts": ts,
+            })))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    // A page view queued offline for ten minutes joins the current session
+    let response = app
+        .send(post(serde_json::json!({
+            "idempotency": "queued",
+            "location": "https://example.com/queued",
+            "loadTime": 100,
+            "ts": 600_000,
+        })))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        now - chrono::Duration::days(7),
+        now + chrono::Duration::seconds(1),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    sessions.sort_by_key(|session| session.start_time);
+    let starts: Vec<_> = sessions.iter().map(|s| s.start_time).collect();
+    assert_eq!(
+        starts,
+        [
+            now - chrono::Duration::days(1),
+            now - chrono::Duration::hours(3),
+            now - chrono::Duration::minutes(10),
+        ]
+    );
+    let current = &sessions[2];
+    assert_eq!(current.last_seen, now);
+    let hits = db::list_hits_for_session(&app.state.pool, current.id, 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 2);
+
+    // Pixels take the same `ts`
+    let response = app
+        .send(
+            Request::builder()
+                .uri(format!(
+                    "/trace/px_{}.gif?ts={}",
+                    service.tracking_id,
+                    at(now - chrono::Duration::hours(5))
+                ))
+                .header("X-Forwarded-For", "203.0.113.10")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        now - chrono::Duration::hours(6),
+        now - chrono::Duration::hours(4),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].start_time, now - chrono::Duration::hours(5));
+}
+
+#[tokio::test]
+async fn test_bounce_rules() {
+    use shymini::db;
+    use shymini::domain::{BounceRule, UpdateService};
+
+    let app = common::TestApp::new().await;
+    let post = |service: &shymini::domain::Service, body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header("X-Forwarded-For", "203.0.113.9")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let mut sessions = Vec::new();
+    for rule in [BounceRule::EngagedTime, BounceRule::NoInteraction] {
+        let service = app.service(rule.as_str()).await;
+        let service = db::update_service(
+            &app.state.pool,
+            service.id,
+            UpdateService {
+                bounce_rule: Some(rule),
+                bounce_threshold_secs: Some(60),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // Only trackers that need it report interaction
+        let script = app
+            .get(&format!("/trace/app_{}.js", service.tracking_id))
+            .await;
+        let script = script.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            String::from_utf8_lossy(&script).contains("markInteraction"),
+            rule == BounceRule::NoInteraction
+        );
+
+        let response = app
+            .send(post(
+                &service,
+                serde_json::json!({
+                    "idempotency": "page",
+                    "location": "https://example.com/",
+                    "loadTime": 100,
+                }),
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        sessions.push((service, rule));
+    }
+
+    let session_of = |service: &shymini::domain::Service| {
+        let pool = app.state.pool.clone();
+        let service_id = service.id;
+        let now = app.now();
+        async move {
+            db::list_sessions(
+                &pool,
+                service_id,
+                now - chrono::Duration::hours(1),
+                now + chrono::Duration::hours(1),
+                None,
+                None,
+                None,
+                10,
+                0,
+            )
+            .await
+            .unwrap()
+            .remove(0)
+        }
+    };
+    for (service, _) in &sessions {
+        assert!(session_of(service).await.is_bounce);
+    }
+
+    // Three minutes of heartbeats on the one page, without interaction
+    app.clock.advance(chrono::Duration::minutes(3));
+    for (service, _) in &sessions {
+        let response = app
+            .send(post(
+                service,
+                serde_json::json!({
+                    "idempotency": "page",
+                    "location": "https://example.com/",
+                }),
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let (engaged, _) = &sessions[0];
+    let (interaction, _) = &sessions[1];
+    assert!(!session_of(engaged).await.is_bounce);
+    assert!(session_of(interaction).await.is_bounce);
+
+    // The visitor scrolls, and says so as they leave
+    let response = app
+        .send(post(
+            interaction,
+            serde_json::json!({
+                "idempotency": "page",
+                "location": "https://example.com/",
+                "end": true,
+                "interacted": true,
+            }),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!session_of(interaction).await.is_bounce);
+}
+
+#[tokio::test]
+async fn test_session_timeout() {
+    use shymini::db;
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Timeouts").await;
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            session_timeout_mins: Some(30),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let post = |body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Content-Type", "application/json")
+            .header("X-Forwarded-For", "203.0.113.9")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let start = app.now();
+
+    let response = app
+        .send(post(serde_json::json!({
+            "idempotency": "page",
+            "location": "https://example.com/",
+            "loadTime": 100,
+        })))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Within the timeout of the last activity the session goes on; eight
+    // hours later the same open page starts a new one
+    for minutes in [20, 20, 8 * 60] {
+        app.clock.advance(chrono::Duration::minutes(minutes));
+        let response = app
+            .send(post(serde_json::json!({
+                "idempotency": "page",
+                "location": "https://example.com/",
+            })))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let mut sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        start - chrono::Duration::hours(1),
+        app.now() + chrono::Duration::hours(1),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    sessions.sort_by_key(|session| session.start_time);
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].start_time, start);
+    assert_eq!(sessions[0].last_seen, start + chrono::Duration::minutes(40));
+    assert_eq!(sessions[1].start_time, app.now());
+    let hits = db::list_hits_for_session(&app.state.pool, sessions[1].id, 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].location, "https://example.com/");
+}
+
+#[tokio::test]
+async fn test_api_bulk_service_update() {
+    let app = common::TestApp::new().await;
+    let blog = app.service("Blog").await;
+    let shop = app.service("Shop").await;
+
+    let bulk = |body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/services/bulk")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let changes = serde_json::json!({
+        "set": {"respect_dnt": true},
+        "add_ignored_ips": ["10.0.0.0/8"],
+    });
+
+    // A dry run tells what would change, and changes nothing
+    let mut dry_run = changes.clone();
+    dry_run["dry_run"] = serde_json::json!(true);
+    let response = app.send(bulk(dry_run)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"]["dry_run"], true);
+    let services = json["data"]["services"].as_array().unwrap();
+    assert_eq!(services.len(), 2);
+    assert_eq!(
+        services[0]["changes"],
+        serde_json::json!([
+            {"field": "ignored_ips", "from": "", "to": "10.0.0.0/8"},
+            {"field": "respect_dnt", "from": false, "to": true},
+        ])
+    );
+    let unchanged = shymini::db::get_service(&app.state.pool, blog.id)
+        .await
+        .unwrap();
+    assert!(!unchanged.respect_dnt);
+    assert!(unchanged.ignored_ips.is_empty());
+
+    let response = app.send(bulk(changes.clone())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    for id in [blog.id, shop.id] {
+        let service = shymini::db::get_service(&app.state.pool, id).await.unwrap();
+        assert!(service.respect_dnt);
+        assert_eq!(service.ignored_ips, "10.0.0.0/8");
+    }
+
+    // Services already as asked are left out
+    let response = app.send(bulk(changes)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"]["services"], serde_json::json!([]));
+
+    // A named set of services
+    let response = app
+        .send(bulk(serde_json::json!({
+            "services": [shop.id],
+            "remove_ignored_ips": ["10.0.0.0/8"],
+        })))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"]["services"][0]["name"], "Shop");
+    let blog = shymini::db::get_service(&app.state.pool, blog.id)
+        .await
+        .unwrap();
+    assert_eq!(blog.ignored_ips, "10.0.0.0/8");
+
+    let unknown = serde_json::json!({
+        "services": [uuid::Uuid::new_v4()],
+        "set": {"collect_ips": false},
+    });
+    let response = app.send(bulk(unknown)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    for invalid in [
+        serde_json::json!({}),
+        serde_json::json!({"add_ignored_ips": ["office"]}),
+    ] {
+        let response = app.send(bulk(invalid)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_ingest_debug_log() {
+    use shymini::domain::{IngressDecision, UpdateService};
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Debugged").await;
+    let service = shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            origins: Some("https://example.com".to_string()),
+            respect_dnt: Some(true),
+            ignore_robots: Some(true),
+            ignored_ips: Some("10.0.0.0/8".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let page_view = |location: &str, origin: &str, ip: &str, user_agent: &str, dnt: bool| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Origin", origin)
+            .header("Content-Type", "application/json")
+            .header("X-Forwarded-For", ip)
+            .header("User-Agent", user_agent);
+        if dnt {
+            request = request.header("DNT", "1");
+        }
+        let body =
+            serde_json::json!({"idempotency": location, "location": location, "loadTime": 100});
+        request.body(Body::from(body.to_string())).unwrap()
+    };
+    let browser = "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0";
+    let requests = || {
+        [
+            page_view(
+                "https://example.com/a",
+                "https://example.com",
+                "203.0.113.1",
+                browser,
+                false,
+            ),
+            page_view(
+                "https://evil.test/b",
+                "https://evil.test",
+                "203.0.113.2",
+                browser,
+                false,
+            ),
+            page_view(
+                "https://example.com/c",
+                "https://example.com",
+                "203.0.113.3",
+                browser,
+                true,
+            ),
+            page_view(
+                "https://example.com/d",
+                "https://example.com",
+                "10.1.2.3",
+                browser,
+                false,
+            ),
+            page_view(
+                "https://example.com/e",
+                "https://example.com",
+                "203.0.113.4",
+                "Googlebot/2.1",
+                false,
+            ),
+        ]
+    };
+
+    // Nothing is kept until the service turns the log on
+    for request in requests() {
+        app.send(request).await;
+    }
+    assert!(app.state.ingress_log.entries(service.id).is_empty());
+    let response = app.get(&format!("/service/{}/debug", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("keeps no debug log"));
+
+    let service = shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            debug_log: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    app.state.cache.invalidate_service(service.id).await;
+    for request in requests() {
+        app.send(request).await;
+    }
+    // Heartbeats aren't logged
+    let heartbeat = serde_json::json!({"idempotency": "https://example.com/a", "location": "https://example.com/a"});
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Origin", "https://example.com")
+                .header("Content-Type", "application/json")
+                .header("X-Forwarded-For", "203.0.113.1")
+                .header("User-Agent", browser)
+                .body(Body::from(heartbeat.to_string()))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let entries = app.state.ingress_log.entries(service.id);
+    let decisions: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.location.as_str(), entry.decision))
+        .collect();
+    assert_eq!(
+        decisions,
+        [
+            ("https://example.com/e", IngressDecision::DroppedBot),
+            ("https://example.com/d", IngressDecision::DroppedIp),
+            ("https://example.com/c", IngressDecision::DroppedDnt),
+            ("https://evil.test/b", IngressDecision::OriginRejected),
+            ("https://example.com/a", IngressDecision::Accepted),
+        ]
+    );
+    assert_eq!(entries[3].origin.as_deref(), Some("https://evil.test"));
+    assert_eq!(entries[0].user_agent, "Googlebot/2.1");
+
+    let response = app.get(&format!("/service/{}/debug", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let page = String::from_utf8_lossy(&body);
+    assert!(page.contains("Origin not allowed"));
+    assert!(page.contains("from https://evil.test"));
+    assert!(page.contains("Dropped: ignored IP"));
+}
+
+#[tokio::test]
+async fn test_api_validate_hit() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Validated").await;
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            origins: Some("https://example.com".to_string()),
+            respect_dnt: Some(true),
+            respect_gpc: Some(true),
+            ignore_robots: Some(true),
+            ignored_ips: Some("10.0.0.0/8".to_string()),
+            strip_trailing_slash: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let validate = |trial: serde_json::Value| {
+        let app = &app;
+        async move {
+            let response = app
+                .send(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/api/services/{}/validate-hit", service.id))
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(trial.to_string()))
+                        .unwrap(),
+                )
+                .await;
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            (status, json["data"].clone())
+        }
+    };
+    let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+
+    let (status, data) = validate(serde_json::json!({
+        "location": "https://example.com/pricing/",
+        "user_agent": firefox,
+        "ip": "203.0.113.1",
+    }))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data["decision"], "accepted");
+    assert_eq!(data["tracker"], "Js");
+    assert_eq!(data["location"], "https://example.com/pricing");
+    assert_eq!(data["environment"], "production");
+    assert_eq!(data["user_agent"]["browser"], "Firefox");
+    assert_eq!(data["stores_ip"], true);
+
+    for (trial, decision) in [
+        (
+            serde_json::json!({"location": "https://evil.test/", "user_agent": firefox}),
+            "origin_rejected",
+        ),
+        (
+            serde_json::json!({"location": "https://example.com/", "user_agent": firefox, "gpc": true}),
+            "dropped_gpc",
+        ),
+        (
+            serde_json::json!({"location": "https://example.com/", "user_agent": firefox, "dnt": true}),
+            "dropped_dnt",
+        ),
+        (
+            serde_json::json!({"location": "https://example.com/", "user_agent": firefox, "ip": "10.1.2.3", "pixel": true}),
+            "dropped_ip",
+        ),
+        (
+            serde_json::json!({"location": "https://example.com/", "user_agent": "Googlebot/2.1 (+http://www.google.com/bot.html)"}),
+            "dropped_bot",
+        ),
+    ] {
+        let (status, data) = validate(trial).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(data["decision"], decision);
+    }
+
+    let (status, _) = validate(serde_json::json!({"location": "/", "dnt": "yes"})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Nothing was recorded
+    let usage = shymini::db::get_usage(
+        &app.state.pool,
+        service.id,
+        &shymini::domain::ServiceUsage::month_of(app.now()),
+    )
+    .await
+    .unwrap();
+    assert_eq!((usage.hits, usage.sessions), (0, 0));
+}
+
+#[tokio::test]
+async fn test_origin_rejection_diagnostics() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Strict").await;
+    let service = shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            origins: Some("https://example.com, https://www.example.com".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let rejection = |method: &str, origin: Option<&str>| {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("/trace/app_{}.js", service.tracking_id));
+        if let Some(origin) = origin {
+            request = request.header("Origin", origin);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let app = &app;
+        async move {
+            let response = app.send(request).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    // The body names the origin received, but not the allowed ones
+    let body = rejection("GET", Some("https://staging.example.com")).await;
+    assert_eq!(body["error"], "Invalid origin");
+    assert_eq!(body["origin"], "https://staging.example.com");
+    assert!(body.get("allowed_origins").is_none());
+
+    // While the service keeps a debug log, they're listed too
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            debug_log: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    app.state.cache.invalidate_service(service.id).await;
+    let body = rejection("OPTIONS", None).await;
+    assert_eq!(body["origin"], serde_json::Value::Null);
+    assert_eq!(
+        body["allowed_origins"],
+        serde_json::json!(["https://example.com", "https://www.example.com"])
+    );
+
+    // Mismatches are counted per service in the status
+    let json = app.get_json("/api/status").await;
+    let mismatches = json["data"]["origin_mismatches"].as_array().unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0]["service_id"], service.id.to_string());
+    assert_eq!(mismatches[0]["count"], 2);
+    assert_eq!(mismatches[0]["last_origin"], serde_json::Value::Null);
+
+    let response = app.get("/admin/status").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Rejected origins"));
+}
+
+#[tokio::test]
+async fn test_dropped_hit_counters() {
+    use shymini::domain::{DroppedHits, IngressDecision, UpdateService};
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Counted").await;
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            origins: Some("https://example.com".to_string()),
+            respect_dnt: Some(true),
+            ignore_robots: Some(true),
+            ignored_ips: Some("10.0.0.0/8".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let browser = "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0";
+    let page_view = |key: &str, origin: &str, ip: &str, user_agent: &str, dnt: bool| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(format!("/trace/app_{}.js", service.tracking_id))
+            .header("Origin", origin)
+            .header("Content-Type", "application/json")
+            .header("X-Forwarded-For", ip)
+            .header("User-Agent", user_agent);
+        if dnt {
+            request = request.header("DNT", "1");
+        }
+        let body = serde_json::json!({
+            "idempotency": key,
+            "location": "https://example.com/",
+            "loadTime": 100,
+        });
+        request.body(Body::from(body.to_string())).unwrap()
+    };
+    let requests = [
+        page_view("a", "https://example.com", "203.0.113.1", browser, false),
+        page_view("b", "https://evil.test", "203.0.113.2", browser, false),
+        page_view("c", "https://example.com", "203.0.113.3", browser, true),
+        page_view("d", "https://example.com", "10.1.2.3", browser, false),
+        page_view("e", "https://example.com", "10.1.2.4", browser, false),
+        page_view(
+            "f",
+            "https://example.com",
+            "203.0.113.5",
+            "Googlebot/2.1",
+            false,
+        ),
+    ];
+    for request in requests {
+        app.send(request).await;
+    }
+    // The first page load again, once the cache has forgotten its key
+    app.state.cache.hit_idempotency.invalidate_all();
+    app.clock.advance(chrono::Duration::hours(1));
+    app.send(page_view(
+        "a",
+        "https://example.com",
+        "203.0.113.1",
+        browser,
+        false,
+    ))
+    .await;
+    // Heartbeats aren't page views
+    let heartbeat = Request::builder()
+        .method("POST")
+        .uri(format!("/trace/app_{}.js", service.tracking_id))
+        .header("Origin", "https://example.com")
+        .header("Content-Type", "application/json")
+        .header("X-Forwarded-For", "10.1.2.3")
+        .body(Body::from(
+            r#"{"idempotency":"d","location":"https://example.com/"}"#,
+        ))
+        .unwrap();
+    app.send(heartbeat).await;
+
+    let day = app.now().date_naive();
+    let dropped = shymini::db::get_dropped_hits(&app.state.pool, service.id, day, day)
+        .await
+        .unwrap();
+    let dropped_hits = |reason, count| DroppedHits { reason, count };
+    assert_eq!(
+        dropped,
+        vec![
+            dropped_hits(IngressDecision::DroppedIp, 2),
+            dropped_hits(IngressDecision::DroppedBot, 1),
+            dropped_hits(IngressDecision::DroppedDnt, 1),
+            dropped_hits(IngressDecision::DroppedDuplicate, 1),
+            dropped_hits(IngressDecision::OriginRejected, 1),
+        ]
+    );
+    let yesterday = day - chrono::Duration::days(1);
+    assert!(
+        shymini::db::get_dropped_hits(&app.state.pool, service.id, yesterday, yesterday)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let response = app
+        .get(&format!("/service/{}/panels/data-quality", service.id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Dropped: ignored IP"));
+    assert!(html.contains("Dropped: repeated page load"));
+    assert!(html.contains("<td class=\"py-2 text-right\">6</td>"));
+}
+
+#[tokio::test]
+async fn test_respect_gpc_without_dnt() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Privacy").await;
+    let service = shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            respect_dnt: Some(false),
+            respect_gpc: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let pixel = |header: &str, ip: &str| {
+        Request::builder()
+            .uri(format!("/trace/px_{}.gif", service.tracking_id))
+            .header("Origin", "https://example.com")
+            .header("Referer", "https://example.com/")
+            .header("X-Forwarded-For", ip)
+            .header(header, "1")
+            .body(Body::empty())
+            .unwrap()
+    };
+    app.send(pixel("DNT", "203.0.113.1")).await;
+    app.send(pixel("Sec-GPC", "203.0.113.2")).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let stats = app
+        .get_json(&format!("/api/services/{}/stats", service.id))
+        .await;
+    assert_eq!(stats["data"]["hit_count"], 1);
+    let day = app.now().date_naive();
+    let dropped = shymini::db::get_dropped_hits(&app.state.pool, service.id, day, day)
+        .await
+        .unwrap();
+    assert_eq!(dropped.len(), 1);
+    assert_eq!(dropped[0].reason.as_str(), "dropped_gpc");
+
+    // The module checks only the signal the service honors
+    let response = app
+        .get(&format!("/trace/app_{}.esm.js", service.tracking_id))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let module = String::from_utf8_lossy(&body);
+    assert!(module.contains("navigator.globalPrivacyControl"));
+    assert!(!module.contains("navigator.doNotTrack"));
+
+    // The setting shows on the form and is saved from it
+    let response = app.get(&format!("/service/{}/manage", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("name=\"respect_gpc\" checked"));
+}
+
+#[tokio::test]
+async fn test_linked_users() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Shop").await;
+    assert!(!service.link_users);
+
+    let desktop = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+    let phone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+    let pixel = |identifier: &str, ip: &str, user_agent: &str| {
+        Request::builder()
+            .uri(format!(
+                "/trace/px_{}/{}.gif",
+                service.tracking_id, identifier
+            ))
+            .header("Origin", "https://example.com")
+            .header("X-Forwarded-For", ip)
+            .header("User-Agent", user_agent)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // Off by default: identifiers stay on their sessions, unlinked
+    app.send(pixel("user-1", "203.0.113.1", desktop)).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+    let users = app
+        .get_json(&format!("/api/services/{}/users", service.id))
+        .await;
+    assert_eq!(users["data"]["users"], 0);
+    let response = app.get(&format!("/service/{}/users", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("doesn&#x27;t link users"));
+
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            link_users: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    app.state.cache.invalidate_service(service.id).await;
+
+    // One user on two devices, another on one
+    for (identifier, ip, user_agent) in [
+        ("user-2", "203.0.113.2", desktop),
+        ("user-2", "203.0.113.3", phone),
+        ("user-3", "203.0.113.4", desktop),
+    ] {
+        app.send(pixel(identifier, ip, user_agent)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let users = app
+        .get_json(&format!("/api/services/{}/users", service.id))
+        .await;
+    assert_eq!(users["data"]["users"], 2);
+    assert_eq!(users["data"]["sessions"], 3);
+    assert_eq!(users["data"]["multi_device_users"], 1);
+    assert_eq!(users["data"]["devices_per_user"][0]["count"], 1);
+    assert_eq!(users["data"]["devices_per_user"][1]["count"], 1);
+
+    // Only the hashed key is stored with a session
+    let sessions = app
+        .get_json(&format!("/api/services/{}/sessions", service.id))
+        .await;
+    assert!(!sessions.to_string().contains("user_key"));
+
+    let response = app.get(&format!("/service/{}/users", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Users on several devices"));
+    let response = app.get(&format!("/service/{}", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(&format!("/service/{}/users", service.id)));
+
+    // Unlinking forgets every user
+    shymini::db::clear_user_keys(&app.state.pool, service.id)
+        .await
+        .unwrap();
+    let users = app
+        .get_json(&format!("/api/services/{}/users", service.id))
+        .await;
+    assert_eq!(users["data"]["users"], 0);
+}
+
+#[tokio::test]
+async fn test_retention() {
+    use shymini::domain::UpdateService;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Shop").await;
+    shymini::db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            link_users: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    app.state.cache.invalidate_service(service.id).await;
+
+    let pixel = |identifier: &str, ip: &str| {
+        Request::builder()
+            .uri(format!(
+                "/trace/px_{}/{}.gif",
+                service.tracking_id, identifier
+            ))
+            .header("Origin", "https://example.com")
+            .header("X-Forwarded-For", ip)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // Two users first seen in one week, one of them back the week after
+    app.send(pixel("user-1", "203.0.113.1")).await;
+    app.send(pixel("user-2", "203.0.113.2")).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::weeks(1));
+    app.send(pixel("user-1", "203.0.113.3")).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let retention = app
+        .get_json(&format!("/api/services/{}/retention?tz=UTC", service.id))
+        .await;
+    let cohorts = retention["data"]["cohorts"].as_array().unwrap();
+    let last = cohorts.len() - 1;
+    assert_eq!(cohorts[last - 1]["users"], 2);
+    assert_eq!(cohorts[last - 1]["returned"], serde_json::json!([1]));
+    assert_eq!(cohorts[last - 1]["retention"], serde_json::json!([0.5]));
+    assert_eq!(cohorts[last]["users"], 0);
+    assert!(cohorts[..last - 1]
+        .iter()
+        .all(|cohort| cohort["users"] == 0));
+
+    let response = app.get(&format!("/service/{}/retention", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("title=\"1 / 2\">50%</td>"));
+    let response = app.get(&format!("/service/{}", service.id)).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(&format!("/service/{}/retention", service.id)));
+}
+
+#[tokio::test]
+async fn test_export_hits_parquet() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Example Site").await;
+    let other = app.service("Other Site").await;
+    let start = app.now() - chrono::Duration::days(1);
+    app.visit(
+        &service,
+        "visitor",
+        &[
+            ("/", start),
+            ("/pricing", start + chrono::Duration::minutes(1)),
+        ],
+    )
+    .await;
+    app.visit(&other, "visitor", &[("/elsewhere", start)]).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    let response = app
+        .get(&format!(
+            "/api/services/{}/export.parquet?tz=UTC",
+            service.id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/vnd.apache.parquet"
+    );
+    assert_eq!(
+        response.headers()["content-disposition"],
+        format!(
+            r#"attachment; filename="{}-hits-2024-05-16-2024-06-15.parquet""#,
+            service.tracking_id
+        )
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.starts_with(b"PAR1"));
+
+    let reader = SerializedFileReader::new(body).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+    let rows: Vec<_> = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(rows[0].get_string(7).unwrap(), "/");
+    assert!(rows[0].get_bool(4).unwrap());
+    assert_eq!(rows[1].get_string(7).unwrap(), "/pricing");
+    assert_eq!(rows[1].get_string(12).unwrap(), "DE");
+    assert_eq!(
+        rows[1].get_timestamp_millis(2).unwrap(),
+        (start + chrono::Duration::minutes(1)).timestamp_millis()
+    );
+
+    // A range without hits still makes a valid file
+    let response = app
+        .get(&format!(
+            "/api/services/{}/export.parquet?startDate=2020-01-01&endDate=2020-01-02",
+            service.id
+        ))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let reader = SerializedFileReader::new(body).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 0);
+}
+
+#[tokio::test]
+async fn test_query_api() {
+    use shymini::auth;
+    use shymini::db;
+    use shymini::domain::{OrganizationId, Role};
+
+    async fn create_token(app: &common::TestApp, role: Role) -> String {
+        let token = auth::generate_token(auth::API_TOKEN_PREFIX);
+        db::create_api_token(
+            &app.state.pool,
+            OrganizationId::DEFAULT,
+            "analysts",
+            &auth::hash_token(&token),
+            role,
+            None,
+        )
+        .await
+        .unwrap();
+        token
+    }
+    let request = |sql: &str, token: Option<&str>| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/api/query")
+            .header("Content-Type", "application/json");
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        request
+            .body(Body::from(serde_json::json!({ "sql": sql }).to_string()))
+            .unwrap()
+    };
+
+    // Off unless turned on
+    let app = common::TestApp::new().await;
+    let owner = create_token(&app, Role::Owner).await;
+    let response = app.send(request("SELECT 1", Some(&owner))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let app = common::TestApp::with(|settings| {
+        settings.query_api = true;
+        settings.query_timeout_secs = 1;
+        settings.query_max_rows = 2;
+    })
+    .await;
+    let owner = create_token(&app, Role::Owner).await;
+    let query = |sql: &str| request(sql, Some(&owner));
+    let service = app.service("Blog").await;
+    app.service("Shop").await;
+
+    let response = app
+        .send(query(&format!(
+            "SELECT name, id = '{}' AS mine, 1.5 AS ratio, NULL AS missing \
+             FROM services WHERE name = 'Blog';",
+            service.id
+        )))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let result = &json["data"];
+    assert_eq!(
+        result["columns"],
+        serde_json::json!(["name", "mine", "ratio", "missing"])
+    );
+    assert_eq!(result["rows"], serde_json::json!([["Blog", 1, 1.5, null]]));
+    assert_eq!(result["truncated"], false);
+
+    // Rows beyond the limit are left out
+    let response = app.send(query("VALUES (1), (2), (3) -- three rows")).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["data"]["rows"], serde_json::json!([[1], [2]]));
+    assert_eq!(json["data"]["truncated"], true);
+
+    // Writes are refused, whether or not they get past the statement check
+    for sql in [
+        "DELETE FROM services",
+        "SELECT 1; DELETE FROM services",
+        "WITH gone AS (SELECT id FROM services) DELETE FROM services",
+        "SELECT nope FROM services",
+    ] {
+        let response = app.send(query(sql)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", sql);
+    }
+
+    // Queries running too long are interrupted
+    let response = app
+        .send(query(
+            "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) \
+             SELECT count(*) FROM n",
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("longer than 1 seconds"));
+
+    // The connection is writable again afterwards
+    app.service("Docs").await;
+    assert_eq!(
+        db::list_services(&app.state.pool, OrganizationId::DEFAULT)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+
+    // Admins only, with a token even though multi-tenancy is off
+    let editor = create_token(&app, Role::Editor).await;
+    let response = app.send(request("SELECT 1", Some(&editor))).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app.send(request("SELECT 1", None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_ip_encryption() {
+    use base64::Engine;
+    use shymini::privacy::ip_cipher::IpEncryptionKey;
+
+    let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+    let app = common::TestApp::with(|settings| {
+        settings.ip_encryption_key = Some(IpEncryptionKey::try_from(key).unwrap());
+    })
+    .await;
+    let service = app.service("Newsletter").await;
+
+    let response = app
+        .send(
+            Request::builder()
+                .uri(format!("/trace/px_{}/reader.gif", service.tracking_id))
+                .header("X-Forwarded-For", "203.0.113.7")
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Thunderbird/115.0",
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    app.clock.advance(chrono::Duration::seconds(1));
+
+    // The database holds no trace of the IP in the clear
+    let (id, ip, ip_encrypted, ip_hash): (String, Option<String>, Option<String>, Option<String>) =
+        sqlx::query_as("SELECT id, ip, ip_encrypted, ip_hash FROM sessions")
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+    assert_eq!(ip, None);
+    assert!(!ip_encrypted.unwrap().contains("203.0.113"));
+    assert_eq!(ip_hash.unwrap().len(), 64);
+
+    // Viewers see it decrypted
+    let json = app.get_json(&format!("/api/sessions/{}", id)).await;
+    assert_eq!(json["data"]["ip"], "203.0.113.7");
+    assert!(json["data"].get("ip_encrypted").is_none());
+    let json = app
+        .get_json(&format!("/api/services/{}/sessions", service.id))
+        .await;
+    assert_eq!(json["data"][0]["ip"], "203.0.113.7");
+    let response = app
+        .send(
+            Request::builder()
+                .uri(format!("/api/services/{}/sessions", service.id))
+                .header("Accept", "application/x-ndjson")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(r#""ip":"203.0.113.7""#));
+    let response = app
+        .get(&format!("/service/{}/sessions/{}", service.id, id))
+        .await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("203.0.113.7"));
+
+    // Searching for the IP finds the session by its hash
+    let response = app.get("/search?q=203.0.113.7").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(&id));
+    let response = app.get("/search?q=203.0.113.8").await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(!String::from_utf8_lossy(&body).contains(&id));
+}
+
+#[tokio::test]
+async fn test_vault_secrets() {
+    use axum::http::HeaderMap;
+    use axum::response::IntoResponse;
+    use shymini::secrets;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let vault = Router::new().route(
+        "/v1/secret/data/shymini",
+        axum::routing::get(|headers: HeaderMap| async move {
+            if headers.get("x-vault-token").map(|t| t.as_bytes()) != Some(b"root-token") {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            axum::Json(serde_json::json!({
+                "data": {
+                    "data": {"secret_key": "from-vault", "smtp_url": "smtp://mail:25"},
+                    "metadata": {"version": 1}
+                }
+            }))
+            .into_response()
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, vault).await.unwrap() });
+
+    let values = secrets::vault_secrets(&addr, "root-token", "secret/data/shymini")
+        .await
+        .unwrap();
+    assert_eq!(values["secret_key"], "from-vault");
+    assert_eq!(values["smtp_url"], "smtp://mail:25");
+
+    assert!(
+        secrets::vault_secrets(&addr, "wrong", "secret/data/shymini")
+            .await
+            .is_err()
+    );
+    assert!(
+        secrets::vault_secrets(&addr, "root-token", "secret/data/other")
+            .await
+            .is_err()
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_listener() {
+    use shymini::server::{self, ListenAddr, Listener, ServeOptions, SocketMode};
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let app = common::TestApp::new().await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shymini.sock");
+    let listen = ListenAddr::try_from(format!("unix:{}", path.display())).unwrap();
+
+    let listener = Listener::bind(&listen, SocketMode(0o600)).await.unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // A second server can't take over the socket
+    assert!(Listener::bind(&listen, SocketMode(0o600)).await.is_err());
+
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let options = ServeOptions::from_settings(&app.state.settings);
+    let server = tokio::spawn(server::serve(
+        listener,
+        app.router.clone(),
+        options,
+        async {
+            let _ = stop_rx.await;
+        },
+    ));
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn test_serve_options() {
+    #[cfg(unix)]
+    use shymini::server::SocketMode;
+    use shymini::server::{self, ListenAddr, Listener, ServeOptions};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let app = common::TestApp::new().await;
+    let listen = ListenAddr::try_from("127.0.0.1:0".to_string()).unwrap();
+    #[cfg(unix)]
+    let listener = Listener::bind(&listen, SocketMode(0o660)).await.unwrap();
+    #[cfg(not(unix))]
+    let listener = Listener::bind(&listen).await.unwrap();
+    #[cfg_attr(not(unix), allow(irrefutable_let_patterns))]
+    let Listener::Tcp(tcp) = &listener
+    else {
+        panic!("not bound to TCP");
+    };
+    let addr = tcp.local_addr().unwrap();
+    let options = ServeOptions {
+        keep_alive: true,
+        keep_alive_timeout: Some(Duration::from_millis(300)),
+        max_connections: Some(1),
+        max_concurrent_streams: None,
+    };
+    tokio::spawn(server::serve(
+        listener,
+        app.router.clone(),
+        options,
+        std::future::pending(),
+    ));
+
+    async fn request(stream: &mut tokio::net::TcpStream) -> String {
+        stream
+            .write_all(b"GET /login HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    // Kept alive for a second request; the login page redirects, with no body
+    let mut first = tokio::net::TcpStream::connect(addr).await.unwrap();
+    assert!(request(&mut first).await.starts_with("HTTP/1.1 303"));
+    assert!(request(&mut first).await.starts_with("HTTP/1.1 303"));
+
+    // Over the limit, a connection waits for the first to close
+    let mut second = tokio::net::TcpStream::connect(addr).await.unwrap();
+    second
+        .write_all(b"GET /login HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0u8; 16];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), second.read(&mut buf))
+            .await
+            .is_err()
+    );
+
+    // Idle past the timeout, the first is closed and the second served
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), first.read_to_end(&mut rest))
+        .await
+        .unwrap()
+        .unwrap();
+    let n = tokio::time::timeout(Duration::from_secs(2), second.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&buf[..n]).starts_with("HTTP/1.1 303"));
+}
+
+#[tokio::test]
+async fn test_internal_referrers() {
+    use shymini::db;
+    use shymini::domain::{CreateHit, TrackerType, UpdateService};
+
+    let app = common::TestApp::new().await;
+    let service = app.service("Site").await;
+    let now = app.now() - chrono::Duration::minutes(5);
+    for (identifier, referrer) in [
+        ("a", "https://www.example.com/pricing"),
+        ("b", "https://news.example/item"),
+        ("c", ""),
+    ] {
+        let session = app.session(&service, identifier, now).await;
+        db::create_hit(
+            &app.state.pool,
+            CreateHit {
+                session_id: session.id,
+                service_id: service.id,
+                initial: true,
+                start_time: now,
+                tracker: TrackerType::Js,
+                location: "https://example.com/".to_string(),
+                referrer: referrer.to_string(),
+                load_time: None,
+                environment: Default::default(),
+                prefetched: false,
+                referrer_spam: false,
+            },
+        )
+        .await
+        .unwrap();
+    }
+    let referrers = |stats: serde_json::Value| {
+        let mut values: Vec<String> = stats["data"]["referrers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["value"].as_str().unwrap().to_string())
+            .collect();
+        values.sort();
+        values
+    };
+    let uri = format!("/api/services/{}/stats", service.id);
+
+    // Navigations from the service's own link don't count as referrers
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            hide_internal_referrers: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    app.state.cache.invalidate_service(service.id).await;
+    assert_eq!(
+        referrers(app.get_json(&uri).await),
+        vec!["", "https://news.example/item"]
+    );
+
+    // Unless the service keeps them
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri("/api/services/bulk")
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::json!({"set": {"hide_internal_referrers": false}}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        referrers(app.get_json(&uri).await),
+        vec![
+            "",
+            "https://news.example/item",
+            "https://www.example.com/pricing"
+        ]
+    );
+
+    // Direct visits are named so on the dashboard
+    let response = app
+        .get(&format!("/service/{}/panels/referrers", service.id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains(">Direct<"));
+}
@@ -0,0 +1,629 @@
+//! Time-dependent stats, checked against a fake clock
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use chrono::Duration;
+use http_body_util::BodyExt;
+use shymini::db;
+use shymini::domain::{Service, UpdateService, WeekStart};
+
+mod common;
+
+use common::TestApp;
+
+// Five-second heartbeats: sessions seen in the last ten seconds are online
+async fn create_app() -> TestApp {
+    TestApp::with(|settings| settings.script_heartbeat_frequency_ms = 5000).await
+}
+
+async fn stats(app: &TestApp, service: &Service, query: &str) -> serde_json::Value {
+    let json = app
+        .get_json(&format!("/api/services/{}/stats{}", service.id, query))
+        .await;
+    json["data"].clone()
+}
+
+#[tokio::test]
+async fn test_currently_online() {
+    let app = create_app().await;
+    let service = app.service("Online").await;
+    let now = app.now();
+
+    app.visit(&service, "a", &[("/", now - Duration::seconds(5))])
+        .await;
+    app.visit(
+        &service,
+        "b",
+        &[
+            ("/", now - Duration::minutes(5)),
+            ("/about", now - Duration::seconds(8)),
+        ],
+    )
+    .await;
+    app.visit(&service, "c", &[("/", now - Duration::minutes(5))])
+        .await;
+
+    // Both the plain and the URL-filtered code paths
+    for query in ["", "?urlPattern=%2F"] {
+        assert_eq!(stats(&app, &service, query).await["currently_online"], 2);
+    }
+
+    app.clock.advance(Duration::seconds(3));
+    for query in ["", "?urlPattern=%2F"] {
+        assert_eq!(stats(&app, &service, query).await["currently_online"], 1);
+    }
+
+    app.clock.advance(Duration::minutes(1));
+    assert_eq!(stats(&app, &service, "").await["currently_online"], 0);
+}
+
+#[tokio::test]
+async fn test_active_user_timeout_override() {
+    let app = create_app().await;
+    let service = app.service("Long reads").await;
+    let now = app.now();
+
+    app.visit(&service, "a", &[("/", now - Duration::seconds(30))])
+        .await;
+    assert_eq!(stats(&app, &service, "").await["currently_online"], 0);
+
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            active_user_timeout_ms: Some(60_000),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    for query in ["", "?urlPattern=%2F"] {
+        assert_eq!(stats(&app, &service, query).await["currently_online"], 1);
+    }
+
+    app.clock.advance(Duration::seconds(31));
+    assert_eq!(stats(&app, &service, "").await["currently_online"], 0);
+}
+
+#[tokio::test]
+async fn test_bounce_rate() {
+    let app = create_app().await;
+    let service = app.service("Bounces").await;
+    let now = app.now();
+
+    assert!(stats(&app, &service, "").await["bounce_rate_pct"].is_null());
+
+    let start = now - Duration::hours(1);
+    app.visit(&service, "a", &[("/", start)]).await;
+    app.visit(
+        &service,
+        "b",
+        &[("/", start), ("/pricing", start + Duration::minutes(2))],
+    )
+    .await;
+    app.visit(
+        &service,
+        "c",
+        &[
+            ("/", start),
+            ("/pricing", start + Duration::minutes(1)),
+            ("/signup", start + Duration::minutes(3)),
+        ],
+    )
+    .await;
+
+    for query in ["", "?urlPattern=%2F"] {
+        let stats = stats(&app, &service, query).await;
+        assert_eq!(stats["session_count"], 3, "query {}", query);
+        assert_eq!(stats["hit_count"], 6, "query {}", query);
+        assert_eq!(stats["bounce_rate_pct"], 33.3, "query {}", query);
+        // Sessions last from their first to their last view
+        assert_eq!(stats["avg_session_duration"], 100.0, "query {}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_session_histograms() {
+    let app = create_app().await;
+    let service = app.service("Histograms").await;
+    let start = app.now() - Duration::hours(1);
+
+    app.visit(&service, "a", &[("/", start)]).await;
+    app.visit(
+        &service,
+        "b",
+        &[("/", start), ("/pricing", start + Duration::minutes(2))],
+    )
+    .await;
+    app.visit(
+        &service,
+        "c",
+        &[
+            ("/", start),
+            ("/pricing", start + Duration::minutes(1)),
+            ("/signup", start + Duration::minutes(3)),
+        ],
+    )
+    .await;
+
+    fn counts(buckets: &serde_json::Value) -> Vec<(String, i64)> {
+        buckets
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|b| b["count"] != 0)
+            .map(|b| {
+                (
+                    b["label"].as_str().unwrap().to_string(),
+                    b["count"].as_i64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    for query in ["", "?urlPattern=%2F"] {
+        let stats = stats(&app, &service, query).await;
+        assert_eq!(
+            counts(&stats["session_durations"]),
+            [
+                ("<10s".to_string(), 1),
+                ("1–3m".to_string(), 1),
+                ("3–10m".to_string(), 1)
+            ],
+            "query {}",
+            query
+        );
+        assert_eq!(
+            counts(&stats["session_depths"]),
+            [
+                ("1".to_string(), 1),
+                ("2".to_string(), 1),
+                ("3".to_string(), 1)
+            ],
+            "query {}",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_comparison_period() {
+    let app = create_app().await;
+    let service = app.service("Comparison").await;
+    let now = app.now();
+
+    for (identifier, age) in [("a", 1), ("b", 10), ("c", 40), ("d", 70)] {
+        app.visit(&service, identifier, &[("/", now - Duration::days(age))])
+            .await;
+    }
+
+    // The last 30 days, compared with the 30 days before
+    let stats_now = stats(&app, &service, "").await;
+    assert_eq!(stats_now["session_count"], 2);
+    assert_eq!(stats_now["compare"]["session_count"], 1);
+    assert_eq!(stats_now["compare"]["hit_count"], 1);
+
+    // The windows move with the clock
+    app.clock.advance(Duration::days(25));
+    let later = stats(&app, &service, "").await;
+    assert_eq!(later["session_count"], 1);
+    assert_eq!(later["compare"]["session_count"], 1);
+
+    // An explicit range is compared with the same length of time before it
+    let stats_june = stats(
+        &app,
+        &service,
+        "?startDate=2024-05-10&endDate=2024-06-09&tz=UTC",
+    )
+    .await;
+    assert_eq!(stats_june["session_count"], 1);
+    assert_eq!(stats_june["compare"]["session_count"], 1);
+
+    // Unless the previous period is turned off
+    let uncompared = stats(&app, &service, "?compare=false").await;
+    assert_eq!(uncompared["session_count"], 1);
+    assert!(uncompared["compare"].is_null());
+}
+
+#[tokio::test]
+async fn test_comparison_modes() {
+    let app = create_app().await;
+    let service = app.service("Comparison modes").await;
+    let now = app.now();
+
+    // A Tuesday and a Thursday the week before, and a Sunday in May
+    for (identifier, age) in [("a", 4), ("b", 11), ("c", 9), ("d", 34)] {
+        app.visit(&service, identifier, &[("/", now - Duration::days(age))])
+            .await;
+    }
+
+    // Monday to Friday
+    let range = "?startDate=2024-06-10T00:00:00Z&endDate=2024-06-15T00:00:00Z";
+    for (mode, compared, start, end) in [
+        (
+            "previous_period",
+            1,
+            "2024-06-05T00:00:00Z",
+            "2024-06-10T00:00:00Z",
+        ),
+        (
+            "last_week",
+            2,
+            "2024-06-03T00:00:00Z",
+            "2024-06-08T00:00:00Z",
+        ),
+        (
+            "last_month",
+            1,
+            "2024-05-10T00:00:00Z",
+            "2024-05-15T00:00:00Z",
+        ),
+    ] {
+        let stats = stats(&app, &service, &format!("{}&compare={}", range, mode)).await;
+        assert_eq!(stats["session_count"], 1, "mode {}", mode);
+        assert_eq!(stats["compare"]["session_count"], compared, "mode {}", mode);
+        assert_eq!(stats["compared_period"]["mode"], mode);
+        assert_eq!(stats["compared_period"]["start"], start, "mode {}", mode);
+        assert_eq!(stats["compared_period"]["end"], end, "mode {}", mode);
+    }
+
+    // Unset, `true` and unknown modes compare with the previous period
+    for query in ["", "&compare=true", "&compare=yesterday"] {
+        let stats = stats(&app, &service, &format!("{}{}", range, query)).await;
+        assert_eq!(stats["compared_period"]["mode"], "previous_period");
+    }
+    let uncompared = stats(&app, &service, &format!("{}&compare=false", range)).await;
+    assert!(uncompared["compared_period"].is_null());
+}
+
+/// What the tracker script posts about a page view
+#[derive(Clone, Copy)]
+enum Beat {
+    Load,
+    Heartbeat,
+    End,
+}
+
+async fn track(app: &TestApp, service: &Service, page: &str, beat: Beat) {
+    let fields = match beat {
+        Beat::Load => r#","loadTime":100"#,
+        Beat::Heartbeat => "",
+        Beat::End => r#","end":true"#,
+    };
+    let response = app
+        .send(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/trace/app_{}.js", service.tracking_id))
+                .header("Content-Type", "application/json")
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (X11; Linux x86_64) Firefox/120.0",
+                )
+                .body(Body::from(format!(
+                    r#"{{"idempotency":"{}","location":"https://example.com{}"{}}}"#,
+                    page, page, fields
+                )))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_heartbeat_cap() {
+    let app = TestApp::with(|settings| {
+        settings.script_heartbeat_frequency_ms = 5000;
+        settings.max_heartbeats_per_hit = 3;
+    })
+    .await;
+    let service = app.service("Overnight").await;
+
+    // A tab left open: the page load, then heartbeats every five seconds
+    track(&app, &service, "/", Beat::Load).await;
+    for _ in 0..10 {
+        app.clock.advance(Duration::seconds(5));
+        track(&app, &service, "/", Beat::Heartbeat).await;
+    }
+
+    // Only the first three heartbeats add engaged time
+    let capped = stats(&app, &service, "").await;
+    assert_eq!(capped["hit_count"], 1);
+    assert_eq!(capped["avg_session_duration"], 15.0);
+    assert_eq!(capped["currently_online"], 0);
+
+    // A new page view counts again
+    app.clock.advance(Duration::seconds(5));
+    track(&app, &service, "/about", Beat::Load).await;
+    app.clock.advance(Duration::seconds(1));
+    let next = stats(&app, &service, "").await;
+    assert_eq!(next["hit_count"], 2);
+    assert_eq!(next["avg_session_duration"], 55.0);
+    assert_eq!(next["currently_online"], 1);
+}
+
+#[tokio::test]
+async fn test_session_end_signal() {
+    let app = create_app().await;
+    let service = app.service("Leaving").await;
+
+    track(&app, &service, "/", Beat::Load).await;
+    app.clock.advance(Duration::seconds(5));
+    track(&app, &service, "/", Beat::Heartbeat).await;
+
+    // The visitor closes the tab two seconds after the last heartbeat
+    app.clock.advance(Duration::seconds(2));
+    track(&app, &service, "/", Beat::End).await;
+    let stats_ended = stats(&app, &service, "").await;
+    assert_eq!(stats_ended["avg_session_duration"], 7.0);
+    assert_eq!(stats_ended["currently_online"], 0);
+    let filtered = stats(&app, &service, "?urlPattern=%2F").await;
+    assert_eq!(filtered["avg_session_duration"], 7.0);
+    assert_eq!(filtered["currently_online"], 0);
+
+    let sessions = db::list_sessions(
+        &app.state.pool,
+        service.id,
+        app.now() - Duration::days(1),
+        app.now() + Duration::days(1),
+        None,
+        None,
+        None,
+        10,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(sessions[0].ended_at, Some(app.now()));
+
+    // Coming back to the tab resumes the session
+    app.clock.advance(Duration::seconds(60));
+    track(&app, &service, "/", Beat::Heartbeat).await;
+    let stats_back = stats(&app, &service, "").await;
+    assert_eq!(stats_back["avg_session_duration"], 67.0);
+    assert_eq!(stats_back["currently_online"], 1);
+}
+
+#[tokio::test]
+async fn test_service_heartbeat_frequency() {
+    let app = create_app().await;
+    let service = app.service("Fast").await;
+    let now = app.now();
+
+    app.visit(&service, "a", &[("/", now - Duration::seconds(3))])
+        .await;
+    assert_eq!(stats(&app, &service, "").await["currently_online"], 1);
+
+    // One-second heartbeats: online means seen in the last two seconds
+    let service = db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            heartbeat_frequency_ms: Some(1000),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(stats(&app, &service, "").await["currently_online"], 0);
+
+    let response = app
+        .get(&format!("/trace/app_{}.js", service.tracking_id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let script = String::from_utf8_lossy(&body);
+    assert!(script.contains("setInterval(shymini.sendHeartbeat,1000)"));
+    assert!(script.contains("var idleTimeout=0;"));
+}
+
+#[tokio::test]
+async fn test_default_range_follows_clock() {
+    let app = create_app().await;
+    let service = app.service("Range").await;
+
+    let response = app.get(&format!("/service/{}?tz=UTC", service.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("2024-05-16T12:00"));
+    assert!(html.contains("2024-06-15T12:00"));
+}
+
+#[tokio::test]
+async fn test_weekly_chart() {
+    let app = create_app().await;
+    let service = app.service("Weeks").await;
+    let now = app.now();
+
+    // A Thursday a month ago and one two days ago
+    app.visit(&service, "a", &[("/", now - Duration::days(30))])
+        .await;
+    app.visit(&service, "b", &[("/", now - Duration::days(2))])
+        .await;
+
+    let week = |chart: &serde_json::Value, label: &str| {
+        let labels = chart["labels"].as_array().unwrap();
+        let index = labels
+            .iter()
+            .position(|l| l == label)
+            .unwrap_or_else(|| panic!("no week {} in {:?}", label, labels));
+        chart["hits"][index].clone()
+    };
+
+    // English weeks start on Sunday and are numbered as in the US
+    for query in ["?range=365d&tz=UTC", "?range=365d&tz=UTC&urlPattern=%2F"] {
+        let stats = stats(&app, &service, query).await;
+        assert_eq!(stats["chart_granularity"], "weekly", "query {}", query);
+        assert_eq!(stats["presentation"]["week_start"], "sunday");
+        let chart = &stats["chart_data"];
+        assert_eq!(week(chart, "W20, May 12"), 1, "query {}", query);
+        assert_eq!(week(chart, "W22, May 26"), 0, "query {}", query);
+        assert_eq!(week(chart, "W24, Jun 09"), 1, "query {}", query);
+    }
+
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            week_start: Some(Some(WeekStart::Monday)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    // ISO weeks from Monday
+    for query in ["?range=365d&tz=UTC", "?range=365d&tz=UTC&urlPattern=%2F"] {
+        let stats = stats(&app, &service, query).await;
+        assert_eq!(stats["presentation"]["week_start"], "monday");
+        let chart = &stats["chart_data"];
+        assert_eq!(week(chart, "W20, May 13"), 1, "query {}", query);
+        assert_eq!(week(chart, "W24, Jun 10"), 1, "query {}", query);
+    }
+
+    // Shorter ranges stay daily
+    let stats = stats(&app, &service, "?range=90d&tz=UTC").await;
+    assert_eq!(stats["chart_granularity"], "daily");
+}
+
+#[tokio::test]
+async fn test_content_groups() {
+    let app = create_app().await;
+    let service = app.service("Groups").await;
+    let now = app.now();
+    let uri = format!("/api/services/{}/content-groups", service.id);
+    let panel = format!("/service/{}/panels/content-groups", service.id);
+
+    app.visit(
+        &service,
+        "a",
+        &[
+            ("https://example.com/", now - Duration::hours(3)),
+            ("https://example.com/blog/one", now - Duration::hours(2)),
+            (
+                "https://example.com/blog/two?ref=x",
+                now - Duration::hours(1),
+            ),
+        ],
+    )
+    .await;
+    app.visit(
+        &service,
+        "b",
+        &[(
+            "https://example.com/docs/v2/start",
+            now - Duration::hours(1),
+        )],
+    )
+    .await;
+
+    // Without rules every hit is ungrouped, and the panel points to the settings
+    let json = app.get_json(&uri).await;
+    assert_eq!(json["data"], serde_json::json!([{"value": "", "count": 4}]));
+    let response = app.get(&panel).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("No content groups yet"));
+
+    db::update_service(
+        &app.state.pool,
+        service.id,
+        UpdateService {
+            content_groups: Some("/blog/* = Blog\n^/docs/v[0-9]+/ = Docs".to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let json = app.get_json(&uri).await;
+    assert_eq!(
+        json["data"],
+        serde_json::json!([
+            {"value": "Blog", "count": 2},
+            {"value": "", "count": 1},
+            {"value": "Docs", "count": 1},
+        ])
+    );
+
+    // The URL filter narrows the hits that are grouped
+    let json = app.get_json(&format!("{}?urlPattern=docs", uri)).await;
+    assert_eq!(
+        json["data"],
+        serde_json::json!([{"value": "Docs", "count": 1}])
+    );
+
+    let response = app.get(&panel).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8_lossy(&body);
+    assert!(html.contains("Blog"));
+    assert!(html.contains("Other pages"));
+}
+
+#[tokio::test]
+async fn test_materialized_top_pages() {
+    let app = create_app().await;
+    let service = app.service("Top pages").await;
+    let now = app.now();
+    let locations = |json: &serde_json::Value| {
+        json["locations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| {
+                (
+                    item["value"].as_str().unwrap().to_string(),
+                    item["count"].as_i64().unwrap(),
+                )
+            })
+            .collect::<std::collections::BTreeMap<_, _>>()
+    };
+
+    app.visit(&service, "a", &[("/a", now - Duration::days(3))])
+        .await;
+    app.visit(&service, "b", &[("/a", now - Duration::days(3))])
+        .await;
+    let earlier = app
+        .visit(&service, "c", &[("/b", now - Duration::days(2))])
+        .await;
+    app.visit(&service, "d", &[("/a", now - Duration::hours(1))])
+        .await;
+    app.clock.advance(Duration::seconds(1));
+
+    shymini::top_pages::run_refresh(&app.state).await.unwrap();
+    let json = stats(&app, &service, "").await;
+    assert_eq!(
+        locations(&json),
+        [("/a".to_string(), 3), ("/b".to_string(), 1)].into()
+    );
+
+    // Completed days are read from the table, while today is counted live
+    app.hit(&earlier, "/c", now - Duration::days(2)).await;
+    app.visit(&service, "e", &[("/c", now - Duration::minutes(30))])
+        .await;
+    let json = stats(&app, &service, "").await;
+    assert_eq!(
+        locations(&json),
+        [
+            ("/a".to_string(), 3),
+            ("/b".to_string(), 1),
+            ("/c".to_string(), 1)
+        ]
+        .into()
+    );
+
+    // Ranges of whole days only
+    let json = stats(
+        &app,
+        &service,
+        "?startDate=2024-06-12&endDate=2024-06-13&tz=UTC",
+    )
+    .await;
+    assert_eq!(
+        locations(&json),
+        [("/a".to_string(), 2), ("/b".to_string(), 1)].into()
+    );
+}